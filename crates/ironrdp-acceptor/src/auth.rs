@@ -0,0 +1,106 @@
+use core::fmt;
+use std::net::SocketAddr;
+
+use ironrdp_pdu::nego::SecurityProtocol;
+
+/// Authenticates the users logging on
+///
+/// The backend is consulted once per connection:
+///
+/// - With CredSSP (NLA), when sspi looks up the user of the client's authenticate message. The
+///   backend returns the secret of the user, and CredSSP verifies that the client knows it.
+/// - With TLS or standard RDP security, when the client sends its Client Info PDU. The password
+///   of the client is part of the request, and the backend verifies it.
+#[async_trait::async_trait]
+pub trait AuthBackend: Send + Sync {
+    async fn authenticate(&self, request: &AuthRequest) -> AuthDecision;
+}
+
+/// Logon attempt of a client
+#[derive(Clone)]
+pub struct AuthRequest {
+    pub username: String,
+    pub domain: Option<String>,
+    /// Password sent by the client in the Client Info PDU
+    ///
+    /// `None` with CredSSP, where the client never sends its password before being authenticated.
+    pub password: Option<String>,
+    /// Security protocol selected for the connection
+    pub protocol: SecurityProtocol,
+    /// Name of the client computer, when sent by the client
+    pub client_name: Option<String>,
+    /// Address of the client, see [`Acceptor::set_client_address`](crate::Acceptor::set_client_address)
+    pub client_address: Option<SocketAddr>,
+}
+
+impl fmt::Debug for AuthRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // NOTE: do not show secret (user password)
+        f.debug_struct("AuthRequest")
+            .field("username", &self.username)
+            .field("domain", &self.domain)
+            .field("protocol", &self.protocol)
+            .field("client_name", &self.client_name)
+            .field("client_address", &self.client_address)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum AuthDecision {
+    /// The user is allowed to log on
+    ///
+    /// The secret is required with CredSSP, and ignored otherwise.
+    Allow(Option<AuthSecret>),
+    Deny,
+}
+
+/// Secret of a user, that CredSSP clients must prove they know
+#[derive(Clone)]
+#[non_exhaustive]
+pub enum AuthSecret {
+    Password(String),
+    /// NT hash of the password: the MD4 digest of the UTF-16LE password
+    ///
+    /// Only usable with NTLM, Kerberos needs the password.
+    NtHash([u8; 16]),
+}
+
+impl AuthSecret {
+    /// Password of the sspi identity of the user
+    ///
+    /// sspi takes a NT hash as the upper case hexadecimal digest followed by 512 bytes of padding, in place of
+    /// the UTF-16LE password. Each UTF-16 code unit holds two of the hexadecimal digits, so the encoded password
+    /// has the expected bytes.
+    pub(crate) fn sspi_password(&self) -> String {
+        const HASH_PADDING: usize = 512;
+
+        match self {
+            Self::Password(password) => password.clone(),
+            Self::NtHash(hash) => {
+                let digits: Vec<u8> = hash
+                    .iter()
+                    .flat_map(|byte| format!("{byte:02X}").into_bytes())
+                    .collect();
+                let code_units = digits
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                    .chain(core::iter::repeat_n(0, HASH_PADDING / 2));
+
+                char::decode_utf16(code_units)
+                    .map(|c| c.expect("hexadecimal digits are not surrogates"))
+                    .collect()
+            }
+        }
+    }
+}
+
+impl fmt::Debug for AuthSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // NOTE: do not show secret (user password)
+        match self {
+            Self::Password(_) => f.write_str("Password(..)"),
+            Self::NtHash(_) => f.write_str("NtHash(..)"),
+        }
+    }
+}
@@ -1,8 +1,10 @@
-use core::mem;
+use core::{fmt, mem};
+use std::net::SocketAddr;
+use std::sync::Arc;
 
 use ironrdp_connector::{
-    encode_x224_packet, reason_err, ConnectorError, ConnectorErrorExt, ConnectorResult, DesktopSize, Sequence, State,
-    Written,
+    custom_err, encode_x224_packet, general_err, reason_err, ConnectorError, ConnectorErrorExt, ConnectorResult,
+    DesktopSize, Sequence, State, Written,
 };
 use ironrdp_core::{decode, WriteBuf};
 use ironrdp_pdu as pdu;
@@ -10,12 +12,15 @@ use ironrdp_pdu::nego::SecurityProtocol;
 use ironrdp_pdu::x224::X224;
 use ironrdp_svc::{StaticChannelSet, SvcServerProcessor};
 use pdu::rdp::capability_sets::CapabilitySet;
-use pdu::rdp::client_info::Credentials;
+use pdu::rdp::client_info::{ClientInfo, ClientInfoFlags, CompressionType, Credentials};
 use pdu::rdp::headers::ShareControlPdu;
 use pdu::rdp::server_error_info::{ErrorInfo, ProtocolIndependentCode, ServerSetErrorInfoPdu};
 use pdu::rdp::server_license::{LicensePdu, LicensingErrorMessage};
 use pdu::{gcc, mcs, nego, rdp};
+use picky_krb::constants::cred_ssp::TS_PASSWORD_CREDS;
+use picky_krb::credssp::{TsCredentials, TsPasswordCreds};
 
+use super::auth::{AuthBackend, AuthDecision, AuthRequest};
 use super::channel_connection::ChannelConnectionSequence;
 use super::finalization::FinalizationSequence;
 use crate::util::{self, wrap_share_data};
@@ -33,6 +38,12 @@ pub struct Acceptor {
     static_channels: StaticChannelSet,
     saved_for_reactivation: AcceptorState,
     pub(crate) creds: Option<Credentials>,
+    pub(crate) auth_backend: Option<Arc<dyn AuthBackend>>,
+    pub(crate) client_address: Option<SocketAddr>,
+    client_name: Option<String>,
+    client_info: Option<ClientInfo>,
+    pub(crate) delegated_credentials: Option<DelegatedCredentials>,
+    compression_type: Option<CompressionType>,
     reactivation: bool,
 }
 
@@ -43,7 +54,95 @@ pub struct AcceptorResult {
     pub input_events: Vec<Vec<u8>>,
     pub user_channel_id: u16,
     pub io_channel_id: u16,
+    /// Bulk compression type supported by the client, if it advertised compression support.
+    pub compression_type: Option<CompressionType>,
     pub reactivation: bool,
+    /// Who connected, and what they asked for
+    ///
+    /// `None` only if the client did not send its Client Info PDU, which it does before being
+    /// accepted.
+    pub logon: Option<ClientLogon>,
+}
+
+/// Logon of a client
+#[derive(Debug, Clone)]
+pub struct ClientLogon {
+    /// Client Info PDU: user and domain, alternate shell, working directory, time zone,
+    /// performance flags…
+    ///
+    /// The password is empty with CredSSP, see [`ClientLogon::delegated_credentials`].
+    pub client_info: ClientInfo,
+    /// Credentials delegated by the client with CredSSP
+    pub delegated_credentials: Option<DelegatedCredentials>,
+}
+
+/// Credentials delegated by a CredSSP client, to log on the user on the server
+///
+/// Decoded from the TSCredentials structure sent by the client at the end of the CredSSP sequence.
+/// Only password credentials are supported: sspi does not decrypt smart card credentials on the
+/// server side, and the CredSSP sequence fails when the client delegates them.
+#[derive(Clone)]
+#[non_exhaustive]
+pub enum DelegatedCredentials {
+    /// TSPasswordCreds
+    Password {
+        username: String,
+        domain: Option<String>,
+        password: String,
+    },
+}
+
+impl DelegatedCredentials {
+    /// Decodes a TSCredentials structure, as decrypted from the `authInfo` field of the last
+    /// TSRequest of the client
+    pub fn decode(ts_credentials: &[u8]) -> ConnectorResult<Self> {
+        let ts_credentials: TsCredentials =
+            picky_asn1_der::from_bytes(ts_credentials).map_err(|e| custom_err!("TSCredentials", e))?;
+
+        match ts_credentials.cred_type.as_unsigned_bytes_be() {
+            [TS_PASSWORD_CREDS] => {
+                let creds: TsPasswordCreds = picky_asn1_der::from_bytes(&ts_credentials.credentials)
+                    .map_err(|e| custom_err!("TSPasswordCreds", e))?;
+                let domain = utf16_string(&creds.domain_name)?;
+
+                Ok(Self::Password {
+                    username: utf16_string(&creds.user_name)?,
+                    domain: (!domain.is_empty()).then_some(domain),
+                    password: utf16_string(&creds.password)?,
+                })
+            }
+            cred_type => Err(reason_err!(
+                "TSCredentials",
+                "unsupported credential type: {cred_type:?}"
+            )),
+        }
+    }
+}
+
+impl fmt::Debug for DelegatedCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // NOTE: do not show secret (user password)
+        match self {
+            Self::Password { username, domain, .. } => f
+                .debug_struct("Password")
+                .field("username", username)
+                .field("domain", domain)
+                .finish_non_exhaustive(),
+        }
+    }
+}
+
+/// Decodes the UTF-16LE strings of the TSCredentials structures
+fn utf16_string(value: &[u8]) -> ConnectorResult<String> {
+    if value.len() % 2 != 0 {
+        return Err(general_err!("odd UTF-16 string length"));
+    }
+
+    let value = value.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+
+    char::decode_utf16(value)
+        .collect::<Result<String, _>>()
+        .map_err(|e| custom_err!("UTF-16 string", e))
 }
 
 impl Acceptor {
@@ -63,6 +162,12 @@ impl Acceptor {
             static_channels: StaticChannelSet::new(),
             saved_for_reactivation: Default::default(),
             creds,
+            auth_backend: None,
+            client_address: None,
+            client_name: None,
+            client_info: None,
+            delegated_credentials: None,
+            compression_type: None,
             reactivation: false,
         }
     }
@@ -104,6 +209,12 @@ impl Acceptor {
             static_channels,
             saved_for_reactivation,
             creds: consumed.creds,
+            auth_backend: consumed.auth_backend,
+            client_address: consumed.client_address,
+            client_name: consumed.client_name,
+            client_info: consumed.client_info,
+            delegated_credentials: consumed.delegated_credentials,
+            compression_type: consumed.compression_type,
             reactivation: true,
         }
     }
@@ -115,6 +226,16 @@ impl Acceptor {
         self.static_channels.insert(channel);
     }
 
+    /// Authenticates the users with the backend, instead of the credentials given to [`Acceptor::new`]
+    pub fn set_auth_backend(&mut self, backend: Arc<dyn AuthBackend>) {
+        self.auth_backend = Some(backend);
+    }
+
+    /// Sets the address of the client, given to the authentication backend
+    pub fn set_client_address(&mut self, address: SocketAddr) {
+        self.client_address = Some(address);
+    }
+
     pub fn reached_security_upgrade(&self) -> Option<SecurityProtocol> {
         match self.state {
             AcceptorState::SecurityUpgrade { .. } => Some(self.security),
@@ -139,6 +260,66 @@ impl Acceptor {
         assert_eq!(res, Written::Nothing);
     }
 
+    /// Returns the logon attempt to authenticate with the backend, once the Client Info PDU is
+    /// received
+    ///
+    /// The decision of the backend is given back with [`Acceptor::mark_auth_as_done`].
+    pub fn should_authenticate(&self) -> Option<&AuthRequest> {
+        match &self.state {
+            AcceptorState::Authentication { request, .. } => Some(request),
+            _ => None,
+        }
+    }
+
+    /// Completes the authentication of the Client Info PDU
+    ///
+    /// When the user is denied, an error is returned, after writing the Set Error Info PDU to send
+    /// to the client.
+    /// An error is also returned when no authentication is in progress.
+    pub fn mark_auth_as_done(&mut self, decision: AuthDecision, output: &mut WriteBuf) -> ConnectorResult<()> {
+        let (request, early_capability, channels) = match mem::take(&mut self.state) {
+            AcceptorState::Authentication {
+                request,
+                early_capability,
+                channels,
+            } => (request, early_capability, channels),
+            state => {
+                self.state = state;
+                return Err(general_err!("no authentication in progress"));
+            }
+        };
+
+        if let AuthDecision::Deny = decision {
+            debug!(?request, "Access denied");
+            return Err(self.deny_logon(output));
+        }
+
+        self.state = AcceptorState::LicensingExchange {
+            early_capability,
+            channels,
+        };
+
+        Ok(())
+    }
+
+    /// Writes the Set Error Info PDU denying the logon, and returns the error to close the
+    /// connection with
+    fn deny_logon(&self, output: &mut WriteBuf) -> ConnectorError {
+        // FIXME: How authorization should be denied with standard RDP security?
+        // Since standard RDP security is not a priority, we just send a ServerDeniedConnection ServerSetErrorInfo PDU.
+        let info = ServerSetErrorInfoPdu(ErrorInfo::ProtocolIndependentCode(
+            ProtocolIndependentCode::ServerDeniedConnection,
+        ));
+
+        debug!(message = ?info, "Send");
+
+        if let Err(e) = util::encode_send_data_indication(self.user_channel_id, self.io_channel_id, &info, output) {
+            return e;
+        }
+
+        ConnectorError::general("invalid credentials")
+    }
+
     pub fn get_result(&mut self) -> Option<AcceptorResult> {
         match mem::take(&mut self.state) {
             AcceptorState::Accepted {
@@ -151,7 +332,12 @@ impl Acceptor {
                 input_events,
                 user_channel_id: self.user_channel_id,
                 io_channel_id: self.io_channel_id,
+                compression_type: self.compression_type,
                 reactivation: self.reactivation,
+                logon: self.client_info.clone().map(|client_info| ClientLogon {
+                    client_info,
+                    delegated_credentials: self.delegated_credentials.clone(),
+                }),
             }),
             previous_state => {
                 self.state = previous_state;
@@ -204,6 +390,11 @@ pub enum AcceptorState {
         early_capability: Option<gcc::ClientEarlyCapabilityFlags>,
         channels: Vec<(u16, gcc::ChannelDef)>,
     },
+    Authentication {
+        request: AuthRequest,
+        early_capability: Option<gcc::ClientEarlyCapabilityFlags>,
+        channels: Vec<(u16, gcc::ChannelDef)>,
+    },
     LicensingExchange {
         early_capability: Option<gcc::ClientEarlyCapabilityFlags>,
         channels: Vec<(u16, gcc::ChannelDef)>,
@@ -243,6 +434,7 @@ impl State for AcceptorState {
             Self::ChannelConnection { .. } => "ChannelConnection",
             Self::RdpSecurityCommencement { .. } => "RdpSecurityCommencement",
             Self::SecureSettingsExchange { .. } => "SecureSettingsExchange",
+            Self::Authentication { .. } => "Authentication",
             Self::LicensingExchange { .. } => "LicensingExchange",
             Self::CapabilitiesSendServer { .. } => "CapabilitiesSendServer",
             Self::MonitorLayoutSend { .. } => "MonitorLayoutSend",
@@ -274,6 +466,7 @@ impl Sequence for Acceptor {
             AcceptorState::ChannelConnection { connection, .. } => connection.next_pdu_hint(),
             AcceptorState::RdpSecurityCommencement { .. } => None,
             AcceptorState::SecureSettingsExchange { .. } => Some(&pdu::X224_HINT),
+            AcceptorState::Authentication { .. } => None,
             AcceptorState::LicensingExchange { .. } => None,
             AcceptorState::CapabilitiesSendServer { .. } => None,
             AcceptorState::MonitorLayoutSend { .. } => None,
@@ -380,6 +573,16 @@ impl Sequence for Acceptor {
 
                 debug!(message = ?settings_initial, "Received");
 
+                self.client_name = Some(
+                    settings_initial
+                        .conference_create_request
+                        .gcc_blocks
+                        .core
+                        .client_name
+                        .clone(),
+                )
+                .filter(|name| !name.is_empty());
+
                 let early_capability = settings_initial
                     .conference_create_request
                     .gcc_blocks
@@ -528,31 +731,50 @@ impl Sequence for Acceptor {
 
                 debug!(message = ?client_info, "Received");
 
-                if !protocol.intersects(SecurityProtocol::HYBRID | SecurityProtocol::HYBRID_EX) {
-                    let creds = client_info.client_info.credentials;
-
-                    if self.creds.as_ref() != Some(&creds) {
-                        // FIXME: How authorization should be denied with standard RDP security?
-                        // Since standard RDP security is not a priority, we just send a ServerDeniedConnection ServerSetErrorInfo PDU.
-                        let info = ServerSetErrorInfoPdu(ErrorInfo::ProtocolIndependentCode(
-                            ProtocolIndependentCode::ServerDeniedConnection,
-                        ));
+                self.compression_type = client_info
+                    .client_info
+                    .flags
+                    .contains(ClientInfoFlags::COMPRESSION)
+                    .then_some(client_info.client_info.compression_type);
 
-                        debug!(message = ?info, "Send");
+                let authenticated = protocol.intersects(SecurityProtocol::HYBRID | SecurityProtocol::HYBRID_EX);
+                self.client_info = Some(client_info.client_info.clone());
+                let creds = client_info.client_info.credentials;
 
-                        util::encode_send_data_indication(self.user_channel_id, self.io_channel_id, &info, output)?;
-
-                        return Err(ConnectorError::general("invalid credentials"));
+                let next_state = if authenticated {
+                    AcceptorState::LicensingExchange {
+                        early_capability,
+                        channels,
                     }
-                }
+                } else if self.auth_backend.is_some() {
+                    let request = AuthRequest {
+                        username: creds.username,
+                        domain: creds.domain,
+                        password: Some(creds.password),
+                        protocol,
+                        client_name: self.client_name.clone(),
+                        client_address: self.client_address,
+                    };
 
-                (
-                    Written::Nothing,
+                    AcceptorState::Authentication {
+                        request,
+                        early_capability,
+                        channels,
+                    }
+                } else if self.creds.as_ref() == Some(&creds) {
                     AcceptorState::LicensingExchange {
                         early_capability,
                         channels,
-                    },
-                )
+                    }
+                } else {
+                    return Err(self.deny_logon(output));
+                };
+
+                (Written::Nothing, next_state)
+            }
+
+            AcceptorState::Authentication { .. } => {
+                return Err(ConnectorError::general("authentication is pending"));
             }
 
             AcceptorState::LicensingExchange {
@@ -1,3 +1,10 @@
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::Poll;
+
+use futures_channel::mpsc::{UnboundedReceiver, UnboundedSender};
+use futures_core::Stream as _;
 use ironrdp_connector::credssp::KerberosConfig;
 use ironrdp_connector::sspi::credssp::{
     ClientMode, CredSspServer, CredentialsProxy, ServerError, ServerState, TsRequest,
@@ -33,39 +40,79 @@ impl PduHint for CredsspTsRequestHint {
 }
 
 #[derive(Debug)]
-pub(crate) struct CredsspSequence<'a> {
-    server: CredSspServer<CredentialsProxyImpl<'a>>,
+pub(crate) struct CredsspSequence {
+    /// `None` while a request is processed on the lookup thread
+    server: Option<CredSspServer<CredentialsProxyImpl>>,
+    lookups: Option<UnboundedReceiver<Lookup>>,
     state: CredsspState,
+    /// Credentials delegated by the client, once the sequence is finished
+    delegated: Option<AuthIdentity>,
+    /// Whether the backend was consulted by sspi for the user of the client
+    looked_up: bool,
     // selected_protocol: nego::SecurityProtocol,
 }
 
-#[derive(Debug)]
-struct CredentialsProxyImpl<'a> {
-    credentials: &'a AuthIdentity,
+type ProcessResult = Result<ServerState, Box<ServerError>>;
+
+/// Lookup of the credentials of a user by sspi, waiting for the reply
+struct Lookup {
+    username: Username,
+    reply: std::sync::mpsc::Sender<std::io::Result<AuthIdentity>>,
 }
 
-impl<'a> CredentialsProxyImpl<'a> {
-    fn new(credentials: &'a AuthIdentity) -> Self {
-        Self { credentials }
+impl fmt::Debug for Lookup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lookup")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
     }
 }
 
-impl CredentialsProxy for CredentialsProxyImpl<'_> {
+/// Gives sspi the credentials of the user of the client
+///
+/// sspi looks them up synchronously, in the middle of processing the authenticate message. With
+/// a backend, the request is processed on a dedicated thread, and the lookup is forwarded to the
+/// asynchronous side of the sequence (see [`CredsspSequence::process_ts_request`]).
+#[derive(Debug)]
+enum CredentialsProxyImpl {
+    Static(AuthIdentity),
+    Backend(UnboundedSender<Lookup>),
+}
+
+impl CredentialsProxy for CredentialsProxyImpl {
     type AuthenticationData = AuthIdentity;
 
     fn auth_data_by_user(&mut self, username: &Username) -> std::io::Result<Self::AuthenticationData> {
-        if username.account_name() != self.credentials.username.account_name() {
-            return Err(std::io::Error::other("invalid username"));
-        }
+        match self {
+            Self::Static(credentials) => {
+                if username.account_name() != credentials.username.account_name() {
+                    return Err(std::io::Error::other("invalid username"));
+                }
 
-        let mut data = self.credentials.clone();
-        // keep the original user/domain
-        data.username = username.clone();
-        Ok(data)
+                let mut data = credentials.clone();
+                // keep the original user/domain
+                data.username = username.clone();
+                Ok(data)
+            }
+            Self::Backend(lookups) => {
+                let (reply, reply_rx) = std::sync::mpsc::channel();
+
+                lookups
+                    .unbounded_send(Lookup {
+                        username: username.clone(),
+                        reply,
+                    })
+                    .map_err(|_| std::io::Error::other("CredSSP sequence dropped"))?;
+
+                reply_rx
+                    .recv()
+                    .map_err(|_| std::io::Error::other("CredSSP sequence dropped"))?
+            }
+        }
     }
 }
 
-impl<'a> CredsspSequence<'a> {
+impl CredsspSequence {
     pub(crate) fn next_pdu_hint(&self) -> ConnectorResult<Option<&dyn PduHint>> {
         match &self.state {
             CredsspState::Ongoing => Ok(Some(&CREDSSP_TS_REQUEST_HINT)),
@@ -74,14 +121,22 @@ impl<'a> CredsspSequence<'a> {
         }
     }
 
+    /// `creds` is `None` when the user is authenticated with a backend, which gives the
+    /// credentials once sspi knows the user (see [`Self::process_ts_request`]).
     pub(crate) fn init(
-        creds: &'a AuthIdentity,
+        creds: Option<AuthIdentity>,
         client_computer_name: ServerName,
         public_key: Vec<u8>,
         kerberos_config: Option<KerberosConfig>,
     ) -> ConnectorResult<Self> {
         let client_computer_name = client_computer_name.into_inner();
-        let credentials = CredentialsProxyImpl::new(creds);
+        let (credentials, lookups) = match creds {
+            Some(creds) => (CredentialsProxyImpl::Static(creds), None),
+            None => {
+                let (lookups_tx, lookups_rx) = futures_channel::mpsc::unbounded();
+                (CredentialsProxyImpl::Backend(lookups_tx), Some(lookups_rx))
+            }
+        };
         let credssp_config: Box<dyn ProtocolConfig>;
         if let Some(ref krb_config) = kerberos_config {
             credssp_config = Box::new(Into::<sspi::KerberosConfig>::into(krb_config.clone()));
@@ -102,13 +157,27 @@ impl<'a> CredsspSequence<'a> {
         .map_err(|e| ConnectorError::new("CredSSP", ConnectorErrorKind::Credssp(e)))?;
 
         let sequence = Self {
-            server,
+            server: Some(server),
+            lookups,
             state: CredsspState::Ongoing,
+            delegated: None,
+            looked_up: false,
         };
 
         Ok(sequence)
     }
 
+    pub(crate) fn delegated_identity(&self) -> Option<&AuthIdentity> {
+        self.delegated.as_ref()
+    }
+
+    /// Whether the backend was consulted for the user of the client
+    ///
+    /// sspi looks up the user of the NTLM authenticate message, but not with every package.
+    pub(crate) fn looked_up(&self) -> bool {
+        self.looked_up
+    }
+
     /// Returns Some(ts_request) when a TS request is received from client,
     pub(crate) fn decode_client_message(&mut self, input: &[u8]) -> ConnectorResult<Option<TsRequest>> {
         match self.state {
@@ -123,18 +192,78 @@ impl<'a> CredsspSequence<'a> {
         }
     }
 
-    pub(crate) fn process_ts_request(&mut self, request: TsRequest) -> Result<ServerState, Box<ServerError>> {
-        Ok(self.server.process(request)?)
+    /// Processes the request of the client
+    ///
+    /// With a backend, the request is processed on a dedicated thread, while `lookup` is awaited
+    /// for the credentials of the user when sspi asks for them.
+    pub(crate) async fn process_ts_request<F, Fut>(
+        &mut self,
+        request: TsRequest,
+        mut lookup: F,
+    ) -> ConnectorResult<ProcessResult>
+    where
+        F: FnMut(Username) -> Fut,
+        Fut: Future<Output = std::io::Result<AuthIdentity>>,
+    {
+        let mut server = self
+            .server
+            .take()
+            .ok_or_else(|| general_err!("CredSSP server lost by a previous request"))?;
+
+        let Some(lookups) = self.lookups.as_mut() else {
+            let result = server.process(request).map_err(Box::new);
+            self.server = Some(server);
+            return Ok(result);
+        };
+
+        let (done_tx, mut done_rx) = futures_channel::oneshot::channel();
+
+        std::thread::Builder::new()
+            .name("credssp-lookup".to_owned())
+            .spawn(move || {
+                let result = server.process(request).map_err(Box::new);
+                let _ = done_tx.send((server, result));
+            })
+            .map_err(|e| custom_err!("spawn CredSSP thread", e))?;
+
+        loop {
+            let event = core::future::poll_fn(|cx| {
+                if let Poll::Ready(done) = Pin::new(&mut done_rx).poll(cx) {
+                    return Poll::Ready(Err(done));
+                }
+
+                match Pin::new(&mut *lookups).poll_next(cx) {
+                    Poll::Ready(Some(lookup)) => Poll::Ready(Ok(lookup)),
+                    _ => Poll::Pending,
+                }
+            })
+            .await;
+
+            match event {
+                Ok(Lookup { username, reply }) => {
+                    self.looked_up = true;
+                    let _ = reply.send(lookup(username).await);
+                }
+                Err(done) => {
+                    let (server, result) = done.map_err(|_| general_err!("CredSSP thread panicked"))?;
+                    self.server = Some(server);
+                    return Ok(result);
+                }
+            }
+        }
     }
 
     pub(crate) fn handle_process_result(
         &mut self,
-        result: Result<ServerState, Box<ServerError>>,
+        result: ProcessResult,
         output: &mut WriteBuf,
     ) -> ConnectorResult<Written> {
         let (ts_request, next_state) = match result {
             Ok(ServerState::ReplyNeeded(ts_request)) => (Some(ts_request), CredsspState::Ongoing),
-            Ok(ServerState::Finished(_id)) => (None, CredsspState::Finished),
+            Ok(ServerState::Finished(identity)) => {
+                self.delegated = Some(identity);
+                (None, CredsspState::Finished)
+            }
             Err(err) => (Some(err.ts_request), CredsspState::ServerError(err.error)),
         };
 
@@ -111,6 +111,13 @@ impl Sequence for FinalizationSequence {
                     (Written::Nothing, FinalizationState::SendSynchronizeConfirm)
                 }
 
+                Err(()) if is_persistent_key_list(input) => {
+                    // The bitmap caches of the client are not used.
+                    debug!("Ignoring Persistent Key List PDU");
+
+                    (Written::Nothing, FinalizationState::WaitFontList)
+                }
+
                 Err(()) => {
                     self.input_events.push(input.to_vec());
 
@@ -243,3 +250,14 @@ fn decode_font_list(input: &[u8]) -> Result<rdp::finalization_messages::FontPdu,
 
     Ok(font_pdu)
 }
+
+fn is_persistent_key_list(input: &[u8]) -> bool {
+    use pdu::rdp::headers::{ShareControlPdu, ShareDataPdu};
+
+    decode_share_control(input).is_ok_and(|share_control| {
+        matches!(
+            share_control.share_control_pdu,
+            ShareControlPdu::Data(data_pdu) if matches!(data_pdu.share_data_pdu, ShareDataPdu::BitmapCachePersistentList(_))
+        )
+    })
+}
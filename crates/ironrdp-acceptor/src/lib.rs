@@ -4,24 +4,28 @@
 #[macro_use]
 extern crate tracing;
 
+use std::net::SocketAddr;
+
 use ironrdp_async::{single_sequence_step, Framed, FramedRead, FramedWrite, StreamWrapper};
-use ironrdp_connector::credssp::KerberosConfig;
 use ironrdp_connector::sspi::credssp::EarlyUserAuthResult;
 use ironrdp_connector::sspi::{AuthIdentity, Username};
 use ironrdp_connector::{custom_err, general_err, ConnectorResult, ServerName};
 use ironrdp_core::WriteBuf;
 
+mod auth;
 mod channel_connection;
 mod connection;
 mod credssp;
 mod finalization;
 mod util;
 
+pub use ironrdp_connector::credssp::KerberosConfig;
 pub use ironrdp_connector::DesktopSize;
 use ironrdp_pdu::nego;
 
+pub use self::auth::{AuthBackend, AuthDecision, AuthRequest, AuthSecret};
 pub use self::channel_connection::{ChannelConnectionSequence, ChannelConnectionState};
-pub use self::connection::{Acceptor, AcceptorResult, AcceptorState};
+pub use self::connection::{Acceptor, AcceptorResult, AcceptorState, ClientLogon, DelegatedCredentials};
 pub use self::finalization::{FinalizationSequence, FinalizationState};
 
 pub enum BeginResult<S>
@@ -93,6 +97,30 @@ where
         if let Some(result) = acceptor.get_result() {
             return Ok((framed, result));
         }
+
+        if let Some(request) = acceptor.should_authenticate() {
+            let backend = acceptor
+                .auth_backend
+                .clone()
+                .ok_or_else(|| general_err!("authentication requires a backend"))?;
+            let decision = backend.authenticate(request).await;
+            debug!(?request, ?decision, "Authenticated");
+
+            buf.clear();
+            let result = acceptor.mark_auth_as_done(decision, &mut buf);
+
+            // A Set Error Info PDU is written when the user is denied.
+            if !buf.filled().is_empty() {
+                framed
+                    .write_all(buf.filled())
+                    .await
+                    .map_err(|e| custom_err!("write all", e))?;
+            }
+
+            result?;
+            continue;
+        }
+
         single_sequence_step(&mut framed, acceptor, &mut buf).await?;
     }
 }
@@ -118,6 +146,7 @@ where
         framed: &mut Framed<S>,
         acceptor: &mut Acceptor,
         buf: &mut WriteBuf,
+        protocol: nego::SecurityProtocol,
         client_computer_name: ServerName,
         public_key: Vec<u8>,
         kerberos_config: Option<KerberosConfig>,
@@ -125,18 +154,25 @@ where
     where
         S: FramedRead + FramedWrite,
     {
-        let creds = acceptor
-            .creds
-            .as_ref()
-            .ok_or_else(|| general_err!("no credentials while doing credssp"))?;
-        let username = Username::new(&creds.username, None).map_err(|e| custom_err!("invalid username", e))?;
-        let identity = AuthIdentity {
-            username,
-            password: creds.password.clone().into(),
+        let backend = acceptor.auth_backend.clone();
+
+        let identity = match backend {
+            // The backend gives the credentials, once the user is known.
+            Some(_) => None,
+            None => {
+                let creds = acceptor
+                    .creds
+                    .as_ref()
+                    .ok_or_else(|| general_err!("no credentials while doing credssp"))?;
+                let username = Username::new(&creds.username, None).map_err(|e| custom_err!("invalid username", e))?;
+                Some(AuthIdentity {
+                    username,
+                    password: creds.password.clone().into(),
+                })
+            }
         };
 
-        let mut sequence =
-            credssp::CredsspSequence::init(&identity, client_computer_name, public_key, kerberos_config)?;
+        let mut sequence = credssp::CredsspSequence::init(identity, client_computer_name, public_key, kerberos_config)?;
 
         loop {
             let Some(next_pdu_hint) = sequence.next_pdu_hint()? else {
@@ -160,7 +196,12 @@ where
                 break;
             };
 
-            let result = sequence.process_ts_request(ts_request);
+            let client_address = acceptor.client_address;
+            let result = sequence
+                .process_ts_request(ts_request, |username| {
+                    lookup_credentials(backend.as_deref(), username, protocol, client_address)
+                })
+                .await?;
             buf.clear();
             let written = sequence.handle_process_result(result, buf)?;
 
@@ -173,10 +214,82 @@ where
                     .map_err(|e| ironrdp_connector::custom_err!("write all", e))?;
             }
         }
+
+        acceptor.delegated_credentials = sequence
+            .delegated_identity()
+            .map(|identity| DelegatedCredentials::Password {
+                username: identity.username.account_name().to_owned(),
+                domain: identity.username.domain_name().map(ToOwned::to_owned),
+                password: identity.password.as_ref().clone(),
+            });
+
+        // Without NTLM (e.g. with Kerberos), the credentials are not looked up by user. The
+        // backend still has the last word on the delegated user.
+        if let (Some(backend), false) = (backend.as_deref(), sequence.looked_up()) {
+            let identity = sequence
+                .delegated_identity()
+                .ok_or_else(|| general_err!("no delegated credentials"))?;
+
+            let request = AuthRequest {
+                username: identity.username.account_name().to_owned(),
+                domain: identity.username.domain_name().map(ToOwned::to_owned),
+                password: None,
+                protocol,
+                client_name: None,
+                client_address: acceptor.client_address,
+            };
+
+            if let AuthDecision::Deny = backend.authenticate(&request).await {
+                debug!(?request, "Access denied");
+                return Err(general_err!("access denied"));
+            }
+        }
+
         Ok(())
     }
 
-    let result = credssp_loop(framed, acceptor, buf, client_computer_name, public_key, kerberos_config).await;
+    /// Looks up the credentials of the user of the client, for sspi to verify that the client knows
+    /// the password
+    async fn lookup_credentials(
+        backend: Option<&dyn AuthBackend>,
+        username: Username,
+        protocol: nego::SecurityProtocol,
+        client_address: Option<SocketAddr>,
+    ) -> std::io::Result<AuthIdentity> {
+        let backend = backend.ok_or_else(|| std::io::Error::other("no authentication backend"))?;
+
+        let request = AuthRequest {
+            username: username.account_name().to_owned(),
+            domain: username.domain_name().map(ToOwned::to_owned),
+            password: None,
+            protocol,
+            client_name: None,
+            client_address,
+        };
+
+        match backend.authenticate(&request).await {
+            AuthDecision::Allow(Some(secret)) => Ok(AuthIdentity {
+                username,
+                password: secret.sspi_password().into(),
+            }),
+            AuthDecision::Allow(None) => Err(std::io::Error::other("no secret to authenticate the user with CredSSP")),
+            AuthDecision::Deny => {
+                debug!(?request, "Access denied");
+                Err(std::io::Error::other("access denied"))
+            }
+        }
+    }
+
+    let result = credssp_loop(
+        framed,
+        acceptor,
+        buf,
+        protocol,
+        client_computer_name,
+        public_key,
+        kerberos_config,
+    )
+    .await;
 
     if protocol.intersects(nego::SecurityProtocol::HYBRID_EX) {
         trace!(?result, "HYBRID_EX");
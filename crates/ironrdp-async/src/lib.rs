@@ -8,6 +8,7 @@ pub use bytes;
 
 mod connector;
 mod framed;
+#[cfg(feature = "session")]
 mod session;
 
 use core::future::Future;
@@ -18,7 +19,8 @@ use ironrdp_connector::ConnectorResult;
 
 pub use self::connector::*;
 pub use self::framed::*;
-// pub use self::session::*;
+#[cfg(feature = "session")]
+pub use self::session::*;
 
 pub trait AsyncNetworkClient {
     fn send<'a>(
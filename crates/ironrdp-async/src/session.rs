@@ -1 +1,227 @@
-// TODO: active session async helpers
+use std::sync::Arc;
+
+use ironrdp_cliprdr::backend::{ClipboardError, ClipboardMessage};
+use ironrdp_cliprdr::CliprdrClient;
+use ironrdp_connector::connection_activation::{ConnectionActivationSequence, ConnectionActivationState};
+use ironrdp_connector::standard_security::SecuredSequence;
+use ironrdp_connector::{AutoReconnectCookie, DesktopSize};
+use ironrdp_core::WriteBuf;
+use ironrdp_displaycontrol::pdu::MonitorLayoutEntry;
+use ironrdp_graphics::pointer::DecodedPointer;
+use ironrdp_pdu::geometry::InclusiveRectangle;
+use ironrdp_pdu::input::fast_path::FastPathInputEvent;
+use ironrdp_session::image::DecodedImage;
+use ironrdp_session::{fast_path, ActiveStage, ActiveStageOutput, GracefulDisconnectReason, SessionResult};
+use ironrdp_svc::{SvcProcessor, SvcProcessorMessages};
+
+use crate::framed::{Framed, FramedRead, FramedWrite};
+use crate::single_sequence_step_read;
+
+/// Event produced by an active session
+#[derive(Debug)]
+pub enum SessionEvent {
+    /// A region of the image was updated
+    GraphicsUpdate(InclusiveRectangle),
+    PointerDefault,
+    PointerHidden,
+    PointerPosition {
+        x: u16,
+        y: u16,
+    },
+    PointerBitmap(Arc<DecodedPointer>),
+    /// Failure reported by the clipboard backend
+    ClipboardError(Box<dyn ClipboardError>),
+    /// The resize request could not be sent, because the Display Control Virtual Channel is not
+    /// available
+    ///
+    /// The only way to get the new size is to reconnect, e.g. with the auto-reconnect cookie.
+    ResizeUnsupported {
+        width: u16,
+        height: u16,
+    },
+    /// The Deactivation-Reactivation Sequence was executed, and the image was resized to the new
+    /// desktop size
+    DeactivateAll(DesktopSize),
+    /// See [`ActiveStageOutput::AutoReconnectCookie`]
+    AutoReconnectCookie(AutoReconnectCookie),
+    /// The session is over, no more events are produced
+    Terminated(GracefulDisconnectReason),
+}
+
+/// Command sent to an active session
+#[derive(Debug)]
+pub enum SessionCommand {
+    FastPathInput(Vec<FastPathInputEvent>),
+    /// Requests a new desktop size over the Display Control Virtual Channel
+    ///
+    /// The size is adjusted with [`MonitorLayoutEntry::adjust_display_size`] before being sent.
+    Resize {
+        width: u16,
+        height: u16,
+        scale_factor: Option<u32>,
+        /// The physical size of the display in millimeters (width, height).
+        physical_size: Option<(u32, u32)>,
+    },
+    Clipboard(ClipboardMessage),
+    SvcMessages(SvcMessages),
+    Shutdown,
+}
+
+type EncodeSvcMessages = Box<dyn FnOnce(&mut ActiveStage) -> SessionResult<Vec<u8>> + Send>;
+
+/// Type-erased [`SvcProcessorMessages`], to be sent on the static virtual channel of their processor
+pub struct SvcMessages {
+    encode: EncodeSvcMessages,
+}
+
+impl SvcMessages {
+    pub fn new<C: SvcProcessor + 'static>(messages: SvcProcessorMessages<C>) -> Self {
+        Self {
+            encode: Box::new(move |active_stage| active_stage.process_svc_processor_messages(messages)),
+        }
+    }
+}
+
+impl<C: SvcProcessor + 'static> From<SvcProcessorMessages<C>> for SvcMessages {
+    fn from(messages: SvcProcessorMessages<C>) -> Self {
+        Self::new(messages)
+    }
+}
+
+impl core::fmt::Debug for SvcMessages {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SvcMessages").finish_non_exhaustive()
+    }
+}
+
+/// Processes a command on the active stage
+///
+/// Returns the outputs to handle, and the event to report to the user, if any.
+pub fn process_session_command(
+    active_stage: &mut ActiveStage,
+    image: &mut DecodedImage,
+    command: SessionCommand,
+) -> SessionResult<(Vec<ActiveStageOutput>, Option<SessionEvent>)> {
+    match command {
+        SessionCommand::FastPathInput(events) => {
+            trace!(?events);
+            Ok((active_stage.process_fastpath_input(image, &events)?, None))
+        }
+        SessionCommand::Resize {
+            width,
+            height,
+            scale_factor,
+            physical_size,
+        } => {
+            let (width, height) = MonitorLayoutEntry::adjust_display_size(width.into(), height.into());
+            debug!(width, height, "Adjusted display size");
+
+            match active_stage.encode_resize(width, height, scale_factor, physical_size) {
+                Some(frame) => Ok((vec![ActiveStageOutput::ResponseFrame(frame?)], None)),
+                None => Ok((
+                    Vec::new(),
+                    Some(SessionEvent::ResizeUnsupported {
+                        width: u16::try_from(width).expect("adjusted width fits in u16"),
+                        height: u16::try_from(height).expect("adjusted height fits in u16"),
+                    }),
+                )),
+            }
+        }
+        SessionCommand::Clipboard(message) => process_clipboard_message(active_stage, message),
+        SessionCommand::SvcMessages(messages) => {
+            let frame = (messages.encode)(active_stage)?;
+            Ok((vec![ActiveStageOutput::ResponseFrame(frame)], None))
+        }
+        SessionCommand::Shutdown => Ok((active_stage.graceful_shutdown()?, None)),
+    }
+}
+
+fn process_clipboard_message(
+    active_stage: &mut ActiveStage,
+    message: ClipboardMessage,
+) -> SessionResult<(Vec<ActiveStageOutput>, Option<SessionEvent>)> {
+    let Some(cliprdr) = active_stage.get_svc_processor::<CliprdrClient>() else {
+        warn!("Clipboard message received, but CLIPRDR is not available");
+        return Ok((Vec::new(), None));
+    };
+
+    let svc_messages = match message {
+        ClipboardMessage::SendInitiateCopy(formats) => cliprdr.initiate_copy(&formats),
+        ClipboardMessage::SendFormatData(response) => cliprdr.submit_format_data(response),
+        ClipboardMessage::SendInitiatePaste(format) => cliprdr.initiate_paste(format),
+        ClipboardMessage::SendFileContents(response) => cliprdr.submit_file_contents(response),
+        ClipboardMessage::SendFileContentsRequest(request) => cliprdr.request_file_contents(request),
+        ClipboardMessage::Error(e) => return Ok((Vec::new(), Some(SessionEvent::ClipboardError(e)))),
+    }
+    .map_err(|e| ironrdp_session::custom_err!("CLIPRDR", e))?;
+
+    let frame = active_stage.process_svc_processor_messages(svc_messages)?;
+
+    Ok((vec![ActiveStageOutput::ResponseFrame(frame)], None))
+}
+
+/// Executes the [Deactivation-Reactivation Sequence] after a Server Deactivate All PDU
+///
+/// The active stage is updated with the new channel IDs and pointer settings, and the image is
+/// resized to the new desktop size, which is returned.
+///
+/// [Deactivation-Reactivation Sequence]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/dfc234ce-481a-4674-9a5d-2a7bafb14432
+pub async fn run_deactivation_reactivation<R, W>(
+    reader: &mut Framed<R>,
+    writer: &mut W,
+    active_stage: &mut ActiveStage,
+    image: &mut DecodedImage,
+    mut connection_activation: Box<ConnectionActivationSequence>,
+) -> SessionResult<DesktopSize>
+where
+    R: FramedRead,
+    W: FramedWrite,
+{
+    debug!("Received Server Deactivate All PDU, executing Deactivation-Reactivation Sequence");
+
+    let mut buf = WriteBuf::new();
+
+    loop {
+        let written = match active_stage.standard_security_mut() {
+            Some(security) => {
+                let mut sequence = SecuredSequence::new(&mut *connection_activation, security);
+                single_sequence_step_read(reader, &mut sequence, &mut buf).await
+            }
+            None => single_sequence_step_read(reader, &mut *connection_activation, &mut buf).await,
+        }
+        .map_err(|e| ironrdp_session::custom_err!("read deactivation-reactivation sequence step", e))?;
+
+        if written.size().is_some() {
+            writer
+                .write_all(buf.filled())
+                .await
+                .map_err(|e| ironrdp_session::custom_err!("write deactivation-reactivation sequence step", e))?;
+        }
+
+        if let ConnectionActivationState::Finalized {
+            io_channel_id,
+            user_channel_id,
+            desktop_size,
+            no_server_pointer,
+            pointer_software_rendering,
+        } = connection_activation.state
+        {
+            debug!(?desktop_size, "Deactivation-Reactivation Sequence completed");
+
+            *image = DecodedImage::new(image.pixel_format(), desktop_size.width, desktop_size.height);
+
+            active_stage.set_fastpath_processor(
+                fast_path::ProcessorBuilder {
+                    io_channel_id,
+                    user_channel_id,
+                    no_server_pointer,
+                    pointer_software_rendering,
+                }
+                .build(),
+            );
+            active_stage.set_no_server_pointer(no_server_pointer);
+
+            return Ok(desktop_size);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use core::num::ParseIntError;
 use core::str::FromStr;
 use std::io;
+use std::path::PathBuf;
 
 use anyhow::Context as _;
 use clap::clap_derive::ValueEnum;
@@ -8,6 +9,10 @@ use clap::Parser;
 use ironrdp::connector::{self, Credentials};
 use ironrdp::pdu::rdp::capability_sets::MajorPlatformType;
 use ironrdp::pdu::rdp::client_info::PerformanceFlags;
+use ironrdp::pdu::rdp::vc::dvc::gfx::{
+    CapabilitiesV103Flags, CapabilitiesV104Flags, CapabilitiesV107Flags, CapabilitiesV10Flags, CapabilitiesV81Flags,
+    CapabilitiesV8Flags, CapabilitySet,
+};
 use tap::prelude::*;
 
 const DEFAULT_WIDTH: u16 = 1920;
@@ -19,6 +24,12 @@ pub struct Config {
     pub destination: Destination,
     pub connector: connector::Config,
     pub clipboard_type: ClipboardType,
+    /// Graphics pipeline capability sets to advertise, if the graphics pipeline is enabled
+    pub gfx_capabilities: Option<Vec<CapabilitySet>>,
+    /// Directory of the persistent bitmaps sent by the server, if the bitmap caches are enabled
+    pub bitmap_cache_dir: Option<PathBuf>,
+    /// Directory redirected as a drive
+    pub drive: Option<PathBuf>,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -55,6 +66,25 @@ impl KeyboardType {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum CompressionType {
+    K8,
+    K64,
+    Rdp6,
+    Rdp61,
+}
+
+impl CompressionType {
+    fn parse(compression_type: CompressionType) -> ironrdp::pdu::rdp::client_info::CompressionType {
+        match compression_type {
+            CompressionType::K8 => ironrdp::pdu::rdp::client_info::CompressionType::K8,
+            CompressionType::K64 => ironrdp::pdu::rdp::client_info::CompressionType::K64,
+            CompressionType::Rdp6 => ironrdp::pdu::rdp::client_info::CompressionType::Rdp6,
+            CompressionType::Rdp61 => ironrdp::pdu::rdp::client_info::CompressionType::Rdp61,
+        }
+    }
+}
+
 fn parse_hex(input: &str) -> Result<u32, ParseIntError> {
     if input.starts_with("0x") {
         u32::from_str_radix(input.get(2..).unwrap_or(""), 16)
@@ -108,6 +138,15 @@ impl Destination {
         self.port
     }
 
+    /// Returns the destination of a server redirection, on the same port.
+    #[must_use]
+    pub fn redirected(&self, name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            port: self.port,
+        }
+    }
+
     pub fn lookup_addr(&self) -> io::Result<std::net::SocketAddr> {
         use std::net::ToSocketAddrs as _;
 
@@ -198,11 +237,19 @@ struct Args {
     #[clap(long)]
     no_server_pointer: bool,
 
-    /// Enabled capability versions. Each bit represents enabling a capability version
+    /// Enabled graphics pipeline capability versions. Each bit represents enabling a capability version
     /// starting from V8 to V10_7
+    ///
+    /// The graphics pipeline is enabled when at least one capability version is enabled.
     #[clap(long, value_parser = parse_hex, default_value_t = 0)]
     capabilities: u32,
 
+    /// Enable bulk compression of the PDUs sent by the server, up to the given compression type
+    ///
+    /// k8 and k64 are MPPC with an 8K and a 64K history, rdp6 is NCRUSH and rdp61 is XCRUSH.
+    #[clap(long, value_enum, value_parser)]
+    compression_type: Option<CompressionType>,
+
     /// Automatically logon to the server by passing the INFO_AUTOLOGON flag
     ///
     /// This flag is ignored if CredSSP authentication is used.
@@ -223,9 +270,29 @@ struct Args {
     #[clap(long, alias = "no-nla")]
     no_credssp: bool,
 
+    /// Allow Standard RDP Security (RC4 or FIPS encryption without TLS) for old servers
+    ///
+    /// The server certificate is not verified, and the encryption is weak. Connections using it are
+    /// exposed to man-in-the-middle attacks.
+    #[clap(long)]
+    standard_rdp_security: bool,
+
     /// The clipboard type
     #[clap(long, value_enum, value_parser, default_value_t = ClipboardType::Default)]
     clipboard_type: ClipboardType,
+
+    /// Enable the bitmap caches of the drawing orders, keeping the bitmaps in this directory
+    ///
+    /// The bitmaps of each server are stored in their own subdirectory, and are not sent again
+    /// by the server on the next connections.
+    #[clap(long, value_parser)]
+    bitmap_cache_dir: Option<PathBuf>,
+
+    /// Redirect a local directory as a drive, named after the directory
+    ///
+    /// Only supported on Linux and macOS.
+    #[clap(long, value_parser)]
+    drive: Option<PathBuf>,
 }
 
 impl Config {
@@ -282,11 +349,62 @@ impl Config {
             args.clipboard_type
         };
 
+        let gfx_capabilities = if args.capabilities != 0 {
+            let mut v8_flags = CapabilitiesV8Flags::empty();
+            v8_flags.set(CapabilitiesV8Flags::THIN_CLIENT, args.thin_client);
+            v8_flags.set(CapabilitiesV8Flags::SMALL_CACHE, args.small_cache);
+
+            let mut v8_1_flags = CapabilitiesV81Flags::empty();
+            v8_1_flags.set(CapabilitiesV81Flags::THIN_CLIENT, args.thin_client);
+            v8_1_flags.set(CapabilitiesV81Flags::SMALL_CACHE, args.small_cache);
+
+            // AVC is not supported by the client, so it is always disabled from V10 onwards.
+            let mut v10_flags = CapabilitiesV10Flags::AVC_DISABLED;
+            v10_flags.set(CapabilitiesV10Flags::SMALL_CACHE, args.small_cache);
+
+            let mut v10_4_flags = CapabilitiesV104Flags::AVC_DISABLED;
+            v10_4_flags.set(CapabilitiesV104Flags::SMALL_CACHE, args.small_cache);
+
+            let mut v10_7_flags = CapabilitiesV107Flags::AVC_DISABLED;
+            v10_7_flags.set(CapabilitiesV107Flags::SMALL_CACHE, args.small_cache);
+
+            let versions = [
+                CapabilitySet::V8 { flags: v8_flags },
+                CapabilitySet::V8_1 { flags: v8_1_flags },
+                CapabilitySet::V10 { flags: v10_flags },
+                CapabilitySet::V10_1,
+                CapabilitySet::V10_2 { flags: v10_flags },
+                CapabilitySet::V10_3 {
+                    flags: CapabilitiesV103Flags::AVC_DISABLED,
+                },
+                CapabilitySet::V10_4 { flags: v10_4_flags },
+                CapabilitySet::V10_5 { flags: v10_4_flags },
+                CapabilitySet::V10_6 { flags: v10_4_flags },
+                CapabilitySet::V10_7 { flags: v10_7_flags },
+            ];
+
+            let capabilities = versions
+                .into_iter()
+                .enumerate()
+                .filter(|(bit, _)| args.capabilities & (1 << bit) != 0)
+                .map(|(_, capability)| capability)
+                .collect::<Vec<_>>();
+
+            if capabilities.is_empty() {
+                anyhow::bail!("Invalid capabilities. At least one of the 10 lowest bits must be set.");
+            }
+
+            Some(capabilities)
+        } else {
+            None
+        };
+
         let connector = connector::Config {
             credentials: Credentials::UsernamePassword { username, password },
             domain: args.domain,
             enable_tls: !args.no_tls,
             enable_credssp: !args.no_credssp,
+            enable_standard_rdp_security: args.standard_rdp_security,
             keyboard_type: KeyboardType::parse(args.keyboard_type),
             keyboard_subtype: args.keyboard_subtype,
             keyboard_layout: 0, // the server SHOULD use the default active input locale identifier
@@ -299,6 +417,7 @@ impl Config {
             },
             desktop_scale_factor: 0, // Default to 0 per FreeRDP
             bitmap,
+            bitmap_cache: args.bitmap_cache_dir.is_some().then(bitmap_cache_config),
             client_build: semver::Version::parse(env!("CARGO_PKG_VERSION"))
                 .map(|version| version.major * 100 + version.minor * 10 + version.patch)
                 .unwrap_or(0)
@@ -320,16 +439,44 @@ impl Config {
             license_cache: None,
             no_server_pointer: args.no_server_pointer,
             autologon: args.autologon,
+            enable_gfx: gfx_capabilities.is_some(),
+            compression_type: args.compression_type.map(CompressionType::parse),
+            auto_reconnect: None,
+            redirected_session_id: None,
             request_data: None,
             pointer_software_rendering: true,
             performance_flags: PerformanceFlags::default(),
         };
 
+        let bitmap_cache_dir = args.bitmap_cache_dir.map(|directory| {
+            let server = format!("{}_{}", destination.name(), destination.port())
+                .replace(|c: char| !c.is_ascii_alphanumeric() && c != '.' && c != '-', "_");
+
+            directory.join(server)
+        });
+
         Ok(Self {
             log_file: args.log_file,
             destination,
             connector,
             clipboard_type,
+            gfx_capabilities,
+            bitmap_cache_dir,
+            drive: args.drive,
         })
     }
 }
+
+/// Cell caches of the bitmaps, from the smallest bitmaps to the biggest ones
+fn bitmap_cache_config() -> connector::BitmapCacheConfig {
+    let cell_caches = [600, 600, 2048, 4096, 2048]
+        .into_iter()
+        .map(|entries| connector::CellCacheConfig {
+            entries,
+            persistent: true,
+            persistent_keys: Vec::new(),
+        })
+        .collect();
+
+    connector::BitmapCacheConfig { cell_caches }
+}
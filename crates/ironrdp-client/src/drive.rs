@@ -0,0 +1,213 @@
+//! Drive redirection, with the native RDPDR backend
+
+#[cfg(target_os = "linux")]
+use std::os::fd::OwnedFd;
+use std::path::Path;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use ironrdp::pdu::PduResult;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use ironrdp::rdpdr::pdu::efs::{DeviceControlRequest, ServerDeviceAnnounceResponse, ServerDriveIoRequest};
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use ironrdp::rdpdr::pdu::esc::{ScardCall, ScardIoCtlCode};
+use ironrdp::rdpdr::{self, NoopRdpdrBackend, RdpdrBackend};
+use ironrdp::session::SessionResult;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use ironrdp::svc::SvcMessage;
+use ironrdp::svc::SvcProcessorMessages;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use ironrdp_core::impl_as_any;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use ironrdp_rdpdr_native::backend::NixRdpdrBackend;
+#[cfg(target_os = "linux")]
+use tokio::io::unix::AsyncFd;
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use tokio::sync::Notify;
+
+/// Device ID of the redirected drive, the smart card being the device 0
+const DRIVE_DEVICE_ID: u32 = 1;
+
+/// Builds the RDPDR channel, redirecting the smart card and the `drive` directory if any
+///
+/// The drive requests which don't complete immediately are completed with the returned [`PendingDriveRequests`].
+pub fn build_rdpdr(drive: Option<&Path>, computer_name: String) -> (rdpdr::Rdpdr, PendingDriveRequests) {
+    let Some(drive) = drive else {
+        let rdpdr = rdpdr::Rdpdr::new(Box::new(NoopRdpdrBackend {}), computer_name).with_smartcard(0);
+        return (rdpdr, PendingDriveRequests::default());
+    };
+
+    let name = drive
+        .file_name()
+        .map_or_else(|| "IronRDP".to_owned(), |name| name.to_string_lossy().into_owned());
+
+    let (backend, pending_requests) = drive_backend(drive);
+    let rdpdr = rdpdr::Rdpdr::new(backend, computer_name)
+        .with_smartcard(0)
+        .with_drives(Some(vec![(DRIVE_DEVICE_ID, name)]));
+
+    (rdpdr, pending_requests)
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn drive_backend(drive: &Path) -> (Box<dyn RdpdrBackend>, PendingDriveRequests) {
+    let backend = SharedDriveBackend {
+        backend: Arc::new(Mutex::new(NixRdpdrBackend::new(drive.to_string_lossy().into_owned()))),
+        updated: Arc::new(Notify::new()),
+    };
+    let pending_requests = PendingDriveRequests {
+        backend: Some(Arc::clone(&backend.backend)),
+        updated: Arc::clone(&backend.updated),
+        ..PendingDriveRequests::default()
+    };
+
+    (Box::new(backend), pending_requests)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn drive_backend(_: &Path) -> (Box<dyn RdpdrBackend>, PendingDriveRequests) {
+    warn!("Drive redirection is not supported on this platform");
+    (Box::new(NoopRdpdrBackend {}), PendingDriveRequests::default())
+}
+
+/// Native backend of the RDPDR channel, shared with the [`PendingDriveRequests`]
+///
+/// The active session owns the RDPDR channel while it waits for the server, so the pending
+/// requests are completed through this shared backend rather than through the channel.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+#[derive(Debug)]
+struct SharedDriveBackend {
+    backend: Arc<Mutex<NixRdpdrBackend>>,
+    /// Notified when the backend handled new requests
+    updated: Arc<Notify>,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+impl_as_any!(SharedDriveBackend);
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+impl RdpdrBackend for SharedDriveBackend {
+    fn handle_server_device_announce_response(&mut self, pdu: ServerDeviceAnnounceResponse) -> PduResult<()> {
+        lock(&self.backend).handle_server_device_announce_response(pdu)
+    }
+
+    fn handle_scard_call(&mut self, req: DeviceControlRequest<ScardIoCtlCode>, call: ScardCall) -> PduResult<()> {
+        lock(&self.backend).handle_scard_call(req, call)
+    }
+
+    fn handle_drive_io_request(&mut self, req: ServerDriveIoRequest) -> PduResult<Vec<SvcMessage>> {
+        let messages = lock(&self.backend).handle_drive_io_request(req);
+        self.updated.notify_one();
+        messages
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn lock(backend: &Mutex<NixRdpdrBackend>) -> MutexGuard<'_, NixRdpdrBackend> {
+    backend.lock().expect("drive backend lock poisoned")
+}
+
+/// The requests of the drive backend which complete later, once a directory changed or a lock was released
+#[derive(Default)]
+pub struct PendingDriveRequests {
+    /// `None` when no drive is redirected
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    backend: Option<Arc<Mutex<NixRdpdrBackend>>>,
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    updated: Arc<Notify>,
+    /// Readable when a watched directory changed
+    #[cfg(target_os = "linux")]
+    change_notifications: Option<AsyncFd<OwnedFd>>,
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    waiting_for_locks: bool,
+}
+
+impl PendingDriveRequests {
+    /// Delay between the attempts to take the awaited locks
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    const LOCK_RETRY_INTERVAL: core::time::Duration = core::time::Duration::from_millis(100);
+
+    /// Updates the state of the pending requests, after the backend handled new requests
+    pub fn update(&mut self) {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        if let Some(backend) = &self.backend {
+            let backend = lock(backend);
+            self.waiting_for_locks = backend.is_waiting_for_locks();
+
+            // The file descriptor is created with the first change notification request.
+            #[cfg(target_os = "linux")]
+            if self.change_notifications.is_none() {
+                if let Some(fd) = backend.change_notification_fd() {
+                    match fd.try_clone_to_owned().and_then(AsyncFd::new) {
+                        Ok(fd) => self.change_notifications = Some(fd),
+                        Err(error) => warn!(%error, "Failed to wait for the directory change notifications"),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Waits until the backend handled new requests, see [`PendingDriveRequests::update`]
+    pub async fn updated(&self) {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        self.updated.notified().await;
+
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        core::future::pending::<()>().await;
+    }
+
+    /// Waits until some requests may be completed with [`PendingDriveRequests::process`]
+    pub async fn ready(&self) {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        let locks = async {
+            if self.waiting_for_locks {
+                tokio::time::sleep(Self::LOCK_RETRY_INTERVAL).await;
+            } else {
+                core::future::pending::<()>().await;
+            }
+        };
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        let locks = core::future::pending::<()>();
+
+        #[cfg(target_os = "linux")]
+        let changes = async {
+            match self.change_notifications.as_ref().map(AsyncFd::readable) {
+                // The events are all read when processing the requests.
+                Some(readable) => match readable.await {
+                    Ok(mut guard) => guard.clear_ready(),
+                    Err(error) => {
+                        warn!(%error, "Failed to wait for the directory change notifications");
+                        core::future::pending::<()>().await;
+                    }
+                },
+                None => core::future::pending::<()>().await,
+            }
+        };
+        #[cfg(not(target_os = "linux"))]
+        let changes = core::future::pending::<()>();
+
+        tokio::select! {
+            () = locks => {}
+            () = changes => {}
+        }
+    }
+
+    /// Completes the requests which are ready, and returns the responses to send to the server, if any
+    pub fn process(&mut self) -> SessionResult<Option<SvcProcessorMessages<rdpdr::Rdpdr>>> {
+        #[cfg(any(target_os = "linux", target_os = "macos"))]
+        if let Some(backend) = &self.backend {
+            let mut backend = lock(backend);
+            let messages = backend
+                .process_pending_requests()
+                .map_err(|e| ironrdp::session::custom_err!("RDPDR", e))?;
+            self.waiting_for_locks = backend.is_waiting_for_locks();
+
+            if !messages.is_empty() {
+                return Ok(Some(messages.into()));
+            }
+        }
+
+        Ok(None)
+    }
+}
@@ -1,23 +1,26 @@
+use core::time::Duration;
+
 use ironrdp::cliprdr::backend::{ClipboardMessage, CliprdrBackendFactory};
-use ironrdp::connector::connection_activation::ConnectionActivationState;
-use ironrdp::connector::{ConnectionResult, ConnectorResult};
+use ironrdp::connector::{AutoReconnectCookie, ConnectionResult, ConnectorErrorKind, ConnectorResult};
 use ironrdp::displaycontrol::client::DisplayControlClient;
-use ironrdp::displaycontrol::pdu::MonitorLayoutEntry;
 use ironrdp::graphics::image_processing::PixelFormat;
 use ironrdp::pdu::input::fast_path::FastPathInputEvent;
+use ironrdp::pdu::rdp::server_redirection::{ServerRedirectionFlags, ServerRedirectionPdu};
+use ironrdp::session::bitmap_cache::{BitmapCache, FileBitmapStore};
+use ironrdp::session::gfx::GfxClient;
 use ironrdp::session::image::DecodedImage;
-use ironrdp::session::{fast_path, ActiveStage, ActiveStageOutput, GracefulDisconnectReason, SessionResult};
-use ironrdp::{cliprdr, connector, rdpdr, rdpsnd, session};
-use ironrdp_core::WriteBuf;
+use ironrdp::session::{ActiveStage, GracefulDisconnectReason, SessionError, SessionResult};
+use ironrdp::{cliprdr, connector, rdpsnd, session};
 use ironrdp_rdpsnd_native::cpal;
-use ironrdp_tokio::{single_sequence_step_read, split_tokio_framed, FramedWrite};
-use rdpdr::NoopRdpdrBackend;
+use ironrdp_tokio::{ActiveSession, SessionCommand, SessionEvent};
 use smallvec::SmallVec;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc;
 use winit::event_loop::EventLoopProxy;
 
 use crate::config::Config;
+use crate::drive::{build_rdpdr, PendingDriveRequests};
 
 #[derive(Debug)]
 pub enum RdpOutputEvent {
@@ -57,19 +60,49 @@ pub struct RdpClient {
 }
 
 impl RdpClient {
+    /// Number of times the client tries to reconnect to its session after losing the connection
+    const MAX_RECONNECT_ATTEMPTS: u32 = 5;
+
+    /// Number of server redirections followed when connecting, to avoid redirection loops
+    const MAX_REDIRECTIONS: u32 = 3;
+
     pub async fn run(mut self) {
+        // Number of failed attempts to reconnect, when the connection was lost
+        let mut failed_reconnect_attempts = None;
+        let mut redirections = 0;
+
         loop {
-            let (connection_result, framed) = match connect(&self.config, self.cliprdr_factory.as_deref()).await {
-                Ok(result) => result,
-                Err(e) => {
-                    let _ = self.event_loop_proxy.send_event(RdpOutputEvent::ConnectionFailure(e));
-                    break;
-                }
-            };
+            let (connection_result, framed, bitmap_cache, drive_requests) =
+                match connect(&self.config, self.cliprdr_factory.as_deref()).await {
+                    Ok(result) => result,
+                    Err(e) => match (e.kind(), failed_reconnect_attempts) {
+                        (ConnectorErrorKind::Redirection(redirection), _) if redirections < Self::MAX_REDIRECTIONS => {
+                            redirections += 1;
+                            self.apply_redirection(redirection);
+                            continue;
+                        }
+                        (_, Some(attempts)) if attempts + 1 < Self::MAX_RECONNECT_ATTEMPTS => {
+                            warn!(error = %e.report(), attempts, "Failed to reconnect");
+                            tokio::time::sleep(reconnect_delay(attempts)).await;
+                            failed_reconnect_attempts = Some(attempts + 1);
+                            continue;
+                        }
+                        _ => {
+                            let _ = self.event_loop_proxy.send_event(RdpOutputEvent::ConnectionFailure(e));
+                            break;
+                        }
+                    },
+                };
+
+            failed_reconnect_attempts = None;
+            redirections = 0;
 
             match active_session(
                 framed,
                 connection_result,
+                bitmap_cache,
+                drive_requests,
+                &mut self.config.connector.auto_reconnect,
                 &self.event_loop_proxy,
                 &mut self.input_event_receiver,
             )
@@ -79,6 +112,16 @@ impl RdpClient {
                     self.config.connector.desktop_size.width = width;
                     self.config.connector.desktop_size.height = height;
                 }
+                Ok(RdpControlFlow::ConnectionLost(e)) => {
+                    if self.config.connector.auto_reconnect.is_none() {
+                        let _ = self.event_loop_proxy.send_event(RdpOutputEvent::Terminated(Err(e)));
+                        break;
+                    }
+
+                    // The auto-reconnect cookie lets the server give us back the same session.
+                    warn!(error = %e.report(), "Connection lost, reconnecting");
+                    failed_reconnect_attempts = Some(0);
+                }
                 Ok(RdpControlFlow::TerminatedGracefully(reason)) => {
                     let _ = self.event_loop_proxy.send_event(RdpOutputEvent::Terminated(Ok(reason)));
                     break;
@@ -92,17 +135,64 @@ impl RdpClient {
     }
 }
 
+impl RdpClient {
+    fn apply_redirection(&mut self, redirection: &ServerRedirectionPdu) {
+        // With the NO_REDIRECT flag, the client reconnects to the same server with the redirection info.
+        if let Some(address) = redirection
+            .target_address()
+            .filter(|_| !redirection.flags.contains(ServerRedirectionFlags::NO_REDIRECT))
+        {
+            info!(address, "Redirected to another server");
+            self.config.destination = self.config.destination.redirected(address);
+        } else {
+            info!("Redirected to the same server");
+        }
+
+        self.config.connector.apply_redirection(redirection);
+    }
+}
+
 enum RdpControlFlow {
     ReconnectWithNewSize { width: u16, height: u16 },
     TerminatedGracefully(GracefulDisconnectReason),
+    ConnectionLost(SessionError),
+}
+
+/// Returns the delay before a reconnection attempt, doubled after each failed attempt.
+fn reconnect_delay(failed_attempts: u32) -> Duration {
+    Duration::from_secs(1 << failed_attempts.min(4))
+}
+
+/// Returns whether the session failed to read from or write to the server, rather than to process the PDUs
+fn is_connection_lost(error: &SessionError) -> bool {
+    let mut source = std::error::Error::source(error);
+
+    while let Some(error) = source {
+        if error.is::<std::io::Error>() {
+            return true;
+        }
+        source = error.source();
+    }
+
+    false
 }
 
-type UpgradedFramed = ironrdp_tokio::TokioFramed<ironrdp_tls::TlsStream<TcpStream>>;
+/// Either the TLS stream, or the TCP stream itself when Standard RDP Security is used
+trait AsyncReadWrite: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
+
+impl<T> AsyncReadWrite for T where T: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
+
+type UpgradedFramed = ironrdp_tokio::TokioFramed<Box<dyn AsyncReadWrite>>;
 
 async fn connect(
     config: &Config,
     cliprdr_factory: Option<&(dyn CliprdrBackendFactory + Send)>,
-) -> ConnectorResult<(ConnectionResult, UpgradedFramed)> {
+) -> ConnectorResult<(
+    ConnectionResult,
+    UpgradedFramed,
+    Option<BitmapCache>,
+    PendingDriveRequests,
+)> {
     let dest = format!("{}:{}", config.destination.name(), config.destination.port());
 
     let stream = TcpStream::connect(dest)
@@ -115,13 +205,34 @@ async fn connect(
 
     let mut framed = ironrdp_tokio::TokioFramed::new(stream);
 
-    let mut connector = connector::ClientConnector::new(config.connector.clone())
+    let mut drdynvc =
+        ironrdp::dvc::DrdynvcClient::new().with_dynamic_channel(DisplayControlClient::new(|_| Ok(Vec::new())));
+
+    if let Some(capabilities) = &config.gfx_capabilities {
+        drdynvc = drdynvc.with_dynamic_channel(GfxClient::with_capabilities(capabilities.clone()));
+    }
+
+    let mut connector_config = config.connector.clone();
+
+    // The persistent bitmaps are loaded before connecting, so that their keys are sent to the server.
+    let bitmap_cache = match (&connector_config.bitmap_cache, &config.bitmap_cache_dir) {
+        (Some(bitmap_cache_config), Some(directory)) => {
+            let mut bitmap_cache = BitmapCache::new(bitmap_cache_config);
+            bitmap_cache.load_persistent(Box::new(FileBitmapStore::new(directory)));
+            connector_config.bitmap_cache = Some(bitmap_cache.config());
+            Some(bitmap_cache)
+        }
+        (Some(bitmap_cache_config), None) => Some(BitmapCache::new(bitmap_cache_config)),
+        (None, _) => None,
+    };
+
+    let (rdpdr, drive_requests) = build_rdpdr(config.drive.as_deref(), "IronRDP".to_owned());
+
+    let mut connector = connector::ClientConnector::new(connector_config)
         .with_server_addr(server_addr)
-        .with_static_channel(
-            ironrdp::dvc::DrdynvcClient::new().with_dynamic_channel(DisplayControlClient::new(|_| Ok(Vec::new()))),
-        )
+        .with_static_channel(drdynvc)
         .with_static_channel(rdpsnd::client::Rdpsnd::new(Box::new(cpal::RdpsndBackend::new())))
-        .with_static_channel(rdpdr::Rdpdr::new(Box::new(NoopRdpdrBackend {}), "IronRDP".to_owned()).with_smartcard(0));
+        .with_static_channel(rdpdr);
 
     if let Some(builder) = cliprdr_factory {
         let backend = builder.build_cliprdr_backend();
@@ -133,14 +244,21 @@ async fn connect(
 
     let should_upgrade = ironrdp_tokio::connect_begin(&mut framed, &mut connector).await?;
 
-    debug!("TLS upgrade");
-
     // Ensure there is no leftover
     let initial_stream = framed.into_inner_no_leftover();
 
-    let (upgraded_stream, server_public_key) = ironrdp_tls::upgrade(initial_stream, config.destination.name())
-        .await
-        .map_err(|e| connector::custom_err!("TLS upgrade", e))?;
+    let (upgraded_stream, server_public_key): (Box<dyn AsyncReadWrite>, _) = if connector.uses_standard_rdp_security() {
+        warn!("Server selected Standard RDP Security, the connection is not protected by TLS");
+        (Box::new(initial_stream), Vec::new())
+    } else {
+        debug!("TLS upgrade");
+
+        let (upgraded_stream, server_public_key) = ironrdp_tls::upgrade(initial_stream, config.destination.name())
+            .await
+            .map_err(|e| connector::custom_err!("TLS upgrade", e))?;
+
+        (Box::new(upgraded_stream), server_public_key)
+    };
 
     let upgraded = ironrdp_tokio::mark_as_upgraded(should_upgrade, &mut connector);
 
@@ -160,17 +278,19 @@ async fn connect(
 
     debug!(?connection_result);
 
-    Ok((connection_result, upgraded_framed))
+    Ok((connection_result, upgraded_framed, bitmap_cache, drive_requests))
 }
 
 async fn active_session(
     framed: UpgradedFramed,
     connection_result: ConnectionResult,
+    bitmap_cache: Option<BitmapCache>,
+    mut drive_requests: PendingDriveRequests,
+    auto_reconnect: &mut Option<AutoReconnectCookie>,
     event_loop_proxy: &EventLoopProxy<RdpOutputEvent>,
     input_event_receiver: &mut mpsc::UnboundedReceiver<RdpInputEvent>,
 ) -> SessionResult<RdpControlFlow> {
-    let (mut reader, mut writer) = split_tokio_framed(framed);
-    let mut image = DecodedImage::new(
+    let image = DecodedImage::new(
         PixelFormat::RgbA32,
         connection_result.desktop_size.width,
         connection_result.desktop_size.height,
@@ -178,13 +298,88 @@ async fn active_session(
 
     let mut active_stage = ActiveStage::new(connection_result);
 
-    let disconnect_reason = 'outer: loop {
-        let outputs = tokio::select! {
-            frame = reader.read_pdu() => {
-                let (action, payload) = frame.map_err(|e| session::custom_err!("read frame", e))?;
-                trace!(?action, frame_length = payload.len(), "Frame received");
+    if let Some(bitmap_cache) = bitmap_cache {
+        active_stage.set_bitmap_cache(bitmap_cache);
+    }
 
-                active_stage.process(&mut image, action, &payload)?
+    let mut session = ActiveSession::new(framed, active_stage, image);
+    let handle = session.handle();
+
+    loop {
+        drive_requests.update();
+
+        tokio::select! {
+            event = session.next_event() => {
+                let event = match event {
+                    Some(Ok(event)) => event,
+                    Some(Err(e)) if is_connection_lost(&e) => return Ok(RdpControlFlow::ConnectionLost(e)),
+                    Some(Err(e)) => return Err(e),
+                    None => return Err(session::general_err!("session ended without termination")),
+                };
+
+                match event {
+                    SessionEvent::GraphicsUpdate(_region) => {
+                        let image = session.image().expect("no step in progress after an event");
+                        let buffer: Vec<u32> = image
+                            .data()
+                            .chunks_exact(4)
+                            .map(|pixel| {
+                                let r = pixel[0];
+                                let g = pixel[1];
+                                let b = pixel[2];
+                                u32::from_be_bytes([0, r, g, b])
+                            })
+                            .collect();
+
+                        event_loop_proxy
+                            .send_event(RdpOutputEvent::Image {
+                                buffer,
+                                width: image.width(),
+                                height: image.height(),
+                            })
+                            .map_err(|e| session::custom_err!("event_loop_proxy", e))?;
+                    }
+                    SessionEvent::PointerDefault => {
+                        event_loop_proxy
+                            .send_event(RdpOutputEvent::PointerDefault)
+                            .map_err(|e| session::custom_err!("event_loop_proxy", e))?;
+                    }
+                    SessionEvent::PointerHidden => {
+                        event_loop_proxy
+                            .send_event(RdpOutputEvent::PointerHidden)
+                            .map_err(|e| session::custom_err!("event_loop_proxy", e))?;
+                    }
+                    SessionEvent::PointerPosition { x, y } => {
+                        event_loop_proxy
+                            .send_event(RdpOutputEvent::PointerPosition { x, y })
+                            .map_err(|e| session::custom_err!("event_loop_proxy", e))?;
+                    }
+                    SessionEvent::PointerBitmap(_) => {
+                        // Not applicable, because we use the software cursor rendering.
+                    }
+                    SessionEvent::ClipboardError(e) => {
+                        error!("Clipboard backend error: {}", e);
+                    }
+                    SessionEvent::ResizeUnsupported { width, height } => {
+                        // The auto-reconnect cookie, if any, is used to get the same session back.
+                        debug!("Reconnecting with new size");
+                        return Ok(RdpControlFlow::ReconnectWithNewSize { width, height });
+                    }
+                    SessionEvent::DeactivateAll(desktop_size) => {
+                        debug!(?desktop_size, "Desktop resized by the server");
+                    }
+                    SessionEvent::AutoReconnectCookie(cookie) => {
+                        debug!("Received the auto-reconnect cookie");
+                        *auto_reconnect = Some(cookie);
+                    }
+                    SessionEvent::Terminated(reason) => return Ok(RdpControlFlow::TerminatedGracefully(reason)),
+                }
+            }
+            () = drive_requests.updated() => {}
+            () = drive_requests.ready() => {
+                if let Some(messages) = drive_requests.process()? {
+                    handle.send_svc_messages(messages)?;
+                }
             }
             input_event = input_event_receiver.recv() => {
                 let input_event = input_event.ok_or_else(|| session::general_err!("GUI is stopped"))?;
@@ -192,149 +387,20 @@ async fn active_session(
                 match input_event {
                     RdpInputEvent::Resize { width, height, scale_factor, physical_size } => {
                         trace!(width, height, "Resize event");
-                        let (width, height) = MonitorLayoutEntry::adjust_display_size(width.into(), height.into());
-                        debug!(width, height, "Adjusted display size");
-                        if let Some(response_frame) = active_stage.encode_resize(width, height, Some(scale_factor), physical_size) {
-                            vec![ActiveStageOutput::ResponseFrame(response_frame?)]
-                        } else {
-                            // TODO(#271): use the "auto-reconnect cookie": https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/15b0d1c9-2891-4adb-a45e-deb4aeeeab7c
-                            debug!("Reconnecting with new size");
-                            return Ok(RdpControlFlow::ReconnectWithNewSize { width: width.try_into().unwrap(), height: height.try_into().unwrap() })
-                        }
-                    },
+                        handle.resize(width, height, Some(scale_factor), physical_size)?;
+                    }
                     RdpInputEvent::FastPath(events) => {
                         trace!(?events);
-                        active_stage.process_fastpath_input(&mut image, &events)?
+                        handle.send_fastpath_input(events.into_vec())?;
                     }
                     RdpInputEvent::Close => {
-                        active_stage.graceful_shutdown()?
+                        handle.shutdown()?;
                     }
-                    RdpInputEvent::Clipboard(event) => {
-                        if let Some(cliprdr) = active_stage.get_svc_processor::<cliprdr::CliprdrClient>() {
-                            if let Some(svc_messages) = match event {
-                                ClipboardMessage::SendInitiateCopy(formats) => {
-                                    Some(cliprdr.initiate_copy(&formats)
-                                        .map_err(|e| session::custom_err!("CLIPRDR", e))?)
-                                }
-                                ClipboardMessage::SendFormatData(response) => {
-                                    Some(cliprdr.submit_format_data(response)
-                                    .map_err(|e| session::custom_err!("CLIPRDR", e))?)
-                                }
-                                ClipboardMessage::SendInitiatePaste(format) => {
-                                    Some(cliprdr.initiate_paste(format)
-                                        .map_err(|e| session::custom_err!("CLIPRDR", e))?)
-                                }
-                                ClipboardMessage::Error(e) => {
-                                    error!("Clipboard backend error: {}", e);
-                                    None
-                                }
-                            } {
-                                let frame = active_stage.process_svc_processor_messages(svc_messages)?;
-                                // Send the messages to the server
-                                vec![ActiveStageOutput::ResponseFrame(frame)]
-                            } else {
-                                // No messages to send to the server
-                                Vec::new()
-                            }
-                        } else  {
-                            warn!("Clipboard event received, but Cliprdr is not available");
-                            Vec::new()
-                        }
+                    RdpInputEvent::Clipboard(message) => {
+                        handle.send(SessionCommand::Clipboard(message))?;
                     }
                 }
             }
-        };
-
-        for out in outputs {
-            match out {
-                ActiveStageOutput::ResponseFrame(frame) => writer
-                    .write_all(&frame)
-                    .await
-                    .map_err(|e| session::custom_err!("write response", e))?,
-                ActiveStageOutput::GraphicsUpdate(_region) => {
-                    let buffer: Vec<u32> = image
-                        .data()
-                        .chunks_exact(4)
-                        .map(|pixel| {
-                            let r = pixel[0];
-                            let g = pixel[1];
-                            let b = pixel[2];
-                            u32::from_be_bytes([0, r, g, b])
-                        })
-                        .collect();
-
-                    event_loop_proxy
-                        .send_event(RdpOutputEvent::Image {
-                            buffer,
-                            width: image.width(),
-                            height: image.height(),
-                        })
-                        .map_err(|e| session::custom_err!("event_loop_proxy", e))?;
-                }
-                ActiveStageOutput::PointerDefault => {
-                    event_loop_proxy
-                        .send_event(RdpOutputEvent::PointerDefault)
-                        .map_err(|e| session::custom_err!("event_loop_proxy", e))?;
-                }
-                ActiveStageOutput::PointerHidden => {
-                    event_loop_proxy
-                        .send_event(RdpOutputEvent::PointerHidden)
-                        .map_err(|e| session::custom_err!("event_loop_proxy", e))?;
-                }
-                ActiveStageOutput::PointerPosition { x, y } => {
-                    event_loop_proxy
-                        .send_event(RdpOutputEvent::PointerPosition { x, y })
-                        .map_err(|e| session::custom_err!("event_loop_proxy", e))?;
-                }
-                ActiveStageOutput::PointerBitmap(_) => {
-                    // Not applicable, because we use the software cursor rendering.
-                }
-                ActiveStageOutput::DeactivateAll(mut connection_activation) => {
-                    // Execute the Deactivation-Reactivation Sequence:
-                    // https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/dfc234ce-481a-4674-9a5d-2a7bafb14432
-                    debug!("Received Server Deactivate All PDU, executing Deactivation-Reactivation Sequence");
-                    let mut buf = WriteBuf::new();
-                    'activation_seq: loop {
-                        let written = single_sequence_step_read(&mut reader, &mut *connection_activation, &mut buf)
-                            .await
-                            .map_err(|e| session::custom_err!("read deactivation-reactivation sequence step", e))?;
-
-                        if written.size().is_some() {
-                            writer.write_all(buf.filled()).await.map_err(|e| {
-                                session::custom_err!("write deactivation-reactivation sequence step", e)
-                            })?;
-                        }
-
-                        if let ConnectionActivationState::Finalized {
-                            io_channel_id,
-                            user_channel_id,
-                            desktop_size,
-                            no_server_pointer,
-                            pointer_software_rendering,
-                        } = connection_activation.state
-                        {
-                            debug!(?desktop_size, "Deactivation-Reactivation Sequence completed");
-                            // Update image size with the new desktop size.
-                            image = DecodedImage::new(PixelFormat::RgbA32, desktop_size.width, desktop_size.height);
-                            // Update the active stage with the new channel IDs and pointer settings.
-                            active_stage.set_fastpath_processor(
-                                fast_path::ProcessorBuilder {
-                                    io_channel_id,
-                                    user_channel_id,
-                                    no_server_pointer,
-                                    pointer_software_rendering,
-                                }
-                                .build(),
-                            );
-                            active_stage.set_no_server_pointer(no_server_pointer);
-                            break 'activation_seq;
-                        }
-                    }
-                }
-                ActiveStageOutput::Terminate(reason) => break 'outer reason,
-            }
         }
-    };
-
-    Ok(RdpControlFlow::TerminatedGracefully(disconnect_reason))
+    }
 }
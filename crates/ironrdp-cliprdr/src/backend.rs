@@ -4,7 +4,7 @@ use ironrdp_core::AsAny;
 
 use crate::pdu::{
     ClipboardFormat, ClipboardFormatId, ClipboardGeneralCapabilityFlags, FileContentsRequest, FileContentsResponse,
-    FormatDataRequest, FormatDataResponse, LockDataId, OwnedFormatDataResponse,
+    FormatDataRequest, FormatDataResponse, LockDataId, OwnedFileContentsResponse, OwnedFormatDataResponse,
 };
 
 pub trait ClipboardError: std::error::Error + Send + Sync + 'static {}
@@ -33,6 +33,19 @@ pub enum ClipboardMessage {
     /// received.
     SendInitiatePaste(ClipboardFormatId),
 
+    /// Sent by clipboard backend when file contents are ready to be sent to the remote.
+    ///
+    /// Client implementation should submit file contents to `CLIPRDR` SVC when this message is
+    /// received.
+    SendFileContents(OwnedFileContentsResponse),
+
+    /// Sent by clipboard backend when file contents (or file size) need to be received from the
+    /// remote.
+    ///
+    /// Client implementation should request file contents on `CLIPRDR` SVC when this message is
+    /// received.
+    SendFileContentsRequest(FileContentsRequest),
+
     /// Failure received from the OS clipboard event loop.
     ///
     /// Client implementation should log/display this error.
@@ -7,6 +7,7 @@
 #![allow(clippy::cast_sign_loss)] // FIXME: remove
 
 pub mod backend;
+pub mod memory;
 pub mod pdu;
 
 use backend::CliprdrBackend;
@@ -19,8 +20,8 @@ use ironrdp_svc::{
 };
 use pdu::{
     Capabilities, ClientTemporaryDirectory, ClipboardFormat, ClipboardFormatId, ClipboardGeneralCapabilityFlags,
-    ClipboardPdu, ClipboardProtocolVersion, FileContentsResponse, FormatDataRequest, FormatListResponse,
-    OwnedFormatDataResponse,
+    ClipboardPdu, ClipboardProtocolVersion, FileContentsRequest, FileContentsResponse, FormatDataRequest,
+    FormatListResponse, OwnedFormatDataResponse,
 };
 use thiserror::Error;
 use tracing::{error, info};
@@ -33,9 +34,6 @@ pub type CliprdrSvcMessages<R> = SvcProcessorMessages<Cliprdr<R>>;
 
 #[derive(Debug, Error)]
 enum ClipboardError {
-    #[error("received clipboard PDU is not implemented")]
-    UnimplementedPdu { pdu: &'static str },
-
     #[error("sent format list was rejected")]
     FormatListRejected,
 }
@@ -213,6 +211,20 @@ impl<R: Role> Cliprdr<R> {
         Ok(vec![into_cliprdr_message(pdu)].into())
     }
 
+    /// Requests file contents from the remote, returning a [`CliprdrSvcMessages`] to send on the
+    /// channel.
+    ///
+    /// Should be called by the clipboard implementation when it pastes files received from the
+    /// remote in a [`crate::pdu::PackedFileList`]. Response will be passed to
+    /// [`CliprdrBackend::on_file_contents_response`].
+    pub fn request_file_contents(&self, request: FileContentsRequest) -> PduResult<CliprdrSvcMessages<R>> {
+        ready_guard!(self, request_file_contents);
+
+        let pdu = ClipboardPdu::FileContentsRequest(request);
+
+        Ok(vec![into_cliprdr_message(pdu)].into())
+    }
+
     pub fn capabilities(&self) -> PduResult<SvcMessage> {
         let pdu = ClipboardPdu::Capabilities(self.capabilities.clone());
 
@@ -303,6 +315,11 @@ impl<R: Role> SvcProcessor for Cliprdr<R> {
             ClipboardPdu::FormatList(format_list) => self.handle_format_list(format_list),
             ClipboardPdu::FormatListResponse(response) => self.handle_format_list_response(response),
             ClipboardPdu::MonitorReady => self.handle_monitor_ready(),
+            ClipboardPdu::TemporaryDirectory(_) => {
+                // The temporary directory of the client is only needed to transfer file paths,
+                // which are not supported.
+                Ok(Vec::new())
+            }
             ClipboardPdu::LockData(id) => {
                 self.backend.on_lock(id);
                 Ok(Vec::new())
@@ -330,9 +347,6 @@ impl<R: Role> SvcProcessor for Cliprdr<R> {
                 self.backend.on_file_contents_response(response);
                 Ok(Vec::new())
             }
-            _ => self.handle_error_transition(ClipboardError::UnimplementedPdu {
-                pdu: pdu.message_name(),
-            }),
         }
     }
 
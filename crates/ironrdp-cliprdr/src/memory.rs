@@ -0,0 +1,917 @@
+//! Platform-independent clipboard backend keeping the clipboard contents in memory.
+//!
+//! [`MemoryClipboard`] holds the local clipboard contents as typed [`ClipboardItem`]s, and answers
+//! the requests of the remote from them. Files are streamed from their path or reader when the
+//! remote requests their contents, and files copied on the remote are assembled into a new
+//! directory of the temporary directory of the clipboard, for each paste.
+//!
+//! It can be used on both sides of the channel, e.g. by headless services or by clients running
+//! on platforms without a native backend. Note that the files are read and written synchronously,
+//! while the channel processes the PDUs.
+
+use core::time::Duration;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, Weak};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ironrdp_cliprdr_format::bitmap::{dib_to_png, dibv5_to_png, png_to_cf_dibv5};
+use ironrdp_cliprdr_format::html::{cf_html_to_plain_html, plain_html_to_cf_html};
+use ironrdp_core::impl_as_any;
+use tracing::{debug, warn};
+
+use crate::backend::{ClipboardMessage, ClipboardMessageProxy, CliprdrBackend, CliprdrBackendFactory};
+use crate::pdu::{
+    ClipboardFileAttributes, ClipboardFormat, ClipboardFormatId, ClipboardFormatName, ClipboardGeneralCapabilityFlags,
+    FileContentsFlags, FileContentsRequest, FileContentsResponse, FileDescriptor, FormatDataRequest,
+    FormatDataResponse, LockDataId, PackedFileList,
+};
+
+const FORMAT_HTML_ID: ClipboardFormatId = ClipboardFormatId(0xC001);
+const FORMAT_PNG_ID: ClipboardFormatId = ClipboardFormatId(0xC002);
+const FORMAT_FILE_LIST_ID: ClipboardFormatId = ClipboardFormatId(0xC003);
+
+const FORMAT_PNG_NAME: ClipboardFormatName = ClipboardFormatName::new_static("PNG");
+
+/// Size of the file contents requested at once, when receiving files
+const FILE_CHUNK_SIZE: u32 = 0x10000;
+
+/// Number of 100-nanosecond intervals between the epoch of `FILETIME` (1601-01-01) and the Unix epoch
+const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
+/// Kind of a [`ClipboardItem`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ClipboardItemKind {
+    Text,
+    Html,
+    Png,
+    Files,
+}
+
+/// Clipboard contents, in one of the formats supported by [`MemoryClipboard`]
+#[derive(Debug, Clone)]
+pub enum ClipboardItem {
+    Text(String),
+    /// HTML fragment, exchanged in the `HTML Format` of Windows
+    Html(String),
+    /// PNG image, also exchanged as `CF_DIBV5`
+    Png(Vec<u8>),
+    /// Files and directories, each directory being followed by its contents
+    Files(Vec<ClipboardFile>),
+}
+
+impl ClipboardItem {
+    pub fn kind(&self) -> ClipboardItemKind {
+        match self {
+            Self::Text(_) => ClipboardItemKind::Text,
+            Self::Html(_) => ClipboardItemKind::Html,
+            Self::Png(_) => ClipboardItemKind::Png,
+            Self::Files(_) => ClipboardItemKind::Files,
+        }
+    }
+}
+
+trait FileReader: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> FileReader for T {}
+
+#[derive(Clone)]
+enum FileSource {
+    Path(PathBuf),
+    Reader(Arc<Mutex<dyn FileReader>>),
+}
+
+/// File or directory of a [`ClipboardItem::Files`] list
+///
+/// The name is relative to the list: files inside directories are named after them, with `\`
+/// separators.
+#[derive(Clone)]
+pub struct ClipboardFile {
+    name: String,
+    size: u64,
+    last_write_time: Option<u64>,
+    is_directory: bool,
+    source: FileSource,
+}
+
+impl core::fmt::Debug for ClipboardFile {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ClipboardFile")
+            .field("name", &self.name)
+            .field("size", &self.size)
+            .field("is_directory", &self.is_directory)
+            .field("path", &self.path())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ClipboardFile {
+    /// Lists the files at the given paths, along with the contents of the directories
+    ///
+    /// Symbolic links inside the directories are only followed when they point to files.
+    pub fn from_paths<P: AsRef<Path>>(paths: impl IntoIterator<Item = P>) -> io::Result<Vec<Self>> {
+        let mut files = Vec::new();
+
+        for path in paths {
+            let path = path.as_ref();
+            let name = path
+                .file_name()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path without file name"))?
+                .to_string_lossy()
+                .into_owned();
+
+            list_path(path, name, fs::metadata(path)?, &mut files)?;
+        }
+
+        Ok(files)
+    }
+
+    /// Creates a file streaming its contents from `reader`
+    pub fn from_reader(name: impl Into<String>, size: u64, reader: impl Read + Seek + Send + 'static) -> Self {
+        Self {
+            name: name.into(),
+            size,
+            last_write_time: None,
+            is_directory: false,
+            source: FileSource::Reader(Arc::new(Mutex::new(reader))),
+        }
+    }
+
+    #[must_use]
+    pub fn with_last_write_time(mut self, time: SystemTime) -> Self {
+        self.last_write_time = to_filetime(time);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.is_directory
+    }
+
+    /// Path of the file, unless it was created from a reader
+    pub fn path(&self) -> Option<&Path> {
+        match &self.source {
+            FileSource::Path(path) => Some(path),
+            FileSource::Reader(_) => None,
+        }
+    }
+
+    fn descriptor(&self) -> FileDescriptor {
+        let attributes = if self.is_directory {
+            ClipboardFileAttributes::DIRECTORY
+        } else {
+            ClipboardFileAttributes::NORMAL
+        };
+
+        FileDescriptor {
+            attributes: Some(attributes),
+            last_write_time: self.last_write_time,
+            file_size: Some(self.size),
+            name: self.name.clone(),
+        }
+    }
+
+    fn read(&self, position: u64, len: u32) -> io::Result<Vec<u8>> {
+        if self.is_directory {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a file"));
+        }
+
+        let mut data = Vec::new();
+
+        match &self.source {
+            FileSource::Path(path) => {
+                let mut file = fs::File::open(path)?;
+                file.seek(SeekFrom::Start(position))?;
+                file.take(u64::from(len)).read_to_end(&mut data)?;
+            }
+            FileSource::Reader(reader) => {
+                let mut reader = reader.lock().expect("clipboard file lock poisoned");
+                reader.seek(SeekFrom::Start(position))?;
+                Read::take(&mut *reader, u64::from(len)).read_to_end(&mut data)?;
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+fn list_path(path: &Path, name: String, metadata: fs::Metadata, files: &mut Vec<ClipboardFile>) -> io::Result<()> {
+    files.push(ClipboardFile {
+        name: name.clone(),
+        size: if metadata.is_dir() { 0 } else { metadata.len() },
+        last_write_time: metadata.modified().ok().and_then(to_filetime),
+        is_directory: metadata.is_dir(),
+        source: FileSource::Path(path.to_owned()),
+    });
+
+    if metadata.is_dir() {
+        let mut entries = fs::read_dir(path)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let entry_path = entry.path();
+            let metadata = fs::metadata(&entry_path)?;
+
+            // Following links to directories could loop forever.
+            if entry.file_type()?.is_symlink() && metadata.is_dir() {
+                continue;
+            }
+
+            let entry_name = format!("{name}\\{}", entry.file_name().to_string_lossy());
+            list_path(&entry_path, entry_name, metadata, files)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn to_filetime(time: SystemTime) -> Option<u64> {
+    let intervals = time.duration_since(UNIX_EPOCH).ok()?.as_nanos() / 100;
+    u64::try_from(intervals).ok()?.checked_add(FILETIME_UNIX_EPOCH)
+}
+
+fn from_filetime(filetime: u64) -> Option<SystemTime> {
+    let nanos = filetime.checked_sub(FILETIME_UNIX_EPOCH)?.checked_mul(100)?;
+    UNIX_EPOCH.checked_add(Duration::from_nanos(nanos))
+}
+
+/// Creates a private directory in the temporary directory, receiving the files of a paste
+fn paste_directory(directory: &Path) -> io::Result<PathBuf> {
+    fs::create_dir_all(directory)?;
+
+    let mut builder = tempfile::Builder::new();
+    builder.prefix("ironrdp-cliprdr-").keep(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        builder.permissions(fs::Permissions::from_mode(0o700));
+    }
+
+    Ok(builder.tempdir_in(directory)?.path().to_owned())
+}
+
+/// Path of a received file in the directory of the paste, rejecting the names escaping it
+fn received_file_path(directory: &Path, name: &str) -> Option<PathBuf> {
+    let mut path = directory.to_owned();
+
+    for component in name.split(['\\', '/']) {
+        if component.is_empty() || component == "." || component == ".." || component.contains(':') {
+            return None;
+        }
+        path.push(component);
+    }
+
+    Some(path)
+}
+
+/// Event of a [`MemoryClipboard`]
+#[derive(Debug)]
+pub enum ClipboardEvent {
+    /// The remote clipboard changed, and now holds items of the given kinds
+    RemoteCopy(Vec<ClipboardItemKind>),
+    /// An item requested with [`MemoryClipboard::paste`] was received
+    ///
+    /// Files are received beforehand, into a new directory of the temporary directory.
+    Pasted(ClipboardItem),
+    /// An item requested with [`MemoryClipboard::paste`] could not be received
+    PasteFailed(ClipboardItemKind),
+}
+
+/// Clipboard keeping its contents in memory, for both sides of the channel
+///
+/// It is the [`CliprdrBackendFactory`] of the channel, and a clone is kept to copy and paste.
+/// The messages for the channel are sent to the [`ClipboardMessageProxy`], and the changes of
+/// the remote clipboard are reported as [`ClipboardEvent`]s.
+#[derive(Debug, Clone)]
+pub struct MemoryClipboard {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    proxy: Option<Box<dyn ClipboardMessageProxy>>,
+    events: Option<mpsc::Sender<ClipboardEvent>>,
+    temporary_directory: PathBuf,
+    local: Vec<ClipboardItem>,
+    /// State of the remote of the last backend built
+    remote: Weak<Mutex<Remote>>,
+}
+
+/// State of the remote, which does not survive the connection of its backend
+#[derive(Debug)]
+struct Remote {
+    capabilities: ClipboardGeneralCapabilityFlags,
+    /// Files of the local clipboard locked by the remote, by clip data ID
+    locked_files: HashMap<u32, Vec<ClipboardFile>>,
+    formats: Vec<ClipboardFormat>,
+    /// Pastes waiting for the format data response, in the order of the requests
+    pending_pastes: VecDeque<(ClipboardItemKind, ClipboardFormatId)>,
+    download: Option<Download>,
+}
+
+impl MemoryClipboard {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                proxy: None,
+                events: None,
+                temporary_directory: std::env::temp_dir(),
+                local: Vec::new(),
+                remote: Weak::new(),
+            })),
+        }
+    }
+
+    /// Sets the directory where the files pasted from the remote are received
+    ///
+    /// Each paste is received into a new directory, only accessible by the current user.
+    #[must_use]
+    pub fn with_temporary_directory(self, directory: impl Into<PathBuf>) -> Self {
+        self.inner().temporary_directory = directory.into();
+        self
+    }
+
+    #[must_use]
+    pub fn with_event_sender(self, sender: mpsc::Sender<ClipboardEvent>) -> Self {
+        self.inner().events = Some(sender);
+        self
+    }
+
+    /// Sets the proxy to the channel, which must be done before the connection
+    pub fn set_message_proxy(&self, proxy: impl ClipboardMessageProxy + 'static) {
+        self.inner().proxy = Some(Box::new(proxy));
+    }
+
+    /// Replaces the contents of the local clipboard, and advertises them to the remote
+    pub fn set_contents(&self, items: Vec<ClipboardItem>) {
+        let mut inner = self.inner();
+        inner.local = items;
+
+        let capabilities = inner
+            .remote
+            .upgrade()
+            .map(|remote| lock_remote(&remote).capabilities)
+            .unwrap_or_else(ClipboardGeneralCapabilityFlags::empty);
+        let formats = inner.local_formats(capabilities);
+        inner.send(ClipboardMessage::SendInitiateCopy(formats));
+    }
+
+    pub fn contents(&self) -> Vec<ClipboardItem> {
+        self.inner().local.clone()
+    }
+
+    /// Kinds of the items available in the remote clipboard
+    pub fn remote_kinds(&self) -> Vec<ClipboardItemKind> {
+        match self.inner().remote.upgrade() {
+            Some(remote) => lock_remote(&remote).kinds(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Requests an item of the remote clipboard, returning `false` if it is not available
+    ///
+    /// The item is reported with [`ClipboardEvent::Pasted`] once received.
+    pub fn paste(&self, kind: ClipboardItemKind) -> bool {
+        let inner = self.inner();
+        let Some(remote) = inner.remote.upgrade() else {
+            return false;
+        };
+        let mut remote = lock_remote(&remote);
+
+        let Some(format) = remote.format(kind) else {
+            return false;
+        };
+
+        remote.pending_pastes.push_back((kind, format));
+        inner.send(ClipboardMessage::SendInitiatePaste(format));
+
+        true
+    }
+
+    fn inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().expect("clipboard lock poisoned")
+    }
+}
+
+impl Default for MemoryClipboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CliprdrBackendFactory for MemoryClipboard {
+    fn build_cliprdr_backend(&self) -> Box<dyn CliprdrBackend> {
+        let mut inner = self.inner();
+
+        let remote = Arc::new(Mutex::new(Remote {
+            capabilities: ClipboardGeneralCapabilityFlags::empty(),
+            locked_files: HashMap::new(),
+            formats: Vec::new(),
+            pending_pastes: VecDeque::new(),
+            download: None,
+        }));
+        inner.remote = Arc::downgrade(&remote);
+
+        Box::new(MemoryCliprdrBackend {
+            inner: Arc::clone(&self.inner),
+            remote,
+            temporary_directory: inner.temporary_directory.to_string_lossy().into_owned(),
+        })
+    }
+}
+
+impl Inner {
+    fn send(&self, message: ClipboardMessage) {
+        match &self.proxy {
+            Some(proxy) => proxy.send_clipboard_message(message),
+            None => debug!(?message, "No clipboard message proxy, dropping message"),
+        }
+    }
+
+    fn emit(&self, event: ClipboardEvent) {
+        if let Some(events) = &self.events {
+            // The receiver is not required to listen to the events.
+            let _ = events.send(event);
+        }
+    }
+
+    fn local_files(&self) -> &[ClipboardFile] {
+        self.local
+            .iter()
+            .find_map(|item| match item {
+                ClipboardItem::Files(files) => Some(files.as_slice()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    fn local_formats(&self, capabilities: ClipboardGeneralCapabilityFlags) -> Vec<ClipboardFormat> {
+        let mut formats = Vec::new();
+
+        for item in &self.local {
+            match item {
+                ClipboardItem::Text(_) => formats.push(ClipboardFormat::new(ClipboardFormatId::CF_UNICODETEXT)),
+                ClipboardItem::Html(_) => {
+                    formats.push(ClipboardFormat::new(FORMAT_HTML_ID).with_name(ClipboardFormatName::HTML))
+                }
+                ClipboardItem::Png(_) => formats.extend([
+                    // CF_DIB is synthesized from CF_DIBV5 on the remote side.
+                    ClipboardFormat::new(ClipboardFormatId::CF_DIBV5),
+                    ClipboardFormat::new(FORMAT_PNG_ID).with_name(FORMAT_PNG_NAME),
+                ]),
+                ClipboardItem::Files(_) => {
+                    if capabilities.contains(ClipboardGeneralCapabilityFlags::STREAM_FILECLIP_ENABLED) {
+                        formats
+                            .push(ClipboardFormat::new(FORMAT_FILE_LIST_ID).with_name(ClipboardFormatName::FILE_LIST));
+                    }
+                }
+            }
+        }
+
+        formats
+    }
+
+    fn format_data(&self, format: ClipboardFormatId) -> Option<FormatDataResponse<'static>> {
+        let kind = match format {
+            ClipboardFormatId::CF_UNICODETEXT => ClipboardItemKind::Text,
+            FORMAT_HTML_ID => ClipboardItemKind::Html,
+            FORMAT_PNG_ID | ClipboardFormatId::CF_DIBV5 => ClipboardItemKind::Png,
+            FORMAT_FILE_LIST_ID => ClipboardItemKind::Files,
+            _ => return None,
+        };
+
+        let response = match self.local.iter().find(|item| item.kind() == kind)? {
+            ClipboardItem::Text(text) => FormatDataResponse::new_unicode_string(text),
+            ClipboardItem::Html(html) => FormatDataResponse::new_data(plain_html_to_cf_html(html).into_bytes()),
+            ClipboardItem::Png(png) if format == ClipboardFormatId::CF_DIBV5 => match png_to_cf_dibv5(png) {
+                Ok(dib) => FormatDataResponse::new_data(dib),
+                Err(error) => {
+                    warn!(%error, "Failed to convert PNG to CF_DIBV5");
+                    return None;
+                }
+            },
+            ClipboardItem::Png(png) => FormatDataResponse::new_data(png.clone()),
+            ClipboardItem::Files(files) => {
+                let list = PackedFileList {
+                    files: files.iter().map(ClipboardFile::descriptor).collect(),
+                };
+
+                match FormatDataResponse::new_file_list(&list) {
+                    Ok(response) => response,
+                    Err(error) => {
+                        warn!(%error, "Failed to encode file list");
+                        return None;
+                    }
+                }
+            }
+        };
+
+        Some(response)
+    }
+
+    fn file_contents(
+        &self,
+        locked_files: &HashMap<u32, Vec<ClipboardFile>>,
+        request: &FileContentsRequest,
+    ) -> FileContentsResponse<'static> {
+        let files = match request.data_id {
+            Some(data_id) => match locked_files.get(&data_id) {
+                Some(files) => files.as_slice(),
+                None => {
+                    warn!(data_id, "File contents requested for unknown clip data ID");
+                    return FileContentsResponse::new_error(request.stream_id);
+                }
+            },
+            None => self.local_files(),
+        };
+
+        let Some(file) = usize::try_from(request.index).ok().and_then(|index| files.get(index)) else {
+            warn!(index = request.index, "File contents requested for unknown file");
+            return FileContentsResponse::new_error(request.stream_id);
+        };
+
+        if request.flags.contains(FileContentsFlags::SIZE) {
+            return FileContentsResponse::new_size_response(request.stream_id, file.size);
+        }
+
+        match file.read(request.position, request.requested_size) {
+            Ok(data) => FileContentsResponse::new_data_response(request.stream_id, data),
+            Err(error) => {
+                warn!(%error, name = file.name, "Failed to read file contents");
+                FileContentsResponse::new_error(request.stream_id)
+            }
+        }
+    }
+}
+
+fn lock_remote(remote: &Mutex<Remote>) -> MutexGuard<'_, Remote> {
+    remote.lock().expect("clipboard remote lock poisoned")
+}
+
+impl Remote {
+    fn format(&self, kind: ClipboardItemKind) -> Option<ClipboardFormatId> {
+        let by_id = |id| self.formats.iter().any(|format| format.id() == id).then_some(id);
+        let by_name = |name| {
+            self.formats
+                .iter()
+                .find(|format| format.name() == Some(name))
+                .map(ClipboardFormat::id)
+        };
+
+        match kind {
+            ClipboardItemKind::Text => {
+                by_id(ClipboardFormatId::CF_UNICODETEXT).or_else(|| by_id(ClipboardFormatId::CF_TEXT))
+            }
+            ClipboardItemKind::Html => by_name(&ClipboardFormatName::HTML),
+            ClipboardItemKind::Png => by_name(&FORMAT_PNG_NAME)
+                .or_else(|| by_id(ClipboardFormatId::CF_DIBV5))
+                .or_else(|| by_id(ClipboardFormatId::CF_DIB)),
+            ClipboardItemKind::Files => by_name(&ClipboardFormatName::FILE_LIST),
+        }
+    }
+
+    fn kinds(&self) -> Vec<ClipboardItemKind> {
+        [
+            ClipboardItemKind::Text,
+            ClipboardItemKind::Html,
+            ClipboardItemKind::Png,
+            ClipboardItemKind::Files,
+        ]
+        .into_iter()
+        .filter(|kind| self.format(*kind).is_some())
+        .collect()
+    }
+
+    fn receive_format_data(&mut self, inner: &Inner, response: &FormatDataResponse<'_>) {
+        let Some((kind, format)) = self.pending_pastes.pop_front() else {
+            warn!("Received unexpected format data response");
+            return;
+        };
+
+        if response.is_error() {
+            warn!(?kind, "Remote failed to provide clipboard data");
+            inner.emit(ClipboardEvent::PasteFailed(kind));
+            return;
+        }
+
+        match self.decode_item(inner, kind, format, response) {
+            Ok(Some(item)) => inner.emit(ClipboardEvent::Pasted(item)),
+            // The files are received first.
+            Ok(None) => {}
+            Err(error) => {
+                warn!(%error, ?kind, "Failed to decode clipboard data");
+                inner.emit(ClipboardEvent::PasteFailed(kind));
+            }
+        }
+    }
+
+    fn decode_item(
+        &mut self,
+        inner: &Inner,
+        kind: ClipboardItemKind,
+        format: ClipboardFormatId,
+        response: &FormatDataResponse<'_>,
+    ) -> Result<Option<ClipboardItem>, Box<dyn std::error::Error>> {
+        let item = match kind {
+            ClipboardItemKind::Text if format == ClipboardFormatId::CF_TEXT => {
+                ClipboardItem::Text(response.to_string()?)
+            }
+            ClipboardItemKind::Text => ClipboardItem::Text(response.to_unicode_string()?),
+            ClipboardItemKind::Html => ClipboardItem::Html(cf_html_to_plain_html(response.data())?.to_owned()),
+            ClipboardItemKind::Png if format == ClipboardFormatId::CF_DIBV5 => {
+                ClipboardItem::Png(dibv5_to_png(response.data())?)
+            }
+            ClipboardItemKind::Png if format == ClipboardFormatId::CF_DIB => {
+                ClipboardItem::Png(dib_to_png(response.data())?)
+            }
+            ClipboardItemKind::Png => ClipboardItem::Png(response.data().to_vec()),
+            ClipboardItemKind::Files => {
+                let files = response.to_file_list()?.files;
+                let directory = paste_directory(&inner.temporary_directory)?;
+                let download = Download::new(files, &directory).map_err(|error| {
+                    let _ = fs::remove_dir(&directory);
+                    error
+                })?;
+
+                if self.download.replace(download).is_some() {
+                    warn!("Files pasted before the previous ones were received");
+                    inner.emit(ClipboardEvent::PasteFailed(ClipboardItemKind::Files));
+                }
+
+                let result = self.download.as_mut().expect("download set above").next_file();
+                self.continue_download(inner, result);
+
+                return Ok(None);
+            }
+        };
+
+        Ok(Some(item))
+    }
+
+    fn receive_file_contents(&mut self, inner: &Inner, response: &FileContentsResponse<'_>) {
+        let Some(download) = self.download.as_mut() else {
+            warn!("Received unexpected file contents response");
+            return;
+        };
+
+        if response.stream_id() != download.stream_id {
+            warn!(
+                stream_id = response.stream_id(),
+                "Received file contents of unknown stream"
+            );
+            return;
+        }
+
+        let result = if response.is_error() {
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                "remote failed to provide file contents",
+            ))
+        } else {
+            download.process(response)
+        };
+
+        self.continue_download(inner, result);
+    }
+
+    fn continue_download(&mut self, inner: &Inner, result: io::Result<Option<FileContentsRequest>>) {
+        match result {
+            Ok(Some(request)) => inner.send(ClipboardMessage::SendFileContentsRequest(request)),
+            Ok(None) => {
+                if let Some(download) = self.download.take() {
+                    inner.emit(ClipboardEvent::Pasted(ClipboardItem::Files(download.into_files())));
+                }
+            }
+            Err(error) => {
+                warn!(%error, "Failed to receive files");
+                self.download = None;
+                inner.emit(ClipboardEvent::PasteFailed(ClipboardItemKind::Files));
+            }
+        }
+    }
+}
+
+/// Files being received from the remote, one at a time
+#[derive(Debug)]
+struct Download {
+    files: Vec<FileDescriptor>,
+    paths: Vec<PathBuf>,
+    index: usize,
+    file: Option<fs::File>,
+    position: u64,
+    stream_id: u32,
+}
+
+impl Download {
+    fn new(files: Vec<FileDescriptor>, directory: &Path) -> Result<Self, String> {
+        let paths = files
+            .iter()
+            .map(|file| {
+                received_file_path(directory, &file.name).ok_or_else(|| format!("invalid file name: {}", file.name))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            files,
+            paths,
+            index: 0,
+            file: None,
+            position: 0,
+            stream_id: 0,
+        })
+    }
+
+    fn is_directory(file: &FileDescriptor) -> bool {
+        file.attributes
+            .is_some_and(|attributes| attributes.contains(ClipboardFileAttributes::DIRECTORY))
+    }
+
+    /// Starts receiving the next file, returning `None` once all of them are received
+    fn next_file(&mut self) -> io::Result<Option<FileContentsRequest>> {
+        while let Some(file) = self.files.get(self.index) {
+            let path = &self.paths[self.index];
+
+            if Self::is_directory(file) {
+                fs::create_dir_all(path)?;
+                self.index += 1;
+                continue;
+            }
+
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            // The files are new, existing files and links are not followed.
+            self.file = Some(fs::OpenOptions::new().write(true).create_new(true).open(path)?);
+            self.position = 0;
+
+            return Ok(Some(self.request()));
+        }
+
+        Ok(None)
+    }
+
+    /// Requests the size of the current file if it is unknown, or its next chunk
+    fn request(&mut self) -> FileContentsRequest {
+        self.stream_id = self.stream_id.wrapping_add(1);
+
+        let (flags, requested_size) = match self.files[self.index].file_size {
+            Some(_) => (FileContentsFlags::DATA, FILE_CHUNK_SIZE),
+            None => (FileContentsFlags::SIZE, 8),
+        };
+
+        FileContentsRequest {
+            stream_id: self.stream_id,
+            index: u32::try_from(self.index).expect("the file list count is an u32"),
+            flags,
+            position: self.position,
+            requested_size,
+            data_id: None,
+        }
+    }
+
+    fn process(&mut self, response: &FileContentsResponse<'_>) -> io::Result<Option<FileContentsRequest>> {
+        let index = self.index;
+
+        match self.files[index].file_size {
+            Some(_) => {
+                let data = response.data();
+                self.file.as_mut().expect("file being received").write_all(data)?;
+                self.position += data.len() as u64;
+
+                // The file is shorter than advertised.
+                if data.is_empty() {
+                    self.files[index].file_size = Some(self.position);
+                }
+            }
+            None => {
+                let size = response
+                    .data_as_size()
+                    .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))?;
+                self.files[index].file_size = Some(size);
+            }
+        }
+
+        if self.position < self.files[index].file_size.unwrap_or_default() {
+            return Ok(Some(self.request()));
+        }
+
+        let received = self.file.take().expect("file being received");
+        if let Some(time) = self.files[index].last_write_time.and_then(from_filetime) {
+            received.set_modified(time)?;
+        }
+        self.index += 1;
+
+        self.next_file()
+    }
+
+    fn into_files(self) -> Vec<ClipboardFile> {
+        self.files
+            .into_iter()
+            .zip(self.paths)
+            .map(|(file, path)| ClipboardFile {
+                is_directory: Self::is_directory(&file),
+                size: file.file_size.unwrap_or_default(),
+                last_write_time: file.last_write_time,
+                name: file.name,
+                source: FileSource::Path(path),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct MemoryCliprdrBackend {
+    inner: Arc<Mutex<Inner>>,
+    remote: Arc<Mutex<Remote>>,
+    temporary_directory: String,
+}
+
+impl_as_any!(MemoryCliprdrBackend);
+
+impl MemoryCliprdrBackend {
+    fn inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().expect("clipboard lock poisoned")
+    }
+
+    /// Locks the clipboard, then the state of the remote
+    fn lock(&self) -> (MutexGuard<'_, Inner>, MutexGuard<'_, Remote>) {
+        (self.inner(), lock_remote(&self.remote))
+    }
+}
+
+impl CliprdrBackend for MemoryCliprdrBackend {
+    fn temporary_directory(&self) -> &str {
+        &self.temporary_directory
+    }
+
+    fn client_capabilities(&self) -> ClipboardGeneralCapabilityFlags {
+        ClipboardGeneralCapabilityFlags::STREAM_FILECLIP_ENABLED
+            | ClipboardGeneralCapabilityFlags::FILECLIP_NO_FILE_PATHS
+            | ClipboardGeneralCapabilityFlags::CAN_LOCK_CLIPDATA
+            | ClipboardGeneralCapabilityFlags::HUGE_FILE_SUPPORT_ENABLED
+    }
+
+    fn on_request_format_list(&mut self) {
+        let (inner, remote) = self.lock();
+        let formats = inner.local_formats(remote.capabilities);
+        inner.send(ClipboardMessage::SendInitiateCopy(formats));
+    }
+
+    fn on_process_negotiated_capabilities(&mut self, capabilities: ClipboardGeneralCapabilityFlags) {
+        debug!(?capabilities);
+        self.lock().1.capabilities = capabilities;
+    }
+
+    fn on_remote_copy(&mut self, available_formats: &[ClipboardFormat]) {
+        let (inner, mut remote) = self.lock();
+        remote.formats = available_formats.to_vec();
+
+        inner.emit(ClipboardEvent::RemoteCopy(remote.kinds()));
+    }
+
+    fn on_format_data_request(&mut self, request: FormatDataRequest) {
+        let inner = self.inner();
+        let response = inner
+            .format_data(request.format)
+            .unwrap_or_else(FormatDataResponse::new_error);
+        inner.send(ClipboardMessage::SendFormatData(response));
+    }
+
+    fn on_format_data_response(&mut self, response: FormatDataResponse<'_>) {
+        let (inner, mut remote) = self.lock();
+        remote.receive_format_data(&inner, &response);
+    }
+
+    fn on_file_contents_request(&mut self, request: FileContentsRequest) {
+        let (inner, remote) = self.lock();
+        let response = inner.file_contents(&remote.locked_files, &request);
+        inner.send(ClipboardMessage::SendFileContents(response));
+    }
+
+    fn on_file_contents_response(&mut self, response: FileContentsResponse<'_>) {
+        let (inner, mut remote) = self.lock();
+        remote.receive_file_contents(&inner, &response);
+    }
+
+    fn on_lock(&mut self, data_id: LockDataId) {
+        let (inner, mut remote) = self.lock();
+        let files = inner.local_files().to_vec();
+        remote.locked_files.insert(data_id.0, files);
+    }
+
+    fn on_unlock(&mut self, data_id: LockDataId) {
+        if self.lock().1.locked_files.remove(&data_id.0).is_none() {
+            debug!(?data_id, "Unlocked unknown clip data ID");
+        }
+    }
+}
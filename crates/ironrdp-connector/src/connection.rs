@@ -3,15 +3,18 @@ use std::borrow::Cow;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
+use hmac::{Hmac, Mac as _};
 use ironrdp_core::{decode, encode_vec, Encode, WriteBuf};
 use ironrdp_pdu::rdp::client_info::{OptionalSystemTime, TimezoneInfo};
 use ironrdp_pdu::x224::X224;
 use ironrdp_pdu::{gcc, mcs, nego, rdp, PduHint};
 use ironrdp_svc::{StaticChannelSet, StaticVirtualChannel, SvcClientProcessor};
+use rand_core::{OsRng, RngCore as _};
 
 use crate::channel_connection::{ChannelConnectionSequence, ChannelConnectionState};
 use crate::connection_activation::{ConnectionActivationSequence, ConnectionActivationState};
 use crate::license_exchange::{LicenseExchangeSequence, NoopLicenseCache};
+use crate::standard_security::{self, SecuredSequence, StandardSecurity};
 use crate::{
     encode_x224_packet, Config, ConnectorError, ConnectorErrorExt as _, ConnectorResult, DesktopSize, Sequence, State,
     Written,
@@ -27,6 +30,8 @@ pub struct ConnectionResult {
     pub no_server_pointer: bool,
     pub pointer_software_rendering: bool,
     pub connection_activation: ConnectionActivationSequence,
+    /// Set when the connection uses Standard RDP Security
+    pub standard_security: Option<StandardSecurity>,
 }
 
 #[derive(Default, Debug)]
@@ -55,6 +60,12 @@ pub enum ClientConnectorState {
     ChannelConnection {
         io_channel_id: u16,
         channel_connection: ChannelConnectionSequence,
+        security_exchange: Option<rdp::SecurityExchangePdu>,
+    },
+    SecurityCommencement {
+        io_channel_id: u16,
+        user_channel_id: u16,
+        security_exchange: rdp::SecurityExchangePdu,
     },
     SecureSettingsExchange {
         io_channel_id: u16,
@@ -95,6 +106,7 @@ impl State for ClientConnectorState {
             Self::BasicSettingsExchangeSendInitial { .. } => "BasicSettingsExchangeSendInitial",
             Self::BasicSettingsExchangeWaitResponse { .. } => "BasicSettingsExchangeWaitResponse",
             Self::ChannelConnection { .. } => "ChannelConnection",
+            Self::SecurityCommencement { .. } => "SecurityCommencement",
             Self::SecureSettingsExchange { .. } => "SecureSettingsExchange",
             Self::ConnectTimeAutoDetection { .. } => "ConnectTimeAutoDetection",
             Self::LicensingExchange { .. } => "LicensingExchange",
@@ -125,6 +137,7 @@ pub struct ClientConnector {
     pub state: ClientConnectorState,
     pub server_addr: Option<SocketAddr>,
     pub static_channels: StaticChannelSet,
+    standard_security: Option<StandardSecurity>,
 }
 
 impl ClientConnector {
@@ -134,6 +147,7 @@ impl ClientConnector {
             state: ClientConnectorState::ConnectionInitiationSendRequest,
             server_addr: None,
             static_channels: StaticChannelSet::new(),
+            standard_security: None,
         }
     }
 
@@ -169,6 +183,18 @@ impl ClientConnector {
         matches!(self.state, ClientConnectorState::EnhancedSecurityUpgrade { .. })
     }
 
+    /// Returns `true` when the server selected Standard RDP Security
+    ///
+    /// In this case, there is no TLS upgrade to perform and [`Self::mark_security_upgrade_as_done`] can be
+    /// called right away.
+    pub fn uses_standard_rdp_security(&self) -> bool {
+        matches!(
+            self.state,
+            ClientConnectorState::EnhancedSecurityUpgrade { selected_protocol }
+                if selected_protocol.is_standard_rdp_security()
+        ) || self.standard_security.is_some()
+    }
+
     pub fn mark_security_upgrade_as_done(&mut self) {
         assert!(self.should_perform_security_upgrade());
         self.step(&[], &mut WriteBuf::new()).expect("transition to next state");
@@ -198,6 +224,7 @@ impl Sequence for ClientConnector {
             ClientConnectorState::BasicSettingsExchangeSendInitial { .. } => None,
             ClientConnectorState::BasicSettingsExchangeWaitResponse { .. } => Some(&ironrdp_pdu::X224_HINT),
             ClientConnectorState::ChannelConnection { channel_connection, .. } => channel_connection.next_pdu_hint(),
+            ClientConnectorState::SecurityCommencement { .. } => None,
             ClientConnectorState::SecureSettingsExchange { .. } => None,
             ClientConnectorState::ConnectTimeAutoDetection { .. } => None,
             ClientConnectorState::LicensingExchange { license_exchange, .. } => license_exchange.next_pdu_hint(),
@@ -247,8 +274,8 @@ impl Sequence for ClientConnector {
                     security_protocol.insert(nego::SecurityProtocol::HYBRID | nego::SecurityProtocol::HYBRID_EX);
                 }
 
-                if security_protocol.is_standard_rdp_security() {
-                    return Err(reason_err!("Initiation", "standard RDP security is not supported",));
+                if security_protocol.is_standard_rdp_security() && !self.config.enable_standard_rdp_security {
+                    return Err(reason_err!("Initiation", "no security protocol is enabled"));
                 }
 
                 let connection_request = nego::ConnectionRequest {
@@ -291,7 +318,13 @@ impl Sequence for ClientConnector {
 
                 info!(?selected_protocol, ?flags, "Server confirmed connection");
 
-                if !selected_protocol.intersects(requested_protocol) {
+                let is_acceptable = if selected_protocol.is_standard_rdp_security() {
+                    self.config.enable_standard_rdp_security
+                } else {
+                    selected_protocol.intersects(requested_protocol)
+                };
+
+                if !is_acceptable {
                     return Err(reason_err!(
                         "Initiation",
                         "client advertised {requested_protocol}, but server selected {selected_protocol}",
@@ -305,8 +338,8 @@ impl Sequence for ClientConnector {
             }
 
             //== Upgrade to Enhanced RDP Security ==//
-            // NOTE: we assume the selected protocol is never the standard RDP security (RC4).
-            // User code should match this variant and perform the appropriate upgrade (TLS handshake, etc).
+            // User code should match this variant and perform the appropriate upgrade (TLS handshake, etc),
+            // unless Standard RDP Security was selected (see `uses_standard_rdp_security`).
             ClientConnectorState::EnhancedSecurityUpgrade { selected_protocol } => {
                 let next_state = if selected_protocol
                     .intersects(nego::SecurityProtocol::HYBRID | nego::SecurityProtocol::HYBRID_EX)
@@ -365,6 +398,42 @@ impl Sequence for ClientConnector {
                     return Err(general_err!("can’t satisfy server security settings"));
                 }
 
+                let security_exchange = if server_gcc_blocks.security.encryption_method.is_empty() {
+                    None
+                } else {
+                    let server_security = &server_gcc_blocks.security;
+
+                    info!(
+                        method = ?server_security.encryption_method,
+                        level = ?server_security.encryption_level,
+                        "Using Standard RDP Security"
+                    );
+
+                    let server_random = server_security
+                        .server_random
+                        .as_ref()
+                        .ok_or_else(|| general_err!("server random is missing"))?;
+
+                    let mut client_random = [0u8; standard_security::CLIENT_RANDOM_LEN];
+                    OsRng.fill_bytes(&mut client_random);
+
+                    let encrypted_client_random =
+                        standard_security::encrypt_client_random(&server_security.server_cert, &client_random)?;
+
+                    self.standard_security = Some(StandardSecurity::new(
+                        server_security.encryption_method,
+                        client_random,
+                        server_random,
+                    )?);
+
+                    Some(rdp::SecurityExchangePdu {
+                        security_header: rdp::headers::BasicSecurityHeader {
+                            flags: rdp::headers::BasicSecurityHeaderFlags::EXCHANGE_PKT,
+                        },
+                        encrypted_client_random,
+                    })
+                };
+
                 if server_gcc_blocks.message_channel.is_some() {
                     warn!("Unexpected ServerMessageChannelData GCC block (not supported)");
                 }
@@ -403,6 +472,7 @@ impl Sequence for ClientConnector {
                         } else {
                             ChannelConnectionSequence::new(io_channel_id, static_channel_ids)
                         },
+                        security_exchange,
                     },
                 )
             }
@@ -412,6 +482,7 @@ impl Sequence for ClientConnector {
             ClientConnectorState::ChannelConnection {
                 io_channel_id,
                 mut channel_connection,
+                security_exchange,
             } => {
                 debug!("Channel Connection");
                 let written = channel_connection.step(input, output)?;
@@ -420,14 +491,22 @@ impl Sequence for ClientConnector {
                 {
                     debug_assert!(channel_connection.state.is_terminal());
 
-                    ClientConnectorState::SecureSettingsExchange {
-                        io_channel_id,
-                        user_channel_id,
+                    match security_exchange {
+                        Some(security_exchange) => ClientConnectorState::SecurityCommencement {
+                            io_channel_id,
+                            user_channel_id,
+                            security_exchange,
+                        },
+                        None => ClientConnectorState::SecureSettingsExchange {
+                            io_channel_id,
+                            user_channel_id,
+                        },
                     }
                 } else {
                     ClientConnectorState::ChannelConnection {
                         io_channel_id,
                         channel_connection,
+                        security_exchange,
                     }
                 };
 
@@ -435,10 +514,25 @@ impl Sequence for ClientConnector {
             }
 
             //== RDP Security Commencement ==//
-            // When using standard RDP security (RC4), a Security Exchange PDU is sent at this point.
-            // However, IronRDP does not support this unsecure security protocol (purposefully) and
-            // this part of the sequence is not implemented.
-            //==============================//
+            // When using Standard RDP Security, the client random encrypted with the server public key is sent.
+            // Every PDU sent after this one is encrypted with the keys derived from both randoms.
+            ClientConnectorState::SecurityCommencement {
+                io_channel_id,
+                user_channel_id,
+                security_exchange,
+            } => {
+                debug!("RDP Security Commencement");
+
+                let written = encode_send_data_request(user_channel_id, io_channel_id, &security_exchange, output)?;
+
+                (
+                    Written::from_size(written)?,
+                    ClientConnectorState::SecureSettingsExchange {
+                        io_channel_id,
+                        user_channel_id,
+                    },
+                )
+            }
 
             //== Secure Settings Exchange ==//
             // Send Client Info PDU (information about supported types of compression, username, password, etc).
@@ -453,11 +547,32 @@ impl Sequence for ClientConnector {
                     .as_ref()
                     .ok_or_else(|| general_err!("server address is missing"))?;
 
-                let client_info = create_client_info_pdu(&self.config, routing_addr);
+                let client_random = self
+                    .standard_security
+                    .as_ref()
+                    .map_or(&[0; standard_security::CLIENT_RANDOM_LEN], |security| {
+                        security.client_random()
+                    });
+
+                let client_info = create_client_info_pdu(&self.config, routing_addr, client_random);
 
                 debug!(message = ?client_info, "Send");
 
-                let written = encode_send_data_request(user_channel_id, io_channel_id, &client_info, output)?;
+                let written = match self.standard_security.as_mut() {
+                    Some(security) => {
+                        let data = encode_vec(&client_info.client_info).map_err(ConnectorError::encode)?;
+                        let user_data = security.encrypt_user_data(client_info.security_header.flags, &data);
+
+                        let pdu = mcs::SendDataRequest {
+                            initiator_id: user_channel_id,
+                            channel_id: io_channel_id,
+                            user_data: Cow::Owned(user_data),
+                        };
+
+                        ironrdp_core::encode_buf(&X224(pdu), output).map_err(ConnectorError::encode)?
+                    }
+                    None => encode_send_data_request(user_channel_id, io_channel_id, &client_info, output)?,
+                };
 
                 (
                     Written::from_size(written)?,
@@ -501,7 +616,12 @@ impl Sequence for ClientConnector {
             } => {
                 debug!("Licensing Exchange");
 
-                let written = license_exchange.step(input, output)?;
+                let written = match self.standard_security.as_mut() {
+                    Some(security) if !input.is_empty() => {
+                        license_exchange.step(&security.decrypt_frame(input)?, output)?
+                    }
+                    _ => license_exchange.step(input, output)?,
+                };
 
                 let next_state = if license_exchange.state.is_terminal() {
                     ClientConnectorState::MultitransportBootstrapping {
@@ -540,7 +660,12 @@ impl Sequence for ClientConnector {
             ClientConnectorState::CapabilitiesExchange {
                 mut connection_activation,
             } => {
-                let written = connection_activation.step(input, output)?;
+                let written = step_connection_activation(
+                    &mut connection_activation,
+                    self.standard_security.as_mut(),
+                    input,
+                    output,
+                )?;
                 match connection_activation.state {
                     ConnectionActivationState::ConnectionFinalization { .. } => (
                         written,
@@ -556,7 +681,12 @@ impl Sequence for ClientConnector {
             ClientConnectorState::ConnectionFinalization {
                 mut connection_activation,
             } => {
-                let written = connection_activation.step(input, output)?;
+                let written = step_connection_activation(
+                    &mut connection_activation,
+                    self.standard_security.as_mut(),
+                    input,
+                    output,
+                )?;
 
                 let next_state = if !connection_activation.state.is_terminal() {
                     ClientConnectorState::ConnectionFinalization { connection_activation }
@@ -577,6 +707,7 @@ impl Sequence for ClientConnector {
                                 no_server_pointer,
                                 pointer_software_rendering,
                                 connection_activation,
+                                standard_security: self.standard_security.take(),
                             },
                         },
                         _ => return Err(general_err!("invalid state (this is a bug)")),
@@ -597,6 +728,18 @@ impl Sequence for ClientConnector {
     }
 }
 
+fn step_connection_activation(
+    connection_activation: &mut ConnectionActivationSequence,
+    standard_security: Option<&mut StandardSecurity>,
+    input: &[u8],
+    output: &mut WriteBuf,
+) -> ConnectorResult<Written> {
+    match standard_security {
+        Some(security) => SecuredSequence::new(connection_activation, security).step(input, output),
+        None => connection_activation.step(input, output),
+    }
+}
+
 pub fn encode_send_data_request<T: Encode>(
     initiator_id: u16,
     channel_id: u16,
@@ -670,6 +813,10 @@ fn create_gcc_blocks<'a>(
                         early_capability_flags |= ClientEarlyCapabilityFlags::WANT_32_BPP_SESSION;
                     }
 
+                    if config.enable_gfx {
+                        early_capability_flags |= ClientEarlyCapabilityFlags::SUPPORT_DYN_VC_GFX_PROTOCOL;
+                    }
+
                     Some(early_capability_flags)
                 },
                 dig_product_id: Some(config.dig_product_id.clone()),
@@ -690,17 +837,23 @@ fn create_gcc_blocks<'a>(
                 },
             },
         },
-        security: ClientSecurityData {
-            encryption_methods: EncryptionMethod::empty(),
-            ext_encryption_methods: 0,
+        security: if selected_protocol.is_standard_rdp_security() {
+            ClientSecurityData {
+                encryption_methods: EncryptionMethod::BIT_40
+                    | EncryptionMethod::BIT_56
+                    | EncryptionMethod::BIT_128
+                    | EncryptionMethod::FIPS,
+                ext_encryption_methods: 0,
+            }
+        } else {
+            ClientSecurityData::no_security()
         },
         network: if channels.is_empty() {
             None
         } else {
             Some(ClientNetworkData { channels })
         },
-        // TODO(#139): support for Some(ClientClusterData { flags: RedirectionFlags::REDIRECTION_SUPPORTED, redirection_version: RedirectionVersion::V4, redirected_session_id: 0, }),
-        cluster: None,
+        cluster: Some(create_cluster_data(config)),
         monitor: None,
         // TODO(#140): support for Client Message Channel Data (https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/f50e791c-de03-4b25-b17e-e914c9020bc3)
         message_channel: None,
@@ -710,7 +863,25 @@ fn create_gcc_blocks<'a>(
     }
 }
 
-fn create_client_info_pdu(config: &Config, routing_addr: &SocketAddr) -> rdp::ClientInfoPdu {
+fn create_cluster_data(config: &Config) -> gcc::ClientClusterData {
+    let mut flags = gcc::RedirectionFlags::REDIRECTION_SUPPORTED;
+
+    if config.redirected_session_id.is_some() {
+        flags |= gcc::RedirectionFlags::REDIRECTED_SESSION_FIELD_VALID;
+
+        if let crate::Credentials::SmartCard { .. } = &config.credentials {
+            flags |= gcc::RedirectionFlags::REDIRECTED_SMARTCARD;
+        }
+    }
+
+    gcc::ClientClusterData {
+        flags,
+        redirection_version: gcc::RedirectionVersion::V4,
+        redirected_session_id: config.redirected_session_id.unwrap_or(0),
+    }
+}
+
+fn create_client_info_pdu(config: &Config, routing_addr: &SocketAddr, client_random: &[u8]) -> rdp::ClientInfoPdu {
     use ironrdp_pdu::rdp::client_info::{
         AddressFamily, ClientInfo, ClientInfoFlags, CompressionType, Credentials, ExtendedClientInfo,
         ExtendedClientOptionalInfo,
@@ -742,6 +913,30 @@ fn create_client_info_pdu(config: &Config, routing_addr: &SocketAddr) -> rdp::Cl
         flags |= ClientInfoFlags::PASSWORD_IS_SC_PIN;
     }
 
+    if config.compression_type.is_some() {
+        flags |= ClientInfoFlags::COMPRESSION;
+    }
+
+    let optional_data = ExtendedClientOptionalInfo::builder()
+        .timezone(TimezoneInfo {
+            bias: 0,
+            standard_name: String::new(),
+            standard_date: OptionalSystemTime(None),
+            standard_bias: 0,
+            daylight_name: String::new(),
+            daylight_date: OptionalSystemTime(None),
+            daylight_bias: 0,
+        })
+        .session_id(0)
+        .performance_flags(config.performance_flags);
+
+    let optional_data = match &config.auto_reconnect {
+        Some(cookie) => optional_data
+            .reconnect_cookie(client_auto_reconnect_packet(cookie, client_random))
+            .build(),
+        None => optional_data.build(),
+    };
+
     let client_info = ClientInfo {
         credentials: Credentials {
             username: config.credentials.username().unwrap_or("").to_owned(),
@@ -750,7 +945,7 @@ fn create_client_info_pdu(config: &Config, routing_addr: &SocketAddr) -> rdp::Cl
         },
         code_page: 0, // ignored if the keyboardLayout field of the Client Core Data is set to zero
         flags,
-        compression_type: CompressionType::K8, // ignored if ClientInfoFlags::COMPRESSION is not set
+        compression_type: config.compression_type.unwrap_or(CompressionType::K8), // ignored if ClientInfoFlags::COMPRESSION is not set
         alternate_shell: String::new(),
         work_dir: String::new(),
         extra_info: ExtendedClientInfo {
@@ -760,19 +955,7 @@ fn create_client_info_pdu(config: &Config, routing_addr: &SocketAddr) -> rdp::Cl
             },
             address: routing_addr.ip().to_string(),
             dir: config.client_dir.clone(),
-            optional_data: ExtendedClientOptionalInfo::builder()
-                .timezone(TimezoneInfo {
-                    bias: 0,
-                    standard_name: String::new(),
-                    standard_date: OptionalSystemTime(None),
-                    standard_bias: 0,
-                    daylight_name: String::new(),
-                    daylight_date: OptionalSystemTime(None),
-                    daylight_bias: 0,
-                })
-                .session_id(0)
-                .performance_flags(config.performance_flags)
-                .build(),
+            optional_data,
         },
     };
 
@@ -781,3 +964,21 @@ fn create_client_info_pdu(config: &Config, routing_addr: &SocketAddr) -> rdp::Cl
         client_info,
     }
 }
+
+/// Computes the Client Auto-Reconnect Packet of an auto-reconnect cookie.
+///
+/// The security verifier is the HMAC-MD5 of the client random, keyed with the random bits of the
+/// cookie. With Enhanced RDP Security (TLS and CredSSP), there is no client random and 32 zero bytes
+/// are used instead.
+pub fn client_auto_reconnect_packet(
+    cookie: &crate::AutoReconnectCookie,
+    client_random: &[u8],
+) -> rdp::client_info::ClientAutoReconnectPacket {
+    let mut mac = Hmac::<md5::Md5>::new_from_slice(&cookie.random_bits).expect("HMAC accepts keys of any size");
+    mac.update(client_random);
+
+    rdp::client_info::ClientAutoReconnectPacket {
+        logon_id: cookie.logon_id,
+        security_verifier: mac.finalize().into_bytes().into(),
+    }
+}
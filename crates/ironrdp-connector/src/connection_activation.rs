@@ -1,9 +1,13 @@
 use core::mem;
 
 use ironrdp_pdu::rdp::capability_sets::CapabilitySet;
+use ironrdp_pdu::rdp::finalization_messages::{PersistentKeyListPdu, PERSISTENT_KEY_LIST_CACHES_NUM};
 use ironrdp_pdu::rdp::{self};
 
-use crate::{legacy, Config, ConnectionFinalizationSequence, ConnectorResult, DesktopSize, Sequence, State, Written};
+use crate::{
+    legacy, BitmapCacheConfig, Config, ConnectionFinalizationSequence, ConnectorError, ConnectorErrorKind,
+    ConnectorResult, DesktopSize, Sequence, State, Written,
+};
 
 /// Represents the Capability Exchange and Connection Finalization phases
 /// of the connection sequence (section [1.3.1.1]).
@@ -109,14 +113,24 @@ impl Sequence for ConnectionActivationSequence {
                     );
                 }
 
-                let capability_sets = if let rdp::headers::ShareControlPdu::ServerDemandActive(server_demand_active) =
-                    share_control_ctx.pdu
-                {
-                    server_demand_active.pdu.capability_sets
-                } else {
-                    return Err(general_err!(
-                        "unexpected Share Control Pdu (expected ServerDemandActive)",
-                    ));
+                let capability_sets = match share_control_ctx.pdu {
+                    rdp::headers::ShareControlPdu::ServerDemandActive(server_demand_active) => {
+                        server_demand_active.pdu.capability_sets
+                    }
+                    // Servers of a load-balanced farm send this PDU instead to redirect the client.
+                    rdp::headers::ShareControlPdu::ServerRedirect(redirection) => {
+                        info!(address = redirection.target_address(), "Server redirection");
+
+                        return Err(ConnectorError::new(
+                            "server redirection",
+                            ConnectorErrorKind::Redirection(Box::new(redirection)),
+                        ));
+                    }
+                    _ => {
+                        return Err(general_err!(
+                            "unexpected Share Control Pdu (expected ServerDemandActive)",
+                        ));
+                    }
                 };
 
                 for c in &capability_sets {
@@ -171,7 +185,8 @@ impl Sequence for ConnectionActivationSequence {
                         io_channel_id,
                         user_channel_id,
                         desktop_size,
-                        connection_finalization: ConnectionFinalizationSequence::new(io_channel_id, user_channel_id),
+                        connection_finalization: ConnectionFinalizationSequence::new(io_channel_id, user_channel_id)
+                            .with_persistent_keys(persistent_keys(&self.config)),
                     },
                 )
             }
@@ -282,7 +297,9 @@ fn create_client_confirm_active(
     server_capability_sets.extend_from_slice(&[
         CapabilitySet::General(General {
             major_platform_type: config.platform,
-            extra_flags: GeneralExtraFlags::FASTPATH_OUTPUT_SUPPORTED | GeneralExtraFlags::NO_BITMAP_COMPRESSION_HDR,
+            extra_flags: GeneralExtraFlags::FASTPATH_OUTPUT_SUPPORTED
+                | GeneralExtraFlags::NO_BITMAP_COMPRESSION_HDR
+                | GeneralExtraFlags::ENC_SALTED_CHECKSUM,
             ..Default::default()
         }),
         CapabilitySet::Bitmap(Bitmap {
@@ -293,18 +310,8 @@ fn create_client_confirm_active(
             desktop_resize_flag: true,
             drawing_flags,
         }),
-        CapabilitySet::Order(Order::new(
-            OrderFlags::NEGOTIATE_ORDER_SUPPORT | OrderFlags::ZERO_BOUNDS_DELTAS_SUPPORT,
-            OrderSupportExFlags::empty(),
-            0,
-            0,
-        )),
-        CapabilitySet::BitmapCache(BitmapCache {
-            caches: [CacheEntry {
-                entries: 0,
-                max_cell_size: 0,
-            }; BITMAP_CACHE_ENTRIES_NUM],
-        }),
+        CapabilitySet::Order(create_order_capability_set(config.bitmap_cache.is_some())),
+        create_bitmap_cache_capability_set(config.bitmap_cache.as_ref()),
         CapabilitySet::Input(Input {
             input_flags: InputFlags::all(),
             keyboard_layout: 0,
@@ -390,3 +397,107 @@ fn create_client_confirm_active(
         },
     }
 }
+
+fn create_order_capability_set(bitmap_cache: bool) -> rdp::capability_sets::Order {
+    use ironrdp_pdu::rdp::capability_sets::{Order, OrderFlags, OrderSupportExFlags, OrderSupportIndex};
+
+    let order_support_ex_flags = if bitmap_cache {
+        OrderSupportExFlags::CACHE_BITMAP_REV3_SUPPORT
+    } else {
+        OrderSupportExFlags::empty()
+    };
+
+    let mut order = Order::new(
+        OrderFlags::NEGOTIATE_ORDER_SUPPORT | OrderFlags::ZERO_BOUNDS_DELTAS_SUPPORT,
+        order_support_ex_flags,
+        0,
+        0,
+    );
+
+    // The cached bitmaps are only drawn with these orders.
+    order.set_support_flag(OrderSupportIndex::MemBlt, bitmap_cache);
+    order.set_support_flag(OrderSupportIndex::Mem3Blt, bitmap_cache);
+
+    order
+}
+
+fn create_bitmap_cache_capability_set(bitmap_cache: Option<&BitmapCacheConfig>) -> CapabilitySet {
+    use ironrdp_pdu::rdp::capability_sets::{
+        BitmapCache, BitmapCacheRev2, CacheEntry, CacheFlags, CellInfo, BITMAP_CACHE_ENTRIES_NUM,
+    };
+
+    let Some(bitmap_cache) = bitmap_cache else {
+        return CapabilitySet::BitmapCache(BitmapCache {
+            caches: [CacheEntry {
+                entries: 0,
+                max_cell_size: 0,
+            }; BITMAP_CACHE_ENTRIES_NUM],
+        });
+    };
+
+    if bitmap_cache.cell_caches.len() > PERSISTENT_KEY_LIST_CACHES_NUM {
+        warn!(
+            count = bitmap_cache.cell_caches.len(),
+            "Too many bitmap cell caches, only the first {PERSISTENT_KEY_LIST_CACHES_NUM} are advertised"
+        );
+    }
+
+    let mut cache_cell_info = [CellInfo::default(); PERSISTENT_KEY_LIST_CACHES_NUM];
+
+    for (cell_info, cell_cache) in cache_cell_info.iter_mut().zip(&bitmap_cache.cell_caches) {
+        *cell_info = CellInfo {
+            num_entries: u32::from(cell_cache.entries),
+            is_cache_persistent: cell_cache.persistent,
+        };
+    }
+
+    let cache_flags = if bitmap_cache.cell_caches.iter().any(|cell_cache| cell_cache.persistent) {
+        CacheFlags::PERSISTENT_KEYS_EXPECTED_FLAG
+    } else {
+        CacheFlags::empty()
+    };
+
+    CapabilitySet::BitmapCacheRev2(BitmapCacheRev2 {
+        cache_flags,
+        num_cell_caches: u8::try_from(bitmap_cache.cell_caches.len().min(PERSISTENT_KEY_LIST_CACHES_NUM))
+            .expect("at most 5 cell caches"),
+        cache_cell_info,
+    })
+}
+
+/// Returns the keys of the bitmaps loaded in the persistent cell caches, for each advertised cell cache.
+///
+/// The keys which do not fit in the advertised size of their cache, or exceed the total number of keys
+/// of a Persistent Key List sequence, are not sent.
+fn persistent_keys(config: &Config) -> Vec<Vec<u64>> {
+    let mut remaining = PersistentKeyListPdu::MAX_TOTAL_ENTRIES;
+
+    config
+        .bitmap_cache
+        .iter()
+        .flat_map(|bitmap_cache| &bitmap_cache.cell_caches)
+        .take(PERSISTENT_KEY_LIST_CACHES_NUM)
+        .enumerate()
+        .map(|(cache_id, cell_cache)| {
+            if !cell_cache.persistent {
+                return Vec::new();
+            }
+
+            let entries = usize::from(cell_cache.entries).min(remaining);
+
+            if cell_cache.persistent_keys.len() > entries {
+                warn!(
+                    cache_id,
+                    entries,
+                    count = cell_cache.persistent_keys.len(),
+                    "Too many persistent bitmap keys, the keys of the last cells are not sent"
+                );
+            }
+
+            let keys: Vec<u64> = cell_cache.persistent_keys.iter().copied().take(entries).collect();
+            remaining -= keys.len();
+
+            keys
+        })
+        .collect()
+}
@@ -18,6 +18,10 @@ pub enum ConnectionFinalizationState {
     SendSynchronize,
     SendControlCooperate,
     SendRequestControl,
+    SendPersistentKeyList {
+        /// Index of the next PDU to send
+        index: usize,
+    },
     SendFontList,
 
     WaitForResponse,
@@ -32,6 +36,7 @@ impl State for ConnectionFinalizationState {
             Self::SendSynchronize => "SendSynchronize",
             Self::SendControlCooperate => "SendControlCooperate",
             Self::SendRequestControl => "SendRequestControl",
+            Self::SendPersistentKeyList { .. } => "SendPersistentKeyList",
             Self::SendFontList => "SendFontList",
             Self::WaitForResponse => "WaitForResponse",
             Self::Finished => "Finished",
@@ -53,6 +58,8 @@ pub struct ConnectionFinalizationSequence {
     pub state: ConnectionFinalizationState,
     pub io_channel_id: u16,
     pub user_channel_id: u16,
+    /// Keys of the bitmaps loaded in the persistent bitmap caches, for each cell cache
+    pub persistent_keys: Vec<Vec<u64>>,
 }
 
 impl ConnectionFinalizationSequence {
@@ -61,8 +68,25 @@ impl ConnectionFinalizationSequence {
             state: ConnectionFinalizationState::SendSynchronize,
             io_channel_id,
             user_channel_id,
+            persistent_keys: Vec::new(),
         }
     }
+
+    /// Sends the Persistent Key List PDUs advertising these keys before the Font List PDU.
+    #[must_use]
+    pub fn with_persistent_keys(mut self, persistent_keys: Vec<Vec<u64>>) -> Self {
+        self.persistent_keys = persistent_keys;
+        self
+    }
+
+    fn persistent_key_lists(&self) -> ConnectorResult<Vec<finalization_messages::PersistentKeyListPdu>> {
+        if self.persistent_keys.iter().all(Vec::is_empty) {
+            return Ok(Vec::new());
+        }
+
+        finalization_messages::PersistentKeyListPdu::from_keys(&self.persistent_keys)
+            .ok_or_else(|| general_err!("too many persistent bitmap keys"))
+    }
 }
 
 impl Sequence for ConnectionFinalizationSequence {
@@ -72,6 +96,7 @@ impl Sequence for ConnectionFinalizationSequence {
             ConnectionFinalizationState::SendSynchronize => None,
             ConnectionFinalizationState::SendControlCooperate => None,
             ConnectionFinalizationState::SendRequestControl => None,
+            ConnectionFinalizationState::SendPersistentKeyList { .. } => None,
             ConnectionFinalizationState::SendFontList => None,
             ConnectionFinalizationState::WaitForResponse => Some(&ironrdp_pdu::X224_HINT),
             ConnectionFinalizationState::Finished => None,
@@ -133,7 +158,31 @@ impl Sequence for ConnectionFinalizationSequence {
 
                 let written = legacy::encode_share_data(self.user_channel_id, self.io_channel_id, 0, message, output)?;
 
-                (Written::from_size(written)?, ConnectionFinalizationState::SendFontList)
+                (
+                    Written::from_size(written)?,
+                    ConnectionFinalizationState::SendPersistentKeyList { index: 0 },
+                )
+            }
+
+            ConnectionFinalizationState::SendPersistentKeyList { index } => {
+                // The PDUs are rebuilt at each step, and only the one at `index` is sent.
+                let mut pdus = self.persistent_key_lists()?;
+
+                if index < pdus.len() {
+                    let message = ShareDataPdu::BitmapCachePersistentList(pdus.swap_remove(index));
+
+                    debug!(?message, "Send");
+
+                    let written =
+                        legacy::encode_share_data(self.user_channel_id, self.io_channel_id, 0, message, output)?;
+
+                    (
+                        Written::from_size(written)?,
+                        ConnectionFinalizationState::SendPersistentKeyList { index: index + 1 },
+                    )
+                } else {
+                    (Written::Nothing, ConnectionFinalizationState::SendFontList)
+                }
             }
 
             ConnectionFinalizationState::SendFontList => {
@@ -16,6 +16,7 @@ mod connection_finalization;
 pub mod credssp;
 mod license_exchange;
 mod server_name;
+pub mod standard_security;
 
 use core::any::Any;
 use core::fmt;
@@ -24,16 +25,20 @@ use std::sync::Arc;
 use ironrdp_core::{encode_buf, encode_vec, Encode, WriteBuf};
 use ironrdp_pdu::nego::NegoRequestData;
 use ironrdp_pdu::rdp::capability_sets;
-use ironrdp_pdu::rdp::client_info::PerformanceFlags;
+use ironrdp_pdu::rdp::client_info::{CompressionType, PerformanceFlags};
+use ironrdp_pdu::rdp::server_redirection::{ServerRedirectionFlags, ServerRedirectionPdu};
 use ironrdp_pdu::x224::X224;
 use ironrdp_pdu::{gcc, x224, PduHint};
 pub use sspi;
 
 pub use self::channel_connection::{ChannelConnectionSequence, ChannelConnectionState};
-pub use self::connection::{encode_send_data_request, ClientConnector, ClientConnectorState, ConnectionResult};
+pub use self::connection::{
+    client_auto_reconnect_packet, encode_send_data_request, ClientConnector, ClientConnectorState, ConnectionResult,
+};
 pub use self::connection_finalization::{ConnectionFinalizationSequence, ConnectionFinalizationState};
 pub use self::license_exchange::{LicenseExchangeSequence, LicenseExchangeState};
 pub use self::server_name::ServerName;
+pub use self::standard_security::StandardSecurity;
 pub use crate::license_exchange::LicenseCache;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -50,6 +55,55 @@ pub struct BitmapConfig {
     pub color_depth: u32,
 }
 
+/// Bitmap caches of the drawing orders, advertised with the Revision 2 Bitmap Cache Capability Set
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct BitmapCacheConfig {
+    /// Cell caches of the Revision 2 bitmap cache, up to 5
+    pub cell_caches: Vec<CellCacheConfig>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct CellCacheConfig {
+    /// Number of cells of the cache
+    pub entries: u16,
+    /// Whether the bitmaps of this cache are kept across sessions
+    pub persistent: bool,
+    /// Keys of the bitmaps already loaded in the cache, from its first cell
+    ///
+    /// They are sent to the server in the Persistent Key List PDUs, so that it doesn’t send these
+    /// bitmaps again.
+    pub persistent_keys: Vec<u64>,
+}
+
+/// Auto-reconnect cookie received from the server in the Save Session Info PDU
+///
+/// It allows the client to reconnect to its session without authenticating again.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct AutoReconnectCookie {
+    pub logon_id: u32,
+    pub random_bits: [u8; 16],
+}
+
+impl fmt::Debug for AutoReconnectCookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AutoReconnectCookie")
+            .field("logon_id", &self.logon_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl From<ironrdp_pdu::rdp::session_info::ServerAutoReconnect> for AutoReconnectCookie {
+    fn from(cookie: ironrdp_pdu::rdp::session_info::ServerAutoReconnect) -> Self {
+        Self {
+            logon_id: cookie.logon_id,
+            random_bits: cookie.random_bits,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct SmartCardIdentity {
     /// DER-encoded X509 certificate
@@ -147,6 +201,14 @@ pub struct Config {
     /// computers.
     #[doc(alias("enable_nla", "nla"))]
     pub enable_credssp: bool,
+    /// Standard RDP Security, using RC4 or FIPS 3DES encryption
+    ///
+    /// When no other security protocol is enabled or selected by the server, the connection is
+    /// encrypted at the RDP layer, with keys exchanged using the server certificate sent in the Server
+    /// Security Data. This certificate is never verified, and the ciphers are weak: this legacy
+    /// protocol is trivially exposed to man-in-the-middle attacks, and should only be enabled when
+    /// connecting to old servers which do not support anything else.
+    pub enable_standard_rdp_security: bool,
     pub credentials: Credentials,
     pub domain: Option<String>,
     /// The build number of the client.
@@ -161,6 +223,11 @@ pub struct Config {
     pub keyboard_layout: u32,
     pub ime_file_name: String,
     pub bitmap: Option<BitmapConfig>,
+    /// If set, the bitmap caches of the drawing orders are advertised to the server
+    ///
+    /// The MemBlt and Mem3Blt orders are then supported, and the server can send bitmaps once for the
+    /// whole session, or for several sessions when the caches are persistent.
+    pub bitmap_cache: Option<BitmapCacheConfig>,
     pub dig_product_id: String,
     pub client_dir: String,
     pub platform: capability_sets::MajorPlatformType,
@@ -177,6 +244,24 @@ pub struct Config {
     pub request_data: Option<NegoRequestData>,
     /// If true, the INFO_AUTOLOGON flag is set in the [`ClientInfoPdu`](ironrdp_pdu::rdp::ClientInfoPdu)
     pub autologon: bool,
+    /// If true, the SUPPORT_DYN_VC_GFX_PROTOCOL early capability flag is set
+    ///
+    /// This tells the server that the Graphics Pipeline Extension can be used. A graphics pipeline
+    /// client must then be registered as a dynamic virtual channel.
+    pub enable_gfx: bool,
+    /// If set, the INFO_COMPRESSION flag is set in the [`ClientInfoPdu`](ironrdp_pdu::rdp::ClientInfoPdu)
+    /// along with the highest bulk compression type supported by the client
+    ///
+    /// The server may then compress the PDUs it sends using this type or any lower one.
+    pub compression_type: Option<CompressionType>,
+    /// If set, the client reconnects to the session identified by this cookie
+    ///
+    /// The cookie of the current session is provided by the active stage once the user is logged on.
+    pub auto_reconnect: Option<AutoReconnectCookie>,
+    /// If set, the client is connecting to the session of a server redirection
+    ///
+    /// This becomes the `redirected_session_id` in the [`TS_UD_CS_CLUSTER`](gcc::ClientClusterData) structure.
+    pub redirected_session_id: Option<u32>,
     pub license_cache: Option<Arc<dyn LicenseCache>>,
 
     // FIXME(@CBenoit): these are client-only options, not part of the connector.
@@ -187,6 +272,68 @@ pub struct Config {
 
 ironrdp_core::assert_impl!(Config: Send, Sync);
 
+impl Config {
+    /// Updates the configuration to connect to the target of a server redirection.
+    ///
+    /// The load balancing info is sent verbatim as the routing token of the X.224 Connection Request, and
+    /// the session to reconnect to is sent in the Client Cluster Data. The caller is responsible for connecting
+    /// to the [target address](ServerRedirectionPdu::target_address), if any, or to the same server
+    /// otherwise.
+    ///
+    /// The password of the redirection, or the password cookie standing for it, replaces the configured
+    /// password. It is ignored when encrypted with the public key of the target server, since it cannot
+    /// be sent as is.
+    pub fn apply_redirection(&mut self, redirection: &ServerRedirectionPdu) {
+        if let Some(load_balance_info) = &redirection.load_balance_info {
+            self.request_data = Some(NegoRequestData::load_balance_info(load_balance_info.clone()));
+        }
+
+        if let (Some(redirected_username), Credentials::UsernamePassword { username, .. }) =
+            (&redirection.username, &mut self.credentials)
+        {
+            username.clone_from(redirected_username);
+        }
+
+        if let (Some(redirected_password), Credentials::UsernamePassword { password, .. }) =
+            (&redirection.password, &mut self.credentials)
+        {
+            if redirection
+                .flags
+                .contains(ServerRedirectionFlags::PASSWORD_IS_PK_ENCRYPTED)
+            {
+                warn!("Ignoring the redirection password, encrypted for the target server");
+            } else if let Some(redirected_password) = redirection_password(redirected_password) {
+                *password = redirected_password;
+            } else {
+                warn!("Ignoring the redirection password, not a valid UTF-16 string");
+            }
+        }
+
+        if redirection.domain.is_some() {
+            self.domain.clone_from(&redirection.domain);
+        }
+
+        self.redirected_session_id = Some(redirection.session_id);
+    }
+}
+
+/// Decodes the null-terminated UTF-16 password of a server redirection
+///
+/// The password cookies are sent back verbatim in the Client Info PDU, which encodes the password in
+/// UTF-16: the cookie is kept as a string encoding to the same bytes.
+fn redirection_password(password: &[u8]) -> Option<String> {
+    if password.len() % 2 != 0 {
+        return None;
+    }
+
+    let password = password
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]));
+    let password = char::decode_utf16(password).collect::<Result<String, _>>().ok()?;
+
+    Some(password.trim_end_matches('\0').to_owned())
+}
+
 pub trait State: Send + fmt::Debug + 'static {
     fn name(&self) -> &'static str;
     fn is_terminal(&self) -> bool;
@@ -270,6 +417,10 @@ pub enum ConnectorErrorKind {
     Credssp(sspi::Error),
     Reason(String),
     AccessDenied,
+    /// The server redirected the client to another server, to which it should connect instead
+    ///
+    /// See [`Config::apply_redirection`].
+    Redirection(Box<ServerRedirectionPdu>),
     General,
     Custom,
 }
@@ -282,6 +433,7 @@ impl fmt::Display for ConnectorErrorKind {
             ConnectorErrorKind::Credssp(_) => write!(f, "CredSSP"),
             ConnectorErrorKind::Reason(description) => write!(f, "reason: {description}"),
             ConnectorErrorKind::AccessDenied => write!(f, "access denied"),
+            ConnectorErrorKind::Redirection(_) => write!(f, "server redirection"),
             ConnectorErrorKind::General => write!(f, "general error"),
             ConnectorErrorKind::Custom => write!(f, "custom error"),
         }
@@ -296,6 +448,7 @@ impl std::error::Error for ConnectorErrorKind {
             ConnectorErrorKind::Credssp(e) => Some(e),
             ConnectorErrorKind::Reason(_) => None,
             ConnectorErrorKind::AccessDenied => None,
+            ConnectorErrorKind::Redirection(_) => None,
             ConnectorErrorKind::Custom => None,
             ConnectorErrorKind::General => None,
         }
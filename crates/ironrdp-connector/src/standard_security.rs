@@ -0,0 +1,710 @@
+//! Standard RDP Security
+//!
+//! Implements the RC4 and FIPS 140-1 (3DES) schemes described in MS-RDPBCGR section 5.3. Standard RDP
+//! Security relies on a server certificate which is not verified, and on weak ciphers. It is not secure
+//! against an active attacker, and should only be used with servers that offer nothing else.
+
+use core::fmt;
+use std::borrow::Cow;
+
+use cbc::cipher::{BlockDecryptMut as _, BlockEncryptMut as _, KeyIvInit as _};
+use hmac::{Hmac, Mac as _};
+use ironrdp_core::{decode, encode_vec, Decode as _, ReadCursor, WriteBuf};
+use ironrdp_pdu::crypto::rc4::Rc4;
+use ironrdp_pdu::fast_path::{EncryptionFlags, FastPathHeader};
+use ironrdp_pdu::gcc::EncryptionMethod;
+use ironrdp_pdu::mcs::{McsMessage, SendDataIndication, SendDataRequest};
+use ironrdp_pdu::rdp::headers::{BasicSecurityHeader, BasicSecurityHeaderFlags};
+use ironrdp_pdu::rdp::server_license::cert::CertificateType;
+use ironrdp_pdu::rdp::server_license::ServerCertificate;
+use ironrdp_pdu::x224::X224;
+use ironrdp_pdu::{find_size, Action, PduHint};
+use md5::{Digest as _, Md5};
+use num_bigint::BigUint;
+use sha1::Sha1;
+
+use crate::{ConnectorError, ConnectorErrorExt as _, ConnectorResult, Sequence, State, Written};
+
+pub const CLIENT_RANDOM_LEN: usize = 32;
+
+/// Number of packets after which the RC4 keys are refreshed
+const KEY_UPDATE_INTERVAL: u32 = 4096;
+
+const SIGNATURE_LEN: usize = 8;
+const FIPS_INFORMATION_LEN: u16 = 0x10;
+const FIPS_VERSION: u8 = 1;
+const FIPS_IV: [u8; 8] = [0x12, 0x34, 0x56, 0x78, 0x90, 0xAB, 0xCD, 0xEF];
+const FIPS_BLOCK_LEN: usize = 8;
+
+const PAD1: [u8; 40] = [0x36; 40];
+const PAD2: [u8; 48] = [0x5C; 48];
+
+/// Security header flags identifying a PDU which is expected by the next layer
+const PDU_TYPE_FLAGS: BasicSecurityHeaderFlags = BasicSecurityHeaderFlags::EXCHANGE_PKT
+    .union(BasicSecurityHeaderFlags::TRANSPORT_REQ)
+    .union(BasicSecurityHeaderFlags::TRANSPORT_RSP)
+    .union(BasicSecurityHeaderFlags::INFO_PKT)
+    .union(BasicSecurityHeaderFlags::LICENSE_PKT)
+    .union(BasicSecurityHeaderFlags::LICENSE_ENCRYPT_CS)
+    .union(BasicSecurityHeaderFlags::LICENSE_ENCRYPT_SC)
+    .union(BasicSecurityHeaderFlags::REDIRECTION_PKT)
+    .union(BasicSecurityHeaderFlags::AUTODETECT_REQ)
+    .union(BasicSecurityHeaderFlags::AUTODETECT_RSP)
+    .union(BasicSecurityHeaderFlags::HEARTBEAT);
+
+type TdesEncryptor = cbc::Encryptor<des::TdesEde3>;
+type TdesDecryptor = cbc::Decryptor<des::TdesEde3>;
+
+/// Encryption state of a connection using Standard RDP Security
+///
+/// Created once the client random is generated during the basic settings exchange, and used to
+/// encrypt and decrypt every PDU sent on the connection after the Security Exchange PDU.
+pub struct StandardSecurity {
+    client_random: [u8; CLIENT_RANDOM_LEN],
+    cipher: Cipher,
+    /// Whether the outgoing packets are signed with the salted MAC
+    salted_mac: bool,
+    /// Number of packets encrypted so far, used by the salted MAC and by FIPS signatures
+    encrypt_count: u32,
+    /// Number of packets decrypted so far, used by the salted MAC and by FIPS signatures
+    decrypt_count: u32,
+}
+
+enum Cipher {
+    Rc4 {
+        method: EncryptionMethod,
+        mac_key: Vec<u8>,
+        encrypt: Rc4Key,
+        decrypt: Rc4Key,
+    },
+    Fips {
+        sign_key: [u8; 20],
+        encrypt: TdesEncryptor,
+        decrypt: TdesDecryptor,
+    },
+}
+
+struct Rc4Key {
+    initial: Vec<u8>,
+    current: Vec<u8>,
+    rc4: Rc4,
+    use_count: u32,
+}
+
+impl fmt::Debug for StandardSecurity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let method = match &self.cipher {
+            Cipher::Rc4 { method, .. } => *method,
+            Cipher::Fips { .. } => EncryptionMethod::FIPS,
+        };
+
+        f.debug_struct("StandardSecurity")
+            .field("method", &method)
+            .field("salted_mac", &self.salted_mac)
+            .field("encrypt_count", &self.encrypt_count)
+            .field("decrypt_count", &self.decrypt_count)
+            .finish_non_exhaustive()
+    }
+}
+
+impl StandardSecurity {
+    /// Derives the session keys from both randoms, for the encryption method selected by the server
+    pub fn new(
+        method: EncryptionMethod,
+        client_random: [u8; CLIENT_RANDOM_LEN],
+        server_random: &[u8; 32],
+    ) -> ConnectorResult<Self> {
+        let cipher = if method == EncryptionMethod::FIPS {
+            Cipher::fips(&client_random, server_random)
+        } else if [
+            EncryptionMethod::BIT_40,
+            EncryptionMethod::BIT_56,
+            EncryptionMethod::BIT_128,
+        ]
+        .contains(&method)
+        {
+            Cipher::rc4(method, &client_random, server_random)
+        } else {
+            return Err(reason_err!(
+                "StandardSecurity",
+                "unsupported encryption method: {method:?}"
+            ));
+        };
+
+        Ok(Self {
+            client_random,
+            cipher,
+            salted_mac: false,
+            encrypt_count: 0,
+            decrypt_count: 0,
+        })
+    }
+
+    /// Whether the salted MAC is used for the signatures of outgoing packets
+    ///
+    /// The salted MAC is negotiated with the ENC_SALTED_CHECKSUM flag of the General Capability Sets. The
+    /// server then signs its packets with it (SEC_SECURE_CHECKSUM), and the client does the same from the
+    /// first such packet on. Incoming packets are always verified according to their own flags.
+    pub fn salted_mac(&self) -> bool {
+        self.salted_mac
+    }
+
+    pub fn client_random(&self) -> &[u8; CLIENT_RANDOM_LEN] {
+        &self.client_random
+    }
+
+    /// Decrypts a frame received from the server, and verifies its signature
+    ///
+    /// The security header is removed from slow-path PDUs, unless the PDU is one of those carrying
+    /// their own security header when Enhanced RDP Security is in effect (licensing, redirection…).
+    /// Frames which are not encrypted are returned as is.
+    pub fn decrypt_frame(&mut self, frame: &[u8]) -> ConnectorResult<Vec<u8>> {
+        let Some(&first_byte) = frame.first() else {
+            return Ok(Vec::new());
+        };
+
+        match Action::from_fp_output_header(first_byte) {
+            Ok(Action::FastPath) => self.decrypt_fast_path(frame),
+            Ok(Action::X224) => self.decrypt_x224(frame),
+            Err(_) => Err(general_err!("invalid action code")),
+        }
+    }
+
+    /// Encrypts and signs the frames to be sent to the server
+    ///
+    /// `frames` may contain several frames back to back. The user data of MCS Send Data Requests and
+    /// fast-path input events are encrypted, other frames are kept unchanged.
+    pub fn encrypt_frames(&mut self, frames: &[u8]) -> ConnectorResult<Vec<u8>> {
+        let mut output = Vec::with_capacity(frames.len() + 32);
+        let mut rest = frames;
+
+        while !rest.is_empty() {
+            let info = find_size(rest)
+                .map_err(ConnectorError::decode)?
+                .filter(|info| info.length <= rest.len())
+                .ok_or_else(|| general_err!("truncated frame"))?;
+
+            let (frame, tail) = rest.split_at(info.length);
+
+            match info.action {
+                Action::FastPath => self.encrypt_fast_path(frame, &mut output)?,
+                Action::X224 => self.encrypt_x224(frame, &mut output)?,
+            }
+
+            rest = tail;
+        }
+
+        Ok(output)
+    }
+
+    /// Builds the user data of an encrypted slow-path PDU, starting with the security header
+    pub(crate) fn encrypt_user_data(&mut self, flags: BasicSecurityHeaderFlags, data: &[u8]) -> Vec<u8> {
+        let mut flags = flags | BasicSecurityHeaderFlags::ENCRYPT;
+        let salted = self.salted_mac && matches!(self.cipher, Cipher::Rc4 { .. });
+        if salted {
+            flags |= BasicSecurityHeaderFlags::SECURE_CHECKSUM;
+        }
+
+        let mut user_data = encode_vec(&BasicSecurityHeader { flags }).expect("infallible");
+        self.seal(salted, data, &mut user_data);
+
+        user_data
+    }
+
+    fn decrypt_x224(&mut self, frame: &[u8]) -> ConnectorResult<Vec<u8>> {
+        let message = decode::<X224<McsMessage<'_>>>(frame).map_err(ConnectorError::decode)?;
+
+        let McsMessage::SendDataIndication(indication) = message.0 else {
+            return Ok(frame.to_vec());
+        };
+
+        let mut src = ReadCursor::new(&indication.user_data);
+        let header = BasicSecurityHeader::decode(&mut src).map_err(ConnectorError::decode)?;
+
+        let data = if header.flags.contains(BasicSecurityHeaderFlags::ENCRYPT) {
+            let salted = header.flags.contains(BasicSecurityHeaderFlags::SECURE_CHECKSUM);
+            self.open(salted, src.remaining())?
+        } else {
+            src.remaining().to_vec()
+        };
+
+        let user_data = if header.flags.intersects(PDU_TYPE_FLAGS) {
+            let header = BasicSecurityHeader {
+                flags: header.flags - BasicSecurityHeaderFlags::ENCRYPT - BasicSecurityHeaderFlags::SECURE_CHECKSUM,
+            };
+
+            let mut user_data = encode_vec(&header).map_err(ConnectorError::encode)?;
+            user_data.extend_from_slice(&data);
+            user_data
+        } else {
+            data
+        };
+
+        encode_vec(&X224(SendDataIndication {
+            initiator_id: indication.initiator_id,
+            channel_id: indication.channel_id,
+            user_data: Cow::Owned(user_data),
+        }))
+        .map_err(ConnectorError::encode)
+    }
+
+    fn decrypt_fast_path(&mut self, frame: &[u8]) -> ConnectorResult<Vec<u8>> {
+        let mut src = ReadCursor::new(frame);
+        let header = FastPathHeader::decode(&mut src).map_err(ConnectorError::decode)?;
+
+        if !header.flags.contains(EncryptionFlags::ENCRYPTED) {
+            return Ok(frame.to_vec());
+        }
+
+        if src.len() < header.data_length {
+            return Err(general_err!("truncated fast-path PDU"));
+        }
+
+        let salted = header.flags.contains(EncryptionFlags::SECURE_CHECKSUM);
+        let data = self.open(salted, src.read_slice(header.data_length))?;
+
+        let mut output =
+            encode_vec(&FastPathHeader::new(EncryptionFlags::empty(), data.len())).map_err(ConnectorError::encode)?;
+        output.extend_from_slice(&data);
+
+        Ok(output)
+    }
+
+    fn encrypt_x224(&mut self, frame: &[u8], output: &mut Vec<u8>) -> ConnectorResult<()> {
+        let message = decode::<X224<McsMessage<'_>>>(frame).map_err(ConnectorError::decode)?;
+
+        let McsMessage::SendDataRequest(request) = message.0 else {
+            output.extend_from_slice(frame);
+            return Ok(());
+        };
+
+        let user_data = self.encrypt_user_data(BasicSecurityHeaderFlags::empty(), &request.user_data);
+
+        let frame = encode_vec(&X224(SendDataRequest {
+            initiator_id: request.initiator_id,
+            channel_id: request.channel_id,
+            user_data: Cow::Owned(user_data),
+        }))
+        .map_err(ConnectorError::encode)?;
+        output.extend_from_slice(&frame);
+
+        Ok(())
+    }
+
+    fn encrypt_fast_path(&mut self, frame: &[u8], output: &mut Vec<u8>) -> ConnectorResult<()> {
+        // fpInputHeader, followed by the length in one or two bytes
+        let header = frame[0];
+        let length_size = match frame.get(1) {
+            Some(byte) if byte & 0x80 != 0 => 2,
+            Some(_) => 1,
+            None => return Err(general_err!("truncated fast-path PDU")),
+        };
+        let Some(data) = frame.get(1 + length_size..) else {
+            return Err(general_err!("truncated fast-path PDU"));
+        };
+
+        let salted = self.salted_mac && matches!(self.cipher, Cipher::Rc4 { .. });
+        let mut flags = EncryptionFlags::ENCRYPTED;
+        if salted {
+            flags |= EncryptionFlags::SECURE_CHECKSUM;
+        }
+
+        let mut sealed = Vec::with_capacity(data.len() + 20);
+        self.seal(salted, data, &mut sealed);
+
+        output.push((header & 0x3F) | (flags.bits() << 6));
+
+        // The length includes the header byte and the length field itself.
+        if let Some(length) = u8::try_from(2 + sealed.len()).ok().filter(|length| *length <= 0x7F) {
+            output.push(length);
+        } else {
+            let length = u16::try_from(3 + sealed.len())
+                .ok()
+                .filter(|length| *length <= 0x7FFF)
+                .ok_or_else(|| general_err!("fast-path PDU is too big"))?;
+            output.extend_from_slice(&(length | 0x8000).to_be_bytes());
+        }
+
+        output.extend_from_slice(&sealed);
+
+        Ok(())
+    }
+
+    /// Writes the signature of `data` followed by its encrypted form, preceded by the FIPS information
+    /// when FIPS encryption is in effect
+    fn seal(&mut self, salted: bool, data: &[u8], output: &mut Vec<u8>) {
+        let count = self.encrypt_count;
+        self.encrypt_count = self.encrypt_count.wrapping_add(1);
+
+        match &mut self.cipher {
+            Cipher::Rc4 {
+                method,
+                mac_key,
+                encrypt,
+                ..
+            } => {
+                output.extend_from_slice(&mac_signature(mac_key, data, salted.then_some(count)));
+                output.extend_from_slice(&encrypt.process(*method, data));
+            }
+            Cipher::Fips { sign_key, encrypt, .. } => {
+                let padding = (FIPS_BLOCK_LEN - data.len() % FIPS_BLOCK_LEN) % FIPS_BLOCK_LEN;
+
+                output.extend_from_slice(&FIPS_INFORMATION_LEN.to_le_bytes());
+                output.push(FIPS_VERSION);
+                output.push(u8::try_from(padding).expect("padding is less than a block"));
+                output.extend_from_slice(&fips_signature(sign_key, data, count));
+
+                let start = output.len();
+                output.extend_from_slice(data);
+                output.resize(output.len() + padding, 0);
+
+                for block in output[start..].chunks_exact_mut(FIPS_BLOCK_LEN) {
+                    encrypt.encrypt_block_mut(block.into());
+                }
+            }
+        }
+    }
+
+    /// Decrypts the data following a security header, and verifies its signature
+    ///
+    /// The salted MAC is used for the next outgoing packets once the server has used it.
+    fn open(&mut self, salted: bool, src: &[u8]) -> ConnectorResult<Vec<u8>> {
+        let count = self.decrypt_count;
+        self.decrypt_count = self.decrypt_count.wrapping_add(1);
+
+        let mut src = ReadCursor::new(src);
+
+        match &mut self.cipher {
+            Cipher::Rc4 {
+                method,
+                mac_key,
+                decrypt,
+                ..
+            } => {
+                if src.len() < SIGNATURE_LEN {
+                    return Err(general_err!("missing data signature"));
+                }
+
+                let signature: [u8; SIGNATURE_LEN] = src.read_array();
+                let data = decrypt.process(*method, src.remaining());
+
+                if mac_signature(mac_key, &data, salted.then_some(count)) != signature {
+                    return Err(general_err!("invalid data signature"));
+                }
+
+                self.salted_mac |= salted;
+
+                Ok(data)
+            }
+            Cipher::Fips { sign_key, decrypt, .. } => {
+                if src.len() < 4 + SIGNATURE_LEN {
+                    return Err(general_err!("missing FIPS security header"));
+                }
+
+                let _length = src.read_u16();
+                let _version = src.read_u8();
+                let padding = usize::from(src.read_u8());
+                let signature: [u8; SIGNATURE_LEN] = src.read_array();
+
+                let mut data = src.remaining().to_vec();
+                if data.len() % FIPS_BLOCK_LEN != 0 || padding >= FIPS_BLOCK_LEN || padding > data.len() {
+                    return Err(general_err!("invalid FIPS encrypted data length"));
+                }
+
+                for block in data.chunks_exact_mut(FIPS_BLOCK_LEN) {
+                    decrypt.decrypt_block_mut(block.into());
+                }
+                data.truncate(data.len() - padding);
+
+                if fips_signature(sign_key, &data, count) != signature {
+                    return Err(general_err!("invalid data signature"));
+                }
+
+                Ok(data)
+            }
+        }
+    }
+}
+
+/// A [`Sequence`] whose PDUs are decrypted and encrypted with Standard RDP Security
+///
+/// Used to drive the connection activation sequence, both during the connection and on a
+/// Deactivation-Reactivation Sequence.
+pub struct SecuredSequence<'a, S> {
+    sequence: &'a mut S,
+    security: &'a mut StandardSecurity,
+}
+
+impl<'a, S: Sequence> SecuredSequence<'a, S> {
+    pub fn new(sequence: &'a mut S, security: &'a mut StandardSecurity) -> Self {
+        Self { sequence, security }
+    }
+}
+
+impl<S: Sequence> Sequence for SecuredSequence<'_, S> {
+    fn next_pdu_hint(&self) -> Option<&dyn PduHint> {
+        self.sequence.next_pdu_hint()
+    }
+
+    fn state(&self) -> &dyn State {
+        self.sequence.state()
+    }
+
+    fn step(&mut self, input: &[u8], output: &mut WriteBuf) -> ConnectorResult<Written> {
+        let input = if input.is_empty() {
+            Cow::Borrowed(input)
+        } else {
+            Cow::Owned(self.security.decrypt_frame(input)?)
+        };
+
+        let mut buf = WriteBuf::new();
+        if self.sequence.step(&input, &mut buf)?.is_nothing() {
+            return Ok(Written::Nothing);
+        }
+
+        let frames = self.security.encrypt_frames(buf.filled())?;
+        output.write_slice(&frames);
+
+        Written::from_size(frames.len())
+    }
+}
+
+impl Cipher {
+    fn rc4(method: EncryptionMethod, client_random: &[u8; 32], server_random: &[u8; 32]) -> Self {
+        let pre_master_secret = [&client_random[..24], &server_random[..24]].concat();
+
+        let master_secret = [b"A".as_slice(), b"BB", b"CCC"]
+            .iter()
+            .flat_map(|input| salted_hash(&pre_master_secret, input, client_random, server_random))
+            .collect::<Vec<u8>>();
+
+        let session_key_blob = [b"X".as_slice(), b"YY", b"ZZZ"]
+            .iter()
+            .flat_map(|input| salted_hash(&master_secret, input, server_random, client_random))
+            .collect::<Vec<u8>>();
+
+        let final_hash = |key: &[u8]| -> Vec<u8> {
+            Md5::new()
+                .chain_update(key)
+                .chain_update(client_random)
+                .chain_update(server_random)
+                .finalize()
+                .to_vec()
+        };
+
+        let mut mac_key = session_key_blob[..16].to_vec();
+        let mut decrypt_key = final_hash(&session_key_blob[16..32]);
+        let mut encrypt_key = final_hash(&session_key_blob[32..48]);
+
+        for key in [&mut mac_key, &mut decrypt_key, &mut encrypt_key] {
+            salt_key(method, key);
+        }
+
+        Self::Rc4 {
+            method,
+            mac_key,
+            encrypt: Rc4Key::new(encrypt_key),
+            decrypt: Rc4Key::new(decrypt_key),
+        }
+    }
+
+    fn fips(client_random: &[u8; 32], server_random: &[u8; 32]) -> Self {
+        let key_t = |client: &[u8], server: &[u8]| -> [u8; 21] {
+            let hash = Sha1::new().chain_update(client).chain_update(server).finalize();
+
+            let mut key = [0; 21];
+            key[..20].copy_from_slice(&hash);
+            key[20] = hash[0];
+            key
+        };
+
+        let encrypt_key_t = key_t(&client_random[16..], &server_random[16..]);
+        let decrypt_key_t = key_t(&client_random[..16], &server_random[..16]);
+
+        let sign_key = Sha1::new()
+            .chain_update(&decrypt_key_t[..20])
+            .chain_update(&encrypt_key_t[..20])
+            .finalize()
+            .into();
+
+        Self::Fips {
+            sign_key,
+            encrypt: TdesEncryptor::new(&expand_des_key(&encrypt_key_t).into(), &FIPS_IV.into()),
+            decrypt: TdesDecryptor::new(&expand_des_key(&decrypt_key_t).into(), &FIPS_IV.into()),
+        }
+    }
+}
+
+impl Rc4Key {
+    fn new(key: Vec<u8>) -> Self {
+        Self {
+            rc4: Rc4::new(&key),
+            initial: key.clone(),
+            current: key,
+            use_count: 0,
+        }
+    }
+
+    fn process(&mut self, method: EncryptionMethod, data: &[u8]) -> Vec<u8> {
+        if self.use_count == KEY_UPDATE_INTERVAL {
+            self.update(method);
+        }
+
+        self.use_count += 1;
+
+        self.rc4.process(data)
+    }
+
+    /// Derives the next key from the initial and the current keys (5.3.7 Session Key Updates)
+    fn update(&mut self, method: EncryptionMethod) {
+        let sha1 = Sha1::new()
+            .chain_update(&self.initial)
+            .chain_update(PAD1)
+            .chain_update(&self.current)
+            .finalize();
+
+        let md5 = Md5::new()
+            .chain_update(&self.initial)
+            .chain_update(PAD2)
+            .chain_update(sha1)
+            .finalize();
+
+        let temp_key = &md5[..self.initial.len()];
+        let mut key = Rc4::new(temp_key).process(temp_key);
+        salt_key(method, &mut key);
+
+        self.rc4 = Rc4::new(&key);
+        self.current = key;
+        self.use_count = 0;
+    }
+}
+
+/// Encrypts the client random with the public key found in the server certificate of the Server
+/// Security Data
+pub(crate) fn encrypt_client_random(server_cert: &[u8], client_random: &[u8]) -> ConnectorResult<Vec<u8>> {
+    let certificate = decode::<ServerCertificate>(server_cert).map_err(ConnectorError::decode)?;
+
+    let (modulus, public_exponent) = match certificate.certificate {
+        CertificateType::Proprietary(certificate) => (
+            BigUint::from_bytes_le(&certificate.public_key.modulus),
+            BigUint::from(certificate.public_key.public_exponent),
+        ),
+        CertificateType::X509(chain) => {
+            let cert_der = chain
+                .certificate_array
+                .last()
+                .ok_or_else(|| general_err!("empty X.509 certificate chain"))?;
+
+            let cert: picky_asn1_x509::Certificate =
+                picky_asn1_der::from_bytes(cert_der).map_err(|e| custom_err!("invalid X.509 server certificate", e))?;
+
+            let picky_asn1_x509::PublicKey::Rsa(public_key) =
+                cert.tbs_certificate.subject_public_key_info.subject_public_key
+            else {
+                return Err(general_err!("server certificate public key is not an RSA key"));
+            };
+
+            (
+                BigUint::from_bytes_be(public_key.0.modulus.as_unsigned_bytes_be()),
+                BigUint::from_bytes_be(public_key.0.public_exponent.as_unsigned_bytes_be()),
+            )
+        }
+    };
+
+    let modulus_len = usize::try_from((modulus.bits() + 7) / 8).map_err(|_| general_err!("modulus is too big"))?;
+
+    let mut encrypted = BigUint::from_bytes_le(client_random)
+        .modpow(&public_exponent, &modulus)
+        .to_bytes_le();
+    // The encrypted random is followed by eight bytes of zero padding.
+    encrypted.resize(modulus_len + 8, 0);
+
+    Ok(encrypted)
+}
+
+/// SaltedHash(S, I) = MD5(S + SHA(I + S + ClientRandom + ServerRandom)), with randoms in the given order
+fn salted_hash(salt: &[u8], input: &[u8], random1: &[u8], random2: &[u8]) -> [u8; 16] {
+    let sha1 = Sha1::new()
+        .chain_update(input)
+        .chain_update(salt)
+        .chain_update(random1)
+        .chain_update(random2)
+        .finalize();
+
+    Md5::new().chain_update(salt).chain_update(sha1).finalize().into()
+}
+
+/// Reduces a 128-bit key to the size used by the 40-bit and 56-bit encryption methods
+fn salt_key(method: EncryptionMethod, key: &mut Vec<u8>) {
+    if method == EncryptionMethod::BIT_40 {
+        key.truncate(8);
+        key[..3].copy_from_slice(&[0xD1, 0x26, 0x9E]);
+    } else if method == EncryptionMethod::BIT_56 {
+        key.truncate(8);
+        key[0] = 0xD1;
+    }
+}
+
+/// MAC signature of the data (5.3.6.1 Non-FIPS), salted with the encryption count if any
+fn mac_signature(mac_key: &[u8], data: &[u8], count: Option<u32>) -> [u8; SIGNATURE_LEN] {
+    let data_len = u32::try_from(data.len()).unwrap_or(u32::MAX);
+
+    let mut sha1 = Sha1::new()
+        .chain_update(mac_key)
+        .chain_update(PAD1)
+        .chain_update(data_len.to_le_bytes())
+        .chain_update(data);
+    if let Some(count) = count {
+        sha1.update(count.to_le_bytes());
+    }
+
+    let md5 = Md5::new()
+        .chain_update(mac_key)
+        .chain_update(PAD2)
+        .chain_update(sha1.finalize())
+        .finalize();
+
+    let mut signature = [0; SIGNATURE_LEN];
+    signature.copy_from_slice(&md5[..SIGNATURE_LEN]);
+    signature
+}
+
+/// Signature of the data in FIPS mode (5.3.6.2 FIPS)
+fn fips_signature(sign_key: &[u8; 20], data: &[u8], count: u32) -> [u8; SIGNATURE_LEN] {
+    let mut hmac = Hmac::<Sha1>::new_from_slice(sign_key).expect("HMAC accepts keys of any size");
+    hmac.update(data);
+    hmac.update(&count.to_le_bytes());
+
+    let mut signature = [0; SIGNATURE_LEN];
+    signature.copy_from_slice(&hmac.finalize().into_bytes()[..SIGNATURE_LEN]);
+    signature
+}
+
+/// Expands a 168-bit key to a 192-bit 3DES key, inserting a parity bit after every seven bits
+fn expand_des_key(key: &[u8; 21]) -> [u8; 24] {
+    let reversed = key.map(u8::reverse_bits);
+
+    let mut output = [0u8; 24];
+    for (i, byte) in output.iter_mut().enumerate() {
+        let bit = i * 7;
+        let (index, shift) = (bit / 8, bit % 8);
+
+        let mut value = reversed[index] << shift;
+        if shift > 1 {
+            value |= reversed[index + 1] >> (8 - shift);
+        }
+
+        *byte = odd_parity((value & 0xFE).reverse_bits());
+    }
+
+    output
+}
+
+fn odd_parity(byte: u8) -> u8 {
+    let byte = byte & 0xFE;
+    if byte.count_ones() % 2 == 0 {
+        byte | 1
+    } else {
+        byte
+    }
+}
@@ -68,6 +68,13 @@ impl DrdynvcClient {
         self.dynamic_channels.get_by_type_id(TypeId::of::<T>())
     }
 
+    pub fn get_dvc_by_type_id_mut<T>(&mut self) -> Option<&mut DynamicVirtualChannel>
+    where
+        T: DvcProcessor,
+    {
+        self.dynamic_channels.get_by_type_id_mut(TypeId::of::<T>())
+    }
+
     fn create_capabilities_response(&mut self) -> SvcMessage {
         let caps_response = DrdynvcClientPdu::Capabilities(CapabilitiesResponsePdu::new(CapsVersion::V1));
         debug!("Send DVC Capabilities Response PDU: {caps_response:?}");
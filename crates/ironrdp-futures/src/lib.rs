@@ -38,10 +38,10 @@ impl<S> StreamWrapper for FuturesStream<S> {
 
 impl<S> FramedRead for FuturesStream<S>
 where
-    S: Send + Sync + Unpin + AsyncRead,
+    S: Send + Unpin + AsyncRead,
 {
     type ReadFut<'read>
-        = Pin<Box<dyn core::future::Future<Output = io::Result<usize>> + Send + Sync + 'read>>
+        = Pin<Box<dyn core::future::Future<Output = io::Result<usize>> + Send + 'read>>
     where
         Self: 'read;
 
@@ -61,10 +61,10 @@ where
 
 impl<S> FramedWrite for FuturesStream<S>
 where
-    S: Send + Sync + Unpin + AsyncWrite,
+    S: Send + Unpin + AsyncWrite,
 {
     type WriteAllFut<'write>
-        = Pin<Box<dyn core::future::Future<Output = io::Result<()>> + Send + Sync + 'write>>
+        = Pin<Box<dyn core::future::Future<Output = io::Result<()>> + Send + 'write>>
     where
         Self: 'write;
 
@@ -0,0 +1,482 @@
+//! RDP Bulk Data Compression
+//!
+//! Implements the bulk compressors described in [MS-RDPBCGR] 3.1.8:
+//!
+//! - MPPC with an 8K history buffer (RDP 4.0),
+//! - MPPC with a 64K history buffer (RDP 5.0),
+//! - NCRUSH (RDP 6.0),
+//! - XCRUSH (RDP 6.1).
+//!
+//! All the fast-path and slow-path PDUs sent on a connection share the same compression
+//! history, so a single [`Decompressor`] (or [`Compressor`]) must be kept for the whole session.
+//!
+//! [MS-RDPBCGR]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/
+
+mod mppc;
+mod ncrush;
+mod xcrush;
+
+use core::mem;
+use core::ops::Range;
+
+use ironrdp_pdu::rdp::client_info::CompressionType;
+use ironrdp_pdu::rdp::headers::CompressionFlags;
+use thiserror::Error;
+
+use self::mppc::{Mppc, MppcCompressor};
+use self::ncrush::{NCrush, NCrushCompressor};
+use self::xcrush::{XCrush, XCrushCompressor};
+
+const MPPC_8K_HISTORY_SIZE: usize = 8 * 1024;
+const MPPC_64K_HISTORY_SIZE: usize = 64 * 1024;
+
+/// Payloads outside of this range are not worth compressing.
+const COMPRESSIBLE_SIZE: Range<usize> = 51..16384;
+
+const MIN_MATCH_LENGTH: usize = 3;
+const MATCH_FINDER_HASH_BITS: u32 = 14;
+
+/// Stateful bulk decompressor, keeping the history buffers of every compression type.
+///
+/// The NCRUSH and XCRUSH contexts are only allocated once a PDU compressed with the
+/// corresponding type is received.
+pub struct Decompressor {
+    mppc: Mppc,
+    ncrush: Option<NCrush>,
+    xcrush: Option<XCrush>,
+}
+
+impl Decompressor {
+    pub fn new() -> Self {
+        Self {
+            mppc: Mppc::new(),
+            ncrush: None,
+            xcrush: None,
+        }
+    }
+
+    /// Decompresses a PDU payload according to the compression flags and type of its header.
+    ///
+    /// The flush and reset flags are honored even if the payload is not compressed, so every
+    /// payload received on the connection must go through this method. Returns `input` as is
+    /// when the payload is not compressed.
+    pub fn decompress<'a>(
+        &'a mut self,
+        input: &'a [u8],
+        flags: CompressionFlags,
+        compression_type: CompressionType,
+    ) -> Result<&'a [u8], BulkError> {
+        match compression_type {
+            CompressionType::K8 => self.mppc.decompress(input, flags, MPPC_8K_HISTORY_SIZE),
+            CompressionType::K64 => self.mppc.decompress(input, flags, MPPC_64K_HISTORY_SIZE),
+            CompressionType::Rdp6 => self.ncrush.get_or_insert_with(NCrush::new).decompress(input, flags),
+            CompressionType::Rdp61 => self.xcrush.get_or_insert_with(XCrush::new).decompress(input, flags),
+        }
+    }
+}
+
+impl Default for Decompressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Stateful bulk compressor, keeping the history buffer of a single compression type.
+///
+/// The first compressed payload flushes the history of the peer, so a new compressor can be
+/// created at any point of the connection, e.g. after a deactivation-reactivation sequence.
+pub struct Compressor {
+    compression_type: CompressionType,
+    context: CompressionContext,
+}
+
+enum CompressionContext {
+    Mppc(MppcCompressor),
+    NCrush(NCrushCompressor),
+    XCrush(XCrushCompressor),
+}
+
+impl Compressor {
+    pub fn new(compression_type: CompressionType) -> Self {
+        let context = match compression_type {
+            CompressionType::K8 => CompressionContext::Mppc(MppcCompressor::new(MPPC_8K_HISTORY_SIZE)),
+            CompressionType::K64 => CompressionContext::Mppc(MppcCompressor::new(MPPC_64K_HISTORY_SIZE)),
+            CompressionType::Rdp6 => CompressionContext::NCrush(NCrushCompressor::new()),
+            CompressionType::Rdp61 => CompressionContext::XCrush(XCrushCompressor::new()),
+        };
+
+        Self {
+            compression_type,
+            context,
+        }
+    }
+
+    pub fn compression_type(&self) -> CompressionType {
+        self.compression_type
+    }
+
+    /// Compresses a PDU payload, appending the result to `output`.
+    ///
+    /// Returns the compression flags to set in the PDU header. Payloads which are too small or
+    /// too large to be compressed, or which would be expanded by the compression, are appended
+    /// as is; the returned flags are then empty or only require the peer to flush its history.
+    pub fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> CompressionFlags {
+        if !COMPRESSIBLE_SIZE.contains(&input.len()) {
+            output.extend_from_slice(input);
+            return CompressionFlags::empty();
+        }
+
+        match &mut self.context {
+            CompressionContext::Mppc(mppc) => mppc.compress(input, output),
+            CompressionContext::NCrush(ncrush) => ncrush.compress(input, output),
+            CompressionContext::XCrush(xcrush) => xcrush.compress(input, output),
+        }
+    }
+}
+
+/// Finds repeated sequences in a history buffer by remembering the last position of every
+/// (hashed) 3-byte prefix.
+struct MatchFinder {
+    /// Last position of each prefix plus one, zero when unset.
+    positions: Box<[u32]>,
+}
+
+impl MatchFinder {
+    fn new() -> Self {
+        Self {
+            positions: vec![0; 1 << MATCH_FINDER_HASH_BITS].into_boxed_slice(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.positions.fill(0);
+    }
+
+    /// Records `position` as the last occurrence of its prefix, returning the previous one.
+    fn insert(&mut self, data: &[u8], position: usize) -> Option<usize> {
+        let prefix = data.get(position..position + MIN_MATCH_LENGTH)?;
+        let value = (u32::from(prefix[0]) << 16) | (u32::from(prefix[1]) << 8) | u32::from(prefix[2]);
+        let hash = value.wrapping_mul(0x9E37_79B1) >> (32 - MATCH_FINDER_HASH_BITS);
+
+        let previous = mem::replace(&mut self.positions[hash as usize], position as u32 + 1);
+
+        previous.checked_sub(1).map(|previous| previous as usize)
+    }
+
+    /// Looks for a previous occurrence of the data located at `position`.
+    ///
+    /// Returns the distance to the match and its length, which may exceed the distance: the
+    /// decompressors copy byte by byte, so a match can overlap the data it produces.
+    fn find(&mut self, data: &[u8], position: usize, max_length: usize) -> Option<(usize, usize)> {
+        let candidate = self.insert(data, position)?;
+
+        let length = data[candidate..]
+            .iter()
+            .zip(&data[position..])
+            .take(max_length)
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        (length >= MIN_MATCH_LENGTH).then_some((position - candidate, length))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum BulkError {
+    #[error("unexpected end of compressed data")]
+    UnexpectedEndOfData,
+    #[error("invalid {0} code")]
+    InvalidCode(&'static str),
+    #[error("invalid copy offset ({0})")]
+    InvalidCopyOffset(usize),
+    #[error("history buffer overflow")]
+    HistoryBufferOverflow,
+    #[error("invalid level-1 compression flags ({0:#04x})")]
+    InvalidLevel1Flags(u8),
+    #[error("invalid match details")]
+    InvalidMatchDetails,
+    #[error("missing end-of-stream marker")]
+    MissingEndOfStream,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BELLS: &[u8] = b"for.whom.the.bell.tolls,.the.bell.tolls.for.thee!";
+
+    #[rustfmt::skip]
+    const BELLS_MPPC_8K: [u8; 33] = [
+        0x66, 0x6f, 0x72, 0x2e, 0x77, 0x68, 0x6f, 0x6d, 0x2e, 0x74, 0x68, 0x65, 0x2e, 0x62, 0x65, 0x6c,
+        0x6c, 0x2e, 0x74, 0x6f, 0x6c, 0x6c, 0x73, 0x2c, 0xf4, 0x37, 0x2e, 0x66, 0xfa, 0x1f, 0x19, 0x94,
+        0x84,
+    ];
+
+    #[rustfmt::skip]
+    const BELLS_MPPC_64K: [u8; 34] = [
+        0x66, 0x6f, 0x72, 0x2e, 0x77, 0x68, 0x6f, 0x6d, 0x2e, 0x74, 0x68, 0x65, 0x2e, 0x62, 0x65, 0x6c,
+        0x6c, 0x2e, 0x74, 0x6f, 0x6c, 0x6c, 0x73, 0x2c, 0xfa, 0x1b, 0x97, 0x33, 0x7e, 0x87, 0xe3, 0x32,
+        0x90, 0x80,
+    ];
+
+    #[rustfmt::skip]
+    const BELLS_NCRUSH: [u8; 44] = [
+        0xfb, 0x1d, 0x7e, 0xe4, 0xda, 0xc7, 0x1d, 0x70, 0xf8, 0xa1, 0x6b, 0x1f, 0x7d, 0xc0, 0xbe, 0x6b,
+        0xef, 0xb5, 0xef, 0x21, 0x87, 0xd0, 0xc5, 0xe1, 0x85, 0x71, 0xd4, 0x10, 0x16, 0xe7, 0xda, 0xfb,
+        0x1d, 0x7e, 0xe4, 0xda, 0x47, 0x1f, 0xb0, 0xef, 0xbe, 0xbd, 0xff, 0x2f,
+    ];
+
+    const MPPC_64K_COMPRESSED: &[u8] = include_bytes!("test_assets/mppc_rdp5.compressed.bin");
+    const MPPC_64K_DECOMPRESSED: &[u8] = include_bytes!("test_assets/mppc_rdp5.decompressed.bin");
+    const XCRUSH_COMPRESSED: &[u8] = include_bytes!("test_assets/xcrush.compressed.bin");
+    const XCRUSH_DECOMPRESSED: &[u8] = include_bytes!("test_assets/xcrush.decompressed.bin");
+
+    #[test]
+    fn uncompressed_data_is_returned_as_is() {
+        let mut decompressor = Decompressor::new();
+
+        for compression_type in [
+            CompressionType::K8,
+            CompressionType::K64,
+            CompressionType::Rdp6,
+            CompressionType::Rdp61,
+        ] {
+            let output = decompressor
+                .decompress(BELLS, CompressionFlags::FLUSHED, compression_type)
+                .unwrap();
+            assert_eq!(output, BELLS);
+        }
+    }
+
+    #[test]
+    fn decompresses_mppc_8k() {
+        let mut decompressor = Decompressor::new();
+
+        let output = decompressor
+            .decompress(
+                &BELLS_MPPC_8K,
+                CompressionFlags::COMPRESSED | CompressionFlags::AT_FRONT,
+                CompressionType::K8,
+            )
+            .unwrap();
+        assert_eq!(output, BELLS);
+    }
+
+    #[test]
+    fn decompresses_mppc_64k() {
+        let mut decompressor = Decompressor::new();
+
+        let output = decompressor
+            .decompress(
+                &BELLS_MPPC_64K,
+                CompressionFlags::COMPRESSED | CompressionFlags::AT_FRONT,
+                CompressionType::K64,
+            )
+            .unwrap();
+        assert_eq!(output, BELLS);
+
+        let output = decompressor
+            .decompress(
+                MPPC_64K_COMPRESSED,
+                CompressionFlags::COMPRESSED | CompressionFlags::AT_FRONT,
+                CompressionType::K64,
+            )
+            .unwrap();
+        assert_eq!(output, MPPC_64K_DECOMPRESSED);
+    }
+
+    #[test]
+    fn decompresses_ncrush() {
+        let mut decompressor = Decompressor::new();
+
+        let output = decompressor
+            .decompress(&BELLS_NCRUSH, CompressionFlags::COMPRESSED, CompressionType::Rdp6)
+            .unwrap();
+        assert_eq!(output, BELLS);
+    }
+
+    #[test]
+    fn decompresses_xcrush() {
+        let mut decompressor = Decompressor::new();
+
+        let output = decompressor
+            .decompress(XCRUSH_COMPRESSED, CompressionFlags::COMPRESSED, CompressionType::Rdp61)
+            .unwrap();
+        assert_eq!(output, XCRUSH_DECOMPRESSED);
+    }
+
+    #[test]
+    fn decompresses_xcrush_level_1_matches() {
+        let mut decompressor = Decompressor::new();
+
+        decompressor
+            .decompress(XCRUSH_COMPRESSED, CompressionFlags::COMPRESSED, CompressionType::Rdp61)
+            .unwrap();
+
+        // Level-1 compressed data, not compressed by the level-2 compressor: the level-2 flags are ignored.
+        // The matches point at "for whom the bell tolls" (offset 343) and "No man is an island" (offset 0)
+        // in the history filled by the previous packet.
+        #[rustfmt::skip]
+        let compressed = [
+            // Level1ComprFlags (L1_COMPRESSED), Level2ComprFlags
+            0x01, 0x21,
+            // MatchCount
+            0x02, 0x00,
+            // MatchLength, MatchOutputOffset, MatchHistoryOffset
+            0x17, 0x00, 0x08, 0x00, 0x57, 0x01, 0x00, 0x00,
+            0x13, 0x00, 0x21, 0x00, 0x00, 0x00, 0x00, 0x00,
+            // Literals
+            b'A', b's', b'k', b' ', b'n', b'o', b't', b' ', b';', b' ', b'.',
+        ];
+        let output = decompressor
+            .decompress(&compressed, CompressionFlags::COMPRESSED, CompressionType::Rdp61)
+            .unwrap();
+        assert_eq!(output, b"Ask not for whom the bell tolls; No man is an island.");
+    }
+
+    #[test]
+    fn history_is_kept_between_packets() {
+        let mut decompressor = Decompressor::new();
+
+        decompressor
+            .decompress(
+                &BELLS_MPPC_64K,
+                CompressionFlags::COMPRESSED | CompressionFlags::AT_FRONT,
+                CompressionType::K64,
+            )
+            .unwrap();
+
+        // CopyOffset 49 (11111 + 6 bits) followed by LengthOfMatch 16 (1110 + 4 bits), pointing
+        // at the beginning of the previous packet.
+        let compressed = [0b1111_1110, 0b0011_1100, 0b0000_0000];
+        let output = decompressor
+            .decompress(&compressed, CompressionFlags::COMPRESSED, CompressionType::K64)
+            .unwrap();
+        assert_eq!(output, &BELLS[..16]);
+    }
+
+    #[test]
+    fn flushed_history_is_cleared() {
+        let mut decompressor = Decompressor::new();
+
+        decompressor
+            .decompress(
+                &BELLS_MPPC_64K,
+                CompressionFlags::COMPRESSED | CompressionFlags::AT_FRONT,
+                CompressionType::K64,
+            )
+            .unwrap();
+
+        let compressed = [0b1111_1110, 0b0011_1100, 0b0000_0000];
+        let output = decompressor
+            .decompress(
+                &compressed,
+                CompressionFlags::COMPRESSED | CompressionFlags::FLUSHED,
+                CompressionType::K64,
+            )
+            .unwrap();
+        assert_eq!(output, [0; 16]);
+    }
+
+    const COMPRESSION_TYPES: [CompressionType; 4] = [
+        CompressionType::K8,
+        CompressionType::K64,
+        CompressionType::Rdp6,
+        CompressionType::Rdp61,
+    ];
+
+    /// Generates payloads of various sizes, mixing noise, runs, text and data from the previous payload.
+    fn generate_payloads(count: usize) -> Vec<Vec<u8>> {
+        let mut state = 0x1234_5678u32;
+        let mut next = move || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (state >> 16) as usize
+        };
+
+        let mut payloads: Vec<Vec<u8>> = Vec::with_capacity(count);
+
+        for i in 0..count {
+            let size = 100 + i * 997 % 16_000;
+            let mut payload = Vec::with_capacity(size);
+
+            while payload.len() < size {
+                match next() % 4 {
+                    0 => payload.extend((0..next() % 64).map(|_| next() as u8)),
+                    1 => payload.extend_from_slice(BELLS),
+                    2 => payload.resize(payload.len() + next() % 1024, next() as u8),
+                    _ => {
+                        if let Some(previous) = payloads.last() {
+                            let start = next() % previous.len();
+                            let end = (start + next() % 2048).min(previous.len());
+                            payload.extend_from_slice(&previous[start..end]);
+                        }
+                    }
+                }
+            }
+
+            payload.truncate(size);
+            payloads.push(payload);
+        }
+
+        payloads
+    }
+
+    #[test]
+    fn compressed_payloads_round_trip() {
+        // Enough data to wrap around the history buffer of every compression type.
+        let payloads = generate_payloads(300);
+
+        for compression_type in COMPRESSION_TYPES {
+            let mut compressor = Compressor::new(compression_type);
+            let mut decompressor = Decompressor::new();
+
+            for payload in &payloads {
+                let mut compressed = Vec::new();
+                let flags = compressor.compress(payload, &mut compressed);
+
+                let output = decompressor.decompress(&compressed, flags, compression_type).unwrap();
+                assert_eq!(output, payload.as_slice(), "{compression_type:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn repeated_data_is_compressed() {
+        let input = BELLS.repeat(20);
+
+        for compression_type in COMPRESSION_TYPES {
+            let mut compressor = Compressor::new(compression_type);
+
+            let mut compressed = Vec::new();
+            let flags = compressor.compress(&input, &mut compressed);
+            assert!(flags.contains(CompressionFlags::COMPRESSED | CompressionFlags::FLUSHED));
+            assert!(compressed.len() < BELLS.len() * 2, "{compression_type:?}");
+
+            let mut decompressor = Decompressor::new();
+            let output = decompressor.decompress(&compressed, flags, compression_type).unwrap();
+            assert_eq!(output, input);
+        }
+    }
+
+    #[test]
+    fn incompressible_data_is_sent_as_is() {
+        let mut compressor = Compressor::new(CompressionType::Rdp6);
+
+        let mut output = Vec::new();
+        assert_eq!(compressor.compress(BELLS, &mut output), CompressionFlags::empty());
+        assert_eq!(output, BELLS);
+
+        let input: Vec<u8> = (0..=255).collect();
+        let mut output = Vec::new();
+        assert_eq!(compressor.compress(&input, &mut output), CompressionFlags::FLUSHED);
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn truncated_ncrush_data_is_rejected() {
+        let mut decompressor = Decompressor::new();
+
+        let result = decompressor.decompress(&BELLS_NCRUSH[..20], CompressionFlags::COMPRESSED, CompressionType::Rdp6);
+        assert!(result.is_err());
+    }
+}
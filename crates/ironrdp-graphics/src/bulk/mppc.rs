@@ -0,0 +1,329 @@
+//! MPPC compression and decompression ([RFC 2118], [MS-RDPBCGR] 3.1.8.4)
+//!
+//! [RFC 2118]: https://www.rfc-editor.org/rfc/rfc2118
+//! [MS-RDPBCGR]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/
+
+use core::mem;
+
+use ironrdp_pdu::rdp::headers::CompressionFlags;
+
+use super::{BulkError, MatchFinder, MPPC_64K_HISTORY_SIZE};
+
+/// Space left unused at the end of the history buffer, as done by the reference implementations.
+const HISTORY_END_MARGIN: usize = 3;
+
+pub(super) struct Mppc {
+    history: Box<[u8]>,
+    history_size: usize,
+    history_offset: usize,
+}
+
+impl Mppc {
+    pub(super) fn new() -> Self {
+        Self {
+            history: vec![0; MPPC_64K_HISTORY_SIZE].into_boxed_slice(),
+            history_size: MPPC_64K_HISTORY_SIZE,
+            history_offset: 0,
+        }
+    }
+
+    pub(super) fn decompress<'a>(
+        &'a mut self,
+        input: &'a [u8],
+        flags: CompressionFlags,
+        history_size: usize,
+    ) -> Result<&'a [u8], BulkError> {
+        if history_size != self.history_size {
+            // The server is not expected to switch between RDP 4.0 and RDP 5.0 without flushing.
+            self.history_size = history_size;
+            self.history.fill(0);
+            self.history_offset = 0;
+        }
+
+        if flags.contains(CompressionFlags::AT_FRONT) {
+            self.history_offset = 0;
+        }
+
+        if flags.contains(CompressionFlags::FLUSHED) {
+            self.history.fill(0);
+            self.history_offset = 0;
+        }
+
+        if !flags.contains(CompressionFlags::COMPRESSED) {
+            return Ok(input);
+        }
+
+        let is_rdp5 = self.history_size == MPPC_64K_HISTORY_SIZE;
+        let history_mask = self.history_size - 1;
+        let start = self.history_offset;
+        let mut offset = self.history_offset;
+        let mut bits = BitReader::new(input);
+
+        // The last byte is padded with zeroes, which cannot encode anything else than a literal.
+        while bits.remaining() >= 8 {
+            let value = bits.peek();
+
+            // Literals are encoded as 0 + 7 bits (< 0x80) or as 10 + 7 bits (>= 0x80).
+            let literal = match value.leading_ones() {
+                0 => Some((value >> 24) as u8),
+                1 => Some(0x80 | ((value >> 23) & 0x7F) as u8),
+                _ => None,
+            };
+
+            if let Some(literal) = literal {
+                if offset >= self.history_size {
+                    return Err(BulkError::HistoryBufferOverflow);
+                }
+
+                self.history[offset] = literal;
+                offset += 1;
+                bits.consume(if literal < 0x80 { 8 } else { 9 })?;
+
+                continue;
+            }
+
+            let (copy_offset, prefix_size, value_size) = if is_rdp5 {
+                match value.leading_ones() {
+                    5.. => (0, 5, 6),
+                    4 => (64, 5, 8),
+                    3 => (320, 4, 11),
+                    _ => (2368, 3, 16),
+                }
+            } else {
+                match value.leading_ones() {
+                    4.. => (0, 4, 6),
+                    3 => (64, 4, 8),
+                    _ => (320, 3, 13),
+                }
+            };
+            let copy_offset = copy_offset + bits_at(value, prefix_size, value_size);
+            bits.consume(prefix_size + value_size)?;
+
+            // LengthOfMatch is encoded as 0 for 3, and as n 1-bits, a 0-bit and n + 1 bits
+            // for the [2^(n + 1), 2^(n + 2)) range.
+            let value = bits.peek();
+            let length_of_match = match value.leading_ones() {
+                0 => {
+                    bits.consume(1)?;
+                    3
+                }
+                ones if ones <= if is_rdp5 { 14 } else { 11 } => {
+                    let value_size = ones + 1;
+                    bits.consume(ones + 1 + value_size)?;
+                    (1 << value_size) + bits_at(value, ones + 1, value_size)
+                }
+                _ => return Err(BulkError::InvalidCode("LengthOfMatch")),
+            };
+
+            if offset + length_of_match > self.history_size {
+                return Err(BulkError::HistoryBufferOverflow);
+            }
+
+            let mut source = offset.wrapping_sub(copy_offset) & history_mask;
+            for _ in 0..length_of_match {
+                self.history[offset] = self.history[source];
+                offset += 1;
+                source = (source + 1) & history_mask;
+            }
+        }
+
+        self.history_offset = offset;
+
+        Ok(&self.history[start..offset])
+    }
+}
+
+pub(super) struct MppcCompressor {
+    history: Box<[u8]>,
+    history_offset: usize,
+    match_finder: MatchFinder,
+    flush_pending: bool,
+}
+
+impl MppcCompressor {
+    pub(super) fn new(history_size: usize) -> Self {
+        Self {
+            history: vec![0; history_size].into_boxed_slice(),
+            history_offset: 0,
+            match_finder: MatchFinder::new(),
+            flush_pending: true,
+        }
+    }
+
+    pub(super) fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> CompressionFlags {
+        let history_size = self.history.len();
+
+        if input.len() > history_size - HISTORY_END_MARGIN {
+            output.extend_from_slice(input);
+            return CompressionFlags::empty();
+        }
+
+        let mut flags = CompressionFlags::COMPRESSED;
+
+        if mem::take(&mut self.flush_pending) {
+            flags |= CompressionFlags::FLUSHED;
+            self.reset();
+        }
+
+        if self.history_offset + input.len() > history_size - HISTORY_END_MARGIN {
+            flags |= CompressionFlags::AT_FRONT;
+            self.reset();
+        }
+
+        let start = self.history_offset;
+        let end = start + input.len();
+        self.history[start..end].copy_from_slice(input);
+
+        let is_rdp5 = history_size == MPPC_64K_HISTORY_SIZE;
+        let max_length = if is_rdp5 { 0xFFFF } else { 0x1FFF };
+        let output_start = output.len();
+        let mut bits = BitWriter::new(output);
+        let mut position = start;
+
+        while position < end {
+            let data = &self.history[..end];
+
+            let Some((copy_offset, length_of_match)) = self.match_finder.find(data, position, max_length) else {
+                let literal = u32::from(data[position]);
+                if literal < 0x80 {
+                    bits.write(literal, 8);
+                } else {
+                    bits.write((0b10 << 7) | (literal & 0x7F), 9);
+                }
+
+                position += 1;
+                continue;
+            };
+
+            let copy_offset = copy_offset as u32;
+            if is_rdp5 {
+                match copy_offset {
+                    0..=63 => bits.write((0b11111 << 6) | copy_offset, 11),
+                    64..=319 => bits.write((0b11110 << 8) | (copy_offset - 64), 13),
+                    320..=2367 => bits.write((0b1110 << 11) | (copy_offset - 320), 15),
+                    _ => bits.write((0b110 << 16) | (copy_offset - 2368), 19),
+                }
+            } else {
+                match copy_offset {
+                    0..=63 => bits.write((0b1111 << 6) | copy_offset, 10),
+                    64..=319 => bits.write((0b1110 << 8) | (copy_offset - 64), 12),
+                    _ => bits.write((0b110 << 13) | (copy_offset - 320), 16),
+                }
+            }
+
+            if length_of_match == 3 {
+                bits.write(0, 1);
+            } else {
+                let value_size = length_of_match.ilog2();
+                let ones = value_size - 1;
+                bits.write(((1 << ones) - 1) << 1, ones + 1);
+                bits.write(length_of_match as u32 - (1 << value_size), value_size);
+            }
+
+            for skipped in position + 1..position + length_of_match {
+                self.match_finder.insert(data, skipped);
+            }
+            position += length_of_match;
+        }
+
+        bits.flush();
+
+        if output.len() - output_start >= input.len() {
+            output.truncate(output_start);
+            output.extend_from_slice(input);
+            self.reset();
+
+            return CompressionFlags::FLUSHED;
+        }
+
+        self.history_offset = end;
+
+        flags
+    }
+
+    fn reset(&mut self) {
+        self.history_offset = 0;
+        self.match_finder.clear();
+    }
+}
+
+/// Extracts `size` bits located after the first `skip` bits of `value`.
+fn bits_at(value: u32, skip: u32, size: u32) -> usize {
+    ((value << skip) >> (32 - size)) as usize
+}
+
+/// Most significant bit first reader.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() * 8 - self.position
+    }
+
+    /// Returns the next 32 bits, padded with zeroes past the end of the data.
+    fn peek(&self) -> u32 {
+        let first_byte = self.position / 8;
+
+        let window = (0..5).fold(0u64, |window, i| {
+            (window << 8) | u64::from(self.data.get(first_byte + i).copied().unwrap_or(0))
+        });
+
+        ((window << (self.position % 8)) >> 8) as u32
+    }
+
+    fn consume(&mut self, count: u32) -> Result<(), BulkError> {
+        let count = count as usize;
+
+        if count > self.remaining() {
+            return Err(BulkError::UnexpectedEndOfData);
+        }
+
+        self.position += count;
+
+        Ok(())
+    }
+}
+
+/// Most significant bit first writer.
+struct BitWriter<'a> {
+    output: &'a mut Vec<u8>,
+    accumulator: u64,
+    count: u32,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(output: &'a mut Vec<u8>) -> Self {
+        Self {
+            output,
+            accumulator: 0,
+            count: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, count: u32) {
+        self.accumulator = (self.accumulator << count) | u64::from(value);
+        self.count += count;
+
+        while self.count >= 8 {
+            self.count -= 8;
+            self.output.push((self.accumulator >> self.count) as u8);
+        }
+
+        self.accumulator &= (1 << self.count) - 1;
+    }
+
+    /// Writes the remaining bits, padding the last byte with zeroes.
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.output.push((self.accumulator << (8 - self.count)) as u8);
+            self.count = 0;
+        }
+    }
+}
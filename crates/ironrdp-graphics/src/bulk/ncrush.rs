@@ -0,0 +1,445 @@
+//! NCRUSH compression and decompression ([MS-RDPEGDI] 3.1.8.1)
+//!
+//! [MS-RDPEGDI]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegdi/
+
+use core::mem;
+use std::sync::OnceLock;
+
+use ironrdp_pdu::rdp::headers::CompressionFlags;
+
+use super::{BulkError, MatchFinder};
+
+const HISTORY_SIZE: usize = 64 * 1024;
+const AT_FRONT_SIZE: usize = 32 * 1024;
+/// Space left unused at the end of the history buffer, as done by the reference implementations.
+const HISTORY_END_MARGIN: usize = 7;
+
+/// Longest match encodable with the first 28 LoM codes (the last two are never emitted).
+const MAX_LENGTH_OF_MATCH: usize = 0x301;
+const MAX_LENGTH_OF_MATCH_INDEX: usize = 28;
+
+const LEC_LOOKUP_BITS: u32 = 13;
+const LOM_LOOKUP_BITS: u32 = 9;
+
+const END_OF_STREAM: u16 = 256;
+const FIRST_COPY_OFFSET: u16 = 257;
+const LAST_COPY_OFFSET: u16 = 288;
+const FIRST_OFFSET_CACHE_HIT: u16 = 289;
+
+/// Huffman decoding tables, indexed by the next bits of the stream.
+///
+/// Each entry holds the symbol in the lower 12 bits and the code length in the upper 4 bits.
+fn lec_lookup() -> &'static [u16] {
+    static LOOKUP: OnceLock<Box<[u16]>> = OnceLock::new();
+    LOOKUP.get_or_init(|| build_lookup_table(&HUFF_CODE_LEC, &HUFF_LENGTH_LEC, LEC_LOOKUP_BITS))
+}
+
+fn lom_lookup() -> &'static [u16] {
+    static LOOKUP: OnceLock<Box<[u16]>> = OnceLock::new();
+    LOOKUP.get_or_init(|| build_lookup_table(&HUFF_CODE_LOM, &HUFF_LENGTH_LOM, LOM_LOOKUP_BITS))
+}
+
+pub(super) struct NCrush {
+    history: Box<[u8]>,
+    history_offset: usize,
+    offset_cache: [usize; 4],
+}
+
+impl NCrush {
+    pub(super) fn new() -> Self {
+        Self {
+            history: vec![0; HISTORY_SIZE].into_boxed_slice(),
+            history_offset: 0,
+            offset_cache: [0; 4],
+        }
+    }
+
+    pub(super) fn decompress<'a>(
+        &'a mut self,
+        input: &'a [u8],
+        flags: CompressionFlags,
+    ) -> Result<&'a [u8], BulkError> {
+        if flags.contains(CompressionFlags::AT_FRONT) {
+            // The last 32K of the history are moved to the front of the buffer.
+            if self.history_offset <= AT_FRONT_SIZE {
+                return Err(BulkError::HistoryBufferOverflow);
+            }
+
+            self.history
+                .copy_within(self.history_offset - AT_FRONT_SIZE..self.history_offset, 0);
+            self.history[AT_FRONT_SIZE..].fill(0);
+            self.history_offset = AT_FRONT_SIZE;
+        }
+
+        if flags.contains(CompressionFlags::FLUSHED) {
+            self.history.fill(0);
+            self.history_offset = 0;
+            self.offset_cache = [0; 4];
+        }
+
+        if !flags.contains(CompressionFlags::COMPRESSED) {
+            return Ok(input);
+        }
+
+        let start = self.history_offset;
+        let mut offset = self.history_offset;
+        let mut bits = BitReader::new(input);
+
+        loop {
+            let symbol = bits.read_symbol(lec_lookup(), LEC_LOOKUP_BITS)?;
+
+            let copy_offset = match symbol {
+                0..=255 => {
+                    if offset >= HISTORY_SIZE {
+                        return Err(BulkError::HistoryBufferOverflow);
+                    }
+
+                    self.history[offset] = symbol as u8;
+                    offset += 1;
+
+                    continue;
+                }
+                END_OF_STREAM => break,
+                FIRST_COPY_OFFSET..=LAST_COPY_OFFSET => {
+                    let index = usize::from(symbol - FIRST_COPY_OFFSET);
+                    let copy_offset = COPY_OFFSET_BASE[index] + bits.read(COPY_OFFSET_BITS[index])? - 1;
+
+                    self.offset_cache.copy_within(0..3, 1);
+                    self.offset_cache[0] = copy_offset;
+
+                    copy_offset
+                }
+                _ => {
+                    let index = usize::from(symbol - FIRST_OFFSET_CACHE_HIT);
+                    if index >= self.offset_cache.len() {
+                        return Err(BulkError::InvalidCode("LEC"));
+                    }
+
+                    self.offset_cache.swap(0, index);
+
+                    self.offset_cache[0]
+                }
+            };
+
+            let length_index = usize::from(bits.read_symbol(lom_lookup(), LOM_LOOKUP_BITS)?);
+            if length_index >= LOM_BASE.len() {
+                return Err(BulkError::InvalidCode("LOM"));
+            }
+            let length_of_match = LOM_BASE[length_index] + bits.read(LOM_BITS[length_index])?;
+
+            if copy_offset == 0 {
+                return Err(BulkError::InvalidCopyOffset(copy_offset));
+            }
+
+            if offset + length_of_match > HISTORY_SIZE {
+                return Err(BulkError::HistoryBufferOverflow);
+            }
+
+            // Copy byte by byte, as the source and the destination may overlap.
+            let mut source = offset.wrapping_sub(copy_offset) % HISTORY_SIZE;
+            for _ in 0..length_of_match {
+                self.history[offset] = self.history[source];
+                offset += 1;
+                source = (source + 1) % HISTORY_SIZE;
+            }
+        }
+
+        self.history_offset = offset;
+
+        Ok(&self.history[start..offset])
+    }
+}
+
+pub(super) struct NCrushCompressor {
+    history: Box<[u8]>,
+    history_offset: usize,
+    match_finder: MatchFinder,
+    flush_pending: bool,
+}
+
+impl NCrushCompressor {
+    pub(super) fn new() -> Self {
+        Self {
+            history: vec![0; HISTORY_SIZE].into_boxed_slice(),
+            history_offset: 0,
+            match_finder: MatchFinder::new(),
+            flush_pending: true,
+        }
+    }
+
+    pub(super) fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> CompressionFlags {
+        if input.len() > HISTORY_SIZE - AT_FRONT_SIZE - HISTORY_END_MARGIN {
+            output.extend_from_slice(input);
+            return CompressionFlags::empty();
+        }
+
+        let mut flags = CompressionFlags::COMPRESSED;
+
+        if mem::take(&mut self.flush_pending) {
+            flags |= CompressionFlags::FLUSHED;
+            self.reset();
+        }
+
+        if self.history_offset + input.len() > HISTORY_SIZE - HISTORY_END_MARGIN {
+            // Same as the decompressor: the last 32K of the history are moved to the front.
+            flags |= CompressionFlags::AT_FRONT;
+
+            self.history
+                .copy_within(self.history_offset - AT_FRONT_SIZE..self.history_offset, 0);
+            self.history_offset = AT_FRONT_SIZE;
+
+            self.match_finder.clear();
+            for position in 0..AT_FRONT_SIZE {
+                self.match_finder.insert(&self.history[..AT_FRONT_SIZE], position);
+            }
+        }
+
+        let start = self.history_offset;
+        let end = start + input.len();
+        self.history[start..end].copy_from_slice(input);
+
+        let output_start = output.len();
+        let mut bits = BitWriter::new(output);
+        let mut position = start;
+
+        while position < end {
+            let data = &self.history[..end];
+
+            let Some((copy_offset, length_of_match)) = self.match_finder.find(data, position, MAX_LENGTH_OF_MATCH)
+            else {
+                bits.write_symbol(usize::from(data[position]), &HUFF_CODE_LEC, &HUFF_LENGTH_LEC);
+
+                position += 1;
+                continue;
+            };
+
+            // CopyOffset values are encoded with a bias of one.
+            let index = COPY_OFFSET_BASE
+                .iter()
+                .rposition(|&base| base <= copy_offset + 1)
+                .expect("the first base is one");
+            bits.write_symbol(usize::from(FIRST_COPY_OFFSET) + index, &HUFF_CODE_LEC, &HUFF_LENGTH_LEC);
+            bits.write(
+                (copy_offset + 1 - COPY_OFFSET_BASE[index]) as u32,
+                COPY_OFFSET_BITS[index],
+            );
+
+            let index = LOM_BASE[..MAX_LENGTH_OF_MATCH_INDEX]
+                .iter()
+                .rposition(|&base| base <= length_of_match)
+                .expect("matches are at least two bytes long");
+            bits.write_symbol(index, &HUFF_CODE_LOM, &HUFF_LENGTH_LOM);
+            bits.write((length_of_match - LOM_BASE[index]) as u32, LOM_BITS[index]);
+
+            for skipped in position + 1..position + length_of_match {
+                self.match_finder.insert(data, skipped);
+            }
+            position += length_of_match;
+        }
+
+        bits.write_symbol(usize::from(END_OF_STREAM), &HUFF_CODE_LEC, &HUFF_LENGTH_LEC);
+        bits.flush();
+
+        if output.len() - output_start >= input.len() {
+            output.truncate(output_start);
+            output.extend_from_slice(input);
+            self.reset();
+
+            return CompressionFlags::FLUSHED;
+        }
+
+        self.history_offset = end;
+
+        flags
+    }
+
+    fn reset(&mut self) {
+        self.history_offset = 0;
+        self.match_finder.clear();
+    }
+}
+
+fn build_lookup_table(codes: &[u16], lengths: &[u8], lookup_bits: u32) -> Box<[u16]> {
+    let mut table = vec![0; 1 << lookup_bits].into_boxed_slice();
+
+    // Codes are stored least significant bit first, so every entry whose lower bits match a
+    // code maps to its symbol.
+    for (symbol, (&code, &length)) in codes.iter().zip(lengths).enumerate() {
+        for suffix in 0..1 << (lookup_bits - u32::from(length)) {
+            table[usize::from(code) | (suffix << length)] = (u16::from(length) << 12) | symbol as u16;
+        }
+    }
+
+    table
+}
+
+/// Least significant bit first reader.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    /// Returns the next 32 bits, padded with zeroes past the end of the data.
+    fn peek(&self) -> u32 {
+        let first_byte = self.position / 8;
+
+        let window = (0..5).rev().fold(0u64, |window, i| {
+            (window << 8) | u64::from(self.data.get(first_byte + i).copied().unwrap_or(0))
+        });
+
+        (window >> (self.position % 8)) as u32
+    }
+
+    fn consume(&mut self, count: usize) -> Result<(), BulkError> {
+        if self.position + count > self.data.len() * 8 {
+            return Err(BulkError::UnexpectedEndOfData);
+        }
+
+        self.position += count;
+
+        Ok(())
+    }
+
+    fn read(&mut self, count: u32) -> Result<usize, BulkError> {
+        let value = (u64::from(self.peek()) & ((1 << count) - 1)) as usize;
+        self.consume(count as usize)?;
+
+        Ok(value)
+    }
+
+    fn read_symbol(&mut self, lookup_table: &[u16], lookup_bits: u32) -> Result<u16, BulkError> {
+        let entry = lookup_table[(self.peek() & ((1 << lookup_bits) - 1)) as usize];
+        self.consume(usize::from(entry >> 12))?;
+
+        Ok(entry & 0xFFF)
+    }
+}
+
+/// Least significant bit first writer.
+struct BitWriter<'a> {
+    output: &'a mut Vec<u8>,
+    accumulator: u64,
+    count: u32,
+}
+
+impl<'a> BitWriter<'a> {
+    fn new(output: &'a mut Vec<u8>) -> Self {
+        Self {
+            output,
+            accumulator: 0,
+            count: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, count: u32) {
+        self.accumulator |= u64::from(value) << self.count;
+        self.count += count;
+
+        while self.count >= 8 {
+            self.output.push(self.accumulator as u8);
+            self.accumulator >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn write_symbol(&mut self, symbol: usize, codes: &[u16], lengths: &[u8]) {
+        self.write(u32::from(codes[symbol]), u32::from(lengths[symbol]));
+    }
+
+    /// Writes the remaining bits, padding the last byte with zeroes.
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.output.push(self.accumulator as u8);
+            self.accumulator = 0;
+            self.count = 0;
+        }
+    }
+}
+
+#[rustfmt::skip]
+const HUFF_LENGTH_LEC: [u8; 294] = [
+    6, 6, 6, 7, 7, 7, 7, 7, 7, 7, 7, 8, 8, 8, 8, 8, 8, 8, 9, 8, 9, 9, 9, 9,
+    8, 8, 9, 9, 9, 9, 9, 9, 8, 9, 9, 10, 9, 9, 9, 9, 9, 9, 9, 10, 9, 10, 10, 10,
+    9, 9, 10, 9, 10, 9, 10, 9, 9, 9, 10, 10, 9, 10, 9, 9, 8, 9, 9, 9, 9, 10, 10, 10,
+    9, 9, 10, 10, 10, 10, 10, 10, 9, 9, 10, 10, 10, 10, 10, 10, 10, 9, 10, 10, 10, 10, 10, 10,
+    8, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 9, 10, 10, 10, 10, 10, 10, 10,
+    9, 10, 10, 10, 10, 10, 10, 9, 7, 9, 9, 10, 9, 10, 10, 10, 9, 10, 10, 10, 10, 10, 10, 10,
+    9, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10,
+    10, 10, 10, 13, 10, 10, 10, 10, 10, 10, 11, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10,
+    9, 10, 10, 10, 10, 10, 9, 10, 10, 10, 10, 10, 9, 10, 10, 10, 9, 10, 10, 10, 10, 10, 10, 10,
+    10, 10, 10, 10, 10, 10, 10, 10, 9, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 10, 9, 10,
+    8, 9, 9, 10, 9, 10, 10, 10, 9, 10, 10, 10, 9, 9, 8, 7, 13, 13, 7, 7, 10, 7, 7, 6,
+    6, 6, 6, 5, 6, 6, 6, 5, 6, 5, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6, 6,
+    8, 5, 6, 7, 7, 13,];
+
+#[rustfmt::skip]
+const HUFF_CODE_LEC: [u16; 294] = [
+    0x0004, 0x0024, 0x0014, 0x0011, 0x0051, 0x0031, 0x0071, 0x0009, 0x0049, 0x0029, 0x0069, 0x0015,
+    0x0095, 0x0055, 0x00D5, 0x0035, 0x00B5, 0x0075, 0x001D, 0x00F5, 0x011D, 0x009D, 0x019D, 0x005D,
+    0x000D, 0x008D, 0x015D, 0x00DD, 0x01DD, 0x003D, 0x013D, 0x00BD, 0x004D, 0x01BD, 0x007D, 0x006B,
+    0x017D, 0x00FD, 0x01FD, 0x0003, 0x0103, 0x0083, 0x0183, 0x026B, 0x0043, 0x016B, 0x036B, 0x00EB,
+    0x0143, 0x00C3, 0x02EB, 0x01C3, 0x01EB, 0x0023, 0x03EB, 0x0123, 0x00A3, 0x01A3, 0x001B, 0x021B,
+    0x0063, 0x011B, 0x0163, 0x00E3, 0x00CD, 0x01E3, 0x0013, 0x0113, 0x0093, 0x031B, 0x009B, 0x029B,
+    0x0193, 0x0053, 0x019B, 0x039B, 0x005B, 0x025B, 0x015B, 0x035B, 0x0153, 0x00D3, 0x00DB, 0x02DB,
+    0x01DB, 0x03DB, 0x003B, 0x023B, 0x013B, 0x01D3, 0x033B, 0x00BB, 0x02BB, 0x01BB, 0x03BB, 0x007B,
+    0x002D, 0x027B, 0x017B, 0x037B, 0x00FB, 0x02FB, 0x01FB, 0x03FB, 0x0007, 0x0207, 0x0107, 0x0307,
+    0x0087, 0x0287, 0x0187, 0x0387, 0x0033, 0x0047, 0x0247, 0x0147, 0x0347, 0x00C7, 0x02C7, 0x01C7,
+    0x0133, 0x03C7, 0x0027, 0x0227, 0x0127, 0x0327, 0x00A7, 0x00B3, 0x0019, 0x01B3, 0x0073, 0x02A7,
+    0x0173, 0x01A7, 0x03A7, 0x0067, 0x00F3, 0x0267, 0x0167, 0x0367, 0x00E7, 0x02E7, 0x01E7, 0x03E7,
+    0x01F3, 0x0017, 0x0217, 0x0117, 0x0317, 0x0097, 0x0297, 0x0197, 0x0397, 0x0057, 0x0257, 0x0157,
+    0x0357, 0x00D7, 0x02D7, 0x01D7, 0x03D7, 0x0037, 0x0237, 0x0137, 0x0337, 0x00B7, 0x02B7, 0x01B7,
+    0x03B7, 0x0077, 0x0277, 0x07FF, 0x0177, 0x0377, 0x00F7, 0x02F7, 0x01F7, 0x03F7, 0x03FF, 0x000F,
+    0x020F, 0x010F, 0x030F, 0x008F, 0x028F, 0x018F, 0x038F, 0x004F, 0x024F, 0x014F, 0x034F, 0x00CF,
+    0x000B, 0x02CF, 0x01CF, 0x03CF, 0x002F, 0x022F, 0x010B, 0x012F, 0x032F, 0x00AF, 0x02AF, 0x01AF,
+    0x008B, 0x03AF, 0x006F, 0x026F, 0x018B, 0x016F, 0x036F, 0x00EF, 0x02EF, 0x01EF, 0x03EF, 0x001F,
+    0x021F, 0x011F, 0x031F, 0x009F, 0x029F, 0x019F, 0x039F, 0x005F, 0x004B, 0x025F, 0x015F, 0x035F,
+    0x00DF, 0x02DF, 0x01DF, 0x03DF, 0x003F, 0x023F, 0x013F, 0x033F, 0x00BF, 0x02BF, 0x014B, 0x01BF,
+    0x00AD, 0x00CB, 0x01CB, 0x03BF, 0x002B, 0x007F, 0x027F, 0x017F, 0x012B, 0x037F, 0x00FF, 0x02FF,
+    0x00AB, 0x01AB, 0x006D, 0x0059, 0x17FF, 0x0FFF, 0x0039, 0x0079, 0x01FF, 0x0005, 0x0045, 0x0034,
+    0x000C, 0x002C, 0x001C, 0x0000, 0x003C, 0x0002, 0x0022, 0x0010, 0x0012, 0x0008, 0x0032, 0x000A,
+    0x002A, 0x001A, 0x003A, 0x0006, 0x0026, 0x0016, 0x0036, 0x000E, 0x002E, 0x001E, 0x003E, 0x0001,
+    0x00ED, 0x0018, 0x0021, 0x0025, 0x0065, 0x1FFF,];
+
+#[rustfmt::skip]
+const HUFF_LENGTH_LOM: [u8; 32] = [
+    4, 2, 3, 4, 3, 4, 4, 5, 4, 5, 5, 6, 6, 7, 7, 8, 7, 8, 8, 9, 9, 8, 9, 9,
+    9, 9, 9, 9, 9, 9, 9, 9,
+];
+
+#[rustfmt::skip]
+const HUFF_CODE_LOM: [u16; 32] = [
+    0x0001, 0x0000, 0x0002, 0x0009, 0x0006, 0x0005, 0x000D, 0x000B, 0x0003, 0x001B, 0x0007, 0x0017,
+    0x0037, 0x000F, 0x004F, 0x006F, 0x002F, 0x00EF, 0x001F, 0x005F, 0x015F, 0x009F, 0x00DF, 0x01DF,
+    0x003F, 0x013F, 0x00BF, 0x01BF, 0x007F, 0x017F, 0x00FF, 0x01FF,
+];
+
+#[rustfmt::skip]
+const COPY_OFFSET_BITS: [u32; 32] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10,
+    11, 11, 12, 12, 13, 13, 14, 14,
+];
+
+#[rustfmt::skip]
+const COPY_OFFSET_BASE: [usize; 32] = [
+    0x1, 0x2, 0x3, 0x4, 0x5, 0x7, 0x9, 0xD, 0x11, 0x19, 0x21, 0x31,
+    0x41, 0x61, 0x81, 0xC1, 0x101, 0x181, 0x201, 0x301, 0x401, 0x601, 0x801, 0xC01,
+    0x1001, 0x1801, 0x2001, 0x3001, 0x4001, 0x6001, 0x8001, 0xC001,
+];
+
+#[rustfmt::skip]
+const LOM_BITS: [u32; 30] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4,
+    6, 6, 8, 8, 14, 14,
+];
+
+#[rustfmt::skip]
+const LOM_BASE: [usize; 30] = [
+    0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0x8, 0x9, 0xA, 0xC, 0xE, 0x10,
+    0x12, 0x16, 0x1A, 0x1E, 0x22, 0x2A, 0x32, 0x3A, 0x42, 0x52, 0x62, 0x72,
+    0x82, 0xC2, 0x102, 0x202, 0x2, 0x2,
+];
@@ -0,0 +1,197 @@
+//! XCRUSH compression and decompression ([MS-RDPEGDI] 3.1.8.2)
+//!
+//! XCRUSH is a two-level compression: the level-1 compressor replaces chunks found in a large
+//! history buffer with match references, and its output is then compressed with MPPC 64K.
+//!
+//! The compressor does not look for level-1 matches, and only relies on the level-2 MPPC
+//! compression. Its output is still valid XCRUSH data, understood by any RDP 6.1 peer.
+//!
+//! [MS-RDPEGDI]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegdi/
+
+use core::mem;
+
+use bitflags::bitflags;
+use ironrdp_core::ReadCursor;
+use ironrdp_pdu::rdp::headers::CompressionFlags;
+
+use super::mppc::{Mppc, MppcCompressor};
+use super::{BulkError, MPPC_64K_HISTORY_SIZE};
+
+const HISTORY_SIZE: usize = 2_000_000;
+
+/// RDP61_MATCH_DETAILS
+const MATCH_DETAILS_SIZE: usize = 2 /* matchLength */ + 2 /* matchOutputOffset */ + 4 /* matchHistoryOffset */;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Level1CompressionFlags: u8 {
+        const COMPRESSED = 0x01;
+        const NO_COMPRESSION = 0x02;
+        const AT_FRONT = 0x04;
+        const INNER_COMPRESSION = 0x10;
+    }
+}
+
+pub(super) struct XCrush {
+    mppc: Mppc,
+    history: Box<[u8]>,
+    history_offset: usize,
+}
+
+impl XCrush {
+    pub(super) fn new() -> Self {
+        Self {
+            mppc: Mppc::new(),
+            history: vec![0; HISTORY_SIZE].into_boxed_slice(),
+            history_offset: 0,
+        }
+    }
+
+    pub(super) fn decompress<'a>(
+        &'a mut self,
+        input: &'a [u8],
+        flags: CompressionFlags,
+    ) -> Result<&'a [u8], BulkError> {
+        if flags.contains(CompressionFlags::FLUSHED) {
+            self.history.fill(0);
+            self.history_offset = 0;
+        }
+
+        if !flags.contains(CompressionFlags::COMPRESSED) {
+            return Ok(input);
+        }
+
+        // RDP_61_COMPRESSED_DATA
+        let [level_1_flags, level_2_flags, data @ ..] = input else {
+            return Err(BulkError::UnexpectedEndOfData);
+        };
+        let level_1_flags = Level1CompressionFlags::from_bits_truncate(*level_1_flags);
+        let level_2_flags = CompressionFlags::from_bits_truncate(*level_2_flags);
+
+        // The level-2 flags are only meaningful when the level-1 output was compressed again.
+        let data = if level_1_flags.contains(Level1CompressionFlags::INNER_COMPRESSION) {
+            self.mppc.decompress(data, level_2_flags, MPPC_64K_HISTORY_SIZE)?
+        } else {
+            data
+        };
+
+        if level_1_flags.contains(Level1CompressionFlags::AT_FRONT) {
+            self.history_offset = 0;
+        }
+
+        let start = self.history_offset;
+        let mut offset = self.history_offset;
+
+        let literals = if level_1_flags.contains(Level1CompressionFlags::NO_COMPRESSION) {
+            data
+        } else if level_1_flags.contains(Level1CompressionFlags::COMPRESSED) {
+            // RDP_61_COMPRESSED_DATA, MatchCount and MatchDetails fields
+            let mut src = ReadCursor::new(data);
+
+            if src.len() < 2 {
+                return Err(BulkError::UnexpectedEndOfData);
+            }
+            let match_count = usize::from(src.read_u16());
+
+            if src.len() < match_count * MATCH_DETAILS_SIZE {
+                return Err(BulkError::UnexpectedEndOfData);
+            }
+            let mut literals = ReadCursor::new(&data[2 + match_count * MATCH_DETAILS_SIZE..]);
+            let mut output_offset = 0;
+
+            for _ in 0..match_count {
+                let match_length = usize::from(src.read_u16());
+                let match_output_offset = usize::from(src.read_u16());
+                let match_history_offset = src.read_u32() as usize;
+
+                // Literals located before the match.
+                let literals_length = match_output_offset
+                    .checked_sub(output_offset)
+                    .ok_or(BulkError::InvalidMatchDetails)?;
+                if literals.len() < literals_length {
+                    return Err(BulkError::UnexpectedEndOfData);
+                }
+                offset = write_literals(&mut self.history, offset, literals.read_slice(literals_length))?;
+
+                if offset + match_length > HISTORY_SIZE || match_history_offset + match_length > HISTORY_SIZE {
+                    return Err(BulkError::HistoryBufferOverflow);
+                }
+
+                // Copy byte by byte, as the source and the destination may overlap.
+                for i in 0..match_length {
+                    self.history[offset + i] = self.history[match_history_offset + i];
+                }
+                offset += match_length;
+
+                output_offset = match_output_offset + match_length;
+            }
+
+            literals.remaining()
+        } else {
+            return Err(BulkError::InvalidLevel1Flags(level_1_flags.bits()));
+        };
+
+        offset = write_literals(&mut self.history, offset, literals)?;
+
+        self.history_offset = offset;
+
+        Ok(&self.history[start..offset])
+    }
+}
+
+pub(super) struct XCrushCompressor {
+    mppc: MppcCompressor,
+    history_offset: usize,
+    flush_pending: bool,
+}
+
+impl XCrushCompressor {
+    pub(super) fn new() -> Self {
+        Self {
+            mppc: MppcCompressor::new(MPPC_64K_HISTORY_SIZE),
+            history_offset: 0,
+            flush_pending: true,
+        }
+    }
+
+    pub(super) fn compress(&mut self, input: &[u8], output: &mut Vec<u8>) -> CompressionFlags {
+        let mut flags = CompressionFlags::COMPRESSED;
+
+        if mem::take(&mut self.flush_pending) {
+            flags |= CompressionFlags::FLUSHED;
+            self.history_offset = 0;
+        }
+
+        // The level-1 history of the decompressor is still filled with the level-1 literals.
+        let mut level_1_flags = Level1CompressionFlags::NO_COMPRESSION;
+        if self.history_offset + input.len() > HISTORY_SIZE {
+            level_1_flags |= Level1CompressionFlags::AT_FRONT;
+            self.history_offset = 0;
+        }
+        self.history_offset += input.len();
+
+        // RDP_61_COMPRESSED_DATA
+        let header_start = output.len();
+        output.extend_from_slice(&[0, 0]);
+
+        // The level-2 flags are sent even when the data is not compressed, as the MPPC history may be flushed.
+        let level_2_flags = self.mppc.compress(input, output);
+        level_1_flags |= Level1CompressionFlags::INNER_COMPRESSION;
+
+        output[header_start] = level_1_flags.bits();
+        output[header_start + 1] = level_2_flags.bits();
+
+        flags
+    }
+}
+
+fn write_literals(history: &mut [u8], offset: usize, literals: &[u8]) -> Result<usize, BulkError> {
+    let end = offset + literals.len();
+
+    history
+        .get_mut(offset..end)
+        .ok_or(BulkError::HistoryBufferOverflow)?
+        .copy_from_slice(literals);
+
+    Ok(end)
+}
@@ -0,0 +1,147 @@
+//! Bands layer ([MS-RDPEGFX] 2.2.4.1.1.2)
+//!
+//! [MS-RDPEGFX]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegfx/da5c75f9-cd99-450c-98c4-014a496942b0
+
+use ironrdp_core::ReadCursor;
+
+use super::{Bgr, Bitmap, ClearCodecError};
+
+const VBAR_CACHE_SIZE: usize = 32768;
+const SHORT_VBAR_CACHE_SIZE: usize = 16384;
+const MAX_BAND_HEIGHT: usize = 52;
+
+/// CLEARCODEC_BAND, without the vertical bars
+const BAND_HEADER_SIZE: usize = 2 /* xStart */ + 2 /* xEnd */ + 2 /* yStart */ + 2 /* yEnd */ + 3 /* blueBkg, greenBkg, redBkg */;
+
+/// Vertical bar caches, both filled in a circular fashion.
+///
+/// Short vertical bars only hold the pixels which differ from the band background, and full
+/// vertical bars are built from a short vertical bar and the background of its band.
+pub(super) struct VBarCache {
+    vbars: Box<[Option<Vec<Bgr>>]>,
+    vbar_cursor: usize,
+    short_vbars: Box<[Option<Vec<Bgr>>]>,
+    short_vbar_cursor: usize,
+}
+
+impl VBarCache {
+    pub(super) fn new() -> Self {
+        Self {
+            vbars: vec![None; VBAR_CACHE_SIZE].into_boxed_slice(),
+            vbar_cursor: 0,
+            short_vbars: vec![None; SHORT_VBAR_CACHE_SIZE].into_boxed_slice(),
+            short_vbar_cursor: 0,
+        }
+    }
+
+    /// Restarts filling the caches from the first entry. Cached entries are kept.
+    pub(super) fn reset_cursors(&mut self) {
+        self.vbar_cursor = 0;
+        self.short_vbar_cursor = 0;
+    }
+
+    pub(super) fn decode_bands(&mut self, data: &[u8], bitmap: &mut Bitmap) -> Result<(), ClearCodecError> {
+        let mut src = ReadCursor::new(data);
+
+        while !src.is_empty() {
+            if src.len() < BAND_HEADER_SIZE {
+                return Err(ClearCodecError::NotEnoughData);
+            }
+            let x_start = usize::from(src.read_u16());
+            let x_end = usize::from(src.read_u16());
+            let y_start = usize::from(src.read_u16());
+            let y_end = usize::from(src.read_u16());
+            let background: Bgr = src.read_array();
+
+            if x_end < x_start || y_end < y_start || y_end - y_start >= MAX_BAND_HEIGHT {
+                return Err(ClearCodecError::InvalidBand);
+            }
+            let band_height = y_end - y_start + 1;
+
+            for x in x_start..=x_end {
+                let vbar = self.read_vbar(&mut src, band_height, background)?;
+
+                // Bands may extend past the bitmap, the extra pixels are discarded.
+                if x >= bitmap.width {
+                    continue;
+                }
+
+                for (y, &color) in (y_start..bitmap.height).zip(vbar) {
+                    bitmap.set_pixel(x, y, color);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a vertical bar, using or filling the caches.
+    fn read_vbar(
+        &mut self,
+        src: &mut ReadCursor<'_>,
+        band_height: usize,
+        background: Bgr,
+    ) -> Result<&[Bgr], ClearCodecError> {
+        if src.len() < 2 {
+            return Err(ClearCodecError::NotEnoughData);
+        }
+        let header = src.read_u16();
+
+        let (short_vbar_index, y_on) = match header >> 14 {
+            // SHORT_VBAR_CACHE_MISS
+            0b00 => {
+                let y_on = usize::from(header & 0xFF);
+                let y_off = usize::from((header >> 8) & 0x3F);
+
+                let pixel_count = y_off.checked_sub(y_on).ok_or(ClearCodecError::InvalidVBar)?;
+                if src.len() < pixel_count * 3 {
+                    return Err(ClearCodecError::NotEnoughData);
+                }
+                let short_vbar = (0..pixel_count).map(|_| src.read_array()).collect();
+
+                let index = self.short_vbar_cursor;
+                self.short_vbars[index] = Some(short_vbar);
+                self.short_vbar_cursor = (index + 1) % SHORT_VBAR_CACHE_SIZE;
+
+                (index, y_on)
+            }
+            // SHORT_VBAR_CACHE_HIT
+            0b01 => {
+                if src.is_empty() {
+                    return Err(ClearCodecError::NotEnoughData);
+                }
+
+                (usize::from(header & 0x3FFF), usize::from(src.read_u8()))
+            }
+            // VBAR_CACHE_HIT
+            _ => {
+                let index = usize::from(header & 0x7FFF);
+                let vbar = self.vbars[index]
+                    .as_deref()
+                    .ok_or(ClearCodecError::MissingVBar(index))?;
+
+                if vbar.len() != band_height {
+                    return Err(ClearCodecError::InvalidVBar);
+                }
+
+                return Ok(vbar);
+            }
+        };
+
+        let short_vbar = self.short_vbars[short_vbar_index]
+            .as_deref()
+            .ok_or(ClearCodecError::MissingShortVBar(short_vbar_index))?;
+
+        if y_on + short_vbar.len() > band_height {
+            return Err(ClearCodecError::InvalidVBar);
+        }
+
+        let mut vbar = vec![background; band_height];
+        vbar[y_on..y_on + short_vbar.len()].copy_from_slice(short_vbar);
+
+        let index = self.vbar_cursor;
+        self.vbar_cursor = (index + 1) % VBAR_CACHE_SIZE;
+
+        Ok(self.vbars[index].insert(vbar))
+    }
+}
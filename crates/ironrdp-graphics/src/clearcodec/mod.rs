@@ -0,0 +1,714 @@
+//! ClearCodec decoding ([MS-RDPEGFX] 2.2.4.1)
+//!
+//! A ClearCodec bitmap is made of three layers drawn on top of each other:
+//!
+//! - the residual layer, a run-length encoded list of colors covering the whole bitmap,
+//! - the bands layer, made of vertical bars which are cached across bitmaps,
+//! - the subcodecs layer, regions encoded as raw pixels, with NSCodec or with RLEX.
+//!
+//! Small bitmaps can also be stored in a glyph cache, and drawn again later without any payload.
+//!
+//! [MS-RDPEGFX]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegfx/da5c75f9-cd99-450c-98c4-014a496942b0
+
+mod bands;
+mod subcodec;
+
+use core::mem;
+use std::io;
+
+use bitflags::bitflags;
+use ironrdp_core::ReadCursor;
+use ironrdp_pdu::geometry::{InclusiveRectangle, Rectangle as _};
+use thiserror::Error;
+
+use self::bands::VBarCache;
+use crate::image_processing::{ImageRegion, ImageRegionMut, PixelFormat};
+use crate::nscodec::NsCodecError;
+
+const GLYPH_CACHE_SIZE: usize = 4000;
+/// Bitmaps larger than this cannot be stored in the glyph cache.
+const MAX_GLYPH_PIXEL_COUNT: usize = 1024;
+
+/// CLEARCODEC_COMPOSITE_PAYLOAD, without the layers
+const COMPOSITE_HEADER_SIZE: usize = 4 /* residualByteCount */ + 4 /* bandsByteCount */ + 4 /* subcodecByteCount */;
+
+const BITMAP_PIXEL_FORMAT: PixelFormat = PixelFormat::BgrX32;
+const BYTES_PER_PIXEL: usize = BITMAP_PIXEL_FORMAT.bytes_per_pixel() as usize;
+
+/// Colors are always transmitted as blue, green and red bytes.
+type Bgr = [u8; 3];
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct GlyphFlags: u8 {
+        const GLYPH_INDEX = 0x01;
+        const GLYPH_HIT = 0x02;
+        const CACHE_RESET = 0x04;
+    }
+}
+
+/// Stateful ClearCodec decoder, keeping the glyph and vertical bar caches.
+///
+/// A single decoder must be used for all the ClearCodec bitmaps received on a graphics pipeline
+/// channel, since the caches are shared by all the surfaces.
+pub struct Decoder {
+    /// Sequence number expected for the next bitmap.
+    sequence_number: u8,
+    /// Glyphs, stored as BGRX pixels.
+    glyph_cache: Box<[Option<Vec<u8>>]>,
+    vbar_cache: VBarCache,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            sequence_number: 0,
+            glyph_cache: vec![None; GLYPH_CACHE_SIZE].into_boxed_slice(),
+            vbar_cache: VBarCache::new(),
+        }
+    }
+
+    /// Decodes a ClearCodec bitmap stream into `output`.
+    ///
+    /// The size of the bitmap is the size of the destination region. The parts of the region
+    /// which are not covered by any layer keep their current content.
+    pub fn decode(&mut self, input: &[u8], output: &mut ImageRegionMut<'_>) -> Result<(), ClearCodecError> {
+        let width = usize::from(output.region.width());
+        let height = usize::from(output.region.height());
+        let step = u16::try_from(width * BYTES_PER_PIXEL).map_err(|_| ClearCodecError::BitmapTooLarge)?;
+
+        let mut src = ReadCursor::new(input);
+
+        if src.len() < 2 {
+            return Err(ClearCodecError::NotEnoughData);
+        }
+        let flags = GlyphFlags::from_bits_truncate(src.read_u8());
+        let sequence_number = src.read_u8();
+
+        // Resynchronize on the received sequence number, so that a single lost or invalid bitmap
+        // does not make all the next ones fail.
+        let expected_sequence_number = mem::replace(&mut self.sequence_number, sequence_number.wrapping_add(1));
+
+        if sequence_number != expected_sequence_number {
+            return Err(ClearCodecError::UnexpectedSequenceNumber {
+                expected: expected_sequence_number,
+                actual: sequence_number,
+            });
+        }
+
+        if flags.contains(GlyphFlags::CACHE_RESET) {
+            self.vbar_cache.reset_cursors();
+        }
+
+        let glyph_index = if flags.contains(GlyphFlags::GLYPH_INDEX) {
+            if src.len() < 2 {
+                return Err(ClearCodecError::NotEnoughData);
+            }
+            let glyph_index = src.read_u16();
+
+            if usize::from(glyph_index) >= GLYPH_CACHE_SIZE {
+                return Err(ClearCodecError::InvalidGlyphIndex(glyph_index));
+            }
+
+            if width * height > MAX_GLYPH_PIXEL_COUNT {
+                return Err(ClearCodecError::GlyphTooLarge);
+            }
+
+            Some(usize::from(glyph_index))
+        } else {
+            None
+        };
+
+        let bitmap_region = InclusiveRectangle {
+            left: 0,
+            top: 0,
+            right: output.region.width() - 1,
+            bottom: output.region.height() - 1,
+        };
+
+        if flags.contains(GlyphFlags::GLYPH_HIT) {
+            let glyph_index = glyph_index.ok_or_else(|| ClearCodecError::InvalidGlyphFlags(flags.bits()))?;
+
+            let glyph = self.glyph_cache[glyph_index]
+                .as_deref()
+                .ok_or(ClearCodecError::MissingGlyph(glyph_index))?;
+
+            if glyph.len() < width * height * BYTES_PER_PIXEL {
+                return Err(ClearCodecError::GlyphSizeMismatch);
+            }
+
+            let glyph = ImageRegion {
+                region: bitmap_region,
+                step,
+                pixel_format: BITMAP_PIXEL_FORMAT,
+                data: glyph,
+            };
+            glyph.copy_to(output)?;
+
+            return Ok(());
+        }
+
+        // The layers are drawn on top of the current content of the destination.
+        let mut bitmap = vec![0; width * height * BYTES_PER_PIXEL];
+
+        let destination = ImageRegion {
+            region: output.region.clone(),
+            step: output.step,
+            pixel_format: output.pixel_format,
+            data: output.data,
+        };
+        destination.copy_to(&mut ImageRegionMut {
+            region: bitmap_region.clone(),
+            step,
+            pixel_format: BITMAP_PIXEL_FORMAT,
+            data: &mut bitmap,
+        })?;
+
+        let mut bitmap = Bitmap {
+            width,
+            height,
+            data: bitmap,
+        };
+
+        // CLEARCODEC_COMPOSITE_PAYLOAD
+        if src.len() < COMPOSITE_HEADER_SIZE {
+            return Err(ClearCodecError::NotEnoughData);
+        }
+        let residual_byte_count = src.read_u32() as usize;
+        let bands_byte_count = src.read_u32() as usize;
+        let subcodec_byte_count = src.read_u32() as usize;
+
+        let layers_byte_count = residual_byte_count
+            .checked_add(bands_byte_count)
+            .and_then(|count| count.checked_add(subcodec_byte_count))
+            .ok_or(ClearCodecError::NotEnoughData)?;
+        if src.len() < layers_byte_count {
+            return Err(ClearCodecError::NotEnoughData);
+        }
+
+        // Unlike the other layers, an empty residual layer is not a layer without any pixel.
+        if residual_byte_count > 0 {
+            decode_residual(src.read_slice(residual_byte_count), &mut bitmap)?;
+        }
+        self.vbar_cache
+            .decode_bands(src.read_slice(bands_byte_count), &mut bitmap)?;
+        subcodec::decode_subcodecs(src.read_slice(subcodec_byte_count), &mut bitmap)?;
+
+        ImageRegion {
+            region: bitmap_region,
+            step,
+            pixel_format: BITMAP_PIXEL_FORMAT,
+            data: &bitmap.data,
+        }
+        .copy_to(output)?;
+
+        if let Some(glyph_index) = glyph_index {
+            self.glyph_cache[glyph_index] = Some(bitmap.data);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Bitmap being decoded, stored as top-down BGRX pixels.
+struct Bitmap {
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+}
+
+impl Bitmap {
+    fn set_pixel(&mut self, x: usize, y: usize, color: Bgr) {
+        let offset = (y * self.width + x) * BYTES_PER_PIXEL;
+        self.data[offset..offset + color.len()].copy_from_slice(&color);
+    }
+}
+
+/// Decodes the residual layer (CLEARCODEC_RESIDUAL_DATA).
+fn decode_residual(data: &[u8], bitmap: &mut Bitmap) -> Result<(), ClearCodecError> {
+    let mut src = ReadCursor::new(data);
+    let mut pixels = bitmap.data.chunks_exact_mut(BYTES_PER_PIXEL);
+
+    while !src.is_empty() {
+        // CLEARCODEC_RGB_RUN_SEGMENT
+        if src.len() < 3 {
+            return Err(ClearCodecError::NotEnoughData);
+        }
+        let color: Bgr = src.read_array();
+        let run_length = read_run_length(&mut src)?;
+
+        for _ in 0..run_length {
+            let pixel = pixels.next().ok_or(ClearCodecError::InvalidRunLength)?;
+            pixel[..color.len()].copy_from_slice(&color);
+        }
+    }
+
+    if pixels.next().is_some() {
+        return Err(ClearCodecError::InvalidRunLength);
+    }
+
+    Ok(())
+}
+
+/// Reads a run length stored on 1, 3 or 7 bytes, as used by the residual and RLEX encodings.
+fn read_run_length(src: &mut ReadCursor<'_>) -> Result<usize, ClearCodecError> {
+    if src.is_empty() {
+        return Err(ClearCodecError::NotEnoughData);
+    }
+    let run_length = src.read_u8();
+    if run_length < 0xFF {
+        return Ok(usize::from(run_length));
+    }
+
+    if src.len() < 2 {
+        return Err(ClearCodecError::NotEnoughData);
+    }
+    let run_length = src.read_u16();
+    if run_length < 0xFFFF {
+        return Ok(usize::from(run_length));
+    }
+
+    if src.len() < 4 {
+        return Err(ClearCodecError::NotEnoughData);
+    }
+
+    Ok(src.read_u32() as usize)
+}
+
+#[derive(Debug, Error)]
+pub enum ClearCodecError {
+    #[error("IO error: {0}")]
+    IOError(#[from] io::Error),
+    #[error("not enough data")]
+    NotEnoughData,
+    #[error("bitmap is too large")]
+    BitmapTooLarge,
+    #[error("unexpected sequence number (expected {expected}, got {actual})")]
+    UnexpectedSequenceNumber { expected: u8, actual: u8 },
+    #[error("invalid glyph flags ({0:#04x})")]
+    InvalidGlyphFlags(u8),
+    #[error("invalid glyph index ({0})")]
+    InvalidGlyphIndex(u16),
+    #[error("bitmap is too large to be stored as a glyph")]
+    GlyphTooLarge,
+    #[error("missing glyph ({0})")]
+    MissingGlyph(usize),
+    #[error("glyph is smaller than the bitmap")]
+    GlyphSizeMismatch,
+    #[error("run lengths do not match the bitmap size")]
+    InvalidRunLength,
+    #[error("invalid band")]
+    InvalidBand,
+    #[error("invalid vertical bar")]
+    InvalidVBar,
+    #[error("missing vertical bar ({0})")]
+    MissingVBar(usize),
+    #[error("missing short vertical bar ({0})")]
+    MissingShortVBar(usize),
+    #[error("subcodec region is out of the bitmap bounds")]
+    InvalidSubcodecRegion,
+    #[error("subcodec data size does not match the region size")]
+    InvalidSubcodecDataSize,
+    #[error("unsupported subcodec ({0})")]
+    UnsupportedSubcodec(u8),
+    #[error("invalid palette size ({0})")]
+    InvalidPaletteSize(u8),
+    #[error("invalid palette index")]
+    InvalidPaletteIndex,
+    #[error("failed to decode NSCodec subcodec: {0}")]
+    NsCodec(#[from] NsCodecError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Bgr = [0x00, 0x00, 0x00];
+    const WHITE: Bgr = [0xFF, 0xFF, 0xFF];
+    const RED: Bgr = [0x00, 0x00, 0xFF];
+    const GREEN: Bgr = [0x00, 0xFF, 0x00];
+    const BLUE: Bgr = [0xFF, 0x00, 0x00];
+
+    /// Destination bitmap, in BGRX
+    struct Output {
+        width: u16,
+        height: u16,
+        data: Vec<u8>,
+    }
+
+    impl Output {
+        fn new(width: u16, height: u16) -> Self {
+            Self {
+                width,
+                height,
+                data: vec![0; usize::from(width) * usize::from(height) * BYTES_PER_PIXEL],
+            }
+        }
+
+        fn decode(&mut self, decoder: &mut Decoder, input: &[u8]) -> Result<(), ClearCodecError> {
+            decoder.decode(
+                input,
+                &mut ImageRegionMut {
+                    region: InclusiveRectangle {
+                        left: 0,
+                        top: 0,
+                        right: self.width - 1,
+                        bottom: self.height - 1,
+                    },
+                    step: self.width * 4,
+                    pixel_format: PixelFormat::BgrX32,
+                    data: &mut self.data,
+                },
+            )
+        }
+
+        fn pixels(&self) -> Vec<Bgr> {
+            self.data
+                .chunks_exact(BYTES_PER_PIXEL)
+                .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                .collect()
+        }
+    }
+
+    fn composite_payload(residual: &[u8], bands: &[u8], subcodecs: &[u8]) -> Vec<u8> {
+        let mut payload = Vec::new();
+        for layer in [residual, bands, subcodecs] {
+            payload.extend_from_slice(&(layer.len() as u32).to_le_bytes());
+        }
+        for layer in [residual, bands, subcodecs] {
+            payload.extend_from_slice(layer);
+        }
+        payload
+    }
+
+    fn bitmap_stream(flags: u8, sequence_number: u8, payload: &[u8]) -> Vec<u8> {
+        let mut stream = vec![flags, sequence_number];
+        stream.extend_from_slice(payload);
+        stream
+    }
+
+    #[test]
+    fn decodes_residual_layer() {
+        #[rustfmt::skip]
+        let residual = [
+            0x00, 0x00, 0xFF, 0x05,
+            0xFF, 0x00, 0x00, 0x03,
+        ];
+
+        let mut decoder = Decoder::new();
+        let mut output = Output::new(4, 2);
+        output
+            .decode(
+                &mut decoder,
+                &bitmap_stream(0, 0, &composite_payload(&residual, &[], &[])),
+            )
+            .unwrap();
+
+        assert_eq!(output.pixels(), [RED, RED, RED, RED, RED, BLUE, BLUE, BLUE]);
+    }
+
+    #[test]
+    fn decodes_residual_layer_with_long_runs() {
+        #[rustfmt::skip]
+        let residual = [
+            0x00, 0xFF, 0x00, 0xFF, 0x2C, 0x01,
+            0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x2C, 0x01, 0x00, 0x00,
+        ];
+
+        let mut decoder = Decoder::new();
+        let mut output = Output::new(200, 3);
+        output
+            .decode(
+                &mut decoder,
+                &bitmap_stream(0, 0, &composite_payload(&residual, &[], &[])),
+            )
+            .unwrap();
+
+        let pixels = output.pixels();
+        assert!(pixels[..300].iter().all(|&pixel| pixel == GREEN));
+        assert!(pixels[300..].iter().all(|&pixel| pixel == WHITE));
+    }
+
+    #[test]
+    fn residual_layer_must_cover_the_whole_bitmap() {
+        let mut decoder = Decoder::new();
+        let mut output = Output::new(4, 2);
+
+        let result = output.decode(
+            &mut decoder,
+            &bitmap_stream(0, 0, &composite_payload(&[0x00, 0x00, 0xFF, 0x07], &[], &[])),
+        );
+        assert!(matches!(result, Err(ClearCodecError::InvalidRunLength)));
+
+        let result = output.decode(
+            &mut decoder,
+            &bitmap_stream(0, 1, &composite_payload(&[0x00, 0x00, 0xFF, 0x09], &[], &[])),
+        );
+        assert!(matches!(result, Err(ClearCodecError::InvalidRunLength)));
+    }
+
+    #[test]
+    fn decodes_bands_layer_with_vbar_caches() {
+        #[rustfmt::skip]
+        let bands = [
+            // Columns 0 and 1, rows 1 to 3, green background
+            0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x03, 0x00, 0x00, 0xFF, 0x00,
+            // Short vertical bar cache miss, one white pixel at row 1 of the band
+            0x01, 0x02, 0xFF, 0xFF, 0xFF,
+            // Short vertical bar cache hit, moved to row 0 of the band
+            0x00, 0x40, 0x00,
+        ];
+
+        let mut decoder = Decoder::new();
+        let mut output = Output::new(3, 4);
+        output
+            .decode(&mut decoder, &bitmap_stream(0, 0, &composite_payload(&[], &bands, &[])))
+            .unwrap();
+
+        #[rustfmt::skip]
+        assert_eq!(output.pixels(), [
+            BLACK, BLACK, BLACK,
+            GREEN, WHITE, BLACK,
+            WHITE, GREEN, BLACK,
+            GREEN, GREEN, BLACK,
+        ]);
+
+        #[rustfmt::skip]
+        let bands = [
+            // Column 2, rows 0 to 2, red background
+            0x02, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0xFF,
+            // Vertical bar cache hit, second vertical bar of the previous bitmap
+            0x01, 0x80,
+        ];
+
+        output
+            .decode(&mut decoder, &bitmap_stream(0, 1, &composite_payload(&[], &bands, &[])))
+            .unwrap();
+
+        #[rustfmt::skip]
+        assert_eq!(output.pixels(), [
+            BLACK, BLACK, WHITE,
+            GREEN, WHITE, GREEN,
+            WHITE, GREEN, GREEN,
+            GREEN, GREEN, BLACK,
+        ]);
+    }
+
+    #[test]
+    fn cache_reset_restarts_vbar_storage() {
+        #[rustfmt::skip]
+        let bands = [
+            // Columns 0 and 1, row 0, blue background
+            0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00,
+            // Empty short vertical bar, stored at the first cache entries
+            0x00, 0x00,
+            // Short vertical bar with a white pixel
+            0x00, 0x01, 0xFF, 0xFF, 0xFF,
+        ];
+
+        let mut decoder = Decoder::new();
+        let mut output = Output::new(2, 1);
+        output
+            .decode(&mut decoder, &bitmap_stream(0, 0, &composite_payload(&[], &bands, &[])))
+            .unwrap();
+        assert_eq!(output.pixels(), [BLUE, WHITE]);
+
+        #[rustfmt::skip]
+        let bands = [
+            // Columns 0 and 1, row 0, red background
+            0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF,
+            // Empty short vertical bar, stored at the first cache entries again
+            0x00, 0x00,
+            // Vertical bar cache hit, first entry
+            0x00, 0x80,
+        ];
+
+        output
+            .decode(
+                &mut decoder,
+                &bitmap_stream(0x04, 1, &composite_payload(&[], &bands, &[])),
+            )
+            .unwrap();
+        assert_eq!(output.pixels(), [RED, RED]);
+    }
+
+    #[test]
+    fn decodes_uncompressed_and_rlex_subcodecs() {
+        #[rustfmt::skip]
+        let subcodecs = [
+            // Uncompressed, 2x1 at (1, 0)
+            0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00,
+            // RLEX, 4x1 at (0, 1)
+            0x00, 0x00, 0x01, 0x00, 0x04, 0x00, 0x01, 0x00, 0x09, 0x00, 0x00, 0x00, 0x02,
+            // Black and white palette
+            0x02, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF,
+            // Stop index 1 and suite depth 1, after a run of 2 pixels
+            0x03, 0x02,
+        ];
+
+        let mut decoder = Decoder::new();
+        let mut output = Output::new(4, 2);
+        output
+            .decode(
+                &mut decoder,
+                &bitmap_stream(
+                    0,
+                    0,
+                    &composite_payload(&[GREEN[0], GREEN[1], GREEN[2], 0x08], &[], &subcodecs),
+                ),
+            )
+            .unwrap();
+
+        #[rustfmt::skip]
+        assert_eq!(output.pixels(), [
+            GREEN, RED, BLUE, GREEN,
+            BLACK, BLACK, BLACK, WHITE,
+        ]);
+    }
+
+    #[test]
+    fn decodes_nscodec_subcodec() {
+        #[rustfmt::skip]
+        let subcodecs = [
+            0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x02, 0x00, 0x20, 0x00, 0x00, 0x00, 0x01,
+            // Plane byte counts, color loss level 1, no chroma subsampling
+            0x04, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x01, 0x00, 0x00, 0x00,
+            // Luma, orange chroma and green chroma planes
+            0x10, 0x20, 0x30, 0x40,
+            0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ];
+
+        let mut decoder = Decoder::new();
+        let mut output = Output::new(2, 2);
+        output
+            .decode(
+                &mut decoder,
+                &bitmap_stream(0, 0, &composite_payload(&[], &[], &subcodecs)),
+            )
+            .unwrap();
+
+        assert_eq!(output.pixels(), [[0x10; 3], [0x20; 3], [0x30; 3], [0x40; 3]]);
+    }
+
+    #[test]
+    fn layers_are_composited_in_order() {
+        // Each layer is drawn over the previous one (MS-RDPEGFX 3.3.8.1.2).
+        #[rustfmt::skip]
+        let stream = [
+            // No glyph flags, sequence number 0
+            0x00, 0x00,
+            // Residual, bands and subcodec layer byte counts
+            0x04, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00,
+            // Residual layer, 8 green pixels
+            0x00, 0xFF, 0x00, 0x08,
+            // Band on column 0, rows 0 to 1, red background
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xFF,
+            // Short vertical bar cache miss, one white pixel at row 1 of the band
+            0x01, 0x02, 0xFF, 0xFF, 0xFF,
+            // Uncompressed subcodec, one blue pixel at (3, 1)
+            0x03, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00,
+            0xFF, 0x00, 0x00,
+        ];
+
+        let mut decoder = Decoder::new();
+        let mut output = Output::new(4, 2);
+        output.decode(&mut decoder, &stream).unwrap();
+
+        #[rustfmt::skip]
+        assert_eq!(output.pixels(), [
+            RED, GREEN, GREEN, GREEN,
+            WHITE, GREEN, GREEN, BLUE,
+        ]);
+    }
+
+    #[test]
+    fn rejects_oversized_layer_byte_counts() {
+        #[rustfmt::skip]
+        let payload = [
+            0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+            0x00, 0xFF, 0x00, 0x02,
+        ];
+
+        let mut decoder = Decoder::new();
+        let mut output = Output::new(2, 1);
+        let result = output.decode(&mut decoder, &bitmap_stream(0, 0, &payload));
+
+        assert!(matches!(result, Err(ClearCodecError::NotEnoughData)));
+    }
+
+    #[test]
+    fn rejects_subcodec_out_of_bounds() {
+        #[rustfmt::skip]
+        let subcodecs = [
+            0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00,
+        ];
+
+        let mut decoder = Decoder::new();
+        let mut output = Output::new(2, 1);
+        let result = output.decode(
+            &mut decoder,
+            &bitmap_stream(0, 0, &composite_payload(&[], &[], &subcodecs)),
+        );
+
+        assert!(matches!(result, Err(ClearCodecError::InvalidSubcodecRegion)));
+    }
+
+    #[test]
+    fn glyph_is_stored_and_reused() {
+        let mut decoder = Decoder::new();
+
+        let mut payload = vec![0x05, 0x00];
+        payload.extend(composite_payload(&[0x11, 0x22, 0x33, 0x02], &[], &[]));
+
+        let mut output = Output::new(2, 1);
+        output.decode(&mut decoder, &bitmap_stream(0x01, 0, &payload)).unwrap();
+        assert_eq!(output.pixels(), [[0x11, 0x22, 0x33]; 2]);
+
+        let mut output = Output::new(2, 1);
+        output
+            .decode(&mut decoder, &bitmap_stream(0x03, 1, &[0x05, 0x00]))
+            .unwrap();
+        assert_eq!(output.pixels(), [[0x11, 0x22, 0x33]; 2]);
+
+        let result = output.decode(&mut decoder, &bitmap_stream(0x03, 2, &[0x06, 0x00]));
+        assert!(matches!(result, Err(ClearCodecError::MissingGlyph(6))));
+    }
+
+    #[test]
+    fn rejects_glyph_hit_without_index() {
+        let mut decoder = Decoder::new();
+        let mut output = Output::new(2, 1);
+
+        let result = output.decode(&mut decoder, &bitmap_stream(0x02, 0, &[]));
+
+        assert!(matches!(result, Err(ClearCodecError::InvalidGlyphFlags(0x02))));
+    }
+
+    #[test]
+    fn rejects_unexpected_sequence_number_and_resynchronizes() {
+        let mut decoder = Decoder::new();
+        let mut output = Output::new(2, 1);
+        let payload = composite_payload(&[0x11, 0x22, 0x33, 0x02], &[], &[]);
+
+        output.decode(&mut decoder, &bitmap_stream(0, 0, &payload)).unwrap();
+        let result = output.decode(&mut decoder, &bitmap_stream(0, 0, &payload));
+
+        assert!(matches!(
+            result,
+            Err(ClearCodecError::UnexpectedSequenceNumber { expected: 1, actual: 0 })
+        ));
+
+        // The decoder resynchronizes on the last received sequence number.
+        output.decode(&mut decoder, &bitmap_stream(0, 1, &payload)).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+//! Subcodecs layer ([MS-RDPEGFX] 2.2.4.1.1.3)
+//!
+//! [MS-RDPEGFX]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegfx/da5c75f9-cd99-450c-98c4-014a496942b0
+
+use ironrdp_core::ReadCursor;
+
+use super::{read_run_length, Bgr, Bitmap, ClearCodecError};
+use crate::nscodec;
+
+/// CLEARCODEC_SUBCODEC, without the bitmap data
+const SUBCODEC_HEADER_SIZE: usize =
+    2 /* xStart */ + 2 /* yStart */ + 2 /* width */ + 2 /* height */ + 4 /* bitmapDataByteCount */ + 1 /* subCodecId */;
+
+const MAX_PALETTE_SIZE: u8 = 127;
+
+const SUBCODEC_ID_UNCOMPRESSED: u8 = 0;
+const SUBCODEC_ID_NSCODEC: u8 = 1;
+const SUBCODEC_ID_RLEX: u8 = 2;
+
+pub(super) fn decode_subcodecs(data: &[u8], bitmap: &mut Bitmap) -> Result<(), ClearCodecError> {
+    let mut src = ReadCursor::new(data);
+
+    while !src.is_empty() {
+        if src.len() < SUBCODEC_HEADER_SIZE {
+            return Err(ClearCodecError::NotEnoughData);
+        }
+        let x_start = usize::from(src.read_u16());
+        let y_start = usize::from(src.read_u16());
+        let width = usize::from(src.read_u16());
+        let height = usize::from(src.read_u16());
+        let bitmap_data_byte_count = src.read_u32() as usize;
+        let subcodec_id = src.read_u8();
+
+        if src.len() < bitmap_data_byte_count {
+            return Err(ClearCodecError::NotEnoughData);
+        }
+        let bitmap_data = src.read_slice(bitmap_data_byte_count);
+
+        if x_start + width > bitmap.width || y_start + height > bitmap.height {
+            return Err(ClearCodecError::InvalidSubcodecRegion);
+        }
+
+        let pixel_count = width * height;
+
+        let pixels: Vec<Bgr> = match subcodec_id {
+            SUBCODEC_ID_UNCOMPRESSED => {
+                if bitmap_data.len() != pixel_count * 3 {
+                    return Err(ClearCodecError::InvalidSubcodecDataSize);
+                }
+
+                bitmap_data
+                    .chunks_exact(3)
+                    .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                    .collect()
+            }
+            SUBCODEC_ID_NSCODEC => {
+                let mut pixels = Vec::with_capacity(pixel_count * 4);
+                nscodec::decode(bitmap_data, &mut pixels, width, height)?;
+
+                pixels
+                    .chunks_exact(4)
+                    .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                    .collect()
+            }
+            SUBCODEC_ID_RLEX => decode_rlex(bitmap_data, pixel_count)?,
+            _ => return Err(ClearCodecError::UnsupportedSubcodec(subcodec_id)),
+        };
+
+        for (index, color) in pixels.into_iter().enumerate() {
+            bitmap.set_pixel(x_start + index % width, y_start + index / width, color);
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes a RLEX bitmap (CLEARCODEC_SUBCODEC_RLEX).
+///
+/// Each segment is a run of a palette color, followed by a suite of consecutive palette colors
+/// starting with the same color.
+fn decode_rlex(data: &[u8], pixel_count: usize) -> Result<Vec<Bgr>, ClearCodecError> {
+    let mut src = ReadCursor::new(data);
+
+    if src.is_empty() {
+        return Err(ClearCodecError::NotEnoughData);
+    }
+    let palette_count = src.read_u8();
+
+    if !(1..=MAX_PALETTE_SIZE).contains(&palette_count) {
+        return Err(ClearCodecError::InvalidPaletteSize(palette_count));
+    }
+
+    if src.len() < usize::from(palette_count) * 3 {
+        return Err(ClearCodecError::NotEnoughData);
+    }
+    let palette: Vec<Bgr> = (0..palette_count).map(|_| src.read_array()).collect();
+
+    // Number of bits used to store palette indices, the remaining bits hold the suite depth.
+    let index_bits = (palette_count - 1).max(1).ilog2() + 1;
+
+    let mut pixels = Vec::with_capacity(pixel_count);
+
+    while !src.is_empty() {
+        // CLEARCODEC_RLEX_SEGMENT
+        let header = src.read_u8();
+        let stop_index = usize::from(header & ((1 << index_bits) - 1));
+        let suite_depth = usize::from(header >> index_bits);
+        let run_length = read_run_length(&mut src)?;
+
+        if stop_index >= palette.len() {
+            return Err(ClearCodecError::InvalidPaletteIndex);
+        }
+        let start_index = stop_index
+            .checked_sub(suite_depth)
+            .ok_or(ClearCodecError::InvalidPaletteIndex)?;
+
+        if pixels.len() + run_length + suite_depth + 1 > pixel_count {
+            return Err(ClearCodecError::InvalidRunLength);
+        }
+
+        pixels.resize(pixels.len() + run_length, palette[start_index]);
+        pixels.extend_from_slice(&palette[start_index..=stop_index]);
+    }
+
+    if pixels.len() != pixel_count {
+        return Err(ClearCodecError::InvalidRunLength);
+    }
+
+    Ok(pixels)
+}
@@ -6,10 +6,14 @@
 #![allow(clippy::cast_possible_wrap)] // FIXME: remove
 #![allow(clippy::cast_sign_loss)] // FIXME: remove
 
+pub mod bulk;
+pub mod clearcodec;
 pub mod color_conversion;
 pub mod dwt;
 pub mod image_processing;
+pub mod nscodec;
 pub mod pointer;
+pub mod progressive;
 pub mod quantization;
 pub mod rdp6;
 pub mod rectangle_processing;
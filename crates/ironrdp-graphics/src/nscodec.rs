@@ -0,0 +1,203 @@
+//! NSCodec decoding ([MS-RDPNSC])
+//!
+//! [MS-RDPNSC]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpnsc/
+
+use ironrdp_core::ReadCursor;
+use thiserror::Error;
+
+/// NSCODEC_BITMAP_STREAM, without the planes
+const HEADER_SIZE: usize = 4 * 4 /* PlaneByteCount */ + 1 /* ColorLossLevel */ + 1 /* ChromaSubsamplingLevel */ + 2 /* Reserved */;
+
+/// The last bytes of a RLE encoded plane are always stored as is.
+const RLE_END_DATA_SIZE: usize = 4;
+
+/// Decodes a NSCodec bitmap stream, appending `width` x `height` top-down BGRA pixels to `dst`.
+pub fn decode(src: &[u8], dst: &mut Vec<u8>, width: usize, height: usize) -> Result<(), NsCodecError> {
+    if src.len() < HEADER_SIZE {
+        return Err(NsCodecError::NotEnoughData);
+    }
+
+    let mut src = ReadCursor::new(src);
+
+    let luma_byte_count = src.read_u32() as usize;
+    let orange_chroma_byte_count = src.read_u32() as usize;
+    let green_chroma_byte_count = src.read_u32() as usize;
+    let alpha_byte_count = src.read_u32() as usize;
+    let color_loss_level = src.read_u8();
+    let chroma_subsampling = src.read_u8() != 0;
+    src.advance(2); // Reserved
+
+    if !(1..=7).contains(&color_loss_level) {
+        return Err(NsCodecError::InvalidColorLossLevel(color_loss_level));
+    }
+
+    // With chroma subsampling, the luma plane width is rounded up to a multiple of 8, and the
+    // chroma planes are subsampled by a factor of 2 in both directions.
+    let (luma_width, chroma_width, chroma_height) = if chroma_subsampling {
+        let luma_width = width.next_multiple_of(8);
+        (luma_width, luma_width / 2, height.div_ceil(2))
+    } else {
+        (width, width, height)
+    };
+
+    let mut read_plane = |byte_count: usize, size: usize| {
+        if src.len() < byte_count {
+            return Err(NsCodecError::NotEnoughData);
+        }
+
+        decode_plane(src.read_slice(byte_count), size)
+    };
+
+    let luma = read_plane(luma_byte_count, luma_width * height)?;
+    let orange_chroma = read_plane(orange_chroma_byte_count, chroma_width * chroma_height)?;
+    let green_chroma = read_plane(green_chroma_byte_count, chroma_width * chroma_height)?;
+    let alpha = read_plane(alpha_byte_count, width * height)?;
+
+    // Color loss reduction is reverted along with the 1-bit shift applied to the chroma values.
+    let shift = color_loss_level - 1;
+    let subsampling_shift = usize::from(chroma_subsampling);
+
+    dst.reserve(width * height * 4);
+
+    for y in 0..height {
+        let luma_row = &luma[y * luma_width..];
+        let chroma_offset = (y >> subsampling_shift) * chroma_width;
+        let orange_chroma_row = &orange_chroma[chroma_offset..];
+        let green_chroma_row = &green_chroma[chroma_offset..];
+        let alpha_row = &alpha[y * width..];
+
+        for x in 0..width {
+            let y_value = i16::from(luma_row[x]);
+            let co = i16::from((orange_chroma_row[x >> subsampling_shift] << shift) as i8);
+            let cg = i16::from((green_chroma_row[x >> subsampling_shift] << shift) as i8);
+
+            let r = y_value + co - cg;
+            let g = y_value + cg;
+            let b = y_value - co - cg;
+
+            dst.extend_from_slice(&[
+                b.clamp(0, 255) as u8,
+                g.clamp(0, 255) as u8,
+                r.clamp(0, 255) as u8,
+                alpha_row[x],
+            ]);
+        }
+    }
+
+    Ok(())
+}
+
+fn decode_plane(data: &[u8], size: usize) -> Result<Vec<u8>, NsCodecError> {
+    match data.len() {
+        // Empty planes are used for fully opaque alpha planes.
+        0 => Ok(vec![0xFF; size]),
+        len if len < size => decode_rle(data, size),
+        len if len == size => Ok(data.to_vec()),
+        _ => Err(NsCodecError::InvalidPlaneSize),
+    }
+}
+
+/// Decodes a RLE encoded plane ([MS-RDPNSC] 2.2.2.1).
+///
+/// Runs are encoded as a value repeated twice, followed by the run length minus two (on one
+/// byte, or 0xFF followed by the full run length on four bytes).
+fn decode_rle(data: &[u8], size: usize) -> Result<Vec<u8>, NsCodecError> {
+    let Some(encoded_size) = size.checked_sub(RLE_END_DATA_SIZE) else {
+        return Err(NsCodecError::InvalidPlaneSize);
+    };
+
+    let mut src = ReadCursor::new(data);
+    let mut plane = Vec::with_capacity(size);
+
+    while plane.len() < encoded_size {
+        if src.is_empty() {
+            return Err(NsCodecError::NotEnoughData);
+        }
+        let value = src.read_u8();
+
+        if plane.len() + 1 == encoded_size || src.try_peek_u8().ok() != Some(value) {
+            plane.push(value);
+            continue;
+        }
+        src.advance(1);
+
+        if src.is_empty() {
+            return Err(NsCodecError::NotEnoughData);
+        }
+        let run_length = match src.read_u8() {
+            0xFF => {
+                if src.len() < 4 {
+                    return Err(NsCodecError::NotEnoughData);
+                }
+                src.read_u32() as usize
+            }
+            factor => usize::from(factor) + 2,
+        };
+
+        if plane.len() + run_length > encoded_size {
+            return Err(NsCodecError::InvalidRunLength);
+        }
+        plane.resize(plane.len() + run_length, value);
+    }
+
+    if src.len() < RLE_END_DATA_SIZE {
+        return Err(NsCodecError::NotEnoughData);
+    }
+    plane.extend_from_slice(src.read_slice(RLE_END_DATA_SIZE));
+
+    Ok(plane)
+}
+
+#[derive(Debug, Error)]
+pub enum NsCodecError {
+    #[error("not enough data")]
+    NotEnoughData,
+    #[error("invalid color loss level ({0})")]
+    InvalidColorLossLevel(u8),
+    #[error("plane size does not match the bitmap size")]
+    InvalidPlaneSize,
+    #[error("run length exceeds the plane size")]
+    InvalidRunLength,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_rle_and_raw_planes() {
+        #[rustfmt::skip]
+        let stream = [
+            // Plane byte counts, color loss level 1, no chroma subsampling
+            0x07, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x01, 0x00, 0x00, 0x00,
+            // Luma plane, a run of 5 followed by the 4 raw end bytes
+            0x10, 0x10, 0x03, 0x01, 0x02, 0x03, 0x04,
+            // Orange chroma plane
+            0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10,
+            // Green chroma plane
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+
+        let mut pixels = Vec::new();
+        decode(&stream, &mut pixels, 3, 3).unwrap();
+
+        #[rustfmt::skip]
+        assert_eq!(pixels, [
+            0x00, 0x10, 0x20, 0xFF, 0x00, 0x10, 0x20, 0xFF, 0x00, 0x10, 0x20, 0xFF,
+            0x00, 0x10, 0x20, 0xFF, 0x00, 0x10, 0x20, 0xFF, 0x00, 0x01, 0x11, 0xFF,
+            0x00, 0x02, 0x12, 0xFF, 0x00, 0x03, 0x13, 0xFF, 0x00, 0x04, 0x14, 0xFF,
+        ]);
+    }
+
+    #[test]
+    fn rejects_invalid_color_loss_level() {
+        let mut stream = [0; HEADER_SIZE];
+        stream[16] = 8;
+
+        assert!(matches!(
+            decode(&stream, &mut Vec::new(), 1, 1),
+            Err(NsCodecError::InvalidColorLossLevel(8))
+        ));
+    }
+}
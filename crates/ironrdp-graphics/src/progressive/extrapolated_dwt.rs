@@ -0,0 +1,152 @@
+//! Inverse DWT with reduce-extrapolate ([MS-RDPEGFX] 3.2.8.1.2.1)
+//!
+//! Unlike the classic RemoteFX DWT, the subbands of each level are not of equal size: the low
+//! band has one more coefficient than half the input, and the high band covers the remainder.
+//!
+//! [MS-RDPEGFX]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegfx/da5c75f9-cd99-450c-98c4-014a496942b0
+
+use super::TILE_SIZE;
+
+/// Offset of the level 2 subbands, following the level 1 HL1, LH1 and HH1 subbands.
+const LEVEL_2_OFFSET: usize = 3007;
+/// Offset of the level 3 subbands, following the level 2 HL2, LH2 and HH2 subbands.
+const LEVEL_3_OFFSET: usize = 3807;
+
+pub(super) fn decode(buffer: &mut [i16], temp_buffer: &mut [i16]) {
+    decode_level(&mut buffer[LEVEL_3_OFFSET..], temp_buffer, 3);
+    decode_level(&mut buffer[LEVEL_2_OFFSET..], temp_buffer, 2);
+    decode_level(buffer, temp_buffer, 1);
+}
+
+fn low_band_size(level: u32) -> usize {
+    (TILE_SIZE >> level) + 1
+}
+
+fn high_band_size(level: u32) -> usize {
+    if level == 1 {
+        (TILE_SIZE >> 1) - 1
+    } else {
+        (TILE_SIZE + (1 << (level - 1))) >> level
+    }
+}
+
+/// Combines the HL, LH, HH and LL subbands of a level, stored in this order at the start of
+/// `buffer`, into the LL subband of the previous level, stored at the start of `buffer`.
+fn decode_level(buffer: &mut [i16], temp_buffer: &mut [i16], level: u32) {
+    let low = low_band_size(level);
+    let high = high_band_size(level);
+    let total = low + high;
+
+    let (hl, buffer_rest) = buffer.split_at(high * low);
+    let (lh, buffer_rest) = buffer_rest.split_at(low * high);
+    let (hh, ll) = buffer_rest.split_at(high * high);
+
+    let (l, h) = temp_buffer[..total * total].split_at_mut(low * total);
+
+    // Horizontal pass: LL + HL -> L, LH + HH -> H
+    for row in 0..low {
+        inverse_lift(
+            |i| ll[row * low + i],
+            |i| hl[row * high + i],
+            |i, value| l[row * total + i] = value,
+            low,
+            high,
+        );
+    }
+    for row in 0..high {
+        inverse_lift(
+            |i| lh[row * low + i],
+            |i| hh[row * high + i],
+            |i, value| h[row * total + i] = value,
+            low,
+            high,
+        );
+    }
+
+    // Vertical pass: L + H -> LL of the previous level
+    for column in 0..total {
+        inverse_lift(
+            |i| l[i * total + column],
+            |i| h[i * total + column],
+            |i, value| buffer[i * total + column] = value,
+            low,
+            high,
+        );
+    }
+}
+
+/// Reconstructs `low + high` samples from `low` low-pass and `high` high-pass coefficients.
+fn inverse_lift(
+    low_band: impl Fn(usize) -> i16,
+    high_band: impl Fn(usize) -> i16,
+    mut output: impl FnMut(usize, i16),
+    low: usize,
+    high: usize,
+) {
+    let mut h0 = i32::from(high_band(0));
+    let mut x0 = i32::from((i32::from(low_band(0)) - h0) as i16);
+    let mut x2 = x0;
+
+    for i in 1..high {
+        let h1 = i32::from(high_band(i));
+        x2 = i32::from((i32::from(low_band(i)) - (h0 + h1) / 2) as i16);
+        let x1 = (x0 + x2) / 2 + 2 * h0;
+
+        output(2 * (i - 1), x0 as i16);
+        output(2 * (i - 1) + 1, x1 as i16);
+
+        x0 = x2;
+        h0 = h1;
+    }
+
+    let last = 2 * (high - 1);
+
+    if low <= high {
+        output(last, x2 as i16);
+        output(last + 1, (x2 + 2 * h0) as i16);
+    } else if low == high + 1 {
+        let x0 = i32::from((i32::from(low_band(high)) - h0) as i16);
+
+        output(last, x2 as i16);
+        output(last + 1, ((x0 + x2) / 2 + 2 * h0) as i16);
+        output(last + 2, x0 as i16);
+    } else {
+        let x0 = i32::from((i32::from(low_band(high)) - h0 / 2) as i16);
+
+        output(last, x2 as i16);
+        output(last + 1, ((x0 + x2) / 2 + 2 * h0) as i16);
+        output(last + 2, x0 as i16);
+        output(last + 3, ((x0 + i32::from(low_band(high + 1))) / 2) as i16);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subbands_fill_the_tile() {
+        let level_size = |level| {
+            let (low, high) = (low_band_size(level), high_band_size(level));
+            2 * low * high + high * high
+        };
+
+        assert_eq!(level_size(1), LEVEL_2_OFFSET);
+        assert_eq!(LEVEL_2_OFFSET + level_size(2), LEVEL_3_OFFSET);
+        assert_eq!(
+            LEVEL_3_OFFSET + level_size(3) + low_band_size(3).pow(2),
+            TILE_SIZE * TILE_SIZE
+        );
+    }
+
+    #[test]
+    fn flat_low_band_gives_flat_tile() {
+        let mut buffer = vec![0; TILE_SIZE * TILE_SIZE];
+        let mut temp_buffer = vec![0; TILE_SIZE * TILE_SIZE];
+        buffer[TILE_SIZE * TILE_SIZE - low_band_size(3).pow(2)..].fill(100);
+
+        decode(&mut buffer, &mut temp_buffer);
+
+        assert!(buffer.iter().all(|&value| value == 100));
+    }
+}
@@ -0,0 +1,826 @@
+//! RemoteFX Progressive decoding ([MS-RDPEGFX] 2.2.4.2)
+//!
+//! A progressive bitmap stream is a sequence of blocks describing regions of a surface. Tiles
+//! are first sent at a reduced quality, then refined by upgrade passes. The decoder keeps the
+//! coefficients of every received tile, for as long as its encoding context is alive.
+//!
+//! [MS-RDPEGFX]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegfx/da5c75f9-cd99-450c-98c4-014a496942b0
+
+mod extrapolated_dwt;
+mod upgrade;
+
+use core::ops::Range;
+use std::collections::BTreeMap;
+use std::io;
+
+use bitflags::bitflags;
+use ironrdp_core::{Decode as _, DecodeError, ReadCursor};
+use ironrdp_pdu::codecs::rfx::{EntropyAlgorithm, Quant};
+use ironrdp_pdu::geometry::{InclusiveRectangle, Rectangle as _};
+use thiserror::Error;
+
+use self::upgrade::UpgradeState;
+use crate::color_conversion::{self, YCbCrBuffer};
+use crate::image_processing::{ImageRegion, ImageRegionMut, PixelFormat};
+use crate::rlgr::{self, RlgrError};
+use crate::{dwt, subband_reconstruction};
+
+const TILE_SIZE: usize = 64;
+const TILE_PIXEL_COUNT: usize = TILE_SIZE * TILE_SIZE;
+const TILE_PIXEL_FORMAT: PixelFormat = PixelFormat::RgbA32;
+
+const BLOCK_HEADER_SIZE: usize = 2 /* blockType */ + 4 /* blockLen */;
+
+const WBT_SYNC: u16 = 0xCCC0;
+const WBT_FRAME_BEGIN: u16 = 0xCCC1;
+const WBT_FRAME_END: u16 = 0xCCC2;
+const WBT_CONTEXT: u16 = 0xCCC3;
+const WBT_REGION: u16 = 0xCCC4;
+const WBT_TILE_SIMPLE: u16 = 0xCCC5;
+const WBT_TILE_FIRST: u16 = 0xCCC6;
+const WBT_TILE_UPGRADE: u16 = 0xCCC7;
+
+const SYNC_MAGIC: u32 = 0xCACC_ACCA;
+const SYNC_VERSION: u16 = 0x0100;
+
+/// RFX_PROGRESSIVE_SYNC, without the block header
+const SYNC_SIZE: usize = 4 /* magic */ + 2 /* version */;
+/// RFX_PROGRESSIVE_CONTEXT, without the block header
+const CONTEXT_SIZE: usize = 1 /* ctxId */ + 2 /* tileSize */ + 1 /* flags */;
+/// RFX_PROGRESSIVE_REGION, without the block header and the variable fields
+const REGION_HEADER_SIZE: usize = 1 /* tileSize */ + 2 /* numRects */ + 1 /* numQuant */ + 1 /* numProgQuant */ + 1 /* flags */ + 2 /* numTiles */ + 4 /* tileDataSize */;
+/// TS_RFX_RECT
+const RECT_SIZE: usize = 2 /* x */ + 2 /* y */ + 2 /* width */ + 2 /* height */;
+/// TS_RFX_CODEC_QUANT
+const QUANT_SIZE: usize = 5;
+/// RFX_PROGRESSIVE_CODEC_QUANT
+const PROGRESSIVE_QUANT_SIZE: usize = 1 /* quality */ + 3 * QUANT_SIZE;
+/// RFX_PROGRESSIVE_TILE_SIMPLE, without the block header and the component data
+const TILE_SIMPLE_HEADER_SIZE: usize = 3 /* quantIdxY, quantIdxCb, quantIdxCr */ + 2 /* xIdx */ + 2 /* yIdx */ + 1 /* flags */ + 2 /* yLen */ + 2 /* cbLen */ + 2 /* crLen */ + 2 /* tailLen */;
+/// RFX_PROGRESSIVE_TILE_FIRST, without the block header and the component data
+const TILE_FIRST_HEADER_SIZE: usize = TILE_SIMPLE_HEADER_SIZE + 1 /* quality */;
+/// RFX_PROGRESSIVE_TILE_UPGRADE, without the block header and the component data
+const TILE_UPGRADE_HEADER_SIZE: usize = 3 /* quantIdxY, quantIdxCb, quantIdxCr */ + 2 /* xIdx */ + 2 /* yIdx */ + 1 /* quality */ + 3 * (2 /* srlLen */ + 2 /* rawLen */);
+
+/// Quality index of the tiles sent without progressive quantization.
+const FULL_QUALITY: u8 = 0xFF;
+
+/// Subbands of the classic DWT, in HL1, LH1, HH1, HL2, LH2, HH2, HL3, LH3, HH3 and LL3 order.
+const SUBBANDS: [Range<usize>; SUBBAND_COUNT] = [
+    0..1024,
+    1024..2048,
+    2048..3072,
+    3072..3328,
+    3328..3584,
+    3584..3840,
+    3840..3904,
+    3904..3968,
+    3968..4032,
+    4032..4096,
+];
+
+/// Subbands of the reduce-extrapolate DWT, in the same order as [`SUBBANDS`].
+const EXTRAPOLATED_SUBBANDS: [Range<usize>; SUBBAND_COUNT] = [
+    0..1023,
+    1023..2046,
+    2046..3007,
+    3007..3279,
+    3279..3551,
+    3551..3807,
+    3807..3879,
+    3879..3951,
+    3951..4015,
+    4015..4096,
+];
+
+const SUBBAND_COUNT: usize = 10;
+const LL3_INDEX: usize = 9;
+
+/// Per-subband values, in the same order as [`SUBBANDS`].
+type SubbandValues = [u8; SUBBAND_COUNT];
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct RegionFlags: u8 {
+        const DWT_REDUCE_EXTRAPOLATE = 0x01;
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct TileFlags: u8 {
+        const DIFFERENCE = 0x01;
+    }
+}
+
+/// Stateful RemoteFX Progressive decoder for a single encoding context.
+///
+/// An encoding context belongs to a surface, and the tiles it holds are positioned relative to
+/// the top-left corner of that surface.
+pub struct Decoder {
+    tiles: BTreeMap<(u16, u16), Tile>,
+    buffers: Buffers,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            tiles: BTreeMap::new(),
+            buffers: Buffers::new(),
+        }
+    }
+
+    /// Decodes a progressive bitmap stream into `output`, which covers the surface.
+    ///
+    /// The coordinates of the stream are relative to the top-left corner of the `output` region.
+    /// Returns the rectangles which have been updated.
+    pub fn decode(
+        &mut self,
+        input: &[u8],
+        output: &mut ImageRegionMut<'_>,
+    ) -> Result<Vec<InclusiveRectangle>, ProgressiveError> {
+        let mut src = ReadCursor::new(input);
+        let mut updated_rectangles = Vec::new();
+
+        while !src.is_empty() {
+            let (block_type, mut block) = read_block(&mut src)?;
+
+            match block_type {
+                WBT_SYNC => {
+                    if block.len() < SYNC_SIZE {
+                        return Err(ProgressiveError::NotEnoughData);
+                    }
+                    let magic = block.read_u32();
+                    let version = block.read_u16();
+
+                    if magic != SYNC_MAGIC || version != SYNC_VERSION {
+                        return Err(ProgressiveError::InvalidSync);
+                    }
+                }
+                // Frames are delimited by the graphics pipeline messages already.
+                WBT_FRAME_BEGIN | WBT_FRAME_END => {}
+                WBT_CONTEXT => {
+                    if block.len() < CONTEXT_SIZE {
+                        return Err(ProgressiveError::NotEnoughData);
+                    }
+                    block.advance(1); // ctxId
+                    let tile_size = block.read_u16();
+                    // The subband diffing flag only tells whether tiles may be sent as differences,
+                    // which is signaled again by each tile.
+                    block.advance(1); // flags
+
+                    if usize::from(tile_size) != TILE_SIZE {
+                        return Err(ProgressiveError::InvalidTileSize(tile_size));
+                    }
+                }
+                WBT_REGION => self.decode_region(block, output, &mut updated_rectangles)?,
+                _ => return Err(ProgressiveError::UnexpectedBlock(block_type)),
+            }
+        }
+
+        Ok(updated_rectangles)
+    }
+
+    fn decode_region(
+        &mut self,
+        mut src: ReadCursor<'_>,
+        output: &mut ImageRegionMut<'_>,
+        updated_rectangles: &mut Vec<InclusiveRectangle>,
+    ) -> Result<(), ProgressiveError> {
+        if src.len() < REGION_HEADER_SIZE {
+            return Err(ProgressiveError::NotEnoughData);
+        }
+        let tile_size = src.read_u8();
+        let rect_count = usize::from(src.read_u16());
+        let quant_count = usize::from(src.read_u8());
+        let progressive_quant_count = usize::from(src.read_u8());
+        let flags = RegionFlags::from_bits_truncate(src.read_u8());
+        let tile_count = src.read_u16();
+        let tile_data_size = src.read_u32() as usize;
+
+        if usize::from(tile_size) != TILE_SIZE {
+            return Err(ProgressiveError::InvalidTileSize(u16::from(tile_size)));
+        }
+
+        if src.len()
+            < rect_count * RECT_SIZE
+                + quant_count * QUANT_SIZE
+                + progressive_quant_count * PROGRESSIVE_QUANT_SIZE
+                + tile_data_size
+        {
+            return Err(ProgressiveError::NotEnoughData);
+        }
+
+        let rectangles: Vec<_> = (0..rect_count)
+            .filter_map(|_| {
+                let x = src.read_u16();
+                let y = src.read_u16();
+                let width = src.read_u16();
+                let height = src.read_u16();
+
+                (width > 0 && height > 0).then(|| InclusiveRectangle {
+                    left: x,
+                    top: y,
+                    right: x.saturating_add(width - 1),
+                    bottom: y.saturating_add(height - 1),
+                })
+            })
+            .collect();
+
+        let quants = (0..quant_count)
+            .map(|_| Quant::decode(&mut src))
+            .collect::<Result<_, _>>()?;
+
+        let progressive_quants = (0..progressive_quant_count)
+            .map(|_| {
+                src.advance(1); // quality
+                Ok([
+                    Quant::decode(&mut src)?,
+                    Quant::decode(&mut src)?,
+                    Quant::decode(&mut src)?,
+                ])
+            })
+            .collect::<Result<_, DecodeError>>()?;
+
+        let region = Region {
+            rectangles,
+            quants,
+            progressive_quants,
+            extrapolate: flags.contains(RegionFlags::DWT_REDUCE_EXTRAPOLATE),
+        };
+
+        let mut tiles = ReadCursor::new(src.read_slice(tile_data_size));
+
+        for _ in 0..tile_count {
+            let (block_type, block) = read_block(&mut tiles)?;
+
+            let tile_position = match block_type {
+                WBT_TILE_SIMPLE => self.decode_first_pass(block, &region, false)?,
+                WBT_TILE_FIRST => self.decode_first_pass(block, &region, true)?,
+                WBT_TILE_UPGRADE => self.decode_upgrade_pass(block, &region)?,
+                _ => return Err(ProgressiveError::UnexpectedBlock(block_type)),
+            };
+
+            self.write_tile(tile_position, &region, output, updated_rectangles)?;
+        }
+
+        Ok(())
+    }
+
+    /// Decodes a RFX_PROGRESSIVE_TILE_SIMPLE or RFX_PROGRESSIVE_TILE_FIRST block.
+    fn decode_first_pass(
+        &mut self,
+        mut src: ReadCursor<'_>,
+        region: &Region,
+        has_quality: bool,
+    ) -> Result<(u16, u16), ProgressiveError> {
+        let header_size = if has_quality {
+            TILE_FIRST_HEADER_SIZE
+        } else {
+            TILE_SIMPLE_HEADER_SIZE
+        };
+        if src.len() < header_size {
+            return Err(ProgressiveError::NotEnoughData);
+        }
+        let quant_indices = [src.read_u8(), src.read_u8(), src.read_u8()];
+        let x_index = src.read_u16();
+        let y_index = src.read_u16();
+        let flags = TileFlags::from_bits_truncate(src.read_u8());
+        let quality = if has_quality { src.read_u8() } else { FULL_QUALITY };
+        let lengths = [src.read_u16(), src.read_u16(), src.read_u16()].map(usize::from);
+        let tail_length = usize::from(src.read_u16());
+
+        if src.len() < lengths.iter().sum::<usize>() + tail_length {
+            return Err(ProgressiveError::NotEnoughData);
+        }
+        let data = lengths.map(|length| src.read_slice(length));
+
+        let bit_positions = region.bit_positions(quant_indices, quality)?;
+        let subbands = region.subbands();
+
+        let tile = self.tiles.entry((x_index, y_index)).or_insert_with(Tile::new);
+
+        for component in 0..3 {
+            let signs = &mut tile.signs[component];
+            let coefficients = &mut tile.coefficients[component];
+            let buffer = &mut self.buffers.temp;
+
+            if data[component].is_empty() {
+                buffer.fill(0);
+            } else {
+                rlgr::decode(EntropyAlgorithm::Rlgr1, data[component], buffer)?;
+            }
+            signs.copy_from_slice(buffer);
+
+            subband_reconstruction::decode(&mut buffer[subbands[LL3_INDEX].clone()]);
+
+            for (subband, bit_position) in subbands.iter().zip(bit_positions[component]) {
+                dequantize(&mut buffer[subband.clone()], bit_position);
+            }
+
+            if flags.contains(TileFlags::DIFFERENCE) {
+                for (coefficient, value) in coefficients.iter_mut().zip(buffer.iter()) {
+                    *coefficient = coefficient.wrapping_add(*value);
+                }
+            } else {
+                coefficients.copy_from_slice(buffer);
+            }
+        }
+
+        tile.bit_positions = bit_positions;
+
+        Ok((x_index, y_index))
+    }
+
+    /// Decodes a RFX_PROGRESSIVE_TILE_UPGRADE block.
+    fn decode_upgrade_pass(
+        &mut self,
+        mut src: ReadCursor<'_>,
+        region: &Region,
+    ) -> Result<(u16, u16), ProgressiveError> {
+        if src.len() < TILE_UPGRADE_HEADER_SIZE {
+            return Err(ProgressiveError::NotEnoughData);
+        }
+        let quant_indices = [src.read_u8(), src.read_u8(), src.read_u8()];
+        let x_index = src.read_u16();
+        let y_index = src.read_u16();
+        let quality = src.read_u8();
+        let lengths = [
+            src.read_u16(),
+            src.read_u16(),
+            src.read_u16(),
+            src.read_u16(),
+            src.read_u16(),
+            src.read_u16(),
+        ]
+        .map(usize::from);
+
+        if src.len() < lengths.iter().sum() {
+            return Err(ProgressiveError::NotEnoughData);
+        }
+        let data = lengths.map(|length| src.read_slice(length));
+
+        let tile = self
+            .tiles
+            .get_mut(&(x_index, y_index))
+            .ok_or(ProgressiveError::MissingTile { x_index, y_index })?;
+
+        let bit_positions = region.bit_positions(quant_indices, quality)?;
+
+        // Upgrade passes can only add bits below the ones already received.
+        let mut bit_counts = [[0; SUBBAND_COUNT]; 3];
+        for component in 0..3 {
+            for subband in 0..SUBBAND_COUNT {
+                bit_counts[component][subband] = tile.bit_positions[component][subband]
+                    .checked_sub(bit_positions[component][subband])
+                    .ok_or(ProgressiveError::InvalidUpgradeQuality(quality))?;
+            }
+        }
+
+        for component in 0..3 {
+            let coefficients = &mut tile.coefficients[component];
+            let signs = &mut tile.signs[component];
+            let mut state = UpgradeState::new(data[2 * component], data[2 * component + 1]);
+
+            for (index, subband) in region.subbands().iter().enumerate() {
+                let shift = u32::from(bit_positions[component][index].saturating_sub(1));
+                let bit_count = u32::from(bit_counts[component][index]);
+
+                if index == LL3_INDEX {
+                    state.upgrade_ll3_subband(&mut coefficients[subband.clone()], shift, bit_count);
+                } else {
+                    state.upgrade_subband(
+                        &mut coefficients[subband.clone()],
+                        &mut signs[subband.clone()],
+                        shift,
+                        bit_count,
+                    );
+                }
+            }
+        }
+
+        tile.bit_positions = bit_positions;
+
+        Ok((x_index, y_index))
+    }
+
+    /// Reconstructs the pixels of a tile, and writes the parts covered by the region rectangles.
+    fn write_tile(
+        &mut self,
+        (x_index, y_index): (u16, u16),
+        region: &Region,
+        output: &mut ImageRegionMut<'_>,
+        updated_rectangles: &mut Vec<InclusiveRectangle>,
+    ) -> Result<(), ProgressiveError> {
+        let Some(tile) = self.tiles.get(&(x_index, y_index)) else {
+            return Ok(());
+        };
+
+        let buffers = &mut self.buffers;
+
+        for (component, coefficients) in buffers.components.iter_mut().zip(&tile.coefficients) {
+            component.copy_from_slice(coefficients);
+
+            if region.extrapolate {
+                extrapolated_dwt::decode(component, &mut buffers.temp);
+            } else {
+                dwt::decode(component, &mut buffers.temp);
+            }
+        }
+
+        let [y, cb, cr] = &buffers.components;
+        color_conversion::ycbcr_to_rgba(YCbCrBuffer { y, cb, cr }, &mut buffers.pixels)?;
+
+        // Tiles located past the u16 range are entirely out of any region rectangle.
+        let (Some(tile_left), Some(tile_top)) = (
+            tile_coordinate(output.region.left, x_index),
+            tile_coordinate(output.region.top, y_index),
+        ) else {
+            return Ok(());
+        };
+
+        let tile_rectangle = InclusiveRectangle {
+            left: tile_left,
+            top: tile_top,
+            right: tile_left.saturating_add(TILE_SIZE as u16 - 1),
+            bottom: tile_top.saturating_add(TILE_SIZE as u16 - 1),
+        };
+
+        for rectangle in &region.rectangles {
+            let rectangle = InclusiveRectangle {
+                left: rectangle.left.saturating_add(output.region.left),
+                top: rectangle.top.saturating_add(output.region.top),
+                right: rectangle.right.saturating_add(output.region.left),
+                bottom: rectangle.bottom.saturating_add(output.region.top),
+            };
+
+            let Some(update_rectangle) = tile_rectangle
+                .intersect(&rectangle)
+                .and_then(|rectangle| rectangle.intersect(&output.region))
+            else {
+                continue;
+            };
+
+            let source = ImageRegion {
+                region: InclusiveRectangle {
+                    left: update_rectangle.left - tile_left,
+                    top: update_rectangle.top - tile_top,
+                    right: update_rectangle.right - tile_left,
+                    bottom: update_rectangle.bottom - tile_top,
+                },
+                step: (TILE_SIZE * usize::from(TILE_PIXEL_FORMAT.bytes_per_pixel())) as u16,
+                pixel_format: TILE_PIXEL_FORMAT,
+                data: &buffers.pixels,
+            };
+
+            source.copy_to(&mut ImageRegionMut {
+                region: update_rectangle.clone(),
+                step: output.step,
+                pixel_format: output.pixel_format,
+                data: output.data,
+            })?;
+
+            updated_rectangles.push(update_rectangle);
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Decoding state of a tile, refined by each pass.
+struct Tile {
+    /// Dequantized DWT coefficients of the Y, Cb and Cr components.
+    coefficients: [Vec<i16>; 3],
+    /// Coefficients as entropy decoded by the first pass, or by the upgrade pass which made them
+    /// non-zero. Only their sign is relevant to the next upgrade passes.
+    signs: [Vec<i16>; 3],
+    /// Bit positions reached so far by each subband of each component.
+    bit_positions: [SubbandValues; 3],
+}
+
+impl Tile {
+    fn new() -> Self {
+        Self {
+            coefficients: [(); 3].map(|_| vec![0; TILE_PIXEL_COUNT]),
+            signs: [(); 3].map(|_| vec![0; TILE_PIXEL_COUNT]),
+            bit_positions: [[0; SUBBAND_COUNT]; 3],
+        }
+    }
+}
+
+/// Working buffers, reused from one tile to the next.
+struct Buffers {
+    components: [Vec<i16>; 3],
+    temp: Vec<i16>,
+    pixels: Vec<u8>,
+}
+
+impl Buffers {
+    fn new() -> Self {
+        Self {
+            components: [(); 3].map(|_| vec![0; TILE_PIXEL_COUNT]),
+            temp: vec![0; TILE_PIXEL_COUNT],
+            pixels: vec![0; TILE_PIXEL_COUNT * usize::from(TILE_PIXEL_FORMAT.bytes_per_pixel())],
+        }
+    }
+}
+
+/// RFX_PROGRESSIVE_REGION
+struct Region {
+    rectangles: Vec<InclusiveRectangle>,
+    quants: Vec<Quant>,
+    /// Progressive quantization values of the Y, Cb and Cr components, for each quality level.
+    progressive_quants: Vec<[Quant; 3]>,
+    extrapolate: bool,
+}
+
+impl Region {
+    fn subbands(&self) -> &'static [Range<usize>; SUBBAND_COUNT] {
+        if self.extrapolate {
+            &EXTRAPOLATED_SUBBANDS
+        } else {
+            &SUBBANDS
+        }
+    }
+
+    /// Returns the bit positions of a tile pass, that is the sum of the quantization and
+    /// progressive quantization values.
+    fn bit_positions(&self, quant_indices: [u8; 3], quality: u8) -> Result<[SubbandValues; 3], ProgressiveError> {
+        let progressive_quants = if quality == FULL_QUALITY {
+            None
+        } else {
+            let progressive_quants = self
+                .progressive_quants
+                .get(usize::from(quality))
+                .ok_or(ProgressiveError::InvalidQuality(quality))?;
+
+            Some(progressive_quants)
+        };
+
+        let mut bit_positions = [[0; SUBBAND_COUNT]; 3];
+
+        for (component, quant_index) in quant_indices.into_iter().enumerate() {
+            let quant = self
+                .quants
+                .get(usize::from(quant_index))
+                .ok_or(ProgressiveError::InvalidQuantIndex(quant_index))?;
+
+            bit_positions[component] = subband_values(quant);
+
+            if let Some(progressive_quants) = progressive_quants {
+                let progressive_values = subband_values(&progressive_quants[component]);
+
+                for (bit_position, progressive_value) in bit_positions[component].iter_mut().zip(progressive_values) {
+                    *bit_position += progressive_value;
+                }
+            }
+        }
+
+        Ok(bit_positions)
+    }
+}
+
+fn subband_values(quant: &Quant) -> SubbandValues {
+    [
+        quant.hl1, quant.lh1, quant.hh1, quant.hl2, quant.lh2, quant.hh2, quant.hl3, quant.lh3, quant.hh3, quant.ll3,
+    ]
+}
+
+fn dequantize(values: &mut [i16], bit_position: u8) {
+    let shift = bit_position.saturating_sub(1);
+
+    for value in values {
+        *value = (i32::from(*value) << shift) as i16;
+    }
+}
+
+/// Returns the coordinate of a tile, offset by `origin`.
+fn tile_coordinate(origin: u16, index: u16) -> Option<u16> {
+    u16::try_from(u32::from(origin) + u32::from(index) * TILE_SIZE as u32).ok()
+}
+
+/// Reads a block header, and returns the block type with the block data.
+fn read_block<'a>(src: &mut ReadCursor<'a>) -> Result<(u16, ReadCursor<'a>), ProgressiveError> {
+    if src.len() < BLOCK_HEADER_SIZE {
+        return Err(ProgressiveError::NotEnoughData);
+    }
+    let block_type = src.read_u16();
+    let block_length = src.read_u32() as usize;
+
+    let data_length = block_length
+        .checked_sub(BLOCK_HEADER_SIZE)
+        .ok_or(ProgressiveError::InvalidBlockLength)?;
+
+    if src.len() < data_length {
+        return Err(ProgressiveError::NotEnoughData);
+    }
+
+    Ok((block_type, ReadCursor::new(src.read_slice(data_length))))
+}
+
+#[derive(Debug, Error)]
+pub enum ProgressiveError {
+    #[error("IO error: {0}")]
+    IOError(#[from] io::Error),
+    #[error("failed to decode quantization values: {0}")]
+    Decode(#[from] DecodeError),
+    #[error("RLGR decoding failed: {0}")]
+    Rlgr(#[from] RlgrError),
+    #[error("not enough data")]
+    NotEnoughData,
+    #[error("invalid block length")]
+    InvalidBlockLength,
+    #[error("unexpected block (type {0:#06x})")]
+    UnexpectedBlock(u16),
+    #[error("invalid sync block")]
+    InvalidSync,
+    #[error("unsupported tile size ({0})")]
+    InvalidTileSize(u16),
+    #[error("invalid quantization index ({0})")]
+    InvalidQuantIndex(u8),
+    #[error("invalid quality ({0})")]
+    InvalidQuality(u8),
+    #[error("quality ({0}) is lower than the one of the previous pass")]
+    InvalidUpgradeQuality(u8),
+    #[error("upgrade of a tile which was never received ({x_index}, {y_index})")]
+    MissingTile { x_index: u16, y_index: u16 },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT_SIZE: u16 = 64;
+
+    fn block(block_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut block = block_type.to_le_bytes().to_vec();
+        block.extend_from_slice(&((BLOCK_HEADER_SIZE + data.len()) as u32).to_le_bytes());
+        block.extend_from_slice(data);
+        block
+    }
+
+    /// Region covering the first tile, with all quantization values set to 1, and a single
+    /// quality level where only the Y component is quantized further.
+    fn region(flags: RegionFlags, tiles: &[Vec<u8>]) -> Vec<u8> {
+        let tile_data = tiles.concat();
+
+        let mut data = vec![TILE_SIZE as u8];
+        data.extend_from_slice(&1u16.to_le_bytes()); // numRects
+        data.extend_from_slice(&[1, 1, flags.bits()]); // numQuant, numProgQuant, flags
+        data.extend_from_slice(&(tiles.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(tile_data.len() as u32).to_le_bytes());
+        data.extend_from_slice(&[0, 0, 0, 0, 64, 0, 64, 0]); // x, y, width, height
+        data.extend_from_slice(&[0x11; QUANT_SIZE]);
+        data.push(0); // quality
+        data.extend_from_slice(&[0x66; QUANT_SIZE]);
+        data.extend_from_slice(&[0x00; 2 * QUANT_SIZE]);
+        data.extend_from_slice(&tile_data);
+
+        block(WBT_REGION, &data)
+    }
+
+    /// Tile whose LL3 subband coefficients are all 63.
+    fn tile_simple(subbands: &[Range<usize>; SUBBAND_COUNT]) -> Vec<u8> {
+        let mut coefficients = vec![0; TILE_PIXEL_COUNT];
+        // LL3 is differentially encoded.
+        coefficients[subbands[LL3_INDEX].start] = 63;
+
+        let mut y_data = vec![0; 64];
+        let y_length = rlgr::encode(EntropyAlgorithm::Rlgr1, &coefficients, &mut y_data).unwrap();
+        y_data.truncate(y_length);
+
+        let mut data = vec![0, 0, 0, 0, 0, 0, 0, 0]; // quantIdx, xIdx, yIdx, flags
+        data.extend_from_slice(&(y_length as u16).to_le_bytes());
+        data.extend_from_slice(&[0; 6]); // cbLen, crLen, tailLen
+        data.extend_from_slice(&y_data);
+
+        block(WBT_TILE_SIMPLE, &data)
+    }
+
+    /// Tile at the first quality level, with zero coefficients.
+    fn tile_first() -> Vec<u8> {
+        let mut data = vec![0, 0, 0, 0, 0, 0, 0, 0, 0]; // quantIdx, xIdx, yIdx, flags, quality
+        data.extend_from_slice(&[0; 8]); // yLen, cbLen, crLen, tailLen
+
+        block(WBT_TILE_FIRST, &data)
+    }
+
+    /// Upgrades a tile to full quality, setting the 6 low bits of every LL3 coefficient.
+    fn tile_upgrade(quality: u8) -> Vec<u8> {
+        let mut data = vec![0, 0, 0, 0, 0, 0, 0, quality]; // quantIdx, xIdx, yIdx, quality
+        data.extend_from_slice(&[0, 0, 64, 0]); // ySrlLen, yRawLen
+        data.extend_from_slice(&[0; 8]); // cbSrlLen, cbRawLen, crSrlLen, crRawLen
+        data.extend_from_slice(&[0xFF; 64]);
+
+        block(WBT_TILE_UPGRADE, &data)
+    }
+
+    fn decode(decoder: &mut Decoder, input: &[u8]) -> Result<(Vec<u8>, Vec<InclusiveRectangle>), ProgressiveError> {
+        let mut data = vec![0; usize::from(OUTPUT_SIZE) * usize::from(OUTPUT_SIZE) * 4];
+
+        let updated_rectangles = decoder.decode(
+            input,
+            &mut ImageRegionMut {
+                region: InclusiveRectangle {
+                    left: 0,
+                    top: 0,
+                    right: OUTPUT_SIZE - 1,
+                    bottom: OUTPUT_SIZE - 1,
+                },
+                step: OUTPUT_SIZE * 4,
+                pixel_format: PixelFormat::RgbA32,
+                data: &mut data,
+            },
+        )?;
+
+        Ok((data, updated_rectangles))
+    }
+
+    fn check_upgrade_matches_simple_tile(flags: RegionFlags) {
+        let subbands = if flags.contains(RegionFlags::DWT_REDUCE_EXTRAPOLATE) {
+            &EXTRAPOLATED_SUBBANDS
+        } else {
+            &SUBBANDS
+        };
+
+        let (expected, _) = decode(&mut Decoder::new(), &region(flags, &[tile_simple(subbands)])).unwrap();
+
+        let mut decoder = Decoder::new();
+
+        let (first, updated_rectangles) = decode(&mut decoder, &region(flags, &[tile_first()])).unwrap();
+        assert_eq!(
+            updated_rectangles,
+            [InclusiveRectangle {
+                left: 0,
+                top: 0,
+                right: 63,
+                bottom: 63,
+            }]
+        );
+        assert_ne!(first, expected);
+
+        let (upgraded, _) = decode(&mut decoder, &region(flags, &[tile_upgrade(FULL_QUALITY)])).unwrap();
+        assert_eq!(upgraded, expected);
+    }
+
+    #[test]
+    fn upgrade_matches_simple_tile() {
+        check_upgrade_matches_simple_tile(RegionFlags::empty());
+    }
+
+    #[test]
+    fn extrapolated_upgrade_matches_simple_tile() {
+        check_upgrade_matches_simple_tile(RegionFlags::DWT_REDUCE_EXTRAPOLATE);
+    }
+
+    #[test]
+    fn stream_blocks_are_accepted() {
+        let mut input = block(WBT_SYNC, &[0xCA, 0xAC, 0xCC, 0xCA, 0x00, 0x01]);
+        input.extend(block(WBT_CONTEXT, &[0x00, 0x40, 0x00, 0x00]));
+        input.extend(block(WBT_FRAME_BEGIN, &[0, 0, 0, 0, 1, 0]));
+        input.extend(block(WBT_FRAME_END, &[]));
+
+        let (_, updated_rectangles) = decode(&mut Decoder::new(), &input).unwrap();
+        assert!(updated_rectangles.is_empty());
+    }
+
+    #[test]
+    fn upgrade_of_missing_tile_is_rejected() {
+        let error = decode(
+            &mut Decoder::new(),
+            &region(RegionFlags::empty(), &[tile_upgrade(FULL_QUALITY)]),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            ProgressiveError::MissingTile { x_index: 0, y_index: 0 }
+        ));
+    }
+
+    #[test]
+    fn upgrade_to_lower_quality_is_rejected() {
+        let mut decoder = Decoder::new();
+        decode(&mut decoder, &region(RegionFlags::empty(), &[tile_simple(&SUBBANDS)])).unwrap();
+
+        let error = decode(&mut decoder, &region(RegionFlags::empty(), &[tile_upgrade(0)])).unwrap_err();
+        assert!(matches!(error, ProgressiveError::InvalidUpgradeQuality(0)));
+    }
+
+    #[test]
+    fn invalid_blocks_are_rejected() {
+        let error = decode(&mut Decoder::new(), &block(WBT_CONTEXT, &[0x00, 0x20, 0x00, 0x00])).unwrap_err();
+        assert!(matches!(error, ProgressiveError::InvalidTileSize(32)));
+
+        let error = decode(&mut Decoder::new(), &block(WBT_TILE_SIMPLE, &[])).unwrap_err();
+        assert!(matches!(error, ProgressiveError::UnexpectedBlock(WBT_TILE_SIMPLE)));
+
+        let error = decode(&mut Decoder::new(), &[0xC0, 0xCC, 0x02, 0x00, 0x00, 0x00]).unwrap_err();
+        assert!(matches!(error, ProgressiveError::InvalidBlockLength));
+    }
+}
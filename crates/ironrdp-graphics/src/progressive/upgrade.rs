@@ -0,0 +1,173 @@
+//! Upgrade passes decoding ([MS-RDPEGFX] 3.2.8.1.2.2)
+//!
+//! Each upgrade pass refines the coefficients of a tile with the bits located between the
+//! previous and the new bit positions. Coefficients which were zero so far are coded with the
+//! simplified run-length (SRL) encoding, while the bits of the other coefficients, and of the
+//! whole LL3 subband, are transmitted as is.
+//!
+//! [MS-RDPEGFX]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegfx/da5c75f9-cd99-450c-98c4-014a496942b0
+
+const KP_MAX: u32 = 80;
+const LS_GR: u32 = 3;
+const UP_GR: u32 = 4;
+const DN_GR: u32 = 6;
+
+pub(super) struct UpgradeState<'a> {
+    srl: BitReader<'a>,
+    raw: BitReader<'a>,
+    /// Adaptive parameter of the SRL encoding, scaled by `1 << LS_GR`.
+    kp: u32,
+    /// Zero coefficients left in the current run.
+    zero_count: u32,
+    /// Whether the next SRL value is a non-zero coefficient, following a run of zeroes.
+    in_magnitude: bool,
+}
+
+impl<'a> UpgradeState<'a> {
+    pub(super) fn new(srl_data: &'a [u8], raw_data: &'a [u8]) -> Self {
+        Self {
+            srl: BitReader::new(srl_data),
+            raw: BitReader::new(raw_data),
+            kp: 8,
+            zero_count: 0,
+            in_magnitude: false,
+        }
+    }
+
+    /// Upgrades the coefficients of a subband other than LL3.
+    ///
+    /// `signs` holds the coefficients as decoded by the first pass, and is updated with the
+    /// coefficients which become non-zero.
+    pub(super) fn upgrade_subband(&mut self, coefficients: &mut [i16], signs: &mut [i16], shift: u32, bit_count: u32) {
+        if bit_count == 0 {
+            return;
+        }
+
+        for (coefficient, sign) in coefficients.iter_mut().zip(signs.iter_mut()) {
+            let value = match (*sign).signum() {
+                1 => self.raw.read_bits(bit_count) as i32,
+                -1 => -(self.raw.read_bits(bit_count) as i32),
+                _ => {
+                    let value = self.read_srl_value(bit_count);
+                    *sign = value as i16;
+                    value
+                }
+            };
+
+            *coefficient = coefficient.wrapping_add((value << shift) as i16);
+        }
+    }
+
+    /// Upgrades the coefficients of the LL3 subband, which are never negative.
+    pub(super) fn upgrade_ll3_subband(&mut self, coefficients: &mut [i16], shift: u32, bit_count: u32) {
+        if bit_count == 0 {
+            return;
+        }
+
+        for coefficient in coefficients {
+            let value = self.raw.read_bits(bit_count) as i32;
+            *coefficient = coefficient.wrapping_add((value << shift) as i16);
+        }
+    }
+
+    fn read_srl_value(&mut self, bit_count: u32) -> i32 {
+        if self.zero_count > 0 {
+            self.zero_count -= 1;
+            return 0;
+        }
+
+        let k = self.kp >> LS_GR;
+
+        if !self.in_magnitude {
+            if self.srl.read_bit() {
+                // Less than 1 << k zeroes, followed by a non-zero value.
+                self.zero_count = self.srl.read_bits(k);
+                self.in_magnitude = true;
+
+                if self.zero_count > 0 {
+                    self.zero_count -= 1;
+                    return 0;
+                }
+            } else {
+                // A full run of 1 << k zeroes.
+                self.zero_count = (1 << k) - 1;
+                self.kp = (self.kp + UP_GR).min(KP_MAX);
+
+                return 0;
+            }
+        }
+
+        self.in_magnitude = false;
+
+        let is_negative = self.srl.read_bit();
+        self.kp = self.kp.saturating_sub(DN_GR);
+
+        // The magnitude is unary encoded, and cannot be zero.
+        let max_magnitude = (1 << bit_count) - 1;
+        let mut magnitude = 1;
+
+        while magnitude < max_magnitude {
+            if self.srl.read_bit() {
+                break;
+            }
+            magnitude += 1;
+        }
+
+        if is_negative {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+}
+
+/// Most significant bit first reader, reading zeroes past the end of the data.
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+
+    fn read_bit(&mut self) -> bool {
+        let byte = self.data.get(self.position / 8).copied().unwrap_or(0);
+        let bit = (byte >> (7 - self.position % 8)) & 1;
+        self.position += 1;
+
+        bit == 1
+    }
+
+    fn read_bits(&mut self, count: u32) -> u32 {
+        (0..count).fold(0, |value, _| (value << 1) | u32::from(self.read_bit()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srl_values() {
+        // A full run of two zeroes, then -1 and 2 without any zero before them.
+        let mut state = UpgradeState::new(&[0b0101_1100, 0b1000_0000], &[]);
+
+        let values: Vec<_> = (0..4).map(|_| state.read_srl_value(2)).collect();
+
+        assert_eq!(values, [0, 0, -1, 2]);
+    }
+
+    #[test]
+    fn raw_bits_follow_the_sign() {
+        let mut state = UpgradeState::new(&[], &[0b1011_0000]);
+        let mut coefficients = [8, -8, 0];
+        let mut signs = [1, -1, 0];
+
+        state.upgrade_subband(&mut coefficients, &mut signs, 1, 2);
+
+        // The zero coefficient reads a zero run from the empty SRL data.
+        assert_eq!(coefficients, [8 + (0b10 << 1), -8 - (0b11 << 1), 0]);
+    }
+}
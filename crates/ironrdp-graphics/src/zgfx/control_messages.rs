@@ -42,6 +42,41 @@ impl<'a> SegmentedDataPdu<'a> {
             }
         }
     }
+
+    /// Sizes are expected to have been checked by the caller.
+    pub(crate) fn encode(&self, output: &mut Vec<u8>) {
+        match self {
+            SegmentedDataPdu::Single(segment) => {
+                output.push(SegmentedDescriptor::Single as u8);
+                segment.encode(output);
+            }
+            SegmentedDataPdu::Multipart {
+                uncompressed_size,
+                segments,
+            } => {
+                output.push(SegmentedDescriptor::Multipart as u8);
+                output.extend_from_slice(
+                    &u16::try_from(segments.len())
+                        .expect("segment count fits in u16")
+                        .to_le_bytes(),
+                );
+                output.extend_from_slice(
+                    &u32::try_from(*uncompressed_size)
+                        .expect("uncompressed size fits in u32")
+                        .to_le_bytes(),
+                );
+
+                for segment in segments {
+                    output.extend_from_slice(
+                        &u32::try_from(segment.size())
+                            .expect("segment size fits in u32")
+                            .to_le_bytes(),
+                    );
+                    segment.encode(output);
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -62,6 +97,15 @@ impl<'a> BulkEncodedData<'a> {
             data: buffer,
         })
     }
+
+    fn size(&self) -> usize {
+        1 /* header */ + self.data.len()
+    }
+
+    fn encode(&self, output: &mut Vec<u8>) {
+        output.push(CompressionType::Rdp8 as u8 | (self.compression_flags.bits() << 4));
+        output.extend_from_slice(self.data);
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, FromPrimitive)]
@@ -154,4 +198,20 @@ mod test {
             SegmentedDataPdu::from_buffer(buffer).unwrap()
         );
     }
+
+    #[test]
+    fn encode_correctly_serializes_zgfx_single_segmented_data_pdu() {
+        let mut buffer = Vec::new();
+        SINGLE_SEGMENTED_DATA_PDU.encode(&mut buffer);
+
+        assert_eq!(SINGLE_SEGMENTED_DATA_PDU_BUFFER.as_ref(), buffer.as_slice());
+    }
+
+    #[test]
+    fn encode_correctly_serializes_zgfx_multipart_segmented_data_pdu() {
+        let mut buffer = Vec::new();
+        MULTIPART_SEGMENTED_DATA_PDU.encode(&mut buffer);
+
+        assert_eq!(MULTIPART_SEGMENTED_DATA_PDU_BUFFER.as_ref(), buffer.as_slice());
+    }
 }
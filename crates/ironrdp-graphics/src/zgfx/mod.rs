@@ -18,6 +18,9 @@ use crate::utils::Bits;
 
 const HISTORY_SIZE: usize = 2_500_000;
 
+/// Maximum size of the data of a segment, before compression
+const MAX_SEGMENT_SIZE: usize = 65_535;
+
 pub struct Decompressor {
     history: FixedCircularBuffer,
 }
@@ -124,6 +127,35 @@ impl Default for Decompressor {
     }
 }
 
+/// Wraps `input` into an RDP_SEGMENTED_DATA structure, without compressing it
+///
+/// The data is split into several segments when it does not fit into a single one.
+pub fn wrap_uncompressed(input: &[u8], output: &mut Vec<u8>) -> Result<(), ZgfxError> {
+    let segment = |data| BulkEncodedData {
+        compression_flags: CompressionFlags::empty(),
+        data,
+    };
+
+    let segmented_data = if input.len() <= MAX_SEGMENT_SIZE {
+        SegmentedDataPdu::Single(segment(input))
+    } else {
+        let segment_count = input.len().div_ceil(MAX_SEGMENT_SIZE);
+
+        if u16::try_from(segment_count).is_err() || u32::try_from(input.len()).is_err() {
+            return Err(ZgfxError::DataTooLarge { size: input.len() });
+        }
+
+        SegmentedDataPdu::Multipart {
+            uncompressed_size: input.len(),
+            segments: input.chunks(MAX_SEGMENT_SIZE).map(segment).collect(),
+        }
+    };
+
+    segmented_data.encode(output);
+
+    Ok(())
+}
+
 fn handle_match(
     bits: &mut Bits<'_>,
     distance_value_size: usize,
@@ -445,6 +477,8 @@ pub enum ZgfxError {
     },
     #[error("token bits not found")]
     TokenBitsNotFound,
+    #[error("data is too large to be segmented ({} bytes)", size)]
+    DataTooLarge { size: usize },
 }
 
 #[cfg(test)]
@@ -629,4 +663,19 @@ mod tests {
         zgfx.decompress_segment(buffer.as_ref(), &mut decompressed).unwrap();
         assert_eq!(decompressed, expected);
     }
+
+    #[test]
+    fn zgfx_wraps_uncompressed_data_into_segments() {
+        for size in [0, 16, MAX_SEGMENT_SIZE, 2 * MAX_SEGMENT_SIZE + 1] {
+            let data: Vec<u8> = (0..size).map(|i| u8::try_from(i % 251).unwrap()).collect();
+
+            let mut wrapped = Vec::new();
+            wrap_uncompressed(&data, &mut wrapped).unwrap();
+
+            let mut zgfx = Decompressor::new();
+            let mut decompressed = Vec::new();
+            zgfx.decompress(&wrapped, &mut decompressed).unwrap();
+            assert_eq!(decompressed, data, "Failed to unwrap {size} bytes");
+        }
+    }
 }
@@ -11,6 +11,7 @@ use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive, ToPrimitive};
 
 use super::bitmap::BitmapUpdateData;
+use super::orders::OrdersUpdate;
 use super::pointer::PointerUpdateData;
 use super::surface_commands::{SurfaceCommand, SURFACE_COMMAND_HEADER_SIZE};
 use crate::per;
@@ -138,11 +139,13 @@ impl Encode for FastPathUpdatePdu<'_> {
         let mut header = 0u8;
         header.set_bits(0..4, self.update_code.to_u8().unwrap());
         header.set_bits(4..6, self.fragmentation.to_u8().unwrap());
+        if self.compression_flags.is_some() {
+            header.set_bits(6..8, Compression::COMPRESSION_USED.bits());
+        }
 
         dst.write_u8(header);
 
         if self.compression_flags.is_some() {
-            header.set_bits(6..8, Compression::COMPRESSION_USED.bits());
             let compression_flags_with_type = self.compression_flags.map(|f| f.bits()).unwrap_or(0)
                 | self.compression_type.and_then(|f| f.to_u8()).unwrap_or(0);
             dst.write_u8(compression_flags_with_type);
@@ -220,6 +223,7 @@ pub enum FastPathUpdate<'a> {
     SurfaceCommands(Vec<SurfaceCommand<'a>>),
     Bitmap(BitmapUpdateData<'a>),
     Pointer(PointerUpdateData<'a>),
+    Orders(OrdersUpdate<'a>),
 }
 
 impl<'a> FastPathUpdate<'a> {
@@ -251,6 +255,7 @@ impl<'a> FastPathUpdate<'a> {
             UpdateCode::CachedPointer => Ok(Self::Pointer(PointerUpdateData::Cached(decode_cursor(src)?))),
             UpdateCode::NewPointer => Ok(Self::Pointer(PointerUpdateData::New(decode_cursor(src)?))),
             UpdateCode::LargePointer => Ok(Self::Pointer(PointerUpdateData::Large(decode_cursor(src)?))),
+            UpdateCode::Orders => Ok(Self::Orders(decode_cursor(src)?)),
             _ => Err(invalid_field_err!("updateCode", "Invalid fast path update code")),
         }
     }
@@ -260,6 +265,7 @@ impl<'a> FastPathUpdate<'a> {
             Self::SurfaceCommands(_) => "Surface Commands",
             Self::Bitmap(_) => "Bitmap",
             Self::Pointer(_) => "Pointer",
+            Self::Orders(_) => "Orders",
         }
     }
 }
@@ -286,6 +292,9 @@ impl Encode for FastPathUpdate<'_> {
                 PointerUpdateData::New(inner) => inner.encode(dst)?,
                 PointerUpdateData::Large(inner) => inner.encode(dst)?,
             },
+            Self::Orders(orders) => {
+                orders.encode(dst)?;
+            }
         }
 
         Ok(())
@@ -308,6 +317,7 @@ impl Encode for FastPathUpdate<'_> {
                 PointerUpdateData::New(inner) => inner.size(),
                 PointerUpdateData::Large(inner) => inner.size(),
             },
+            Self::Orders(orders) => orders.size(),
         }
     }
 }
@@ -333,6 +343,7 @@ impl From<&FastPathUpdate<'_>> for UpdateCode {
         match update {
             FastPathUpdate::SurfaceCommands(_) => Self::SurfaceCommands,
             FastPathUpdate::Bitmap(_) => Self::Bitmap,
+            FastPathUpdate::Orders(_) => Self::Orders,
             FastPathUpdate::Pointer(action) => match action {
                 PointerUpdateData::SetHidden => Self::HiddenPointer,
                 PointerUpdateData::SetDefault => Self::DefaultPointer,
@@ -0,0 +1,324 @@
+//! Drawing orders ([MS-RDPEGDI] 2.2.2.2)
+//!
+//! Primary drawing orders only carry the fields which changed since the previous order of the
+//! same type, and the secondary orders depend on the negotiated capabilities. Orders must
+//! therefore be decoded in sequence by the same [`OrderDecoder`] for the whole session.
+//!
+//! [MS-RDPEGDI]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegdi/745f2eee-d110-464c-8aca-06fc1814f6ad
+
+#[cfg(test)]
+mod tests;
+
+mod alternate_secondary;
+mod primary;
+mod secondary;
+
+use bitflags::bitflags;
+use ironrdp_core::{
+    ensure_fixed_part_size, ensure_size, invalid_field_err, Decode, DecodeResult, Encode, EncodeResult, ReadCursor,
+    WriteCursor,
+};
+
+pub use self::alternate_secondary::*;
+pub use self::primary::*;
+pub use self::secondary::*;
+use crate::rdp::capability_sets::GlyphSupportLevel;
+
+bitflags! {
+    /// TS_*_ORDER control flags
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    struct ControlFlags: u8 {
+        const STANDARD = 0x01;
+        const SECONDARY = 0x02;
+        const BOUNDS = 0x04;
+        const TYPE_CHANGE = 0x08;
+        const DELTA_COORDINATES = 0x10;
+        const ZERO_BOUNDS_DELTAS = 0x20;
+        const ZERO_FIELD_BYTE_BIT0 = 0x40;
+        const ZERO_FIELD_BYTE_BIT1 = 0x80;
+    }
+}
+
+/// Orders part of the TS_FP_UPDATE_ORDERS structure
+///
+/// The orders are kept encoded, to be decoded by an [`OrderDecoder`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrdersUpdate<'a> {
+    pub number_orders: u16,
+    pub order_data: &'a [u8],
+}
+
+impl OrdersUpdate<'_> {
+    const NAME: &'static str = "TS_FP_UPDATE_ORDERS";
+
+    const FIXED_PART_SIZE: usize = 2 /* numberOrders */;
+}
+
+impl Encode for OrdersUpdate<'_> {
+    fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+
+        dst.write_u16(self.number_orders);
+        dst.write_slice(self.order_data);
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn size(&self) -> usize {
+        Self::FIXED_PART_SIZE + self.order_data.len()
+    }
+}
+
+impl<'de> Decode<'de> for OrdersUpdate<'de> {
+    fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+
+        let number_orders = src.read_u16();
+        let order_data = src.read_slice(src.len());
+
+        Ok(Self {
+            number_orders,
+            order_data,
+        })
+    }
+}
+
+/// A decoded drawing order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DrawingOrder {
+    Primary {
+        order: PrimaryOrder,
+        /// Clipping rectangle of the order, if any
+        bounds: Option<Bounds>,
+    },
+    Secondary(SecondaryOrder),
+    AlternateSecondary(AlternateSecondaryOrder),
+}
+
+/// Decoding state of the drawing orders, shared by all the orders of a session.
+#[derive(Debug, Clone)]
+pub struct OrderDecoder {
+    primary: PrimaryOrderState,
+    glyph_support_level: GlyphSupportLevel,
+}
+
+impl OrderDecoder {
+    pub fn new() -> Self {
+        Self {
+            primary: PrimaryOrderState::default(),
+            glyph_support_level: GlyphSupportLevel::Full,
+        }
+    }
+
+    /// Sets the glyph support level advertised in the Glyph Cache capability set.
+    ///
+    /// Glyphs are cached by revision 2 orders when the level is [`GlyphSupportLevel::Encode`], and
+    /// by revision 1 orders otherwise.
+    pub fn set_glyph_support_level(&mut self, glyph_support_level: GlyphSupportLevel) {
+        self.glyph_support_level = glyph_support_level;
+    }
+
+    /// Resets the primary orders state, as required when the connection is reactivated.
+    pub fn reset(&mut self) {
+        self.primary = PrimaryOrderState::default();
+    }
+
+    /// Decodes the next order of `src`.
+    pub fn decode_order(&mut self, src: &mut ReadCursor<'_>) -> DecodeResult<DrawingOrder> {
+        ensure_size!(in: src, size: 1);
+        let control_flags = ControlFlags::from_bits_retain(src.read_u8());
+
+        if !control_flags.contains(ControlFlags::STANDARD) {
+            if control_flags.contains(ControlFlags::SECONDARY) {
+                let order_type = control_flags.bits() >> 2;
+                let order = AlternateSecondaryOrder::decode_with_type(src, order_type)?;
+
+                Ok(DrawingOrder::AlternateSecondary(order))
+            } else {
+                Err(invalid_field_err!(
+                    "controlFlags",
+                    "neither standard nor secondary order"
+                ))
+            }
+        } else if control_flags.contains(ControlFlags::SECONDARY) {
+            let order = SecondaryOrder::decode_with_glyph_support_level(src, self.glyph_support_level)?;
+
+            Ok(DrawingOrder::Secondary(order))
+        } else {
+            let (order, bounds) = self.primary.decode(src, control_flags)?;
+
+            Ok(DrawingOrder::Primary { order, bounds })
+        }
+    }
+}
+
+impl Default for OrderDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// TS_COLOR
+///
+/// With color depths lower than 24 bpp, the color is a palette index or a packed RGB value
+/// stored in the lowest bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+    const SIZE: usize = 3;
+
+    fn read(src: &mut ReadCursor<'_>) -> Self {
+        let [red, green, blue] = src.read_array();
+
+        Self { red, green, blue }
+    }
+}
+
+/// TS_BRUSH
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Brush {
+    /// Horizontal origin of the brush
+    pub x: u8,
+    /// Vertical origin of the brush
+    pub y: u8,
+    pub style: u8,
+    /// Hatch style of hatched brushes, first row of pattern brushes or index of cached brushes
+    pub hatch: u8,
+    /// Remaining rows of pattern brushes, from the last to the second one
+    pub extra: [u8; 7],
+}
+
+impl Brush {
+    pub const STYLE_SOLID: u8 = 0x00;
+    pub const STYLE_NULL: u8 = 0x01;
+    pub const STYLE_HATCHED: u8 = 0x02;
+    pub const STYLE_PATTERN: u8 = 0x03;
+    /// Flag set on the style of cached brushes, along with the bitmap format of the brush.
+    pub const CACHED_BRUSH: u8 = 0x80;
+
+    /// Returns the rows of a 1 bpp pattern brush, from top to bottom.
+    pub fn pattern(&self) -> [u8; 8] {
+        let mut pattern = [self.hatch, 0, 0, 0, 0, 0, 0, 0];
+
+        for (row, byte) in pattern[1..].iter_mut().zip(self.extra.iter().rev()) {
+            *row = *byte;
+        }
+
+        pattern
+    }
+}
+
+/// Inclusive bounding rectangle of primary drawing orders
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Bounds {
+    pub left: i16,
+    pub top: i16,
+    pub right: i16,
+    pub bottom: i16,
+}
+
+/// Glyph bitmap of the glyph cache orders (TS_CACHE_GLYPH_DATA) and of the fast glyph order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Glyph {
+    /// Horizontal offset of the bitmap from the glyph origin
+    pub x: i16,
+    /// Vertical offset of the bitmap from the glyph origin
+    pub y: i16,
+    pub width: u16,
+    pub height: u16,
+    /// 1 bpp bitmap, with byte-aligned rows stored from top to bottom
+    pub bitmap: Vec<u8>,
+}
+
+impl Glyph {
+    /// Size of the bitmap of a glyph, padded to a multiple of 4 bytes.
+    fn bitmap_size(width: u16, height: u16) -> usize {
+        let size = usize::from(width).div_ceil(8) * usize::from(height);
+        size.next_multiple_of(4)
+    }
+}
+
+bitflags! {
+    /// TS_TEXT flAccel flags
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct GlyphFlags: u8 {
+        const SO_FLAG_DEFAULT_PLACEMENT = 0x01;
+        const SO_HORIZONTAL = 0x02;
+        const SO_VERTICAL = 0x04;
+        const SO_REVERSED = 0x08;
+        const SO_ZERO_BEARINGS = 0x10;
+        const SO_CHAR_INC_EQUAL_BM_BASE = 0x20;
+        const SO_MAXEXT_EQUAL_BM_SIDE = 0x40;
+    }
+}
+
+impl Default for GlyphFlags {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+/// Reads a TS_2BYTE_SIGNED_ENCODING value.
+fn read_2_byte_signed(src: &mut ReadCursor<'_>) -> DecodeResult<i16> {
+    ensure_size!(in: src, size: 1);
+    let first = src.read_u8();
+
+    let mut value = i16::from(first & 0x3F);
+    if first & 0x80 != 0 {
+        ensure_size!(in: src, size: 1);
+        value = (value << 8) | i16::from(src.read_u8());
+    }
+
+    Ok(if first & 0x40 != 0 { -value } else { value })
+}
+
+/// Reads a TS_2BYTE_UNSIGNED_ENCODING value.
+fn read_2_byte_unsigned(src: &mut ReadCursor<'_>) -> DecodeResult<u16> {
+    ensure_size!(in: src, size: 1);
+    let first = src.read_u8();
+
+    let mut value = u16::from(first & 0x7F);
+    if first & 0x80 != 0 {
+        ensure_size!(in: src, size: 1);
+        value = (value << 8) | u16::from(src.read_u8());
+    }
+
+    Ok(value)
+}
+
+/// Reads a TS_4BYTE_UNSIGNED_ENCODING value.
+fn read_4_byte_unsigned(src: &mut ReadCursor<'_>) -> DecodeResult<u32> {
+    ensure_size!(in: src, size: 1);
+    let first = src.read_u8();
+
+    let additional_bytes = usize::from(first >> 6);
+    ensure_size!(in: src, size: additional_bytes);
+
+    let mut value = u32::from(first & 0x3F);
+    for _ in 0..additional_bytes {
+        value = (value << 8) | u32::from(src.read_u8());
+    }
+
+    Ok(value)
+}
+
+/// Returns the bits per pixel of a brush bitmap format (BMF_*).
+fn brush_bpp(bitmap_format: u8) -> Option<u8> {
+    match bitmap_format {
+        0x01 => Some(1),
+        0x03 => Some(8),
+        0x04 => Some(16),
+        0x05 => Some(24),
+        0x06 => Some(32),
+        _ => None,
+    }
+}
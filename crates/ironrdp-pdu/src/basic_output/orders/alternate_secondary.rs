@@ -0,0 +1,101 @@
+use ironrdp_core::{ensure_size, unsupported_value_err, DecodeResult, ReadCursor};
+
+const SWITCH_SURFACE_ORDER_TYPE: u8 = 0x00;
+const CREATE_OFFSCREEN_BITMAP_ORDER_TYPE: u8 = 0x01;
+const FRAME_MARKER_ORDER_TYPE: u8 = 0x0D;
+
+/// Identifier of the primary drawing surface in the Switch Surface order
+pub const SCREEN_BITMAP_SURFACE: u16 = 0xFFFF;
+
+/// Alternate secondary drawing order ([MS-RDPEGDI] 2.2.2.2.1.3)
+///
+/// [MS-RDPEGDI]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegdi/745f2eee-d110-464c-8aca-06fc1814f6ad
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlternateSecondaryOrder {
+    SwitchSurface { bitmap_id: u16 },
+    CreateOffscreenBitmap(CreateOffscreenBitmap),
+    FrameMarker(FrameMarker),
+}
+
+impl AlternateSecondaryOrder {
+    /// Decodes an alternate secondary drawing order, once its control flags have been read.
+    pub(super) fn decode_with_type(src: &mut ReadCursor<'_>, order_type: u8) -> DecodeResult<Self> {
+        match order_type {
+            SWITCH_SURFACE_ORDER_TYPE => {
+                ensure_size!(in: src, size: 2);
+
+                Ok(Self::SwitchSurface {
+                    bitmap_id: src.read_u16(),
+                })
+            }
+            CREATE_OFFSCREEN_BITMAP_ORDER_TYPE => Ok(Self::CreateOffscreenBitmap(CreateOffscreenBitmap::decode(src)?)),
+            FRAME_MARKER_ORDER_TYPE => {
+                ensure_size!(in: src, size: 4);
+
+                let action = if src.read_u32() == 0 {
+                    FrameMarker::Begin
+                } else {
+                    FrameMarker::End
+                };
+
+                Ok(Self::FrameMarker(action))
+            }
+            // Other orders have no length, so the following orders can't be decoded.
+            _ => Err(unsupported_value_err!("orderType", order_type.to_string())),
+        }
+    }
+
+    pub fn as_short_name(&self) -> &str {
+        match self {
+            Self::SwitchSurface { .. } => "SwitchSurface",
+            Self::CreateOffscreenBitmap(_) => "CreateOffscreenBitmap",
+            Self::FrameMarker(_) => "FrameMarker",
+        }
+    }
+}
+
+/// CREATE_OFFSCREEN_BITMAP_ORDER
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateOffscreenBitmap {
+    pub bitmap_id: u16,
+    pub width: u16,
+    pub height: u16,
+    /// Offscreen bitmaps to delete before creating this one
+    pub delete_list: Vec<u16>,
+}
+
+impl CreateOffscreenBitmap {
+    const FIXED_PART_SIZE: usize = 2 /* flags */ + 2 /* cx */ + 2 /* cy */;
+
+    fn decode(src: &mut ReadCursor<'_>) -> DecodeResult<Self> {
+        ensure_size!(in: src, size: Self::FIXED_PART_SIZE);
+
+        let flags = src.read_u16();
+        let width = src.read_u16();
+        let height = src.read_u16();
+
+        let delete_list = if flags & 0x8000 != 0 {
+            ensure_size!(in: src, size: 2);
+            let count = usize::from(src.read_u16());
+
+            ensure_size!(in: src, size: count * 2);
+            (0..count).map(|_| src.read_u16()).collect()
+        } else {
+            Vec::new()
+        };
+
+        Ok(Self {
+            bitmap_id: flags & 0x7FFF,
+            width,
+            height,
+            delete_list,
+        })
+    }
+}
+
+/// FRAME_MARKER action
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameMarker {
+    Begin,
+    End,
+}
@@ -0,0 +1,902 @@
+use ironrdp_core::{ensure_size, invalid_field_err, unsupported_value_err, DecodeResult, ReadCursor};
+
+use super::{read_2_byte_signed, read_2_byte_unsigned, Bounds, Brush, Color, ControlFlags, Glyph, GlyphFlags};
+
+const DSTBLT_ORDER_TYPE: u8 = 0x00;
+const PATBLT_ORDER_TYPE: u8 = 0x01;
+const SCRBLT_ORDER_TYPE: u8 = 0x02;
+const LINETO_ORDER_TYPE: u8 = 0x09;
+const OPAQUERECT_ORDER_TYPE: u8 = 0x0A;
+const MEMBLT_ORDER_TYPE: u8 = 0x0D;
+const MEM3BLT_ORDER_TYPE: u8 = 0x0E;
+const MULTI_OPAQUERECT_ORDER_TYPE: u8 = 0x12;
+const FAST_INDEX_ORDER_TYPE: u8 = 0x13;
+const POLYLINE_ORDER_TYPE: u8 = 0x16;
+const FAST_GLYPH_ORDER_TYPE: u8 = 0x18;
+const GLYPH_INDEX_ORDER_TYPE: u8 = 0x1B;
+
+const BOUND_LEFT: u8 = 0x01;
+const BOUND_TOP: u8 = 0x02;
+const BOUND_RIGHT: u8 = 0x04;
+const BOUND_BOTTOM: u8 = 0x08;
+const BOUND_DELTA_LEFT: u8 = 0x10;
+const BOUND_DELTA_TOP: u8 = 0x20;
+const BOUND_DELTA_RIGHT: u8 = 0x40;
+const BOUND_DELTA_BOTTOM: u8 = 0x80;
+
+/// Maximum number of rectangles of a MULTI_OPAQUERECT_ORDER
+const MAX_DELTA_RECTANGLES: u8 = 45;
+/// Maximum number of points of a POLYLINE_ORDER
+const MAX_DELTA_POINTS: u8 = 32;
+
+/// Primary drawing order ([MS-RDPEGDI] 2.2.2.2.1.1)
+///
+/// [MS-RDPEGDI]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegdi/745f2eee-d110-464c-8aca-06fc1814f6ad
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrimaryOrder {
+    DstBlt(DstBlt),
+    PatBlt(PatBlt),
+    ScrBlt(ScrBlt),
+    OpaqueRect(OpaqueRect),
+    MultiOpaqueRect(MultiOpaqueRect),
+    MemBlt(MemBlt),
+    Mem3Blt(Mem3Blt),
+    LineTo(LineTo),
+    Polyline(Polyline),
+    GlyphIndex(GlyphIndex),
+    FastIndex(FastIndex),
+    FastGlyph(FastGlyph),
+}
+
+impl PrimaryOrder {
+    pub fn as_short_name(&self) -> &str {
+        match self {
+            Self::DstBlt(_) => "DstBlt",
+            Self::PatBlt(_) => "PatBlt",
+            Self::ScrBlt(_) => "ScrBlt",
+            Self::OpaqueRect(_) => "OpaqueRect",
+            Self::MultiOpaqueRect(_) => "MultiOpaqueRect",
+            Self::MemBlt(_) => "MemBlt",
+            Self::Mem3Blt(_) => "Mem3Blt",
+            Self::LineTo(_) => "LineTo",
+            Self::Polyline(_) => "Polyline",
+            Self::GlyphIndex(_) => "GlyphIndex",
+            Self::FastIndex(_) => "FastIndex",
+            Self::FastGlyph(_) => "FastGlyph",
+        }
+    }
+}
+
+/// DSTBLT_ORDER
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DstBlt {
+    pub left: i16,
+    pub top: i16,
+    pub width: i16,
+    pub height: i16,
+    pub rop: u8,
+}
+
+/// PATBLT_ORDER
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PatBlt {
+    pub left: i16,
+    pub top: i16,
+    pub width: i16,
+    pub height: i16,
+    pub rop: u8,
+    pub back_color: Color,
+    pub fore_color: Color,
+    pub brush: Brush,
+}
+
+/// SCRBLT_ORDER
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScrBlt {
+    pub left: i16,
+    pub top: i16,
+    pub width: i16,
+    pub height: i16,
+    pub rop: u8,
+    pub src_x: i16,
+    pub src_y: i16,
+}
+
+/// OPAQUERECT_ORDER
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OpaqueRect {
+    pub left: i16,
+    pub top: i16,
+    pub width: i16,
+    pub height: i16,
+    pub color: Color,
+}
+
+/// MULTI_OPAQUERECT_ORDER
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MultiOpaqueRect {
+    /// Left of the bounding rectangle of all the rectangles
+    pub left: i16,
+    pub top: i16,
+    pub width: i16,
+    pub height: i16,
+    pub color: Color,
+    pub rectangles: Vec<Rectangle>,
+}
+
+/// Rectangle of a delta-encoded rectangle list (TS_DELTA_RECT), with absolute coordinates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rectangle {
+    pub left: i16,
+    pub top: i16,
+    pub width: i16,
+    pub height: i16,
+}
+
+/// MEMBLT_ORDER
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemBlt {
+    /// Bitmap cache identifier in the low byte, and color table index in the high byte
+    pub cache_id: u16,
+    pub left: i16,
+    pub top: i16,
+    pub width: i16,
+    pub height: i16,
+    pub rop: u8,
+    pub src_x: i16,
+    pub src_y: i16,
+    pub cache_index: u16,
+}
+
+/// MEM3BLT_ORDER
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Mem3Blt {
+    /// Bitmap cache identifier in the low byte, and color table index in the high byte
+    pub cache_id: u16,
+    pub left: i16,
+    pub top: i16,
+    pub width: i16,
+    pub height: i16,
+    pub rop: u8,
+    pub src_x: i16,
+    pub src_y: i16,
+    pub back_color: Color,
+    pub fore_color: Color,
+    pub brush: Brush,
+    pub cache_index: u16,
+}
+
+/// LINETO_ORDER
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineTo {
+    pub back_mode: u16,
+    pub start_x: i16,
+    pub start_y: i16,
+    pub end_x: i16,
+    pub end_y: i16,
+    pub back_color: Color,
+    pub rop2: u8,
+    pub pen_style: u8,
+    pub pen_width: u8,
+    pub pen_color: Color,
+}
+
+/// POLYLINE_ORDER
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Polyline {
+    pub start_x: i16,
+    pub start_y: i16,
+    pub rop2: u8,
+    pub brush_cache_entry: u16,
+    pub pen_color: Color,
+    /// Points following the start point, with absolute coordinates
+    pub points: Vec<Point>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Point {
+    pub x: i16,
+    pub y: i16,
+}
+
+/// GLYPHINDEX_ORDER
+///
+/// The back color is the text color, and the fore color is the color of the opaque rectangle.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GlyphIndex {
+    pub cache_id: u8,
+    pub flags: GlyphFlags,
+    pub char_inc: u8,
+    pub op_redundant: bool,
+    pub back_color: Color,
+    pub fore_color: Color,
+    pub bk_left: i16,
+    pub bk_top: i16,
+    pub bk_right: i16,
+    pub bk_bottom: i16,
+    pub op_left: i16,
+    pub op_top: i16,
+    pub op_right: i16,
+    pub op_bottom: i16,
+    pub brush: Brush,
+    pub x: i16,
+    pub y: i16,
+    /// Glyph indices and fragment operations
+    pub data: Vec<u8>,
+}
+
+/// FASTINDEX_ORDER
+///
+/// The back color is the text color, and the fore color is the color of the opaque rectangle.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FastIndex {
+    pub cache_id: u8,
+    pub flags: GlyphFlags,
+    pub char_inc: u8,
+    pub back_color: Color,
+    pub fore_color: Color,
+    pub bk_left: i16,
+    pub bk_top: i16,
+    pub bk_right: i16,
+    pub bk_bottom: i16,
+    pub op_left: i16,
+    pub op_top: i16,
+    pub op_right: i16,
+    pub op_bottom: i16,
+    pub x: i16,
+    pub y: i16,
+    /// Glyph indices and fragment operations
+    pub data: Vec<u8>,
+}
+
+/// FASTGLYPH_ORDER
+///
+/// The back color is the text color, and the fore color is the color of the opaque rectangle.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FastGlyph {
+    pub cache_id: u8,
+    pub flags: GlyphFlags,
+    pub char_inc: u8,
+    pub back_color: Color,
+    pub fore_color: Color,
+    pub bk_left: i16,
+    pub bk_top: i16,
+    pub bk_right: i16,
+    pub bk_bottom: i16,
+    pub op_left: i16,
+    pub op_top: i16,
+    pub op_right: i16,
+    pub op_bottom: i16,
+    pub x: i16,
+    pub y: i16,
+    pub cache_index: u8,
+    /// Glyph to cache at `cache_index` before drawing it, if any
+    pub glyph: Option<Glyph>,
+}
+
+/// Values of the previous primary drawing orders, which are the defaults of the omitted fields.
+#[derive(Debug, Clone)]
+pub(super) struct PrimaryOrderState {
+    order_type: u8,
+    bounds: Bounds,
+    dst_blt: DstBlt,
+    pat_blt: PatBlt,
+    scr_blt: ScrBlt,
+    opaque_rect: OpaqueRect,
+    multi_opaque_rect: MultiOpaqueRect,
+    mem_blt: MemBlt,
+    mem3_blt: Mem3Blt,
+    line_to: LineTo,
+    polyline: Polyline,
+    glyph_index: GlyphIndex,
+    fast_index: FastIndex,
+    fast_glyph: FastGlyph,
+}
+
+impl Default for PrimaryOrderState {
+    fn default() -> Self {
+        Self {
+            // The initial order type is PatBlt.
+            order_type: PATBLT_ORDER_TYPE,
+            bounds: Bounds::default(),
+            dst_blt: DstBlt::default(),
+            pat_blt: PatBlt::default(),
+            scr_blt: ScrBlt::default(),
+            opaque_rect: OpaqueRect::default(),
+            multi_opaque_rect: MultiOpaqueRect::default(),
+            mem_blt: MemBlt::default(),
+            mem3_blt: Mem3Blt::default(),
+            line_to: LineTo::default(),
+            polyline: Polyline::default(),
+            glyph_index: GlyphIndex::default(),
+            fast_index: FastIndex::default(),
+            fast_glyph: FastGlyph::default(),
+        }
+    }
+}
+
+impl PrimaryOrderState {
+    /// Decodes a primary drawing order, once its control flags have been read.
+    pub(super) fn decode(
+        &mut self,
+        src: &mut ReadCursor<'_>,
+        control_flags: ControlFlags,
+    ) -> DecodeResult<(PrimaryOrder, Option<Bounds>)> {
+        if control_flags.contains(ControlFlags::TYPE_CHANGE) {
+            ensure_size!(in: src, size: 1);
+            self.order_type = src.read_u8();
+        }
+
+        let field_bytes = field_bytes(self.order_type)
+            .ok_or_else(|| unsupported_value_err!("orderType", self.order_type.to_string()))?;
+
+        // The leading zero bytes of the field flags are omitted.
+        let mut zero_bytes = 0;
+        if control_flags.contains(ControlFlags::ZERO_FIELD_BYTE_BIT0) {
+            zero_bytes += 1;
+        }
+        if control_flags.contains(ControlFlags::ZERO_FIELD_BYTE_BIT1) {
+            zero_bytes += 2;
+        }
+        let field_bytes = field_bytes.saturating_sub(zero_bytes);
+
+        ensure_size!(in: src, size: field_bytes);
+        let mut field_flags = 0;
+        for byte in 0..field_bytes {
+            field_flags |= u32::from(src.read_u8()) << (byte * 8);
+        }
+
+        let bounds = if control_flags.contains(ControlFlags::BOUNDS) {
+            if !control_flags.contains(ControlFlags::ZERO_BOUNDS_DELTAS) {
+                self.read_bounds(src)?;
+            }
+
+            Some(self.bounds)
+        } else {
+            None
+        };
+
+        let mut fields = Fields {
+            src,
+            flags: field_flags,
+            delta_coordinates: control_flags.contains(ControlFlags::DELTA_COORDINATES),
+            next: 0,
+        };
+
+        let order = match self.order_type {
+            DSTBLT_ORDER_TYPE => PrimaryOrder::DstBlt(fields.dst_blt(&mut self.dst_blt)?.clone()),
+            PATBLT_ORDER_TYPE => PrimaryOrder::PatBlt(fields.pat_blt(&mut self.pat_blt)?.clone()),
+            SCRBLT_ORDER_TYPE => PrimaryOrder::ScrBlt(fields.scr_blt(&mut self.scr_blt)?.clone()),
+            OPAQUERECT_ORDER_TYPE => PrimaryOrder::OpaqueRect(fields.opaque_rect(&mut self.opaque_rect)?.clone()),
+            MULTI_OPAQUERECT_ORDER_TYPE => {
+                PrimaryOrder::MultiOpaqueRect(fields.multi_opaque_rect(&mut self.multi_opaque_rect)?.clone())
+            }
+            MEMBLT_ORDER_TYPE => PrimaryOrder::MemBlt(fields.mem_blt(&mut self.mem_blt)?.clone()),
+            MEM3BLT_ORDER_TYPE => PrimaryOrder::Mem3Blt(fields.mem3_blt(&mut self.mem3_blt)?.clone()),
+            LINETO_ORDER_TYPE => PrimaryOrder::LineTo(fields.line_to(&mut self.line_to)?.clone()),
+            POLYLINE_ORDER_TYPE => PrimaryOrder::Polyline(fields.polyline(&mut self.polyline)?.clone()),
+            GLYPH_INDEX_ORDER_TYPE => PrimaryOrder::GlyphIndex(fields.glyph_index(&mut self.glyph_index)?.clone()),
+            FAST_INDEX_ORDER_TYPE => PrimaryOrder::FastIndex(fields.fast_index(&mut self.fast_index)?.clone()),
+            FAST_GLYPH_ORDER_TYPE => PrimaryOrder::FastGlyph(fields.fast_glyph(&mut self.fast_glyph)?.clone()),
+            _ => unreachable!("field_bytes returns None for unsupported order types"),
+        };
+
+        Ok((order, bounds))
+    }
+
+    /// Reads the TS_BOUNDS structure, updating the previous bounds.
+    fn read_bounds(&mut self, src: &mut ReadCursor<'_>) -> DecodeResult<()> {
+        ensure_size!(in: src, size: 1);
+        let flags = src.read_u8();
+
+        let coordinates = [
+            (BOUND_LEFT, BOUND_DELTA_LEFT, &mut self.bounds.left),
+            (BOUND_TOP, BOUND_DELTA_TOP, &mut self.bounds.top),
+            (BOUND_RIGHT, BOUND_DELTA_RIGHT, &mut self.bounds.right),
+            (BOUND_BOTTOM, BOUND_DELTA_BOTTOM, &mut self.bounds.bottom),
+        ];
+
+        for (absolute, delta, value) in coordinates {
+            if flags & absolute != 0 {
+                ensure_size!(in: src, size: 2);
+                *value = src.read_i16();
+            } else if flags & delta != 0 {
+                ensure_size!(in: src, size: 1);
+                *value = value.wrapping_add(i16::from(src.read_u8() as i8));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the size of the field flags of an order type, or `None` if the order is not supported.
+fn field_bytes(order_type: u8) -> Option<usize> {
+    match order_type {
+        DSTBLT_ORDER_TYPE | SCRBLT_ORDER_TYPE | OPAQUERECT_ORDER_TYPE | POLYLINE_ORDER_TYPE => Some(1),
+        PATBLT_ORDER_TYPE
+        | MULTI_OPAQUERECT_ORDER_TYPE
+        | LINETO_ORDER_TYPE
+        | MEMBLT_ORDER_TYPE
+        | FAST_INDEX_ORDER_TYPE
+        | FAST_GLYPH_ORDER_TYPE => Some(2),
+        MEM3BLT_ORDER_TYPE | GLYPH_INDEX_ORDER_TYPE => Some(3),
+        _ => None,
+    }
+}
+
+/// Reader of the fields of a primary drawing order, in order.
+///
+/// Each read consumes a field, and leaves the value untouched if the field is not present.
+struct Fields<'a, 'b> {
+    src: &'b mut ReadCursor<'a>,
+    flags: u32,
+    delta_coordinates: bool,
+    next: u32,
+}
+
+impl Fields<'_, '_> {
+    /// Consumes the next field, returning whether it is present.
+    fn next_present(&mut self) -> bool {
+        let present = self.flags & (1 << self.next) != 0;
+        self.next += 1;
+        present
+    }
+
+    /// Reads a coordinate field, which may be encoded as a delta from its previous value.
+    fn coord(&mut self, value: &mut i16) -> DecodeResult<()> {
+        if self.next_present() {
+            let src = &mut *self.src;
+
+            if self.delta_coordinates {
+                ensure_size!(in: src, size: 1);
+                *value = value.wrapping_add(i16::from(src.read_u8() as i8));
+            } else {
+                ensure_size!(in: src, size: 2);
+                *value = src.read_i16();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn u8(&mut self, value: &mut u8) -> DecodeResult<()> {
+        if self.next_present() {
+            let src = &mut *self.src;
+            ensure_size!(in: src, size: 1);
+            *value = src.read_u8();
+        }
+
+        Ok(())
+    }
+
+    fn u16(&mut self, value: &mut u16) -> DecodeResult<()> {
+        if self.next_present() {
+            let src = &mut *self.src;
+            ensure_size!(in: src, size: 2);
+            *value = src.read_u16();
+        }
+
+        Ok(())
+    }
+
+    fn i16(&mut self, value: &mut i16) -> DecodeResult<()> {
+        if self.next_present() {
+            let src = &mut *self.src;
+            ensure_size!(in: src, size: 2);
+            *value = src.read_i16();
+        }
+
+        Ok(())
+    }
+
+    fn color(&mut self, value: &mut Color) -> DecodeResult<()> {
+        if self.next_present() {
+            let src = &mut *self.src;
+            ensure_size!(in: src, size: Color::SIZE);
+            *value = Color::read(src);
+        }
+
+        Ok(())
+    }
+
+    /// Reads the five fields of a TS_BRUSH structure.
+    fn brush(&mut self, brush: &mut Brush) -> DecodeResult<()> {
+        self.u8(&mut brush.x)?;
+        self.u8(&mut brush.y)?;
+        self.u8(&mut brush.style)?;
+        self.u8(&mut brush.hatch)?;
+
+        if self.next_present() {
+            let src = &mut *self.src;
+            ensure_size!(in: src, size: 7);
+            brush.extra = src.read_array();
+        }
+
+        Ok(())
+    }
+
+    /// Reads the `flAccel` and `ulCharInc` fields.
+    fn glyph_flags(&mut self, flags: &mut GlyphFlags, char_inc: &mut u8) -> DecodeResult<()> {
+        let mut bits = flags.bits();
+        self.u8(&mut bits)?;
+        *flags = GlyphFlags::from_bits_retain(bits);
+
+        self.u8(char_inc)
+    }
+
+    /// Reads a variable-sized field with a one-byte length.
+    fn variable(&mut self, value: &mut Vec<u8>) -> DecodeResult<bool> {
+        if !self.next_present() {
+            return Ok(false);
+        }
+
+        let src = &mut *self.src;
+        ensure_size!(in: src, size: 1);
+        let length = usize::from(src.read_u8());
+
+        ensure_size!(in: src, size: length);
+        *value = src.read_slice(length).to_vec();
+
+        Ok(true)
+    }
+
+    fn dst_blt<'o>(&mut self, order: &'o mut DstBlt) -> DecodeResult<&'o DstBlt> {
+        self.coord(&mut order.left)?;
+        self.coord(&mut order.top)?;
+        self.coord(&mut order.width)?;
+        self.coord(&mut order.height)?;
+        self.u8(&mut order.rop)?;
+
+        Ok(order)
+    }
+
+    fn pat_blt<'o>(&mut self, order: &'o mut PatBlt) -> DecodeResult<&'o PatBlt> {
+        self.coord(&mut order.left)?;
+        self.coord(&mut order.top)?;
+        self.coord(&mut order.width)?;
+        self.coord(&mut order.height)?;
+        self.u8(&mut order.rop)?;
+        self.color(&mut order.back_color)?;
+        self.color(&mut order.fore_color)?;
+        self.brush(&mut order.brush)?;
+
+        Ok(order)
+    }
+
+    fn scr_blt<'o>(&mut self, order: &'o mut ScrBlt) -> DecodeResult<&'o ScrBlt> {
+        self.coord(&mut order.left)?;
+        self.coord(&mut order.top)?;
+        self.coord(&mut order.width)?;
+        self.coord(&mut order.height)?;
+        self.u8(&mut order.rop)?;
+        self.coord(&mut order.src_x)?;
+        self.coord(&mut order.src_y)?;
+
+        Ok(order)
+    }
+
+    fn opaque_rect<'o>(&mut self, order: &'o mut OpaqueRect) -> DecodeResult<&'o OpaqueRect> {
+        self.coord(&mut order.left)?;
+        self.coord(&mut order.top)?;
+        self.coord(&mut order.width)?;
+        self.coord(&mut order.height)?;
+        // Each color component is a field of its own.
+        self.u8(&mut order.color.red)?;
+        self.u8(&mut order.color.green)?;
+        self.u8(&mut order.color.blue)?;
+
+        Ok(order)
+    }
+
+    fn multi_opaque_rect<'o>(&mut self, order: &'o mut MultiOpaqueRect) -> DecodeResult<&'o MultiOpaqueRect> {
+        self.coord(&mut order.left)?;
+        self.coord(&mut order.top)?;
+        self.coord(&mut order.width)?;
+        self.coord(&mut order.height)?;
+        self.u8(&mut order.color.red)?;
+        self.u8(&mut order.color.green)?;
+        self.u8(&mut order.color.blue)?;
+
+        let mut count = u8::try_from(order.rectangles.len()).unwrap_or(u8::MAX);
+        self.u8(&mut count)?;
+
+        if count > MAX_DELTA_RECTANGLES {
+            return Err(invalid_field_err!("numRectangles", "too many rectangles"));
+        }
+
+        if self.next_present() {
+            let src = &mut *self.src;
+            ensure_size!(in: src, size: 2);
+            let length = usize::from(src.read_u16());
+
+            ensure_size!(in: src, size: length);
+            order.rectangles = read_delta_rectangles(&mut ReadCursor::new(src.read_slice(length)), count)?;
+        } else {
+            order.rectangles.resize(usize::from(count), Rectangle::default());
+        }
+
+        Ok(order)
+    }
+
+    fn mem_blt<'o>(&mut self, order: &'o mut MemBlt) -> DecodeResult<&'o MemBlt> {
+        self.u16(&mut order.cache_id)?;
+        self.coord(&mut order.left)?;
+        self.coord(&mut order.top)?;
+        self.coord(&mut order.width)?;
+        self.coord(&mut order.height)?;
+        self.u8(&mut order.rop)?;
+        self.coord(&mut order.src_x)?;
+        self.coord(&mut order.src_y)?;
+        self.u16(&mut order.cache_index)?;
+
+        Ok(order)
+    }
+
+    fn mem3_blt<'o>(&mut self, order: &'o mut Mem3Blt) -> DecodeResult<&'o Mem3Blt> {
+        self.u16(&mut order.cache_id)?;
+        self.coord(&mut order.left)?;
+        self.coord(&mut order.top)?;
+        self.coord(&mut order.width)?;
+        self.coord(&mut order.height)?;
+        self.u8(&mut order.rop)?;
+        self.coord(&mut order.src_x)?;
+        self.coord(&mut order.src_y)?;
+        self.color(&mut order.back_color)?;
+        self.color(&mut order.fore_color)?;
+        self.brush(&mut order.brush)?;
+        self.u16(&mut order.cache_index)?;
+
+        Ok(order)
+    }
+
+    fn line_to<'o>(&mut self, order: &'o mut LineTo) -> DecodeResult<&'o LineTo> {
+        self.u16(&mut order.back_mode)?;
+        self.coord(&mut order.start_x)?;
+        self.coord(&mut order.start_y)?;
+        self.coord(&mut order.end_x)?;
+        self.coord(&mut order.end_y)?;
+        self.color(&mut order.back_color)?;
+        self.u8(&mut order.rop2)?;
+        self.u8(&mut order.pen_style)?;
+        self.u8(&mut order.pen_width)?;
+        self.color(&mut order.pen_color)?;
+
+        Ok(order)
+    }
+
+    fn polyline<'o>(&mut self, order: &'o mut Polyline) -> DecodeResult<&'o Polyline> {
+        self.coord(&mut order.start_x)?;
+        self.coord(&mut order.start_y)?;
+        self.u8(&mut order.rop2)?;
+        self.u16(&mut order.brush_cache_entry)?;
+        self.color(&mut order.pen_color)?;
+
+        let mut count = u8::try_from(order.points.len()).unwrap_or(u8::MAX);
+        self.u8(&mut count)?;
+
+        if count > MAX_DELTA_POINTS {
+            return Err(invalid_field_err!("numDeltaEntries", "too many points"));
+        }
+
+        if self.next_present() {
+            let src = &mut *self.src;
+            ensure_size!(in: src, size: 1);
+            let length = usize::from(src.read_u8());
+
+            ensure_size!(in: src, size: length);
+            let start = Point {
+                x: order.start_x,
+                y: order.start_y,
+            };
+            order.points = read_delta_points(&mut ReadCursor::new(src.read_slice(length)), count, start)?;
+        } else {
+            order.points.resize(usize::from(count), Point::default());
+        }
+
+        Ok(order)
+    }
+
+    fn glyph_index<'o>(&mut self, order: &'o mut GlyphIndex) -> DecodeResult<&'o GlyphIndex> {
+        self.u8(&mut order.cache_id)?;
+        self.glyph_flags(&mut order.flags, &mut order.char_inc)?;
+
+        let mut op_redundant = u8::from(order.op_redundant);
+        self.u8(&mut op_redundant)?;
+        order.op_redundant = op_redundant != 0;
+
+        self.color(&mut order.back_color)?;
+        self.color(&mut order.fore_color)?;
+        // The rectangles of this order are never delta-encoded.
+        self.i16(&mut order.bk_left)?;
+        self.i16(&mut order.bk_top)?;
+        self.i16(&mut order.bk_right)?;
+        self.i16(&mut order.bk_bottom)?;
+        self.i16(&mut order.op_left)?;
+        self.i16(&mut order.op_top)?;
+        self.i16(&mut order.op_right)?;
+        self.i16(&mut order.op_bottom)?;
+        self.brush(&mut order.brush)?;
+        self.i16(&mut order.x)?;
+        self.i16(&mut order.y)?;
+        self.variable(&mut order.data)?;
+
+        Ok(order)
+    }
+
+    fn fast_index<'o>(&mut self, order: &'o mut FastIndex) -> DecodeResult<&'o FastIndex> {
+        self.u8(&mut order.cache_id)?;
+        self.fast_glyph_flags(&mut order.flags, &mut order.char_inc)?;
+        self.color(&mut order.back_color)?;
+        self.color(&mut order.fore_color)?;
+        self.coord(&mut order.bk_left)?;
+        self.coord(&mut order.bk_top)?;
+        self.coord(&mut order.bk_right)?;
+        self.coord(&mut order.bk_bottom)?;
+        self.coord(&mut order.op_left)?;
+        self.coord(&mut order.op_top)?;
+        self.coord(&mut order.op_right)?;
+        self.coord(&mut order.op_bottom)?;
+        self.coord(&mut order.x)?;
+        self.coord(&mut order.y)?;
+        self.variable(&mut order.data)?;
+
+        Ok(order)
+    }
+
+    fn fast_glyph<'o>(&mut self, order: &'o mut FastGlyph) -> DecodeResult<&'o FastGlyph> {
+        self.u8(&mut order.cache_id)?;
+        self.fast_glyph_flags(&mut order.flags, &mut order.char_inc)?;
+        self.color(&mut order.back_color)?;
+        self.color(&mut order.fore_color)?;
+        self.coord(&mut order.bk_left)?;
+        self.coord(&mut order.bk_top)?;
+        self.coord(&mut order.bk_right)?;
+        self.coord(&mut order.bk_bottom)?;
+        self.coord(&mut order.op_left)?;
+        self.coord(&mut order.op_top)?;
+        self.coord(&mut order.op_right)?;
+        self.coord(&mut order.op_bottom)?;
+        self.coord(&mut order.x)?;
+        self.coord(&mut order.y)?;
+
+        let mut data = Vec::new();
+        if self.variable(&mut data)? {
+            let mut src = ReadCursor::new(&data);
+
+            ensure_size!(in: src, size: 1);
+            order.cache_index = src.read_u8();
+            order.glyph = if src.is_empty() {
+                None
+            } else {
+                Some(read_fast_glyph(&mut src)?)
+            };
+        }
+
+        Ok(order)
+    }
+
+    /// Reads the `fDrawing` field, holding `ulCharInc` and `flAccel`.
+    fn fast_glyph_flags(&mut self, flags: &mut GlyphFlags, char_inc: &mut u8) -> DecodeResult<()> {
+        if self.next_present() {
+            let src = &mut *self.src;
+            ensure_size!(in: src, size: 2);
+            *char_inc = src.read_u8();
+            *flags = GlyphFlags::from_bits_retain(src.read_u8());
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads a glyph definition of a FASTGLYPH_ORDER.
+fn read_fast_glyph(src: &mut ReadCursor<'_>) -> DecodeResult<Glyph> {
+    let x = read_2_byte_signed(src)?;
+    let y = read_2_byte_signed(src)?;
+    let width = read_2_byte_unsigned(src)?;
+    let height = read_2_byte_unsigned(src)?;
+
+    // The padding of the bitmap is sometimes omitted, and the optional Unicode character which
+    // may follow it is ignored.
+    let size = Glyph::bitmap_size(width, height);
+    let unpadded_size = usize::from(width).div_ceil(8) * usize::from(height);
+    ensure_size!(in: src, size: unpadded_size);
+
+    let mut bitmap = src.read_slice(size.min(src.len())).to_vec();
+    bitmap.resize(size, 0);
+
+    Ok(Glyph {
+        x,
+        y,
+        width,
+        height,
+        bitmap,
+    })
+}
+
+/// Reads a DELTA_RECTS_FIELD.
+fn read_delta_rectangles(src: &mut ReadCursor<'_>, count: u8) -> DecodeResult<Vec<Rectangle>> {
+    let count = usize::from(count);
+
+    // Four flag bits per rectangle, telling which values are zero or repeated.
+    let flags_size = count.div_ceil(2);
+    ensure_size!(in: src, size: flags_size);
+    let zero_bits = src.read_slice(flags_size);
+
+    let mut rectangles: Vec<Rectangle> = Vec::with_capacity(count);
+
+    for index in 0..count {
+        let flags = zero_bits[index / 2] << ((index % 2) * 4);
+        let previous = rectangles.last().copied().unwrap_or_default();
+
+        let mut rectangle = Rectangle::default();
+
+        if flags & 0x80 == 0 {
+            rectangle.left = read_delta(src)?;
+        }
+        if flags & 0x40 == 0 {
+            rectangle.top = read_delta(src)?;
+        }
+        rectangle.width = if flags & 0x20 == 0 {
+            read_delta(src)?
+        } else {
+            previous.width
+        };
+        rectangle.height = if flags & 0x10 == 0 {
+            read_delta(src)?
+        } else {
+            previous.height
+        };
+
+        rectangle.left = rectangle.left.wrapping_add(previous.left);
+        rectangle.top = rectangle.top.wrapping_add(previous.top);
+
+        rectangles.push(rectangle);
+    }
+
+    Ok(rectangles)
+}
+
+/// Reads a DELTA_PTS_FIELD.
+fn read_delta_points(src: &mut ReadCursor<'_>, count: u8, start: Point) -> DecodeResult<Vec<Point>> {
+    let count = usize::from(count);
+
+    // Two flag bits per point, telling which values are zero.
+    let flags_size = count.div_ceil(4);
+    ensure_size!(in: src, size: flags_size);
+    let zero_bits = src.read_slice(flags_size);
+
+    let mut points = Vec::with_capacity(count);
+    let mut point = start;
+
+    for index in 0..count {
+        let flags = zero_bits[index / 4] << ((index % 4) * 2);
+
+        if flags & 0x80 == 0 {
+            point.x = point.x.wrapping_add(read_delta(src)?);
+        }
+        if flags & 0x40 == 0 {
+            point.y = point.y.wrapping_add(read_delta(src)?);
+        }
+
+        points.push(point);
+    }
+
+    Ok(points)
+}
+
+/// Reads a one or two-byte signed delta value of a delta-encoded list.
+fn read_delta(src: &mut ReadCursor<'_>) -> DecodeResult<i16> {
+    ensure_size!(in: src, size: 1);
+    let first = src.read_u8();
+
+    // Sign-extend the 7-bit value.
+    let mut value = i16::from(((first << 1) as i8) >> 1);
+
+    if first & 0x80 != 0 {
+        ensure_size!(in: src, size: 1);
+        value = (value << 8) | i16::from(src.read_u8());
+    }
+
+    Ok(value)
+}
@@ -0,0 +1,495 @@
+use ironrdp_core::{ensure_size, invalid_field_err, unsupported_value_err, DecodeResult, ReadCursor};
+
+use super::{brush_bpp, read_2_byte_signed, read_2_byte_unsigned, read_4_byte_unsigned, Color, Glyph};
+use crate::rdp::capability_sets::GlyphSupportLevel;
+
+const CACHE_BITMAP_UNCOMPRESSED_ORDER_TYPE: u8 = 0x00;
+const CACHE_COLOR_TABLE_ORDER_TYPE: u8 = 0x01;
+const CACHE_BITMAP_COMPRESSED_ORDER_TYPE: u8 = 0x02;
+const CACHE_GLYPH_ORDER_TYPE: u8 = 0x03;
+const CACHE_BITMAP_REV2_UNCOMPRESSED_ORDER_TYPE: u8 = 0x04;
+const CACHE_BITMAP_REV2_COMPRESSED_ORDER_TYPE: u8 = 0x05;
+const CACHE_BRUSH_ORDER_TYPE: u8 = 0x07;
+const CACHE_BITMAP_REV3_ORDER_TYPE: u8 = 0x08;
+
+const NO_BITMAP_COMPRESSION_HDR: u16 = 0x0400;
+const CG_GLYPH_UNICODE_PRESENT: u16 = 0x0010;
+
+const CBR2_HEIGHT_SAME_AS_WIDTH: u16 = 0x01;
+const CBR2_PERSISTENT_KEY_PRESENT: u16 = 0x02;
+const CBR2_NO_BITMAP_COMPRESSION_HDR: u16 = 0x08;
+const CBR2_DO_NOT_CACHE: u16 = 0x10;
+
+/// Bits per pixel of the revision 2 and 3 cache bitmap orders, indexed by the bitsPerPixelId field
+const CBR23_BPP: [u8; 7] = [0, 0, 0, 8, 16, 24, 32];
+
+/// Size of the TS_CD_HEADER structure
+const COMPRESSION_HEADER_SIZE: usize = 8;
+
+/// Secondary drawing order ([MS-RDPEGDI] 2.2.2.2.1.2)
+///
+/// [MS-RDPEGDI]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegdi/745f2eee-d110-464c-8aca-06fc1814f6ad
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecondaryOrder {
+    CacheBitmap(CacheBitmap),
+    CacheBitmapV2(CacheBitmapV2),
+    CacheBitmapV3(CacheBitmapV3),
+    CacheColorTable(CacheColorTable),
+    CacheGlyph(CacheGlyph),
+    CacheBrush(CacheBrush),
+    /// Order which is not supported, kept as is
+    Other {
+        order_type: u8,
+        extra_flags: u16,
+        data: Vec<u8>,
+    },
+}
+
+impl SecondaryOrder {
+    const FIXED_PART_SIZE: usize = 2 /* orderLength */ + 2 /* extraFlags */ + 1 /* orderType */;
+
+    /// Decodes a secondary drawing order, once its control flags have been read.
+    pub(super) fn decode_with_glyph_support_level(
+        src: &mut ReadCursor<'_>,
+        glyph_support_level: GlyphSupportLevel,
+    ) -> DecodeResult<Self> {
+        ensure_size!(in: src, size: Self::FIXED_PART_SIZE);
+
+        // The order length is the size of the order minus 13 bytes, and the first 6 bytes are
+        // the control flags and this header.
+        let length = usize::from(src.read_u16()) + 7;
+        let extra_flags = src.read_u16();
+        let order_type = src.read_u8();
+
+        ensure_size!(in: src, size: length);
+        let src = &mut ReadCursor::new(src.read_slice(length));
+
+        let order = match order_type {
+            CACHE_BITMAP_UNCOMPRESSED_ORDER_TYPE | CACHE_BITMAP_COMPRESSED_ORDER_TYPE => {
+                let compressed = order_type == CACHE_BITMAP_COMPRESSED_ORDER_TYPE;
+                Self::CacheBitmap(CacheBitmap::decode(src, extra_flags, compressed)?)
+            }
+            CACHE_BITMAP_REV2_UNCOMPRESSED_ORDER_TYPE | CACHE_BITMAP_REV2_COMPRESSED_ORDER_TYPE => {
+                let compressed = order_type == CACHE_BITMAP_REV2_COMPRESSED_ORDER_TYPE;
+                Self::CacheBitmapV2(CacheBitmapV2::decode(src, extra_flags, compressed)?)
+            }
+            CACHE_BITMAP_REV3_ORDER_TYPE => Self::CacheBitmapV3(CacheBitmapV3::decode(src, extra_flags)?),
+            CACHE_COLOR_TABLE_ORDER_TYPE => Self::CacheColorTable(CacheColorTable::decode(src)?),
+            CACHE_GLYPH_ORDER_TYPE => {
+                if glyph_support_level == GlyphSupportLevel::Encode {
+                    Self::CacheGlyph(CacheGlyph::decode_rev2(src, extra_flags)?)
+                } else {
+                    Self::CacheGlyph(CacheGlyph::decode_rev1(src)?)
+                }
+            }
+            CACHE_BRUSH_ORDER_TYPE => Self::CacheBrush(CacheBrush::decode(src)?),
+            _ => Self::Other {
+                order_type,
+                extra_flags,
+                data: src.read_remaining().to_vec(),
+            },
+        };
+
+        Ok(order)
+    }
+
+    pub fn as_short_name(&self) -> &str {
+        match self {
+            Self::CacheBitmap(_) => "CacheBitmap",
+            Self::CacheBitmapV2(_) => "CacheBitmapV2",
+            Self::CacheBitmapV3(_) => "CacheBitmapV3",
+            Self::CacheColorTable(_) => "CacheColorTable",
+            Self::CacheGlyph(_) => "CacheGlyph",
+            Self::CacheBrush(_) => "CacheBrush",
+            Self::Other { .. } => "Other",
+        }
+    }
+}
+
+/// Cache Bitmap - Revision 1 (CACHE_BITMAP_ORDER)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheBitmap {
+    pub cache_id: u8,
+    pub cache_index: u16,
+    pub width: u16,
+    pub height: u16,
+    pub bpp: u8,
+    /// Whether the bitmap data is compressed with the Interleaved RLE codec
+    pub compressed: bool,
+    pub data: Vec<u8>,
+}
+
+impl CacheBitmap {
+    const FIXED_PART_SIZE: usize = 1 /* cacheId */ + 1 /* pad */ + 1 /* width */ + 1 /* height */ + 1 /* bpp */ + 2 /* length */ + 2 /* cacheIndex */;
+
+    fn decode(src: &mut ReadCursor<'_>, extra_flags: u16, compressed: bool) -> DecodeResult<Self> {
+        ensure_size!(in: src, size: Self::FIXED_PART_SIZE);
+
+        let cache_id = src.read_u8();
+        let _pad = src.read_u8();
+        let width = u16::from(src.read_u8());
+        let height = u16::from(src.read_u8());
+        let bpp = src.read_u8();
+        let mut length = usize::from(src.read_u16());
+        let cache_index = src.read_u16();
+
+        if compressed && extra_flags & NO_BITMAP_COMPRESSION_HDR == 0 {
+            length = read_compression_header(src)?;
+        }
+
+        ensure_size!(in: src, size: length);
+        let data = src.read_slice(length).to_vec();
+
+        Ok(Self {
+            cache_id,
+            cache_index,
+            width,
+            height,
+            bpp,
+            compressed,
+            data,
+        })
+    }
+}
+
+/// Cache Bitmap - Revision 2 (CACHE_BITMAP_REV2_ORDER)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheBitmapV2 {
+    pub cache_id: u8,
+    pub cache_index: u16,
+    pub width: u16,
+    pub height: u16,
+    pub bpp: u8,
+    /// Whether the bitmap data is compressed with the Interleaved RLE codec
+    pub compressed: bool,
+    /// Key of the bitmap in the persistent bitmap cache
+    pub persistent_key: Option<u64>,
+    /// Whether the bitmap should be stored in the last entry of the cache, and not at `cache_index`
+    pub do_not_cache: bool,
+    pub data: Vec<u8>,
+}
+
+impl CacheBitmapV2 {
+    fn decode(src: &mut ReadCursor<'_>, extra_flags: u16, compressed: bool) -> DecodeResult<Self> {
+        let cache_id = (extra_flags & 0x03) as u8;
+        let bpp = read_cbr23_bpp(extra_flags)?;
+        let flags = extra_flags >> 7;
+
+        let persistent_key = if flags & CBR2_PERSISTENT_KEY_PRESENT != 0 {
+            ensure_size!(in: src, size: 8);
+            Some(src.read_u64())
+        } else {
+            None
+        };
+
+        let width = read_2_byte_unsigned(src)?;
+        let height = if flags & CBR2_HEIGHT_SAME_AS_WIDTH != 0 {
+            width
+        } else {
+            read_2_byte_unsigned(src)?
+        };
+        let mut length = read_4_byte_unsigned(src)? as usize;
+        let cache_index = read_2_byte_unsigned(src)?;
+
+        if compressed && flags & CBR2_NO_BITMAP_COMPRESSION_HDR == 0 {
+            length = read_compression_header(src)?;
+        }
+
+        ensure_size!(in: src, size: length);
+        let data = src.read_slice(length).to_vec();
+
+        Ok(Self {
+            cache_id,
+            cache_index,
+            width,
+            height,
+            bpp,
+            compressed,
+            persistent_key,
+            do_not_cache: flags & CBR2_DO_NOT_CACHE != 0,
+            data,
+        })
+    }
+}
+
+/// Cache Bitmap - Revision 3 (CACHE_BITMAP_REV3_ORDER)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheBitmapV3 {
+    pub cache_id: u8,
+    pub cache_index: u16,
+    /// Key of the bitmap in the persistent bitmap cache
+    pub persistent_key: u64,
+    pub bpp: u8,
+    /// Codec of the bitmap data, or 0 if it is not compressed
+    pub codec_id: u8,
+    pub width: u16,
+    pub height: u16,
+    pub data: Vec<u8>,
+}
+
+impl CacheBitmapV3 {
+    const FIXED_PART_SIZE: usize = 2 /* cacheIndex */ + 8 /* key */ + 1 /* bpp */ + 2 /* reserved */ + 1 /* codecId */ + 2 /* width */ + 2 /* height */ + 4 /* length */;
+
+    fn decode(src: &mut ReadCursor<'_>, extra_flags: u16) -> DecodeResult<Self> {
+        let cache_id = (extra_flags & 0x03) as u8;
+        // The bpp of TS_BITMAP_DATA_EX prevails.
+        let _ = read_cbr23_bpp(extra_flags)?;
+
+        ensure_size!(in: src, size: Self::FIXED_PART_SIZE);
+
+        let cache_index = src.read_u16();
+        let persistent_key = src.read_u64();
+        let bpp = src.read_u8();
+        let _reserved1 = src.read_u8();
+        let _reserved2 = src.read_u8();
+        let codec_id = src.read_u8();
+        let width = src.read_u16();
+        let height = src.read_u16();
+        let length = src.read_u32() as usize;
+
+        ensure_size!(in: src, size: length);
+        let data = src.read_slice(length).to_vec();
+
+        Ok(Self {
+            cache_id,
+            cache_index,
+            persistent_key,
+            bpp,
+            codec_id,
+            width,
+            height,
+            data,
+        })
+    }
+}
+
+/// Cache Color Table (CACHE_COLOR_TABLE_ORDER)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheColorTable {
+    pub cache_index: u8,
+    pub colors: Vec<Color>,
+}
+
+impl CacheColorTable {
+    const FIXED_PART_SIZE: usize = 1 /* cacheIndex */ + 2 /* numberColors */;
+
+    fn decode(src: &mut ReadCursor<'_>) -> DecodeResult<Self> {
+        ensure_size!(in: src, size: Self::FIXED_PART_SIZE);
+
+        let cache_index = src.read_u8();
+        let number_colors = usize::from(src.read_u16());
+
+        ensure_size!(in: src, size: number_colors * 4);
+        let colors = (0..number_colors)
+            .map(|_| {
+                let [blue, green, red, _pad] = src.read_array();
+                Color { red, green, blue }
+            })
+            .collect();
+
+        Ok(Self { cache_index, colors })
+    }
+}
+
+/// Cache Glyph - Revision 1 and 2 (CACHE_GLYPH_ORDER and CACHE_GLYPH_REV2_ORDER)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheGlyph {
+    pub cache_id: u8,
+    pub glyphs: Vec<CachedGlyph>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedGlyph {
+    pub cache_index: u16,
+    pub glyph: Glyph,
+}
+
+impl CacheGlyph {
+    const FIXED_PART_SIZE: usize = 1 /* cacheId */ + 1 /* cGlyphs */;
+
+    const GLYPH_FIXED_PART_SIZE: usize = 2 /* cacheIndex */ + 2 /* x */ + 2 /* y */ + 2 /* cx */ + 2 /* cy */;
+
+    fn decode_rev1(src: &mut ReadCursor<'_>) -> DecodeResult<Self> {
+        ensure_size!(in: src, size: Self::FIXED_PART_SIZE);
+
+        let cache_id = src.read_u8();
+        let count = src.read_u8();
+
+        let mut glyphs = Vec::with_capacity(usize::from(count));
+
+        for _ in 0..count {
+            ensure_size!(in: src, size: Self::GLYPH_FIXED_PART_SIZE);
+
+            let cache_index = src.read_u16();
+            let x = src.read_i16();
+            let y = src.read_i16();
+            let width = src.read_u16();
+            let height = src.read_u16();
+
+            glyphs.push(CachedGlyph {
+                cache_index,
+                glyph: read_glyph_bitmap(src, x, y, width, height)?,
+            });
+        }
+
+        // The Unicode characters which may follow the glyphs are ignored.
+
+        Ok(Self { cache_id, glyphs })
+    }
+
+    fn decode_rev2(src: &mut ReadCursor<'_>, extra_flags: u16) -> DecodeResult<Self> {
+        let cache_id = (extra_flags & 0x0F) as u8;
+        let count = extra_flags >> 8;
+
+        let mut glyphs = Vec::with_capacity(usize::from(count));
+
+        for _ in 0..count {
+            ensure_size!(in: src, size: 1);
+
+            let cache_index = u16::from(src.read_u8());
+            let x = read_2_byte_signed(src)?;
+            let y = read_2_byte_signed(src)?;
+            let width = read_2_byte_unsigned(src)?;
+            let height = read_2_byte_unsigned(src)?;
+
+            glyphs.push(CachedGlyph {
+                cache_index,
+                glyph: read_glyph_bitmap(src, x, y, width, height)?,
+            });
+        }
+
+        if extra_flags & CG_GLYPH_UNICODE_PRESENT != 0 {
+            // The Unicode characters are ignored.
+            let _ = src.read_remaining();
+        }
+
+        Ok(Self { cache_id, glyphs })
+    }
+}
+
+/// Cache Brush (CACHE_BRUSH_ORDER)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheBrush {
+    pub cache_index: u8,
+    pub bpp: u8,
+    pub width: u8,
+    pub height: u8,
+    pub style: u8,
+    /// Decompressed pixels of the brush, stored from top to bottom
+    ///
+    /// 1 bpp brushes have one byte per row, and other brushes have little-endian pixels of
+    /// `bpp` bits, 8 bpp pixels being color table indices.
+    pub data: Vec<u8>,
+}
+
+impl CacheBrush {
+    const FIXED_PART_SIZE: usize = 1 /* cacheIndex */ + 1 /* iBitmapFormat */ + 1 /* cx */ + 1 /* cy */ + 1 /* style */ + 1 /* iBytes */;
+
+    fn decode(src: &mut ReadCursor<'_>) -> DecodeResult<Self> {
+        ensure_size!(in: src, size: Self::FIXED_PART_SIZE);
+
+        let cache_index = src.read_u8();
+        let bitmap_format = src.read_u8();
+        let width = src.read_u8();
+        let height = src.read_u8();
+        let style = src.read_u8();
+        let length = usize::from(src.read_u8());
+
+        let bpp = brush_bpp(bitmap_format)
+            .ok_or_else(|| unsupported_value_err!("iBitmapFormat", bitmap_format.to_string()))?;
+
+        if width != 8 || height != 8 {
+            return Err(invalid_field_err!("cx", "brushes must be 8x8 bitmaps"));
+        }
+
+        ensure_size!(in: src, size: length);
+        let brush_data = src.read_slice(length);
+
+        // Rows are stored from bottom to top.
+        let data = if bpp == 1 {
+            if length != 8 {
+                return Err(invalid_field_err!("iBytes", "invalid 1 bpp brush size"));
+            }
+
+            brush_data.iter().rev().copied().collect()
+        } else {
+            let bytes_per_pixel = usize::from(bpp).div_ceil(8);
+            let compressed_length = 16 + 4 * bytes_per_pixel;
+
+            if length == compressed_length {
+                decompress_brush(brush_data, bytes_per_pixel)
+            } else if length == 64 * bytes_per_pixel {
+                brush_data
+                    .chunks_exact(8 * bytes_per_pixel)
+                    .rev()
+                    .flatten()
+                    .copied()
+                    .collect()
+            } else {
+                return Err(invalid_field_err!("iBytes", "invalid brush size"));
+            }
+        };
+
+        Ok(Self {
+            cache_index,
+            bpp,
+            width,
+            height,
+            style,
+            data,
+        })
+    }
+}
+
+/// Decompresses a brush bitmap, made of 2-bit indices in a palette of 4 colors.
+fn decompress_brush(src: &[u8], bytes_per_pixel: usize) -> Vec<u8> {
+    let (indices, palette) = src.split_at(16);
+
+    let mut data = vec![0; 64 * bytes_per_pixel];
+
+    for (y, row) in indices.chunks_exact(2).enumerate() {
+        let dst_row = &mut data[(7 - y) * 8 * bytes_per_pixel..][..8 * bytes_per_pixel];
+
+        for (x, dst) in dst_row.chunks_exact_mut(bytes_per_pixel).enumerate() {
+            let index = usize::from((row[x / 4] >> ((3 - (x % 4)) * 2)) & 0x03);
+            dst.copy_from_slice(&palette[index * bytes_per_pixel..][..bytes_per_pixel]);
+        }
+    }
+
+    data
+}
+
+/// Reads a TS_CD_HEADER structure, returning the size of the compressed bitmap.
+fn read_compression_header(src: &mut ReadCursor<'_>) -> DecodeResult<usize> {
+    ensure_size!(in: src, size: COMPRESSION_HEADER_SIZE);
+
+    let _first_row_size = src.read_u16();
+    let main_body_size = src.read_u16();
+    let _scan_width = src.read_u16();
+    let _uncompressed_size = src.read_u16();
+
+    Ok(usize::from(main_body_size))
+}
+
+/// Returns the bpp of the extra flags of revision 2 and 3 cache bitmap orders.
+fn read_cbr23_bpp(extra_flags: u16) -> DecodeResult<u8> {
+    let id = usize::from((extra_flags & 0x78) >> 3);
+
+    match CBR23_BPP.get(id) {
+        Some(&bpp) if bpp != 0 => Ok(bpp),
+        _ => Err(unsupported_value_err!("bitsPerPixelId", id.to_string())),
+    }
+}
+
+fn read_glyph_bitmap(src: &mut ReadCursor<'_>, x: i16, y: i16, width: u16, height: u16) -> DecodeResult<Glyph> {
+    let size = Glyph::bitmap_size(width, height);
+    ensure_size!(in: src, size: size);
+
+    Ok(Glyph {
+        x,
+        y,
+        width,
+        height,
+        bitmap: src.read_slice(size).to_vec(),
+    })
+}
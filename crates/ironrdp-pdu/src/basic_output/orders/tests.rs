@@ -0,0 +1,289 @@
+use ironrdp_core::{decode, encode_vec};
+
+use super::*;
+
+const ORDERS_UPDATE_BUFFER: [u8; 6] = [
+    0x02, 0x00, // numberOrders
+    0x36, 0x00, 0x00, 0x00, // orderData
+];
+
+fn decode_orders(decoder: &mut OrderDecoder, buffer: &[u8]) -> Vec<DrawingOrder> {
+    let mut src = ReadCursor::new(buffer);
+    let mut orders = Vec::new();
+
+    while !src.is_empty() {
+        orders.push(decoder.decode_order(&mut src).unwrap());
+    }
+
+    orders
+}
+
+#[test]
+fn orders_update_round_trip() {
+    let update = decode::<OrdersUpdate<'_>>(ORDERS_UPDATE_BUFFER.as_ref()).unwrap();
+
+    assert_eq!(update.number_orders, 2);
+    assert_eq!(update.order_data, &ORDERS_UPDATE_BUFFER[2..]);
+    assert_eq!(encode_vec(&update).unwrap(), ORDERS_UPDATE_BUFFER);
+}
+
+#[test]
+fn primary_orders_keep_previous_fields() {
+    #[rustfmt::skip]
+    let buffer = [
+        // OpaqueRect, with a type change and absolute coordinates
+        0x09, 0x0A, 0x7F,
+        0x0A, 0x00, 0x14, 0x00, 0x1E, 0x00, 0x28, 0x00,
+        0xFF, 0x00, 0x80,
+        // OpaqueRect, with delta coordinates for left and top
+        0x11, 0x03, 0x05, 0xFD,
+        // OpaqueRect, without fields but with absolute bounds
+        0x05, 0x00, 0x0F, 0x01, 0x00, 0x02, 0x00, 0x03, 0x00, 0x04, 0x00,
+        // OpaqueRect, without field flags nor bounds
+        0x65,
+    ];
+
+    let orders = decode_orders(&mut OrderDecoder::new(), &buffer);
+
+    let first = OpaqueRect {
+        left: 10,
+        top: 20,
+        width: 30,
+        height: 40,
+        color: Color {
+            red: 0xFF,
+            green: 0x00,
+            blue: 0x80,
+        },
+    };
+    let second = OpaqueRect {
+        left: 15,
+        top: 17,
+        ..first
+    };
+    let bounds = Bounds {
+        left: 1,
+        top: 2,
+        right: 3,
+        bottom: 4,
+    };
+
+    assert_eq!(
+        orders,
+        [
+            DrawingOrder::Primary {
+                order: PrimaryOrder::OpaqueRect(first),
+                bounds: None,
+            },
+            DrawingOrder::Primary {
+                order: PrimaryOrder::OpaqueRect(second.clone()),
+                bounds: None,
+            },
+            DrawingOrder::Primary {
+                order: PrimaryOrder::OpaqueRect(second.clone()),
+                bounds: Some(bounds),
+            },
+            DrawingOrder::Primary {
+                order: PrimaryOrder::OpaqueRect(second),
+                bounds: Some(bounds),
+            },
+        ]
+    );
+}
+
+#[test]
+fn multi_opaque_rect_delta_rectangles_are_resolved() {
+    #[rustfmt::skip]
+    let buffer = [
+        0x09, 0x12, 0xFF, 0x01,
+        0x00, 0x00, 0x00, 0x00, 0x64, 0x00, 0x64, 0x00,
+        0x01, 0x02, 0x03,
+        // numRectangles
+        0x02,
+        // cbData
+        0x07, 0x00,
+        // zeroBits
+        0x07,
+        // first rectangle
+        0x0A, 0x14, 0x05, 0x06,
+        // second rectangle, with a two-byte left delta
+        0x80, 0x64,
+    ];
+
+    let orders = decode_orders(&mut OrderDecoder::new(), &buffer);
+
+    let DrawingOrder::Primary {
+        order: PrimaryOrder::MultiOpaqueRect(order),
+        ..
+    } = &orders[0]
+    else {
+        panic!("unexpected order: {orders:?}");
+    };
+
+    assert_eq!(
+        order.color,
+        Color {
+            red: 1,
+            green: 2,
+            blue: 3
+        }
+    );
+    assert_eq!(
+        order.rectangles,
+        [
+            Rectangle {
+                left: 10,
+                top: 20,
+                width: 5,
+                height: 6,
+            },
+            Rectangle {
+                left: 110,
+                top: 20,
+                width: 5,
+                height: 6,
+            },
+        ]
+    );
+}
+
+#[test]
+fn fast_glyph_with_glyph_definition() {
+    #[rustfmt::skip]
+    let buffer = [
+        0x09, 0x18, 0x0F, 0x70,
+        // cacheId, fDrawing
+        0x03, 0x00, 0x20,
+        // backColor, foreColor
+        0x11, 0x22, 0x33, 0x44, 0x55, 0x66,
+        // x, y
+        0x64, 0x00, 0x32, 0x00,
+        // data, with an unpadded glyph bitmap
+        0x07, 0x05, 0x00, 0x4A, 0x08, 0x02, 0xFF, 0x81,
+    ];
+
+    let orders = decode_orders(&mut OrderDecoder::new(), &buffer);
+
+    let DrawingOrder::Primary {
+        order: PrimaryOrder::FastGlyph(order),
+        ..
+    } = &orders[0]
+    else {
+        panic!("unexpected order: {orders:?}");
+    };
+
+    assert_eq!(order.cache_id, 3);
+    assert_eq!(order.flags, GlyphFlags::SO_CHAR_INC_EQUAL_BM_BASE);
+    assert_eq!((order.x, order.y), (100, 50));
+    assert_eq!(order.cache_index, 5);
+    assert_eq!(
+        order.glyph,
+        Some(Glyph {
+            x: 0,
+            y: -10,
+            width: 8,
+            height: 2,
+            bitmap: vec![0xFF, 0x81, 0x00, 0x00],
+        })
+    );
+}
+
+#[test]
+fn secondary_orders() {
+    #[rustfmt::skip]
+    let buffer = [
+        // Cache Glyph - Revision 1
+        0x03, 0x09, 0x00, 0x00, 0x00, 0x03,
+        0x07, 0x01,
+        0x02, 0x00, 0xFF, 0xFF, 0x03, 0x00, 0x08, 0x00, 0x02, 0x00,
+        0xAA, 0x55, 0x00, 0x00,
+        // Unsupported order, which is skipped
+        0x03, 0x00, 0x00, 0x00, 0x00, 0x06,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+        // Cache Bitmap - Revision 2, with the height same as the width
+        0x03, 0x09, 0x00, 0xA9, 0x00, 0x04,
+        0x02, 0x0C, 0x81, 0x00,
+        0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C,
+    ];
+
+    let orders = decode_orders(&mut OrderDecoder::new(), &buffer);
+
+    assert_eq!(
+        orders,
+        [
+            DrawingOrder::Secondary(SecondaryOrder::CacheGlyph(CacheGlyph {
+                cache_id: 7,
+                glyphs: vec![CachedGlyph {
+                    cache_index: 2,
+                    glyph: Glyph {
+                        x: -1,
+                        y: 3,
+                        width: 8,
+                        height: 2,
+                        bitmap: vec![0xAA, 0x55, 0x00, 0x00],
+                    },
+                }],
+            })),
+            DrawingOrder::Secondary(SecondaryOrder::Other {
+                order_type: 0x06,
+                extra_flags: 0,
+                data: (1..=7).collect(),
+            }),
+            DrawingOrder::Secondary(SecondaryOrder::CacheBitmapV2(CacheBitmapV2 {
+                cache_id: 1,
+                cache_index: 256,
+                width: 2,
+                height: 2,
+                bpp: 24,
+                compressed: false,
+                persistent_key: None,
+                do_not_cache: false,
+                data: (1..=12).collect(),
+            })),
+        ]
+    );
+}
+
+#[test]
+fn compressed_brush_is_flipped() {
+    #[rustfmt::skip]
+    let buffer = [
+        0x03, 0x13, 0x00, 0x00, 0x00, 0x07,
+        0x01, 0x03, 0x08, 0x08, 0x00, 0x14,
+        // indices, from the bottom row to the top row
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF,
+        // palette
+        0x0A, 0x14, 0x1E, 0x28,
+    ];
+
+    let orders = decode_orders(&mut OrderDecoder::new(), &buffer);
+
+    let DrawingOrder::Secondary(SecondaryOrder::CacheBrush(brush)) = &orders[0] else {
+        panic!("unexpected order: {orders:?}");
+    };
+
+    assert_eq!(brush.bpp, 8);
+    assert_eq!(brush.data[..8], [0x28; 8]);
+    assert!(brush.data[8..].iter().all(|&pixel| pixel == 0x0A));
+}
+
+#[test]
+fn alternate_secondary_orders() {
+    let buffer = [
+        0x36, 0x00, 0x00, 0x00, 0x00, 0x02, 0xFF, 0xFF, 0x36, 0x01, 0x00, 0x00, 0x00,
+    ];
+
+    let orders = decode_orders(&mut OrderDecoder::new(), &buffer);
+
+    assert_eq!(
+        orders,
+        [
+            DrawingOrder::AlternateSecondary(AlternateSecondaryOrder::FrameMarker(FrameMarker::Begin)),
+            DrawingOrder::AlternateSecondary(AlternateSecondaryOrder::SwitchSurface {
+                bitmap_id: SCREEN_BITMAP_SURFACE,
+            }),
+            DrawingOrder::AlternateSecondary(AlternateSecondaryOrder::FrameMarker(FrameMarker::End)),
+        ]
+    );
+}
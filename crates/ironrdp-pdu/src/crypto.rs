@@ -1,2 +1,2 @@
-pub(crate) mod rc4;
+pub mod rc4;
 pub(crate) mod rsa;
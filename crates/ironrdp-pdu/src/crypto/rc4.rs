@@ -1,14 +1,15 @@
 use core::{fmt, ops};
 
+/// RC4 stream cipher, used by the licensing protocol and Standard RDP Security
 #[derive(Debug, Clone)]
-pub(crate) struct Rc4 {
+pub struct Rc4 {
     i: usize,
     j: usize,
     state: State,
 }
 
 impl Rc4 {
-    pub(crate) fn new(key: &[u8]) -> Self {
+    pub fn new(key: &[u8]) -> Self {
         // key scheduling
         let mut state = State::default();
         for (i, item) in state.iter_mut().enumerate().take(256) {
@@ -23,7 +24,7 @@ impl Rc4 {
         Self { i: 0, j: 0, state }
     }
 
-    pub(crate) fn process(&mut self, message: &[u8]) -> Vec<u8> {
+    pub fn process(&mut self, message: &[u8]) -> Vec<u8> {
         // PRGA
         let mut output = Vec::with_capacity(message.len());
         while output.capacity() > output.len() {
@@ -17,7 +17,7 @@ const SERVER_ENCRYPTION_LEVEL_SIZE: usize = 4;
 const SERVER_RANDOM_LEN_SIZE: usize = 4;
 const SERVER_CERT_LEN_SIZE: usize = 4;
 const SERVER_RANDOM_LEN: usize = 0x20;
-const MAX_SERVER_CERT_LEN: usize = 1024;
+const MAX_SERVER_CERT_LEN: usize = 8192;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ClientSecurityData {
@@ -16,6 +16,7 @@ use ironrdp_error::Source;
 mod macros;
 
 pub mod codecs;
+pub mod crypto;
 pub mod gcc;
 pub mod geometry;
 pub mod input;
@@ -32,10 +33,9 @@ pub mod x224;
 
 pub(crate) mod basic_output;
 pub(crate) mod ber;
-pub(crate) mod crypto;
 pub(crate) mod per;
 
-pub use crate::basic_output::{bitmap, fast_path, pointer, surface_commands};
+pub use crate::basic_output::{bitmap, fast_path, orders, pointer, surface_commands};
 pub use crate::rdp::vc::dvc;
 
 pub type PduResult<T> = Result<T, PduError>;
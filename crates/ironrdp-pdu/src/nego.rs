@@ -160,6 +160,10 @@ impl fmt::Display for FailureCode {
 pub enum NegoRequestData {
     RoutingToken(RoutingToken),
     Cookie(Cookie),
+    /// Load balancing info of a server redirection, sent verbatim as the routing token
+    ///
+    /// It is never produced when decoding.
+    LoadBalanceInfo(LoadBalanceInfo),
 }
 
 impl NegoRequestData {
@@ -171,6 +175,10 @@ impl NegoRequestData {
         Self::Cookie(Cookie(value))
     }
 
+    pub fn load_balance_info(value: Vec<u8>) -> Self {
+        Self::LoadBalanceInfo(LoadBalanceInfo(value))
+    }
+
     pub fn read(src: &mut ReadCursor<'_>) -> DecodeResult<Option<Self>> {
         match RoutingToken::read(src)? {
             Some(token) => Ok(Some(Self::RoutingToken(token))),
@@ -182,6 +190,7 @@ impl NegoRequestData {
         match self {
             NegoRequestData::RoutingToken(token) => token.write(dst),
             NegoRequestData::Cookie(cookie) => cookie.write(dst),
+            NegoRequestData::LoadBalanceInfo(info) => info.write(dst),
         }
     }
 
@@ -189,6 +198,7 @@ impl NegoRequestData {
         match self {
             NegoRequestData::RoutingToken(token) => token.size(),
             NegoRequestData::Cookie(cookie) => cookie.size(),
+            NegoRequestData::LoadBalanceInfo(info) => info.size(),
         }
     }
 }
@@ -231,6 +241,38 @@ impl RoutingToken {
     }
 }
 
+/// Raw routing token, such as the load balancing info of a server redirection
+///
+/// The info is written as is, and the terminating CR+LF is only added when missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadBalanceInfo(pub Vec<u8>);
+
+impl LoadBalanceInfo {
+    fn is_terminated(&self) -> bool {
+        self.0.ends_with(b"\r\n")
+    }
+
+    pub fn write(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(ctx: "LoadBalanceInfo", in: dst, size: self.size());
+
+        dst.write_slice(&self.0);
+
+        if !self.is_terminated() {
+            dst.write_u16(0x0A0D);
+        }
+
+        Ok(())
+    }
+
+    pub fn size(&self) -> usize {
+        if self.is_terminated() {
+            self.0.len()
+        } else {
+            self.0.len() + 2
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 struct NegoMsgType(u8);
 
@@ -1,7 +1,8 @@
 use std::io;
 
 use ironrdp_core::{
-    ensure_fixed_part_size, invalid_field_err, Decode, DecodeResult, Encode, EncodeResult, ReadCursor, WriteCursor,
+    cast_length, ensure_fixed_part_size, ensure_size, invalid_field_err, Decode, DecodeResult, Encode, EncodeResult,
+    ReadCursor, WriteCursor,
 };
 use thiserror::Error;
 
@@ -19,6 +20,7 @@ pub mod headers;
 pub mod refresh_rectangle;
 pub mod server_error_info;
 pub mod server_license;
+pub mod server_redirection;
 pub mod session_info;
 pub mod suppress_output;
 pub mod vc;
@@ -72,6 +74,63 @@ impl<'de> Decode<'de> for ClientInfoPdu {
     }
 }
 
+/// 2.2.1.10 Client Security Exchange PDU
+///
+/// Carries the client random, encrypted with the public key of the server certificate, when
+/// Standard RDP Security is in effect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityExchangePdu {
+    pub security_header: BasicSecurityHeader,
+    /// Encrypted client random, including the eight bytes of zero padding.
+    pub encrypted_client_random: Vec<u8>,
+}
+
+impl SecurityExchangePdu {
+    const NAME: &'static str = "SecurityExchangePdu";
+
+    const FIXED_PART_SIZE: usize = BasicSecurityHeader::FIXED_PART_SIZE + 4 /* length */;
+}
+
+impl Encode for SecurityExchangePdu {
+    fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+
+        self.security_header.encode(dst)?;
+        dst.write_u32(cast_length!("length", self.encrypted_client_random.len())?);
+        dst.write_slice(&self.encrypted_client_random);
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn size(&self) -> usize {
+        Self::FIXED_PART_SIZE + self.encrypted_client_random.len()
+    }
+}
+
+impl<'de> Decode<'de> for SecurityExchangePdu {
+    fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+
+        let security_header = BasicSecurityHeader::decode(src)?;
+        if !security_header.flags.contains(BasicSecurityHeaderFlags::EXCHANGE_PKT) {
+            return Err(invalid_field_err!("securityHeader", "got invalid security header"));
+        }
+
+        let length = cast_length!("length", src.read_u32())?;
+        ensure_size!(in: src, size: length);
+        let encrypted_client_random = src.read_slice(length).into();
+
+        Ok(Self {
+            security_header,
+            encrypted_client_random,
+        })
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum RdpError {
     #[error("IO error")]
@@ -14,6 +14,7 @@ use crate::utils::CharacterSet;
 use crate::{utils, PduError};
 
 const RECONNECT_COOKIE_LEN: usize = 28;
+const AUTO_RECONNECT_VERSION_1: u32 = 0x0000_0001;
 const TIMEZONE_INFO_NAME_LEN: usize = 64;
 const COMPRESSION_TYPE_MASK: u32 = 0x0000_1E00;
 
@@ -262,7 +263,7 @@ pub struct ExtendedClientOptionalInfo {
     timezone: Option<TimezoneInfo>,
     session_id: Option<u32>,
     performance_flags: Option<PerformanceFlags>,
-    reconnect_cookie: Option<[u8; RECONNECT_COOKIE_LEN]>,
+    reconnect_cookie: Option<ClientAutoReconnectPacket>,
     // other fields are read by RdpVersion::Ten+
 }
 
@@ -290,7 +291,7 @@ impl ExtendedClientOptionalInfo {
         self.performance_flags
     }
 
-    pub fn reconnect_cookie(&self) -> Option<&[u8; RECONNECT_COOKIE_LEN]> {
+    pub fn reconnect_cookie(&self) -> Option<&ClientAutoReconnectPacket> {
         self.reconnect_cookie.as_ref()
     }
 }
@@ -308,9 +309,9 @@ impl Encode for ExtendedClientOptionalInfo {
         if let Some(performance_flags) = self.performance_flags {
             dst.write_u32(performance_flags.bits());
         }
-        if let Some(reconnect_cookie) = self.reconnect_cookie {
+        if let Some(ref reconnect_cookie) = self.reconnect_cookie {
             dst.write_u16(RECONNECT_COOKIE_LEN as u16);
-            dst.write_array(reconnect_cookie);
+            reconnect_cookie.encode(dst)?;
         }
 
         Ok(())
@@ -373,7 +374,7 @@ impl<'de> Decode<'de> for ExtendedClientOptionalInfo {
             if src.len() < RECONNECT_COOKIE_LEN {
                 return Err(invalid_field_err!("cbAutoReconnectCookie", "missing cookie data"));
             }
-            optional_data.reconnect_cookie = Some(src.read_array());
+            optional_data.reconnect_cookie = Some(ClientAutoReconnectPacket::decode(src)?);
         }
 
         if src.len() < 2 * 2 {
@@ -386,6 +387,68 @@ impl<'de> Decode<'de> for ExtendedClientOptionalInfo {
     }
 }
 
+/// 2.2.4.3 Client Auto-Reconnect Packet (ARC_CS_PRIVATE_PACKET)
+///
+/// Sent by the client to reconnect to its previous session, using the cookie received from the server
+/// in the Save Session Info PDU.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientAutoReconnectPacket {
+    pub logon_id: u32,
+    /// HMAC-MD5 of the client random, keyed with the random bits of the server cookie
+    pub security_verifier: [u8; 16],
+}
+
+impl ClientAutoReconnectPacket {
+    const NAME: &'static str = "ClientAutoReconnectPacket";
+
+    const FIXED_PART_SIZE: usize = RECONNECT_COOKIE_LEN;
+}
+
+impl Encode for ClientAutoReconnectPacket {
+    fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_fixed_part_size!(in: dst);
+
+        dst.write_u32(RECONNECT_COOKIE_LEN as u32);
+        dst.write_u32(AUTO_RECONNECT_VERSION_1);
+        dst.write_u32(self.logon_id);
+        dst.write_array(self.security_verifier);
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn size(&self) -> usize {
+        Self::FIXED_PART_SIZE
+    }
+}
+
+impl<'de> Decode<'de> for ClientAutoReconnectPacket {
+    fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+
+        let length = src.read_u32();
+        if length != RECONNECT_COOKIE_LEN as u32 {
+            return Err(invalid_field_err!("cbLen", "invalid auto-reconnect packet size"));
+        }
+
+        let version = src.read_u32();
+        if version != AUTO_RECONNECT_VERSION_1 {
+            return Err(invalid_field_err!("version", "invalid auto-reconnect version"));
+        }
+
+        let logon_id = src.read_u32();
+        let security_verifier = src.read_array();
+
+        Ok(Self {
+            logon_id,
+            security_verifier,
+        })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TimezoneInfo {
     pub bias: u32,
@@ -783,7 +846,7 @@ pub mod builder {
     impl ExtendedClientOptionalInfoBuilder<ExtendedClientOptionalInfoBuilderStateSetReconnectCookie> {
         pub fn reconnect_cookie(
             mut self,
-            reconnect_cookie: [u8; RECONNECT_COOKIE_LEN],
+            reconnect_cookie: ClientAutoReconnectPacket,
         ) -> ExtendedClientOptionalInfoBuilder<ExtendedClientOptionalInfoBuilderStateFinal> {
             self.inner.reconnect_cookie = Some(reconnect_cookie);
             ExtendedClientOptionalInfoBuilder {
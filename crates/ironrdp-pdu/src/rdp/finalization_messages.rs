@@ -1,7 +1,7 @@
 use bitflags::bitflags;
 use ironrdp_core::{
-    cast_length, ensure_fixed_part_size, invalid_field_err, Decode, DecodeResult, Encode, EncodeResult, ReadCursor,
-    WriteCursor,
+    cast_length, ensure_fixed_part_size, ensure_size, invalid_field_err, Decode, DecodeResult, Encode, EncodeResult,
+    ReadCursor, WriteCursor,
 };
 use num_derive::{FromPrimitive, ToPrimitive};
 use num_traits::{FromPrimitive as _, ToPrimitive as _};
@@ -14,6 +14,9 @@ const FONT_PDU_SIZE: usize = 2 * 4;
 const SYNCHRONIZE_MESSAGE_TYPE: u16 = 1;
 const MAX_MONITOR_COUNT: u32 = 64;
 
+/// Number of cell caches described by the Persistent Key List PDU
+pub const PERSISTENT_KEY_LIST_CACHES_NUM: usize = 5;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SynchronizePdu {
     pub target_user_id: u16,
@@ -229,6 +232,161 @@ impl<'de> Decode<'de> for MonitorLayoutPdu {
     }
 }
 
+/// 2.2.1.17.1 Persistent Key List PDU Data (TS_BITMAPCACHE_PERSISTENT_LIST_PDU)
+///
+/// Sent by the client during the connection finalization to tell the server which bitmaps are
+/// loaded in its persistent bitmap caches. The keys of a cell cache may be split over several PDUs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistentKeyListPdu {
+    /// Number of entries of each cell cache in this PDU
+    pub num_entries: [u16; PERSISTENT_KEY_LIST_CACHES_NUM],
+    /// Number of entries of each cell cache in all the PDUs of the sequence
+    pub total_entries: [u16; PERSISTENT_KEY_LIST_CACHES_NUM],
+    pub flags: PersistentKeyListFlags,
+    /// 64-bit keys of the entries, ordered by cell cache
+    pub entries: Vec<u64>,
+}
+
+impl PersistentKeyListPdu {
+    const NAME: &'static str = "PersistentKeyListPdu";
+
+    const FIXED_PART_SIZE: usize = PERSISTENT_KEY_LIST_CACHES_NUM * 2 /* numEntriesCache */
+        + PERSISTENT_KEY_LIST_CACHES_NUM * 2 /* totalEntriesCache */
+        + 1 /* bBitMask */
+        + 1 /* Pad2 */
+        + 2 /* Pad3 */;
+
+    const ENTRY_SIZE: usize = 8;
+
+    /// Maximum number of entries in a single PDU
+    pub const MAX_ENTRIES: usize = 169;
+
+    /// Maximum number of entries in all the PDUs of the sequence
+    pub const MAX_TOTAL_ENTRIES: usize = 262_144;
+
+    /// Splits the keys of each cell cache into the sequence of PDUs to send.
+    ///
+    /// Returns `None` if there are more than 5 cell caches, if a cell cache has more than `u16::MAX` keys,
+    /// or if there are more than [`Self::MAX_TOTAL_ENTRIES`] keys in total.
+    pub fn from_keys(keys: &[Vec<u64>]) -> Option<Vec<Self>> {
+        if keys.len() > PERSISTENT_KEY_LIST_CACHES_NUM
+            || keys.iter().map(Vec::len).sum::<usize>() > Self::MAX_TOTAL_ENTRIES
+        {
+            return None;
+        }
+
+        let mut total_entries = [0; PERSISTENT_KEY_LIST_CACHES_NUM];
+
+        for (total, keys) in total_entries.iter_mut().zip(keys) {
+            *total = u16::try_from(keys.len()).ok()?;
+        }
+
+        let mut pdus = Vec::new();
+        let mut current = Self {
+            num_entries: [0; PERSISTENT_KEY_LIST_CACHES_NUM],
+            total_entries,
+            flags: PersistentKeyListFlags::FIRST,
+            entries: Vec::new(),
+        };
+
+        for (cache_id, keys) in keys.iter().enumerate() {
+            for &key in keys {
+                if current.entries.len() == Self::MAX_ENTRIES {
+                    let next = Self {
+                        num_entries: [0; PERSISTENT_KEY_LIST_CACHES_NUM],
+                        total_entries,
+                        flags: PersistentKeyListFlags::empty(),
+                        entries: Vec::new(),
+                    };
+
+                    pdus.push(core::mem::replace(&mut current, next));
+                }
+
+                current.num_entries[cache_id] += 1;
+                current.entries.push(key);
+            }
+        }
+
+        current.flags |= PersistentKeyListFlags::LAST;
+        pdus.push(current);
+
+        Some(pdus)
+    }
+}
+
+impl Encode for PersistentKeyListPdu {
+    fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+
+        let num_entries = self.num_entries.iter().map(|&n| usize::from(n)).sum::<usize>();
+
+        if num_entries != self.entries.len() || num_entries > Self::MAX_ENTRIES {
+            return Err(invalid_field_err!("numEntriesCache", "invalid number of entries"));
+        }
+
+        for num_entries in self.num_entries {
+            dst.write_u16(num_entries);
+        }
+
+        for total_entries in self.total_entries {
+            dst.write_u16(total_entries);
+        }
+
+        dst.write_u8(self.flags.bits());
+        write_padding!(dst, 3);
+
+        for &key in &self.entries {
+            // Key1 holds the low 32 bits of the key, and Key2 the high ones.
+            dst.write_u64(key);
+        }
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn size(&self) -> usize {
+        Self::FIXED_PART_SIZE + self.entries.len() * Self::ENTRY_SIZE
+    }
+}
+
+impl<'de> Decode<'de> for PersistentKeyListPdu {
+    fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+
+        let mut num_entries = [0; PERSISTENT_KEY_LIST_CACHES_NUM];
+        for num_entries in num_entries.iter_mut() {
+            *num_entries = src.read_u16();
+        }
+
+        let mut total_entries = [0; PERSISTENT_KEY_LIST_CACHES_NUM];
+        for total_entries in total_entries.iter_mut() {
+            *total_entries = src.read_u16();
+        }
+
+        let flags = PersistentKeyListFlags::from_bits_truncate(src.read_u8());
+        read_padding!(src, 3);
+
+        let entry_count = num_entries.iter().map(|&n| usize::from(n)).sum::<usize>();
+        if entry_count > Self::MAX_ENTRIES {
+            return Err(invalid_field_err!("numEntriesCache", "too many entries"));
+        }
+
+        ensure_size!(in: src, size: entry_count * Self::ENTRY_SIZE);
+
+        let entries = (0..entry_count).map(|_| src.read_u64()).collect();
+
+        Ok(Self {
+            num_entries,
+            total_entries,
+            flags,
+            entries,
+        })
+    }
+}
+
 #[repr(u16)]
 #[derive(Debug, Clone, PartialEq, Eq, FromPrimitive, ToPrimitive)]
 pub enum ControlAction {
@@ -245,3 +403,13 @@ bitflags! {
         const LAST = 2;
     }
 }
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct PersistentKeyListFlags: u8 {
+        /// PERSIST_FIRST_PDU
+        const FIRST = 0x01;
+        /// PERSIST_LAST_PDU
+        const LAST = 0x02;
+    }
+}
@@ -10,16 +10,18 @@ use crate::codecs::rfx::FrameAcknowledgePdu;
 use crate::input::InputEventPdu;
 use crate::rdp::capability_sets::{ClientConfirmActive, ServerDemandActive};
 use crate::rdp::client_info;
-use crate::rdp::finalization_messages::{ControlPdu, FontPdu, MonitorLayoutPdu, SynchronizePdu};
+use crate::rdp::finalization_messages::{ControlPdu, FontPdu, MonitorLayoutPdu, PersistentKeyListPdu, SynchronizePdu};
 use crate::rdp::refresh_rectangle::RefreshRectanglePdu;
 use crate::rdp::server_error_info::ServerSetErrorInfoPdu;
+use crate::rdp::server_redirection::ServerRedirectionPdu;
 use crate::rdp::session_info::SaveSessionInfoPdu;
 use crate::rdp::suppress_output::SuppressOutputPdu;
 
 pub const BASIC_SECURITY_HEADER_SIZE: usize = 4;
 pub const SHARE_DATA_HEADER_COMPRESSION_MASK: u8 = 0xF;
 const SHARE_CONTROL_HEADER_MASK: u16 = 0xF;
-const SHARE_CONTROL_HEADER_SIZE: usize = 2 * 3 + 4;
+const SHARE_CONTROL_HEADER_SIZE: usize = 2 * 3 + SHARE_ID_FIELD_SIZE;
+const SHARE_ID_FIELD_SIZE: usize = 4;
 
 const PROTOCOL_VERSION: u16 = 0x10;
 
@@ -83,6 +85,15 @@ impl ShareControlHeader {
     const NAME: &'static str = "ShareControlHeader";
 
     const FIXED_PART_SIZE: usize = SHARE_CONTROL_HEADER_SIZE;
+
+    /// Size of the header, the Enhanced Security Server Redirection PDU having no shareId field
+    fn header_size(&self) -> usize {
+        if matches!(self.share_control_pdu, ShareControlPdu::ServerRedirect(_)) {
+            SHARE_CONTROL_HEADER_SIZE - SHARE_ID_FIELD_SIZE
+        } else {
+            SHARE_CONTROL_HEADER_SIZE
+        }
+    }
 }
 
 impl Encode for ShareControlHeader {
@@ -91,13 +102,12 @@ impl Encode for ShareControlHeader {
 
         let pdu_type_with_version = PROTOCOL_VERSION | self.share_control_pdu.share_header_type().to_u16().unwrap();
 
-        dst.write_u16(cast_length!(
-            "len",
-            self.share_control_pdu.size() + SHARE_CONTROL_HEADER_SIZE
-        )?);
+        dst.write_u16(cast_length!("len", self.size())?);
         dst.write_u16(pdu_type_with_version);
         dst.write_u16(self.pdu_source);
-        dst.write_u32(self.share_id);
+        if self.header_size() == SHARE_CONTROL_HEADER_SIZE {
+            dst.write_u32(self.share_id);
+        }
 
         self.share_control_pdu.encode(dst)
     }
@@ -107,7 +117,7 @@ impl Encode for ShareControlHeader {
     }
 
     fn size(&self) -> usize {
-        Self::FIXED_PART_SIZE + self.share_control_pdu.size()
+        self.header_size() + self.share_control_pdu.size()
     }
 }
 
@@ -118,7 +128,6 @@ impl<'de> Decode<'de> for ShareControlHeader {
         let total_length = src.read_u16() as usize;
         let pdu_type_with_version = src.read_u16();
         let pdu_source = src.read_u16();
-        let share_id = src.read_u32();
 
         let pdu_type = ShareControlPduType::from_u16(pdu_type_with_version & SHARE_CONTROL_HEADER_MASK)
             .ok_or_else(|| invalid_field_err!("pdu_type", "invalid pdu type"))?;
@@ -127,6 +136,13 @@ impl<'de> Decode<'de> for ShareControlHeader {
             return Err(invalid_field_err!("pdu_version", "invalid PDU version"));
         }
 
+        // The Enhanced Security Server Redirection PDU has no shareId field.
+        let share_id = if pdu_type == ShareControlPduType::ServerRedirect {
+            0
+        } else {
+            src.read_u32()
+        };
+
         let share_pdu = ShareControlPdu::from_type(src, pdu_type)?;
         let header = Self {
             share_control_pdu: share_pdu,
@@ -134,10 +150,13 @@ impl<'de> Decode<'de> for ShareControlHeader {
             share_id,
         };
 
-        if pdu_type == ShareControlPduType::DataPdu {
+        if matches!(
+            pdu_type,
+            ShareControlPduType::DataPdu | ShareControlPduType::ServerRedirect
+        ) {
             // Some windows version have an issue where
             // there is some padding not part of the inner unit.
-            // Consume that data
+            // Consume that data (the redirection PDU may also end with a padding byte)
             let header_length = header.size();
 
             if header_length != total_length {
@@ -161,6 +180,8 @@ pub enum ShareControlPdu {
     ClientConfirmActive(ClientConfirmActive),
     Data(ShareDataHeader),
     ServerDeactivateAll(ServerDeactivateAll),
+    /// Enhanced Security Server Redirection PDU
+    ServerRedirect(ServerRedirectionPdu),
 }
 
 impl ShareControlPdu {
@@ -172,6 +193,7 @@ impl ShareControlPdu {
             ShareControlPdu::ClientConfirmActive(_) => "Client Confirm Active PDU",
             ShareControlPdu::Data(_) => "Data PDU",
             ShareControlPdu::ServerDeactivateAll(_) => "Server Deactivate All PDU",
+            ShareControlPdu::ServerRedirect(_) => "Server Redirection PDU",
         }
     }
 
@@ -181,6 +203,7 @@ impl ShareControlPdu {
             ShareControlPdu::ClientConfirmActive(_) => ShareControlPduType::ConfirmActivePdu,
             ShareControlPdu::Data(_) => ShareControlPduType::DataPdu,
             ShareControlPdu::ServerDeactivateAll(_) => ShareControlPduType::DeactivateAllPdu,
+            ShareControlPdu::ServerRedirect(_) => ShareControlPduType::ServerRedirect,
         }
     }
 
@@ -196,7 +219,12 @@ impl ShareControlPdu {
             ShareControlPduType::DeactivateAllPdu => {
                 Ok(ShareControlPdu::ServerDeactivateAll(ServerDeactivateAll::decode(src)?))
             }
-            _ => Err(invalid_field_err!("share_type", "unexpected share control PDU type")),
+            ShareControlPduType::ServerRedirect => {
+                ensure_size!(in: src, size: 2);
+                read_padding!(src, 2);
+
+                Ok(ShareControlPdu::ServerRedirect(ServerRedirectionPdu::decode(src)?))
+            }
         }
     }
 }
@@ -208,6 +236,12 @@ impl Encode for ShareControlPdu {
             ShareControlPdu::ClientConfirmActive(pdu) => pdu.encode(dst),
             ShareControlPdu::Data(share_data_header) => share_data_header.encode(dst),
             ShareControlPdu::ServerDeactivateAll(deactivate_all) => deactivate_all.encode(dst),
+            ShareControlPdu::ServerRedirect(redirection) => {
+                ensure_size!(in: dst, size: 2);
+                write_padding!(dst, 2);
+
+                redirection.encode(dst)
+            }
         }
     }
 
@@ -221,6 +255,7 @@ impl Encode for ShareControlPdu {
             ShareControlPdu::ClientConfirmActive(pdu) => pdu.size(),
             ShareControlPdu::Data(share_data_header) => share_data_header.size(),
             ShareControlPdu::ServerDeactivateAll(deactivate_all) => deactivate_all.size(),
+            ShareControlPdu::ServerRedirect(redirection) => 2 /* pad2Octets */ + redirection.size(),
         }
     }
 }
@@ -328,7 +363,7 @@ pub enum ShareDataPdu {
     Pointer(Vec<u8>),
     PlaySound(Vec<u8>),
     SetKeyboardIndicators(Vec<u8>),
-    BitmapCachePersistentList(Vec<u8>),
+    BitmapCachePersistentList(PersistentKeyListPdu),
     BitmapCacheErrorPdu(Vec<u8>),
     SetKeyboardImeStatus(Vec<u8>),
     OffscreenCacheErrorPdu(Vec<u8>),
@@ -426,9 +461,9 @@ impl ShareDataPdu {
             ShareDataPduType::SetKeyboardIndicators => {
                 Ok(ShareDataPdu::SetKeyboardIndicators(src.remaining().to_vec()))
             }
-            ShareDataPduType::BitmapCachePersistentList => {
-                Ok(ShareDataPdu::BitmapCachePersistentList(src.remaining().to_vec()))
-            }
+            ShareDataPduType::BitmapCachePersistentList => Ok(ShareDataPdu::BitmapCachePersistentList(
+                PersistentKeyListPdu::decode(src)?,
+            )),
             ShareDataPduType::BitmapCacheErrorPdu => Ok(ShareDataPdu::BitmapCacheErrorPdu(src.remaining().to_vec())),
             ShareDataPduType::SetKeyboardImeStatus => Ok(ShareDataPdu::SetKeyboardImeStatus(src.remaining().to_vec())),
             ShareDataPduType::OffscreenCacheErrorPdu => {
@@ -456,6 +491,7 @@ impl Encode for ShareDataPdu {
             ShareDataPdu::ShutdownRequest | ShareDataPdu::ShutdownDenied => Ok(()),
             ShareDataPdu::SuppressOutput(pdu) => pdu.encode(dst),
             ShareDataPdu::RefreshRectangle(pdu) => pdu.encode(dst),
+            ShareDataPdu::BitmapCachePersistentList(pdu) => pdu.encode(dst),
             _ => Err(other_err!("Encoding not implemented")),
         }
     }
@@ -477,11 +513,11 @@ impl Encode for ShareDataPdu {
             ShareDataPdu::ShutdownRequest | ShareDataPdu::ShutdownDenied => 0,
             ShareDataPdu::SuppressOutput(pdu) => pdu.size(),
             ShareDataPdu::RefreshRectangle(pdu) => pdu.size(),
+            ShareDataPdu::BitmapCachePersistentList(pdu) => pdu.size(),
             ShareDataPdu::Update(buffer)
             | ShareDataPdu::Pointer(buffer)
             | ShareDataPdu::PlaySound(buffer)
             | ShareDataPdu::SetKeyboardIndicators(buffer)
-            | ShareDataPdu::BitmapCachePersistentList(buffer)
             | ShareDataPdu::BitmapCacheErrorPdu(buffer)
             | ShareDataPdu::SetKeyboardImeStatus(buffer)
             | ShareDataPdu::OffscreenCacheErrorPdu(buffer)
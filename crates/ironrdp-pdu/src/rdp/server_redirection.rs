@@ -0,0 +1,329 @@
+use bitflags::bitflags;
+use ironrdp_core::{
+    cast_length, ensure_fixed_part_size, ensure_size, invalid_field_err, Decode, DecodeResult, Encode, EncodeResult,
+    ReadCursor, WriteCursor,
+};
+
+use crate::utils::{self, CharacterSet};
+
+const SEC_REDIRECTION_PKT: u16 = 0x0400;
+
+const LENGTH_FIELD_SIZE: usize = 4;
+
+/// 2.2.13.1 Server Redirection Packet (RDP_SERVER_REDIRECTION_PACKET)
+///
+/// Sent by a server of a load-balanced farm, or by a Connection Broker, to tell the client which server
+/// it should connect to instead.
+///
+/// The flags telling which fields are present are set from the fields themselves when encoding, and are
+/// not kept in `flags` when decoding.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ServerRedirectionPdu {
+    /// Identifier of the session to reconnect to on the target server
+    pub session_id: u32,
+    pub flags: ServerRedirectionFlags,
+    pub target_net_address: Option<String>,
+    /// Routing token to send in the X.224 Connection Request PDU
+    pub load_balance_info: Option<Vec<u8>>,
+    pub username: Option<String>,
+    pub domain: Option<String>,
+    /// Password, or opaque cookie to send in place of the password
+    pub password: Option<Vec<u8>>,
+    pub target_fqdn: Option<String>,
+    pub target_netbios_name: Option<String>,
+    pub tsv_url: Option<Vec<u8>>,
+    pub redirection_guid: Option<Vec<u8>>,
+    /// Base64-encoded certificate container of the target server
+    pub target_certificate: Option<Vec<u8>>,
+    pub target_net_addresses: Option<Vec<String>>,
+}
+
+impl ServerRedirectionPdu {
+    const NAME: &'static str = "ServerRedirectionPdu";
+
+    const FIXED_PART_SIZE: usize = 2 /* flags */ + 2 /* length */ + 4 /* sessionId */ + 4 /* redirFlags */;
+
+    /// Returns the address of the server to connect to, if it is not the same server.
+    pub fn target_address(&self) -> Option<&str> {
+        self.target_net_address
+            .as_deref()
+            .or(self.target_fqdn.as_deref())
+            .or(self.target_netbios_name.as_deref())
+    }
+
+    fn present_fields(&self) -> ServerRedirectionFlags {
+        let mut flags = ServerRedirectionFlags::empty();
+
+        flags.set(
+            ServerRedirectionFlags::TARGET_NET_ADDRESS,
+            self.target_net_address.is_some(),
+        );
+        flags.set(
+            ServerRedirectionFlags::LOAD_BALANCE_INFO,
+            self.load_balance_info.is_some(),
+        );
+        flags.set(ServerRedirectionFlags::USERNAME, self.username.is_some());
+        flags.set(ServerRedirectionFlags::DOMAIN, self.domain.is_some());
+        flags.set(ServerRedirectionFlags::PASSWORD, self.password.is_some());
+        flags.set(ServerRedirectionFlags::TARGET_FQDN, self.target_fqdn.is_some());
+        flags.set(
+            ServerRedirectionFlags::TARGET_NETBIOS_NAME,
+            self.target_netbios_name.is_some(),
+        );
+        flags.set(ServerRedirectionFlags::CLIENT_TSV_URL, self.tsv_url.is_some());
+        flags.set(
+            ServerRedirectionFlags::REDIRECTION_GUID,
+            self.redirection_guid.is_some(),
+        );
+        flags.set(
+            ServerRedirectionFlags::TARGET_CERTIFICATE,
+            self.target_certificate.is_some(),
+        );
+        flags.set(
+            ServerRedirectionFlags::TARGET_NET_ADDRESSES,
+            self.target_net_addresses.is_some(),
+        );
+
+        flags
+    }
+}
+
+impl Encode for ServerRedirectionPdu {
+    fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+
+        dst.write_u16(SEC_REDIRECTION_PKT);
+        dst.write_u16(cast_length!("length", self.size())?);
+        dst.write_u32(self.session_id);
+        dst.write_u32(((self.flags - ServerRedirectionFlags::FIELDS) | self.present_fields()).bits());
+
+        if let Some(target_net_address) = &self.target_net_address {
+            write_string(dst, target_net_address)?;
+        }
+        if let Some(load_balance_info) = &self.load_balance_info {
+            write_bytes(dst, load_balance_info)?;
+        }
+        if let Some(username) = &self.username {
+            write_string(dst, username)?;
+        }
+        if let Some(domain) = &self.domain {
+            write_string(dst, domain)?;
+        }
+        if let Some(password) = &self.password {
+            write_bytes(dst, password)?;
+        }
+        if let Some(target_fqdn) = &self.target_fqdn {
+            write_string(dst, target_fqdn)?;
+        }
+        if let Some(target_netbios_name) = &self.target_netbios_name {
+            write_string(dst, target_netbios_name)?;
+        }
+        if let Some(tsv_url) = &self.tsv_url {
+            write_bytes(dst, tsv_url)?;
+        }
+        if let Some(redirection_guid) = &self.redirection_guid {
+            write_bytes(dst, redirection_guid)?;
+        }
+        if let Some(target_certificate) = &self.target_certificate {
+            write_bytes(dst, target_certificate)?;
+        }
+        if let Some(target_net_addresses) = &self.target_net_addresses {
+            dst.write_u32(cast_length!(
+                "targetNetAddressesLength",
+                target_net_addresses_size(target_net_addresses) - LENGTH_FIELD_SIZE
+            )?);
+            dst.write_u32(cast_length!("addressCount", target_net_addresses.len())?);
+
+            for address in target_net_addresses {
+                write_string(dst, address)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn size(&self) -> usize {
+        let strings = [
+            &self.target_net_address,
+            &self.username,
+            &self.domain,
+            &self.target_fqdn,
+            &self.target_netbios_name,
+        ];
+
+        let byte_arrays = [
+            &self.load_balance_info,
+            &self.password,
+            &self.tsv_url,
+            &self.redirection_guid,
+            &self.target_certificate,
+        ];
+
+        Self::FIXED_PART_SIZE
+            + strings
+                .into_iter()
+                .flatten()
+                .map(|value| string_size(value))
+                .sum::<usize>()
+            + byte_arrays
+                .into_iter()
+                .flatten()
+                .map(|value| LENGTH_FIELD_SIZE + value.len())
+                .sum::<usize>()
+            + self
+                .target_net_addresses
+                .as_deref()
+                .map(target_net_addresses_size)
+                .unwrap_or(0)
+    }
+}
+
+impl<'de> Decode<'de> for ServerRedirectionPdu {
+    fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+
+        let start = src.pos();
+
+        let flags = src.read_u16();
+        if flags != SEC_REDIRECTION_PKT {
+            return Err(invalid_field_err!("flags", "invalid server redirection flags"));
+        }
+
+        let length = usize::from(src.read_u16());
+        let session_id = src.read_u32();
+        let redirection_flags = ServerRedirectionFlags::from_bits_retain(src.read_u32());
+
+        let mut pdu = Self {
+            session_id,
+            flags: redirection_flags - ServerRedirectionFlags::FIELDS,
+            ..Self::default()
+        };
+
+        if redirection_flags.contains(ServerRedirectionFlags::TARGET_NET_ADDRESS) {
+            pdu.target_net_address = Some(read_string(src)?);
+        }
+        if redirection_flags.contains(ServerRedirectionFlags::LOAD_BALANCE_INFO) {
+            pdu.load_balance_info = Some(read_bytes(src)?);
+        }
+        if redirection_flags.contains(ServerRedirectionFlags::USERNAME) {
+            pdu.username = Some(read_string(src)?);
+        }
+        if redirection_flags.contains(ServerRedirectionFlags::DOMAIN) {
+            pdu.domain = Some(read_string(src)?);
+        }
+        if redirection_flags.contains(ServerRedirectionFlags::PASSWORD) {
+            pdu.password = Some(read_bytes(src)?);
+        }
+        if redirection_flags.contains(ServerRedirectionFlags::TARGET_FQDN) {
+            pdu.target_fqdn = Some(read_string(src)?);
+        }
+        if redirection_flags.contains(ServerRedirectionFlags::TARGET_NETBIOS_NAME) {
+            pdu.target_netbios_name = Some(read_string(src)?);
+        }
+        if redirection_flags.contains(ServerRedirectionFlags::CLIENT_TSV_URL) {
+            pdu.tsv_url = Some(read_bytes(src)?);
+        }
+        if redirection_flags.contains(ServerRedirectionFlags::REDIRECTION_GUID) {
+            pdu.redirection_guid = Some(read_bytes(src)?);
+        }
+        if redirection_flags.contains(ServerRedirectionFlags::TARGET_CERTIFICATE) {
+            pdu.target_certificate = Some(read_bytes(src)?);
+        }
+        if redirection_flags.contains(ServerRedirectionFlags::TARGET_NET_ADDRESSES) {
+            ensure_size!(in: src, size: LENGTH_FIELD_SIZE * 2);
+            let _length = src.read_u32();
+            let count = src.read_u32();
+
+            let addresses = (0..count).map(|_| read_string(src)).collect::<DecodeResult<_>>()?;
+            pdu.target_net_addresses = Some(addresses);
+        }
+
+        // The packet may end with 8 bytes of padding.
+        let read = src.pos() - start;
+        if length > read {
+            let padding = length - read;
+            ensure_size!(in: src, size: padding);
+            read_padding!(src, padding);
+        }
+
+        Ok(pdu)
+    }
+}
+
+fn string_size(value: &str) -> usize {
+    LENGTH_FIELD_SIZE + utils::encoded_str_len(value, CharacterSet::Unicode, true)
+}
+
+fn target_net_addresses_size(addresses: &[String]) -> usize {
+    LENGTH_FIELD_SIZE + 4 /* addressCount */ + addresses.iter().map(|address| string_size(address)).sum::<usize>()
+}
+
+fn write_string(dst: &mut WriteCursor<'_>, value: &str) -> EncodeResult<()> {
+    dst.write_u32(cast_length!(
+        "length",
+        utils::encoded_str_len(value, CharacterSet::Unicode, true)
+    )?);
+    utils::write_string_to_cursor(dst, value, CharacterSet::Unicode, true)
+}
+
+fn write_bytes(dst: &mut WriteCursor<'_>, value: &[u8]) -> EncodeResult<()> {
+    dst.write_u32(cast_length!("length", value.len())?);
+    dst.write_slice(value);
+
+    Ok(())
+}
+
+fn read_bytes(src: &mut ReadCursor<'_>) -> DecodeResult<Vec<u8>> {
+    ensure_size!(in: src, size: LENGTH_FIELD_SIZE);
+    let length = cast_length!("length", src.read_u32())?;
+
+    ensure_size!(in: src, size: length);
+    Ok(src.read_slice(length).to_vec())
+}
+
+fn read_string(src: &mut ReadCursor<'_>) -> DecodeResult<String> {
+    let bytes = read_bytes(src)?;
+    utils::decode_string(&bytes, CharacterSet::Unicode, true)
+}
+
+bitflags! {
+    /// Redirection flags (RedirFlags) of the [`ServerRedirectionPdu`]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct ServerRedirectionFlags: u32 {
+        const TARGET_NET_ADDRESS = 0x0000_0001;
+        const LOAD_BALANCE_INFO = 0x0000_0002;
+        const USERNAME = 0x0000_0004;
+        const DOMAIN = 0x0000_0008;
+        const PASSWORD = 0x0000_0010;
+        const DONT_STORE_USERNAME = 0x0000_0020;
+        const SMARTCARD_LOGON = 0x0000_0040;
+        const NO_REDIRECT = 0x0000_0080;
+        const TARGET_FQDN = 0x0000_0100;
+        const TARGET_NETBIOS_NAME = 0x0000_0200;
+        const TARGET_NET_ADDRESSES = 0x0000_0800;
+        const CLIENT_TSV_URL = 0x0000_1000;
+        const SERVER_TSV_CAPABLE = 0x0000_2000;
+        const PASSWORD_IS_PK_ENCRYPTED = 0x0000_4000;
+        const REDIRECTION_GUID = 0x0000_8000;
+        const TARGET_CERTIFICATE = 0x0001_0000;
+
+        /// Flags telling which fields are present
+        const FIELDS = Self::TARGET_NET_ADDRESS.bits()
+            | Self::LOAD_BALANCE_INFO.bits()
+            | Self::USERNAME.bits()
+            | Self::DOMAIN.bits()
+            | Self::PASSWORD.bits()
+            | Self::TARGET_FQDN.bits()
+            | Self::TARGET_NETBIOS_NAME.bits()
+            | Self::TARGET_NET_ADDRESSES.bits()
+            | Self::CLIENT_TSV_URL.bits()
+            | Self::REDIRECTION_GUID.bits()
+            | Self::TARGET_CERTIFICATE.bits();
+
+        const _ = !0;
+    }
+}
@@ -12,6 +12,10 @@ use ironrdp_rdpdr::RdpdrBackend;
 use ironrdp_svc::SvcMessage;
 use nix::dir::{Dir, OwningIter};
 
+use super::lock::FileLocks;
+#[cfg(target_os = "linux")]
+use super::notify::DirectoryWatcher;
+
 #[derive(Debug, Default)]
 pub struct NixRdpdrBackend {
     file_id: u32,
@@ -19,6 +23,10 @@ pub struct NixRdpdrBackend {
     file_map: std::collections::HashMap<u32, std::fs::File>,
     file_path_map: std::collections::HashMap<u32, String>,
     file_dir_map: std::collections::HashMap<u32, OwningIter>,
+    file_locks: FileLocks,
+    /// Created with the first change notification request
+    #[cfg(target_os = "linux")]
+    directory_watcher: Option<DirectoryWatcher>,
 }
 
 impl NixRdpdrBackend {
@@ -28,6 +36,34 @@ impl NixRdpdrBackend {
             ..Default::default()
         }
     }
+
+    /// Completes the requests which were waiting for a change in a directory, or for a lock to be released
+    ///
+    /// This should be called when [`NixRdpdrBackend::change_notification_fd`] is readable, and
+    /// periodically while [`NixRdpdrBackend::is_waiting_for_locks`]. The messages are sent on the RDPDR channel.
+    pub fn process_pending_requests(&mut self) -> PduResult<Vec<SvcMessage>> {
+        let mut messages = self.file_locks.retry_waiting(&self.file_map);
+
+        #[cfg(target_os = "linux")]
+        if let Some(watcher) = self.directory_watcher.as_mut() {
+            messages.extend(watcher.process_events()?);
+        }
+
+        Ok(messages)
+    }
+
+    /// Returns whether lock requests are waiting for a conflicting lock to be released
+    pub fn is_waiting_for_locks(&self) -> bool {
+        self.file_locks.is_waiting()
+    }
+
+    /// Returns the file descriptor which becomes readable when a watched directory changes
+    ///
+    /// Directory change notifications are only supported on Linux, with inotify.
+    #[cfg(target_os = "linux")]
+    pub fn change_notification_fd(&self) -> Option<std::os::fd::BorrowedFd<'_>> {
+        self.directory_watcher.as_ref().map(AsFd::as_fd)
+    }
 }
 
 impl_as_any!(NixRdpdrBackend);
@@ -46,9 +82,8 @@ impl RdpdrBackend for NixRdpdrBackend {
             ServerDriveIoRequest::ServerCreateDriveRequest(req_inner) => create_drive(self, req_inner),
             ServerDriveIoRequest::DeviceReadRequest(req_inner) => read_device(self, req_inner),
             ServerDriveIoRequest::DeviceCloseRequest(req_inner) => close_device(self, req_inner),
-            ServerDriveIoRequest::ServerDriveNotifyChangeDirectoryRequest(_) => {
-                // TODO
-                Ok(Vec::new())
+            ServerDriveIoRequest::ServerDriveNotifyChangeDirectoryRequest(req_inner) => {
+                notify_change_directory(self, req_inner)
             }
             ServerDriveIoRequest::ServerDriveQueryDirectoryRequest(req_inner) => query_directory(self, req_inner),
             ServerDriveIoRequest::ServerDriveQueryInformationRequest(req_inner) => query_information(self, req_inner),
@@ -62,10 +97,7 @@ impl RdpdrBackend for NixRdpdrBackend {
                     output_buffer: None,
                 }),
             )]),
-            ServerDriveIoRequest::ServerDriveLockControlRequest(_) => {
-                // TODO
-                Ok(Vec::new())
-            }
+            ServerDriveIoRequest::ServerDriveLockControlRequest(req_inner) => lock_control(self, req_inner),
         }
     }
 }
@@ -163,13 +195,82 @@ pub(crate) fn read_device(backend: &mut NixRdpdrBackend, req_inner: DeviceReadRe
 }
 
 pub(crate) fn close_device(backend: &mut NixRdpdrBackend, req_inner: DeviceCloseRequest) -> PduResult<Vec<SvcMessage>> {
-    backend.file_map.remove(&req_inner.device_io_request.file_id);
-    backend.file_path_map.remove(&req_inner.device_io_request.file_id);
-    backend.file_dir_map.remove(&req_inner.device_io_request.file_id);
+    let file_id = req_inner.device_io_request.file_id;
+    backend.file_map.remove(&file_id);
+    backend.file_path_map.remove(&file_id);
+    backend.file_dir_map.remove(&file_id);
+
+    // The pending requests on the file are completed before it is closed.
+    let mut messages = backend.file_locks.close(file_id);
+    #[cfg(target_os = "linux")]
+    if let Some(watcher) = backend.directory_watcher.as_mut() {
+        messages.extend(watcher.close(file_id));
+    }
+
     let res = RdpdrPdu::DeviceCloseResponse(DeviceCloseResponse {
         device_io_response: DeviceIoResponse::new(req_inner.device_io_request, NtStatus::SUCCESS),
     });
-    Ok(vec![SvcMessage::from(res)])
+    messages.push(SvcMessage::from(res));
+    Ok(messages)
+}
+
+pub(crate) fn lock_control(
+    backend: &mut NixRdpdrBackend,
+    req_inner: ServerDriveLockControlRequest,
+) -> PduResult<Vec<SvcMessage>> {
+    match backend.file_map.get(&req_inner.device_io_request.file_id) {
+        Some(file) => Ok(backend.file_locks.lock_control(file, req_inner).into_iter().collect()),
+        None => {
+            warn!("no file to lock");
+            let res = RdpdrPdu::ClientDriveLockControlResponse(ClientDriveLockControlResponse {
+                device_io_reply: DeviceIoResponse::new(req_inner.device_io_request, NtStatus::NO_SUCH_FILE),
+            });
+            Ok(vec![SvcMessage::from(res)])
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn notify_change_directory(
+    backend: &mut NixRdpdrBackend,
+    req_inner: ServerDriveNotifyChangeDirectoryRequest,
+) -> PduResult<Vec<SvcMessage>> {
+    let Some(path) = backend.file_path_map.get(&req_inner.device_io_request.file_id) else {
+        warn!("no directory to watch");
+        return Ok(vec![SvcMessage::from(
+            RdpdrPdu::ClientDriveNotifyChangeDirectoryResponse(ClientDriveNotifyChangeDirectoryResponse {
+                device_io_reply: DeviceIoResponse::new(req_inner.device_io_request, NtStatus::NO_SUCH_FILE),
+                buffer: Vec::new(),
+            }),
+        )]);
+    };
+
+    let watcher = match backend.directory_watcher.as_mut() {
+        Some(watcher) => watcher,
+        None => match DirectoryWatcher::new() {
+            Ok(watcher) => backend.directory_watcher.insert(watcher),
+            Err(error) => {
+                warn!(%error, "Failed to initialize inotify");
+                return Ok(vec![SvcMessage::from(
+                    RdpdrPdu::ClientDriveNotifyChangeDirectoryResponse(ClientDriveNotifyChangeDirectoryResponse {
+                        device_io_reply: DeviceIoResponse::new(req_inner.device_io_request, NtStatus::NOT_SUPPORTED),
+                        buffer: Vec::new(),
+                    }),
+                )]);
+            }
+        },
+    };
+
+    watcher.notify_change_directory(path, req_inner)
+}
+
+/// Change notifications are not supported, the request stays pending until the directory is closed.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn notify_change_directory(
+    _backend: &mut NixRdpdrBackend,
+    _req_inner: ServerDriveNotifyChangeDirectoryRequest,
+) -> PduResult<Vec<SvcMessage>> {
+    Ok(Vec::new())
 }
 
 pub(crate) fn query_information(
@@ -0,0 +1,183 @@
+//! Byte-range locks, implemented with `fcntl`.
+//!
+//! On Linux, open file description locks are used, so that the locks are owned by the file IDs
+//! rather than by the process. On macOS, the locks are owned by the process: they don't conflict
+//! with each other, and closing any file ID of a file releases all the locks on it.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::os::fd::AsRawFd;
+
+use ironrdp_rdpdr::pdu::efs::{
+    ClientDriveLockControlResponse, DeviceIoRequest, DeviceIoResponse, LockOperation, NtStatus, RdpLockInfo,
+    ServerDriveLockControlRequest,
+};
+use ironrdp_rdpdr::pdu::RdpdrPdu;
+use ironrdp_svc::SvcMessage;
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg};
+use nix::libc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LockType {
+    Shared,
+    Exclusive,
+    Unlock,
+}
+
+/// The locks held on the files, and the lock requests waiting for a conflicting lock to be released
+#[derive(Debug, Default)]
+pub(crate) struct FileLocks {
+    /// Locks held, by file ID
+    held: Vec<(u32, RdpLockInfo)>,
+    waiting: Vec<ServerDriveLockControlRequest>,
+}
+
+impl FileLocks {
+    /// Handles a request on `file`
+    ///
+    /// The locks of a request are granted all at once or not at all. A request which can't be
+    /// granted right away is kept waiting if it has the F bit set, see [`FileLocks::retry_waiting`].
+    pub(crate) fn lock_control(&mut self, file: &File, req: ServerDriveLockControlRequest) -> Option<SvcMessage> {
+        let status = self.try_lock(file, &req);
+
+        if status == NtStatus::LOCK_NOT_GRANTED && req.wait {
+            debug!(completion_id = req.device_io_request.completion_id, "Waiting for lock");
+            self.waiting.push(req);
+            return None;
+        }
+
+        Some(lock_response(req.device_io_request, status))
+    }
+
+    /// Retries the waiting requests, and returns the responses of the ones which completed
+    pub(crate) fn retry_waiting(&mut self, files: &HashMap<u32, File>) -> Vec<SvcMessage> {
+        let mut responses = Vec::new();
+
+        for req in core::mem::take(&mut self.waiting) {
+            let Some(file) = files.get(&req.device_io_request.file_id) else {
+                responses.push(lock_response(req.device_io_request, NtStatus::CANCELLED));
+                continue;
+            };
+
+            match self.try_lock(file, &req) {
+                NtStatus::LOCK_NOT_GRANTED => self.waiting.push(req),
+                status => responses.push(lock_response(req.device_io_request, status)),
+            }
+        }
+
+        responses
+    }
+
+    pub(crate) fn is_waiting(&self) -> bool {
+        !self.waiting.is_empty()
+    }
+
+    /// Forgets the locks of `file_id` when it is closed, and cancels its waiting requests
+    ///
+    /// The locks themselves are released by closing the file.
+    pub(crate) fn close(&mut self, file_id: u32) -> Vec<SvcMessage> {
+        self.held.retain(|(id, _)| *id != file_id);
+
+        let (cancelled, waiting) = core::mem::take(&mut self.waiting)
+            .into_iter()
+            .partition(|req| req.device_io_request.file_id == file_id);
+        self.waiting = waiting;
+
+        cancelled
+            .into_iter()
+            .map(|req: ServerDriveLockControlRequest| lock_response(req.device_io_request, NtStatus::CANCELLED))
+            .collect()
+    }
+
+    fn try_lock(&mut self, file: &File, req: &ServerDriveLockControlRequest) -> NtStatus {
+        let file_id = req.device_io_request.file_id;
+
+        let lock_type = match req.operation {
+            LockOperation::SharedLock => LockType::Shared,
+            LockOperation::ExclusiveLock => LockType::Exclusive,
+            LockOperation::Unlock | LockOperation::UnlockMultiple => {
+                // Only the ranges locked with the same offset and length can be unlocked.
+                if !req.locks.iter().all(|lock| self.held.contains(&(file_id, *lock))) {
+                    return NtStatus::RANGE_NOT_LOCKED;
+                }
+                LockType::Unlock
+            }
+        };
+
+        for (i, lock) in req.locks.iter().enumerate() {
+            if let Err(error) = set_lock(file, lock_type, lock) {
+                debug!(%error, ?lock, "Failed to set lock");
+                if lock_type != LockType::Unlock {
+                    for acquired in &req.locks[..i] {
+                        let _ = set_lock(file, LockType::Unlock, acquired);
+                    }
+                }
+
+                return match error {
+                    Errno::EAGAIN | Errno::EACCES => NtStatus::LOCK_NOT_GRANTED,
+                    Errno::EBADF => NtStatus::ACCESS_DENIED,
+                    _ => NtStatus::UNSUCCESSFUL,
+                };
+            }
+        }
+
+        if lock_type == LockType::Unlock {
+            for lock in &req.locks {
+                if let Some(index) = self.held.iter().position(|held| *held == (file_id, *lock)) {
+                    self.held.swap_remove(index);
+                }
+            }
+        } else {
+            self.held.extend(req.locks.iter().map(|lock| (file_id, *lock)));
+        }
+
+        NtStatus::SUCCESS
+    }
+}
+
+#[allow(clippy::unnecessary_cast)] // the constants are c_int on Linux, and c_short on macOS
+fn set_lock(file: &File, lock_type: LockType, lock: &RdpLockInfo) -> nix::Result<()> {
+    // A zero-length range doesn't conflict with any lock, and isn't supported by fcntl.
+    if lock.length == 0 {
+        return Ok(());
+    }
+    // No byte can be written beyond the largest offset.
+    let Ok(start) = libc::off_t::try_from(lock.offset) else {
+        return Ok(());
+    };
+    // A length of 0 locks up to the end of the file, whatever its size.
+    let len = lock
+        .offset
+        .checked_add(lock.length)
+        .and_then(|end| libc::off_t::try_from(end).ok())
+        .map_or(0, |_| libc::off_t::try_from(lock.length).unwrap_or(0));
+
+    let l_type = match lock_type {
+        LockType::Shared => libc::F_RDLCK as libc::c_short,
+        LockType::Exclusive => libc::F_WRLCK as libc::c_short,
+        LockType::Unlock => libc::F_UNLCK as libc::c_short,
+    };
+
+    // SAFETY: `flock` is a plain C struct, for which all zeros is a valid value.
+    let mut flock: libc::flock = unsafe { core::mem::zeroed() };
+    flock.l_type = l_type;
+    flock.l_whence = libc::SEEK_SET as libc::c_short;
+    flock.l_start = start;
+    flock.l_len = len;
+
+    #[cfg(target_os = "linux")]
+    let arg = FcntlArg::F_OFD_SETLK(&flock);
+    #[cfg(not(target_os = "linux"))]
+    let arg = FcntlArg::F_SETLK(&flock);
+
+    fcntl(file.as_raw_fd(), arg).map(drop)
+}
+
+fn lock_response(request: DeviceIoRequest, status: NtStatus) -> SvcMessage {
+    SvcMessage::from(RdpdrPdu::ClientDriveLockControlResponse(
+        ClientDriveLockControlResponse {
+            device_io_reply: DeviceIoResponse::new(request, status),
+        },
+    ))
+}
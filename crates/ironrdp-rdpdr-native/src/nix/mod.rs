@@ -1 +1,4 @@
 pub mod backend;
+mod lock;
+#[cfg(target_os = "linux")]
+mod notify;
@@ -0,0 +1,383 @@
+//! Directory change notifications, implemented with inotify.
+
+use std::collections::{HashMap, HashSet};
+use std::os::fd::{AsFd, BorrowedFd};
+
+use ironrdp_pdu::PduResult;
+use ironrdp_rdpdr::pdu::efs::{
+    ClientDriveNotifyChangeDirectoryResponse, CompletionFilter, DeviceIoRequest, DeviceIoResponse, FileAction,
+    FileNotifyInformation, NtStatus, ServerDriveNotifyChangeDirectoryRequest,
+};
+use ironrdp_rdpdr::pdu::RdpdrPdu;
+use ironrdp_svc::SvcMessage;
+use nix::errno::Errno;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent, WatchDescriptor};
+
+/// Above this number of changes waiting for a request, the server is told to enumerate the directory again.
+const MAX_PENDING_CHANGES: usize = 1024;
+
+/// A directory watched by the server, with a file ID
+#[derive(Debug)]
+struct Watch {
+    watch_tree: bool,
+    filter: CompletionFilter,
+    /// The request to complete with the next changes
+    pending: Option<DeviceIoRequest>,
+    changes: Vec<FileNotifyInformation>,
+    overflow: bool,
+}
+
+impl Watch {
+    fn take_response(&mut self) -> Option<SvcMessage> {
+        if self.changes.is_empty() && !self.overflow {
+            return None;
+        }
+
+        let request = self.pending.take()?;
+        let (status, buffer) = if self.overflow {
+            self.changes.clear();
+            (NtStatus::NOTIFY_ENUM_DIR, Vec::new())
+        } else {
+            (NtStatus::SUCCESS, core::mem::take(&mut self.changes))
+        };
+        self.overflow = false;
+
+        Some(notify_response(request, status, buffer))
+    }
+
+    fn push_change(&mut self, action: FileAction, file_name: String) {
+        if self.changes.len() >= MAX_PENDING_CHANGES {
+            self.overflow = true;
+        } else {
+            self.changes.push(FileNotifyInformation { action, file_name });
+        }
+    }
+}
+
+/// Watches the directories opened by the server for changes
+///
+/// The inotify watches are shared by the file IDs opened on the same directories.
+#[derive(Debug)]
+pub(crate) struct DirectoryWatcher {
+    inotify: Inotify,
+    watches: HashMap<u32, Watch>,
+    /// The file IDs watching each descriptor, with the path of the directory relative to the watched one
+    descriptors: HashMap<WatchDescriptor, Vec<(u32, String)>>,
+    /// The absolute path of the directory of each descriptor
+    paths: HashMap<WatchDescriptor, String>,
+}
+
+impl DirectoryWatcher {
+    pub(crate) fn new() -> nix::Result<Self> {
+        Ok(Self {
+            inotify: Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?,
+            watches: HashMap::new(),
+            descriptors: HashMap::new(),
+            paths: HashMap::new(),
+        })
+    }
+
+    /// Handles a request on the directory at `path`
+    ///
+    /// The request is completed right away if changes are waiting, otherwise it stays pending
+    /// until [`DirectoryWatcher::process_events`] finds some. A request still pending on the same
+    /// directory is completed with STATUS_CANCELLED.
+    pub(crate) fn notify_change_directory(
+        &mut self,
+        path: &str,
+        req: ServerDriveNotifyChangeDirectoryRequest,
+    ) -> PduResult<Vec<SvcMessage>> {
+        let file_id = req.device_io_request.file_id;
+
+        #[allow(clippy::map_entry)] // the entry can't be held while the watches are added
+        if !self.watches.contains_key(&file_id) {
+            let filter = CompletionFilter::from_bits_retain(req.completion_filter);
+            let watch_tree = req.watch_tree != 0;
+
+            if let Err(error) = self.add_watches(file_id, path, String::new(), filter, watch_tree) {
+                warn!(%error, %path, "Failed to watch directory");
+                self.remove_descriptors(file_id);
+                let status = if error == Errno::ENOTDIR {
+                    NtStatus::NOT_A_DIRECTORY
+                } else {
+                    NtStatus::UNSUCCESSFUL
+                };
+                return Ok(vec![notify_response(req.device_io_request, status, Vec::new())]);
+            }
+
+            self.watches.insert(
+                file_id,
+                Watch {
+                    watch_tree,
+                    filter,
+                    pending: None,
+                    changes: Vec::new(),
+                    overflow: false,
+                },
+            );
+        }
+
+        let watch = self.watches.get_mut(&file_id).expect("watch inserted above");
+        let mut responses = Vec::new();
+        if let Some(previous) = watch.pending.replace(req.device_io_request) {
+            warn!(
+                completion_id = previous.completion_id,
+                "Cancelling a pending change notification replaced by a new one"
+            );
+            responses.push(notify_response(previous, NtStatus::CANCELLED, Vec::new()));
+        }
+        responses.extend(watch.take_response());
+
+        Ok(responses)
+    }
+
+    /// Reads the inotify events, and completes the pending requests with the changes
+    pub(crate) fn process_events(&mut self) -> PduResult<Vec<SvcMessage>> {
+        loop {
+            let events = match self.inotify.read_events() {
+                Ok(events) => events,
+                Err(Errno::EAGAIN) => break,
+                Err(error) => {
+                    warn!(%error, "Failed to read inotify events");
+                    break;
+                }
+            };
+
+            self.dispatch_events(&events);
+        }
+
+        Ok(self.watches.values_mut().filter_map(Watch::take_response).collect())
+    }
+
+    /// Stops watching the directory of `file_id`, when it is closed
+    ///
+    /// A pending request is completed with STATUS_NOTIFY_CLEANUP.
+    pub(crate) fn close(&mut self, file_id: u32) -> Option<SvcMessage> {
+        self.remove_descriptors(file_id);
+        let request = self.watches.remove(&file_id)?.pending?;
+
+        Some(notify_response(request, NtStatus::NOTIFY_CLEANUP, Vec::new()))
+    }
+
+    fn dispatch_events(&mut self, events: &[InotifyEvent]) {
+        // A rename within a directory is reported as two events with the same cookie, other moves
+        // are reported as a removal and an addition.
+        let moved_from: HashSet<_> = events
+            .iter()
+            .filter(|event| event.mask.contains(AddWatchFlags::IN_MOVED_FROM))
+            .map(|event| (event.wd, event.cookie))
+            .collect();
+        let moved_to: HashSet<_> = events
+            .iter()
+            .filter(|event| event.mask.contains(AddWatchFlags::IN_MOVED_TO))
+            .map(|event| (event.wd, event.cookie))
+            .collect();
+
+        for event in events {
+            if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                for watch in self.watches.values_mut() {
+                    watch.overflow = true;
+                }
+                continue;
+            }
+
+            if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+                self.descriptors.remove(&event.wd);
+                self.paths.remove(&event.wd);
+                continue;
+            }
+
+            // Events on the watched directory itself are not reported.
+            let Some(name) = event.name.as_ref() else {
+                continue;
+            };
+            let name = name.to_string_lossy();
+            let is_dir = event.mask.contains(AddWatchFlags::IN_ISDIR);
+
+            let action = if event.mask.contains(AddWatchFlags::IN_CREATE) {
+                FileAction::Added
+            } else if event.mask.contains(AddWatchFlags::IN_DELETE) {
+                FileAction::Removed
+            } else if event.mask.contains(AddWatchFlags::IN_MOVED_FROM) {
+                if moved_to.contains(&(event.wd, event.cookie)) {
+                    FileAction::RenamedOldName
+                } else {
+                    FileAction::Removed
+                }
+            } else if event.mask.contains(AddWatchFlags::IN_MOVED_TO) {
+                if moved_from.contains(&(event.wd, event.cookie)) {
+                    FileAction::RenamedNewName
+                } else {
+                    FileAction::Added
+                }
+            } else {
+                FileAction::Modified
+            };
+
+            let Some(watchers) = self.descriptors.get(&event.wd).cloned() else {
+                continue;
+            };
+
+            for (file_id, dir) in watchers {
+                let Some(watch) = self.watches.get_mut(&file_id) else {
+                    continue;
+                };
+
+                if !is_reported(watch.filter, event.mask, is_dir) {
+                    continue;
+                }
+
+                let file_name = if dir.is_empty() {
+                    name.clone().into_owned()
+                } else {
+                    format!("{dir}\\{name}")
+                };
+
+                let (filter, watch_tree) = (watch.filter, watch.watch_tree);
+                watch.push_change(action, file_name.clone());
+
+                // The directories added to a watched tree are watched too.
+                if watch_tree && is_dir && matches!(action, FileAction::Added | FileAction::RenamedNewName) {
+                    if let Some(parent) = self.paths.get(&event.wd) {
+                        let path = format!("{parent}/{name}");
+                        if let Err(error) = self.add_watches(file_id, &path, file_name, filter, watch_tree) {
+                            warn!(%error, %path, "Failed to watch directory");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Watches the directory at `path`, and its subdirectories if `watch_tree` is set
+    fn add_watches(
+        &mut self,
+        file_id: u32,
+        path: &str,
+        relative_path: String,
+        filter: CompletionFilter,
+        watch_tree: bool,
+    ) -> nix::Result<()> {
+        let wd = self.inotify.add_watch(path, watch_mask(filter, watch_tree))?;
+        self.descriptors
+            .entry(wd)
+            .or_default()
+            .push((file_id, relative_path.clone()));
+        self.paths.insert(wd, path.to_owned());
+
+        if watch_tree {
+            let entries = std::fs::read_dir(path).map_err(|_| Errno::EACCES)?;
+            for entry in entries.flatten() {
+                if !entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                    continue;
+                }
+
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let child_relative_path = if relative_path.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{relative_path}\\{name}")
+                };
+                // The subdirectories may be removed meanwhile.
+                let _ = self.add_watches(
+                    file_id,
+                    &format!("{path}/{name}"),
+                    child_relative_path,
+                    filter,
+                    watch_tree,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_descriptors(&mut self, file_id: u32) {
+        let mut unused = Vec::new();
+        for (wd, watchers) in self.descriptors.iter_mut() {
+            watchers.retain(|(id, _)| *id != file_id);
+            if watchers.is_empty() {
+                unused.push(*wd);
+            }
+        }
+
+        for wd in unused {
+            self.descriptors.remove(&wd);
+            self.paths.remove(&wd);
+            // The watch is already gone if its directory was removed.
+            let _ = self.inotify.rm_watch(wd);
+        }
+    }
+}
+
+impl AsFd for DirectoryWatcher {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inotify.as_fd()
+    }
+}
+
+fn watch_mask(filter: CompletionFilter, watch_tree: bool) -> AddWatchFlags {
+    // The descriptors are shared, the events are filtered for each watch by `is_reported`.
+    let mut mask = AddWatchFlags::IN_ONLYDIR | AddWatchFlags::from_bits_retain(nix::libc::IN_MASK_ADD);
+
+    if filter.intersects(CompletionFilter::FILE_NOTIFY_CHANGE_FILE_NAME | CompletionFilter::FILE_NOTIFY_CHANGE_DIR_NAME)
+        || watch_tree
+    {
+        mask |= AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_DELETE
+            | AddWatchFlags::IN_MOVED_FROM
+            | AddWatchFlags::IN_MOVED_TO;
+    }
+    if filter.intersects(MODIFIED_FILTER) {
+        mask |= AddWatchFlags::IN_MODIFY;
+    }
+    if filter.intersects(ATTRIBUTES_FILTER) {
+        mask |= AddWatchFlags::IN_ATTRIB;
+    }
+    if filter.contains(CompletionFilter::FILE_NOTIFY_CHANGE_LAST_ACCESS) {
+        mask |= AddWatchFlags::IN_ACCESS;
+    }
+
+    mask
+}
+
+const MODIFIED_FILTER: CompletionFilter = CompletionFilter::FILE_NOTIFY_CHANGE_SIZE
+    .union(CompletionFilter::FILE_NOTIFY_CHANGE_LAST_WRITE)
+    .union(CompletionFilter::FILE_NOTIFY_CHANGE_STREAM_SIZE)
+    .union(CompletionFilter::FILE_NOTIFY_CHANGE_STREAM_WRITE);
+
+const ATTRIBUTES_FILTER: CompletionFilter = CompletionFilter::FILE_NOTIFY_CHANGE_ATTRIBUTES
+    .union(CompletionFilter::FILE_NOTIFY_CHANGE_CREATION)
+    .union(CompletionFilter::FILE_NOTIFY_CHANGE_EA)
+    .union(CompletionFilter::FILE_NOTIFY_CHANGE_SECURITY);
+
+/// Returns whether an event matches the completion filter of a watch
+fn is_reported(filter: CompletionFilter, mask: AddWatchFlags, is_dir: bool) -> bool {
+    if mask.intersects(
+        AddWatchFlags::IN_CREATE | AddWatchFlags::IN_DELETE | AddWatchFlags::IN_MOVED_FROM | AddWatchFlags::IN_MOVED_TO,
+    ) {
+        let name_filter = if is_dir {
+            CompletionFilter::FILE_NOTIFY_CHANGE_DIR_NAME
+        } else {
+            CompletionFilter::FILE_NOTIFY_CHANGE_FILE_NAME
+        };
+        filter.contains(name_filter)
+    } else if mask.contains(AddWatchFlags::IN_MODIFY) {
+        filter.intersects(MODIFIED_FILTER)
+    } else if mask.contains(AddWatchFlags::IN_ATTRIB) {
+        filter.intersects(ATTRIBUTES_FILTER)
+    } else if mask.contains(AddWatchFlags::IN_ACCESS) {
+        filter.contains(CompletionFilter::FILE_NOTIFY_CHANGE_LAST_ACCESS)
+    } else {
+        false
+    }
+}
+
+fn notify_response(request: DeviceIoRequest, status: NtStatus, buffer: Vec<FileNotifyInformation>) -> SvcMessage {
+    SvcMessage::from(RdpdrPdu::ClientDriveNotifyChangeDirectoryResponse(
+        ClientDriveNotifyChangeDirectoryResponse {
+            device_io_reply: DeviceIoResponse::new(request, status),
+            buffer,
+        },
+    ))
+}
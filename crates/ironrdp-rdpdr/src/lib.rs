@@ -23,6 +23,7 @@ use pdu::RdpdrPdu;
 
 pub mod backend;
 pub mod pdu;
+pub mod server;
 
 pub use self::backend::noop::NoopRdpdrBackend;
 pub use self::backend::RdpdrBackend;
@@ -207,12 +208,19 @@ impl SvcProcessor for Rdpdr {
             }
             RdpdrPdu::ServerDeviceAnnounceResponse(pdu) => self.handle_server_device_announce_response(pdu),
             RdpdrPdu::DeviceIoRequest(pdu) => self.handle_device_io_request(pdu, &mut src),
+            // All the devices are announced after the client ID confirm.
+            RdpdrPdu::UserLoggedOn => Ok(Vec::new()),
             // TODO: This can eventually become a `_ => {}` block, but being explicit for now
             // to make sure we don't miss handling new RdpdrPdu variants here during active development.
             RdpdrPdu::ClientNameRequest(_)
             | RdpdrPdu::ClientDeviceListAnnounce(_)
             | RdpdrPdu::VersionAndIdPdu(_)
             | RdpdrPdu::CoreCapability(_)
+            | RdpdrPdu::DeviceCreateRequest(_)
+            | RdpdrPdu::DeviceCloseRequest(_)
+            | RdpdrPdu::DeviceReadRequest(_)
+            | RdpdrPdu::DeviceWriteRequest(_)
+            | RdpdrPdu::ServerDriveQueryDirectoryRequest(_)
             | RdpdrPdu::DeviceControlResponse(_)
             | RdpdrPdu::DeviceCreateResponse(_)
             | RdpdrPdu::ClientDriveQueryInformationResponse(_)
@@ -222,6 +230,8 @@ impl SvcProcessor for Rdpdr {
             | RdpdrPdu::DeviceReadResponse(_)
             | RdpdrPdu::DeviceWriteResponse(_)
             | RdpdrPdu::ClientDriveSetInformationResponse(_)
+            | RdpdrPdu::ClientDriveNotifyChangeDirectoryResponse(_)
+            | RdpdrPdu::ClientDriveLockControlResponse(_)
             | RdpdrPdu::EmptyResponse => Err(pdu_other_err!("Rdpdr", "received unexpected packet")),
         }
     }
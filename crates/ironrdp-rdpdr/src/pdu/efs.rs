@@ -75,6 +75,30 @@ impl VersionAndIdPdu {
         })
     }
 
+    /// Creates a new [`DR_CORE_SERVER_ANNOUNCE_REQ`] with the given `client_id`.
+    ///
+    /// [`DR_CORE_SERVER_ANNOUNCE_REQ`]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpefs/046047aa-62d8-49f9-bf16-7fe41880aaf4
+    pub fn new_server_announce_request(client_id: u32) -> Self {
+        Self {
+            version_major: VERSION_MAJOR,
+            version_minor: VERSION_MINOR_12,
+            client_id,
+            kind: VersionAndIdPduKind::ServerAnnounceRequest,
+        }
+    }
+
+    /// Creates a new [`DR_CORE_SERVER_CLIENTID_CONFIRM`] in reply to the client announce reply.
+    ///
+    /// [`DR_CORE_SERVER_CLIENTID_CONFIRM`]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpefs/bbbb9666-6994-4cf6-8e65-0d46eb319c6e
+    pub fn new_server_client_id_confirm(reply: &VersionAndIdPdu) -> Self {
+        Self {
+            version_major: VERSION_MAJOR,
+            version_minor: VERSION_MINOR_12,
+            client_id: reply.client_id,
+            kind: VersionAndIdPduKind::ServerClientIdConfirm,
+        }
+    }
+
     pub fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
         ensure_size!(ctx: self.name(), in: dst, size: Self::FIXED_PART_SIZE);
         dst.write_u16(self.version_major);
@@ -160,6 +184,22 @@ impl ClientNameRequest {
         write_string_to_cursor(dst, self.computer_name(), self.unicode_flag().into(), true)
     }
 
+    pub fn decode(src: &mut ReadCursor<'_>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+        let unicode_flag = if src.read_u32() == u32::from(ClientNameRequestUnicodeFlag::Ascii) {
+            ClientNameRequestUnicodeFlag::Ascii
+        } else {
+            ClientNameRequestUnicodeFlag::Unicode
+        };
+        let _code_page = src.read_u32();
+        let computer_name_len = cast_length!("ClientNameRequest", "ComputerNameLen", src.read_u32())?;
+
+        ensure_size!(ctx: Self::NAME, in: src, size: computer_name_len);
+        let computer_name = decode_string(src.read_slice(computer_name_len), unicode_flag.into(), true)?;
+
+        Ok(Self::new(computer_name, unicode_flag))
+    }
+
     pub fn name(&self) -> &'static str {
         Self::NAME
     }
@@ -215,6 +255,24 @@ impl CoreCapability {
         }
     }
 
+    /// Creates a new [`DR_CORE_CAPABILITY_REQ`] with the given `capabilities`.
+    ///
+    /// [`DR_CORE_CAPABILITY_REQ`]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpefs/702789c3-b924-4bc2-9280-3221bc7d6797
+    pub fn new_request(capabilities: Vec<CapabilityMessage>) -> Self {
+        Self {
+            capabilities,
+            kind: CoreCapabilityKind::ServerCoreCapabilityRequest,
+        }
+    }
+
+    /// Returns whether the Server User Logged On PDU is allowed by the general capabilities.
+    pub fn user_logged_on_pdu_allowed(&self) -> bool {
+        self.capabilities.iter().any(|cap| match &cap.capability_data {
+            CapabilityData::General(general) => general.extended_pdu.contains(ExtendedPdu::RDPDR_USER_LOGGEDON_PDU),
+            _ => false,
+        })
+    }
+
     pub fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
         ensure_size!(ctx: self.name(), in: dst, size: self.size());
         dst.write_u16(cast_length!(
@@ -294,6 +352,21 @@ impl Capabilities {
         this
     }
 
+    /// Returns the capabilities of a server, which redirects drives and smart cards and sends the
+    /// Server User Logged On PDU.
+    pub fn new_server() -> Self {
+        let mut general = CapabilityMessage::new_general(0);
+        if let CapabilityData::General(general) = &mut general.capability_data {
+            general.extended_pdu |= ExtendedPdu::RDPDR_USER_LOGGEDON_PDU;
+        }
+
+        Self(vec![
+            general,
+            CapabilityMessage::new_drive(),
+            CapabilityMessage::new_smartcard(),
+        ])
+    }
+
     pub fn clone_inner(&mut self) -> Vec<CapabilityMessage> {
         self.0.clone()
     }
@@ -771,6 +844,17 @@ impl ClientDeviceListAnnounce {
         Ok(())
     }
 
+    pub fn decode(src: &mut ReadCursor<'_>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+        let device_count = src.read_u32();
+
+        let device_list = (0..device_count)
+            .map(|_| DeviceAnnounceHeader::decode(src))
+            .collect::<DecodeResult<_>>()?;
+
+        Ok(Self { device_list })
+    }
+
     pub fn name(&self) -> &'static str {
         "DR_CORE_DEVICELIST_ANNOUNCE_REQ"
     }
@@ -867,6 +951,31 @@ impl DeviceAnnounceHeader {
         }
     }
 
+    pub fn device_type(&self) -> DeviceType {
+        self.device_type
+    }
+
+    pub fn device_id(&self) -> u32 {
+        self.device_id
+    }
+
+    /// Returns the name of the device: the full name of a drive when the client sends it, or the
+    /// preferred DOS name.
+    pub fn name(&self) -> String {
+        if self.device_type != DeviceType::Filesystem || self.device_data.is_empty() {
+            return self.preferred_dos_name.0.clone();
+        }
+
+        // The spec says Unicode, but some clients (including ours) send UTF-8.
+        let name = if self.device_data.len() >= 2 && self.device_data[1] == 0 {
+            from_utf16_bytes(&self.device_data)
+        } else {
+            String::from_utf8_lossy(&self.device_data).into_owned()
+        };
+
+        name.trim_end_matches('\0').to_owned()
+    }
+
     fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
         dst.write_u32(self.device_type.into());
         dst.write_u32(self.device_id);
@@ -880,6 +989,24 @@ impl DeviceAnnounceHeader {
         Ok(())
     }
 
+    fn decode(src: &mut ReadCursor<'_>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+        let device_type = DeviceType::try_from(src.read_u32())?;
+        let device_id = src.read_u32();
+        let preferred_dos_name = PreferredDosName::decode(src);
+        let device_data_length = cast_length!("DeviceAnnounceHeader", "DeviceDataLength", src.read_u32())?;
+
+        ensure_size!(in: src, size: device_data_length);
+        let device_data = src.read_slice(device_data_length).to_vec();
+
+        Ok(Self {
+            device_type,
+            device_id,
+            preferred_dos_name,
+            device_data,
+        })
+    }
+
     fn size(&self) -> usize {
         Self::FIXED_PART_SIZE + self.device_data.len()
     }
@@ -904,6 +1031,13 @@ impl PreferredDosName {
         write_string_to_cursor(dst, &self.format(), CharacterSet::Ansi, false)
     }
 
+    fn decode(src: &mut ReadCursor<'_>) -> Self {
+        let name = src.read_array::<8>();
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+
+        Self(String::from_utf8_lossy(&name[..len]).into_owned())
+    }
+
     /// Returns the underlying String with a maximum length of 7 characters plus a null terminator.
     fn format(&self) -> String {
         let mut name: &str = &self.0;
@@ -987,6 +1121,48 @@ impl ServerDeviceAnnounceResponse {
     }
 }
 
+/// 2.2.3.2 Client Drive Device List Remove (DR_DEVICELIST_REMOVE)
+#[derive(Debug, PartialEq, Clone)]
+pub struct ClientDeviceListRemove {
+    pub device_ids: Vec<u32>,
+}
+
+impl ClientDeviceListRemove {
+    const NAME: &'static str = "DR_DEVICELIST_REMOVE";
+    const FIXED_PART_SIZE: usize = size_of::<u32>(); // DeviceCount
+
+    pub fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    pub fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+        dst.write_u32(cast_length!(
+            "ClientDeviceListRemove",
+            "DeviceCount",
+            self.device_ids.len()
+        )?);
+        for device_id in self.device_ids.iter() {
+            dst.write_u32(*device_id);
+        }
+        Ok(())
+    }
+
+    pub fn decode(src: &mut ReadCursor<'_>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+        let device_count = cast_length!("ClientDeviceListRemove", "DeviceCount", src.read_u32())?;
+
+        ensure_size!(ctx: Self::NAME, in: src, size: device_count * size_of::<u32>());
+        let device_ids = (0..device_count).map(|_| src.read_u32()).collect();
+
+        Ok(Self { device_ids })
+    }
+
+    pub fn size(&self) -> usize {
+        Self::FIXED_PART_SIZE + self.device_ids.len() * size_of::<u32>()
+    }
+}
+
 /// [2.3.1] NTSTATUS Values
 ///
 /// Windows defines an absolutely massive list of potential NTSTATUS values.
@@ -1017,6 +1193,16 @@ impl NtStatus {
     pub const NOT_SUPPORTED: Self = Self(0xC000_00BB);
     /// STATUS_DIRECTORY_NOT_EMPTY
     pub const DIRECTORY_NOT_EMPTY: Self = Self(0xC000_0101);
+    /// STATUS_CANCELLED
+    pub const CANCELLED: Self = Self(0xC000_0120);
+    /// STATUS_LOCK_NOT_GRANTED
+    pub const LOCK_NOT_GRANTED: Self = Self(0xC000_0055);
+    /// STATUS_RANGE_NOT_LOCKED
+    pub const RANGE_NOT_LOCKED: Self = Self(0xC000_007E);
+    /// STATUS_NOTIFY_CLEANUP
+    pub const NOTIFY_CLEANUP: Self = Self(0x0000_010B);
+    /// STATUS_NOTIFY_ENUM_DIR
+    pub const NOTIFY_ENUM_DIR: Self = Self(0x0000_010C);
 }
 
 impl Debug for NtStatus {
@@ -1032,6 +1218,11 @@ impl Debug for NtStatus {
             NtStatus::NO_SUCH_FILE => write!(f, "STATUS_NO_SUCH_FILE"),
             NtStatus::NOT_SUPPORTED => write!(f, "STATUS_NOT_SUPPORTED"),
             NtStatus::DIRECTORY_NOT_EMPTY => write!(f, "STATUS_DIRECTORY_NOT_EMPTY"),
+            NtStatus::CANCELLED => write!(f, "STATUS_CANCELLED"),
+            NtStatus::LOCK_NOT_GRANTED => write!(f, "STATUS_LOCK_NOT_GRANTED"),
+            NtStatus::RANGE_NOT_LOCKED => write!(f, "STATUS_RANGE_NOT_LOCKED"),
+            NtStatus::NOTIFY_CLEANUP => write!(f, "STATUS_NOTIFY_CLEANUP"),
+            NtStatus::NOTIFY_ENUM_DIR => write!(f, "STATUS_NOTIFY_ENUM_DIR"),
             _ => write!(f, "NtStatus({:#010X})", self.0),
         }
     }
@@ -1587,6 +1778,32 @@ impl DeviceCreateRequest {
             path,
         })
     }
+
+    pub fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+        self.device_io_request.encode(dst)?;
+        dst.write_u32(self.desired_access.bits());
+        dst.write_u64(self.allocation_size);
+        dst.write_u32(self.file_attributes.bits());
+        dst.write_u32(self.shared_access.bits());
+        dst.write_u32(self.create_disposition.bits());
+        dst.write_u32(self.create_options.bits());
+        dst.write_u32(cast_length!(
+            "DeviceCreateRequest",
+            "PathLength",
+            encoded_str_len(&self.path, CharacterSet::Unicode, true)
+        )?);
+        write_string_to_cursor(dst, &self.path, CharacterSet::Unicode, true)
+    }
+
+    pub fn name(&self) -> &'static str {
+        "DR_CREATE_REQ"
+    }
+
+    pub fn size(&self) -> usize {
+        self.device_io_request.size() + Self::FIXED_PART_SIZE + encoded_str_len(&self.path, CharacterSet::Unicode, true)
+        // Path
+    }
 }
 
 bitflags! {
@@ -1767,6 +1984,23 @@ impl DeviceCreateResponse {
         Ok(())
     }
 
+    pub fn decode(device_io_reply: DeviceIoResponse, src: &mut ReadCursor<'_>) -> DecodeResult<Self> {
+        ensure_size!(ctx: Self::NAME, in: src, size: 4);
+        let file_id = src.read_u32();
+        // Some clients skip the Information field when the create failed.
+        let information = if src.is_empty() {
+            Information::empty()
+        } else {
+            Information::from_bits_retain(src.read_u8())
+        };
+
+        Ok(Self {
+            device_io_reply,
+            file_id,
+            information,
+        })
+    }
+
     pub fn size(&self) -> usize {
         self.device_io_reply.size() // DeviceIoReply
         + 4 // FileId
@@ -1816,7 +2050,7 @@ impl ServerDriveQueryInformationRequest {
 /// [2.4] File Information Classes \[MS-FSCC\]
 ///
 /// [2.4]: https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/4718fc40-e539-4014-8e33-b675af74e3e1
-#[derive(PartialEq, Eq, Clone)]
+#[derive(PartialEq, Eq, Clone, Copy)]
 pub struct FileInformationClassLevel(u32);
 
 impl FileInformationClassLevel {
@@ -2258,6 +2492,9 @@ pub struct FileBothDirectoryInformation {
 }
 
 impl FileBothDirectoryInformation {
+    const FIXED_PART_SIZE: usize = 4 /* NextEntryOffset */ + 4 /* FileIndex */ + 8 * 6 /* Times, EndOfFile, AllocationSize */
+        + 4 /* FileAttributes */ + 4 /* FileNameLength */ + 4 /* EaSize */ + 1 /* ShortNameLength */ + 24 /* ShortName */;
+
     pub fn new(
         creation_time: i64,
         last_access_time: i64,
@@ -2311,6 +2548,48 @@ impl FileBothDirectoryInformation {
         Ok(())
     }
 
+    /// Decodes the information from a buffer of `length` bytes.
+    fn decode(src: &mut ReadCursor<'_>, length: usize) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+        let next_entry_offset = src.read_u32();
+        let file_index = src.read_u32();
+        let creation_time = src.read_i64();
+        let last_access_time = src.read_i64();
+        let last_write_time = src.read_i64();
+        let change_time = src.read_i64();
+        let end_of_file = src.read_i64();
+        let allocation_size = src.read_i64();
+        let file_attributes = FileAttributes::from_bits_retain(src.read_u32());
+        let file_name_length = cast_length!("FileBothDirectoryInformation", "file_name_length", src.read_u32())?;
+        let ea_size = src.read_u32();
+        let short_name_length = src.read_u8() as i8;
+        // Unlike FreeRDP (and our encoder), Windows clients do write the reserved byte.
+        if length == Self::FIXED_PART_SIZE + 1 + file_name_length {
+            ensure_size!(in: src, size: 1);
+            read_padding!(src, 1);
+        }
+
+        ensure_size!(in: src, size: 24 + file_name_length);
+        let short_name = src.read_array::<24>();
+        let file_name = decode_string(src.read_slice(file_name_length), CharacterSet::Unicode, false)?;
+
+        Ok(Self {
+            next_entry_offset,
+            file_index,
+            creation_time,
+            last_access_time,
+            last_write_time,
+            change_time,
+            end_of_file,
+            allocation_size,
+            file_attributes,
+            ea_size,
+            short_name_length,
+            short_name,
+            file_name,
+        })
+    }
+
     fn size(&self) -> usize {
         4 // NextEntryOffset
         + 4 // FileIndex
@@ -2542,11 +2821,28 @@ pub struct DeviceCloseRequest {
 }
 
 impl DeviceCloseRequest {
+    const FIXED_PART_SIZE: usize = 32; // Padding
+
     pub fn decode(dev_io_req: DeviceIoRequest) -> Self {
         Self {
             device_io_request: dev_io_req,
         }
     }
+
+    pub fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+        self.device_io_request.encode(dst)?;
+        write_padding!(dst, 32);
+        Ok(())
+    }
+
+    pub fn name(&self) -> &'static str {
+        "DR_CLOSE_REQ"
+    }
+
+    pub fn size(&self) -> usize {
+        self.device_io_request.size() + Self::FIXED_PART_SIZE
+    }
 }
 
 /// [2.2.1.5.2] Device Close Response (DR_CLOSE_RSP)
@@ -2572,6 +2868,11 @@ impl DeviceCloseResponse {
         Ok(())
     }
 
+    pub fn decode(device_io_response: DeviceIoResponse) -> Self {
+        // The padding is ignored.
+        Self { device_io_response }
+    }
+
     pub fn size(&self) -> usize {
         self.device_io_response.size() // DeviceIoResponse
         + 4 // Padding
@@ -2626,6 +2927,40 @@ impl ServerDriveQueryDirectoryRequest {
             path,
         })
     }
+
+    pub fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+        self.device_io_request.encode(dst)?;
+        dst.write_u32(self.file_info_class_lvl.into());
+        dst.write_u8(self.initial_query);
+        dst.write_u32(cast_length!(
+            "ServerDriveQueryDirectoryRequest",
+            "PathLength",
+            self.path_length()
+        )?);
+        write_padding!(dst, 23);
+        if !self.path.is_empty() {
+            write_string_to_cursor(dst, &self.path, CharacterSet::Unicode, true)?;
+        }
+        Ok(())
+    }
+
+    pub fn name(&self) -> &'static str {
+        "DR_DRIVE_QUERY_DIRECTORY_REQ"
+    }
+
+    pub fn size(&self) -> usize {
+        self.device_io_request.size() + Self::FIXED_PART_SIZE + self.path_length()
+    }
+
+    /// The path is only sent with the initial query.
+    fn path_length(&self) -> usize {
+        if self.path.is_empty() {
+            0
+        } else {
+            encoded_str_len(&self.path, CharacterSet::Unicode, true)
+        }
+    }
 }
 
 /// 2.2.3.3.11 Server Drive NotifyChange Directory Request (DR_DRIVE_NOTIFY_CHANGE_DIRECTORY_REQ)
@@ -2635,6 +2970,7 @@ impl ServerDriveQueryDirectoryRequest {
 pub struct ServerDriveNotifyChangeDirectoryRequest {
     pub device_io_request: DeviceIoRequest,
     pub watch_tree: u8,
+    /// See [`CompletionFilter`]
     pub completion_filter: u32,
 }
 
@@ -2656,6 +2992,150 @@ impl ServerDriveNotifyChangeDirectoryRequest {
     }
 }
 
+bitflags! {
+    /// CompletionFilter of the [`ServerDriveNotifyChangeDirectoryRequest`], defined in [2.2.35] SMB2 CHANGE_NOTIFY Request
+    ///
+    /// [2.2.35]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-smb2/598f395a-e7a2-4cc8-afb3-ccb30dd2df7c
+    #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+    pub struct CompletionFilter: u32 {
+        const FILE_NOTIFY_CHANGE_FILE_NAME = 0x0000_0001;
+        const FILE_NOTIFY_CHANGE_DIR_NAME = 0x0000_0002;
+        const FILE_NOTIFY_CHANGE_ATTRIBUTES = 0x0000_0004;
+        const FILE_NOTIFY_CHANGE_SIZE = 0x0000_0008;
+        const FILE_NOTIFY_CHANGE_LAST_WRITE = 0x0000_0010;
+        const FILE_NOTIFY_CHANGE_LAST_ACCESS = 0x0000_0020;
+        const FILE_NOTIFY_CHANGE_CREATION = 0x0000_0040;
+        const FILE_NOTIFY_CHANGE_EA = 0x0000_0080;
+        const FILE_NOTIFY_CHANGE_SECURITY = 0x0000_0100;
+        const FILE_NOTIFY_CHANGE_STREAM_NAME = 0x0000_0200;
+        const FILE_NOTIFY_CHANGE_STREAM_SIZE = 0x0000_0400;
+        const FILE_NOTIFY_CHANGE_STREAM_WRITE = 0x0000_0800;
+    }
+}
+
+/// 2.2.3.4.11 Client Drive NotifyChange Directory Response (DR_DRIVE_NOTIFY_CHANGE_DIRECTORY_RSP)
+///
+/// The changes are sent as a list of [`FileNotifyInformation`].
+#[derive(Debug, PartialEq, Clone)]
+pub struct ClientDriveNotifyChangeDirectoryResponse {
+    pub device_io_reply: DeviceIoResponse,
+    pub buffer: Vec<FileNotifyInformation>,
+}
+
+impl ClientDriveNotifyChangeDirectoryResponse {
+    const NAME: &'static str = "DR_DRIVE_NOTIFY_CHANGE_DIRECTORY_RSP";
+
+    pub fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    pub fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+        self.device_io_reply.encode(dst)?;
+        dst.write_u32(cast_length!(
+            "ClientDriveNotifyChangeDirectoryResponse",
+            "length",
+            self.buffer_size()
+        )?);
+
+        let mut entries = self.buffer.iter().peekable();
+        while let Some(entry) = entries.next() {
+            let is_last = entries.peek().is_none();
+            entry.encode(dst, is_last)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn size(&self) -> usize {
+        self.device_io_reply.size() // DeviceIoResponse
+        + 4 // Length
+        + self.buffer_size()
+    }
+
+    fn buffer_size(&self) -> usize {
+        let mut entries = self.buffer.iter().peekable();
+        let mut size = 0;
+        while let Some(entry) = entries.next() {
+            let is_last = entries.peek().is_none();
+            size += entry.size(is_last);
+        }
+        size
+    }
+}
+
+/// [2.7.1] FILE_NOTIFY_INFORMATION \[MS-FSCC\]
+///
+/// The entries of a list are aligned on 4-byte boundaries.
+///
+/// [2.7.1]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-fscc/634043d7-7b39-47e9-9e26-bda64685e4c9
+#[derive(Debug, PartialEq, Clone)]
+pub struct FileNotifyInformation {
+    pub action: FileAction,
+    /// The name of the changed file, relative to the watched directory
+    pub file_name: String,
+}
+
+impl FileNotifyInformation {
+    const FIXED_PART_SIZE: usize = 4 /* NextEntryOffset */ + 4 /* Action */ + 4 /* FileNameLength */;
+
+    fn encode(&self, dst: &mut WriteCursor<'_>, is_last: bool) -> EncodeResult<()> {
+        let size = self.size(is_last);
+        ensure_size!(in: dst, size: size);
+        let next_entry_offset = if is_last { 0 } else { size };
+        dst.write_u32(cast_length!(
+            "FileNotifyInformation",
+            "next_entry_offset",
+            next_entry_offset
+        )?);
+        dst.write_u32(u32::from(self.action));
+        dst.write_u32(cast_length!(
+            "FileNotifyInformation",
+            "file_name_length",
+            encoded_str_len(&self.file_name, CharacterSet::Unicode, false)
+        )?);
+        write_string_to_cursor(dst, &self.file_name, CharacterSet::Unicode, false)?;
+        write_padding!(dst, size - self.unpadded_size());
+        Ok(())
+    }
+
+    fn unpadded_size(&self) -> usize {
+        Self::FIXED_PART_SIZE + encoded_str_len(&self.file_name, CharacterSet::Unicode, false)
+    }
+
+    /// The size of the entry, padded to 4 bytes unless it is the last one.
+    fn size(&self, is_last: bool) -> usize {
+        let size = self.unpadded_size();
+        if is_last {
+            size
+        } else {
+            size.next_multiple_of(4)
+        }
+    }
+}
+
+/// Action of a [`FileNotifyInformation`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(u32)]
+pub enum FileAction {
+    /// FILE_ACTION_ADDED
+    Added = 0x0000_0001,
+    /// FILE_ACTION_REMOVED
+    Removed = 0x0000_0002,
+    /// FILE_ACTION_MODIFIED
+    Modified = 0x0000_0003,
+    /// FILE_ACTION_RENAMED_OLD_NAME
+    RenamedOldName = 0x0000_0004,
+    /// FILE_ACTION_RENAMED_NEW_NAME
+    RenamedNewName = 0x0000_0005,
+}
+
+impl From<FileAction> for u32 {
+    fn from(action: FileAction) -> Self {
+        action as u32
+    }
+}
+
 /// [2.2.3.4.10] Client Drive Query Directory Response (DR_DRIVE_QUERY_DIRECTORY_RSP)
 ///
 /// [2.2.3.4.10]: https://docs.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpefs/9c929407-a833-4893-8f20-90c984756140
@@ -2692,6 +3172,45 @@ impl ClientDriveQueryDirectoryResponse {
         Ok(())
     }
 
+    /// Decodes the response to a query directory request of the given information class.
+    ///
+    /// Only [`FileInformationClassLevel::FILE_BOTH_DIRECTORY_INFORMATION`] is supported.
+    pub fn decode(
+        device_io_reply: DeviceIoResponse,
+        file_info_class_lvl: FileInformationClassLevel,
+        src: &mut ReadCursor<'_>,
+    ) -> DecodeResult<Self> {
+        ensure_size!(ctx: Self::NAME, in: src, size: 4);
+        let length = cast_length!("ClientDriveQueryDirectoryResponse", "length", src.read_u32())?;
+        if length == 0 {
+            // The padding is ignored.
+            return Ok(Self {
+                device_io_reply,
+                buffer: None,
+            });
+        }
+
+        ensure_size!(ctx: Self::NAME, in: src, size: length);
+        let mut buffer = ReadCursor::new(src.read_slice(length));
+        let buffer = match file_info_class_lvl {
+            FileInformationClassLevel::FILE_BOTH_DIRECTORY_INFORMATION => {
+                FileBothDirectoryInformation::decode(&mut buffer, length)?.into()
+            }
+            _ => {
+                return Err(unsupported_value_err!(
+                    "ClientDriveQueryDirectoryResponse::decode",
+                    "FileInformationClassLevel",
+                    file_info_class_lvl.to_string()
+                ))
+            }
+        };
+
+        Ok(Self {
+            device_io_reply,
+            buffer: Some(buffer),
+        })
+    }
+
     pub fn size(&self) -> usize {
         self.device_io_reply.size() // DeviceIoResponse
         + 4 // Length
@@ -3145,6 +3664,23 @@ impl DeviceReadRequest {
             offset,
         })
     }
+
+    pub fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+        self.device_io_request.encode(dst)?;
+        dst.write_u32(self.length);
+        dst.write_u64(self.offset);
+        write_padding!(dst, 20);
+        Ok(())
+    }
+
+    pub fn name(&self) -> &'static str {
+        "DR_READ_REQ"
+    }
+
+    pub fn size(&self) -> usize {
+        self.device_io_request.size() + Self::FIXED_PART_SIZE
+    }
 }
 
 /// [2.2.1.5.3] Device Read Response (DR_READ_RSP)
@@ -3166,6 +3702,19 @@ impl DeviceReadResponse {
         Ok(())
     }
 
+    pub fn decode(device_io_reply: DeviceIoResponse, src: &mut ReadCursor<'_>) -> DecodeResult<Self> {
+        ensure_size!(ctx: Self::NAME, in: src, size: 4);
+        let length = cast_length!("DeviceReadResponse", "length", src.read_u32())?;
+
+        ensure_size!(ctx: Self::NAME, in: src, size: length);
+        let read_data = src.read_slice(length).to_vec();
+
+        Ok(Self {
+            device_io_reply,
+            read_data,
+        })
+    }
+
     pub fn name(&self) -> &'static str {
         Self::NAME
     }
@@ -3215,6 +3764,24 @@ impl DeviceWriteRequest {
             write_data,
         })
     }
+
+    pub fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+        self.device_io_request.encode(dst)?;
+        dst.write_u32(cast_length!("DeviceWriteRequest", "length", self.write_data.len())?);
+        dst.write_u64(self.offset);
+        write_padding!(dst, 20);
+        dst.write_slice(&self.write_data);
+        Ok(())
+    }
+
+    pub fn name(&self) -> &'static str {
+        "DR_WRITE_REQ"
+    }
+
+    pub fn size(&self) -> usize {
+        self.device_io_request.size() + Self::FIXED_PART_SIZE + self.write_data.len()
+    }
 }
 
 impl Debug for DeviceWriteRequest {
@@ -3251,6 +3818,17 @@ impl DeviceWriteResponse {
         Ok(())
     }
 
+    pub fn decode(device_io_reply: DeviceIoResponse, src: &mut ReadCursor<'_>) -> DecodeResult<Self> {
+        ensure_size!(ctx: Self::NAME, in: src, size: 4);
+        let length = src.read_u32();
+        // The padding is optional.
+
+        Ok(Self {
+            device_io_reply,
+            length,
+        })
+    }
+
     pub fn size(&self) -> usize {
         self.device_io_reply.size() // DeviceIoResponse
         + 4 // Length
@@ -3450,16 +4028,111 @@ impl ClientDriveSetInformationResponse {
 #[derive(Debug, PartialEq, Clone)]
 pub struct ServerDriveLockControlRequest {
     pub device_io_request: DeviceIoRequest,
+    pub operation: LockOperation,
+    /// The F bit: if set, the client waits for the lock to be granted, otherwise the request fails
+    /// immediately when the region cannot be locked
+    pub wait: bool,
+    pub locks: Vec<RdpLockInfo>,
 }
 
 impl ServerDriveLockControlRequest {
+    const NAME: &'static str = "DR_DRIVE_LOCK_REQ";
+    const FIXED_PART_SIZE: usize = 4 /* Operation */ + 4 /* F + Padding */ + 4 /* NumLocks */ + 20 /* Padding2 */;
+
     fn decode(dev_io_req: DeviceIoRequest, src: &mut ReadCursor<'_>) -> DecodeResult<Self> {
-        // It's not quite clear why this is done this way, but it's what FreeRDP does:
-        // https://github.com/FreeRDP/FreeRDP/blob/dfa231c0a55b005af775b833f92f6bcd30363d77/channels/drive/client/drive_main.c#L600
-        ensure_size!(in: src, size: 4);
-        let _ = src.read_u32();
+        ensure_fixed_part_size!(in: src);
+        let operation = LockOperation::try_from(src.read_u32())?;
+        let wait = src.read_u32() & 0x0000_0001 != 0;
+        let num_locks = cast_length!("ServerDriveLockControlRequest", "num_locks", src.read_u32())?;
+        // Padding2 (20 bytes): An array of 20 bytes. Reserved. This field can be set to any value and MUST be ignored.
+        read_padding!(src, 20);
+
+        ensure_size!(ctx: Self::NAME, in: src, size: num_locks * RdpLockInfo::FIXED_PART_SIZE);
+        let locks = (0..num_locks).map(|_| RdpLockInfo::decode(src)).collect();
+
         Ok(Self {
             device_io_request: dev_io_req,
+            operation,
+            wait,
+            locks,
         })
     }
 }
+
+/// Operation of a [`ServerDriveLockControlRequest`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(u32)]
+pub enum LockOperation {
+    /// RDP_LOWIO_OP_SHAREDLOCK
+    SharedLock = 0x0000_0002,
+    /// RDP_LOWIO_OP_EXCLUSIVELOCK
+    ExclusiveLock = 0x0000_0003,
+    /// RDP_LOWIO_OP_UNLOCK
+    Unlock = 0x0000_0004,
+    /// RDP_LOWIO_OP_UNLOCK_MULTIPLE
+    UnlockMultiple = 0x0000_0005,
+}
+
+impl TryFrom<u32> for LockOperation {
+    type Error = DecodeError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0x0000_0002 => Ok(LockOperation::SharedLock),
+            0x0000_0003 => Ok(LockOperation::ExclusiveLock),
+            0x0000_0004 => Ok(LockOperation::Unlock),
+            0x0000_0005 => Ok(LockOperation::UnlockMultiple),
+            _ => Err(invalid_field_err!("try_from", "LockOperation", "unsupported value")),
+        }
+    }
+}
+
+impl From<LockOperation> for u32 {
+    fn from(operation: LockOperation) -> Self {
+        operation as u32
+    }
+}
+
+/// 2.2.1.6 RDP_LOCK_INFO
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RdpLockInfo {
+    pub length: u64,
+    pub offset: u64,
+}
+
+impl RdpLockInfo {
+    const FIXED_PART_SIZE: usize = 8 /* Length */ + 8 /* Offset */;
+
+    fn decode(src: &mut ReadCursor<'_>) -> Self {
+        let length = src.read_u64();
+        let offset = src.read_u64();
+        Self { length, offset }
+    }
+}
+
+/// 2.2.3.4.12 Client Drive Lock Control Response (DR_DRIVE_LOCK_RSP)
+#[derive(Debug, PartialEq, Clone)]
+pub struct ClientDriveLockControlResponse {
+    pub device_io_reply: DeviceIoResponse,
+}
+
+impl ClientDriveLockControlResponse {
+    const NAME: &'static str = "DR_DRIVE_LOCK_RSP";
+
+    pub fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    pub fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+        self.device_io_reply.encode(dst)?;
+        // Padding (5 bytes): An array of 5 bytes. Reserved. This field can be set to any value and MUST be ignored.
+        write_padding!(dst, 5);
+        Ok(())
+    }
+
+    pub fn size(&self) -> usize {
+        self.device_io_reply.size() // DeviceIoResponse
+        + 5 // Padding
+    }
+}
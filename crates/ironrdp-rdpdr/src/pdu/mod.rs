@@ -8,10 +8,12 @@ use ironrdp_core::{
 use ironrdp_svc::SvcEncode;
 
 use self::efs::{
-    ClientDeviceListAnnounce, ClientDriveQueryDirectoryResponse, ClientDriveQueryInformationResponse,
-    ClientDriveQueryVolumeInformationResponse, ClientDriveSetInformationResponse, ClientNameRequest, CoreCapability,
-    CoreCapabilityKind, DeviceCloseResponse, DeviceControlResponse, DeviceCreateResponse, DeviceIoRequest,
-    DeviceReadResponse, DeviceWriteResponse, ServerDeviceAnnounceResponse, VersionAndIdPdu, VersionAndIdPduKind,
+    ClientDeviceListAnnounce, ClientDriveLockControlResponse, ClientDriveNotifyChangeDirectoryResponse,
+    ClientDriveQueryDirectoryResponse, ClientDriveQueryInformationResponse, ClientDriveQueryVolumeInformationResponse,
+    ClientDriveSetInformationResponse, ClientNameRequest, CoreCapability, CoreCapabilityKind, DeviceCloseRequest,
+    DeviceCloseResponse, DeviceControlResponse, DeviceCreateRequest, DeviceCreateResponse, DeviceIoRequest,
+    DeviceReadRequest, DeviceReadResponse, DeviceWriteRequest, DeviceWriteResponse, ServerDeviceAnnounceResponse,
+    ServerDriveQueryDirectoryRequest, VersionAndIdPdu, VersionAndIdPduKind,
 };
 
 pub mod efs;
@@ -24,7 +26,14 @@ pub enum RdpdrPdu {
     CoreCapability(CoreCapability),
     ClientDeviceListAnnounce(ClientDeviceListAnnounce),
     ServerDeviceAnnounceResponse(ServerDeviceAnnounceResponse),
+    /// 2.2.2.5 Server User Logged On (DR_CORE_USER_LOGGEDON), which is only a header
+    UserLoggedOn,
     DeviceIoRequest(DeviceIoRequest),
+    DeviceCreateRequest(DeviceCreateRequest),
+    DeviceCloseRequest(DeviceCloseRequest),
+    DeviceReadRequest(DeviceReadRequest),
+    DeviceWriteRequest(DeviceWriteRequest),
+    ServerDriveQueryDirectoryRequest(ServerDriveQueryDirectoryRequest),
     DeviceControlResponse(DeviceControlResponse),
     DeviceCreateResponse(DeviceCreateResponse),
     ClientDriveQueryInformationResponse(ClientDriveQueryInformationResponse),
@@ -34,6 +43,8 @@ pub enum RdpdrPdu {
     DeviceReadResponse(DeviceReadResponse),
     DeviceWriteResponse(DeviceWriteResponse),
     ClientDriveSetInformationResponse(ClientDriveSetInformationResponse),
+    ClientDriveNotifyChangeDirectoryResponse(ClientDriveNotifyChangeDirectoryResponse),
+    ClientDriveLockControlResponse(ClientDriveLockControlResponse),
     EmptyResponse,
 }
 
@@ -77,7 +88,16 @@ impl RdpdrPdu {
                 component: Component::RdpdrCtypCore,
                 packet_id: PacketId::CoreDeviceReply,
             },
-            RdpdrPdu::DeviceIoRequest(_) => SharedHeader {
+            RdpdrPdu::UserLoggedOn => SharedHeader {
+                component: Component::RdpdrCtypCore,
+                packet_id: PacketId::CoreUserLoggedon,
+            },
+            RdpdrPdu::DeviceIoRequest(_)
+            | RdpdrPdu::DeviceCreateRequest(_)
+            | RdpdrPdu::DeviceCloseRequest(_)
+            | RdpdrPdu::DeviceReadRequest(_)
+            | RdpdrPdu::DeviceWriteRequest(_)
+            | RdpdrPdu::ServerDriveQueryDirectoryRequest(_) => SharedHeader {
                 component: Component::RdpdrCtypCore,
                 packet_id: PacketId::CoreDeviceIoRequest,
             },
@@ -90,6 +110,8 @@ impl RdpdrPdu {
             | RdpdrPdu::DeviceReadResponse(_)
             | RdpdrPdu::DeviceWriteResponse(_)
             | RdpdrPdu::ClientDriveSetInformationResponse(_)
+            | RdpdrPdu::ClientDriveNotifyChangeDirectoryResponse(_)
+            | RdpdrPdu::ClientDriveLockControlResponse(_)
             | RdpdrPdu::EmptyResponse => SharedHeader {
                 component: Component::RdpdrCtypCore,
                 packet_id: PacketId::CoreDeviceIoCompletion,
@@ -109,6 +131,7 @@ impl Decode<'_> for RdpdrPdu {
                 ServerDeviceAnnounceResponse::decode(src)?,
             )),
             PacketId::CoreDeviceIoRequest => Ok(RdpdrPdu::DeviceIoRequest(DeviceIoRequest::decode(src)?)),
+            PacketId::CoreUserLoggedon => Ok(RdpdrPdu::UserLoggedOn),
             _ => Err(unsupported_value_err!(
                 "RdpdrPdu",
                 "PacketId",
@@ -128,7 +151,13 @@ impl Encode for RdpdrPdu {
             RdpdrPdu::CoreCapability(pdu) => pdu.encode(dst),
             RdpdrPdu::ClientDeviceListAnnounce(pdu) => pdu.encode(dst),
             RdpdrPdu::ServerDeviceAnnounceResponse(pdu) => pdu.encode(dst),
+            RdpdrPdu::UserLoggedOn => Ok(()),
             RdpdrPdu::DeviceIoRequest(pdu) => pdu.encode(dst),
+            RdpdrPdu::DeviceCreateRequest(pdu) => pdu.encode(dst),
+            RdpdrPdu::DeviceCloseRequest(pdu) => pdu.encode(dst),
+            RdpdrPdu::DeviceReadRequest(pdu) => pdu.encode(dst),
+            RdpdrPdu::DeviceWriteRequest(pdu) => pdu.encode(dst),
+            RdpdrPdu::ServerDriveQueryDirectoryRequest(pdu) => pdu.encode(dst),
             RdpdrPdu::DeviceControlResponse(pdu) => pdu.encode(dst),
             RdpdrPdu::DeviceCreateResponse(pdu) => pdu.encode(dst),
             RdpdrPdu::ClientDriveQueryInformationResponse(pdu) => pdu.encode(dst),
@@ -138,6 +167,8 @@ impl Encode for RdpdrPdu {
             RdpdrPdu::DeviceReadResponse(pdu) => pdu.encode(dst),
             RdpdrPdu::DeviceWriteResponse(pdu) => pdu.encode(dst),
             RdpdrPdu::ClientDriveSetInformationResponse(pdu) => pdu.encode(dst),
+            RdpdrPdu::ClientDriveNotifyChangeDirectoryResponse(pdu) => pdu.encode(dst),
+            RdpdrPdu::ClientDriveLockControlResponse(pdu) => pdu.encode(dst),
             RdpdrPdu::EmptyResponse => {
                 // https://github.com/FreeRDP/FreeRDP/blob/dfa231c0a55b005af775b833f92f6bcd30363d77/channels/drive/client/drive_main.c#L601
                 dst.write_u32(0);
@@ -153,7 +184,13 @@ impl Encode for RdpdrPdu {
             RdpdrPdu::CoreCapability(pdu) => pdu.name(),
             RdpdrPdu::ClientDeviceListAnnounce(pdu) => pdu.name(),
             RdpdrPdu::ServerDeviceAnnounceResponse(pdu) => pdu.name(),
+            RdpdrPdu::UserLoggedOn => "DR_CORE_USER_LOGGEDON",
             RdpdrPdu::DeviceIoRequest(pdu) => pdu.name(),
+            RdpdrPdu::DeviceCreateRequest(pdu) => pdu.name(),
+            RdpdrPdu::DeviceCloseRequest(pdu) => pdu.name(),
+            RdpdrPdu::DeviceReadRequest(pdu) => pdu.name(),
+            RdpdrPdu::DeviceWriteRequest(pdu) => pdu.name(),
+            RdpdrPdu::ServerDriveQueryDirectoryRequest(pdu) => pdu.name(),
             RdpdrPdu::DeviceControlResponse(pdu) => pdu.name(),
             RdpdrPdu::DeviceCreateResponse(pdu) => pdu.name(),
             RdpdrPdu::ClientDriveQueryInformationResponse(pdu) => pdu.name(),
@@ -163,6 +200,8 @@ impl Encode for RdpdrPdu {
             RdpdrPdu::DeviceReadResponse(pdu) => pdu.name(),
             RdpdrPdu::DeviceWriteResponse(pdu) => pdu.name(),
             RdpdrPdu::ClientDriveSetInformationResponse(pdu) => pdu.name(),
+            RdpdrPdu::ClientDriveNotifyChangeDirectoryResponse(pdu) => pdu.name(),
+            RdpdrPdu::ClientDriveLockControlResponse(pdu) => pdu.name(),
             RdpdrPdu::EmptyResponse => "EmptyResponse",
         }
     }
@@ -175,7 +214,13 @@ impl Encode for RdpdrPdu {
                 RdpdrPdu::CoreCapability(pdu) => pdu.size(),
                 RdpdrPdu::ClientDeviceListAnnounce(pdu) => pdu.size(),
                 RdpdrPdu::ServerDeviceAnnounceResponse(pdu) => pdu.size(),
+                RdpdrPdu::UserLoggedOn => 0,
                 RdpdrPdu::DeviceIoRequest(pdu) => pdu.size(),
+                RdpdrPdu::DeviceCreateRequest(pdu) => pdu.size(),
+                RdpdrPdu::DeviceCloseRequest(pdu) => pdu.size(),
+                RdpdrPdu::DeviceReadRequest(pdu) => pdu.size(),
+                RdpdrPdu::DeviceWriteRequest(pdu) => pdu.size(),
+                RdpdrPdu::ServerDriveQueryDirectoryRequest(pdu) => pdu.size(),
                 RdpdrPdu::DeviceControlResponse(pdu) => pdu.size(),
                 RdpdrPdu::DeviceCreateResponse(pdu) => pdu.size(),
                 RdpdrPdu::ClientDriveQueryInformationResponse(pdu) => pdu.size(),
@@ -185,6 +230,8 @@ impl Encode for RdpdrPdu {
                 RdpdrPdu::DeviceReadResponse(pdu) => pdu.size(),
                 RdpdrPdu::DeviceWriteResponse(pdu) => pdu.size(),
                 RdpdrPdu::ClientDriveSetInformationResponse(pdu) => pdu.size(),
+                RdpdrPdu::ClientDriveNotifyChangeDirectoryResponse(pdu) => pdu.size(),
+                RdpdrPdu::ClientDriveLockControlResponse(pdu) => pdu.size(),
                 RdpdrPdu::EmptyResponse => size_of::<u32>(),
             }
     }
@@ -210,9 +257,27 @@ impl fmt::Debug for RdpdrPdu {
             Self::ServerDeviceAnnounceResponse(it) => {
                 write!(f, "RdpdrPdu({:?})", it)
             }
+            Self::UserLoggedOn => {
+                write!(f, "RdpdrPdu(UserLoggedOn)")
+            }
             Self::DeviceIoRequest(it) => {
                 write!(f, "RdpdrPdu({:?})", it)
             }
+            Self::DeviceCreateRequest(it) => {
+                write!(f, "RdpdrPdu({:?})", it)
+            }
+            Self::DeviceCloseRequest(it) => {
+                write!(f, "RdpdrPdu({:?})", it)
+            }
+            Self::DeviceReadRequest(it) => {
+                write!(f, "RdpdrPdu({:?})", it)
+            }
+            Self::DeviceWriteRequest(it) => {
+                write!(f, "RdpdrPdu({:?})", it)
+            }
+            Self::ServerDriveQueryDirectoryRequest(it) => {
+                write!(f, "RdpdrPdu({:?})", it)
+            }
             Self::DeviceControlResponse(it) => {
                 write!(f, "RdpdrPdu({:?})", it)
             }
@@ -240,6 +305,12 @@ impl fmt::Debug for RdpdrPdu {
             Self::ClientDriveSetInformationResponse(it) => {
                 write!(f, "RdpdrPdu({:?})", it)
             }
+            Self::ClientDriveNotifyChangeDirectoryResponse(it) => {
+                write!(f, "RdpdrPdu({:?})", it)
+            }
+            Self::ClientDriveLockControlResponse(it) => {
+                write!(f, "RdpdrPdu({:?})", it)
+            }
             Self::EmptyResponse => {
                 write!(f, "RdpdrPdu(EmptyResponse)")
             }
@@ -247,6 +318,36 @@ impl fmt::Debug for RdpdrPdu {
     }
 }
 
+impl From<DeviceCreateRequest> for RdpdrPdu {
+    fn from(value: DeviceCreateRequest) -> Self {
+        Self::DeviceCreateRequest(value)
+    }
+}
+
+impl From<DeviceCloseRequest> for RdpdrPdu {
+    fn from(value: DeviceCloseRequest) -> Self {
+        Self::DeviceCloseRequest(value)
+    }
+}
+
+impl From<DeviceReadRequest> for RdpdrPdu {
+    fn from(value: DeviceReadRequest) -> Self {
+        Self::DeviceReadRequest(value)
+    }
+}
+
+impl From<DeviceWriteRequest> for RdpdrPdu {
+    fn from(value: DeviceWriteRequest) -> Self {
+        Self::DeviceWriteRequest(value)
+    }
+}
+
+impl From<ServerDriveQueryDirectoryRequest> for RdpdrPdu {
+    fn from(value: ServerDriveQueryDirectoryRequest) -> Self {
+        Self::ServerDriveQueryDirectoryRequest(value)
+    }
+}
+
 impl From<DeviceControlResponse> for RdpdrPdu {
     fn from(value: DeviceControlResponse) -> Self {
         Self::DeviceControlResponse(value)
@@ -301,6 +402,18 @@ impl From<ClientDriveSetInformationResponse> for RdpdrPdu {
     }
 }
 
+impl From<ClientDriveNotifyChangeDirectoryResponse> for RdpdrPdu {
+    fn from(value: ClientDriveNotifyChangeDirectoryResponse) -> Self {
+        Self::ClientDriveNotifyChangeDirectoryResponse(value)
+    }
+}
+
+impl From<ClientDriveLockControlResponse> for RdpdrPdu {
+    fn from(value: ClientDriveLockControlResponse) -> Self {
+        Self::ClientDriveLockControlResponse(value)
+    }
+}
+
 /// [2.2.1.1] Shared Header (RDPDR_HEADER), a header that is shared by all RDPDR PDUs.
 ///
 /// [2.2.1.1]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpefs/29d4108f-8163-4a67-8271-e48c4b9c2a7c
@@ -0,0 +1,458 @@
+use core::fmt;
+use std::collections::HashMap;
+
+use ironrdp_core::{impl_as_any, ReadCursor};
+use ironrdp_pdu::gcc::ChannelName;
+use ironrdp_pdu::{decode_err, pdu_other_err, PduResult};
+use ironrdp_svc::{CompressionCondition, SvcMessage, SvcProcessor, SvcProcessorMessages, SvcServerProcessor};
+
+use crate::pdu::efs::{
+    Capabilities, ClientDeviceListAnnounce, ClientDeviceListRemove, ClientDriveQueryDirectoryResponse,
+    ClientNameRequest, CoreCapability, CreateDisposition, CreateOptions, DesiredAccess, DeviceAnnounceHeader,
+    DeviceCloseRequest, DeviceCloseResponse, DeviceCreateRequest, DeviceCreateResponse, DeviceIoRequest,
+    DeviceIoResponse, DeviceReadRequest, DeviceReadResponse, DeviceType, DeviceWriteRequest, DeviceWriteResponse,
+    FileAttributes, FileInformationClassLevel, MajorFunction, MinorFunction, NtStatus, ServerDeviceAnnounceResponse,
+    ServerDriveQueryDirectoryRequest, SharedAccess, VersionAndIdPdu, VersionAndIdPduKind,
+};
+use crate::pdu::{PacketId, RdpdrPdu, SharedHeader};
+
+pub type RdpdrServerMessages = SvcProcessorMessages<RdpdrServer>;
+
+/// Receives the devices announced by the client
+pub trait RdpdrServerHandler: Send + fmt::Debug {
+    /// Called when the client announces new devices
+    fn devices_announced(&mut self, devices: &[DeviceAnnounceHeader]);
+
+    /// Called when the client stops redirecting devices
+    fn devices_removed(&mut self, device_ids: &[u32]);
+}
+
+/// I/O request on a drive redirected by the client
+#[derive(Debug, Clone)]
+pub enum DriveRequest {
+    /// Opens or creates a file or a directory
+    Create {
+        device_id: u32,
+        path: String,
+        desired_access: DesiredAccess,
+        create_disposition: CreateDisposition,
+        create_options: CreateOptions,
+    },
+    Close {
+        device_id: u32,
+        file_id: u32,
+    },
+    Read {
+        device_id: u32,
+        file_id: u32,
+        offset: u64,
+        length: u32,
+    },
+    Write {
+        device_id: u32,
+        file_id: u32,
+        offset: u64,
+        data: Vec<u8>,
+    },
+    /// Returns the next entry of an opened directory, with [`FileBothDirectoryInformation`]
+    ///
+    /// The `path`, which may contain wildcards, is only sent with the initial query.
+    ///
+    /// [`FileBothDirectoryInformation`]: crate::pdu::efs::FileBothDirectoryInformation
+    QueryDirectory {
+        device_id: u32,
+        file_id: u32,
+        initial_query: bool,
+        path: String,
+    },
+}
+
+impl DriveRequest {
+    fn device_id(&self) -> u32 {
+        match self {
+            Self::Create { device_id, .. }
+            | Self::Close { device_id, .. }
+            | Self::Read { device_id, .. }
+            | Self::Write { device_id, .. }
+            | Self::QueryDirectory { device_id, .. } => *device_id,
+        }
+    }
+}
+
+/// Completion of a [`DriveRequest`]
+#[derive(Debug)]
+pub enum DriveResponse {
+    Create(DeviceCreateResponse),
+    Close(DeviceCloseResponse),
+    Read(DeviceReadResponse),
+    Write(DeviceWriteResponse),
+    QueryDirectory(ClientDriveQueryDirectoryResponse),
+}
+
+impl DriveResponse {
+    pub fn io_status(&self) -> NtStatus {
+        match self {
+            Self::Create(response) => response.device_io_reply.io_status,
+            Self::Close(response) => response.device_io_response.io_status,
+            Self::Read(response) => response.device_io_reply.io_status,
+            Self::Write(response) => response.device_io_reply.io_status,
+            Self::QueryDirectory(response) => response.device_io_reply.io_status,
+        }
+    }
+}
+
+/// Called with the response of the client to a [`DriveRequest`]
+///
+/// The completion is dropped without being called when the device is removed.
+pub type DriveCompletion = Box<dyn FnOnce(DriveResponse) + Send>;
+
+/// I/O request waiting for its completion by the client
+struct PendingIrp {
+    device_id: u32,
+    major_function: MajorFunction,
+    completion: DriveCompletion,
+}
+
+impl fmt::Debug for PendingIrp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PendingIrp")
+            .field("device_id", &self.device_id)
+            .field("major_function", &self.major_function)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum RdpdrState {
+    Start,
+    WaitingForClientAnnounceReply,
+    Ready,
+}
+
+/// The server side of the RDPDR channel as specified in [\[MS-RDPEFS\]]
+///
+/// The server announces drive and smart card redirection, keeps track of the devices announced
+/// by the client, and sends I/O requests to the redirected drives. The responses of the client are
+/// routed to the completion of each request, by completion ID.
+///
+/// [\[MS-RDPEFS\]]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpefs/34d9de58-b2b5-40b6-b970-f82d4603bdb5
+#[derive(Debug)]
+pub struct RdpdrServer {
+    handler: Box<dyn RdpdrServerHandler>,
+    state: RdpdrState,
+    client_id: u32,
+    computer_name: Option<String>,
+    devices: Vec<DeviceAnnounceHeader>,
+    pending: HashMap<u32, PendingIrp>,
+    next_completion_id: u32,
+}
+
+impl_as_any!(RdpdrServer);
+
+impl RdpdrServer {
+    pub const NAME: ChannelName = ChannelName::from_static(b"rdpdr\0\0\0");
+
+    pub fn new(handler: Box<dyn RdpdrServerHandler>) -> Self {
+        Self {
+            handler,
+            state: RdpdrState::Start,
+            // The client ID is picked by the server, it is only echoed back by the client.
+            client_id: 1,
+            computer_name: None,
+            devices: Vec::new(),
+            pending: HashMap::new(),
+            next_completion_id: 0,
+        }
+    }
+
+    /// Returns the devices currently redirected by the client
+    pub fn devices(&self) -> &[DeviceAnnounceHeader] {
+        &self.devices
+    }
+
+    /// Returns the name of the client computer, once sent by the client
+    pub fn computer_name(&self) -> Option<&str> {
+        self.computer_name.as_deref()
+    }
+
+    /// Sends an I/O request to a drive
+    ///
+    /// The `completion` is called when the client responds to the request.
+    pub fn drive_request(
+        &mut self,
+        request: DriveRequest,
+        completion: DriveCompletion,
+    ) -> PduResult<RdpdrServerMessages> {
+        if self.state != RdpdrState::Ready {
+            return Err(pdu_other_err!("RdpdrServer", "channel is not ready"));
+        }
+
+        let device_id = request.device_id();
+        if !self
+            .devices
+            .iter()
+            .any(|device| device.device_id() == device_id && device.device_type() == DeviceType::Filesystem)
+        {
+            return Err(pdu_other_err!("RdpdrServer", "no drive with that ID"));
+        }
+
+        let completion_id = self.next_completion_id();
+        let io_request = |file_id, major_function, minor_function| DeviceIoRequest {
+            device_id,
+            file_id,
+            completion_id,
+            major_function,
+            minor_function,
+        };
+
+        let pdu = match request {
+            DriveRequest::Create {
+                path,
+                desired_access,
+                create_disposition,
+                create_options,
+                ..
+            } => RdpdrPdu::from(DeviceCreateRequest {
+                device_io_request: io_request(0, MajorFunction::Create, MinorFunction::from(0)),
+                desired_access,
+                allocation_size: 0,
+                file_attributes: FileAttributes::empty(),
+                shared_access: SharedAccess::FILE_SHARE_READ,
+                create_disposition,
+                create_options,
+                path,
+            }),
+            DriveRequest::Close { file_id, .. } => RdpdrPdu::from(DeviceCloseRequest {
+                device_io_request: io_request(file_id, MajorFunction::Close, MinorFunction::from(0)),
+            }),
+            DriveRequest::Read {
+                file_id,
+                offset,
+                length,
+                ..
+            } => RdpdrPdu::from(DeviceReadRequest {
+                device_io_request: io_request(file_id, MajorFunction::Read, MinorFunction::from(0)),
+                length,
+                offset,
+            }),
+            DriveRequest::Write {
+                file_id, offset, data, ..
+            } => RdpdrPdu::from(DeviceWriteRequest {
+                device_io_request: io_request(file_id, MajorFunction::Write, MinorFunction::from(0)),
+                offset,
+                write_data: data,
+            }),
+            DriveRequest::QueryDirectory {
+                file_id,
+                initial_query,
+                path,
+                ..
+            } => RdpdrPdu::from(ServerDriveQueryDirectoryRequest {
+                device_io_request: io_request(
+                    file_id,
+                    MajorFunction::DirectoryControl,
+                    MinorFunction::IRP_MN_QUERY_DIRECTORY,
+                ),
+                file_info_class_lvl: FileInformationClassLevel::FILE_BOTH_DIRECTORY_INFORMATION,
+                initial_query: u8::from(initial_query),
+                path,
+            }),
+        };
+
+        let major_function = match &pdu {
+            RdpdrPdu::DeviceCreateRequest(pdu) => pdu.device_io_request.major_function,
+            RdpdrPdu::DeviceCloseRequest(pdu) => pdu.device_io_request.major_function,
+            RdpdrPdu::DeviceReadRequest(pdu) => pdu.device_io_request.major_function,
+            RdpdrPdu::DeviceWriteRequest(pdu) => pdu.device_io_request.major_function,
+            RdpdrPdu::ServerDriveQueryDirectoryRequest(pdu) => pdu.device_io_request.major_function,
+            _ => unreachable!("not a drive request"),
+        };
+
+        self.pending.insert(
+            completion_id,
+            PendingIrp {
+                device_id,
+                major_function,
+                completion,
+            },
+        );
+
+        Ok(RdpdrServerMessages::new(vec![SvcMessage::from(pdu)]))
+    }
+
+    fn next_completion_id(&mut self) -> u32 {
+        loop {
+            let completion_id = self.next_completion_id;
+            self.next_completion_id = self.next_completion_id.wrapping_add(1);
+            if !self.pending.contains_key(&completion_id) {
+                return completion_id;
+            }
+        }
+    }
+
+    fn handle_client_announce_reply(&mut self, pdu: VersionAndIdPdu) -> PduResult<Vec<SvcMessage>> {
+        if self.state != RdpdrState::WaitingForClientAnnounceReply {
+            return Err(pdu_other_err!("RdpdrServer", "unexpected client announce reply"));
+        }
+
+        // Both the client announce reply and the server client ID confirm use
+        // PAKID_CORE_CLIENTID_CONFIRM.
+        let reply = VersionAndIdPdu {
+            kind: VersionAndIdPduKind::ClientAnnounceReply,
+            ..pdu
+        };
+        debug!(?reply);
+
+        let capabilities =
+            RdpdrPdu::CoreCapability(CoreCapability::new_request(Capabilities::new_server().clone_inner()));
+        let client_id_confirm = RdpdrPdu::VersionAndIdPdu(VersionAndIdPdu::new_server_client_id_confirm(&reply));
+        self.state = RdpdrState::Ready;
+
+        Ok(vec![
+            SvcMessage::from(capabilities),
+            SvcMessage::from(client_id_confirm),
+        ])
+    }
+
+    fn handle_client_capability(pdu: CoreCapability) -> Vec<SvcMessage> {
+        debug!(?pdu);
+
+        // The server is only started once the user is logged on. Without this PDU, the client
+        // only announces the devices that are safe to redirect before logon, such as smart cards.
+        if pdu.user_logged_on_pdu_allowed() {
+            vec![SvcMessage::from(RdpdrPdu::UserLoggedOn)]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn handle_device_list_announce(&mut self, pdu: ClientDeviceListAnnounce) -> Vec<SvcMessage> {
+        let responses = pdu
+            .device_list
+            .iter()
+            .map(|device| {
+                SvcMessage::from(RdpdrPdu::ServerDeviceAnnounceResponse(ServerDeviceAnnounceResponse {
+                    device_id: device.device_id(),
+                    result_code: NtStatus::SUCCESS,
+                }))
+            })
+            .collect();
+
+        self.handler.devices_announced(&pdu.device_list);
+        for device in pdu.device_list {
+            self.devices.retain(|d| d.device_id() != device.device_id());
+            self.devices.push(device);
+        }
+
+        responses
+    }
+
+    fn handle_device_list_remove(&mut self, pdu: ClientDeviceListRemove) {
+        self.devices.retain(|d| !pdu.device_ids.contains(&d.device_id()));
+        // The requests on the removed devices will never complete.
+        self.pending.retain(|_, irp| !pdu.device_ids.contains(&irp.device_id));
+        self.handler.devices_removed(&pdu.device_ids);
+    }
+
+    fn handle_device_io_completion(&mut self, src: &mut ReadCursor<'_>) -> PduResult<()> {
+        let reply = DeviceIoResponse::decode(src).map_err(|e| decode_err!(e))?;
+
+        let Some(irp) = self.pending.remove(&reply.completion_id) else {
+            warn!(?reply, "Unexpected I/O completion");
+            return Ok(());
+        };
+
+        let response = match irp.major_function {
+            MajorFunction::Create => {
+                DriveResponse::Create(DeviceCreateResponse::decode(reply, src).map_err(|e| decode_err!(e))?)
+            }
+            MajorFunction::Close => DriveResponse::Close(DeviceCloseResponse::decode(reply)),
+            MajorFunction::Read => {
+                DriveResponse::Read(DeviceReadResponse::decode(reply, src).map_err(|e| decode_err!(e))?)
+            }
+            MajorFunction::Write => {
+                DriveResponse::Write(DeviceWriteResponse::decode(reply, src).map_err(|e| decode_err!(e))?)
+            }
+            MajorFunction::DirectoryControl => DriveResponse::QueryDirectory(
+                ClientDriveQueryDirectoryResponse::decode(
+                    reply,
+                    FileInformationClassLevel::FILE_BOTH_DIRECTORY_INFORMATION,
+                    src,
+                )
+                .map_err(|e| decode_err!(e))?,
+            ),
+            _ => unreachable!("not a drive request"),
+        };
+
+        (irp.completion)(response);
+
+        Ok(())
+    }
+}
+
+impl SvcProcessor for RdpdrServer {
+    fn channel_name(&self) -> ChannelName {
+        Self::NAME
+    }
+
+    fn compression_condition(&self) -> CompressionCondition {
+        CompressionCondition::WhenRdpDataIsCompressed
+    }
+
+    fn start(&mut self) -> PduResult<Vec<SvcMessage>> {
+        if self.state != RdpdrState::Start {
+            return Err(pdu_other_err!("RdpdrServer", "invalid state"));
+        }
+
+        let announce = RdpdrPdu::VersionAndIdPdu(VersionAndIdPdu::new_server_announce_request(self.client_id));
+        self.state = RdpdrState::WaitingForClientAnnounceReply;
+
+        Ok(vec![SvcMessage::from(announce)])
+    }
+
+    fn process(&mut self, src: &[u8]) -> PduResult<Vec<SvcMessage>> {
+        let mut src = ReadCursor::new(src);
+        let header = SharedHeader::decode(&mut src).map_err(|e| decode_err!(e))?;
+        trace!(?header);
+
+        match header.packet_id {
+            PacketId::CoreClientidConfirm => {
+                let pdu = VersionAndIdPdu::decode(header, &mut src).map_err(|e| decode_err!(e))?;
+                self.handle_client_announce_reply(pdu)
+            }
+            PacketId::CoreClientName => {
+                let pdu = ClientNameRequest::decode(&mut src).map_err(|e| decode_err!(e))?;
+                debug!(?pdu);
+                self.computer_name = Some(match pdu {
+                    ClientNameRequest::Ascii(name) | ClientNameRequest::Unicode(name) => name,
+                });
+                Ok(Vec::new())
+            }
+            PacketId::CoreClientCapability => {
+                let pdu = CoreCapability::decode(header, &mut src).map_err(|e| decode_err!(e))?;
+                Ok(Self::handle_client_capability(pdu))
+            }
+            PacketId::CoreDevicelistAnnounce => {
+                let pdu = ClientDeviceListAnnounce::decode(&mut src).map_err(|e| decode_err!(e))?;
+                debug!(?pdu);
+                Ok(self.handle_device_list_announce(pdu))
+            }
+            PacketId::CoreDevicelistRemove => {
+                let pdu = ClientDeviceListRemove::decode(&mut src).map_err(|e| decode_err!(e))?;
+                debug!(?pdu);
+                self.handle_device_list_remove(pdu);
+                Ok(Vec::new())
+            }
+            PacketId::CoreDeviceIoCompletion => {
+                self.handle_device_io_completion(&mut src)?;
+                Ok(Vec::new())
+            }
+            packet_id => {
+                debug!(%packet_id, "Ignoring RDPDR packet");
+                Ok(Vec::new())
+            }
+        }
+    }
+}
+
+impl SvcServerProcessor for RdpdrServer {}
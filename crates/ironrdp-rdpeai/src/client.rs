@@ -0,0 +1,205 @@
+use core::fmt;
+
+use ironrdp_core::{decode, impl_as_any};
+use ironrdp_dvc::{encode_dvc_messages, DvcClientProcessor, DvcMessage, DvcProcessor};
+use ironrdp_pdu::{decode_err, encode_err, PduResult};
+use ironrdp_svc::{ChannelFlags, SvcMessage};
+use tracing::{debug, error, warn};
+
+use crate::pdu::{
+    AudioFormat, AudioInputPdu, DataPdu, FormatChangePdu, FormatsPdu, OpenPdu, OpenReplyPdu, Version, VersionPdu,
+};
+use crate::CHANNEL_NAME;
+
+/// A source of captured audio, such as a microphone
+pub trait AudioCaptureSource: Send + fmt::Debug {
+    /// Returns the formats in which the audio can be captured
+    fn get_formats(&self) -> &[AudioFormat];
+
+    /// Starts capturing the audio in `format`
+    ///
+    /// Returns `false` if the capture device could not be opened.
+    fn open(&mut self, format: &AudioFormat) -> bool;
+
+    /// Appends the audio captured since the last call to `buffer`
+    fn read(&mut self, buffer: &mut Vec<u8>);
+
+    fn close(&mut self);
+}
+
+/// A client for the Audio Input Virtual Channel
+///
+/// Once the server has opened the capture, the audio is sent by calling [`AudioInputClient::capture`]
+/// periodically, e.g. at the duration of a packet.
+#[derive(Debug)]
+pub struct AudioInputClient {
+    source: Box<dyn AudioCaptureSource>,
+    /// Formats supported by both the server and the source, as sent to the server
+    formats: Vec<AudioFormat>,
+    /// Index of the format being captured
+    format_no: Option<usize>,
+    frames_per_packet: u32,
+    /// Captured audio not sent yet
+    pending: Vec<u8>,
+}
+
+impl AudioInputClient {
+    pub fn new(source: Box<dyn AudioCaptureSource>) -> Self {
+        Self {
+            source,
+            formats: Vec::new(),
+            format_no: None,
+            frames_per_packet: 0,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Returns the format being captured, once the server has opened the capture
+    pub fn format(&self) -> Option<&AudioFormat> {
+        self.formats.get(self.format_no?)
+    }
+
+    /// Reads the audio captured by the source, and encodes it into complete packets
+    ///
+    /// The audio which does not fill a packet is kept for the next call.
+    pub fn capture(&mut self, channel_id: u32) -> PduResult<Vec<SvcMessage>> {
+        let Some(format) = self.format() else {
+            return Ok(Vec::new());
+        };
+
+        let packet_size = usize::try_from(self.frames_per_packet)
+            .ok()
+            .and_then(|frames| frames.checked_mul(usize::from(format.n_block_align)))
+            .unwrap_or(0);
+
+        self.source.read(&mut self.pending);
+
+        let mut messages: Vec<DvcMessage> = Vec::new();
+        if packet_size == 0 {
+            // The server does not ask for a packet size, the audio is sent as soon as it is captured.
+            if !self.pending.is_empty() {
+                messages.push(Box::new(AudioInputPdu::DataIncoming));
+                messages.push(Box::new(AudioInputPdu::Data(DataPdu {
+                    data: core::mem::take(&mut self.pending),
+                })));
+            }
+        } else {
+            while self.pending.len() >= packet_size {
+                let remaining = self.pending.split_off(packet_size);
+                let data = core::mem::replace(&mut self.pending, remaining);
+                messages.push(Box::new(AudioInputPdu::DataIncoming));
+                messages.push(Box::new(AudioInputPdu::Data(DataPdu { data })));
+            }
+        }
+
+        encode_dvc_messages(channel_id, messages, ChannelFlags::empty()).map_err(|e| encode_err!(e))
+    }
+
+    fn open(&mut self, format_no: u32) -> bool {
+        self.close_source();
+
+        let Some(format) = usize::try_from(format_no)
+            .ok()
+            .and_then(|format_no| self.formats.get(format_no))
+        else {
+            error!(format_no, "Invalid audio input format");
+            return false;
+        };
+
+        debug!(?format, "Opening audio capture");
+        if !self.source.open(format) {
+            error!(?format, "Failed to open audio capture");
+            return false;
+        }
+
+        self.format_no = usize::try_from(format_no).ok();
+        true
+    }
+
+    fn close_source(&mut self) {
+        if self.format_no.take().is_some() {
+            self.source.close();
+        }
+        self.pending.clear();
+    }
+}
+
+impl_as_any!(AudioInputClient);
+
+impl DvcProcessor for AudioInputClient {
+    fn channel_name(&self) -> &str {
+        CHANNEL_NAME
+    }
+
+    fn start(&mut self, _channel_id: u32) -> PduResult<Vec<DvcMessage>> {
+        // The server starts the exchange.
+        Ok(Vec::new())
+    }
+
+    fn process(&mut self, _channel_id: u32, payload: &[u8]) -> PduResult<Vec<DvcMessage>> {
+        let pdu = decode(payload).map_err(|e| decode_err!(e))?;
+        debug!(?pdu);
+
+        let response: Vec<AudioInputPdu> = match pdu {
+            AudioInputPdu::Version(pdu) => vec![VersionPdu {
+                version: pdu.version.min(Version::V2),
+            }
+            .into()],
+            AudioInputPdu::Formats(pdu) => {
+                let supported = self.source.get_formats();
+                self.formats = pdu
+                    .formats
+                    .into_iter()
+                    .filter(|format| supported.contains(format))
+                    .collect();
+
+                vec![FormatsPdu {
+                    formats: self.formats.clone(),
+                }
+                .into()]
+            }
+            // The audio is captured in the negotiated format, the capture format of the server is
+            // not used.
+            AudioInputPdu::Open(OpenPdu {
+                frames_per_packet,
+                initial_format,
+                ..
+            }) => {
+                self.frames_per_packet = frames_per_packet;
+                if self.open(initial_format) {
+                    vec![
+                        FormatChangePdu {
+                            new_format: initial_format,
+                        }
+                        .into(),
+                        OpenReplyPdu { result: 0 }.into(),
+                    ]
+                } else {
+                    vec![OpenReplyPdu {
+                        result: OpenReplyPdu::E_FAIL,
+                    }
+                    .into()]
+                }
+            }
+            AudioInputPdu::FormatChange(pdu) => {
+                if !self.open(pdu.new_format) {
+                    warn!("Audio capture stopped");
+                }
+                vec![pdu.into()]
+            }
+            pdu => {
+                warn!(?pdu, "Unexpected audio input PDU");
+                Vec::new()
+            }
+        };
+
+        Ok(response.into_iter().map(|pdu| Box::new(pdu) as DvcMessage).collect())
+    }
+
+    fn close(&mut self, _channel_id: u32) {
+        self.close_source();
+        self.formats.clear();
+    }
+}
+
+impl DvcClientProcessor for AudioInputClient {}
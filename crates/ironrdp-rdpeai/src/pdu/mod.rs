@@ -0,0 +1,458 @@
+//! Audio Input Redirection Virtual Channel Extension PDUs (MS-RDPEAI) implementation.
+//!
+//! Every message starts with a `SNDIN_PDU` header, made of a single `MessageId` byte.
+
+use core::fmt;
+
+use ironrdp_core::{
+    cast_length, ensure_fixed_part_size, ensure_size, invalid_field_err, Decode, DecodeResult, Encode, EncodeResult,
+    ReadCursor, WriteCursor,
+};
+use ironrdp_dvc::DvcEncode;
+pub use ironrdp_rdpsnd::pdu::{AudioFormat, WaveFormat};
+
+const MSG_SNDIN_VERSION: u8 = 0x01;
+const MSG_SNDIN_FORMATS: u8 = 0x02;
+const MSG_SNDIN_OPEN: u8 = 0x03;
+const MSG_SNDIN_OPEN_REPLY: u8 = 0x04;
+const MSG_SNDIN_DATA_INCOMING: u8 = 0x05;
+const MSG_SNDIN_DATA: u8 = 0x06;
+const MSG_SNDIN_FORMATCHANGE: u8 = 0x07;
+
+/// Audio input channel message (PDU prefixed with `SNDIN_PDU`)
+///
+/// The same messages are used in both directions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioInputPdu {
+    Version(VersionPdu),
+    Formats(FormatsPdu),
+    Open(OpenPdu),
+    OpenReply(OpenReplyPdu),
+    /// Sent by the client before each [`AudioInputPdu::Data`]
+    DataIncoming,
+    Data(DataPdu),
+    FormatChange(FormatChangePdu),
+}
+
+impl AudioInputPdu {
+    const FIXED_PART_SIZE: usize = 1 /* MessageId */;
+}
+
+impl Encode for AudioInputPdu {
+    fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+
+        let message_id = match self {
+            Self::Version(_) => MSG_SNDIN_VERSION,
+            Self::Formats(_) => MSG_SNDIN_FORMATS,
+            Self::Open(_) => MSG_SNDIN_OPEN,
+            Self::OpenReply(_) => MSG_SNDIN_OPEN_REPLY,
+            Self::DataIncoming => MSG_SNDIN_DATA_INCOMING,
+            Self::Data(_) => MSG_SNDIN_DATA,
+            Self::FormatChange(_) => MSG_SNDIN_FORMATCHANGE,
+        };
+
+        dst.write_u8(message_id);
+
+        match self {
+            Self::Version(pdu) => pdu.encode(dst),
+            Self::Formats(pdu) => pdu.encode(dst),
+            Self::Open(pdu) => pdu.encode(dst),
+            Self::OpenReply(pdu) => pdu.encode(dst),
+            Self::DataIncoming => Ok(()),
+            Self::Data(pdu) => pdu.encode(dst),
+            Self::FormatChange(pdu) => pdu.encode(dst),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Version(pdu) => pdu.name(),
+            Self::Formats(pdu) => pdu.name(),
+            Self::Open(pdu) => pdu.name(),
+            Self::OpenReply(pdu) => pdu.name(),
+            Self::DataIncoming => "MSG_SNDIN_DATA_INCOMING",
+            Self::Data(pdu) => pdu.name(),
+            Self::FormatChange(pdu) => pdu.name(),
+        }
+    }
+
+    fn size(&self) -> usize {
+        Self::FIXED_PART_SIZE
+            .checked_add(match self {
+                Self::Version(pdu) => pdu.size(),
+                Self::Formats(pdu) => pdu.size(),
+                Self::Open(pdu) => pdu.size(),
+                Self::OpenReply(pdu) => pdu.size(),
+                Self::DataIncoming => 0,
+                Self::Data(pdu) => pdu.size(),
+                Self::FormatChange(pdu) => pdu.size(),
+            })
+            .expect("never overflow")
+    }
+}
+
+impl DvcEncode for AudioInputPdu {}
+
+impl<'de> Decode<'de> for AudioInputPdu {
+    fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+
+        match src.read_u8() {
+            MSG_SNDIN_VERSION => Ok(Self::Version(VersionPdu::decode(src)?)),
+            MSG_SNDIN_FORMATS => Ok(Self::Formats(FormatsPdu::decode(src)?)),
+            MSG_SNDIN_OPEN => Ok(Self::Open(OpenPdu::decode(src)?)),
+            MSG_SNDIN_OPEN_REPLY => Ok(Self::OpenReply(OpenReplyPdu::decode(src)?)),
+            MSG_SNDIN_DATA_INCOMING => Ok(Self::DataIncoming),
+            MSG_SNDIN_DATA => Ok(Self::Data(DataPdu::decode(src)?)),
+            MSG_SNDIN_FORMATCHANGE => Ok(Self::FormatChange(FormatChangePdu::decode(src)?)),
+            _ => Err(invalid_field_err!("MessageId", "unknown audio input PDU type")),
+        }
+    }
+}
+
+impl From<VersionPdu> for AudioInputPdu {
+    fn from(pdu: VersionPdu) -> Self {
+        Self::Version(pdu)
+    }
+}
+
+impl From<FormatsPdu> for AudioInputPdu {
+    fn from(pdu: FormatsPdu) -> Self {
+        Self::Formats(pdu)
+    }
+}
+
+impl From<OpenPdu> for AudioInputPdu {
+    fn from(pdu: OpenPdu) -> Self {
+        Self::Open(pdu)
+    }
+}
+
+impl From<OpenReplyPdu> for AudioInputPdu {
+    fn from(pdu: OpenReplyPdu) -> Self {
+        Self::OpenReply(pdu)
+    }
+}
+
+impl From<DataPdu> for AudioInputPdu {
+    fn from(pdu: DataPdu) -> Self {
+        Self::Data(pdu)
+    }
+}
+
+impl From<FormatChangePdu> for AudioInputPdu {
+    fn from(pdu: FormatChangePdu) -> Self {
+        Self::FormatChange(pdu)
+    }
+}
+
+/// Version of the audio input protocol
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version(pub u32);
+
+impl Version {
+    pub const V1: Self = Self(0x0000_0001);
+    pub const V2: Self = Self(0x0000_0002);
+}
+
+impl fmt::Debug for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::V1 => write!(f, "V1"),
+            Self::V2 => write!(f, "V2"),
+            Self(version) => write!(f, "Version({version})"),
+        }
+    }
+}
+
+/// Version PDU (MSG_SNDIN_VERSION)
+///
+/// Sent by the server when the channel is opened, and answered by the client with its own version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionPdu {
+    pub version: Version,
+}
+
+impl VersionPdu {
+    const NAME: &'static str = "MSG_SNDIN_VERSION";
+
+    const FIXED_PART_SIZE: usize = 4 /* Version */;
+}
+
+impl Encode for VersionPdu {
+    fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_fixed_part_size!(in: dst);
+
+        dst.write_u32(self.version.0);
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn size(&self) -> usize {
+        Self::FIXED_PART_SIZE
+    }
+}
+
+impl<'de> Decode<'de> for VersionPdu {
+    fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+
+        let version = Version(src.read_u32());
+
+        Ok(Self { version })
+    }
+}
+
+/// Sound Formats PDU (MSG_SNDIN_FORMATS)
+///
+/// The server sends the formats it can receive, and the client answers with the ones it can capture.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatsPdu {
+    pub formats: Vec<AudioFormat>,
+}
+
+impl FormatsPdu {
+    const NAME: &'static str = "MSG_SNDIN_FORMATS";
+
+    const FIXED_PART_SIZE: usize = 4 /* NumFormats */ + 4 /* cbSizeFormatsPacket */;
+}
+
+impl Encode for FormatsPdu {
+    fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+
+        dst.write_u32(cast_length!("NumFormats", self.formats.len())?);
+        // The size of the whole PDU, including its header.
+        let packet_size = self
+            .size()
+            .checked_add(AudioInputPdu::FIXED_PART_SIZE)
+            .expect("never overflow");
+        dst.write_u32(cast_length!("cbSizeFormatsPacket", packet_size)?);
+        for format in &self.formats {
+            format.encode(dst)?;
+        }
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn size(&self) -> usize {
+        Self::FIXED_PART_SIZE
+            .checked_add(self.formats.iter().map(|format| format.size()).sum::<usize>())
+            .expect("never overflow")
+    }
+}
+
+impl<'de> Decode<'de> for FormatsPdu {
+    fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+
+        let num_formats = src.read_u32();
+        let _size_formats_packet = src.read_u32();
+        let formats = (0..num_formats)
+            .map(|_| AudioFormat::decode(src))
+            .collect::<DecodeResult<_>>()?;
+        // The optional ExtraData field is ignored.
+
+        Ok(Self { formats })
+    }
+}
+
+/// Open PDU (MSG_SNDIN_OPEN)
+///
+/// Asks the client to start capturing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenPdu {
+    /// Number of audio frames the client should send in each [`DataPdu`]
+    pub frames_per_packet: u32,
+    /// Index, in the formats sent by the client, of the format of the data
+    pub initial_format: u32,
+    /// Format in which the client should capture the audio, as a `WAVEFORMATEX` structure
+    pub capture_format: AudioFormat,
+}
+
+impl OpenPdu {
+    const NAME: &'static str = "MSG_SNDIN_OPEN";
+
+    const FIXED_PART_SIZE: usize = 4 /* FramesPerPacket */ + 4 /* initialFormat */;
+}
+
+impl Encode for OpenPdu {
+    fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+
+        dst.write_u32(self.frames_per_packet);
+        dst.write_u32(self.initial_format);
+        self.capture_format.encode(dst)
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn size(&self) -> usize {
+        Self::FIXED_PART_SIZE
+            .checked_add(self.capture_format.size())
+            .expect("never overflow")
+    }
+}
+
+impl<'de> Decode<'de> for OpenPdu {
+    fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+
+        let frames_per_packet = src.read_u32();
+        let initial_format = src.read_u32();
+        let capture_format = AudioFormat::decode(src)?;
+
+        Ok(Self {
+            frames_per_packet,
+            initial_format,
+            capture_format,
+        })
+    }
+}
+
+/// Open Reply PDU (MSG_SNDIN_OPEN_REPLY)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpenReplyPdu {
+    /// HRESULT of the opening of the capture device, zero on success
+    pub result: u32,
+}
+
+impl OpenReplyPdu {
+    const NAME: &'static str = "MSG_SNDIN_OPEN_REPLY";
+
+    const FIXED_PART_SIZE: usize = 4 /* Result */;
+
+    /// `E_FAIL`, returned when the capture device could not be opened
+    pub const E_FAIL: u32 = 0x8000_4005;
+
+    pub fn is_success(&self) -> bool {
+        // A negative HRESULT is a failure.
+        self.result & 0x8000_0000 == 0
+    }
+}
+
+impl Encode for OpenReplyPdu {
+    fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_fixed_part_size!(in: dst);
+
+        dst.write_u32(self.result);
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn size(&self) -> usize {
+        Self::FIXED_PART_SIZE
+    }
+}
+
+impl<'de> Decode<'de> for OpenReplyPdu {
+    fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+
+        let result = src.read_u32();
+
+        Ok(Self { result })
+    }
+}
+
+/// Sound Data PDU (MSG_SNDIN_DATA)
+///
+/// Audio captured by the client, in the current format.
+#[derive(Clone, PartialEq, Eq)]
+pub struct DataPdu {
+    pub data: Vec<u8>,
+}
+
+impl DataPdu {
+    const NAME: &'static str = "MSG_SNDIN_DATA";
+}
+
+impl fmt::Debug for DataPdu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DataPdu").field("data.len", &self.data.len()).finish()
+    }
+}
+
+impl Encode for DataPdu {
+    fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+
+        dst.write_slice(&self.data);
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn size(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl<'de> Decode<'de> for DataPdu {
+    fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
+        let data = src.read_remaining().to_vec();
+
+        Ok(Self { data })
+    }
+}
+
+/// Sound Format Change PDU (MSG_SNDIN_FORMATCHANGE)
+///
+/// Sent by the server to change the format of the data, and by the client to acknowledge it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatChangePdu {
+    /// Index of the new format, in the formats sent by the client
+    pub new_format: u32,
+}
+
+impl FormatChangePdu {
+    const NAME: &'static str = "MSG_SNDIN_FORMATCHANGE";
+
+    const FIXED_PART_SIZE: usize = 4 /* NewFormat */;
+}
+
+impl Encode for FormatChangePdu {
+    fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_fixed_part_size!(in: dst);
+
+        dst.write_u32(self.new_format);
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn size(&self) -> usize {
+        Self::FIXED_PART_SIZE
+    }
+}
+
+impl<'de> Decode<'de> for FormatChangePdu {
+    fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+
+        let new_format = src.read_u32();
+
+        Ok(Self { new_format })
+    }
+}
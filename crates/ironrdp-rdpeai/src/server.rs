@@ -0,0 +1,172 @@
+use core::fmt;
+
+use ironrdp_core::{decode, impl_as_any};
+use ironrdp_dvc::{DvcMessage, DvcProcessor, DvcServerProcessor};
+use ironrdp_pdu::{decode_err, PduResult};
+use tracing::{debug, error, warn};
+
+use crate::pdu::{AudioFormat, AudioInputPdu, FormatsPdu, OpenPdu, Version, VersionPdu};
+use crate::CHANNEL_NAME;
+
+pub trait AudioInputServerHandler: Send + fmt::Debug {
+    /// Returns the formats in which the audio can be received
+    fn get_formats(&self) -> &[AudioFormat];
+
+    /// Chooses the format of the capture, among the formats supported by the client
+    ///
+    /// Returns the index of the format, or `None` to not capture the audio.
+    fn start(&mut self, client_formats: &[AudioFormat]) -> Option<u32> {
+        (!client_formats.is_empty()).then_some(0)
+    }
+
+    /// Called with each packet of audio captured by the client
+    fn data(&mut self, format: &AudioFormat, data: Vec<u8>);
+
+    /// Called when the capture is stopped, including when the client fails to open it
+    fn stop(&mut self);
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum AudioInputState {
+    Start,
+    WaitingForVersion,
+    WaitingForFormats,
+    WaitingForOpenReply,
+    Ready,
+    Stop,
+}
+
+/// A server for the Audio Input Virtual Channel
+///
+/// The capture is opened as soon as the formats are negotiated with the client.
+#[derive(Debug)]
+pub struct AudioInputServer {
+    handler: Box<dyn AudioInputServerHandler>,
+    state: AudioInputState,
+    client_formats: Vec<AudioFormat>,
+    format_no: Option<usize>,
+}
+
+impl AudioInputServer {
+    pub fn new(handler: Box<dyn AudioInputServerHandler>) -> Self {
+        Self {
+            handler,
+            state: AudioInputState::Start,
+            client_formats: Vec::new(),
+            format_no: None,
+        }
+    }
+
+    fn open(&mut self) -> Vec<AudioInputPdu> {
+        let format = self
+            .handler
+            .start(&self.client_formats)
+            .and_then(|format_no| Some((format_no, self.client_formats.get(usize::try_from(format_no).ok()?)?)));
+
+        let Some((initial_format, format)) = format else {
+            debug!("No audio input format");
+            self.state = AudioInputState::Stop;
+            return Vec::new();
+        };
+
+        // 20 ms of audio in each packet.
+        let pdu = OpenPdu {
+            frames_per_packet: format.n_samples_per_sec / 50,
+            initial_format,
+            capture_format: format.clone(),
+        };
+        self.format_no = usize::try_from(initial_format).ok();
+        self.state = AudioInputState::WaitingForOpenReply;
+
+        vec![pdu.into()]
+    }
+
+    fn stop(&mut self) {
+        if self.format_no.take().is_some() {
+            self.handler.stop();
+        }
+        self.state = AudioInputState::Stop;
+    }
+}
+
+impl_as_any!(AudioInputServer);
+
+impl DvcProcessor for AudioInputServer {
+    fn channel_name(&self) -> &str {
+        CHANNEL_NAME
+    }
+
+    fn start(&mut self, _channel_id: u32) -> PduResult<Vec<DvcMessage>> {
+        if self.state != AudioInputState::Start {
+            error!("Attempted to start audio input channel in invalid state");
+        }
+
+        self.state = AudioInputState::WaitingForVersion;
+
+        Ok(vec![Box::new(AudioInputPdu::from(VersionPdu { version: Version::V2 }))])
+    }
+
+    fn process(&mut self, _channel_id: u32, payload: &[u8]) -> PduResult<Vec<DvcMessage>> {
+        let pdu = decode(payload).map_err(|e| decode_err!(e))?;
+        debug!(?pdu);
+
+        let response = match (self.state, pdu) {
+            (AudioInputState::WaitingForVersion, AudioInputPdu::Version(pdu)) => {
+                debug!(version = ?pdu.version, "Client audio input version");
+                self.state = AudioInputState::WaitingForFormats;
+                vec![FormatsPdu {
+                    formats: self.handler.get_formats().into(),
+                }
+                .into()]
+            }
+            (AudioInputState::WaitingForFormats, AudioInputPdu::Formats(pdu)) => {
+                self.client_formats = pdu.formats;
+                self.open()
+            }
+            (AudioInputState::WaitingForOpenReply | AudioInputState::Ready, AudioInputPdu::FormatChange(pdu)) => {
+                match usize::try_from(pdu.new_format)
+                    .ok()
+                    .filter(|format_no| *format_no < self.client_formats.len())
+                {
+                    Some(format_no) => self.format_no = Some(format_no),
+                    None => {
+                        error!(new_format = pdu.new_format, "Invalid audio input format");
+                        self.stop();
+                    }
+                }
+                Vec::new()
+            }
+            (AudioInputState::WaitingForOpenReply, AudioInputPdu::OpenReply(pdu)) => {
+                if pdu.is_success() {
+                    self.state = AudioInputState::Ready;
+                } else {
+                    error!(
+                        result = format_args!("{:#010x}", pdu.result),
+                        "Client failed to open audio capture"
+                    );
+                    self.stop();
+                }
+                Vec::new()
+            }
+            (AudioInputState::Ready, AudioInputPdu::DataIncoming) => Vec::new(),
+            (AudioInputState::Ready, AudioInputPdu::Data(pdu)) => {
+                if let Some(format) = self.format_no.and_then(|format_no| self.client_formats.get(format_no)) {
+                    self.handler.data(format, pdu.data);
+                }
+                Vec::new()
+            }
+            (state, pdu) => {
+                warn!(?state, ?pdu, "Unexpected audio input PDU");
+                Vec::new()
+            }
+        };
+
+        Ok(response.into_iter().map(|pdu| Box::new(pdu) as DvcMessage).collect())
+    }
+
+    fn close(&mut self, _channel_id: u32) {
+        self.stop();
+    }
+}
+
+impl DvcServerProcessor for AudioInputServer {}
@@ -7,9 +7,11 @@ use std::sync::Arc;
 use std::thread::{self, JoinHandle};
 
 use anyhow::{bail, Context};
-use cpal::traits::{DeviceTrait, HostTrait};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{SampleFormat, Stream, StreamConfig};
+use ironrdp_rdpeai::client::AudioCaptureSource;
 use ironrdp_rdpsnd::client::RdpsndClientHandler;
+use ironrdp_rdpsnd::codec::{self, AudioDecoder};
 use ironrdp_rdpsnd::pdu::{AudioFormat, PitchPdu, VolumePdu, WaveFormat};
 
 #[derive(Debug)]
@@ -19,6 +21,7 @@ pub struct RdpsndBackend {
     stream_ended: Arc<AtomicBool>,
     tx: Option<Sender<Vec<u8>>>,
     format: Option<AudioFormat>,
+    formats: Vec<AudioFormat>,
 }
 
 impl Default for RdpsndBackend {
@@ -29,9 +32,24 @@ impl Default for RdpsndBackend {
 
 impl RdpsndBackend {
     pub fn new() -> Self {
+        let mut formats = vec![
+            #[cfg(feature = "opus")]
+            AudioFormat {
+                format: WaveFormat::OPUS,
+                n_channels: 2,
+                n_samples_per_sec: 48000,
+                n_avg_bytes_per_sec: 192000,
+                n_block_align: 4,
+                bits_per_sample: 16,
+                data: None,
+            },
+        ];
+        formats.extend(codec::supported_formats(2, 44100));
+
         Self {
             tx: None,
             format: None,
+            formats,
             stream_handle: None,
             stream_ended: Arc::new(AtomicBool::new(false)),
         }
@@ -46,27 +64,7 @@ impl Drop for RdpsndBackend {
 
 impl RdpsndClientHandler for RdpsndBackend {
     fn get_formats(&self) -> &[AudioFormat] {
-        &[
-            #[cfg(feature = "opus")]
-            AudioFormat {
-                format: WaveFormat::OPUS,
-                n_channels: 2,
-                n_samples_per_sec: 48000,
-                n_avg_bytes_per_sec: 192000,
-                n_block_align: 4,
-                bits_per_sample: 16,
-                data: None,
-            },
-            AudioFormat {
-                format: WaveFormat::PCM,
-                n_channels: 2,
-                n_samples_per_sec: 44100,
-                n_avg_bytes_per_sec: 176400,
-                n_block_align: 4,
-                bits_per_sample: 16,
-                data: None,
-            },
-        ]
+        &self.formats
     }
 
     fn wave(&mut self, format: &AudioFormat, _ts: u32, data: Cow<'_, [u8]>) {
@@ -133,6 +131,7 @@ pub struct DecodeStream {
 impl DecodeStream {
     pub fn new(rx_format: &AudioFormat, mut rx: Receiver<Vec<u8>>) -> anyhow::Result<Self> {
         let mut dec_thread = None;
+        let mut bits_per_sample = rx_format.bits_per_sample;
         match rx_format.format {
             #[cfg(feature = "opus")]
             WaveFormat::OPUS => {
@@ -155,10 +154,29 @@ impl DecodeStream {
                 rx = dec_rx;
             }
             WaveFormat::PCM => {}
-            _ => bail!("audio format not supported"),
+            _ => {
+                let mut dec = AudioDecoder::new(rx_format).context("audio format not supported")?;
+                let (dec_tx, dec_rx) = mpsc::channel();
+                dec_thread = Some(thread::spawn(move || {
+                    let mut samples = Vec::new();
+                    while let Ok(pkt) = rx.recv() {
+                        samples.clear();
+                        if let Err(error) = dec.decode(&pkt, &mut samples) {
+                            error!(%error);
+                            continue;
+                        }
+                        let pcm = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+                        if dec_tx.send(pcm).is_err() {
+                            break;
+                        }
+                    }
+                }));
+                rx = dec_rx;
+                bits_per_sample = 16;
+            }
         }
 
-        let sample_format = match rx_format.bits_per_sample {
+        let sample_format = match bits_per_sample {
             8 => SampleFormat::U8,
             16 => SampleFormat::I16,
             _ => {
@@ -255,3 +273,160 @@ impl RxBuffer {
         }
     }
 }
+
+/// Captures the audio of the default input device, for the audio input channel
+#[derive(Debug)]
+pub struct AudioCaptureBackend {
+    // Unfortunately, Stream is not `Send`, so we move it to a separate thread.
+    stream_handle: Option<JoinHandle<()>>,
+    stream_ended: Arc<AtomicBool>,
+    rx: Option<Receiver<Vec<u8>>>,
+}
+
+impl Default for AudioCaptureBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AudioCaptureBackend {
+    pub fn new() -> Self {
+        Self {
+            stream_handle: None,
+            stream_ended: Arc::new(AtomicBool::new(false)),
+            rx: None,
+        }
+    }
+}
+
+impl Drop for AudioCaptureBackend {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl AudioCaptureSource for AudioCaptureBackend {
+    fn get_formats(&self) -> &[AudioFormat] {
+        &[
+            AudioFormat {
+                format: WaveFormat::PCM,
+                n_channels: 2,
+                n_samples_per_sec: 44100,
+                n_avg_bytes_per_sec: 176400,
+                n_block_align: 4,
+                bits_per_sample: 16,
+                data: None,
+            },
+            AudioFormat {
+                format: WaveFormat::PCM,
+                n_channels: 1,
+                n_samples_per_sec: 44100,
+                n_avg_bytes_per_sec: 88200,
+                n_block_align: 2,
+                bits_per_sample: 16,
+                data: None,
+            },
+        ]
+    }
+
+    fn open(&mut self, format: &AudioFormat) -> bool {
+        self.close();
+
+        let (tx, rx) = mpsc::channel();
+        let (opened_tx, opened_rx) = mpsc::channel();
+        let format = format.clone();
+        self.stream_ended.store(false, Ordering::Relaxed);
+        let stream_ended = Arc::clone(&self.stream_ended);
+        self.stream_handle = Some(thread::spawn(move || {
+            let stream = match CaptureStream::new(&format, tx) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!(error = format!("{e:#}"));
+                    let _ = opened_tx.send(false);
+                    return;
+                }
+            };
+            let _ = opened_tx.send(true);
+            debug!("Capture stream thread parking loop");
+            while !stream_ended.load(Ordering::Relaxed) {
+                thread::park();
+            }
+            debug!("Capture stream thread unparked");
+            drop(stream);
+        }));
+
+        let opened = opened_rx.recv().unwrap_or(false);
+        if opened {
+            self.rx = Some(rx);
+        } else {
+            self.close();
+        }
+
+        opened
+    }
+
+    fn read(&mut self, buffer: &mut Vec<u8>) {
+        if let Some(rx) = &self.rx {
+            for data in rx.try_iter() {
+                buffer.extend_from_slice(&data);
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        self.rx = None;
+        if let Some(stream) = self.stream_handle.take() {
+            self.stream_ended.store(true, Ordering::Relaxed);
+            stream.thread().unpark();
+            stream.join().unwrap();
+        }
+    }
+}
+
+struct CaptureStream {
+    _stream: Stream,
+}
+
+impl CaptureStream {
+    fn new(tx_format: &AudioFormat, tx: Sender<Vec<u8>>) -> anyhow::Result<Self> {
+        if tx_format.format != WaveFormat::PCM {
+            bail!("audio format not supported");
+        }
+
+        let sample_format = match tx_format.bits_per_sample {
+            8 => SampleFormat::U8,
+            16 => SampleFormat::I16,
+            _ => {
+                bail!("only PCM 8/16 bits formats supported");
+            }
+        };
+
+        let host = cpal::default_host();
+        let device = host.default_input_device().context("no default input device")?;
+        let default_config = device.default_input_config()?;
+        debug!(?default_config);
+
+        let config = StreamConfig {
+            channels: tx_format.n_channels,
+            sample_rate: cpal::SampleRate(tx_format.n_samples_per_sec),
+            buffer_size: cpal::BufferSize::Default,
+        };
+        debug!(?config);
+
+        let stream = device
+            .build_input_stream_raw(
+                &config,
+                sample_format,
+                move |data, _info: &cpal::InputCallbackInfo| {
+                    // The receiver is dropped when the capture is closed.
+                    let _ = tx.send(data.bytes().to_vec());
+                },
+                |error| error!(%error),
+                None,
+            )
+            .context("failed to setup input stream")?;
+        stream.play().context("failed to start input stream")?;
+
+        Ok(Self { _stream: stream })
+    }
+}
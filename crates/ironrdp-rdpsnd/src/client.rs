@@ -1,12 +1,16 @@
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 
 use ironrdp_core::{cast_length, impl_as_any, Decode, EncodeResult, ReadCursor};
 use ironrdp_pdu::gcc::ChannelName;
 use ironrdp_pdu::{decode_err, encode_err, pdu_other_err, PduResult};
 use ironrdp_svc::{CompressionCondition, SvcClientProcessor, SvcMessage, SvcProcessor};
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
-use crate::pdu::{self, AudioFormat, PitchPdu, ServerAudioFormatPdu, TrainingPdu, VolumePdu};
+use crate::pdu::{
+    self, AudioFormat, PitchPdu, ServerAudioFormatPdu, SndWavePdu, TrainingPdu, UdpWaveLastPdu, UdpWavePdu, VolumePdu,
+    WaveInfoPdu, WavePdu,
+};
 use crate::server::RdpsndSvcMessages;
 
 pub trait RdpsndClientHandler: Send + core::fmt::Debug {
@@ -54,6 +58,11 @@ pub struct Rdpsnd {
     handler: Box<dyn RdpsndClientHandler>,
     state: RdpsndState,
     server_format: Option<ServerAudioFormatPdu>,
+    dgram_port: u16,
+    /// Wave Info PDU waiting for its Wave PDU (versions before 8)
+    wave_info: Option<WaveInfoPdu>,
+    /// Block number and fragments of the wave being received over UDP
+    udp_fragments: Option<(u8, BTreeMap<u16, Vec<u8>>)>,
 }
 
 impl Rdpsnd {
@@ -64,9 +73,21 @@ impl Rdpsnd {
             handler,
             state: RdpsndState::Start,
             server_format: None,
+            dgram_port: 0,
+            wave_info: None,
+            udp_fragments: None,
         }
     }
 
+    /// Advertises the UDP port on which the client receives the waves
+    ///
+    /// The datagrams received on this port are given to [`Rdpsnd::process_datagram`].
+    #[must_use]
+    pub fn with_dgram_port(mut self, port: u16) -> Self {
+        self.dgram_port = port;
+        self
+    }
+
     pub fn get_format(&self, format_no: u16) -> PduResult<&AudioFormat> {
         let server_format = self
             .server_format
@@ -96,7 +117,7 @@ impl Rdpsnd {
             volume_left: 0xFFFF,
             volume_right: 0xFFFF,
             pitch: 0x00010000,
-            dgram_port: 0,
+            dgram_port: self.dgram_port,
         };
         Ok(RdpsndSvcMessages::new(vec![pdu::ClientAudioOutputPdu::AudioFormat(
             pdu,
@@ -133,38 +154,29 @@ impl Rdpsnd {
         )
         .into()]))
     }
-}
 
-impl_as_any!(Rdpsnd);
+    /// Processes a datagram received on the port given to [`Rdpsnd::with_dgram_port`]
+    ///
+    /// The returned messages are sent on the static virtual channel.
+    pub fn process_datagram(&mut self, payload: &[u8]) -> PduResult<Vec<SvcMessage>> {
+        let pdu = pdu::ServerAudioOutputPdu::decode(&mut ReadCursor::new(payload)).map_err(|e| decode_err!(e))?;
 
-impl SvcProcessor for Rdpsnd {
-    fn channel_name(&self) -> ChannelName {
-        Self::NAME
+        self.process_pdu(pdu)
     }
 
-    fn compression_condition(&self) -> CompressionCondition {
-        CompressionCondition::Never
-    }
+    fn process_pdu(&mut self, pdu: pdu::ServerAudioOutputPdu<'_>) -> PduResult<Vec<SvcMessage>> {
+        debug!(?pdu, ?self.state);
 
-    fn process(&mut self, payload: &[u8]) -> PduResult<Vec<SvcMessage>> {
-        let pdu = pdu::ServerAudioOutputPdu::decode(&mut ReadCursor::new(payload)).map_err(|e| decode_err!(e))?;
+        // The server may renegotiate the formats at any time.
+        if let pdu::ServerAudioOutputPdu::AudioFormat(af) = pdu {
+            return self.start(af);
+        }
 
-        debug!(?pdu, ?self.state);
         let msg = match self.state {
             RdpsndState::Start => {
-                let pdu::ServerAudioOutputPdu::AudioFormat(af) = pdu else {
-                    error!("Invalid pdu");
-                    self.state = RdpsndState::Stop;
-                    return Ok(vec![]);
-                };
-                self.server_format = Some(af);
-                self.state = RdpsndState::WaitingForTraining;
-                let mut msgs: Vec<SvcMessage> = self.client_formats()?.into();
-                if self.version()? >= pdu::Version::V6 {
-                    let mut m = self.quality_mode()?.into();
-                    msgs.append(&mut m);
-                }
-                msgs
+                error!("Invalid pdu");
+                self.state = RdpsndState::Stop;
+                vec![]
             }
             RdpsndState::WaitingForTraining => {
                 let pdu::ServerAudioOutputPdu::Training(pdu) = pdu else {
@@ -175,32 +187,48 @@ impl SvcProcessor for Rdpsnd {
                 self.state = RdpsndState::Ready;
                 self.training_confirm(&pdu)?.into()
             }
-            RdpsndState::Ready => {
-                match pdu {
-                    // TODO: handle WaveInfo for < v8
-                    pdu::ServerAudioOutputPdu::Wave2(pdu) => {
-                        let fmt = self.get_format(pdu.format_no)?.clone();
-                        let ts = pdu.audio_timestamp;
-                        self.handler.wave(&fmt, ts, pdu.data);
-                        return Ok(self.wave_confirm(pdu.timestamp, pdu.block_no)?.into());
-                    }
-                    pdu::ServerAudioOutputPdu::Volume(pdu) => {
-                        self.handler.set_volume(pdu);
-                    }
-                    pdu::ServerAudioOutputPdu::Pitch(pdu) => {
-                        self.handler.set_pitch(pdu);
-                    }
-                    pdu::ServerAudioOutputPdu::Close => {
-                        self.handler.close();
-                    }
-                    _ => {
-                        error!("Invalid PDU");
-                        self.state = RdpsndState::Stop;
-                        return Ok(vec![]);
-                    }
+            RdpsndState::Ready => match pdu {
+                pdu::ServerAudioOutputPdu::Training(pdu) => self.training_confirm(&pdu)?.into(),
+                pdu::ServerAudioOutputPdu::Wave(pdu) => {
+                    let ts = u32::from(pdu.timestamp);
+                    self.wave(pdu.format_no, ts, pdu.data, pdu.timestamp, pdu.block_no)?
                 }
-                vec![]
-            }
+                pdu::ServerAudioOutputPdu::WaveInfo(pdu) => {
+                    self.wave_info = Some(pdu);
+                    vec![]
+                }
+                pdu::ServerAudioOutputPdu::Wave2(pdu) => {
+                    let ts = pdu.audio_timestamp;
+                    self.wave(pdu.format_no, ts, pdu.data, pdu.timestamp, pdu.block_no)?
+                }
+                pdu::ServerAudioOutputPdu::UdpWave(pdu) => {
+                    self.udp_wave(pdu);
+                    vec![]
+                }
+                pdu::ServerAudioOutputPdu::UdpWaveLast(pdu) => self.udp_wave_last(pdu)?,
+                pdu::ServerAudioOutputPdu::CryptKey(_) => {
+                    // Only needed to decrypt the Wave Encrypt PDUs.
+                    debug!("Ignoring crypt key");
+                    vec![]
+                }
+                pdu::ServerAudioOutputPdu::WaveEncrypt(pdu) => {
+                    warn!(block_no = pdu.block_no, "Encrypted waves are not supported");
+                    vec![]
+                }
+                pdu::ServerAudioOutputPdu::Volume(pdu) => {
+                    self.handler.set_volume(pdu);
+                    vec![]
+                }
+                pdu::ServerAudioOutputPdu::Pitch(pdu) => {
+                    self.handler.set_pitch(pdu);
+                    vec![]
+                }
+                pdu::ServerAudioOutputPdu::Close => {
+                    self.handler.close();
+                    vec![]
+                }
+                pdu::ServerAudioOutputPdu::AudioFormat(_) => unreachable!("handled above"),
+            },
             state => {
                 error!(?state, "Invalid state");
                 vec![]
@@ -209,6 +237,107 @@ impl SvcProcessor for Rdpsnd {
 
         Ok(msg)
     }
+
+    fn start(&mut self, af: ServerAudioFormatPdu) -> PduResult<Vec<SvcMessage>> {
+        self.server_format = Some(af);
+        self.wave_info = None;
+        self.udp_fragments = None;
+        self.state = RdpsndState::WaitingForTraining;
+
+        let mut msgs: Vec<SvcMessage> = self.client_formats()?.into();
+        if self.version()? >= pdu::Version::V6 {
+            let mut m = self.quality_mode()?.into();
+            msgs.append(&mut m);
+        }
+
+        Ok(msgs)
+    }
+
+    fn wave(
+        &mut self,
+        format_no: u16,
+        ts: u32,
+        data: Cow<'_, [u8]>,
+        timestamp: u16,
+        block_no: u8,
+    ) -> PduResult<Vec<SvcMessage>> {
+        let fmt = self.get_format(format_no)?.clone();
+        self.handler.wave(&fmt, ts, data);
+
+        Ok(self.wave_confirm(timestamp, block_no)?.into())
+    }
+
+    fn udp_wave(&mut self, pdu: UdpWavePdu<'_>) {
+        // A new block number means that the previous wave was lost.
+        let fragments = match &mut self.udp_fragments {
+            Some((block_no, fragments)) if *block_no == pdu.block_no => fragments,
+            udp_fragments => &mut udp_fragments.insert((pdu.block_no, BTreeMap::new())).1,
+        };
+
+        fragments.insert(pdu.frag_no, pdu.data.into_owned());
+    }
+
+    fn udp_wave_last(&mut self, pdu: UdpWaveLastPdu<'_>) -> PduResult<Vec<SvcMessage>> {
+        let fragments = match self.udp_fragments.take() {
+            Some((block_no, fragments)) if block_no == pdu.block_no => fragments,
+            _ => BTreeMap::new(),
+        };
+
+        // The fragment numbers are sorted, a gap means a lost fragment.
+        let contiguous = fragments.keys().copied().eq((0..).take(fragments.len()));
+
+        let mut data = Vec::with_capacity(usize::from(pdu.total_size));
+        for fragment in fragments.values() {
+            data.extend_from_slice(fragment);
+        }
+        data.extend_from_slice(&pdu.data);
+
+        if !contiguous || data.len() != usize::from(pdu.total_size) {
+            warn!(
+                block_no = pdu.block_no,
+                expected = pdu.total_size,
+                actual = data.len(),
+                "Dropping incomplete UDP wave"
+            );
+            return Ok(vec![]);
+        }
+
+        let ts = u32::from(pdu.timestamp);
+        self.wave(pdu.format_no, ts, data.into(), pdu.timestamp, pdu.block_no)
+    }
+}
+
+impl_as_any!(Rdpsnd);
+
+impl SvcProcessor for Rdpsnd {
+    fn channel_name(&self) -> ChannelName {
+        Self::NAME
+    }
+
+    fn compression_condition(&self) -> CompressionCondition {
+        CompressionCondition::Never
+    }
+
+    fn process(&mut self, payload: &[u8]) -> PduResult<Vec<SvcMessage>> {
+        // The Wave PDU following a Wave Info PDU has no header.
+        if let Some(info) = self.wave_info.take() {
+            let wave = SndWavePdu::decode(&mut ReadCursor::new(payload)).map_err(|e| decode_err!(e))?;
+            if info.data_len != wave.data.len() + info.data.len() {
+                warn!(
+                    expected = info.data_len,
+                    actual = wave.data.len() + info.data.len(),
+                    "Wave length mismatch"
+                );
+            }
+            let pdu = WavePdu::from_parts(info, wave);
+            let ts = u32::from(pdu.timestamp);
+            return self.wave(pdu.format_no, ts, pdu.data, pdu.timestamp, pdu.block_no);
+        }
+
+        let pdu = pdu::ServerAudioOutputPdu::decode(&mut ReadCursor::new(payload)).map_err(|e| decode_err!(e))?;
+
+        self.process_pdu(pdu)
+    }
 }
 
 impl Drop for Rdpsnd {
@@ -0,0 +1,74 @@
+//! G.711 A-law and µ-law companding, as in the reference implementation of Sun Microsystems
+
+use super::saturate;
+
+/// Upper bound of each A-law segment, on 13-bit magnitudes
+const ALAW_SEGMENT_ENDS: [i32; 8] = [0x1F, 0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF];
+
+/// Upper bound of each µ-law segment, on 14-bit magnitudes
+const MULAW_SEGMENT_ENDS: [i32; 8] = [0x3F, 0x7F, 0xFF, 0x1FF, 0x3FF, 0x7FF, 0xFFF, 0x1FFF];
+
+const MULAW_BIAS: i32 = 0x84;
+const MULAW_CLIP: i32 = 8159;
+
+fn segment(value: i32, ends: &[i32; 8]) -> Option<i32> {
+    ends.iter()
+        .zip(0..)
+        .find(|(end, _)| value <= **end)
+        .map(|(_, segment)| segment)
+}
+
+fn low_byte(value: i32) -> u8 {
+    value.to_le_bytes()[0]
+}
+
+pub(super) fn alaw_encode(sample: i16) -> u8 {
+    let value = i32::from(sample) >> 3;
+    let (value, mask) = if value >= 0 { (value, 0xD5) } else { (-value - 1, 0x55) };
+
+    let encoded = match segment(value, &ALAW_SEGMENT_ENDS) {
+        None => 0x7F,
+        Some(segment @ (0 | 1)) => (segment << 4) | ((value >> 1) & 0x0F),
+        Some(segment) => (segment << 4) | ((value >> segment) & 0x0F),
+    };
+
+    low_byte(encoded ^ mask)
+}
+
+pub(super) fn alaw_decode(value: u8) -> i16 {
+    let value = i32::from(value ^ 0x55);
+
+    let magnitude = (value & 0x0F) << 4;
+    let magnitude = match (value & 0x70) >> 4 {
+        0 => magnitude + 8,
+        1 => magnitude + 0x108,
+        segment => (magnitude + 0x108) << (segment - 1),
+    };
+
+    saturate(if value & 0x80 != 0 { magnitude } else { -magnitude })
+}
+
+pub(super) fn mulaw_encode(sample: i16) -> u8 {
+    let value = i32::from(sample) >> 2;
+    let (value, mask) = if value < 0 { (-value, 0x7F) } else { (value, 0xFF) };
+    let value = value.min(MULAW_CLIP) + (MULAW_BIAS >> 2);
+
+    let encoded = match segment(value, &MULAW_SEGMENT_ENDS) {
+        None => 0x7F,
+        Some(segment) => (segment << 4) | ((value >> (segment + 1)) & 0x0F),
+    };
+
+    low_byte(encoded ^ mask)
+}
+
+pub(super) fn mulaw_decode(value: u8) -> i16 {
+    let value = i32::from(!value);
+
+    let magnitude = (((value & 0x0F) << 3) + MULAW_BIAS) << ((value & 0x70) >> 4);
+
+    saturate(if value & 0x80 != 0 {
+        MULAW_BIAS - magnitude
+    } else {
+        magnitude - MULAW_BIAS
+    })
+}
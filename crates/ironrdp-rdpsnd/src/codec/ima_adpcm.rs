@@ -0,0 +1,147 @@
+//! IMA (DVI) ADPCM, in the block layout of the WAVE format
+//!
+//! Each block starts with a header per channel: the first sample, the step index and a reserved byte.
+//! The data of the channels is then interleaved by groups of 4 bytes (8 samples), low nibble first.
+
+use super::{saturate, CodecError};
+
+/// Size of the header of a channel, in bytes
+pub(super) const HEADER_SIZE: usize = 4;
+
+const INDEX_TABLE: [i8; 16] = [-1, -1, -1, -1, 2, 4, 6, 8, -1, -1, -1, -1, 2, 4, 6, 8];
+
+const STEP_TABLE: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66, 73, 80, 88, 97, 107,
+    118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449, 494, 544, 598, 658, 724, 796, 876, 963,
+    1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272, 2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894,
+    6484, 7132, 7845, 8630, 9493, 10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794,
+    32767,
+];
+
+const MAX_STEP_INDEX: u8 = 88;
+
+/// Number of samples per channel in a block
+pub(super) fn samples_per_block(block_align: usize, n_channels: usize) -> usize {
+    // The header holds the first sample, each byte of data two more.
+    block_align.saturating_sub(HEADER_SIZE * n_channels) * 2 / n_channels + 1
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct ImaState {
+    predictor: i32,
+    step_index: u8,
+}
+
+impl ImaState {
+    fn decode(&mut self, nibble: u8) -> i16 {
+        let step = STEP_TABLE[usize::from(self.step_index)];
+
+        let mut diff = step >> 3;
+        if nibble & 1 != 0 {
+            diff += step >> 2;
+        }
+        if nibble & 2 != 0 {
+            diff += step >> 1;
+        }
+        if nibble & 4 != 0 {
+            diff += step;
+        }
+        if nibble & 8 != 0 {
+            diff = -diff;
+        }
+
+        let sample = saturate(self.predictor + diff);
+        self.predictor = i32::from(sample);
+
+        let step_index = (i32::from(self.step_index) + i32::from(INDEX_TABLE[usize::from(nibble & 0x0F)]))
+            .clamp(0, i32::from(MAX_STEP_INDEX));
+        self.step_index = u8::try_from(step_index).expect("clamped to the step table");
+
+        sample
+    }
+
+    fn encode(&mut self, sample: i16) -> u8 {
+        let mut step = STEP_TABLE[usize::from(self.step_index)];
+        let mut diff = i32::from(sample) - self.predictor;
+
+        let mut nibble = 0;
+        if diff < 0 {
+            nibble = 8;
+            diff = -diff;
+        }
+        for bit in [4, 2, 1] {
+            if diff >= step {
+                nibble |= bit;
+                diff -= step;
+            }
+            step >>= 1;
+        }
+
+        // The predictor follows the decoder, so that the errors do not accumulate.
+        self.decode(nibble);
+
+        nibble
+    }
+}
+
+pub(super) fn decode_block(block: &[u8], n_channels: usize, dst: &mut Vec<i16>) -> Result<(), CodecError> {
+    let header_size = HEADER_SIZE * n_channels;
+    if block.len() < header_size {
+        return Err(CodecError::InvalidData("truncated IMA ADPCM block header"));
+    }
+    let (header, data) = block.split_at(header_size);
+
+    let mut states = Vec::with_capacity(n_channels);
+    for header in header.chunks_exact(HEADER_SIZE) {
+        let sample = i16::from_le_bytes([header[0], header[1]]);
+        let step_index = header[2];
+        if step_index > MAX_STEP_INDEX {
+            return Err(CodecError::InvalidData("invalid IMA ADPCM step index"));
+        }
+
+        dst.push(sample);
+        states.push(ImaState {
+            predictor: i32::from(sample),
+            step_index,
+        });
+    }
+
+    let mut samples = vec![[0; 8]; n_channels];
+    for group in data.chunks_exact(4 * n_channels) {
+        for ((state, data), samples) in states.iter_mut().zip(group.chunks_exact(4)).zip(samples.iter_mut()) {
+            for (byte, samples) in data.iter().zip(samples.chunks_exact_mut(2)) {
+                samples[0] = state.decode(byte & 0x0F);
+                samples[1] = state.decode(byte >> 4);
+            }
+        }
+
+        for i in 0..8 {
+            dst.extend(samples.iter().map(|samples| samples[i]));
+        }
+    }
+
+    Ok(())
+}
+
+/// Encodes a complete block, carrying the step indexes of the channels over from the previous block
+pub(super) fn encode_block(block: &[i16], states: &mut [ImaState], dst: &mut Vec<u8>) {
+    let n_channels = states.len();
+    let (first, samples) = block.split_at(n_channels);
+
+    for (state, sample) in states.iter_mut().zip(first) {
+        state.predictor = i32::from(*sample);
+        dst.extend_from_slice(&sample.to_le_bytes());
+        dst.push(state.step_index);
+        dst.push(0);
+    }
+
+    for group in samples.chunks_exact(8 * n_channels) {
+        for (channel, state) in states.iter_mut().enumerate() {
+            for pair in group.chunks_exact(2 * n_channels) {
+                let low = state.encode(pair[channel]);
+                let high = state.encode(pair[n_channels + channel]);
+                dst.push(low | (high << 4));
+            }
+        }
+    }
+}
@@ -0,0 +1,360 @@
+//! Audio codecs implemented without native dependencies
+//!
+//! Supports PCM, G.711 A-law and µ-law, IMA (DVI) ADPCM and Microsoft ADPCM.
+//!
+//! The samples handled by the codecs are signed 16-bit integers, interleaved by channel.
+
+mod g711;
+mod ima_adpcm;
+mod ms_adpcm;
+
+use core::fmt;
+
+use crate::pdu::{AudioFormat, WaveFormat};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CodecError {
+    UnsupportedFormat(WaveFormat),
+    InvalidFormat(&'static str),
+    InvalidData(&'static str),
+}
+
+impl std::error::Error for CodecError {}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::UnsupportedFormat(format) => write!(f, "unsupported audio format: {format}"),
+            CodecError::InvalidFormat(reason) => write!(f, "invalid audio format: {reason}"),
+            CodecError::InvalidData(reason) => write!(f, "invalid audio data: {reason}"),
+        }
+    }
+}
+
+/// Returns the formats supported by the codecs, from the best quality to the lowest bandwidth
+pub fn supported_formats(n_channels: u16, n_samples_per_sec: u32) -> Vec<AudioFormat> {
+    vec![
+        pcm_format(n_channels, n_samples_per_sec),
+        ms_adpcm_format(n_channels, n_samples_per_sec),
+        ima_adpcm_format(n_channels, n_samples_per_sec),
+        alaw_format(n_channels, n_samples_per_sec),
+        mulaw_format(n_channels, n_samples_per_sec),
+    ]
+}
+
+/// Returns `true` if the audio can be encoded and decoded in `format`
+pub fn is_supported(format: &AudioFormat) -> bool {
+    Codec::new(format).is_ok()
+}
+
+/// 16-bit PCM
+pub fn pcm_format(n_channels: u16, n_samples_per_sec: u32) -> AudioFormat {
+    sample_format(WaveFormat::PCM, n_channels, n_samples_per_sec, 16)
+}
+
+/// G.711 A-law, 8 bits per sample
+pub fn alaw_format(n_channels: u16, n_samples_per_sec: u32) -> AudioFormat {
+    sample_format(WaveFormat::ALAW, n_channels, n_samples_per_sec, 8)
+}
+
+/// G.711 µ-law, 8 bits per sample
+pub fn mulaw_format(n_channels: u16, n_samples_per_sec: u32) -> AudioFormat {
+    sample_format(WaveFormat::MULAW, n_channels, n_samples_per_sec, 8)
+}
+
+/// IMA ADPCM, 4 bits per sample, with the block size used by Windows
+pub fn ima_adpcm_format(n_channels: u16, n_samples_per_sec: u32) -> AudioFormat {
+    let n_block_align = adpcm_block_align(n_channels, n_samples_per_sec);
+    let samples_per_block = ima_adpcm::samples_per_block(usize::from(n_block_align), usize::from(n_channels).max(1));
+
+    adpcm_format(
+        WaveFormat::DVI_ADPCM,
+        n_channels,
+        n_samples_per_sec,
+        n_block_align,
+        samples_per_block,
+        Vec::new(),
+    )
+}
+
+/// Microsoft ADPCM, 4 bits per sample, with the block size and the coefficients used by Windows
+pub fn ms_adpcm_format(n_channels: u16, n_samples_per_sec: u32) -> AudioFormat {
+    let n_block_align = adpcm_block_align(n_channels, n_samples_per_sec);
+    let samples_per_block = ms_adpcm::samples_per_block(usize::from(n_block_align), usize::from(n_channels).max(1));
+
+    let mut extra = Vec::with_capacity(4 * ms_adpcm::COEFFICIENTS.len());
+    extra.extend_from_slice(
+        &u16::try_from(ms_adpcm::COEFFICIENTS.len())
+            .expect("7 coefficients")
+            .to_le_bytes(),
+    );
+    for (coef1, coef2) in ms_adpcm::COEFFICIENTS {
+        extra.extend_from_slice(&coef1.to_le_bytes());
+        extra.extend_from_slice(&coef2.to_le_bytes());
+    }
+
+    adpcm_format(
+        WaveFormat::ADPCM,
+        n_channels,
+        n_samples_per_sec,
+        n_block_align,
+        samples_per_block,
+        extra,
+    )
+}
+
+fn sample_format(format: WaveFormat, n_channels: u16, n_samples_per_sec: u32, bits_per_sample: u16) -> AudioFormat {
+    let n_block_align = n_channels.saturating_mul(bits_per_sample / 8);
+
+    AudioFormat {
+        format,
+        n_channels,
+        n_samples_per_sec,
+        n_avg_bytes_per_sec: n_samples_per_sec.saturating_mul(u32::from(n_block_align)),
+        n_block_align,
+        bits_per_sample,
+        data: None,
+    }
+}
+
+fn adpcm_format(
+    format: WaveFormat,
+    n_channels: u16,
+    n_samples_per_sec: u32,
+    n_block_align: u16,
+    samples_per_block: usize,
+    extra: Vec<u8>,
+) -> AudioFormat {
+    let samples_per_block = u16::try_from(samples_per_block).unwrap_or(u16::MAX);
+    let n_avg_bytes_per_sec =
+        u64::from(n_samples_per_sec) * u64::from(n_block_align) / u64::from(samples_per_block.max(1));
+
+    // The extra data starts with wSamplesPerBlock.
+    let mut data = samples_per_block.to_le_bytes().to_vec();
+    data.extend(extra);
+
+    AudioFormat {
+        format,
+        n_channels,
+        n_samples_per_sec,
+        n_avg_bytes_per_sec: u32::try_from(n_avg_bytes_per_sec).unwrap_or(u32::MAX),
+        n_block_align,
+        bits_per_sample: 4,
+        data: Some(data),
+    }
+}
+
+/// 256 bytes per channel, per 11.025 kHz of sample rate
+fn adpcm_block_align(n_channels: u16, n_samples_per_sec: u32) -> u16 {
+    let block_align = 256 * u32::from(n_channels) * (n_samples_per_sec / 11025).max(1);
+    u16::try_from(block_align).unwrap_or(u16::MAX)
+}
+
+#[derive(Debug, Clone)]
+enum Codec {
+    Pcm8,
+    Pcm16,
+    Alaw,
+    Mulaw,
+    ImaAdpcm {
+        block_align: usize,
+    },
+    MsAdpcm {
+        block_align: usize,
+        coefficients: Vec<(i16, i16)>,
+    },
+}
+
+impl Codec {
+    fn new(format: &AudioFormat) -> Result<Self, CodecError> {
+        let n_channels = usize::from(format.n_channels);
+        let block_align = usize::from(format.n_block_align);
+
+        if n_channels == 0 {
+            return Err(CodecError::InvalidFormat("no channel"));
+        }
+
+        let codec = match (format.format, format.bits_per_sample) {
+            (WaveFormat::PCM, 8) => Codec::Pcm8,
+            (WaveFormat::PCM, 16) => Codec::Pcm16,
+            (WaveFormat::PCM, _) => return Err(CodecError::InvalidFormat("PCM must have 8 or 16 bits per sample")),
+            (WaveFormat::ALAW, 8) => Codec::Alaw,
+            (WaveFormat::MULAW, 8) => Codec::Mulaw,
+            (WaveFormat::ALAW | WaveFormat::MULAW, _) => {
+                return Err(CodecError::InvalidFormat("G.711 must have 8 bits per sample"))
+            }
+            (WaveFormat::DVI_ADPCM, 4) => {
+                let header_size = ima_adpcm::HEADER_SIZE * n_channels;
+                if block_align <= header_size || (block_align - header_size) % (4 * n_channels) != 0 {
+                    return Err(CodecError::InvalidFormat("invalid IMA ADPCM block alignment"));
+                }
+                Codec::ImaAdpcm { block_align }
+            }
+            (WaveFormat::ADPCM, 4) => {
+                let header_size = ms_adpcm::HEADER_SIZE * n_channels;
+                if block_align <= header_size || ((block_align - header_size) * 2) % n_channels != 0 {
+                    return Err(CodecError::InvalidFormat("invalid MS ADPCM block alignment"));
+                }
+                let coefficients = match format.data.as_deref() {
+                    Some(data) if data.len() > 2 => ms_adpcm::decode_coefficients(&data[2..])?,
+                    _ => ms_adpcm::COEFFICIENTS.to_vec(),
+                };
+                Codec::MsAdpcm {
+                    block_align,
+                    coefficients,
+                }
+            }
+            (WaveFormat::DVI_ADPCM | WaveFormat::ADPCM, _) => {
+                return Err(CodecError::InvalidFormat("ADPCM must have 4 bits per sample"))
+            }
+            (format, _) => return Err(CodecError::UnsupportedFormat(format)),
+        };
+
+        if block_align != codec.block_align(n_channels) {
+            return Err(CodecError::InvalidFormat("invalid block alignment"));
+        }
+
+        Ok(codec)
+    }
+
+    /// Size of an encoded block, in bytes
+    fn block_align(&self, n_channels: usize) -> usize {
+        match self {
+            Codec::Pcm8 | Codec::Alaw | Codec::Mulaw => n_channels,
+            Codec::Pcm16 => 2 * n_channels,
+            Codec::ImaAdpcm { block_align } | Codec::MsAdpcm { block_align, .. } => *block_align,
+        }
+    }
+
+    /// Number of samples per channel in a block
+    fn samples_per_block(&self, n_channels: usize) -> usize {
+        match self {
+            Codec::Pcm8 | Codec::Pcm16 | Codec::Alaw | Codec::Mulaw => 1,
+            Codec::ImaAdpcm { block_align } => ima_adpcm::samples_per_block(*block_align, n_channels),
+            Codec::MsAdpcm { block_align, .. } => ms_adpcm::samples_per_block(*block_align, n_channels),
+        }
+    }
+}
+
+/// Decodes the audio received in a given format
+#[derive(Debug, Clone)]
+pub struct AudioDecoder {
+    codec: Codec,
+    n_channels: usize,
+}
+
+impl AudioDecoder {
+    pub fn new(format: &AudioFormat) -> Result<Self, CodecError> {
+        Ok(Self {
+            codec: Codec::new(format)?,
+            n_channels: usize::from(format.n_channels),
+        })
+    }
+
+    /// Decodes `src` and appends the samples to `dst`
+    ///
+    /// For ADPCM, `src` is made of complete blocks, except the last one which may be shorter.
+    pub fn decode(&mut self, src: &[u8], dst: &mut Vec<i16>) -> Result<(), CodecError> {
+        let block_align = self.codec.block_align(self.n_channels);
+        if matches!(self.codec, Codec::Pcm8 | Codec::Pcm16 | Codec::Alaw | Codec::Mulaw) && src.len() % block_align != 0
+        {
+            return Err(CodecError::InvalidData("incomplete sample"));
+        }
+
+        match &self.codec {
+            Codec::Pcm8 => dst.extend(
+                src.iter()
+                    .map(|value| i16::from(i8::from_le_bytes([value ^ 0x80])) << 8),
+            ),
+            Codec::Pcm16 => dst.extend(
+                src.chunks_exact(2)
+                    .map(|value| i16::from_le_bytes([value[0], value[1]])),
+            ),
+            Codec::Alaw => dst.extend(src.iter().copied().map(g711::alaw_decode)),
+            Codec::Mulaw => dst.extend(src.iter().copied().map(g711::mulaw_decode)),
+            Codec::ImaAdpcm { block_align } => {
+                for block in src.chunks(*block_align) {
+                    ima_adpcm::decode_block(block, self.n_channels, dst)?;
+                }
+            }
+            Codec::MsAdpcm {
+                block_align,
+                coefficients,
+            } => {
+                for block in src.chunks(*block_align) {
+                    ms_adpcm::decode_block(block, self.n_channels, coefficients, dst)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Encodes audio in a given format
+///
+/// The ADPCM formats are encoded by blocks: the samples which do not fill a block are kept for the next
+/// call, or until [`AudioEncoder::flush`].
+#[derive(Debug, Clone)]
+pub struct AudioEncoder {
+    codec: Codec,
+    n_channels: usize,
+    /// Samples not encoded yet
+    pending: Vec<i16>,
+    ima_states: Vec<ima_adpcm::ImaState>,
+    ms_deltas: Vec<i32>,
+}
+
+impl AudioEncoder {
+    pub fn new(format: &AudioFormat) -> Result<Self, CodecError> {
+        let n_channels = usize::from(format.n_channels);
+
+        Ok(Self {
+            codec: Codec::new(format)?,
+            n_channels,
+            pending: Vec::new(),
+            ima_states: vec![ima_adpcm::ImaState::default(); n_channels],
+            ms_deltas: vec![ms_adpcm::MIN_DELTA; n_channels],
+        })
+    }
+
+    /// Number of samples, for all the channels, in an encoded block
+    pub fn block_samples(&self) -> usize {
+        self.codec.samples_per_block(self.n_channels) * self.n_channels
+    }
+
+    /// Encodes the complete blocks of samples and appends them to `dst`
+    pub fn encode(&mut self, samples: &[i16], dst: &mut Vec<u8>) {
+        self.pending.extend_from_slice(samples);
+
+        let block_samples = self.block_samples();
+        let encoded = self.pending.len() - self.pending.len() % block_samples;
+        for block in self.pending[..encoded].chunks_exact(block_samples) {
+            match &self.codec {
+                Codec::Pcm8 => dst.extend(block.iter().map(|sample| sample.to_be_bytes()[0] ^ 0x80)),
+                Codec::Pcm16 => dst.extend(block.iter().flat_map(|sample| sample.to_le_bytes())),
+                Codec::Alaw => dst.extend(block.iter().copied().map(g711::alaw_encode)),
+                Codec::Mulaw => dst.extend(block.iter().copied().map(g711::mulaw_encode)),
+                Codec::ImaAdpcm { .. } => ima_adpcm::encode_block(block, &mut self.ima_states, dst),
+                Codec::MsAdpcm { coefficients, .. } => {
+                    ms_adpcm::encode_block(block, coefficients, &mut self.ms_deltas, dst)
+                }
+            }
+        }
+        self.pending.drain(..encoded);
+    }
+
+    /// Encodes the pending samples, padded with silence to fill a block
+    pub fn flush(&mut self, dst: &mut Vec<u8>) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let padding = self.block_samples() - self.pending.len();
+        self.encode(&vec![0; padding], dst);
+    }
+}
+
+fn saturate(value: i32) -> i16 {
+    i16::try_from(value.clamp(i32::from(i16::MIN), i32::from(i16::MAX))).expect("clamped to the range of i16")
+}
@@ -0,0 +1,217 @@
+//! Microsoft ADPCM, in the block layout of the WAVE format
+//!
+//! Each block starts with the predictors of the channels, then their initial deltas and their two first
+//! samples, the second one first. The following samples are interleaved by channel, high nibble first.
+
+use super::{saturate, CodecError};
+
+/// Size of the header of a channel, in bytes
+pub(super) const HEADER_SIZE: usize = 7;
+
+/// Coefficients of the predictors defined by the format, which the extra data of the format repeats
+pub(super) const COEFFICIENTS: [(i16, i16); 7] = [
+    (256, 0),
+    (512, -256),
+    (0, 0),
+    (192, 64),
+    (240, 0),
+    (460, -208),
+    (392, -232),
+];
+
+const ADAPTATION_TABLE: [i32; 16] = [
+    230, 230, 230, 230, 307, 409, 512, 614, 768, 614, 512, 409, 307, 230, 230, 230,
+];
+
+pub(super) const MIN_DELTA: i32 = 16;
+
+/// Keeps the adaptation of the delta from overflowing
+const MAX_DELTA: i32 = i32::MAX / 768;
+
+/// Number of samples per channel in a block
+pub(super) fn samples_per_block(block_align: usize, n_channels: usize) -> usize {
+    // The header holds the two first samples, each byte of data two more.
+    block_align.saturating_sub(HEADER_SIZE * n_channels) * 2 / n_channels + 2
+}
+
+/// Decodes the coefficients from the extra data of the format, following wSamplesPerBlock
+pub(super) fn decode_coefficients(data: &[u8]) -> Result<Vec<(i16, i16)>, CodecError> {
+    if data.len() < 2 {
+        return Err(CodecError::InvalidFormat("missing MS ADPCM coefficients"));
+    }
+    let (num_coef, data) = data.split_at(2);
+    let num_coef = usize::from(u16::from_le_bytes([num_coef[0], num_coef[1]]));
+
+    if num_coef == 0 || data.len() < 4 * num_coef {
+        return Err(CodecError::InvalidFormat("invalid MS ADPCM coefficients"));
+    }
+
+    Ok(data
+        .chunks_exact(4)
+        .take(num_coef)
+        .map(|coef| {
+            (
+                i16::from_le_bytes([coef[0], coef[1]]),
+                i16::from_le_bytes([coef[2], coef[3]]),
+            )
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MsState {
+    coef1: i32,
+    coef2: i32,
+    delta: i32,
+    sample1: i32,
+    sample2: i32,
+}
+
+impl MsState {
+    fn predict(&self) -> i32 {
+        (self.sample1 * self.coef1 + self.sample2 * self.coef2) >> 8
+    }
+
+    fn decode(&mut self, nibble: u8) -> i16 {
+        let nibble = nibble & 0x0F;
+        let signed = i32::from(nibble) - if nibble & 8 != 0 { 16 } else { 0 };
+
+        let sample = saturate(self.predict() + signed * self.delta);
+        self.sample2 = self.sample1;
+        self.sample1 = i32::from(sample);
+        self.delta = ((ADAPTATION_TABLE[usize::from(nibble)] * self.delta) >> 8).clamp(MIN_DELTA, MAX_DELTA);
+
+        sample
+    }
+
+    fn encode(&mut self, sample: i16) -> u8 {
+        let error = i32::from(sample) - self.predict();
+
+        // Rounded to the nearest multiple of the delta.
+        let bias = if error < 0 { -self.delta / 2 } else { self.delta / 2 };
+        let signed = ((error + bias) / self.delta).clamp(-8, 7);
+        let nibble = signed.to_le_bytes()[0] & 0x0F;
+
+        self.decode(nibble);
+
+        nibble
+    }
+}
+
+pub(super) fn decode_block(
+    block: &[u8],
+    n_channels: usize,
+    coefficients: &[(i16, i16)],
+    dst: &mut Vec<i16>,
+) -> Result<(), CodecError> {
+    let header_size = HEADER_SIZE * n_channels;
+    if block.len() < header_size {
+        return Err(CodecError::InvalidData("truncated MS ADPCM block header"));
+    }
+    let (header, data) = block.split_at(header_size);
+
+    let read_i16 = |field: usize, channel: usize| {
+        let offset = n_channels + 2 * (field * n_channels + channel);
+        i32::from(i16::from_le_bytes([header[offset], header[offset + 1]]))
+    };
+
+    let mut states = Vec::with_capacity(n_channels);
+    for (channel, predictor) in header[..n_channels].iter().enumerate() {
+        let &(coef1, coef2) = coefficients
+            .get(usize::from(*predictor))
+            .ok_or(CodecError::InvalidData("invalid MS ADPCM predictor"))?;
+
+        states.push(MsState {
+            coef1: i32::from(coef1),
+            coef2: i32::from(coef2),
+            delta: read_i16(0, channel),
+            sample1: read_i16(1, channel),
+            sample2: read_i16(2, channel),
+        });
+    }
+
+    dst.extend(states.iter().map(|state| saturate(state.sample2)));
+    dst.extend(states.iter().map(|state| saturate(state.sample1)));
+
+    let mut channels = (0..n_channels).cycle();
+    for byte in data {
+        for nibble in [byte >> 4, byte & 0x0F] {
+            let channel = channels.next().expect("infinite iterator");
+            dst.push(states[channel].decode(nibble));
+        }
+    }
+
+    Ok(())
+}
+
+/// Encodes a complete block, with the predictor which best fits each channel
+///
+/// The deltas of the channels are carried over from the previous block.
+pub(super) fn encode_block(block: &[i16], coefficients: &[(i16, i16)], deltas: &mut [i32], dst: &mut Vec<u8>) {
+    let n_channels = deltas.len();
+
+    let mut states = Vec::with_capacity(n_channels);
+    let mut predictors = Vec::with_capacity(n_channels);
+    for (channel, delta) in deltas.iter().enumerate() {
+        let channel_samples = || block.iter().skip(channel).step_by(n_channels).copied();
+        let mut head = channel_samples();
+        let sample2 = i32::from(head.next().unwrap_or_default());
+        let sample1 = i32::from(head.next().unwrap_or_default());
+
+        let initial_state = |(coef1, coef2): (i16, i16)| MsState {
+            coef1: i32::from(coef1),
+            coef2: i32::from(coef2),
+            delta: *delta,
+            sample1,
+            sample2,
+        };
+
+        // Picks the predictor with the smallest squared error over the block.
+        let (predictor, state) = coefficients
+            .iter()
+            .copied()
+            .zip(0..=u8::MAX)
+            .min_by_key(|(coefficients, _)| {
+                let mut state = initial_state(*coefficients);
+                channel_samples()
+                    .skip(2)
+                    .map(|sample| {
+                        state.encode(sample);
+                        let error = i64::from(sample) - i64::from(state.sample1);
+                        error * error
+                    })
+                    .sum::<i64>()
+            })
+            .map(|(coefficients, predictor)| (predictor, initial_state(coefficients)))
+            .expect("at least one predictor");
+
+        predictors.push(predictor);
+        states.push(state);
+    }
+
+    dst.extend_from_slice(&predictors);
+    for field in [
+        |state: &MsState| state.delta,
+        |state: &MsState| state.sample1,
+        |state: &MsState| state.sample2,
+    ] {
+        for state in &states {
+            dst.extend_from_slice(&saturate(field(state)).to_le_bytes());
+        }
+    }
+
+    let mut channels = (0..n_channels).cycle();
+    let mut nibbles = block[2 * n_channels..].iter().map(|sample| {
+        let channel = channels.next().expect("infinite iterator");
+        states[channel].encode(*sample)
+    });
+    while let Some(high) = nibbles.next() {
+        let low = nibbles.next().unwrap_or(0);
+        dst.push((high << 4) | low);
+    }
+
+    // The delta of the next header must fit in 16 bits.
+    for (delta, state) in deltas.iter_mut().zip(&states) {
+        *delta = state.delta.min(i32::from(i16::MAX));
+    }
+}
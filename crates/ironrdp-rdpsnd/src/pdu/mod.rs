@@ -7,8 +7,8 @@ use std::fmt;
 
 use bitflags::bitflags;
 use ironrdp_core::{
-    cast_length, ensure_fixed_part_size, ensure_size, invalid_field_err, other_err, Decode, DecodeError, DecodeResult,
-    Encode, EncodeResult, ReadCursor, WriteCursor,
+    cast_length, ensure_fixed_part_size, ensure_size, invalid_field_err, Decode, DecodeError, DecodeResult, Encode,
+    EncodeResult, ReadCursor, WriteCursor,
 };
 use ironrdp_pdu::{read_padding, write_padding};
 use ironrdp_svc::SvcEncode;
@@ -24,8 +24,8 @@ const SNDC_CLOSE: u8 = 0x01;
 const SNDC_WAVE2: u8 = 0x0D;
 const SNDC_VOLUME: u8 = 0x03;
 const SNDC_PITCH: u8 = 0x04;
-
-// TODO: UDP PDUs
+const SNDC_UDPWAVE: u8 = 0x0E;
+const SNDC_UDPWAVELAST: u8 = 0x0F;
 
 #[repr(u16)]
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Eq)]
@@ -673,12 +673,16 @@ impl<'de> Decode<'de> for TrainingConfirmPdu {
     }
 }
 
+/// First part of a wave, followed by a [`SndWavePdu`] with the rest of the audio data
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct WaveInfoPdu {
     pub timestamp: u16,
     pub format_no: u16,
     pub block_no: u8,
+    /// The first 4 bytes of the audio data
     pub data: [u8; 4],
+    /// Length of the audio data, including the first 4 bytes
+    pub data_len: usize,
 }
 
 impl WaveInfoPdu {
@@ -690,6 +694,35 @@ impl WaveInfoPdu {
         + 1 /* cBlockNo */
         + 3 /* bPad */
         + 4 /* data */;
+
+    /// Value of the `BodySize` field of the header, which also covers the following Wave PDU
+    fn body_size(&self) -> usize {
+        (Self::FIXED_PART_SIZE - 4)
+            .checked_add(self.data_len)
+            .expect("never overflow")
+    }
+
+    fn decode(src: &mut ReadCursor<'_>, body_size: u16) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+
+        let timestamp = src.read_u16();
+        let format_no = src.read_u16();
+        let block_no = src.read_u8();
+        read_padding!(src, 3);
+        let data = src.read_array();
+        let data_len = usize::from(body_size)
+            .checked_sub(Self::FIXED_PART_SIZE - 4)
+            .filter(|data_len| *data_len >= 4)
+            .ok_or_else(|| invalid_field_err!("Length", "WaveInfo body_size is too small"))?;
+
+        Ok(Self {
+            timestamp,
+            format_no,
+            block_no,
+            data,
+            data_len,
+        })
+    }
 }
 
 impl Encode for WaveInfoPdu {
@@ -714,30 +747,21 @@ impl Encode for WaveInfoPdu {
     }
 }
 
-impl<'de> Decode<'de> for WaveInfoPdu {
-    fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
-        ensure_fixed_part_size!(in: src);
-
-        let timestamp = src.read_u16();
-        let format_no = src.read_u16();
-        let block_no = src.read_u8();
-        read_padding!(src, 3);
-        let data = src.read_array();
-
-        Ok(Self {
-            timestamp,
-            format_no,
-            block_no,
-            data,
-        })
-    }
-}
-
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Rest of the audio data of a wave, sent without header after a [`WaveInfoPdu`]
+#[derive(Clone, PartialEq, Eq)]
 pub struct SndWavePdu {
+    /// The audio data, except the first 4 bytes sent in the [`WaveInfoPdu`]
     pub data: Vec<u8>,
 }
 
+impl fmt::Debug for SndWavePdu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SndWavePdu")
+            .field("data_len", &self.data.len())
+            .finish()
+    }
+}
+
 impl SndWavePdu {
     const NAME: &'static str = "SNDWAVE";
 
@@ -765,18 +789,19 @@ impl Encode for SndWavePdu {
     }
 }
 
-impl SndWavePdu {
-    fn decode(src: &mut ReadCursor<'_>, data_len: usize) -> DecodeResult<Self> {
+impl<'de> Decode<'de> for SndWavePdu {
+    fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
         ensure_fixed_part_size!(in: src);
 
         read_padding!(src, 4);
-        ensure_size!(in: src, size: data_len);
-        let data = src.read_slice(data_len).into();
+        let data = src.read_remaining().into();
 
         Ok(Self { data })
     }
 }
 
+impl SvcEncode for SndWavePdu {}
+
 // combines WaveInfoPdu + WavePdu
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct WavePdu<'a> {
@@ -802,9 +827,12 @@ impl Encode for WavePdu<'_> {
             timestamp: self.timestamp,
             format_no: self.format_no,
             block_no: self.block_no,
-            data: self.data[0..4]
-                .try_into()
-                .map_err(|e| other_err!("invalid data", source: e))?,
+            data: self
+                .data
+                .get(0..4)
+                .and_then(|data| data.try_into().ok())
+                .ok_or_else(|| invalid_field_err!("data", "wave data is shorter than 4 bytes"))?,
+            data_len: self.data.len(),
         };
         let wave = SndWavePdu {
             data: self.data[4..].into(),
@@ -827,23 +855,26 @@ impl Encode for WavePdu<'_> {
 
 impl WavePdu<'_> {
     fn decode(src: &mut ReadCursor<'_>, body_size: u16) -> DecodeResult<Self> {
-        let info = WaveInfoPdu::decode(src)?;
-        let body_size = body_size as usize;
-        let data_len = body_size
-            .checked_sub(info.size())
-            .ok_or_else(|| invalid_field_err!("Length", "WaveInfo body_size is too small"))?;
-        let wave = SndWavePdu::decode(src, data_len)?;
+        let info = WaveInfoPdu::decode(src, body_size)?;
+        let wave_size = SndWavePdu::FIXED_PART_SIZE + info.data_len - 4;
+        ensure_size!(in: src, size: wave_size);
+        let wave = SndWavePdu::decode(&mut ReadCursor::new(src.read_slice(wave_size)))?;
+
+        Ok(Self::from_parts(info, wave))
+    }
 
-        let mut data = Vec::with_capacity(wave.size());
+    /// Combines a Wave Info PDU and the following Wave PDU
+    pub fn from_parts(info: WaveInfoPdu, wave: SndWavePdu) -> Self {
+        let mut data = Vec::with_capacity(info.data.len() + wave.data.len());
         data.extend_from_slice(&info.data);
         data.extend_from_slice(&wave.data);
 
-        Ok(Self {
+        Self {
             timestamp: info.timestamp,
             format_no: info.format_no,
             block_no: info.block_no,
             data: data.into(),
-        })
+        }
     }
 }
 
@@ -1046,6 +1077,171 @@ impl<'de> Decode<'de> for Wave2Pdu<'_> {
     }
 }
 
+/// Fragment of a wave sent over UDP, before the [`UdpWaveLastPdu`] completing it
+#[derive(Clone, PartialEq, Eq)]
+pub struct UdpWavePdu<'a> {
+    pub block_no: u8,
+    /// Fragment number, in the order of the data
+    pub frag_no: u16,
+    pub data: Cow<'a, [u8]>,
+}
+
+impl fmt::Debug for UdpWavePdu<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UdpWavePdu")
+            .field("block_no", &self.block_no)
+            .field("frag_no", &self.frag_no)
+            .field("data_len", &self.data.len())
+            .finish()
+    }
+}
+
+impl UdpWavePdu<'_> {
+    const NAME: &'static str = "SNDUDPWAVE";
+
+    /// Fragment numbers above this one are encoded on 2 bytes
+    const MAX_SHORT_FRAG_NO: u16 = 0x7F;
+
+    pub const MAX_FRAG_NO: u16 = 0x7FFF;
+
+    const FIXED_PART_SIZE: usize =
+        1 /* cBlockNo */
+        + 1 /* cFragNo */;
+}
+
+impl Encode for UdpWavePdu<'_> {
+    fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+
+        dst.write_u8(self.block_no);
+        if self.frag_no > Self::MAX_FRAG_NO {
+            return Err(invalid_field_err!("cFragNo", "fragment number is too large"));
+        }
+        let [high, low] = self.frag_no.to_be_bytes();
+        if self.frag_no > Self::MAX_SHORT_FRAG_NO {
+            dst.write_u8(0x80 | high);
+        }
+        dst.write_u8(low);
+        dst.write_slice(&self.data);
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn size(&self) -> usize {
+        let frag_no_size = if self.frag_no > Self::MAX_SHORT_FRAG_NO { 1 } else { 0 };
+
+        (Self::FIXED_PART_SIZE + frag_no_size)
+            .checked_add(self.data.len())
+            .expect("never overflow")
+    }
+}
+
+impl<'de> Decode<'de> for UdpWavePdu<'_> {
+    fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+
+        let block_no = src.read_u8();
+        let frag_no = src.read_u8();
+        let frag_no = if frag_no & 0x80 != 0 {
+            ensure_size!(in: src, size: 1);
+            u16::from_be_bytes([frag_no & 0x7F, src.read_u8()])
+        } else {
+            u16::from(frag_no)
+        };
+        let data = src.read_remaining().to_vec().into();
+
+        Ok(Self {
+            block_no,
+            frag_no,
+            data,
+        })
+    }
+}
+
+/// Last fragment of a wave sent over UDP
+#[derive(Clone, PartialEq, Eq)]
+pub struct UdpWaveLastPdu<'a> {
+    /// Length of the audio data of all the fragments
+    pub total_size: u16,
+    pub timestamp: u16,
+    pub format_no: u16,
+    pub block_no: u8,
+    pub data: Cow<'a, [u8]>,
+}
+
+impl fmt::Debug for UdpWaveLastPdu<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UdpWaveLastPdu")
+            .field("total_size", &self.total_size)
+            .field("timestamp", &self.timestamp)
+            .field("format_no", &self.format_no)
+            .field("block_no", &self.block_no)
+            .field("data_len", &self.data.len())
+            .finish()
+    }
+}
+
+impl UdpWaveLastPdu<'_> {
+    const NAME: &'static str = "SNDUDPWAVELAST";
+
+    const FIXED_PART_SIZE: usize =
+        2 /* wTotalSize */
+        + 2 /* wTimeStamp */
+        + 2 /* wFormatNo */
+        + 1 /* cBlockNo */
+        + 3 /* bPad */;
+}
+
+impl Encode for UdpWaveLastPdu<'_> {
+    fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ensure_size!(in: dst, size: self.size());
+
+        dst.write_u16(self.total_size);
+        dst.write_u16(self.timestamp);
+        dst.write_u16(self.format_no);
+        dst.write_u8(self.block_no);
+        write_padding!(dst, 3);
+        dst.write_slice(&self.data);
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn size(&self) -> usize {
+        Self::FIXED_PART_SIZE
+            .checked_add(self.data.len())
+            .expect("never overflow")
+    }
+}
+
+impl<'de> Decode<'de> for UdpWaveLastPdu<'_> {
+    fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
+        ensure_fixed_part_size!(in: src);
+
+        let total_size = src.read_u16();
+        let timestamp = src.read_u16();
+        let format_no = src.read_u16();
+        let block_no = src.read_u8();
+        read_padding!(src, 3);
+        let data = src.read_remaining().to_vec().into();
+
+        Ok(Self {
+            total_size,
+            timestamp,
+            format_no,
+            block_no,
+            data,
+        })
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct VolumePdu {
     pub volume_left: u16,
@@ -1132,17 +1328,25 @@ impl<'de> Decode<'de> for PitchPdu {
 }
 
 /// Server Audio Output Channel message (PDU prefixed with `SNDPROLOG`)
+///
+/// A [`WaveInfoPdu`] is followed by a [`SndWavePdu`], which has no header and must be decoded on its
+/// own. The Wave PDU is decoded as a [`WavePdu`] when both are in the same message.
+///
+/// The UDP Wave PDU is only prefixed with its message type.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ServerAudioOutputPdu<'a> {
     AudioFormat(ServerAudioFormatPdu),
     CryptKey(CryptKeyPdu),
     Training(TrainingPdu),
     Wave(WavePdu<'a>),
+    WaveInfo(WaveInfoPdu),
     WaveEncrypt(WaveEncryptPdu),
     Close,
     Wave2(Wave2Pdu<'a>),
     Volume(VolumePdu),
     Pitch(PitchPdu),
+    UdpWave(UdpWavePdu<'a>),
+    UdpWaveLast(UdpWaveLastPdu<'a>),
 }
 
 impl ServerAudioOutputPdu<'_> {
@@ -1153,6 +1357,12 @@ impl ServerAudioOutputPdu<'_> {
 
 impl Encode for ServerAudioOutputPdu<'_> {
     fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        if let Self::UdpWave(pdu) = self {
+            ensure_size!(in: dst, size: self.size());
+            dst.write_u8(SNDC_UDPWAVE);
+            return pdu.encode(dst);
+        }
+
         ensure_fixed_part_size!(in: dst);
 
         let (msg_type, pdu_size) = match self {
@@ -1160,11 +1370,14 @@ impl Encode for ServerAudioOutputPdu<'_> {
             Self::CryptKey(pdu) => (SNDC_CRYPTKEY, pdu.size()),
             Self::Training(pdu) => (SNDC_TRAINING, pdu.size()),
             Self::Wave(pdu) => (SNDC_WAVE, pdu.body_size()),
+            Self::WaveInfo(pdu) => (SNDC_WAVE, pdu.body_size()),
             Self::WaveEncrypt(pdu) => (SNDC_WAVEENCRYPT, pdu.size()),
             Self::Close => (SNDC_CLOSE, 0),
             Self::Wave2(pdu) => (SNDC_WAVE2, pdu.size()),
             Self::Volume(pdu) => (SNDC_VOLUME, pdu.size()),
             Self::Pitch(pdu) => (SNDC_PITCH, pdu.size()),
+            Self::UdpWaveLast(pdu) => (SNDC_UDPWAVELAST, pdu.size()),
+            Self::UdpWave(_) => unreachable!("encoded without SNDPROLOG"),
         };
 
         dst.write_u8(msg_type);
@@ -1176,11 +1389,14 @@ impl Encode for ServerAudioOutputPdu<'_> {
             Self::CryptKey(pdu) => pdu.encode(dst),
             Self::Training(pdu) => pdu.encode(dst),
             Self::Wave(pdu) => pdu.encode(dst),
+            Self::WaveInfo(pdu) => pdu.encode(dst),
             Self::WaveEncrypt(pdu) => pdu.encode(dst),
             Self::Close => Ok(()),
             Self::Wave2(pdu) => pdu.encode(dst),
             Self::Volume(pdu) => pdu.encode(dst),
             Self::Pitch(pdu) => pdu.encode(dst),
+            Self::UdpWave(pdu) => pdu.encode(dst),
+            Self::UdpWaveLast(pdu) => pdu.encode(dst),
         }?;
 
         Ok(())
@@ -1191,27 +1407,39 @@ impl Encode for ServerAudioOutputPdu<'_> {
     }
 
     fn size(&self) -> usize {
-        Self::FIXED_PART_SIZE
-            .checked_add(match self {
-                Self::AudioFormat(pdu) => pdu.size(),
-                Self::CryptKey(pdu) => pdu.size(),
-                Self::Training(pdu) => pdu.size(),
-                Self::Wave(pdu) => pdu.size(),
-                Self::WaveEncrypt(pdu) => pdu.size(),
-                Self::Close => 0,
-                Self::Wave2(pdu) => pdu.size(),
-                Self::Volume(pdu) => pdu.size(),
-                Self::Pitch(pdu) => pdu.size(),
-            })
-            .expect("never overflow")
+        match self {
+            Self::UdpWave(pdu) => 1 /* msgType */ + pdu.size(),
+            _ => Self::FIXED_PART_SIZE
+                .checked_add(match self {
+                    Self::AudioFormat(pdu) => pdu.size(),
+                    Self::CryptKey(pdu) => pdu.size(),
+                    Self::Training(pdu) => pdu.size(),
+                    Self::Wave(pdu) => pdu.size(),
+                    Self::WaveInfo(pdu) => pdu.size(),
+                    Self::WaveEncrypt(pdu) => pdu.size(),
+                    Self::Close => 0,
+                    Self::Wave2(pdu) => pdu.size(),
+                    Self::Volume(pdu) => pdu.size(),
+                    Self::Pitch(pdu) => pdu.size(),
+                    Self::UdpWave(pdu) => pdu.size(),
+                    Self::UdpWaveLast(pdu) => pdu.size(),
+                })
+                .expect("never overflow"),
+        }
     }
 }
 
 impl<'de> Decode<'de> for ServerAudioOutputPdu<'_> {
     fn decode(src: &mut ReadCursor<'de>) -> DecodeResult<Self> {
-        ensure_fixed_part_size!(in: src);
+        ensure_size!(in: src, size: 1);
 
         let msg_type = src.read_u8();
+        if msg_type == SNDC_UDPWAVE {
+            let pdu = UdpWavePdu::decode(src)?;
+            return Ok(Self::UdpWave(pdu));
+        }
+
+        ensure_size!(in: src, size: Self::FIXED_PART_SIZE - 1);
         read_padding!(src, 1);
         let body_size = src.read_u16();
 
@@ -1228,6 +1456,11 @@ impl<'de> Decode<'de> for ServerAudioOutputPdu<'_> {
                 let pdu = TrainingPdu::decode(src)?;
                 Ok(Self::Training(pdu))
             }
+            // The Wave PDU usually follows in a separate message.
+            SNDC_WAVE if src.len() < usize::from(body_size) + SndWavePdu::FIXED_PART_SIZE => {
+                let pdu = WaveInfoPdu::decode(src, body_size)?;
+                Ok(Self::WaveInfo(pdu))
+            }
             SNDC_WAVE => {
                 let pdu = WavePdu::decode(src, body_size)?;
                 Ok(Self::Wave(pdu))
@@ -1249,6 +1482,10 @@ impl<'de> Decode<'de> for ServerAudioOutputPdu<'_> {
                 let pdu = PitchPdu::decode(src)?;
                 Ok(Self::Pitch(pdu))
             }
+            SNDC_UDPWAVELAST => {
+                let pdu = UdpWaveLastPdu::decode(src)?;
+                Ok(Self::UdpWaveLast(pdu))
+            }
             _ => Err(invalid_field_err!(
                 "ServerAudioOutputPdu::msgType",
                 "Unknown audio output PDU type"
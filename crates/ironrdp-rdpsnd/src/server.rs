@@ -1,8 +1,12 @@
-use ironrdp_core::{impl_as_any, Decode, ReadCursor};
+use core::time::Duration;
+use std::collections::VecDeque;
+use std::time::Instant;
+
+use ironrdp_core::{encode_vec, impl_as_any, Decode, ReadCursor};
 use ironrdp_pdu::gcc::ChannelName;
-use ironrdp_pdu::{decode_err, pdu_other_err, PduResult};
+use ironrdp_pdu::{decode_err, encode_err, pdu_other_err, PduResult};
 use ironrdp_svc::{CompressionCondition, SvcMessage, SvcProcessor, SvcProcessorMessages, SvcServerProcessor};
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
 
 use crate::pdu::{self, ClientAudioFormatPdu, QualityMode};
 
@@ -33,9 +37,22 @@ pub trait RdpsndServerHandler: Send + core::fmt::Debug {
 
     fn start(&mut self, client_format: &ClientAudioFormatPdu) -> Option<u16>;
 
+    /// Called when the client confirms a wave
+    ///
+    /// The latency is the round trip of the wave and its confirmation, plus the playback delay reported by the
+    /// client.
+    fn wave_confirm(&mut self, _block_no: u8, _latency: Duration) {}
+
+    /// Called when a wave is sent with [`RdpsndServer::wave`] or [`RdpsndServer::udp_wave`], with its block number
+    /// and audio timestamp
+    fn wave_sent(&mut self, _block_no: u8, _ts: u32) {}
+
     fn stop(&mut self);
 }
 
+/// Waves not yet confirmed, kept for at most a full cycle of block numbers
+const MAX_SENT_WAVES: usize = 256;
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum RdpsndState {
     Start,
@@ -54,6 +71,9 @@ pub struct RdpsndServer {
     quality_mode: Option<QualityMode>,
     block_no: u8,
     format_no: Option<u16>,
+    start_time: Instant,
+    /// Block number, timestamp and send time of the waves not yet confirmed
+    sent_waves: VecDeque<(u8, u16, Instant)>,
 }
 
 impl RdpsndServer {
@@ -67,7 +87,50 @@ impl RdpsndServer {
             quality_mode: None,
             format_no: None,
             block_no: 0,
+            start_time: Instant::now(),
+            sent_waves: VecDeque::new(),
+        }
+    }
+
+    /// Timestamp of the waves: the time elapsed since the channel was created, in milliseconds, on 16 bits
+    fn timestamp(&self) -> u16 {
+        let elapsed = self.start_time.elapsed().as_millis() % 0x1_0000;
+        u16::try_from(elapsed).expect("less than 0x10000")
+    }
+
+    /// Records a sent wave and moves to the next block number
+    fn wave_sent(&mut self, timestamp: u16) {
+        if self.sent_waves.len() == MAX_SENT_WAVES {
+            self.sent_waves.pop_front();
         }
+        self.sent_waves.push_back((self.block_no, timestamp, Instant::now()));
+        self.block_no = self.block_no.wrapping_add(1);
+    }
+
+    fn wave_confirm(&mut self, confirm: &pdu::WaveConfirmPdu) {
+        let Some(position) = self
+            .sent_waves
+            .iter()
+            .position(|(block_no, _, _)| *block_no == confirm.block_no)
+        else {
+            warn!(?confirm, "Confirmation of an unknown wave");
+            return;
+        };
+
+        // The waves sent before the confirmed one are considered lost.
+        let (block_no, timestamp, sent) = self
+            .sent_waves
+            .drain(..=position)
+            .last()
+            .expect("at least the confirmed wave");
+
+        // The client adds its playback delay to the timestamp of the wave.
+        let delay = confirm.timestamp.wrapping_sub(timestamp);
+        let delay = if delay < 0x8000 { delay } else { 0 };
+        let latency = sent.elapsed() + Duration::from_millis(u64::from(delay));
+        debug!(block_no, ?latency, "Wave confirmed");
+
+        self.handler.wave_confirm(block_no, latency);
     }
 
     pub fn version(&self) -> PduResult<pdu::Version> {
@@ -88,6 +151,14 @@ impl RdpsndServer {
         Ok(client_format.flags)
     }
 
+    /// UDP port on which the client receives the waves, if any
+    pub fn dgram_port(&self) -> Option<u16> {
+        self.client_format
+            .as_ref()
+            .map(|client_format| client_format.dgram_port)
+            .filter(|port| *port != 0)
+    }
+
     pub fn training_pdu(&mut self) -> PduResult<RdpsndSvcMessages> {
         let pdu = pdu::TrainingPdu {
             timestamp: 4231, // a random number
@@ -104,31 +175,93 @@ impl RdpsndServer {
             .format_no
             .ok_or_else(|| pdu_other_err!("invalid state - no format"))?;
 
+        let timestamp = self.timestamp();
+
         // The server doesn't wait for wave confirm, apparently FreeRDP neither.
         let msg = if version >= pdu::Version::V8 {
             let pdu = pdu::Wave2Pdu {
                 block_no: self.block_no,
-                timestamp: 0,
+                timestamp,
                 audio_timestamp: ts,
                 format_no,
                 data: data.into(),
             };
             RdpsndSvcMessages::new(vec![pdu::ServerAudioOutputPdu::Wave2(pdu).into()])
         } else {
-            let pdu = pdu::WavePdu {
-                block_no: self.block_no,
+            // The Wave PDU must follow the Wave Info PDU in a separate message.
+            if data.len() < 4 {
+                return Err(pdu_other_err!("wave data is shorter than 4 bytes"));
+            }
+            let (first, rest) = data.split_at(4);
+            let info = pdu::WaveInfoPdu {
+                timestamp,
                 format_no,
-                timestamp: 0,
-                data: data.into(),
+                block_no: self.block_no,
+                data: first.try_into().expect("4 bytes"),
+                data_len: data.len(),
             };
-            RdpsndSvcMessages::new(vec![pdu::ServerAudioOutputPdu::Wave(pdu).into()])
+            let wave = pdu::SndWavePdu { data: rest.to_vec() };
+            RdpsndSvcMessages::new(vec![pdu::ServerAudioOutputPdu::WaveInfo(info).into(), wave.into()])
         };
 
-        self.block_no = self.block_no.overflowing_add(1).0;
+        self.handler.wave_sent(self.block_no, ts);
+        self.wave_sent(timestamp);
 
         Ok(msg)
     }
 
+    /// Encodes a wave in datagrams for the UDP port of the client
+    ///
+    /// The wave is split in UDP Wave PDUs of at most `max_datagram_size` bytes, completed by a UDP Wave
+    /// Last PDU. The block numbers are shared with [`RdpsndServer::wave`]. The audio timestamp `ts` isn't sent to
+    /// the client, UDP waves only carry the 16 bits timestamp of the channel.
+    pub fn udp_wave(&mut self, data: &[u8], ts: u32, max_datagram_size: usize) -> PduResult<Vec<Vec<u8>>> {
+        let format_no = self
+            .format_no
+            .ok_or_else(|| pdu_other_err!("invalid state - no format"))?;
+        let timestamp = self.timestamp();
+        let total_size = u16::try_from(data.len()).map_err(|_| pdu_other_err!("wave is too large for UDP"))?;
+
+        let last_size = max_datagram_size.saturating_sub(14 /* SNDPROLOG and the fixed part */);
+        if last_size == 0 {
+            return Err(pdu_other_err!("datagrams are too small"));
+        }
+
+        let (mut fragments, last) = data.split_at(data.len().saturating_sub(last_size));
+        let mut datagrams = Vec::new();
+        let mut frag_no = 0u16;
+        while !fragments.is_empty() {
+            if frag_no > pdu::UdpWavePdu::MAX_FRAG_NO {
+                return Err(pdu_other_err!("too many UDP wave fragments"));
+            }
+            // msgType, cBlockNo and cFragNo, which takes 2 bytes after the first 128 fragments
+            let header_size = if frag_no > 0x7F { 4 } else { 3 };
+            let (fragment, rest) = fragments.split_at(fragments.len().min(max_datagram_size - header_size));
+            let pdu = pdu::ServerAudioOutputPdu::UdpWave(pdu::UdpWavePdu {
+                block_no: self.block_no,
+                frag_no,
+                data: fragment.into(),
+            });
+            datagrams.push(encode_vec(&pdu).map_err(|e| encode_err!(e))?);
+            fragments = rest;
+            frag_no += 1;
+        }
+
+        let pdu = pdu::ServerAudioOutputPdu::UdpWaveLast(pdu::UdpWaveLastPdu {
+            total_size,
+            timestamp,
+            format_no,
+            block_no: self.block_no,
+            data: last.into(),
+        });
+        datagrams.push(encode_vec(&pdu).map_err(|e| encode_err!(e))?);
+
+        self.handler.wave_sent(self.block_no, ts);
+        self.wave_sent(timestamp);
+
+        Ok(datagrams)
+    }
+
     pub fn set_volume(&mut self, volume_left: u16, volume_right: u16) -> PduResult<RdpsndSvcMessages> {
         if !self.flags()?.contains(pdu::AudioFormatFlags::VOLUME) {
             return Err(pdu_other_err!("client doesn't support volume"));
@@ -199,8 +332,17 @@ impl SvcProcessor for RdpsndServer {
                 vec![]
             }
             RdpsndState::Ready => {
-                if let pdu::ClientAudioOutputPdu::WaveConfirm(c) = pdu {
-                    debug!(?c);
+                match pdu {
+                    pdu::ClientAudioOutputPdu::WaveConfirm(c) => {
+                        self.wave_confirm(&c);
+                    }
+                    // Sent again by some clients, when the training is repeated.
+                    pdu::ClientAudioOutputPdu::TrainingConfirm(c) => {
+                        debug!(?c);
+                    }
+                    pdu => {
+                        warn!(?pdu, "Unexpected PDU");
+                    }
                 }
                 vec![]
             }
@@ -0,0 +1,7 @@
+pub use ironrdp_rdpeai::pdu::AudioFormat;
+pub use ironrdp_rdpeai::server::AudioInputServerHandler;
+
+/// Builds the handler receiving the audio captured by the client of each connection, e.g. its microphone
+pub trait AudioInputServerFactory: Send + Sync {
+    fn build_backend(&self) -> Box<dyn AudioInputServerHandler>;
+}
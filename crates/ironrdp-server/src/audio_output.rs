@@ -0,0 +1,599 @@
+use core::fmt;
+use core::time::Duration;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use anyhow::Result;
+use ironrdp_rdpsnd::codec::{self, AudioEncoder};
+use ironrdp_rdpsnd::pdu::{AudioFormat, ClientAudioFormatPdu, WaveFormat};
+use ironrdp_rdpsnd::server::{RdpsndServerHandler, RdpsndServerMessage};
+use tokio::sync::mpsc;
+
+use crate::{ServerEvent, ServerEventSender, SoundServerFactory};
+
+/// Duration of the audio sent in each wave, in milliseconds
+const WAVE_DURATION_MS: u32 = 20;
+
+/// Waves not confirmed after this delay are considered lost
+const LOST_WAVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[cfg(feature = "opus")]
+const MAX_OPUS_PACKET_SIZE: usize = 4000;
+
+/// Audio output pipeline, sending PCM audio to the clients
+///
+/// It is the [`SoundServerFactory`] of the server, and a clone is kept to write the audio. Each backend
+/// streams the audio to its own connection, so a clone may be returned for each connection by
+/// [`RdpServerHandlerFactory::build_sound_factory`](crate::RdpServerHandlerFactory::build_sound_factory).
+/// The PCM audio is resampled and encoded in the best format supported by each client: Opus with the
+/// `opus` feature, then ADPCM, PCM and G.711.
+///
+/// The latency is measured with the confirmations of the client. While it exceeds the maximum latency,
+/// the audio of this client is dropped.
+#[derive(Debug, Clone)]
+pub struct AudioOutput {
+    inner: Arc<Mutex<Inner>>,
+    /// Event sender of the connection of the backends built by this factory
+    sender: Option<mpsc::UnboundedSender<ServerEvent>>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    max_latency: Duration,
+    next_id: u64,
+    connections: Vec<Connection>,
+}
+
+/// Audio output of a connection, from the creation of its backend
+#[derive(Debug)]
+struct Connection {
+    id: u64,
+    sender: Option<mpsc::UnboundedSender<ServerEvent>>,
+    stream: Option<Stream>,
+}
+
+impl AudioOutput {
+    pub const DEFAULT_MAX_LATENCY: Duration = Duration::from_millis(250);
+
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner {
+                max_latency: Self::DEFAULT_MAX_LATENCY,
+                next_id: 0,
+                connections: Vec::new(),
+            })),
+            sender: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_max_latency(self, max_latency: Duration) -> Self {
+        self.inner().max_latency = max_latency;
+        self
+    }
+
+    /// Format of the audio sent to the first listening client, once negotiated
+    pub fn format(&self) -> Option<AudioFormat> {
+        self.inner().first_stream().map(|stream| stream.format.clone())
+    }
+
+    /// Latency of the last wave confirmed by the first listening client
+    pub fn latency(&self) -> Option<Duration> {
+        self.inner().first_stream().and_then(|stream| stream.latency)
+    }
+
+    /// Writes interleaved 16-bit samples
+    ///
+    /// The audio is dropped while no client is listening.
+    pub fn write_i16(&self, samples: &[i16], n_channels: u16, sample_rate: u32) {
+        let mut inner = self.inner();
+        let max_latency = inner.max_latency;
+        for connection in &mut inner.connections {
+            let (Some(sender), Some(stream)) = (connection.sender.as_ref(), connection.stream.as_mut()) else {
+                continue;
+            };
+
+            stream
+                .resampler
+                .process(samples, n_channels, sample_rate, &mut stream.pending);
+            if let Err(error) = stream.send_waves(sender, max_latency) {
+                warn!(%error, "Failed to send audio");
+            }
+        }
+    }
+
+    /// Writes interleaved floating-point samples, between -1.0 and 1.0
+    pub fn write_f32(&self, samples: &[f32], n_channels: u16, sample_rate: u32) {
+        #[allow(clippy::cast_possible_truncation)] // the value is clamped to the range of i16
+        let samples: Vec<i16> = samples
+            .iter()
+            .map(|sample| (sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16)
+            .collect();
+
+        self.write_i16(&samples, n_channels, sample_rate);
+    }
+
+    fn inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().expect("audio output lock poisoned")
+    }
+}
+
+impl Default for AudioOutput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Inner {
+    fn first_stream(&self) -> Option<&Stream> {
+        self.connections
+            .iter()
+            .find_map(|connection| connection.stream.as_ref())
+    }
+}
+
+impl ServerEventSender for AudioOutput {
+    fn set_sender(&mut self, sender: mpsc::UnboundedSender<ServerEvent>) {
+        self.sender = Some(sender);
+    }
+}
+
+impl SoundServerFactory for AudioOutput {
+    fn build_backend(&self) -> Box<dyn RdpsndServerHandler> {
+        let mut formats = Vec::new();
+        #[cfg(feature = "opus")]
+        formats.push(opus_format(2, 48000));
+        formats.extend(codec::supported_formats(2, 44100));
+
+        let mut inner = self.inner();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.connections.push(Connection {
+            id,
+            sender: self.sender.clone(),
+            stream: None,
+        });
+
+        Box::new(AudioOutputHandler {
+            inner: Arc::clone(&self.inner),
+            id,
+            formats,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct AudioOutputHandler {
+    inner: Arc<Mutex<Inner>>,
+    /// Identifier of the connection of the handler
+    id: u64,
+    formats: Vec<AudioFormat>,
+}
+
+impl AudioOutputHandler {
+    fn with_stream(&self, f: impl FnOnce(&mut Option<Stream>)) {
+        let mut inner = self.inner.lock().expect("audio output lock poisoned");
+        if let Some(connection) = inner.connections.iter_mut().find(|connection| connection.id == self.id) {
+            f(&mut connection.stream);
+        }
+    }
+}
+
+impl RdpsndServerHandler for AudioOutputHandler {
+    fn get_formats(&self) -> &[AudioFormat] {
+        &self.formats
+    }
+
+    fn start(&mut self, client_format: &ClientAudioFormatPdu) -> Option<u16> {
+        let Some((format_no, format)) = client_format
+            .formats
+            .iter()
+            .zip(0..)
+            .filter_map(|(format, format_no)| Some((format_no, format, format_rank(format)?)))
+            .max_by_key(|(_, _, rank)| *rank)
+            .map(|(format_no, format, _)| (format_no, format.clone()))
+        else {
+            warn!(formats = ?client_format.formats, "No supported audio format");
+            return None;
+        };
+
+        let encoder = match Encoder::new(&format) {
+            Ok(encoder) => encoder,
+            Err(error) => {
+                warn!(%error, ?format, "Failed to create the audio encoder");
+                return None;
+            }
+        };
+
+        debug!(?format, "Starting audio output");
+        self.with_stream(|stream| *stream = Some(Stream::new(format, encoder)));
+
+        Some(format_no)
+    }
+
+    fn wave_sent(&mut self, block_no: u8, ts: u32) {
+        self.with_stream(|stream| {
+            if let Some(stream) = stream {
+                stream.wave_sent(block_no, ts);
+            }
+        });
+    }
+
+    fn wave_confirm(&mut self, block_no: u8, latency: Duration) {
+        self.with_stream(|stream| {
+            if let Some(stream) = stream {
+                stream.wave_confirm(block_no, latency);
+            }
+        });
+    }
+
+    fn stop(&mut self) {
+        self.with_stream(|stream| *stream = None);
+    }
+}
+
+impl Drop for AudioOutputHandler {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.connections.retain(|connection| connection.id != self.id);
+        }
+    }
+}
+
+/// Preference of the formats: the codec, then the sample rate and the number of channels
+fn format_rank(format: &AudioFormat) -> Option<(u8, u32, u16)> {
+    let codec = match format.format {
+        #[cfg(feature = "opus")]
+        WaveFormat::OPUS if is_opus_supported(format) => 4,
+        _ if !codec::is_supported(format) => return None,
+        WaveFormat::ADPCM | WaveFormat::DVI_ADPCM => 3,
+        WaveFormat::PCM if format.bits_per_sample == 16 => 2,
+        WaveFormat::ALAW | WaveFormat::MULAW => 1,
+        _ => 0,
+    };
+
+    Some((codec, format.n_samples_per_sec, format.n_channels))
+}
+
+#[cfg(feature = "opus")]
+fn opus_format(n_channels: u16, n_samples_per_sec: u32) -> AudioFormat {
+    AudioFormat {
+        format: WaveFormat::OPUS,
+        n_channels,
+        n_samples_per_sec,
+        n_avg_bytes_per_sec: n_samples_per_sec * u32::from(n_channels) * 2,
+        n_block_align: n_channels * 2,
+        bits_per_sample: 16,
+        data: None,
+    }
+}
+
+#[cfg(feature = "opus")]
+fn is_opus_supported(format: &AudioFormat) -> bool {
+    matches!(format.n_channels, 1 | 2) && matches!(format.n_samples_per_sec, 8000 | 12000 | 16000 | 24000 | 48000)
+}
+
+enum Encoder {
+    #[cfg(feature = "opus")]
+    Opus(opus::Encoder),
+    Codec(AudioEncoder),
+}
+
+impl fmt::Debug for Encoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "opus")]
+            Self::Opus(_) => f.write_str("Opus"),
+            Self::Codec(_) => f.write_str("Codec"),
+        }
+    }
+}
+
+impl Encoder {
+    fn new(format: &AudioFormat) -> Result<Self> {
+        #[cfg(feature = "opus")]
+        if format.format == WaveFormat::OPUS {
+            let channels = if format.n_channels == 1 {
+                opus::Channels::Mono
+            } else {
+                opus::Channels::Stereo
+            };
+            let encoder = opus::Encoder::new(format.n_samples_per_sec, channels, opus::Application::Audio)?;
+            return Ok(Self::Opus(encoder));
+        }
+
+        Ok(Self::Codec(AudioEncoder::new(format)?))
+    }
+
+    fn encode(&mut self, samples: &[i16], dst: &mut Vec<u8>) -> Result<()> {
+        match self {
+            #[cfg(feature = "opus")]
+            Self::Opus(encoder) => dst.extend(encoder.encode_vec(samples, MAX_OPUS_PACKET_SIZE)?),
+            Self::Codec(encoder) => encoder.encode(samples, dst),
+        }
+
+        Ok(())
+    }
+}
+
+/// Wave sent to the client, not yet confirmed
+#[derive(Debug)]
+struct InFlightWave {
+    /// Audio timestamp of the wave
+    ts: u32,
+    /// Block number of the wave, once sent by the rdpsnd channel
+    block_no: Option<u8>,
+    sent: Instant,
+}
+
+/// Audio stream of the connected client
+#[derive(Debug)]
+struct Stream {
+    format: AudioFormat,
+    encoder: Encoder,
+    resampler: Resampler,
+    /// Resampled samples, not yet encoded
+    pending: Vec<i16>,
+    /// Number of samples encoded in each wave
+    wave_samples: usize,
+    /// Number of frames encoded, for the timestamps of the waves
+    frames: u64,
+    /// Waves not yet confirmed, in the order they were sent
+    in_flight: VecDeque<InFlightWave>,
+    latency: Option<Duration>,
+}
+
+impl Stream {
+    fn new(format: AudioFormat, encoder: Encoder) -> Self {
+        let wave_frames = u64::from(format.n_samples_per_sec) * u64::from(WAVE_DURATION_MS) / 1000;
+        let wave_frames = usize::try_from(wave_frames).expect("small value").max(1);
+
+        Self {
+            resampler: Resampler::new(format.n_channels, format.n_samples_per_sec),
+            wave_samples: wave_frames * usize::from(format.n_channels),
+            format,
+            encoder,
+            pending: Vec::new(),
+            frames: 0,
+            in_flight: VecDeque::new(),
+            latency: None,
+        }
+    }
+
+    /// Audio timestamp of the next wave, in milliseconds
+    fn timestamp(&self) -> u32 {
+        let ms = self.frames * 1000 / u64::from(self.format.n_samples_per_sec);
+        u32::try_from(ms % (1 << 32)).expect("less than 2^32")
+    }
+
+    fn wave_sent(&mut self, block_no: u8, ts: u32) {
+        let Some(position) = self
+            .in_flight
+            .iter()
+            .position(|wave| wave.block_no.is_none() && wave.ts == ts)
+        else {
+            return;
+        };
+        self.in_flight[position].block_no = Some(block_no);
+
+        // The waves queued before this one and never sent were dropped by the server.
+        let mut index = 0;
+        self.in_flight.retain(|wave| {
+            index += 1;
+            index > position || wave.block_no.is_some()
+        });
+    }
+
+    fn wave_confirm(&mut self, block_no: u8, latency: Duration) {
+        let Some(position) = self.in_flight.iter().position(|wave| wave.block_no == Some(block_no)) else {
+            debug!(block_no, "Confirmation of an unknown wave");
+            return;
+        };
+
+        // The waves sent before the confirmed one are considered lost.
+        self.in_flight.drain(..=position);
+        self.latency = Some(latency);
+    }
+
+    /// Whether the client is too far behind to send more audio
+    fn is_behind(&mut self, now: Instant, max_latency: Duration) -> bool {
+        while self
+            .in_flight
+            .front()
+            .is_some_and(|wave| now.duration_since(wave.sent) > LOST_WAVE_TIMEOUT)
+        {
+            self.in_flight.pop_front();
+        }
+
+        // The client doesn't confirm the waves.
+        let Some(latency) = self.latency else {
+            return false;
+        };
+
+        // Once all the waves are confirmed, the latency is measured again.
+        let Some(oldest) = self.in_flight.front() else {
+            return false;
+        };
+
+        latency.max(now.duration_since(oldest.sent)) > max_latency
+    }
+
+    fn send_waves(&mut self, sender: &mpsc::UnboundedSender<ServerEvent>, max_latency: Duration) -> Result<()> {
+        let n_channels = usize::from(self.format.n_channels);
+
+        while self.pending.len() >= self.wave_samples {
+            let samples: Vec<i16> = self.pending.drain(..self.wave_samples).collect();
+            let ts = self.timestamp();
+            self.frames += u64::try_from(samples.len() / n_channels)?;
+
+            // ADPCM is encoded by blocks, which may be longer than a wave.
+            let mut data = Vec::new();
+            self.encoder.encode(&samples, &mut data)?;
+            if data.is_empty() {
+                continue;
+            }
+
+            let now = Instant::now();
+            if self.is_behind(now, max_latency) {
+                debug!(latency = ?self.latency, "Dropping late audio");
+                continue;
+            }
+
+            self.in_flight.push_back(InFlightWave {
+                ts,
+                block_no: None,
+                sent: now,
+            });
+            sender.send(ServerEvent::Rdpsnd(RdpsndServerMessage::Wave(data, ts)))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Converts the channels and the sample rate of the audio, with a linear interpolation
+#[derive(Debug)]
+struct Resampler {
+    n_channels: u16,
+    sample_rate: u32,
+    /// Channels and sample rate of the input, which resets the resampler when changed
+    input: Option<(u16, u32)>,
+    /// Position of the next output frame in the input, where 0 is the last frame of the previous input
+    position: f64,
+    previous: Vec<i16>,
+}
+
+impl Resampler {
+    fn new(n_channels: u16, sample_rate: u32) -> Self {
+        Self {
+            n_channels,
+            sample_rate,
+            input: None,
+            position: 1.0,
+            previous: vec![0; usize::from(n_channels)],
+        }
+    }
+
+    fn process(&mut self, samples: &[i16], n_channels: u16, sample_rate: u32, dst: &mut Vec<i16>) {
+        if n_channels == 0 || sample_rate == 0 {
+            return;
+        }
+
+        if self.input != Some((n_channels, sample_rate)) {
+            self.input = Some((n_channels, sample_rate));
+            self.position = 1.0;
+            self.previous.fill(0);
+        }
+
+        let remixed = self.remix(samples, usize::from(n_channels));
+        if sample_rate == self.sample_rate {
+            dst.extend_from_slice(&remixed);
+            return;
+        }
+
+        let n_channels = usize::from(self.n_channels);
+        let frames = remixed.len() / n_channels;
+        let frame = |index: usize| {
+            if index == 0 {
+                &self.previous[..]
+            } else {
+                &remixed[(index - 1) * n_channels..index * n_channels]
+            }
+        };
+
+        let step = f64::from(sample_rate) / f64::from(self.sample_rate);
+        #[allow(clippy::cast_precision_loss)] // the number of frames is small
+        let end = frames as f64;
+        while self.position < end {
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // positive and less than `frames`
+            let index = self.position as usize;
+            #[allow(clippy::cast_precision_loss)]
+            let fraction = self.position - index as f64;
+
+            for (a, b) in frame(index).iter().zip(frame(index + 1)) {
+                let (a, b) = (f64::from(*a), f64::from(*b));
+                #[allow(clippy::cast_possible_truncation)] // between two i16 values
+                dst.push((a + (b - a) * fraction).round() as i16);
+            }
+
+            self.position += step;
+        }
+
+        self.position -= end;
+        if let Some(last) = remixed.chunks_exact(n_channels).last() {
+            self.previous.copy_from_slice(last);
+        }
+    }
+
+    /// Converts the samples to the number of channels of the output
+    fn remix(&self, samples: &[i16], n_channels: usize) -> Vec<i16> {
+        let out_channels = usize::from(self.n_channels);
+        if n_channels == out_channels {
+            return samples.to_vec();
+        }
+
+        let mut remixed = Vec::with_capacity(samples.len() / n_channels * out_channels);
+        for frame in samples.chunks_exact(n_channels) {
+            if out_channels == 1 {
+                let sum: i32 = frame.iter().copied().map(i32::from).sum();
+                let n_channels = i32::try_from(n_channels).expect("u16 value");
+                remixed.push(i16::try_from(sum / n_channels).expect("average of i16 values"));
+            } else {
+                remixed.extend((0..out_channels).map(|channel| frame[channel.min(n_channels - 1)]));
+            }
+        }
+
+        remixed
+    }
+}
+
+#[cfg(all(test, feature = "opus"))]
+mod tests {
+    use ironrdp_rdpsnd::pdu::{AudioFormatFlags, Version};
+
+    use super::*;
+
+    #[test]
+    fn opus_is_preferred() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut output = AudioOutput::new();
+        output.set_sender(tx);
+
+        let mut handler = output.build_backend();
+        let client_format = ClientAudioFormatPdu {
+            version: Version::V8,
+            flags: AudioFormatFlags::ALIVE,
+            formats: vec![
+                codec::pcm_format(2, 48000),
+                opus_format(2, 48000),
+                codec::ima_adpcm_format(2, 44100),
+            ],
+            volume_left: 0xFFFF,
+            volume_right: 0xFFFF,
+            pitch: 0x00010000,
+            dgram_port: 0,
+        };
+        assert_eq!(handler.start(&client_format), Some(1));
+        assert_eq!(output.format(), Some(opus_format(2, 48000)));
+
+        // 100 ms of a stereo 440 Hz sine wave, encoded in packets of 20 ms.
+        let samples: Vec<i16> = (0..4800)
+            .flat_map(|i| {
+                #[allow(clippy::cast_possible_truncation)] // the amplitude fits in i16
+                let sample = (f64::sin(2.0 * core::f64::consts::PI * 440.0 * f64::from(i) / 48000.0) * 10000.0) as i16;
+                [sample, -sample]
+            })
+            .collect();
+        output.write_i16(&samples, 2, 48000);
+
+        let mut decoder = opus::Decoder::new(48000, opus::Channels::Stereo).unwrap();
+        let mut timestamps = Vec::new();
+        while let Ok(ServerEvent::Rdpsnd(RdpsndServerMessage::Wave(data, ts))) = rx.try_recv() {
+            let mut pcm = [0; 2 * 960];
+            assert_eq!(decoder.decode(&data, &mut pcm, false).unwrap(), 960);
+            timestamps.push(ts);
+        }
+        assert_eq!(timestamps, [0, 20, 40, 60, 80]);
+    }
+}
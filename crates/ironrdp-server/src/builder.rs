@@ -1,4 +1,5 @@
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 use anyhow::Result;
 use tokio_rustls::TlsAcceptor;
@@ -7,7 +8,8 @@ use super::clipboard::CliprdrServerFactory;
 use super::display::{DesktopSize, RdpServerDisplay};
 use super::handler::{KeyboardEvent, MouseEvent, RdpServerInputHandler};
 use super::server::*;
-use crate::{DisplayUpdate, RdpServerDisplayUpdates, SoundServerFactory};
+use super::session::RdpServerHandlerFactory;
+use crate::{AudioInputServerFactory, DisplayUpdate, RdpServerDisplayUpdates, RdpdrServerFactory, SoundServerFactory};
 
 pub struct WantsAddr {}
 pub struct WantsSecurity {
@@ -26,10 +28,17 @@ pub struct BuilderDone {
     addr: SocketAddr,
     security: RdpServerSecurity,
     with_remote_fx: bool,
+    with_gfx: bool,
     handler: Box<dyn RdpServerInputHandler>,
     display: Box<dyn RdpServerDisplay>,
+    handler_factory: Option<Box<dyn RdpServerHandlerFactory>>,
+    max_sessions: Option<usize>,
+    auth_backend: Option<Arc<dyn AuthBackend>>,
+    kerberos_config: Option<KerberosConfig>,
     cliprdr_factory: Option<Box<dyn CliprdrServerFactory>>,
     sound_factory: Option<Box<dyn SoundServerFactory>>,
+    rdpdr_factory: Option<Box<dyn RdpdrServerFactory>>,
+    audio_input_factory: Option<Box<dyn AudioInputServerFactory>>,
 }
 
 pub struct RdpServerBuilder<State> {
@@ -109,6 +118,31 @@ impl RdpServerBuilder<WantsHandler> {
             },
         }
     }
+
+    /// Serves the client connections concurrently, with handlers built for each connection
+    pub fn with_handler_factory<F>(self, factory: F) -> RdpServerBuilder<BuilderDone>
+    where
+        F: RdpServerHandlerFactory + 'static,
+    {
+        RdpServerBuilder {
+            state: BuilderDone {
+                addr: self.state.addr,
+                security: self.state.security,
+                handler: Box::new(NoopInputHandler),
+                display: Box::new(NoopDisplay),
+                handler_factory: Some(Box::new(factory)),
+                max_sessions: None,
+                auth_backend: None,
+                kerberos_config: None,
+                sound_factory: None,
+                cliprdr_factory: None,
+                rdpdr_factory: None,
+                audio_input_factory: None,
+                with_remote_fx: true,
+                with_gfx: true,
+            },
+        }
+    }
 }
 
 impl RdpServerBuilder<WantsDisplay> {
@@ -122,9 +156,16 @@ impl RdpServerBuilder<WantsDisplay> {
                 security: self.state.security,
                 handler: self.state.handler,
                 display: Box::new(display),
+                handler_factory: None,
+                max_sessions: None,
+                auth_backend: None,
+                kerberos_config: None,
                 sound_factory: None,
                 cliprdr_factory: None,
+                rdpdr_factory: None,
+                audio_input_factory: None,
                 with_remote_fx: true,
+                with_gfx: true,
             },
         }
     }
@@ -136,9 +177,16 @@ impl RdpServerBuilder<WantsDisplay> {
                 security: self.state.security,
                 handler: self.state.handler,
                 display: Box::new(NoopDisplay),
+                handler_factory: None,
+                max_sessions: None,
+                auth_backend: None,
+                kerberos_config: None,
                 sound_factory: None,
                 cliprdr_factory: None,
+                rdpdr_factory: None,
+                audio_input_factory: None,
                 with_remote_fx: true,
+                with_gfx: true,
             },
         }
     }
@@ -155,27 +203,84 @@ impl RdpServerBuilder<BuilderDone> {
         self
     }
 
+    /// Redirects the drives of the clients, see [`ClientDevices`](crate::ClientDevices)
+    pub fn with_rdpdr_factory(mut self, rdpdr: Option<Box<dyn RdpdrServerFactory>>) -> Self {
+        self.state.rdpdr_factory = rdpdr;
+        self
+    }
+
+    /// Receives the audio captured by the clients, e.g. their microphone
+    pub fn with_audio_input_factory(mut self, audio_input: Option<Box<dyn AudioInputServerFactory>>) -> Self {
+        self.state.audio_input_factory = audio_input;
+        self
+    }
+
     pub fn with_remote_fx(mut self, enabled: bool) -> Self {
         self.state.with_remote_fx = enabled;
         self
     }
 
+    /// See [`RdpServerOptions::with_gfx`]
+    pub fn with_gfx(mut self, enabled: bool) -> Self {
+        self.state.with_gfx = enabled;
+        self
+    }
+
+    /// See [`RdpServerOptions::max_sessions`]
+    pub fn with_max_sessions(mut self, max_sessions: usize) -> Self {
+        self.state.max_sessions = Some(max_sessions);
+        self
+    }
+
+    /// See [`RdpServerOptions::auth_backend`]
+    pub fn with_auth_backend<B>(mut self, backend: B) -> Self
+    where
+        B: AuthBackend + 'static,
+    {
+        self.state.auth_backend = Some(Arc::new(backend));
+        self
+    }
+
+    /// See [`RdpServerOptions::kerberos_config`]
+    pub fn with_kerberos_config(mut self, config: KerberosConfig) -> Self {
+        self.state.kerberos_config = Some(config);
+        self
+    }
+
     pub fn build(self) -> RdpServer {
-        RdpServer::new(
+        let mut server = RdpServer::new(
             RdpServerOptions {
                 addr: self.state.addr,
                 security: self.state.security,
                 with_remote_fx: self.state.with_remote_fx,
+                with_gfx: self.state.with_gfx,
+                max_sessions: self.state.max_sessions,
+                auth_backend: self.state.auth_backend,
+                kerberos_config: self.state.kerberos_config,
             },
             self.state.handler,
             self.state.display,
             self.state.sound_factory,
             self.state.cliprdr_factory,
-        )
+        );
+
+        if let Some(factory) = self.state.handler_factory {
+            server.set_handler_factory(factory);
+        }
+
+        if let Some(factory) = self.state.rdpdr_factory {
+            server.set_rdpdr_factory(factory);
+        }
+
+        if let Some(factory) = self.state.audio_input_factory {
+            server.set_audio_input_factory(factory);
+        }
+
+        server
     }
 }
 
-struct NoopInputHandler;
+pub(crate) struct NoopInputHandler;
 
 impl RdpServerInputHandler for NoopInputHandler {
     fn keyboard(&mut self, _: KeyboardEvent) {}
@@ -192,7 +297,7 @@ impl RdpServerDisplayUpdates for NoopDisplayUpdates {
     }
 }
 
-struct NoopDisplay;
+pub(crate) struct NoopDisplay;
 
 #[async_trait::async_trait]
 impl RdpServerDisplay for NoopDisplay {
@@ -1,5 +1,28 @@
-use ironrdp_cliprdr::backend::CliprdrBackendFactory;
+use ironrdp_cliprdr::backend::{ClipboardMessage, ClipboardMessageProxy, CliprdrBackendFactory};
+use ironrdp_cliprdr::memory::MemoryClipboard;
+use tokio::sync::mpsc;
 
-use crate::ServerEventSender;
+use crate::{ServerEvent, ServerEventSender};
 
-pub trait CliprdrServerFactory: CliprdrBackendFactory + ServerEventSender {}
+pub trait CliprdrServerFactory: CliprdrBackendFactory + ServerEventSender + Send {}
+
+#[derive(Debug)]
+struct ServerClipboardMessageProxy {
+    sender: mpsc::UnboundedSender<ServerEvent>,
+}
+
+impl ClipboardMessageProxy for ServerClipboardMessageProxy {
+    fn send_clipboard_message(&self, message: ClipboardMessage) {
+        if self.sender.send(ServerEvent::Clipboard(message)).is_err() {
+            warn!("Failed to send clipboard message, server is closed");
+        }
+    }
+}
+
+impl ServerEventSender for MemoryClipboard {
+    fn set_sender(&mut self, sender: mpsc::UnboundedSender<ServerEvent>) {
+        self.set_message_proxy(ServerClipboardMessageProxy { sender });
+    }
+}
+
+impl CliprdrServerFactory for MemoryClipboard {}
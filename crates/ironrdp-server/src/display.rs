@@ -2,10 +2,12 @@ use core::num::NonZeroU16;
 
 use anyhow::Result;
 use ironrdp_displaycontrol::pdu::DisplayControlMonitorLayout;
+use ironrdp_pdu::geometry::InclusiveRectangle;
 use ironrdp_pdu::pointer::PointerPositionAttribute;
+use ironrdp_pdu::rdp::vc::dvc::gfx::QuantQuality;
 
 #[rustfmt::skip]
-pub use ironrdp_acceptor::DesktopSize;
+pub use ironrdp_acceptor::{ClientLogon, DesktopSize};
 pub use ironrdp_graphics::image_processing::PixelFormat;
 
 /// Display Update
@@ -22,6 +24,10 @@ pub enum DisplayUpdate {
     RGBAPointer(RGBAPointer),
     HidePointer,
     DefaultPointer,
+    /// Pre-encoded H.264 frame, only sent on the graphics pipeline
+    Avc420(Avc420Update),
+    /// Pre-encoded H.264 frames, only sent on the graphics pipeline
+    Avc444(Avc444Update),
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -91,6 +97,73 @@ impl core::fmt::Debug for BitmapUpdate {
     }
 }
 
+/// H.264 frame, sent with the AVC420 codec of the graphics pipeline ([MS-RDPEGFX] 2.2.4.4)
+///
+/// [MS-RDPEGFX]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegfx/da5c75f9-cd99-450c-98c4-014a496942b0
+#[derive(Debug, Clone)]
+pub struct Avc420Update {
+    /// Position of the frame on the desktop
+    pub top: u16,
+    pub left: u16,
+    pub width: NonZeroU16,
+    pub height: NonZeroU16,
+    pub stream: AvcStream,
+}
+
+/// H.264 frames, sent with the AVC444 codec of the graphics pipeline ([MS-RDPEGFX] 2.2.4.5)
+///
+/// [MS-RDPEGFX]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegfx/da5c75f9-cd99-450c-98c4-014a496942b0
+#[derive(Debug, Clone)]
+pub struct Avc444Update {
+    /// Position of the frames on the desktop
+    pub top: u16,
+    pub left: u16,
+    pub width: NonZeroU16,
+    pub height: NonZeroU16,
+    pub streams: Avc444Streams,
+}
+
+/// Streams of an AVC444 update, the luma and chroma frames may be sent separately
+#[derive(Debug, Clone)]
+pub enum Avc444Streams {
+    LumaAndChroma { luma: AvcStream, chroma: AvcStream },
+    Luma(AvcStream),
+    Chroma(AvcStream),
+}
+
+/// H.264 bitstream, with its metadata
+#[derive(Clone)]
+pub struct AvcStream {
+    /// Bitstream in Annex B format
+    pub data: Vec<u8>,
+    /// Regions of the frame updated by the bitstream
+    pub regions: Vec<AvcRegion>,
+}
+
+impl core::fmt::Debug for AvcStream {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AvcStream")
+            .field("data_len", &self.data.len())
+            .field("regions", &self.regions)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AvcRegion {
+    /// Rectangle relative to the top-left corner of the frame
+    pub rectangle: InclusiveRectangle,
+    /// Encoding quality of the region
+    pub quant_quality: QuantQuality,
+}
+
+/// H.264 codecs confirmed by the client on the graphics pipeline
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AvcSupport {
+    pub avc420: bool,
+    pub avc444: bool,
+}
+
 /// Display Updates receiver for an RDP server
 ///
 /// The RDP server will repeatedly call the `next_update` method to receive
@@ -98,13 +171,21 @@ impl core::fmt::Debug for BitmapUpdate {
 ///
 /// See [`RdpServerDisplay`] example.
 #[async_trait::async_trait]
-pub trait RdpServerDisplayUpdates {
+pub trait RdpServerDisplayUpdates: Send {
     /// # Cancel safety
     ///
     /// This method MUST be cancellation safe because it is used in a
     /// `tokio::select!` statement. If some other branch completes first, it
     /// MUST be guaranteed that no data is lost.
     async fn next_update(&mut self) -> Option<DisplayUpdate>;
+
+    /// Called when an AVC update is dropped, because the client did not confirm its codec
+    ///
+    /// `support` are the H.264 codecs the client accepts, if any. Other updates, e.g.
+    /// [`DisplayUpdate::Bitmap`], should be sent instead.
+    fn avc_unsupported(&mut self, support: AvcSupport) {
+        warn!(?support, "AVC update dropped, the client does not support it");
+    }
 }
 
 /// Display for an RDP server
@@ -152,6 +233,14 @@ pub trait RdpServerDisplay: Send {
     /// Return a display updates receiver
     async fn updates(&mut self) -> Result<Box<dyn RdpServerDisplayUpdates>>;
 
+    /// Called when a client has logged on, before the display is queried
+    ///
+    /// The logon carries the user credentials, delegated with CredSSP, and the requested
+    /// alternate shell and working directory, to start the session of the user.
+    async fn logon(&mut self, logon: &ClientLogon) {
+        debug!(?logon, "Client logged on")
+    }
+
     /// Request a new size for the display
     fn request_layout(&mut self, layout: DisplayControlMonitorLayout) {
         debug!(?layout, "Requesting layout")
@@ -0,0 +1,471 @@
+use core::num::NonZeroU16;
+use core::ops::Range;
+use core::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::{BitmapUpdate, DesktopSize, PixelFormat, PixelOrder};
+
+/// Bitmaps are compared by tiles of 64x64 pixels, like RemoteFX tiles.
+const TILE_SIZE: usize = 64;
+
+/// Tiles of the bitmap updates, since the server started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DamageStats {
+    /// Tiles which changed, and were encoded
+    pub encoded_tiles: u64,
+    /// Tiles identical to the content of the client, which were not encoded
+    pub skipped_tiles: u64,
+}
+
+/// Tile counters, shared by the connections of the server
+#[derive(Debug, Default)]
+pub(crate) struct DamageCounters {
+    encoded_tiles: AtomicU64,
+    skipped_tiles: AtomicU64,
+}
+
+impl DamageCounters {
+    pub(crate) fn stats(&self) -> DamageStats {
+        DamageStats {
+            encoded_tiles: self.encoded_tiles.load(Ordering::Relaxed),
+            skipped_tiles: self.skipped_tiles.load(Ordering::Relaxed),
+        }
+    }
+
+    fn add(&self, encoded_tiles: usize, skipped_tiles: usize) {
+        let to_u64 = |count: usize| u64::try_from(count).unwrap_or(u64::MAX);
+
+        self.encoded_tiles.fetch_add(to_u64(encoded_tiles), Ordering::Relaxed);
+        self.skipped_tiles.fetch_add(to_u64(skipped_tiles), Ordering::Relaxed);
+    }
+}
+
+/// Keeps the content of the client, to only encode the parts of the bitmaps which changed
+pub(crate) struct DamageTracker {
+    width: usize,
+    height: usize,
+    format: Option<PixelFormat>,
+    /// Top-down copy of the desktop, as sent to the client
+    framebuffer: Vec<u8>,
+    /// Whether each tile of the desktop grid is in the framebuffer, which is the case once it was
+    /// entirely covered by a bitmap
+    known_tiles: Vec<bool>,
+    counters: Arc<DamageCounters>,
+}
+
+impl DamageTracker {
+    pub(crate) fn new(desktop_size: DesktopSize, counters: Arc<DamageCounters>) -> Self {
+        let width = usize::from(desktop_size.width);
+        let height = usize::from(desktop_size.height);
+
+        Self {
+            width,
+            height,
+            format: None,
+            framebuffer: Vec::new(),
+            known_tiles: vec![false; width.div_ceil(TILE_SIZE) * height.div_ceil(TILE_SIZE)],
+            counters,
+        }
+    }
+
+    /// Forgets the content of the client, e.g. when its surface is recreated
+    pub(crate) fn reset(&mut self) {
+        self.known_tiles.fill(false);
+    }
+
+    /// Forgets the content of the client overlapped by a rectangle, e.g. when it was updated with
+    /// frames which are not tracked
+    pub(crate) fn invalidate(&mut self, left: u16, top: u16, width: NonZeroU16, height: NonZeroU16) {
+        let clamp = |start: u16, length: NonZeroU16, size: usize| {
+            let start = usize::from(start);
+            start.min(size)..(start + usize::from(length.get())).min(size)
+        };
+
+        let x_range = clamp(left, width, self.width);
+        let y_range = clamp(top, height, self.height);
+        if x_range.is_empty() || y_range.is_empty() {
+            return;
+        }
+
+        let tiles: Vec<usize> = self.tiles(x_range, y_range).collect();
+        for tile in tiles {
+            self.known_tiles[tile] = false;
+        }
+    }
+
+    /// Returns the parts of the bitmap which changed
+    ///
+    /// The bitmap is split into tiles from its top-left corner, and the adjacent tiles which
+    /// changed are merged into rectangles. The bitmap is returned as is when all of its tiles
+    /// changed.
+    pub(crate) fn damaged(&mut self, bitmap: BitmapUpdate) -> Vec<BitmapUpdate> {
+        let bytes_per_pixel = usize::from(bitmap.format.bytes_per_pixel());
+        let left = usize::from(bitmap.left);
+        let top = usize::from(bitmap.top);
+        let width = usize::from(bitmap.width.get());
+        let height = usize::from(bitmap.height.get());
+
+        let columns = width.div_ceil(TILE_SIZE);
+        let rows = height.div_ceil(TILE_SIZE);
+
+        if left + width > self.width || top + height > self.height {
+            debug!(?bitmap, "Bitmap out of the desktop, damage tracking is reset");
+            self.reset();
+            self.counters.add(columns * rows, 0);
+            return vec![bitmap];
+        }
+
+        if self.format != Some(bitmap.format) {
+            self.format = Some(bitmap.format);
+            self.framebuffer = vec![0; self.width * self.height * bytes_per_pixel];
+            self.reset();
+        }
+
+        let framebuffer_stride = self.width * bytes_per_pixel;
+        let mut changed = vec![false; columns * rows];
+
+        for row in 0..rows {
+            let y_range = row * TILE_SIZE..((row + 1) * TILE_SIZE).min(height);
+
+            for column in 0..columns {
+                let x_range = column * TILE_SIZE..((column + 1) * TILE_SIZE).min(width);
+                let bytes = x_range.start * bytes_per_pixel..x_range.end * bytes_per_pixel;
+
+                let mut tile_changed = !self.is_known(
+                    left + x_range.start..left + x_range.end,
+                    top + y_range.start..top + y_range.end,
+                );
+
+                for y in y_range.clone() {
+                    let source = &bitmap_row(&bitmap, y)[bytes.clone()];
+                    let offset = (top + y) * framebuffer_stride + left * bytes_per_pixel;
+                    let destination = &mut self.framebuffer[offset + bytes.start..offset + bytes.end];
+
+                    if destination != source {
+                        destination.copy_from_slice(source);
+                        tile_changed = true;
+                    }
+                }
+
+                changed[row * columns + column] = tile_changed;
+            }
+        }
+
+        self.mark_known(left..left + width, top..top + height);
+
+        let encoded_tiles = changed.iter().filter(|changed| **changed).count();
+        self.counters.add(encoded_tiles, changed.len() - encoded_tiles);
+
+        if encoded_tiles == changed.len() {
+            return vec![bitmap];
+        }
+
+        merge_tiles(&changed, columns)
+            .into_iter()
+            .map(|rectangle| {
+                let x_range = rectangle.left * TILE_SIZE..(rectangle.right * TILE_SIZE).min(width);
+                let y_range = rectangle.top * TILE_SIZE..(rectangle.bottom * TILE_SIZE).min(height);
+                sub_bitmap(&bitmap, x_range, y_range)
+            })
+            .collect()
+    }
+
+    /// Returns the desktop tiles overlapped by the pixel ranges
+    fn tiles(&self, x_range: Range<usize>, y_range: Range<usize>) -> impl Iterator<Item = usize> + '_ {
+        let columns = self.width.div_ceil(TILE_SIZE);
+
+        (y_range.start / TILE_SIZE..y_range.end.div_ceil(TILE_SIZE)).flat_map(move |row| {
+            (x_range.start / TILE_SIZE..x_range.end.div_ceil(TILE_SIZE)).map(move |column| row * columns + column)
+        })
+    }
+
+    fn is_known(&self, x_range: Range<usize>, y_range: Range<usize>) -> bool {
+        self.tiles(x_range, y_range).all(|tile| self.known_tiles[tile])
+    }
+
+    /// Marks the desktop tiles entirely covered by the pixel ranges as known
+    fn mark_known(&mut self, x_range: Range<usize>, y_range: Range<usize>) {
+        // Tiles at the right and bottom edges of the desktop may be smaller.
+        let covered = |range: &Range<usize>, size: usize| {
+            let start = range.start.div_ceil(TILE_SIZE) * TILE_SIZE;
+            let end = if range.end == size {
+                range.end
+            } else {
+                range.end / TILE_SIZE * TILE_SIZE
+            };
+            start..end.max(start)
+        };
+
+        let x_range = covered(&x_range, self.width);
+        let y_range = covered(&y_range, self.height);
+
+        let tiles: Vec<usize> = self.tiles(x_range, y_range).collect();
+        for tile in tiles {
+            self.known_tiles[tile] = true;
+        }
+    }
+}
+
+/// Rectangle of tiles, right and bottom bounds are exclusive
+#[derive(Debug, PartialEq, Eq)]
+struct TileRectangle {
+    left: usize,
+    top: usize,
+    right: usize,
+    bottom: usize,
+}
+
+/// Merges the changed tiles of each row, then the rows spanning the same columns
+fn merge_tiles(changed: &[bool], columns: usize) -> Vec<TileRectangle> {
+    let mut rectangles: Vec<TileRectangle> = Vec::new();
+
+    for (row, tiles) in changed.chunks(columns).enumerate() {
+        let mut column = 0;
+
+        while column < columns {
+            if !tiles[column] {
+                column += 1;
+                continue;
+            }
+
+            let start = column;
+            while column < columns && tiles[column] {
+                column += 1;
+            }
+
+            match rectangles
+                .iter_mut()
+                .find(|rectangle| rectangle.bottom == row && rectangle.left == start && rectangle.right == column)
+            {
+                Some(rectangle) => rectangle.bottom = row + 1,
+                None => rectangles.push(TileRectangle {
+                    left: start,
+                    top: row,
+                    right: column,
+                    bottom: row + 1,
+                }),
+            }
+        }
+    }
+
+    rectangles
+}
+
+/// Row of the bitmap, from the top
+fn bitmap_row(bitmap: &BitmapUpdate, y: usize) -> &[u8] {
+    let height = usize::from(bitmap.height.get());
+    let row_len = usize::from(bitmap.width.get()) * usize::from(bitmap.format.bytes_per_pixel());
+
+    let index = match bitmap.order {
+        PixelOrder::TopToBottom => y,
+        PixelOrder::BottomToTop => height - 1 - y,
+    };
+
+    &bitmap.data[index * bitmap.stride..][..row_len]
+}
+
+fn sub_bitmap(bitmap: &BitmapUpdate, x_range: Range<usize>, y_range: Range<usize>) -> BitmapUpdate {
+    let bytes_per_pixel = usize::from(bitmap.format.bytes_per_pixel());
+    let bytes = x_range.start * bytes_per_pixel..x_range.end * bytes_per_pixel;
+
+    let mut data = Vec::with_capacity(bytes.len() * y_range.len());
+    for y in y_range.clone() {
+        data.extend_from_slice(&bitmap_row(bitmap, y)[bytes.clone()]);
+    }
+
+    let to_u16 = |value: usize| u16::try_from(value).expect("sub-bitmap is within the bitmap");
+
+    BitmapUpdate {
+        top: bitmap.top + to_u16(y_range.start),
+        left: bitmap.left + to_u16(x_range.start),
+        width: NonZeroU16::new(to_u16(x_range.len())).expect("sub-bitmap is not empty"),
+        height: NonZeroU16::new(to_u16(y_range.len())).expect("sub-bitmap is not empty"),
+        format: bitmap.format,
+        order: PixelOrder::TopToBottom,
+        data,
+        stride: bytes.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker(width: u16, height: u16) -> DamageTracker {
+        DamageTracker::new(DesktopSize { width, height }, Arc::new(DamageCounters::default()))
+    }
+
+    /// Bitmap whose pixels are `pixel(x, y)`
+    fn bitmap(left: u16, top: u16, width: u16, height: u16, pixel: impl Fn(usize, usize) -> u8) -> BitmapUpdate {
+        let pixel = &pixel;
+        let data = (0..usize::from(height))
+            .flat_map(|y| (0..usize::from(width)).flat_map(move |x| [pixel(x, y); 4]))
+            .collect();
+
+        BitmapUpdate {
+            top,
+            left,
+            width: NonZeroU16::new(width).unwrap(),
+            height: NonZeroU16::new(height).unwrap(),
+            format: PixelFormat::BgrA32,
+            order: PixelOrder::TopToBottom,
+            data,
+            stride: usize::from(width) * 4,
+        }
+    }
+
+    fn position(bitmap: &BitmapUpdate) -> (u16, u16, u16, u16) {
+        (bitmap.left, bitmap.top, bitmap.width.get(), bitmap.height.get())
+    }
+
+    #[test]
+    fn unchanged_tiles_are_skipped() {
+        let mut tracker = tracker(256, 128);
+
+        let first = tracker.damaged(bitmap(0, 0, 256, 128, |_, _| 1));
+        assert_eq!(first.iter().map(position).collect::<Vec<_>>(), [(0, 0, 256, 128)]);
+
+        assert!(tracker.damaged(bitmap(0, 0, 256, 128, |_, _| 1)).is_empty());
+
+        // A single pixel of the third tile of the second row
+        let changed = tracker.damaged(bitmap(0, 0, 256, 128, |x, y| if (x, y) == (130, 70) { 2 } else { 1 }));
+        assert_eq!(changed.iter().map(position).collect::<Vec<_>>(), [(128, 64, 64, 64)]);
+        assert_eq!(changed[0].data[(6 * 64 + 2) * 4], 2);
+
+        let stats = tracker.counters.stats();
+        assert_eq!((stats.encoded_tiles, stats.skipped_tiles), (9, 15));
+    }
+
+    #[test]
+    fn tiles_not_entirely_known_are_sent() {
+        let mut tracker = tracker(256, 128);
+
+        // Covers a part of the first tile only.
+        tracker.damaged(bitmap(0, 0, 32, 32, |_, _| 0));
+
+        let sent = tracker.damaged(bitmap(0, 0, 64, 64, |_, _| 0));
+        assert_eq!(sent.iter().map(position).collect::<Vec<_>>(), [(0, 0, 64, 64)]);
+    }
+
+    #[test]
+    fn invalidated_tiles_are_sent_again() {
+        let mut tracker = tracker(256, 128);
+        tracker.damaged(bitmap(0, 0, 256, 128, |_, _| 1));
+
+        tracker.invalidate(60, 10, NonZeroU16::new(10).unwrap(), NonZeroU16::new(u16::MAX).unwrap());
+
+        let sent = tracker.damaged(bitmap(0, 0, 256, 128, |_, _| 1));
+        assert_eq!(sent.iter().map(position).collect::<Vec<_>>(), [(0, 0, 128, 128)]);
+
+        // Out of the desktop
+        tracker.invalidate(256, 0, NonZeroU16::new(64).unwrap(), NonZeroU16::new(64).unwrap());
+        assert!(tracker.damaged(bitmap(0, 0, 256, 128, |_, _| 1)).is_empty());
+    }
+
+    #[test]
+    fn bitmaps_out_of_the_desktop_are_sent_as_is() {
+        let mut tracker = tracker(128, 128);
+        tracker.damaged(bitmap(0, 0, 128, 128, |_, _| 1));
+
+        let sent = tracker.damaged(bitmap(64, 64, 128, 64, |_, _| 1));
+        assert_eq!(sent.iter().map(position).collect::<Vec<_>>(), [(64, 64, 128, 64)]);
+
+        // The tracking is reset.
+        let sent = tracker.damaged(bitmap(0, 0, 128, 128, |_, _| 1));
+        assert_eq!(sent.iter().map(position).collect::<Vec<_>>(), [(0, 0, 128, 128)]);
+    }
+
+    #[test]
+    fn edge_tiles_are_cut_from_bottom_up_bitmaps() {
+        let mut tracker = tracker(100, 70);
+
+        let pixel = |x: usize, y: usize| u8::try_from((x + y) % 251).unwrap();
+        let mut update = bitmap(0, 0, 100, 70, pixel);
+        // Stored bottom-up, with padding at the end of the rows
+        update.stride = 100 * 4 + 8;
+        update.data = (0..70)
+            .rev()
+            .flat_map(|y| {
+                let mut row: Vec<u8> = (0..100).flat_map(|x| [pixel(x, y); 4]).collect();
+                row.resize(100 * 4 + 8, 0xFF);
+                row
+            })
+            .collect();
+        update.order = PixelOrder::BottomToTop;
+        tracker.damaged(update.clone());
+
+        // Bottom right tile, which is 36x6
+        let index = (70 - 1 - 66) * update.stride + 90 * 4;
+        update.data[index..index + 4].fill(0);
+
+        let changed = tracker.damaged(update);
+        assert_eq!(changed.iter().map(position).collect::<Vec<_>>(), [(64, 64, 36, 6)]);
+
+        let tile = &changed[0];
+        assert_eq!(tile.order, PixelOrder::TopToBottom);
+        assert_eq!(tile.stride, 36 * 4);
+        let expected: Vec<u8> = (64..70)
+            .flat_map(|y| (64..100).flat_map(move |x| [if (x, y) == (90, 66) { 0 } else { pixel(x, y) }; 4]))
+            .collect();
+        assert_eq!(tile.data, expected);
+    }
+
+    #[test]
+    fn adjacent_tiles_are_merged() {
+        #[rustfmt::skip]
+        let changed = [
+            true, true, false,
+            true, true, false,
+            false, false, true,
+        ];
+        assert_eq!(
+            merge_tiles(&changed, 3),
+            [
+                TileRectangle {
+                    left: 0,
+                    top: 0,
+                    right: 2,
+                    bottom: 2
+                },
+                TileRectangle {
+                    left: 2,
+                    top: 2,
+                    right: 3,
+                    bottom: 3
+                },
+            ]
+        );
+
+        // Rows spanning different columns are not merged.
+        #[rustfmt::skip]
+        let changed = [
+            true, false, true,
+            true, true, true,
+        ];
+        assert_eq!(
+            merge_tiles(&changed, 3),
+            [
+                TileRectangle {
+                    left: 0,
+                    top: 0,
+                    right: 1,
+                    bottom: 1
+                },
+                TileRectangle {
+                    left: 2,
+                    top: 0,
+                    right: 3,
+                    bottom: 1
+                },
+                TileRectangle {
+                    left: 0,
+                    top: 1,
+                    right: 3,
+                    bottom: 2
+                },
+            ]
+        );
+
+        assert!(merge_tiles(&[false; 4], 2).is_empty());
+    }
+}
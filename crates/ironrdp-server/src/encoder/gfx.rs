@@ -0,0 +1,269 @@
+use core::num::NonZeroU16;
+
+use anyhow::{Context as _, Result};
+use ironrdp_core::{encode_vec, EncodeErrorKind};
+use ironrdp_graphics::image_processing::{ImageRegion, ImageRegionMut, PixelFormat};
+use ironrdp_graphics::rdp6::{ABgrChannels, ARgbChannels, BgrAChannels, BitmapStreamEncoder, RgbAChannels};
+use ironrdp_pdu::geometry::InclusiveRectangle;
+use ironrdp_pdu::rdp::capability_sets::EntropyBits;
+use ironrdp_pdu::rdp::vc::dvc::gfx::{
+    self, Avc420BitmapStream, Avc444BitmapStream, Codec1Type, Encoding, EndFramePdu, ServerPdu, StartFramePdu,
+    WireToSurface1Pdu,
+};
+
+use super::rfx::RfxEncoder;
+use crate::gfx::{AvcUpdate, GfxFrame, GfxServerMessage};
+use crate::{Avc444Streams, AvcStream, BitmapUpdate, PixelOrder};
+
+/// Encodes the bitmaps sent on the graphics pipeline
+///
+/// Bitmaps are encoded with RemoteFX when enabled, with the planar codec otherwise. Bitmaps that
+/// the planar codec does not compress are sent uncompressed.
+pub(crate) struct GfxEncoder {
+    remotefx: Option<RfxEncoder>,
+}
+
+impl GfxEncoder {
+    pub(crate) fn new(with_remote_fx: bool) -> Self {
+        Self {
+            remotefx: with_remote_fx.then(|| RfxEncoder::new(EntropyBits::Rlgr3)),
+        }
+    }
+
+    /// Encodes the bitmaps into a single frame
+    pub(crate) fn encode(&mut self, frame: GfxFrame, bitmaps: Vec<BitmapUpdate>) -> Result<GfxServerMessage> {
+        let mut wire_to_surface = Vec::with_capacity(bitmaps.len());
+
+        for bitmap in bitmaps {
+            let bitmap = into_top_down(bitmap);
+
+            let (codec_id, bitmap_data) = match self.remotefx.as_mut() {
+                Some(remotefx) => (Codec1Type::RemoteFx, encode_remotefx(remotefx, &bitmap)?),
+                None => match encode_planar(&bitmap) {
+                    Some(data) => (Codec1Type::Planar, data),
+                    None => (Codec1Type::Uncompressed, encode_uncompressed(&bitmap)?),
+                },
+            };
+
+            wire_to_surface.push(WireToSurface1Pdu {
+                surface_id: frame.surface_id,
+                codec_id,
+                pixel_format: gfx::PixelFormat::XRgb,
+                destination_rectangle: rect16(bitmap.left, bitmap.top, bitmap.width, bitmap.height),
+                bitmap_data,
+            });
+        }
+
+        frame_message(frame, wire_to_surface)
+    }
+}
+
+/// Wraps a pre-encoded H.264 update into a frame
+///
+/// The AVC444 streams are sent with the first version of the codec.
+pub(crate) fn encode_avc(frame: GfxFrame, update: &AvcUpdate) -> Result<GfxServerMessage> {
+    let (codec_id, destination_rectangle, bitmap_data) = match update {
+        AvcUpdate::Avc420(update) => (
+            Codec1Type::Avc420,
+            rect16(update.left, update.top, update.width, update.height),
+            encode_vec(&avc420_bitmap_stream(&update.stream)).context("AVC420 bitmap stream")?,
+        ),
+        AvcUpdate::Avc444(update) => {
+            let (encoding, stream1, stream2) = match &update.streams {
+                Avc444Streams::LumaAndChroma { luma, chroma } => (Encoding::LUMA_AND_CHROMA, luma, Some(chroma)),
+                Avc444Streams::Luma(luma) => (Encoding::LUMA, luma, None),
+                Avc444Streams::Chroma(chroma) => (Encoding::CHROMA, chroma, None),
+            };
+
+            let stream = Avc444BitmapStream {
+                encoding,
+                stream1: avc420_bitmap_stream(stream1),
+                stream2: stream2.map(avc420_bitmap_stream),
+            };
+
+            (
+                Codec1Type::Avc444,
+                rect16(update.left, update.top, update.width, update.height),
+                encode_vec(&stream).context("AVC444 bitmap stream")?,
+            )
+        }
+    };
+
+    let wire_to_surface = WireToSurface1Pdu {
+        surface_id: frame.surface_id,
+        codec_id,
+        pixel_format: gfx::PixelFormat::XRgb,
+        destination_rectangle,
+        bitmap_data,
+    };
+
+    frame_message(frame, vec![wire_to_surface])
+}
+
+fn avc420_bitmap_stream(stream: &AvcStream) -> Avc420BitmapStream<'_> {
+    // The metablock rectangles are RDPGFX_RECT16 as well.
+    let rectangles = stream
+        .regions
+        .iter()
+        .map(|region| InclusiveRectangle {
+            left: region.rectangle.left,
+            top: region.rectangle.top,
+            right: region.rectangle.right.saturating_add(1),
+            bottom: region.rectangle.bottom.saturating_add(1),
+        })
+        .collect();
+
+    Avc420BitmapStream {
+        rectangles,
+        quant_qual_vals: stream
+            .regions
+            .iter()
+            .map(|region| region.quant_quality.clone())
+            .collect(),
+        data: &stream.data,
+    }
+}
+
+/// RDPGFX_RECT16 bounds are exclusive.
+fn rect16(left: u16, top: u16, width: NonZeroU16, height: NonZeroU16) -> InclusiveRectangle {
+    InclusiveRectangle {
+        left,
+        top,
+        right: left.saturating_add(width.get()),
+        bottom: top.saturating_add(height.get()),
+    }
+}
+
+fn frame_message(frame: GfxFrame, wire_to_surface: Vec<WireToSurface1Pdu>) -> Result<GfxServerMessage> {
+    let mut pdus = frame.setup;
+    pdus.push(ServerPdu::StartFrame(StartFramePdu {
+        timestamp: frame.timestamp,
+        frame_id: frame.frame_id,
+    }));
+    pdus.extend(wire_to_surface.into_iter().map(ServerPdu::WireToSurface1));
+    pdus.push(ServerPdu::EndFrame(EndFramePdu {
+        frame_id: frame.frame_id,
+    }));
+
+    Ok(GfxServerMessage::new(&pdus)?)
+}
+
+/// Bitmaps are top-down on the graphics pipeline.
+fn into_top_down(mut bitmap: BitmapUpdate) -> BitmapUpdate {
+    if bitmap.order == PixelOrder::BottomToTop {
+        let row_len = usize::from(bitmap.width.get()) * usize::from(bitmap.format.bytes_per_pixel());
+
+        let mut data = Vec::with_capacity(row_len * usize::from(bitmap.height.get()));
+        for row in rows(&bitmap).rev() {
+            data.extend_from_slice(row);
+        }
+
+        bitmap.data = data;
+        bitmap.stride = row_len;
+        bitmap.order = PixelOrder::TopToBottom;
+    }
+
+    bitmap
+}
+
+fn rows(bitmap: &BitmapUpdate) -> impl DoubleEndedIterator<Item = &[u8]> + Clone {
+    let row_len = usize::from(bitmap.width.get()) * usize::from(bitmap.format.bytes_per_pixel());
+
+    bitmap
+        .data
+        .chunks(bitmap.stride)
+        .take(usize::from(bitmap.height.get()))
+        .map(move |row| &row[..row_len])
+}
+
+fn encode_remotefx(remotefx: &mut RfxEncoder, bitmap: &BitmapUpdate) -> Result<Vec<u8>> {
+    let mut buffer = vec![0; bitmap.data.len()];
+
+    loop {
+        match remotefx.encode(bitmap, buffer.as_mut_slice()) {
+            Err(e) => match e.kind() {
+                EncodeErrorKind::NotEnoughBytes { .. } => {
+                    buffer.resize(buffer.len() * 2, 0);
+                    debug!("GFX RemoteFX buffer resized to: {}", buffer.len());
+                }
+
+                _ => return Err(e).context("RemoteFX encode error"),
+            },
+            Ok(len) => {
+                buffer.truncate(len);
+                return Ok(buffer);
+            }
+        }
+    }
+}
+
+/// Returns `None` if the encoded bitmap is not smaller than the uncompressed one.
+fn encode_planar(bitmap: &BitmapUpdate) -> Option<Vec<u8>> {
+    let width = usize::from(bitmap.width.get());
+    let height = usize::from(bitmap.height.get());
+    let bytes_per_pixel = usize::from(bitmap.format.bytes_per_pixel());
+
+    let mut buffer = vec![0; width * height * 4];
+    let mut encoder = BitmapStreamEncoder::new(width, height);
+    let pixels = rows(bitmap).flat_map(|row| row.chunks(bytes_per_pixel));
+
+    let result = match bitmap.format {
+        PixelFormat::ARgb32 | PixelFormat::XRgb32 => {
+            encoder.encode_pixels_stream::<_, ARgbChannels>(pixels, &mut buffer, true)
+        }
+        PixelFormat::RgbA32 | PixelFormat::RgbX32 => {
+            encoder.encode_pixels_stream::<_, RgbAChannels>(pixels, &mut buffer, true)
+        }
+        PixelFormat::ABgr32 | PixelFormat::XBgr32 => {
+            encoder.encode_pixels_stream::<_, ABgrChannels>(pixels, &mut buffer, true)
+        }
+        PixelFormat::BgrA32 | PixelFormat::BgrX32 => {
+            encoder.encode_pixels_stream::<_, BgrAChannels>(pixels, &mut buffer, true)
+        }
+    };
+
+    match result {
+        Ok(len) => {
+            buffer.truncate(len);
+            Some(buffer)
+        }
+        Err(error) => {
+            trace!(%error, "Planar encoding failed, falling back to uncompressed");
+            None
+        }
+    }
+}
+
+/// RDPGFX_PIXELFORMAT values describe little-endian 32-bit words, that is BGR byte order in memory.
+fn encode_uncompressed(bitmap: &BitmapUpdate) -> Result<Vec<u8>> {
+    let width = bitmap.width.get();
+    let row_len = usize::from(width) * 4;
+
+    let mut data = vec![0; row_len * usize::from(bitmap.height.get())];
+
+    for (row, output) in rows(bitmap).zip(data.chunks_mut(row_len)) {
+        let region = InclusiveRectangle {
+            left: 0,
+            top: 0,
+            right: width - 1,
+            bottom: 0,
+        };
+
+        let source = ImageRegion {
+            region: region.clone(),
+            step: 0,
+            pixel_format: bitmap.format,
+            data: row,
+        };
+        let mut destination = ImageRegionMut {
+            region,
+            step: 0,
+            pixel_format: PixelFormat::BgrX32,
+            data: output,
+        };
+
+        source.copy_to(&mut destination).context("pixel format conversion")?;
+    }
+
+    Ok(data)
+}
@@ -1,14 +1,18 @@
 mod bitmap;
+pub(crate) mod damage;
+pub(crate) mod gfx;
 pub(crate) mod rfx;
 
 use core::{cmp, mem};
 
 use anyhow::{Context, Result};
 use ironrdp_core::{Encode, WriteCursor};
+use ironrdp_graphics::bulk;
 use ironrdp_pdu::fast_path::{EncryptionFlags, FastPathHeader, FastPathUpdatePdu, Fragmentation, UpdateCode};
 use ironrdp_pdu::geometry::ExclusiveRectangle;
 use ironrdp_pdu::pointer::{ColorPointerAttribute, Point16, PointerAttribute, PointerPositionAttribute};
 use ironrdp_pdu::rdp::capability_sets::{CmdFlags, EntropyBits};
+use ironrdp_pdu::rdp::client_info::CompressionType;
 use ironrdp_pdu::surface_commands::{ExtendedBitmapDataPdu, SurfaceBitsPdu, SurfaceCommand};
 
 use self::bitmap::BitmapEncoder;
@@ -31,11 +35,16 @@ pub(crate) struct UpdateEncoder {
     buffer: Vec<u8>,
     bitmap: BitmapEncoder,
     remotefx: Option<(RfxEncoder, u8)>,
+    compressor: Option<bulk::Compressor>,
     update: for<'a> fn(&'a mut UpdateEncoder, BitmapUpdate) -> Result<UpdateFragmenter<'a>>,
 }
 
 impl UpdateEncoder {
-    pub(crate) fn new(surface_flags: CmdFlags, remotefx: Option<(EntropyBits, u8)>) -> Self {
+    pub(crate) fn new(
+        surface_flags: CmdFlags,
+        remotefx: Option<(EntropyBits, u8)>,
+        compression_type: Option<CompressionType>,
+    ) -> Self {
         let update = if !surface_flags.contains(CmdFlags::SET_SURFACE_BITS) {
             Self::bitmap_update
         } else if remotefx.is_some() {
@@ -48,6 +57,7 @@ impl UpdateEncoder {
             buffer: vec![0; 16384],
             bitmap: BitmapEncoder::new(),
             remotefx: remotefx.map(|(algo, id)| (RfxEncoder::new(algo), id)),
+            compressor: compression_type.map(bulk::Compressor::new),
             update,
         }
     }
@@ -89,7 +99,7 @@ impl UpdateEncoder {
             color_pointer,
         };
         let len = self.encode_pdu(ptr)?;
-        Ok(UpdateFragmenter::new(UpdateCode::NewPointer, &self.buffer[..len]))
+        Ok(self.fragmenter(UpdateCode::NewPointer, len))
     }
 
     pub(crate) fn color_pointer(&mut self, ptr: ColorPointer) -> Result<UpdateFragmenter<'_>> {
@@ -106,22 +116,20 @@ impl UpdateEncoder {
             and_mask: &ptr.and_mask,
         };
         let len = self.encode_pdu(ptr)?;
-        Ok(UpdateFragmenter::new(UpdateCode::ColorPointer, &self.buffer[..len]))
+        Ok(self.fragmenter(UpdateCode::ColorPointer, len))
     }
 
-    #[allow(clippy::unused_self)]
     pub(crate) fn default_pointer(&mut self) -> Result<UpdateFragmenter<'_>> {
-        Ok(UpdateFragmenter::new(UpdateCode::DefaultPointer, &[]))
+        Ok(self.fragmenter(UpdateCode::DefaultPointer, 0))
     }
 
-    #[allow(clippy::unused_self)]
     pub(crate) fn hide_pointer(&mut self) -> Result<UpdateFragmenter<'_>> {
-        Ok(UpdateFragmenter::new(UpdateCode::HiddenPointer, &[]))
+        Ok(self.fragmenter(UpdateCode::HiddenPointer, 0))
     }
 
     pub(crate) fn pointer_position(&mut self, pos: PointerPositionAttribute) -> Result<UpdateFragmenter<'_>> {
         let len = self.encode_pdu(pos)?;
-        Ok(UpdateFragmenter::new(UpdateCode::PositionPointer, &self.buffer[..len]))
+        Ok(self.fragmenter(UpdateCode::PositionPointer, len))
     }
 
     pub(crate) fn bitmap(&mut self, bitmap: BitmapUpdate) -> Result<UpdateFragmenter<'_>> {
@@ -130,11 +138,21 @@ impl UpdateEncoder {
         update(self, bitmap)
     }
 
-    pub(crate) fn fragmenter_from_owned(&self, res: UpdateFragmenterOwned) -> UpdateFragmenter<'_> {
+    pub(crate) fn fragmenter_from_owned(&mut self, res: UpdateFragmenterOwned) -> UpdateFragmenter<'_> {
         UpdateFragmenter {
             code: res.code,
             index: res.index,
             data: &self.buffer[0..res.len],
+            compressor: self.compressor.as_mut(),
+        }
+    }
+
+    fn fragmenter(&mut self, code: UpdateCode, len: usize) -> UpdateFragmenter<'_> {
+        UpdateFragmenter {
+            code,
+            index: 0,
+            data: &self.buffer[..len],
+            compressor: self.compressor.as_mut(),
         }
     }
 
@@ -153,7 +171,7 @@ impl UpdateEncoder {
             }
         };
 
-        Ok(UpdateFragmenter::new(UpdateCode::Bitmap, &self.buffer[..len]))
+        Ok(self.fragmenter(UpdateCode::Bitmap, len))
     }
 
     fn set_surface(&mut self, bitmap: BitmapUpdate, codec_id: u8, data: &[u8]) -> Result<UpdateFragmenter<'_>> {
@@ -177,7 +195,7 @@ impl UpdateEncoder {
         };
         let cmd = SurfaceCommand::SetSurfaceBits(pdu);
         let len = self.encode_pdu(cmd)?;
-        Ok(UpdateFragmenter::new(UpdateCode::SurfaceCommands, &self.buffer[..len]))
+        Ok(self.fragmenter(UpdateCode::SurfaceCommands, len))
     }
 
     fn remotefx_update(&mut self, bitmap: BitmapUpdate) -> Result<UpdateFragmenter<'_>> {
@@ -238,13 +256,11 @@ pub(crate) struct UpdateFragmenter<'a> {
     code: UpdateCode,
     index: usize,
     data: &'a [u8],
+    /// Bulk compressor of the connection, when the client supports compression.
+    compressor: Option<&'a mut bulk::Compressor>,
 }
 
-impl<'a> UpdateFragmenter<'a> {
-    pub(crate) fn new(code: UpdateCode, data: &'a [u8]) -> Self {
-        Self { code, index: 0, data }
-    }
-
+impl UpdateFragmenter<'_> {
     pub(crate) fn into_owned(self) -> UpdateFragmenterOwned {
         UpdateFragmenterOwned {
             code: self.code,
@@ -254,7 +270,10 @@ impl<'a> UpdateFragmenter<'a> {
     }
 
     pub(crate) fn size_hint(&self) -> usize {
-        FASTPATH_HEADER_SIZE + cmp::min(self.data.len(), MAX_FASTPATH_UPDATE_SIZE)
+        // The compressed data is never larger than the original one.
+        let compression_flags_size = if self.compressor.is_some() { 1 } else { 0 };
+
+        FASTPATH_HEADER_SIZE + compression_flags_size + cmp::min(self.data.len(), MAX_FASTPATH_UPDATE_SIZE)
     }
 
     pub(crate) fn next(&mut self, dst: &mut [u8]) -> Option<usize> {
@@ -292,14 +311,28 @@ impl<'a> UpdateFragmenter<'a> {
         }
     }
 
-    fn encode_fastpath(&self, frag: Fragmentation, data: &[u8], dst: &mut [u8]) -> Option<usize> {
+    fn encode_fastpath(&mut self, frag: Fragmentation, data: &[u8], dst: &mut [u8]) -> Option<usize> {
         let mut cursor = WriteCursor::new(dst);
 
+        // Every fragment is compressed on its own, and the history is shared by all of them.
+        let mut compressed = Vec::new();
+        let (data, compression_flags, compression_type) = match self.compressor.as_deref_mut() {
+            Some(compressor) => {
+                let flags = compressor.compress(data, &mut compressed);
+                if flags.is_empty() {
+                    (data, None, None)
+                } else {
+                    (compressed.as_slice(), Some(flags), Some(compressor.compression_type()))
+                }
+            }
+            None => (data, None, None),
+        };
+
         let update = FastPathUpdatePdu {
             fragmentation: frag,
             update_code: self.code,
-            compression_flags: None,
-            compression_type: None,
+            compression_flags,
+            compression_type,
             data,
         };
 
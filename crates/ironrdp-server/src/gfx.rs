@@ -0,0 +1,491 @@
+//! Server-side implementation of the Graphics Pipeline Extension ([MS-RDPEGFX])
+//!
+//! [MS-RDPEGFX]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegfx/da5c75f9-cd99-450c-98c4-014a496942b0
+
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context as _, Result};
+use ironrdp_acceptor::DesktopSize;
+use ironrdp_core::{decode, encode_vec, impl_as_any, Encode, EncodeResult, WriteCursor};
+use ironrdp_dvc::{self as dvc, DvcEncode};
+use ironrdp_graphics::zgfx;
+use ironrdp_pdu::rdp::vc::dvc::gfx::{
+    CapabilitiesConfirmPdu, CapabilitiesV103Flags, CapabilitiesV104Flags, CapabilitiesV107Flags, CapabilitiesV10Flags,
+    CapabilitiesV81Flags, CapabilitySet, ClientPdu, CreateSurfacePdu, DeleteSurfacePdu, FrameAcknowledgePdu,
+    MapSurfaceToOutputPdu, PixelFormat, QueueDepth, ResetGraphicsPdu, ServerPdu, Timestamp,
+};
+use ironrdp_pdu::{pdu_other_err, PduResult};
+use ironrdp_svc::{server_encode_svc_messages, ChannelFlags};
+use ironrdp_tokio::FramedWrite;
+use tokio::sync::Notify;
+use tokio::task;
+
+use crate::encoder::gfx::{self as encoder, GfxEncoder};
+use crate::{time_warn, Avc420Update, Avc444Update, AvcSupport, BitmapUpdate};
+
+pub(crate) const CHANNEL_NAME: &str = "Microsoft::Windows::RDS::Graphics";
+
+/// The whole desktop is drawn on a single surface, mapped at the origin of the output.
+const SURFACE_ID: u16 = 0;
+
+/// Maximum number of frames sent to the client, and not acknowledged yet
+const MAX_FRAMES_IN_FLIGHT: usize = 3;
+
+#[derive(Debug, Default)]
+struct GfxState {
+    channel_id: Option<u32>,
+    capabilities: Option<CapabilitySet>,
+    /// Size of the surface mapped to the output, once created
+    surface_size: Option<DesktopSize>,
+    next_frame_id: u32,
+    unacknowledged_frames: BTreeSet<u32>,
+    /// Number of frames waiting to be decoded, as last reported by the client
+    client_queue_depth: u32,
+    /// The client will not acknowledge frames anymore, until it sends a queue depth again
+    acknowledgement_suspended: bool,
+}
+
+impl GfxState {
+    fn is_active(&self) -> bool {
+        self.channel_id.is_some() && self.capabilities.is_some()
+    }
+
+    fn can_send_frame(&self) -> bool {
+        if !self.is_active() || self.acknowledgement_suspended {
+            return true;
+        }
+
+        let queue_depth = usize::try_from(self.client_queue_depth).unwrap_or(usize::MAX);
+
+        self.unacknowledged_frames.len().max(queue_depth) < MAX_FRAMES_IN_FLIGHT
+    }
+
+    fn acknowledge_frame(&mut self, pdu: &FrameAcknowledgePdu) {
+        trace!(frame_id = pdu.frame_id, queue_depth = ?pdu.queue_depth, "Frame acknowledged");
+
+        self.unacknowledged_frames.remove(&pdu.frame_id);
+
+        match pdu.queue_depth {
+            QueueDepth::Suspend => {
+                debug!("Frame acknowledgement suspended by the client");
+                self.acknowledgement_suspended = true;
+                self.unacknowledged_frames.clear();
+                self.client_queue_depth = 0;
+            }
+            QueueDepth::Unavailable => {
+                self.acknowledgement_suspended = false;
+                self.client_queue_depth = 0;
+            }
+            QueueDepth::AvailableBytes(depth) => {
+                self.acknowledgement_suspended = false;
+                self.client_queue_depth = depth;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<GfxState>,
+    frame_acknowledged: Notify,
+}
+
+/// Handle shared by the [`GfxServer`] and the display updates of the connection
+#[derive(Debug, Clone, Default)]
+pub(crate) struct GfxHandle {
+    shared: Arc<Shared>,
+}
+
+impl GfxHandle {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// H.264 codecs confirmed to the client, none while the graphics pipeline is not active
+    pub(crate) fn avc_support(&self) -> AvcSupport {
+        let state = self.state();
+
+        match (state.channel_id, state.capabilities.as_ref()) {
+            (Some(_), Some(capabilities)) => avc_support(capabilities),
+            _ => AvcSupport::default(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, GfxState> {
+        self.shared.state.lock().expect("GFX state lock poisoned")
+    }
+
+    /// Waits until the client can receive a new frame, according to its frame acknowledgements
+    ///
+    /// Returns immediately while the graphics pipeline is not active.
+    pub(crate) async fn wait_for_frame_slot(&self) {
+        loop {
+            // The notification permit is kept if an acknowledgement arrives before we wait on it.
+            if self.state().can_send_frame() {
+                return;
+            }
+
+            self.shared.frame_acknowledged.notified().await;
+        }
+    }
+
+    /// Starts a new frame, if the graphics pipeline is active
+    ///
+    /// The surface is (re)created first if it does not match the desktop size.
+    pub(crate) fn start_frame(&self, desktop_size: DesktopSize) -> Option<GfxFrame> {
+        let mut state = self.state();
+
+        if !state.is_active() {
+            return None;
+        }
+
+        let channel_id = state.channel_id?;
+        let mut setup = Vec::new();
+
+        if state.surface_size != Some(desktop_size) {
+            if state.surface_size.is_some() {
+                setup.push(ServerPdu::DeleteSurface(DeleteSurfacePdu { surface_id: SURFACE_ID }));
+            }
+
+            debug!(?desktop_size, "Creating GFX surface");
+
+            setup.push(ServerPdu::ResetGraphics(ResetGraphicsPdu {
+                width: u32::from(desktop_size.width),
+                height: u32::from(desktop_size.height),
+                monitors: Vec::new(),
+            }));
+            setup.push(ServerPdu::CreateSurface(CreateSurfacePdu {
+                surface_id: SURFACE_ID,
+                width: desktop_size.width,
+                height: desktop_size.height,
+                pixel_format: PixelFormat::XRgb,
+            }));
+            setup.push(ServerPdu::MapSurfaceToOutput(MapSurfaceToOutputPdu {
+                surface_id: SURFACE_ID,
+                output_origin_x: 0,
+                output_origin_y: 0,
+            }));
+
+            state.surface_size = Some(desktop_size);
+        }
+
+        let frame_id = state.next_frame_id;
+        state.next_frame_id = frame_id.wrapping_add(1);
+        state.unacknowledged_frames.insert(frame_id);
+
+        Some(GfxFrame {
+            channel_id,
+            surface_id: SURFACE_ID,
+            frame_id,
+            timestamp: timestamp(),
+            setup,
+        })
+    }
+}
+
+/// Frame to be encoded and sent on the graphics pipeline
+#[derive(Debug)]
+pub(crate) struct GfxFrame {
+    pub(crate) channel_id: u32,
+    pub(crate) surface_id: u16,
+    pub(crate) frame_id: u32,
+    pub(crate) timestamp: Timestamp,
+    /// PDUs to send before the frame, to set the surface up
+    pub(crate) setup: Vec<ServerPdu>,
+}
+
+/// Server for the Graphics Pipeline Virtual Channel
+///
+/// The capability set confirmed to the client is the most recent one it advertises. The
+/// display updates are then sent on this channel, see [`GfxOutput`].
+pub(crate) struct GfxServer {
+    handle: GfxHandle,
+}
+
+impl GfxServer {
+    pub(crate) fn new(handle: GfxHandle) -> Self {
+        Self { handle }
+    }
+}
+
+impl_as_any!(GfxServer);
+
+impl dvc::DvcProcessor for GfxServer {
+    fn channel_name(&self) -> &str {
+        CHANNEL_NAME
+    }
+
+    fn start(&mut self, channel_id: u32) -> PduResult<Vec<dvc::DvcMessage>> {
+        debug!(channel_id, "GFX channel opened");
+
+        // The client speaks first, by advertising its capabilities.
+        *self.handle.state() = GfxState {
+            channel_id: Some(channel_id),
+            ..GfxState::default()
+        };
+
+        Ok(Vec::new())
+    }
+
+    fn close(&mut self, _channel_id: u32) {
+        debug!("GFX channel closed");
+
+        *self.handle.state() = GfxState::default();
+        self.handle.shared.frame_acknowledged.notify_one();
+    }
+
+    fn process(&mut self, _channel_id: u32, payload: &[u8]) -> PduResult<Vec<dvc::DvcMessage>> {
+        let pdu = match decode::<ClientPdu>(payload) {
+            Ok(pdu) => pdu,
+            Err(error) => {
+                // E.g. RDPGFX_CACHE_IMPORT_OFFER_PDU, the persistent cache is not supported.
+                debug!(%error, "Unsupported GFX PDU");
+                return Ok(Vec::new());
+            }
+        };
+
+        match pdu {
+            ClientPdu::CapabilitiesAdvertise(pdu) => {
+                let Some(capabilities) = pdu.0.into_iter().max_by_key(capability_version_rank) else {
+                    warn!("The client advertised no GFX capabilities");
+                    return Ok(Vec::new());
+                };
+
+                debug!(?capabilities, "Confirming GFX capabilities");
+
+                {
+                    let mut state = self.handle.state();
+                    state.capabilities = Some(capabilities.clone());
+                    state.surface_size = None;
+                    state.unacknowledged_frames.clear();
+                }
+
+                let confirm = ServerPdu::CapabilitiesConfirm(CapabilitiesConfirmPdu(capabilities));
+
+                Ok(vec![Box::new(GfxServerMessage::new(&[confirm])?)])
+            }
+            ClientPdu::FrameAcknowledge(pdu) => {
+                self.handle.state().acknowledge_frame(&pdu);
+                self.handle.shared.frame_acknowledged.notify_one();
+
+                Ok(Vec::new())
+            }
+        }
+    }
+}
+
+impl dvc::DvcServerProcessor for GfxServer {}
+
+/// Unknown capability sets are never selected.
+fn capability_version_rank(capabilities: &CapabilitySet) -> u8 {
+    match capabilities {
+        CapabilitySet::Unknown(_) => 0,
+        CapabilitySet::V8 { .. } => 1,
+        CapabilitySet::V8_1 { .. } => 2,
+        CapabilitySet::V10 { .. } => 3,
+        CapabilitySet::V10_1 => 4,
+        CapabilitySet::V10_2 { .. } => 5,
+        CapabilitySet::V10_3 { .. } => 6,
+        CapabilitySet::V10_4 { .. } => 7,
+        CapabilitySet::V10_5 { .. } => 8,
+        // Flawed version of 10.6, only used as a fallback by some clients.
+        CapabilitySet::V10_6Err { .. } => 9,
+        CapabilitySet::V10_6 { .. } => 10,
+        CapabilitySet::V10_7 { .. } => 11,
+    }
+}
+
+fn avc_support(capabilities: &CapabilitySet) -> AvcSupport {
+    let (avc420, avc444) = match capabilities {
+        CapabilitySet::V8 { .. } | CapabilitySet::Unknown(_) => (false, false),
+        CapabilitySet::V8_1 { flags } => (flags.contains(CapabilitiesV81Flags::AVC420_ENABLED), false),
+        CapabilitySet::V10_1 => (true, true),
+        CapabilitySet::V10 { flags } | CapabilitySet::V10_2 { flags } => {
+            let enabled = !flags.contains(CapabilitiesV10Flags::AVC_DISABLED);
+            (enabled, enabled)
+        }
+        CapabilitySet::V10_3 { flags } => {
+            let enabled = !flags.contains(CapabilitiesV103Flags::AVC_DISABLED);
+            (enabled, enabled)
+        }
+        CapabilitySet::V10_4 { flags }
+        | CapabilitySet::V10_5 { flags }
+        | CapabilitySet::V10_6 { flags }
+        | CapabilitySet::V10_6Err { flags } => {
+            let enabled = !flags.contains(CapabilitiesV104Flags::AVC_DISABLED);
+            (enabled, enabled)
+        }
+        CapabilitySet::V10_7 { flags } => {
+            let enabled = !flags.contains(CapabilitiesV107Flags::AVC_DISABLED);
+            (enabled, enabled)
+        }
+    };
+
+    AvcSupport { avc420, avc444 }
+}
+
+/// Timestamp of a frame, relative to the beginning of the current day
+fn timestamp() -> Timestamp {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = elapsed.as_secs() % 86_400;
+
+    Timestamp {
+        milliseconds: u16::try_from(elapsed.subsec_millis()).expect("milliseconds fit in u16"),
+        seconds: u8::try_from(seconds % 60).expect("seconds fit in u8"),
+        minutes: u8::try_from(seconds / 60 % 60).expect("minutes fit in u8"),
+        hours: u16::try_from(seconds / 3600).expect("hours fit in u16"),
+    }
+}
+
+/// Server PDUs, wrapped into an uncompressed RDP_SEGMENTED_DATA structure
+pub(crate) struct GfxServerMessage(Vec<u8>);
+
+impl GfxServerMessage {
+    pub(crate) fn new(pdus: &[ServerPdu]) -> PduResult<Self> {
+        let mut data = Vec::new();
+
+        for pdu in pdus {
+            data.extend_from_slice(&encode_vec(pdu).map_err(|e| pdu_other_err!("GFX PDU", source: e))?);
+        }
+
+        let mut segmented = Vec::with_capacity(data.len() + 16);
+        zgfx::wrap_uncompressed(&data, &mut segmented).map_err(|e| pdu_other_err!("ZGFX", source: e))?;
+
+        Ok(Self(segmented))
+    }
+}
+
+impl Encode for GfxServerMessage {
+    fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        ironrdp_core::ensure_size!(in: dst, size: self.size());
+        dst.write_slice(&self.0);
+
+        Ok(())
+    }
+
+    fn name(&self) -> &'static str {
+        "GfxServerMessage"
+    }
+
+    fn size(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl DvcEncode for GfxServerMessage {}
+
+/// Pre-encoded H.264 update
+#[derive(Debug)]
+pub(crate) enum AvcUpdate {
+    Avc420(Avc420Update),
+    Avc444(Avc444Update),
+}
+
+/// Sends the bitmap updates of a connection on the graphics pipeline, once it is active
+pub(crate) struct GfxOutput {
+    handle: GfxHandle,
+    /// `None` while a bitmap is being encoded
+    encoder: Option<GfxEncoder>,
+    desktop_size: DesktopSize,
+    drdynvc_channel_id: u16,
+    user_channel_id: u16,
+}
+
+impl GfxOutput {
+    pub(crate) fn new(
+        handle: GfxHandle,
+        encoder: GfxEncoder,
+        desktop_size: DesktopSize,
+        drdynvc_channel_id: u16,
+        user_channel_id: u16,
+    ) -> Self {
+        Self {
+            handle,
+            encoder: Some(encoder),
+            desktop_size,
+            drdynvc_channel_id,
+            user_channel_id,
+        }
+    }
+
+    /// Whether the surface will be (re)created with the next frame, the client then loses the
+    /// content of the desktop
+    pub(crate) fn surface_outdated(&self) -> bool {
+        let state = self.handle.state();
+
+        state.is_active() && state.surface_size != Some(self.desktop_size)
+    }
+
+    /// Sends bitmaps as a new frame
+    ///
+    /// Waits for the client to acknowledge enough frames first. The bitmaps are given back if the
+    /// graphics pipeline is not active, they must then be sent with the legacy updates.
+    pub(crate) async fn send_bitmaps(
+        &mut self,
+        bitmaps: Vec<BitmapUpdate>,
+        writer: &mut impl FramedWrite,
+    ) -> Result<Option<Vec<BitmapUpdate>>> {
+        self.handle.wait_for_frame_slot().await;
+
+        let Some(frame) = self.handle.start_frame(self.desktop_size) else {
+            return Ok(Some(bitmaps));
+        };
+        let channel_id = frame.channel_id;
+
+        let mut encoder = self.encoder.take().expect("encoder is not in use");
+        let (encoder, message) = task::spawn_blocking(move || {
+            let message = time_warn!("Encoding GFX frame", 10, encoder.encode(frame, bitmaps));
+            (encoder, message)
+        })
+        .await?;
+        self.encoder = Some(encoder);
+
+        self.write_frame(channel_id, message?, writer).await?;
+
+        Ok(None)
+    }
+
+    /// Sends a pre-encoded H.264 update as a new frame
+    ///
+    /// Returns `false` if the client did not confirm the codec of the update, which is then dropped.
+    pub(crate) async fn send_avc(&mut self, update: AvcUpdate, writer: &mut impl FramedWrite) -> Result<bool> {
+        self.handle.wait_for_frame_slot().await;
+
+        let support = self.handle.avc_support();
+        let supported = match update {
+            AvcUpdate::Avc420(_) => support.avc420,
+            AvcUpdate::Avc444(_) => support.avc444,
+        };
+        if !supported {
+            return Ok(false);
+        }
+
+        let Some(frame) = self.handle.start_frame(self.desktop_size) else {
+            return Ok(false);
+        };
+        let channel_id = frame.channel_id;
+
+        let message = encoder::encode_avc(frame, &update)?;
+        self.write_frame(channel_id, message, writer).await?;
+
+        Ok(true)
+    }
+
+    /// H.264 codecs confirmed by the client
+    pub(crate) fn avc_support(&self) -> AvcSupport {
+        self.handle.avc_support()
+    }
+
+    async fn write_frame(
+        &self,
+        channel_id: u32,
+        message: GfxServerMessage,
+        writer: &mut impl FramedWrite,
+    ) -> Result<()> {
+        let messages = dvc::encode_dvc_messages(channel_id, vec![Box::new(message)], ChannelFlags::SHOW_PROTOCOL)?;
+        let data = server_encode_svc_messages(messages, self.drdynvc_channel_id, self.user_channel_id)?;
+        writer.write_all(&data).await.context("failed to write GFX frame")
+    }
+}
@@ -7,23 +7,33 @@ pub use {tokio, tokio_rustls};
 #[macro_use]
 extern crate tracing;
 
+mod audio_input;
+mod audio_output;
 mod builder;
 mod capabilities;
 mod clipboard;
 mod display;
 mod encoder;
+mod gfx;
 mod handler;
 #[cfg(feature = "helper")]
 mod helper;
+mod rdpdr;
 mod server;
+mod session;
 mod sound;
 
+pub use audio_input::*;
+pub use audio_output::*;
 pub use clipboard::*;
 pub use display::*;
+pub use encoder::damage::DamageStats;
 pub use handler::*;
 #[cfg(feature = "helper")]
 pub use helper::*;
+pub use rdpdr::*;
 pub use server::*;
+pub use session::*;
 pub use sound::*;
 
 #[cfg(feature = "__bench")]
@@ -0,0 +1,168 @@
+use core::fmt;
+
+use anyhow::{anyhow, Context as _, Result};
+use ironrdp_rdpdr::pdu::efs::{
+    CreateDisposition, CreateOptions, DesiredAccess, DeviceAnnounceHeader, FileBothDirectoryInformation,
+    FileInformationClass, NtStatus,
+};
+pub use ironrdp_rdpdr::server::{DriveRequest, DriveResponse, RdpdrServerHandler};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{ServerEvent, ServerEventSender};
+
+pub trait RdpdrServerFactory: ServerEventSender + Send {
+    fn build_backend(&self) -> Box<dyn RdpdrServerHandler>;
+}
+
+#[derive(Debug)]
+pub enum RdpdrServerMessage {
+    /// Returns the devices currently redirected by the client
+    GetDevices(oneshot::Sender<Vec<DeviceAnnounceHeader>>),
+    /// Sends an I/O request to a drive, the sender is dropped if the request could not be sent
+    DriveRequest(DriveRequest, oneshot::Sender<DriveResponse>),
+}
+
+/// Error status returned by the client for an I/O request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceIoError(pub NtStatus);
+
+impl fmt::Display for DeviceIoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "device I/O request failed with {:?}", self.0)
+    }
+}
+
+impl std::error::Error for DeviceIoError {}
+
+/// Accesses the drives redirected by the client of a connection
+///
+/// The I/O errors reported by the client are returned as a [`DeviceIoError`].
+#[derive(Debug, Clone)]
+pub struct ClientDevices {
+    sender: mpsc::UnboundedSender<ServerEvent>,
+}
+
+impl ClientDevices {
+    /// Creates the handle from the event sender of the connection, see [`ServerEventSender`]
+    pub fn new(sender: mpsc::UnboundedSender<ServerEvent>) -> Self {
+        Self { sender }
+    }
+
+    pub async fn devices(&self) -> Result<Vec<DeviceAnnounceHeader>> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(ServerEvent::Rdpdr(RdpdrServerMessage::GetDevices(tx)))
+            .map_err(|_| anyhow!("server is not running"))?;
+
+        rx.await.context("no rdpdr channel")
+    }
+
+    /// Sends an I/O request and waits for the response of the client
+    pub async fn request(&self, request: DriveRequest) -> Result<DriveResponse> {
+        let (tx, rx) = oneshot::channel();
+        self.sender
+            .send(ServerEvent::Rdpdr(RdpdrServerMessage::DriveRequest(request, tx)))
+            .map_err(|_| anyhow!("server is not running"))?;
+
+        let response = rx.await.context("drive request dropped")?;
+        match response.io_status() {
+            NtStatus::SUCCESS => Ok(response),
+            status => Err(DeviceIoError(status).into()),
+        }
+    }
+
+    /// Opens or creates a file, and returns its file ID
+    pub async fn create(
+        &self,
+        device_id: u32,
+        path: &str,
+        desired_access: DesiredAccess,
+        create_disposition: CreateDisposition,
+        create_options: CreateOptions,
+    ) -> Result<u32> {
+        let response = self
+            .request(DriveRequest::Create {
+                device_id,
+                path: path.to_owned(),
+                desired_access,
+                create_disposition,
+                create_options,
+            })
+            .await?;
+
+        match response {
+            DriveResponse::Create(response) => Ok(response.file_id),
+            _ => Err(anyhow!("unexpected response")),
+        }
+    }
+
+    pub async fn read(&self, device_id: u32, file_id: u32, offset: u64, length: u32) -> Result<Vec<u8>> {
+        let response = self
+            .request(DriveRequest::Read {
+                device_id,
+                file_id,
+                offset,
+                length,
+            })
+            .await?;
+
+        match response {
+            DriveResponse::Read(response) => Ok(response.read_data),
+            _ => Err(anyhow!("unexpected response")),
+        }
+    }
+
+    /// Writes to a file, and returns the number of bytes written
+    pub async fn write(&self, device_id: u32, file_id: u32, offset: u64, data: Vec<u8>) -> Result<u32> {
+        let response = self
+            .request(DriveRequest::Write {
+                device_id,
+                file_id,
+                offset,
+                data,
+            })
+            .await?;
+
+        match response {
+            DriveResponse::Write(response) => Ok(response.length),
+            _ => Err(anyhow!("unexpected response")),
+        }
+    }
+
+    /// Returns the next entry of an opened directory, or `None` when there are no more entries
+    ///
+    /// The `path` is only used by the initial query, to filter the entries.
+    pub async fn query_directory(
+        &self,
+        device_id: u32,
+        file_id: u32,
+        initial_query: bool,
+        path: &str,
+    ) -> Result<Option<FileBothDirectoryInformation>> {
+        let response = self
+            .request(DriveRequest::QueryDirectory {
+                device_id,
+                file_id,
+                initial_query,
+                path: path.to_owned(),
+            })
+            .await;
+
+        match response {
+            Ok(DriveResponse::QueryDirectory(response)) => match response.buffer {
+                Some(FileInformationClass::BothDirectory(info)) => Ok(Some(info)),
+                None => Ok(None),
+                Some(_) => Err(anyhow!("unexpected file information class")),
+            },
+            Ok(_) => Err(anyhow!("unexpected response")),
+            Err(e) if e.downcast_ref() == Some(&DeviceIoError(NtStatus::NO_MORE_FILES)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub async fn close(&self, device_id: u32, file_id: u32) -> Result<()> {
+        self.request(DriveRequest::Close { device_id, file_id }).await?;
+
+        Ok(())
+    }
+}
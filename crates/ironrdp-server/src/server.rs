@@ -1,9 +1,11 @@
+use core::time::Duration;
+use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::rc::Rc;
 use std::sync::Arc;
 
 use anyhow::{anyhow, bail, Context, Result};
 use ironrdp_acceptor::{self, Acceptor, AcceptorResult, BeginResult, DesktopSize};
+pub use ironrdp_acceptor::{AuthBackend, AuthDecision, AuthRequest, AuthSecret, DelegatedCredentials, KerberosConfig};
 use ironrdp_async::{bytes, Framed};
 use ironrdp_cliprdr::backend::ClipboardMessage;
 use ironrdp_cliprdr::CliprdrServer;
@@ -14,31 +16,62 @@ use ironrdp_pdu::input::fast_path::{FastPathInput, FastPathInputEvent};
 use ironrdp_pdu::input::InputEventPdu;
 use ironrdp_pdu::mcs::{SendDataIndication, SendDataRequest};
 use ironrdp_pdu::rdp::capability_sets::{BitmapCodecs, CapabilitySet, CmdFlags, GeneralExtraFlags};
+use ironrdp_pdu::rdp::client_info::CompressionType;
 pub use ironrdp_pdu::rdp::client_info::Credentials;
-use ironrdp_pdu::rdp::headers::{ServerDeactivateAll, ShareControlPdu};
+use ironrdp_pdu::rdp::headers::{
+    CompressionFlags, ServerDeactivateAll, ShareControlPdu, ShareDataHeader, ShareDataPdu, StreamPriority,
+};
+use ironrdp_pdu::rdp::server_error_info::{ErrorInfo, ProtocolIndependentCode, ServerSetErrorInfoPdu};
 use ironrdp_pdu::x224::X224;
 use ironrdp_pdu::{self, decode_err, mcs, nego, rdp, Action, PduResult};
+use ironrdp_rdpdr::server::RdpdrServer;
+use ironrdp_rdpeai::server::AudioInputServer;
 use ironrdp_svc::{server_encode_svc_messages, StaticChannelId, StaticChannelSet, SvcProcessor};
 use ironrdp_tokio::{split_tokio_framed, unsplit_tokio_framed, FramedRead, FramedWrite, TokioFramed};
 use rdpsnd::server::{RdpsndServer, RdpsndServerMessage};
 use tokio::io::{AsyncRead, AsyncWrite};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
 use tokio::sync::{mpsc, oneshot, Mutex};
-use tokio::task;
+use tokio::task::{self, AbortHandle, JoinSet};
 use tokio_rustls::TlsAcceptor;
 use {ironrdp_dvc as dvc, ironrdp_rdpsnd as rdpsnd};
 
 use crate::clipboard::CliprdrServerFactory;
-use crate::display::{DisplayUpdate, RdpServerDisplay};
+use crate::display::{AvcSupport, DisplayUpdate, RdpServerDisplay, RdpServerDisplayUpdates};
+use crate::encoder::damage::{DamageCounters, DamageStats, DamageTracker};
+use crate::encoder::gfx::GfxEncoder;
 use crate::encoder::UpdateEncoder;
+use crate::gfx::{AvcUpdate, GfxHandle, GfxOutput, GfxServer};
 use crate::handler::RdpServerInputHandler;
-use crate::{builder, capabilities, time_warn, SoundServerFactory};
+use crate::session::{RdpServerHandlerFactory, SessionId, SessionInfo};
+use crate::{
+    builder, capabilities, time_warn, AudioInputServerFactory, RdpdrServerFactory, RdpdrServerMessage,
+    SoundServerFactory,
+};
+
+/// Time left to a connection to disconnect gracefully, when its session is disconnected
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[derive(Clone)]
 pub struct RdpServerOptions {
     pub addr: SocketAddr,
     pub security: RdpServerSecurity,
     pub with_remote_fx: bool,
+    /// Offers the Graphics Pipeline Virtual Channel to the clients
+    ///
+    /// The bitmap updates are sent on this channel to the clients opening it, encoded with
+    /// RemoteFX when `with_remote_fx` is set, and with the planar codec otherwise.
+    pub with_gfx: bool,
+    /// Maximum number of concurrent client connections, when the server serves several clients
+    /// concurrently
+    ///
+    /// Connections over the limit are denied once accepted: the client is sent a Set Error Info
+    /// PDU (server denied connection), then a Disconnect Provider Ultimatum.
+    pub max_sessions: Option<usize>,
+    /// Authenticates the users, instead of the credentials set with [`RdpServer::set_credentials`]
+    pub auth_backend: Option<Arc<dyn AuthBackend>>,
+    /// Kerberos configuration of CredSSP, with hybrid security
+    pub kerberos_config: Option<KerberosConfig>,
 }
 
 #[derive(Clone)]
@@ -122,6 +155,11 @@ impl DisplayControlHandler for DisplayControlBackend {
 ///  - receive display updates from a [`RdpServerDisplay`] and forward them to the client
 ///  - receive input events from a client and forward them to an [`RdpServerInputHandler`]
 ///
+/// By default, the connections are served one after the other, with the same handlers. When built
+/// with an [`RdpServerHandlerFactory`], each connection is served concurrently in its own task,
+/// with its own handlers, static channels and event channel. The sessions can then be managed
+/// with [`ServerEvent::GetSessions`] and [`ServerEvent::DisconnectSession`].
+///
 /// # Example
 ///
 /// ```
@@ -175,6 +213,10 @@ impl DisplayControlHandler for DisplayControlBackend {
 /// server.run().await;
 ///# }
 /// ```
+///
+/// When the connections are served concurrently, each connection has its own server, with the handlers,
+/// channel factories and event channel built for it. Only the options, the credentials and the audio
+/// input factory are shared with the connections.
 pub struct RdpServer {
     opts: RdpServerOptions,
     // FIXME: replace with a channel and poll/process the handler?
@@ -183,10 +225,19 @@ pub struct RdpServer {
     static_channels: StaticChannelSet,
     sound_factory: Option<Box<dyn SoundServerFactory>>,
     cliprdr_factory: Option<Box<dyn CliprdrServerFactory>>,
+    rdpdr_factory: Option<Box<dyn RdpdrServerFactory>>,
+    audio_input_factory: Option<Arc<dyn AudioInputServerFactory>>,
+    handler_factory: Option<Arc<dyn RdpServerHandlerFactory>>,
     ev_sender: mpsc::UnboundedSender<ServerEvent>,
     ev_receiver: Arc<Mutex<mpsc::UnboundedReceiver<ServerEvent>>>,
     creds: Option<Credentials>,
     local_addr: Option<SocketAddr>,
+    /// Graphics pipeline of the current connection, when offered
+    gfx: Option<GfxHandle>,
+    /// Tiles of the bitmap updates of the current connection
+    damage_counters: Arc<DamageCounters>,
+    /// Denies the connection once accepted, when it is over the session limit
+    denied: bool,
 }
 
 #[derive(Debug)]
@@ -194,8 +245,18 @@ pub enum ServerEvent {
     Quit(String),
     Clipboard(ClipboardMessage),
     Rdpsnd(RdpsndServerMessage),
+    Rdpdr(RdpdrServerMessage),
     SetCredentials(Credentials),
     GetLocalAddr(oneshot::Sender<Option<SocketAddr>>),
+    /// Lists the active sessions, when the connections are served concurrently
+    GetSessions(oneshot::Sender<Vec<SessionInfo>>),
+    /// Closes the connection of a session, when the connections are served concurrently
+    DisconnectSession(SessionId),
+    /// Returns the number of tiles of the bitmap updates of the current connection which were encoded,
+    /// or skipped because they did not change
+    GetDamageStats(oneshot::Sender<DamageStats>),
+    /// Same as [`ServerEvent::GetDamageStats`] for a session, when the connections are served concurrently
+    GetSessionDamageStats(SessionId, oneshot::Sender<Option<DamageStats>>),
 }
 
 pub trait ServerEventSender {
@@ -215,6 +276,13 @@ enum RunState {
     DeactivationReactivation { desktop_size: DesktopSize },
 }
 
+struct Session {
+    info: SessionInfo,
+    task: AbortHandle,
+    ev_sender: mpsc::UnboundedSender<ServerEvent>,
+    damage_counters: Arc<DamageCounters>,
+}
+
 impl RdpServer {
     pub fn new(
         opts: RdpServerOptions,
@@ -237,10 +305,90 @@ impl RdpServer {
             static_channels: StaticChannelSet::new(),
             sound_factory,
             cliprdr_factory,
+            rdpdr_factory: None,
+            audio_input_factory: None,
+            handler_factory: None,
             ev_sender,
             ev_receiver: Arc::new(Mutex::new(ev_receiver)),
             creds: None,
             local_addr: None,
+            gfx: None,
+            damage_counters: Arc::new(DamageCounters::default()),
+            denied: false,
+        }
+    }
+
+    pub(crate) fn set_handler_factory(&mut self, factory: Box<dyn RdpServerHandlerFactory>) {
+        self.handler_factory = Some(Arc::from(factory));
+    }
+
+    pub(crate) fn set_rdpdr_factory(&mut self, mut factory: Box<dyn RdpdrServerFactory>) {
+        factory.set_sender(self.ev_sender.clone());
+        self.rdpdr_factory = Some(factory);
+    }
+
+    pub(crate) fn set_audio_input_factory(&mut self, factory: Box<dyn AudioInputServerFactory>) {
+        self.audio_input_factory = Some(Arc::from(factory));
+    }
+
+    /// Creates the server of a connection, when the connections are served concurrently
+    fn new_connection(&self, factory: &dyn RdpServerHandlerFactory, session: &SessionInfo) -> Self {
+        let (ev_sender, ev_receiver) = ServerEvent::create_channel();
+
+        let mut cliprdr_factory = factory.build_cliprdr_factory(session);
+        if let Some(factory) = cliprdr_factory.as_mut() {
+            factory.set_sender(ev_sender.clone());
+        }
+        let mut sound_factory = factory.build_sound_factory(session);
+        if let Some(factory) = sound_factory.as_mut() {
+            factory.set_sender(ev_sender.clone());
+        }
+        let mut rdpdr_factory = factory.build_rdpdr_factory(session);
+        if let Some(factory) = rdpdr_factory.as_mut() {
+            factory.set_sender(ev_sender.clone());
+        }
+
+        Self {
+            opts: self.opts.clone(),
+            handler: Arc::new(Mutex::new(factory.build_input_handler(session))),
+            display: Arc::new(Mutex::new(factory.build_display(session))),
+            static_channels: StaticChannelSet::new(),
+            sound_factory,
+            cliprdr_factory,
+            rdpdr_factory,
+            audio_input_factory: self.audio_input_factory.clone(),
+            handler_factory: None,
+            ev_sender,
+            ev_receiver: Arc::new(Mutex::new(ev_receiver)),
+            creds: self.creds.clone(),
+            local_addr: self.local_addr,
+            gfx: None,
+            damage_counters: Arc::new(DamageCounters::default()),
+            denied: false,
+        }
+    }
+
+    /// Connection over the session limit, accepted only to be denied
+    fn denied_connection(&self) -> Self {
+        let (ev_sender, ev_receiver) = ServerEvent::create_channel();
+
+        Self {
+            opts: self.opts.clone(),
+            handler: Arc::new(Mutex::new(Box::new(builder::NoopInputHandler))),
+            display: Arc::new(Mutex::new(Box::new(builder::NoopDisplay))),
+            static_channels: StaticChannelSet::new(),
+            sound_factory: None,
+            cliprdr_factory: None,
+            rdpdr_factory: None,
+            audio_input_factory: None,
+            handler_factory: None,
+            ev_sender,
+            ev_receiver: Arc::new(Mutex::new(ev_receiver)),
+            creds: self.creds.clone(),
+            local_addr: self.local_addr,
+            gfx: None,
+            damage_counters: Arc::new(DamageCounters::default()),
+            denied: true,
         }
     }
 
@@ -267,21 +415,53 @@ impl RdpServer {
             acceptor.attach_static_channel(RdpsndServer::new(backend));
         }
 
+        if let Some(factory) = self.rdpdr_factory.as_deref() {
+            let backend = factory.build_backend();
+
+            acceptor.attach_static_channel(RdpdrServer::new(backend));
+        }
+
         let dcs_backend = DisplayControlBackend::new(Arc::clone(&self.display));
-        let dvc = dvc::DrdynvcServer::new()
+        let mut dvc = dvc::DrdynvcServer::new()
             .with_dynamic_channel(AInputHandler {
                 handler: Arc::clone(&self.handler),
             })
             .with_dynamic_channel(DisplayControlServer::new(Box::new(dcs_backend)));
+
+        if let Some(factory) = self.audio_input_factory.as_deref() {
+            dvc = dvc.with_dynamic_channel(AudioInputServer::new(factory.build_backend()));
+        }
+
+        if self.opts.with_gfx {
+            let gfx = GfxHandle::new();
+            dvc = dvc.with_dynamic_channel(GfxServer::new(gfx.clone()));
+            self.gfx = Some(gfx);
+        }
+
         acceptor.attach_static_channel(dvc);
     }
 
-    pub async fn run_connection(&mut self, stream: TcpStream) -> Result<()> {
+    /// Serves a client connection, until the client disconnects
+    ///
+    /// The stream can be any transport already accepted by the caller: TCP or Unix domain sockets,
+    /// WebSocket streams, in-memory streams… `peer_addr` is the address of the client, when known
+    /// (e.g. from the PROXY protocol).
+    pub async fn run_connection<S>(&mut self, stream: S, peer_addr: Option<SocketAddr>) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
         let framed = TokioFramed::new(stream);
+        self.damage_counters = Arc::new(DamageCounters::default());
 
         let size = self.display.lock().await.size().await;
         let capabilities = capabilities::capabilities(&self.opts, size);
         let mut acceptor = Acceptor::new(self.opts.security.flag(), size, capabilities, self.creds.clone());
+        if let Some(backend) = &self.opts.auth_backend {
+            acceptor.set_auth_backend(Arc::clone(backend));
+        }
+        if let Some(peer_addr) = peer_addr {
+            acceptor.set_client_address(peer_addr);
+        }
 
         self.attach_channels(&mut acceptor);
 
@@ -310,14 +490,14 @@ impl RdpServer {
                 if let RdpServerSecurity::Hybrid((_, pub_key)) = &self.opts.security {
                     // how to get the client name?
                     // doesn't seem to matter yet
-                    let client_name = framed.get_inner().0.get_ref().0.peer_addr()?.to_string();
+                    let client_name = peer_addr.map_or_else(|| "unknown".to_owned(), |addr| addr.to_string());
 
                     ironrdp_acceptor::accept_credssp(
                         &mut framed,
                         &mut acceptor,
                         client_name.into(),
                         pub_key.clone(),
-                        None,
+                        self.opts.kerberos_config.clone(),
                     )
                     .await?;
                 }
@@ -340,6 +520,13 @@ impl RdpServer {
         debug!("Listening for connections on {local_addr}");
         self.local_addr = Some(local_addr);
 
+        if let Some(factory) = self.handler_factory.clone() {
+            if self.cliprdr_factory.is_some() || self.sound_factory.is_some() || self.rdpdr_factory.is_some() {
+                warn!("The channel factories of the connections served concurrently are built by the handler factory");
+            }
+            return self.run_concurrent(listener, factory.as_ref()).await;
+        }
+
         loop {
             let ev_receiver = Arc::clone(&self.ev_receiver);
             let mut ev_receiver = ev_receiver.lock().await;
@@ -356,6 +543,9 @@ impl RdpServer {
                         ServerEvent::SetCredentials(creds) => {
                             self.set_credentials(Some(creds));
                         }
+                        ServerEvent::GetDamageStats(tx) => {
+                            let _ = tx.send(self.damage_counters.stats());
+                        }
                         ev => {
                             debug!("Unexpected event {:?}", ev);
                         }
@@ -364,7 +554,7 @@ impl RdpServer {
                 Ok((stream, peer)) = listener.accept() => {
                     debug!(?peer, "Received connection");
                     drop(ev_receiver);
-                    if let Err(error) = self.run_connection(stream).await {
+                    if let Err(error) = self.run_connection(stream, Some(peer)).await {
                         error!(?error, "Connection error");
                     }
                     self.static_channels = StaticChannelSet::new();
@@ -376,6 +566,129 @@ impl RdpServer {
         Ok(())
     }
 
+    async fn run_concurrent(&mut self, listener: TcpListener, factory: &dyn RdpServerHandlerFactory) -> Result<()> {
+        let mut tasks = JoinSet::new();
+        let mut denied = JoinSet::new();
+        let mut sessions: HashMap<SessionId, Session> = HashMap::new();
+        let mut next_session_id = 0;
+
+        let ev_receiver = Arc::clone(&self.ev_receiver);
+        let mut ev_receiver = ev_receiver.lock().await;
+
+        loop {
+            tokio::select! {
+                Some(event) = ev_receiver.recv() => {
+                    match event {
+                        ServerEvent::Quit(reason) => {
+                            debug!("Got quit event {reason}");
+                            break;
+                        }
+                        ServerEvent::GetLocalAddr(tx) => {
+                            let _ = tx.send(self.local_addr);
+                        }
+                        ServerEvent::SetCredentials(creds) => {
+                            self.set_credentials(Some(creds));
+                        }
+                        ServerEvent::GetSessions(tx) => {
+                            let _ = tx.send(sessions.values().map(|session| session.info.clone()).collect());
+                        }
+                        ServerEvent::GetSessionDamageStats(id, tx) => {
+                            let _ = tx.send(sessions.get(&id).map(|session| session.damage_counters.stats()));
+                        }
+                        ServerEvent::DisconnectSession(id) => {
+                            if let Some(session) = sessions.get(&id) {
+                                debug!(%id, "Disconnecting session");
+                                // The connection sends the Disconnect Provider Ultimatum to the client, once
+                                // it is active. It is closed regardless after a while.
+                                let quit = ServerEvent::Quit(format!("session {id} disconnected"));
+                                if session.ev_sender.send(quit).is_err() {
+                                    session.task.abort();
+                                } else {
+                                    let task = session.task.clone();
+                                    task::spawn(async move {
+                                        tokio::time::sleep(DISCONNECT_TIMEOUT).await;
+                                        task.abort();
+                                    });
+                                }
+                            } else {
+                                warn!(%id, "Unknown session");
+                            }
+                        }
+                        ev => {
+                            debug!("Unexpected event {:?}", ev);
+                        }
+                    }
+                },
+                Ok((stream, peer)) = listener.accept() => {
+                    debug!(?peer, "Received connection");
+
+                    if let Some(max) = self.opts.max_sessions.filter(|max| sessions.len() >= *max) {
+                        warn!(?peer, max, "Maximum number of sessions reached, denying the connection");
+                        let mut connection = self.denied_connection();
+                        denied.spawn(async move {
+                            if let Err(error) = connection.run_connection(stream, Some(peer)).await {
+                                debug!(?error, ?peer, "Denied connection error");
+                            }
+                        });
+                        continue;
+                    }
+
+                    next_session_id += 1;
+                    let info = SessionInfo {
+                        id: SessionId(next_session_id),
+                        peer_addr: peer,
+                    };
+
+                    let mut connection = self.new_connection(factory, &info);
+                    let ev_sender = connection.ev_sender.clone();
+                    let damage_counters = Arc::clone(&connection.damage_counters);
+                    let id = info.id;
+                    let task = tasks.spawn(async move {
+                        if let Err(error) = connection.run_connection(stream, Some(peer)).await {
+                            error!(?error, %id, "Connection error");
+                        }
+                    });
+
+                    sessions.insert(
+                        id,
+                        Session {
+                            info,
+                            task,
+                            ev_sender,
+                            damage_counters,
+                        },
+                    );
+                }
+                Some(result) = tasks.join_next_with_id() => {
+                    let task_id = match result {
+                        Ok((task_id, ())) => task_id,
+                        Err(error) => {
+                            if !error.is_cancelled() {
+                                error!(?error, "Connection task failed");
+                            }
+                            error.id()
+                        }
+                    };
+
+                    sessions.retain(|id, session| {
+                        let ended = session.task.id() == task_id;
+                        if ended {
+                            debug!(%id, "Session ended");
+                        }
+                        !ended
+                    });
+                }
+                Some(_) = denied.join_next() => {}
+                else => break,
+            }
+        }
+
+        tasks.shutdown().await;
+        denied.shutdown().await;
+
+        Ok(())
+    }
+
     pub fn get_svc_processor<T: SvcProcessor + 'static>(&mut self) -> Option<&mut T> {
         self.static_channels
             .get_by_type_mut::<T>()
@@ -443,6 +756,9 @@ impl RdpServer {
             DisplayUpdate::ColorPointer(ptr) => encoder.color_pointer(ptr),
             DisplayUpdate::HidePointer => encoder.hide_pointer(),
             DisplayUpdate::DefaultPointer => encoder.default_pointer(),
+            DisplayUpdate::Avc420(_) | DisplayUpdate::Avc444(_) => {
+                bail!("AVC updates are only sent on the graphics pipeline")
+            }
         }
         .context("error during update encoding")?;
 
@@ -474,6 +790,10 @@ impl RdpServer {
             match event {
                 ServerEvent::Quit(reason) => {
                     debug!("Got quit event: {reason}");
+                    let ultimatum = mcs::McsMessage::DisconnectProviderUltimatum(
+                        mcs::DisconnectProviderUltimatum::from_reason(mcs::DisconnectReason::ProviderInitiated),
+                    );
+                    writer.write_all(&encode_vec(&X224(ultimatum))?).await?;
                     return Ok(RunState::Disconnect);
                 }
                 ServerEvent::GetLocalAddr(tx) => {
@@ -482,6 +802,14 @@ impl RdpServer {
                 ServerEvent::SetCredentials(creds) => {
                     self.set_credentials(Some(creds));
                 }
+                ServerEvent::GetDamageStats(tx) => {
+                    let _ = tx.send(self.damage_counters.stats());
+                }
+                ev @ (ServerEvent::GetSessions(_)
+                | ServerEvent::DisconnectSession(_)
+                | ServerEvent::GetSessionDamageStats(..)) => {
+                    debug!("Unexpected event {:?}", ev);
+                }
                 ServerEvent::Rdpsnd(s) => {
                     let Some(rdpsnd) = self.get_svc_processor::<RdpsndServer>() else {
                         warn!("No rdpsnd channel, dropping event");
@@ -510,6 +838,35 @@ impl RdpServer {
                     let data = server_encode_svc_messages(msgs.into(), channel_id, user_channel_id)?;
                     writer.write_all(&data).await?;
                 }
+                ServerEvent::Rdpdr(message) => {
+                    let Some(rdpdr) = self.get_svc_processor::<RdpdrServer>() else {
+                        warn!("No rdpdr channel, dropping event");
+                        continue;
+                    };
+                    let msgs = match message {
+                        RdpdrServerMessage::GetDevices(tx) => {
+                            let _ = tx.send(rdpdr.devices().to_vec());
+                            continue;
+                        }
+                        RdpdrServerMessage::DriveRequest(request, tx) => {
+                            let completion = Box::new(move |response| {
+                                let _ = tx.send(response);
+                            });
+                            match rdpdr.drive_request(request, completion) {
+                                Ok(msgs) => msgs,
+                                Err(error) => {
+                                    warn!(%error, "Failed to send drive request");
+                                    continue;
+                                }
+                            }
+                        }
+                    };
+                    let channel_id = self
+                        .get_channel_id_by_type::<RdpdrServer>()
+                        .ok_or_else(|| anyhow!("SVC channel not found"))?;
+                    let data = server_encode_svc_messages(msgs.into(), channel_id, user_channel_id)?;
+                    writer.write_all(&data).await?;
+                }
                 ServerEvent::Clipboard(c) => {
                     let Some(cliprdr) = self.get_svc_processor::<CliprdrServer>() else {
                         warn!("No clipboard channel, dropping event");
@@ -519,6 +876,8 @@ impl RdpServer {
                         ClipboardMessage::SendInitiateCopy(formats) => cliprdr.initiate_copy(&formats),
                         ClipboardMessage::SendFormatData(data) => cliprdr.submit_format_data(data),
                         ClipboardMessage::SendInitiatePaste(format) => cliprdr.initiate_paste(format),
+                        ClipboardMessage::SendFileContents(response) => cliprdr.submit_file_contents(response),
+                        ClipboardMessage::SendFileContentsRequest(request) => cliprdr.request_file_contents(request),
                         ClipboardMessage::Error(error) => {
                             error!(?error, "Handling clipboard event");
                             continue;
@@ -537,27 +896,32 @@ impl RdpServer {
         Ok(RunState::Continue)
     }
 
+    /// Returns the writer along with the state, for the Deactivation-Reactivation Sequence
     async fn client_loop<R, W>(
         &mut self,
         reader: &mut Framed<R>,
-        writer: &mut Framed<W>,
+        writer: TokioFramed<W>,
         io_channel_id: u16,
         user_channel_id: u16,
         mut encoder: UpdateEncoder,
-    ) -> Result<RunState>
+        mut gfx: Option<GfxOutput>,
+    ) -> Result<(RunState, TokioFramed<W>)>
     where
         R: FramedRead,
-        W: FramedWrite,
+        W: AsyncWrite + Unpin + Send,
     {
         debug!("Starting client loop");
         let mut display_updates = self.display.lock().await.updates().await?;
-        let mut writer = SharedWriter::new(writer);
+        let desktop_size = self.display.lock().await.size().await;
+        let mut damage = DamageTracker::new(desktop_size, Arc::clone(&self.damage_counters));
+        let shared_writer = SharedWriter::new(writer);
+        let mut writer = shared_writer.clone();
         let mut display_writer = writer.clone();
         let mut event_writer = writer.clone();
         let ev_receiver = Arc::clone(&self.ev_receiver);
-        let s = Rc::new(Mutex::new(self));
+        let s = Arc::new(Mutex::new(self));
 
-        let this = Rc::clone(&s);
+        let this = Arc::clone(&s);
         let dispatch_pdu = async move {
             loop {
                 let (action, bytes) = reader.read_pdu().await?;
@@ -576,22 +940,72 @@ impl RdpServer {
             let mut buffer = vec![0u8; 4096];
             loop {
                 if let Some(update) = display_updates.next_update().await {
-                    match Self::dispatch_display_update(
-                        update,
-                        &mut display_writer,
-                        user_channel_id,
-                        io_channel_id,
-                        &mut buffer,
-                        encoder,
-                    )
-                    .await?
-                    {
-                        (RunState::Continue, enc) => {
-                            encoder = enc;
+                    // The surface is recreated with the next frame, along with the content of the client.
+                    if gfx.as_ref().is_some_and(GfxOutput::surface_outdated) {
+                        damage.reset();
+                    }
+
+                    let updates = match (update, gfx.as_mut()) {
+                        (DisplayUpdate::Bitmap(bitmap), gfx) => {
+                            let bitmaps = time_warn!("Damage tracking", 10, damage.damaged(bitmap));
+
+                            let bitmaps = match gfx {
+                                Some(gfx) if !bitmaps.is_empty() => {
+                                    match gfx.send_bitmaps(bitmaps, &mut display_writer).await? {
+                                        // The graphics pipeline is not active (yet).
+                                        Some(bitmaps) => bitmaps,
+                                        None => continue,
+                                    }
+                                }
+                                _ => bitmaps,
+                            };
+
+                            bitmaps.into_iter().map(DisplayUpdate::Bitmap).collect()
+                        }
+                        (DisplayUpdate::Avc420(update), gfx) => {
+                            let update = AvcUpdate::Avc420(update);
+                            dispatch_avc_update(
+                                update,
+                                gfx,
+                                &mut damage,
+                                &mut display_writer,
+                                display_updates.as_mut(),
+                            )
+                            .await?;
                             continue;
                         }
-                        (state, _) => {
-                            break Ok(state);
+                        (DisplayUpdate::Avc444(update), gfx) => {
+                            let update = AvcUpdate::Avc444(update);
+                            dispatch_avc_update(
+                                update,
+                                gfx,
+                                &mut damage,
+                                &mut display_writer,
+                                display_updates.as_mut(),
+                            )
+                            .await?;
+                            continue;
+                        }
+                        (update, _) => vec![update],
+                    };
+
+                    for update in updates {
+                        match Self::dispatch_display_update(
+                            update,
+                            &mut display_writer,
+                            user_channel_id,
+                            io_channel_id,
+                            &mut buffer,
+                            encoder,
+                        )
+                        .await?
+                        {
+                            (RunState::Continue, enc) => {
+                                encoder = enc;
+                            }
+                            (state, _) => {
+                                return Ok(state);
+                            }
                         }
                     }
                 } else {
@@ -600,7 +1014,7 @@ impl RdpServer {
             }
         };
 
-        let this = Rc::clone(&s);
+        let this = Arc::clone(&s);
         let mut ev_receiver = ev_receiver.lock().await;
         let dispatch_events = async move {
             let mut events = Vec::with_capacity(100);
@@ -624,32 +1038,34 @@ impl RdpServer {
             }
         };
 
-        let state = tokio::select!(
+        let state: Result<RunState> = tokio::select!(
             state = dispatch_pdu => state,
             state = dispatch_display => state,
             state = dispatch_events => state,
         );
 
         debug!("End of client loop: {state:?}");
-        state
+
+        // The tasks of the loop are dropped, along with their writers.
+        Ok((state?, shared_writer.into_inner()?))
     }
 
     async fn client_accepted<R, W>(
         &mut self,
         reader: &mut Framed<R>,
-        writer: &mut Framed<W>,
+        mut writer: TokioFramed<W>,
         result: AcceptorResult,
-    ) -> Result<RunState>
+    ) -> Result<(RunState, TokioFramed<W>)>
     where
         R: FramedRead,
-        W: FramedWrite,
+        W: AsyncWrite + Unpin + Send,
     {
         debug!("Client accepted");
 
         if !result.input_events.is_empty() {
             debug!("Handling input event backlog from acceptor sequence");
             self.handle_input_backlog(
-                writer,
+                &mut writer,
                 result.io_channel_id,
                 result.user_channel_id,
                 result.input_events,
@@ -659,6 +1075,10 @@ impl RdpServer {
 
         self.static_channels = result.static_channels;
         if !result.reactivation {
+            if let Some(logon) = &result.logon {
+                self.display.lock().await.logon(logon).await;
+            }
+
             for (_type_id, channel, channel_id) in self.static_channels.iter_mut() {
                 debug!(?channel, ?channel_id, "Start");
                 let Some(channel_id) = channel_id else {
@@ -672,6 +1092,7 @@ impl RdpServer {
 
         let mut rfxcodec = None;
         let mut surface_flags = CmdFlags::empty();
+        let mut client_size = None;
         for c in result.capabilities {
             match c {
                 CapabilitySet::General(c) => {
@@ -681,6 +1102,11 @@ impl RdpServer {
                     }
                 }
                 CapabilitySet::Bitmap(b) => {
+                    client_size = Some(DesktopSize {
+                        width: b.desktop_width,
+                        height: b.desktop_height,
+                    });
+
                     if !b.desktop_resize_flag {
                         debug!("Desktop resize is not supported by the client");
                         continue;
@@ -740,14 +1166,34 @@ impl RdpServer {
             }
         }
 
-        let encoder = UpdateEncoder::new(surface_flags, rfxcodec);
+        if let Some(compression_type) = result.compression_type {
+            debug!(?compression_type, "Bulk compression enabled");
+        }
+
+        let encoder = UpdateEncoder::new(surface_flags, rfxcodec, result.compression_type);
 
-        let state = self
-            .client_loop(reader, writer, result.io_channel_id, result.user_channel_id, encoder)
-            .await
-            .context("client loop failure")?;
+        let drdynvc_channel_id = self.get_channel_id_by_type::<dvc::DrdynvcServer>();
+        let gfx = match (self.gfx.clone(), drdynvc_channel_id, client_size) {
+            (Some(handle), Some(drdynvc_channel_id), Some(desktop_size)) => Some(GfxOutput::new(
+                handle,
+                GfxEncoder::new(self.opts.with_remote_fx),
+                desktop_size,
+                drdynvc_channel_id,
+                result.user_channel_id,
+            )),
+            _ => None,
+        };
 
-        Ok(state)
+        self.client_loop(
+            reader,
+            writer,
+            result.io_channel_id,
+            result.user_channel_id,
+            encoder,
+            gfx,
+        )
+        .await
+        .context("client loop failure")
     }
 
     async fn handle_input_backlog(
@@ -817,11 +1263,11 @@ impl RdpServer {
 
         match control.share_control_pdu {
             ShareControlPdu::Data(header) => match header.share_data_pdu {
-                rdp::headers::ShareDataPdu::Input(pdu) => {
+                ShareDataPdu::Input(pdu) => {
                     self.handle_input_event(pdu).await;
                 }
 
-                rdp::headers::ShareDataPdu::ShutdownRequest => {
+                ShareDataPdu::ShutdownRequest => {
                     return Ok(true);
                 }
 
@@ -911,16 +1357,22 @@ impl RdpServer {
 
     async fn accept_finalize<S>(&mut self, mut framed: TokioFramed<S>, mut acceptor: Acceptor) -> Result<()>
     where
-        S: AsyncRead + AsyncWrite + Sync + Send + Unpin,
+        S: AsyncRead + AsyncWrite + Send + Unpin,
     {
         loop {
-            let (new_framed, result) = ironrdp_acceptor::accept_finalize(framed, &mut acceptor)
+            let (mut new_framed, result) = ironrdp_acceptor::accept_finalize(framed, &mut acceptor)
                 .await
                 .context("failed to accept client during finalize")?;
 
-            let (mut reader, mut writer) = split_tokio_framed(new_framed);
+            if self.denied {
+                deny_connection(result.io_channel_id, result.user_channel_id, &mut new_framed).await?;
+                break;
+            }
+
+            let (mut reader, writer) = split_tokio_framed(new_framed);
+            let (state, writer) = self.client_accepted(&mut reader, writer, result).await?;
 
-            match self.client_accepted(&mut reader, &mut writer, result).await? {
+            match state {
                 RunState::Continue => {
                     unreachable!();
                 }
@@ -950,6 +1402,38 @@ impl RdpServer {
     }
 }
 
+/// AVC updates are only sent on the graphics pipeline, the display is told when they are dropped.
+///
+/// The frames are not tracked, the content of the client they cover is forgotten.
+async fn dispatch_avc_update(
+    update: AvcUpdate,
+    gfx: Option<&mut GfxOutput>,
+    damage: &mut DamageTracker,
+    writer: &mut impl FramedWrite,
+    display_updates: &mut dyn RdpServerDisplayUpdates,
+) -> Result<()> {
+    let (left, top, width, height) = match &update {
+        AvcUpdate::Avc420(update) => (update.left, update.top, update.width, update.height),
+        AvcUpdate::Avc444(update) => (update.left, update.top, update.width, update.height),
+    };
+
+    let support = match gfx {
+        Some(gfx) => {
+            if gfx.send_avc(update, writer).await? {
+                damage.invalidate(left, top, width, height);
+                return Ok(());
+            }
+
+            gfx.avc_support()
+        }
+        None => AvcSupport::default(),
+    };
+
+    display_updates.avc_unsupported(support);
+
+    Ok(())
+}
+
 async fn deactivate_all(
     io_channel_id: u16,
     user_channel_id: u16,
@@ -972,24 +1456,59 @@ async fn deactivate_all(
     Ok(())
 }
 
-struct SharedWriter<'w, W: FramedWrite> {
-    writer: Rc<Mutex<&'w mut W>>,
+/// Graceful disconnection of an accepted client, telling it the server denied the connection
+async fn deny_connection(
+    io_channel_id: u16,
+    user_channel_id: u16,
+    writer: &mut impl FramedWrite,
+) -> Result<(), anyhow::Error> {
+    let pdu = ShareDataPdu::ServerSetErrorInfo(ServerSetErrorInfoPdu(ErrorInfo::ProtocolIndependentCode(
+        ProtocolIndependentCode::ServerDeniedConnection,
+    )));
+    let pdu = rdp::headers::ShareControlHeader {
+        share_id: 0,
+        pdu_source: io_channel_id,
+        share_control_pdu: ShareControlPdu::Data(ShareDataHeader {
+            share_data_pdu: pdu,
+            stream_priority: StreamPriority::Undefined,
+            compression_flags: CompressionFlags::empty(),
+            compression_type: CompressionType::K8,
+        }),
+    };
+    let user_data = encode_vec(&pdu)?.into();
+    let pdu = SendDataIndication {
+        initiator_id: user_channel_id,
+        channel_id: io_channel_id,
+        user_data,
+    };
+    writer.write_all(&encode_vec(&X224(pdu))?).await?;
+
+    let ultimatum = mcs::McsMessage::DisconnectProviderUltimatum(mcs::DisconnectProviderUltimatum::from_reason(
+        mcs::DisconnectReason::ProviderInitiated,
+    ));
+    writer.write_all(&encode_vec(&X224(ultimatum))?).await?;
+    Ok(())
+}
+
+/// Writer shared by the tasks of the client loop
+struct SharedWriter<W> {
+    writer: Arc<Mutex<TokioFramed<W>>>,
 }
 
-impl<W: FramedWrite> Clone for SharedWriter<'_, W> {
+impl<W> Clone for SharedWriter<W> {
     fn clone(&self) -> Self {
         Self {
-            writer: Rc::clone(&self.writer),
+            writer: Arc::clone(&self.writer),
         }
     }
 }
 
-impl<W> FramedWrite for SharedWriter<'_, W>
+impl<W> FramedWrite for SharedWriter<W>
 where
-    W: FramedWrite,
+    W: AsyncWrite + Unpin + Send,
 {
     type WriteAllFut<'write>
-        = core::pin::Pin<Box<dyn core::future::Future<Output = std::io::Result<()>> + 'write>>
+        = core::pin::Pin<Box<dyn core::future::Future<Output = std::io::Result<()>> + Send + 'write>>
     where
         Self: 'write;
 
@@ -1003,10 +1522,17 @@ where
     }
 }
 
-impl<'a, W: FramedWrite> SharedWriter<'a, W> {
-    fn new(writer: &'a mut W) -> Self {
+impl<W> SharedWriter<W> {
+    fn new(writer: TokioFramed<W>) -> Self {
         Self {
-            writer: Rc::new(Mutex::new(writer)),
+            writer: Arc::new(Mutex::new(writer)),
         }
     }
+
+    /// Returns the writer, once the other clones are dropped
+    fn into_inner(self) -> Result<TokioFramed<W>> {
+        Arc::try_unwrap(self.writer)
+            .map(Mutex::into_inner)
+            .map_err(|_| anyhow!("the writer is still shared"))
+    }
 }
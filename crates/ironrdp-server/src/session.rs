@@ -0,0 +1,52 @@
+use core::fmt;
+use std::net::SocketAddr;
+
+use crate::clipboard::CliprdrServerFactory;
+use crate::display::RdpServerDisplay;
+use crate::handler::RdpServerInputHandler;
+use crate::rdpdr::RdpdrServerFactory;
+use crate::sound::SoundServerFactory;
+
+/// Identifier of a client connection, unique for the lifetime of the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SessionId(pub u32);
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Client connection served by the server
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id: SessionId,
+    pub peer_addr: SocketAddr,
+}
+
+/// Builds the handlers of each client connection, when the server serves several clients
+/// concurrently
+///
+/// The channel factories are built for each connection as well, and only send the events of their
+/// backends to this connection. The clipboard, audio output and device redirection factories given to the
+/// builder are not used in this case.
+pub trait RdpServerHandlerFactory: Send + Sync {
+    fn build_input_handler(&self, session: &SessionInfo) -> Box<dyn RdpServerInputHandler>;
+
+    fn build_display(&self, session: &SessionInfo) -> Box<dyn RdpServerDisplay>;
+
+    /// Builds the clipboard factory of the connection, if the clipboard is redirected
+    fn build_cliprdr_factory(&self, _session: &SessionInfo) -> Option<Box<dyn CliprdrServerFactory>> {
+        None
+    }
+
+    /// Builds the audio output factory of the connection, if the audio is redirected
+    fn build_sound_factory(&self, _session: &SessionInfo) -> Option<Box<dyn SoundServerFactory>> {
+        None
+    }
+
+    /// Builds the device redirection factory of the connection, if the drives are redirected
+    fn build_rdpdr_factory(&self, _session: &SessionInfo) -> Option<Box<dyn RdpdrServerFactory>> {
+        None
+    }
+}
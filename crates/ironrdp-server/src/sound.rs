@@ -2,6 +2,6 @@ pub use ironrdp_rdpsnd::server::{RdpsndServerHandler, RdpsndServerMessage};
 
 use crate::ServerEventSender;
 
-pub trait SoundServerFactory: ServerEventSender {
+pub trait SoundServerFactory: ServerEventSender + Send {
     fn build_backend(&self) -> Box<dyn RdpsndServerHandler>;
 }
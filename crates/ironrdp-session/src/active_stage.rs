@@ -1,10 +1,11 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use ironrdp_connector::connection_activation::ConnectionActivationSequence;
-use ironrdp_connector::ConnectionResult;
+use ironrdp_connector::{AutoReconnectCookie, ConnectionResult, StandardSecurity};
 use ironrdp_core::WriteBuf;
 use ironrdp_displaycontrol::client::DisplayControlClient;
 use ironrdp_dvc::{DrdynvcClient, DvcProcessor, DynamicVirtualChannel};
+use ironrdp_graphics::bulk;
 use ironrdp_graphics::pointer::DecodedPointer;
 use ironrdp_pdu::geometry::InclusiveRectangle;
 use ironrdp_pdu::input::fast_path::{FastPathInput, FastPathInputEvent};
@@ -12,14 +13,18 @@ use ironrdp_pdu::rdp::headers::ShareDataPdu;
 use ironrdp_pdu::{mcs, Action};
 use ironrdp_svc::{SvcProcessor, SvcProcessorMessages};
 
+use crate::bitmap_cache::BitmapCache;
 use crate::fast_path::UpdateKind;
+use crate::gfx::GfxClient;
 use crate::image::DecodedImage;
 use crate::{fast_path, x224, SessionError, SessionErrorExt, SessionResult};
 
 pub struct ActiveStage {
     x224_processor: x224::Processor,
     fast_path_processor: fast_path::Processor,
+    bulk_decompressor: bulk::Decompressor,
     no_server_pointer: bool,
+    standard_security: Option<StandardSecurity>,
 }
 
 impl ActiveStage {
@@ -42,7 +47,9 @@ impl ActiveStage {
         Self {
             x224_processor,
             fast_path_processor,
+            bulk_decompressor: bulk::Decompressor::new(),
             no_server_pointer: connection_result.no_server_pointer,
+            standard_security: connection_result.standard_security,
         }
     }
 
@@ -69,7 +76,7 @@ impl ActiveStage {
         // PERF: unnecessary copy
         let fastpath_input = FastPathInput(events.to_vec());
         let frame = ironrdp_core::encode_vec(&fastpath_input).map_err(SessionError::encode)?;
-        output.push(ActiveStageOutput::ResponseFrame(frame));
+        output.push(ActiveStageOutput::ResponseFrame(self.encrypt(frame)?));
 
         // If pointer rendering is disabled - we can skip the rest
         if self.no_server_pointer {
@@ -104,10 +111,23 @@ impl ActiveStage {
         action: Action,
         frame: &[u8],
     ) -> SessionResult<Vec<ActiveStageOutput>> {
+        let decrypted;
+        let frame = match self.standard_security.as_mut() {
+            Some(security) => {
+                decrypted = security
+                    .decrypt_frame(frame)
+                    .map_err(|e| custom_err!("Standard RDP Security", e))?;
+                decrypted.as_slice()
+            }
+            None => frame,
+        };
+
         let (mut stage_outputs, processor_updates) = match action {
             Action::FastPath => {
                 let mut output = WriteBuf::new();
-                let processor_updates = self.fast_path_processor.process(image, frame, &mut output)?;
+                let processor_updates =
+                    self.fast_path_processor
+                        .process(image, frame, &mut output, &mut self.bulk_decompressor)?;
                 (
                     vec![ActiveStageOutput::ResponseFrame(output.into_inner())],
                     processor_updates,
@@ -116,14 +136,34 @@ impl ActiveStage {
             Action::X224 => {
                 let outputs = self
                     .x224_processor
-                    .process(frame)?
+                    .process(frame, &mut self.bulk_decompressor)?
                     .into_iter()
                     .map(TryFrom::try_from)
                     .collect::<Result<Vec<_>, _>>()?;
-                (outputs, Vec::new())
+
+                let mut processor_updates = Vec::new();
+
+                // Graphics pipeline messages are received on a dynamic virtual channel.
+                if let Some(gfx) = self
+                    .x224_processor
+                    .get_dvc_mut::<GfxClient>()
+                    .and_then(|dvc| dvc.channel_processor_downcast_mut::<GfxClient>())
+                {
+                    if let Some(region) = gfx.update_image(image)? {
+                        processor_updates.push(UpdateKind::Region(region));
+                    }
+                }
+
+                (outputs, processor_updates)
             }
         };
 
+        for output in stage_outputs.iter_mut() {
+            if let ActiveStageOutput::ResponseFrame(frame) = output {
+                *frame = self.encrypt(core::mem::take(frame))?;
+            }
+        }
+
         for update in processor_updates {
             match update {
                 UpdateKind::None => {}
@@ -152,6 +192,12 @@ impl ActiveStage {
         self.fast_path_processor = processor;
     }
 
+    /// Sets the bitmap caches of the drawing orders, usually loaded with the persistent bitmaps
+    /// advertised during the connection.
+    pub fn set_bitmap_cache(&mut self, bitmap_cache: BitmapCache) {
+        self.fast_path_processor.set_bitmap_cache(bitmap_cache);
+    }
+
     pub fn set_no_server_pointer(&mut self, no_server_pointer: bool) {
         self.no_server_pointer = no_server_pointer;
     }
@@ -162,17 +208,37 @@ impl ActiveStage {
     /// Client-side graceful shutdown is defined in [MS-RDPBCGR]
     ///
     /// [MS-RDPBCGR]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/27915739-8f77-487e-9927-55008af7fd68
-    pub fn graceful_shutdown(&self) -> SessionResult<Vec<ActiveStageOutput>> {
+    pub fn graceful_shutdown(&mut self) -> SessionResult<Vec<ActiveStageOutput>> {
         let mut frame = WriteBuf::new();
         self.x224_processor
             .encode_static(&mut frame, ShareDataPdu::ShutdownRequest)?;
 
-        Ok(vec![ActiveStageOutput::ResponseFrame(frame.into_inner())])
+        Ok(vec![ActiveStageOutput::ResponseFrame(
+            self.encrypt(frame.into_inner())?,
+        )])
     }
 
     /// Send a pdu on the static global channel. Typically used to send input events
-    pub fn encode_static(&self, output: &mut WriteBuf, pdu: ShareDataPdu) -> SessionResult<usize> {
-        self.x224_processor.encode_static(output, pdu)
+    pub fn encode_static(&mut self, output: &mut WriteBuf, pdu: ShareDataPdu) -> SessionResult<usize> {
+        if self.standard_security.is_none() {
+            return self.x224_processor.encode_static(output, pdu);
+        }
+
+        let mut frame = WriteBuf::new();
+        self.x224_processor.encode_static(&mut frame, pdu)?;
+
+        let frame = self.encrypt(frame.into_inner())?;
+        output.write_slice(&frame);
+
+        Ok(frame.len())
+    }
+
+    /// Returns the Standard RDP Security state, when the connection uses it
+    ///
+    /// Required to drive a Deactivation-Reactivation Sequence (see
+    /// [`ironrdp_connector::standard_security::SecuredSequence`]).
+    pub fn standard_security_mut(&mut self) -> Option<&mut StandardSecurity> {
+        self.standard_security.as_mut()
     }
 
     pub fn get_svc_processor<T: SvcProcessor + 'static>(&mut self) -> Option<&T> {
@@ -187,13 +253,28 @@ impl ActiveStage {
         self.x224_processor.get_dvc::<T>()
     }
 
+    pub fn get_dvc_mut<T: DvcProcessor + 'static>(&mut self) -> Option<&mut DynamicVirtualChannel> {
+        self.x224_processor.get_dvc_mut::<T>()
+    }
+
     /// Completes user's SVC request with data, required to sent it over the network and returns
     /// a buffer with encoded data.
     pub fn process_svc_processor_messages<C: SvcProcessor + 'static>(
-        &self,
+        &mut self,
         messages: SvcProcessorMessages<C>,
     ) -> SessionResult<Vec<u8>> {
-        self.x224_processor.process_svc_processor_messages(messages)
+        let frame = self.x224_processor.process_svc_processor_messages(messages)?;
+        self.encrypt(frame)
+    }
+
+    /// Encrypts frames to be sent to the server, when Standard RDP Security is in effect
+    fn encrypt(&mut self, frame: Vec<u8>) -> SessionResult<Vec<u8>> {
+        match self.standard_security.as_mut() {
+            Some(security) if !frame.is_empty() => security
+                .encrypt_frames(&frame)
+                .map_err(|e| custom_err!("Standard RDP Security", e)),
+            _ => Ok(frame),
+        }
     }
 
     /// Fully encodes a resize request for sending over the Display Control Virtual Channel.
@@ -253,10 +334,16 @@ pub enum ActiveStageOutput {
     GraphicsUpdate(InclusiveRectangle),
     PointerDefault,
     PointerHidden,
-    PointerPosition { x: u16, y: u16 },
-    PointerBitmap(Rc<DecodedPointer>),
+    PointerPosition {
+        x: u16,
+        y: u16,
+    },
+    PointerBitmap(Arc<DecodedPointer>),
     Terminate(GracefulDisconnectReason),
     DeactivateAll(Box<ConnectionActivationSequence>),
+    /// The auto-reconnect cookie of the session, to set in [`ironrdp_connector::Config::auto_reconnect`]
+    /// when reconnecting after a network failure
+    AutoReconnectCookie(AutoReconnectCookie),
 }
 
 impl TryFrom<x224::ProcessorOutput> for ActiveStageOutput {
@@ -278,6 +365,7 @@ impl TryFrom<x224::ProcessorOutput> for ActiveStageOutput {
                 Ok(Self::Terminate(desc))
             }
             x224::ProcessorOutput::DeactivateAll(cas) => Ok(Self::DeactivateAll(cas)),
+            x224::ProcessorOutput::AutoReconnectCookie(cookie) => Ok(Self::AutoReconnectCookie(cookie)),
         }
     }
 }
@@ -0,0 +1,315 @@
+//! Bitmap caches of the drawing orders
+//!
+//! Bitmaps are stored in cell caches, and addressed by their cache and cell index. The bitmaps of the
+//! persistent cell caches are also identified by a 64-bit key, and saved in a [`PersistentBitmapStore`]
+//! so that the server doesn't have to send them again in the next sessions.
+//!
+//! The persistent bitmaps are loaded in the cells of their cache from the first one, and their keys are
+//! sent to the server in the Persistent Key List PDUs during the connection finalization. The bitmaps
+//! which do not fit in their cache, or whose cell is overwritten, are removed from the store:
+//!
+//! ```ignore
+//! let mut bitmap_cache = BitmapCache::new(&bitmap_cache_config);
+//! bitmap_cache.load_persistent(Box::new(FileBitmapStore::new(directory_of_the_server)));
+//!
+//! config.bitmap_cache = Some(bitmap_cache.config());
+//!
+//! // Once connected…
+//! active_stage.set_bitmap_cache(bitmap_cache);
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use ironrdp_connector::{BitmapCacheConfig, CellCacheConfig};
+
+/// Bitmap of the bitmap caches, with pixels stored from top to bottom
+///
+/// Pixels are RGB values, or color table indices for 8 bpp bitmaps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedBitmap {
+    pub width: u16,
+    pub height: u16,
+    pub bpp: u8,
+    pub pixels: Vec<u32>,
+}
+
+/// Storage of the bitmaps of the persistent cell caches
+///
+/// A store should only hold the bitmaps sent by a single server.
+pub trait PersistentBitmapStore: Send {
+    /// Returns the keys of the bitmaps stored for a cell cache, the most recently stored first.
+    fn keys(&self, cache_id: u8) -> io::Result<Vec<u64>>;
+
+    fn load(&self, cache_id: u8, key: u64) -> io::Result<CachedBitmap>;
+
+    fn store(&mut self, cache_id: u8, key: u64, bitmap: &CachedBitmap) -> io::Result<()>;
+
+    /// Removes a bitmap evicted from its cell cache.
+    fn remove(&mut self, cache_id: u8, key: u64) -> io::Result<()>;
+}
+
+ironrdp_core::assert_obj_safe!(PersistentBitmapStore);
+
+/// [`PersistentBitmapStore`] keeping each bitmap in its own file
+///
+/// The bitmaps of a cell cache are stored in the `cache<id>` subdirectory of the store, in files named
+/// after their key.
+#[derive(Debug, Clone)]
+pub struct FileBitmapStore {
+    directory: PathBuf,
+}
+
+impl FileBitmapStore {
+    const HEADER_SIZE: usize = 2 /* width */ + 2 /* height */ + 1 /* bpp */;
+
+    /// Creates a store of the bitmaps in this directory, which should be specific to the server.
+    ///
+    /// The directory is created when the first bitmap is stored.
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    fn cache_directory(&self, cache_id: u8) -> PathBuf {
+        self.directory.join(format!("cache{cache_id}"))
+    }
+
+    fn path(&self, cache_id: u8, key: u64) -> PathBuf {
+        self.cache_directory(cache_id).join(format!("{key:016x}.bmp"))
+    }
+}
+
+impl PersistentBitmapStore for FileBitmapStore {
+    fn keys(&self, cache_id: u8) -> io::Result<Vec<u64>> {
+        let entries = match fs::read_dir(self.cache_directory(cache_id)) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error),
+        };
+
+        let mut keys = Vec::new();
+
+        for entry in entries {
+            let entry = entry?;
+
+            let key = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_suffix(".bmp"))
+                .and_then(|key| u64::from_str_radix(key, 16).ok());
+
+            if let Some(key) = key {
+                keys.push((entry.metadata()?.modified()?, key));
+            }
+        }
+
+        keys.sort_unstable_by(|a, b| b.cmp(a));
+
+        Ok(keys.into_iter().map(|(_, key)| key).collect())
+    }
+
+    fn load(&self, cache_id: u8, key: u64) -> io::Result<CachedBitmap> {
+        let data = fs::read(self.path(cache_id, key))?;
+
+        let invalid_data = || io::Error::new(io::ErrorKind::InvalidData, "invalid cached bitmap file");
+
+        if data.len() < Self::HEADER_SIZE {
+            return Err(invalid_data());
+        }
+
+        let (header, pixels) = data.split_at(Self::HEADER_SIZE);
+
+        let width = u16::from_le_bytes([header[0], header[1]]);
+        let height = u16::from_le_bytes([header[2], header[3]]);
+        let bpp = header[4];
+
+        if pixels.len() != usize::from(width) * usize::from(height) * 4 {
+            return Err(invalid_data());
+        }
+
+        let pixels = pixels
+            .chunks_exact(4)
+            .map(|pixel| u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]))
+            .collect();
+
+        Ok(CachedBitmap {
+            width,
+            height,
+            bpp,
+            pixels,
+        })
+    }
+
+    fn store(&mut self, cache_id: u8, key: u64, bitmap: &CachedBitmap) -> io::Result<()> {
+        fs::create_dir_all(self.cache_directory(cache_id))?;
+
+        let mut data = Vec::with_capacity(Self::HEADER_SIZE + bitmap.pixels.len() * 4);
+        data.extend_from_slice(&bitmap.width.to_le_bytes());
+        data.extend_from_slice(&bitmap.height.to_le_bytes());
+        data.push(bitmap.bpp);
+
+        for pixel in &bitmap.pixels {
+            data.extend_from_slice(&pixel.to_le_bytes());
+        }
+
+        fs::write(self.path(cache_id, key), data)
+    }
+
+    fn remove(&mut self, cache_id: u8, key: u64) -> io::Result<()> {
+        match fs::remove_file(self.path(cache_id, key)) {
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+/// Cell caches of the bitmaps sent with the Cache Bitmap secondary drawing orders
+///
+/// By default, the number of caches and cells is not limited, as expected with the Revision 1 bitmap
+/// caches.
+#[derive(Default)]
+pub struct BitmapCache {
+    /// Configuration of the Revision 2 cell caches, if any
+    cell_caches: Vec<CellCacheConfig>,
+    cells: BTreeMap<(u8, u16), CachedBitmap>,
+    /// Keys of the persistent bitmaps held by the cells
+    keys: BTreeMap<(u8, u16), u64>,
+    store: Option<Box<dyn PersistentBitmapStore>>,
+}
+
+impl BitmapCache {
+    /// Creates the cell caches of this configuration, ignoring their persistent keys.
+    pub fn new(config: &BitmapCacheConfig) -> Self {
+        let cell_caches = config
+            .cell_caches
+            .iter()
+            .map(|cell_cache| CellCacheConfig {
+                persistent_keys: Vec::new(),
+                ..cell_cache.clone()
+            })
+            .collect();
+
+        Self {
+            cell_caches,
+            cells: BTreeMap::new(),
+            keys: BTreeMap::new(),
+            store: None,
+        }
+    }
+
+    /// Loads the bitmaps of the persistent cell caches from the store, which then receives the new
+    /// persistent bitmaps.
+    ///
+    /// The bitmaps which do not fit in their cache are removed from the store. The keys of the loaded
+    /// bitmaps must be sent to the server, see [`BitmapCache::config`].
+    pub fn load_persistent(&mut self, mut store: Box<dyn PersistentBitmapStore>) {
+        for (cache_id, cell_cache) in self.cell_caches.iter_mut().enumerate() {
+            let Ok(cache_id) = u8::try_from(cache_id) else {
+                break;
+            };
+
+            cell_cache.persistent_keys.clear();
+
+            if !cell_cache.persistent {
+                continue;
+            }
+
+            let keys = match store.keys(cache_id) {
+                Ok(keys) => keys,
+                Err(error) => {
+                    warn!(%error, cache_id, "Failed to list the persistent bitmaps");
+                    continue;
+                }
+            };
+
+            for key in keys {
+                let cache_index = u16::try_from(cell_cache.persistent_keys.len())
+                    .ok()
+                    .filter(|&cache_index| cache_index < cell_cache.entries);
+
+                let Some(cache_index) = cache_index else {
+                    if let Err(error) = store.remove(cache_id, key) {
+                        warn!(%error, cache_id, key, "Failed to remove an evicted persistent bitmap");
+                    }
+                    continue;
+                };
+
+                match store.load(cache_id, key) {
+                    Ok(bitmap) => {
+                        self.cells.insert((cache_id, cache_index), bitmap);
+                        self.keys.insert((cache_id, cache_index), key);
+                        cell_cache.persistent_keys.push(key);
+                    }
+                    Err(error) => debug!(%error, cache_id, key, "Failed to load a persistent bitmap"),
+                }
+            }
+
+            debug!(
+                cache_id,
+                count = cell_cache.persistent_keys.len(),
+                "Loaded persistent bitmaps"
+            );
+        }
+
+        self.store = Some(store);
+    }
+
+    /// Returns the configuration of the cell caches to use for the connection, with the keys of the
+    /// loaded persistent bitmaps.
+    pub fn config(&self) -> BitmapCacheConfig {
+        BitmapCacheConfig {
+            cell_caches: self.cell_caches.clone(),
+        }
+    }
+
+    pub(crate) fn get(&self, cache_id: u8, cache_index: u16) -> Option<&CachedBitmap> {
+        self.cells.get(&(cache_id, cache_index))
+    }
+
+    /// Puts a bitmap in a cell, saving it in the persistent store when it has a key.
+    ///
+    /// The persistent bitmap previously held by the cell is removed from the store.
+    pub(crate) fn insert(&mut self, cache_id: u8, cache_index: u16, key: Option<u64>, bitmap: CachedBitmap) {
+        let persistent = if self.cell_caches.is_empty() {
+            false
+        } else {
+            let Some(cell_cache) = self.cell_caches.get(usize::from(cache_id)) else {
+                warn!(cache_id, "Invalid bitmap cache");
+                return;
+            };
+
+            if cache_index >= cell_cache.entries {
+                warn!(cache_id, cache_index, "Invalid bitmap cache index");
+                return;
+            }
+
+            cell_cache.persistent
+        };
+
+        let key = key.filter(|_| persistent);
+        let evicted = match key {
+            Some(key) => self.keys.insert((cache_id, cache_index), key),
+            None => self.keys.remove(&(cache_id, cache_index)),
+        };
+
+        if let Some(store) = self.store.as_mut() {
+            if let Some(evicted) = evicted.filter(|&evicted| Some(evicted) != key) {
+                if let Err(error) = store.remove(cache_id, evicted) {
+                    warn!(%error, cache_id, key = evicted, "Failed to remove an evicted persistent bitmap");
+                }
+            }
+
+            if let Some(key) = key {
+                if let Err(error) = store.store(cache_id, key, &bitmap) {
+                    warn!(%error, cache_id, key, "Failed to store a persistent bitmap");
+                }
+            }
+        }
+
+        self.cells.insert((cache_id, cache_index), bitmap);
+    }
+}
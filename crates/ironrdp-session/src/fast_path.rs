@@ -1,6 +1,7 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use ironrdp_core::{decode_cursor, DecodeErrorKind, ReadCursor, WriteBuf};
+use ironrdp_graphics::bulk;
 use ironrdp_graphics::image_processing::PixelFormat;
 use ironrdp_graphics::pointer::{DecodedPointer, PointerBitmapTarget};
 use ironrdp_graphics::rdp6::BitmapStreamDecoder;
@@ -12,7 +13,9 @@ use ironrdp_pdu::pointer::PointerUpdateData;
 use ironrdp_pdu::rdp::headers::ShareDataPdu;
 use ironrdp_pdu::surface_commands::{FrameAction, FrameMarkerPdu, SurfaceCommand};
 
+use crate::bitmap_cache::BitmapCache;
 use crate::image::DecodedImage;
+use crate::orders::OrderProcessor;
 use crate::pointer::PointerCache;
 use crate::utils::CodecId;
 use crate::{rfx, SessionError, SessionErrorExt, SessionResult};
@@ -24,7 +27,7 @@ pub enum UpdateKind {
     PointerDefault,
     PointerHidden,
     PointerPosition { x: u16, y: u16 },
-    PointerBitmap(Rc<DecodedPointer>),
+    PointerBitmap(Arc<DecodedPointer>),
 }
 
 pub struct Processor {
@@ -32,6 +35,7 @@ pub struct Processor {
     rfx_handler: rfx::DecodingContext,
     marker_processor: FrameMarkerProcessor,
     bitmap_stream_decoder: BitmapStreamDecoder,
+    order_processor: OrderProcessor,
     pointer_cache: PointerCache,
     use_system_pointer: bool,
     mouse_pos_update: Option<(u16, u16)>,
@@ -40,16 +44,25 @@ pub struct Processor {
 }
 
 impl Processor {
+    /// Sets the bitmap caches used by the drawing orders.
+    pub fn set_bitmap_cache(&mut self, bitmap_cache: BitmapCache) {
+        self.order_processor.set_bitmap_cache(bitmap_cache);
+    }
+
     pub fn update_mouse_pos(&mut self, x: u16, y: u16) {
         self.mouse_pos_update = Some((x, y));
     }
 
     /// Process input fast path frame and return list of updates.
+    ///
+    /// `bulk_decompressor` must be shared with the slow-path processing, as the server is using
+    /// the same compression history for all the PDUs.
     pub fn process(
         &mut self,
         image: &mut DecodedImage,
         input: &[u8],
         output: &mut WriteBuf,
+        bulk_decompressor: &mut bulk::Decompressor,
     ) -> SessionResult<Vec<UpdateKind>> {
         let mut processor_updates = Vec::new();
 
@@ -67,9 +80,15 @@ impl Processor {
         let update_pdu = decode_cursor::<FastPathUpdatePdu<'_>>(&mut input).map_err(SessionError::decode)?;
         trace!(fast_path_update_fragmentation = ?update_pdu.fragmentation);
 
-        let processed_complete_data = self
-            .complete_data
-            .process_data(update_pdu.data, update_pdu.fragmentation);
+        // Each fragment is compressed separately.
+        let update_data = match (update_pdu.compression_flags, update_pdu.compression_type) {
+            (Some(compression_flags), Some(compression_type)) => bulk_decompressor
+                .decompress(update_pdu.data, compression_flags, compression_type)
+                .map_err(|e| custom_err!("bulk decompression", e))?,
+            _ => update_pdu.data,
+        };
+
+        let processed_complete_data = self.complete_data.process_data(update_data, update_pdu.fragmentation);
 
         let update_code = update_pdu.update_code;
 
@@ -95,6 +114,9 @@ impl Processor {
                     trace!("{update:?}");
                     buf.clear();
 
+                    // Drawing orders are using the color depth of the bitmaps.
+                    self.order_processor.set_color_depth(update.bits_per_pixel);
+
                     // Bitmap data is either compressed or uncompressed, depending
                     // on whether the BITMAP_COMPRESSION flag is present in the
                     // flags field.
@@ -173,6 +195,13 @@ impl Processor {
 
                 processor_updates.push(update_kind);
             }
+            Ok(FastPathUpdate::Orders(orders)) => {
+                trace!("Received {} drawing orders", orders.number_orders);
+
+                if let Some(update_rectangle) = self.order_processor.process(image, &orders)? {
+                    processor_updates.push(UpdateKind::Region(update_rectangle));
+                }
+            }
             Ok(FastPathUpdate::Pointer(update)) => {
                 if self.no_server_pointer {
                     return Ok(processor_updates);
@@ -216,17 +245,17 @@ impl Processor {
                     PointerUpdateData::Color(pointer) => {
                         let cache_index = pointer.cache_index;
 
-                        let decoded_pointer = Rc::new(
+                        let decoded_pointer = Arc::new(
                             DecodedPointer::decode_color_pointer_attribute(&pointer, bitmap_target)
                                 .expect("Failed to decode color pointer attribute"),
                         );
 
                         let _ = self
                             .pointer_cache
-                            .insert(usize::from(cache_index), Rc::clone(&decoded_pointer));
+                            .insert(usize::from(cache_index), Arc::clone(&decoded_pointer));
 
                         if !self.pointer_software_rendering {
-                            processor_updates.push(UpdateKind::PointerBitmap(Rc::clone(&decoded_pointer)));
+                            processor_updates.push(UpdateKind::PointerBitmap(Arc::clone(&decoded_pointer)));
                         } else if let Some(rect) = image.update_pointer(decoded_pointer)? {
                             processor_updates.push(UpdateKind::Region(rect));
                         }
@@ -240,7 +269,7 @@ impl Processor {
                             self.use_system_pointer = false;
                             // Send graphics update
                             if !self.pointer_software_rendering {
-                                processor_updates.push(UpdateKind::PointerBitmap(Rc::clone(&cached_pointer)));
+                                processor_updates.push(UpdateKind::PointerBitmap(Arc::clone(&cached_pointer)));
                             } else if let Some(rect) = image.update_pointer(cached_pointer)? {
                                 processor_updates.push(UpdateKind::Region(rect));
                             } else {
@@ -256,17 +285,17 @@ impl Processor {
                     PointerUpdateData::New(pointer) => {
                         let cache_index = pointer.color_pointer.cache_index;
 
-                        let decoded_pointer = Rc::new(
+                        let decoded_pointer = Arc::new(
                             DecodedPointer::decode_pointer_attribute(&pointer, bitmap_target)
                                 .expect("Failed to decode pointer attribute"),
                         );
 
                         let _ = self
                             .pointer_cache
-                            .insert(usize::from(cache_index), Rc::clone(&decoded_pointer));
+                            .insert(usize::from(cache_index), Arc::clone(&decoded_pointer));
 
                         if !self.pointer_software_rendering {
-                            processor_updates.push(UpdateKind::PointerBitmap(Rc::clone(&decoded_pointer)));
+                            processor_updates.push(UpdateKind::PointerBitmap(Arc::clone(&decoded_pointer)));
                         } else if let Some(rect) = image.update_pointer(decoded_pointer)? {
                             processor_updates.push(UpdateKind::Region(rect));
                         }
@@ -274,17 +303,17 @@ impl Processor {
                     PointerUpdateData::Large(pointer) => {
                         let cache_index = pointer.cache_index;
 
-                        let decoded_pointer: Rc<DecodedPointer> = Rc::new(
+                        let decoded_pointer: Arc<DecodedPointer> = Arc::new(
                             DecodedPointer::decode_large_pointer_attribute(&pointer, bitmap_target)
                                 .expect("Failed to decode large pointer attribute"),
                         );
 
                         let _ = self
                             .pointer_cache
-                            .insert(usize::from(cache_index), Rc::clone(&decoded_pointer));
+                            .insert(usize::from(cache_index), Arc::clone(&decoded_pointer));
 
                         if !self.pointer_software_rendering {
-                            processor_updates.push(UpdateKind::PointerBitmap(Rc::clone(&decoded_pointer)));
+                            processor_updates.push(UpdateKind::PointerBitmap(Arc::clone(&decoded_pointer)));
                         } else if let Some(rect) = image.update_pointer(decoded_pointer)? {
                             processor_updates.push(UpdateKind::Region(rect));
                         }
@@ -390,6 +419,7 @@ impl ProcessorBuilder {
             rfx_handler: rfx::DecodingContext::new(),
             marker_processor: FrameMarkerProcessor::new(self.user_channel_id, self.io_channel_id),
             bitmap_stream_decoder: BitmapStreamDecoder::default(),
+            order_processor: OrderProcessor::new(),
             pointer_cache: PointerCache::default(),
             use_system_pointer: true,
             mouse_pos_update: None,
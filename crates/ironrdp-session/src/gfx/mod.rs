@@ -0,0 +1,731 @@
+//! Client-side implementation of the Graphics Pipeline Extension ([MS-RDPEGFX]).
+//!
+//! [MS-RDPEGFX]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegfx/da5c75f9-cd99-450c-98c4-014a496942b0
+
+mod surface;
+
+use std::collections::BTreeMap;
+
+use ironrdp_core::{impl_as_any, Decode as _, Encode, EncodeResult, ReadCursor, WriteCursor};
+use ironrdp_dvc::{DvcClientProcessor, DvcEncode, DvcMessage, DvcProcessor};
+use ironrdp_graphics::clearcodec;
+use ironrdp_graphics::image_processing::{ImageRegion, PixelFormat};
+use ironrdp_graphics::progressive;
+use ironrdp_graphics::rdp6::BitmapStreamDecoder;
+use ironrdp_graphics::zgfx;
+use ironrdp_pdu::geometry::{InclusiveRectangle, Rectangle as _};
+use ironrdp_pdu::rdp::vc::dvc::gfx::{
+    self, CacheToSurfacePdu, CapabilitiesAdvertisePdu, CapabilitiesV103Flags, CapabilitiesV104Flags,
+    CapabilitiesV107Flags, CapabilitiesV10Flags, CapabilitiesV81Flags, CapabilitiesV8Flags, CapabilitySet, ClientPdu,
+    Codec1Type, Codec2Type, CreateSurfacePdu, FrameAcknowledgePdu, QueueDepth, ServerPdu, SolidFillPdu,
+    SurfaceToCachePdu, SurfaceToSurfacePdu, WireToSurface1Pdu, WireToSurface2Pdu,
+};
+use ironrdp_pdu::{decode_err, pdu_other_err, PduResult};
+
+use self::surface::{image_step, rect16_to_inclusive, Bitmap, Surface, SURFACE_PIXEL_FORMAT};
+use crate::image::DecodedImage;
+use crate::{rfx, SessionResult};
+
+pub const CHANNEL_NAME: &str = "Microsoft::Windows::RDS::Graphics";
+
+/// Number of cache slots available when the `SMALL_CACHE` capability flag is negotiated.
+const SMALL_CACHE_SLOTS: u16 = 4096;
+/// Number of cache slots available otherwise.
+const DEFAULT_CACHE_SLOTS: u16 = 25600;
+/// Size of the bitmap cache when the `SMALL_CACHE` capability flag is negotiated, in bytes.
+const SMALL_CACHE_SIZE: usize = 16 * 1024 * 1024;
+/// Size of the bitmap cache otherwise, in bytes.
+const DEFAULT_CACHE_SIZE: usize = 100 * 1024 * 1024;
+/// Number of RemoteFX Progressive codec contexts a surface can have at the same time.
+const MAX_CODEC_CONTEXTS_PER_SURFACE: usize = 16;
+
+/// A client for the Graphics Pipeline Virtual Channel.
+///
+/// The server draws into off-screen surfaces, which are then mapped into the graphics output
+/// buffer. Call [`GfxClient::update_image`] to composite the modified parts of the mapped surfaces
+/// into a [`DecodedImage`]. When the processor is registered with the `DrdynvcClient`,
+/// [`crate::ActiveStage`] takes care of it and emits the corresponding
+/// [`crate::ActiveStageOutput::GraphicsUpdate`] events.
+pub struct GfxClient {
+    /// Capability sets advertised to the server, in order of preference.
+    capabilities: Vec<CapabilitySet>,
+    /// Capability set selected by the server.
+    confirmed_capabilities: Option<CapabilitySet>,
+    decompressor: zgfx::Decompressor,
+    decompressed_buffer: Vec<u8>,
+    surfaces: BTreeMap<u16, Surface>,
+    cache_slots: BTreeMap<u16, Bitmap>,
+    /// Size of the bitmaps stored in the cache slots, in bytes.
+    cache_size: usize,
+    /// Size of the graphics output buffer, as announced by the last RDPGFX_RESET_GRAPHICS_PDU message.
+    output_size: Option<(u32, u32)>,
+    rfx_handler: rfx::DecodingContext,
+    bitmap_stream_decoder: BitmapStreamDecoder,
+    clear_codec_decoder: clearcodec::Decoder,
+    /// RemoteFX Progressive decoders, by surface and codec context identifiers.
+    progressive_contexts: BTreeMap<(u16, u32), progressive::Decoder>,
+    /// Identifier of the frame being currently decoded, if any.
+    current_frame: Option<u32>,
+    total_frames_decoded: u32,
+}
+
+impl GfxClient {
+    /// Creates a new [`GfxClient`] advertising every capability set supported by this implementation.
+    ///
+    /// AVC (H.264) is never advertised, since there is no decoder for it.
+    pub fn new() -> Self {
+        Self::with_capabilities(vec![
+            CapabilitySet::V10_7 {
+                flags: CapabilitiesV107Flags::AVC_DISABLED,
+            },
+            CapabilitySet::V10_6 {
+                flags: CapabilitiesV104Flags::AVC_DISABLED,
+            },
+            CapabilitySet::V10_5 {
+                flags: CapabilitiesV104Flags::AVC_DISABLED,
+            },
+            CapabilitySet::V10_4 {
+                flags: CapabilitiesV104Flags::AVC_DISABLED,
+            },
+            CapabilitySet::V10_3 {
+                flags: CapabilitiesV103Flags::AVC_DISABLED,
+            },
+            CapabilitySet::V10_2 {
+                flags: CapabilitiesV10Flags::AVC_DISABLED,
+            },
+            CapabilitySet::V10 {
+                flags: CapabilitiesV10Flags::AVC_DISABLED,
+            },
+            CapabilitySet::V8_1 {
+                flags: CapabilitiesV81Flags::empty(),
+            },
+            CapabilitySet::V8 {
+                flags: CapabilitiesV8Flags::empty(),
+            },
+        ])
+    }
+
+    /// Creates a new [`GfxClient`] advertising the given capability sets.
+    pub fn with_capabilities(capabilities: Vec<CapabilitySet>) -> Self {
+        Self {
+            capabilities,
+            confirmed_capabilities: None,
+            decompressor: zgfx::Decompressor::new(),
+            decompressed_buffer: Vec::new(),
+            surfaces: BTreeMap::new(),
+            cache_slots: BTreeMap::new(),
+            cache_size: 0,
+            output_size: None,
+            rfx_handler: rfx::DecodingContext::new(),
+            bitmap_stream_decoder: BitmapStreamDecoder::default(),
+            clear_codec_decoder: clearcodec::Decoder::new(),
+            progressive_contexts: BTreeMap::new(),
+            current_frame: None,
+            total_frames_decoded: 0,
+        }
+    }
+
+    /// Capability set confirmed by the server, if the capability exchange is complete.
+    pub fn confirmed_capabilities(&self) -> Option<&CapabilitySet> {
+        self.confirmed_capabilities.as_ref()
+    }
+
+    /// Size of the graphics output buffer requested by the server.
+    pub fn output_size(&self) -> Option<(u32, u32)> {
+        self.output_size
+    }
+
+    pub fn total_frames_decoded(&self) -> u32 {
+        self.total_frames_decoded
+    }
+
+    /// Composites the mapped surfaces modified since the last call into `image`.
+    ///
+    /// Nothing is done while a frame is being decoded, so that partially decoded frames are never displayed.
+    /// Returns the region of `image` which was updated, if any.
+    pub fn update_image(&mut self, image: &mut DecodedImage) -> SessionResult<Option<InclusiveRectangle>> {
+        if self.current_frame.is_some() || image.width() == 0 || image.height() == 0 {
+            return Ok(None);
+        }
+
+        let image_bounds = InclusiveRectangle {
+            left: 0,
+            top: 0,
+            right: image.width() - 1,
+            bottom: image.height() - 1,
+        };
+
+        let mut updated_region: Option<InclusiveRectangle> = None;
+
+        for surface in self.surfaces.values_mut() {
+            let Some(damage) = surface.damage.take() else {
+                continue;
+            };
+
+            let Some((origin_x, origin_y)) = surface.output_origin else {
+                continue;
+            };
+
+            let (Ok(origin_x), Ok(origin_y)) = (u16::try_from(origin_x), u16::try_from(origin_y)) else {
+                continue;
+            };
+
+            let destination = InclusiveRectangle {
+                left: origin_x.saturating_add(damage.left),
+                top: origin_y.saturating_add(damage.top),
+                right: origin_x.saturating_add(damage.right),
+                bottom: origin_y.saturating_add(damage.bottom),
+            };
+
+            let Some(destination) = destination.intersect(&image_bounds) else {
+                continue;
+            };
+
+            let Some(step) = image_step(surface.width) else {
+                warn!(width = surface.width, "Surface too wide to be composited");
+                continue;
+            };
+
+            let source = ImageRegion {
+                region: InclusiveRectangle {
+                    left: destination.left - origin_x,
+                    top: destination.top - origin_y,
+                    right: destination.right - origin_x,
+                    bottom: destination.bottom - origin_y,
+                },
+                step,
+                pixel_format: SURFACE_PIXEL_FORMAT,
+                data: &surface.data,
+            };
+
+            let updated = image.apply_image_region(&source, &destination)?;
+
+            updated_region = Some(match updated_region {
+                Some(region) => region.union(&updated),
+                None => updated,
+            });
+        }
+
+        Ok(updated_region)
+    }
+
+    fn process_pdu(&mut self, pdu: ServerPdu, output: &mut Vec<DvcMessage>) -> SessionResult<()> {
+        match pdu {
+            ServerPdu::CapabilitiesConfirm(pdu) => {
+                debug!(capabilities = ?pdu.0, "Server confirmed capabilities");
+                self.confirmed_capabilities = Some(pdu.0);
+            }
+            ServerPdu::ResetGraphics(pdu) => {
+                debug!(width = pdu.width, height = pdu.height, monitors = ?pdu.monitors, "Reset graphics");
+                self.output_size = Some((pdu.width, pdu.height));
+                self.surfaces.clear();
+                self.progressive_contexts.clear();
+            }
+            ServerPdu::CreateSurface(pdu) => self.create_surface(pdu)?,
+            ServerPdu::DeleteSurface(pdu) => {
+                if self.surfaces.remove(&pdu.surface_id).is_none() {
+                    warn!(surface_id = pdu.surface_id, "Attempted to delete an unknown surface");
+                }
+
+                // Encoding contexts do not outlive their surface.
+                self.progressive_contexts
+                    .retain(|&(surface_id, _), _| surface_id != pdu.surface_id);
+            }
+            ServerPdu::MapSurfaceToOutput(pdu) => {
+                self.map_surface(pdu.surface_id, pdu.output_origin_x, pdu.output_origin_y);
+            }
+            ServerPdu::MapSurfaceToScaledOutput(pdu) => {
+                warn!(
+                    target_width = pdu.target_width,
+                    target_height = pdu.target_height,
+                    "Scaled output is not supported, mapping the surface unscaled"
+                );
+                self.map_surface(pdu.surface_id, pdu.output_origin_x, pdu.output_origin_y);
+            }
+            ServerPdu::MapSurfaceToScaledWindow(pdu) => {
+                warn!(
+                    surface_id = pdu.surface_id,
+                    window_id = pdu.window_id,
+                    "Mapping surfaces to windows is not supported"
+                );
+            }
+            ServerPdu::StartFrame(pdu) => {
+                trace!(frame_id = pdu.frame_id, "Start frame");
+                self.current_frame = Some(pdu.frame_id);
+            }
+            ServerPdu::EndFrame(pdu) => {
+                trace!(frame_id = pdu.frame_id, "End frame");
+
+                if self.current_frame.take() != Some(pdu.frame_id) {
+                    warn!(frame_id = pdu.frame_id, "Unexpected end of frame");
+                }
+
+                self.total_frames_decoded = self.total_frames_decoded.wrapping_add(1);
+
+                output.push(Box::new(GfxClientPdu(ClientPdu::FrameAcknowledge(
+                    FrameAcknowledgePdu {
+                        queue_depth: QueueDepth::Unavailable,
+                        frame_id: pdu.frame_id,
+                        total_frames_decoded: self.total_frames_decoded,
+                    },
+                ))));
+            }
+            ServerPdu::WireToSurface1(pdu) => self.wire_to_surface(pdu)?,
+            ServerPdu::WireToSurface2(pdu) => self.wire_to_surface_2(pdu)?,
+            ServerPdu::DeleteEncodingContext(pdu) => {
+                if self
+                    .progressive_contexts
+                    .remove(&(pdu.surface_id, pdu.codec_context_id))
+                    .is_none()
+                {
+                    debug!(
+                        surface_id = pdu.surface_id,
+                        codec_context_id = pdu.codec_context_id,
+                        "Deleted an unknown encoding context"
+                    );
+                }
+            }
+            ServerPdu::SolidFill(pdu) => self.solid_fill(pdu),
+            ServerPdu::SurfaceToSurface(pdu) => self.surface_to_surface(pdu)?,
+            ServerPdu::SurfaceToCache(pdu) => self.surface_to_cache(pdu)?,
+            ServerPdu::CacheToSurface(pdu) => self.cache_to_surface(pdu),
+            ServerPdu::EvictCacheEntry(pdu) => {
+                if let Some(bitmap) = self.cache_slots.remove(&pdu.cache_slot) {
+                    self.cache_size -= bitmap.data.len();
+                } else {
+                    debug!(cache_slot = pdu.cache_slot, "Evicted an empty cache slot");
+                }
+            }
+            ServerPdu::CacheImportReply(pdu) => {
+                debug!(count = pdu.cache_slots.len(), "Received cache import reply");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn create_surface(&mut self, pdu: CreateSurfacePdu) -> SessionResult<()> {
+        debug!(
+            surface_id = pdu.surface_id,
+            width = pdu.width,
+            height = pdu.height,
+            pixel_format = ?pdu.pixel_format,
+            "Create surface"
+        );
+
+        // The replaced surface is released first, so that both are never allocated at the same time.
+        if self.surfaces.remove(&pdu.surface_id).is_some() {
+            warn!(surface_id = pdu.surface_id, "Replaced an existing surface");
+        }
+        self.progressive_contexts
+            .retain(|&(surface_id, _), _| surface_id != pdu.surface_id);
+
+        let surface = Surface::new(pdu.width, pdu.height, pdu.pixel_format)?;
+        self.surfaces.insert(pdu.surface_id, surface);
+
+        Ok(())
+    }
+
+    fn map_surface(&mut self, surface_id: u16, origin_x: u32, origin_y: u32) {
+        let Some(surface) = self.surfaces.get_mut(&surface_id) else {
+            warn!(surface_id, "Attempted to map an unknown surface");
+            return;
+        };
+
+        surface.output_origin = Some((origin_x, origin_y));
+        surface.damage_all();
+    }
+
+    fn wire_to_surface(&mut self, pdu: WireToSurface1Pdu) -> SessionResult<()> {
+        let Some(surface) = self.surfaces.get_mut(&pdu.surface_id) else {
+            warn!(
+                surface_id = pdu.surface_id,
+                "Received bitmap data for an unknown surface"
+            );
+            return Ok(());
+        };
+
+        let Some(destination) = rect16_to_inclusive(&pdu.destination_rectangle) else {
+            return Ok(());
+        };
+
+        match pdu.codec_id {
+            Codec1Type::Uncompressed => {
+                // RDPGFX_PIXELFORMAT values describe little-endian 32-bit words, that is BGR byte order in memory.
+                let pixel_format = match pdu.pixel_format {
+                    gfx::PixelFormat::XRgb => PixelFormat::BgrX32,
+                    gfx::PixelFormat::ARgb => PixelFormat::BgrA32,
+                };
+
+                let stride = usize::from(destination.width()) * usize::from(pixel_format.bytes_per_pixel());
+                let expected_length = stride * usize::from(destination.height());
+
+                if pdu.bitmap_data.len() < expected_length {
+                    warn!(
+                        expected_length,
+                        actual_length = pdu.bitmap_data.len(),
+                        "Uncompressed bitmap data is too short"
+                    );
+                    return Ok(());
+                }
+
+                let Ok(step) = u16::try_from(stride) else {
+                    warn!(stride, "Uncompressed bitmap is too wide");
+                    return Ok(());
+                };
+
+                let source = ImageRegion {
+                    region: InclusiveRectangle {
+                        left: 0,
+                        top: 0,
+                        right: destination.width() - 1,
+                        bottom: destination.height() - 1,
+                    },
+                    step,
+                    pixel_format,
+                    data: &pdu.bitmap_data,
+                };
+
+                surface.write_region(&source, destination.left, destination.top);
+            }
+            Codec1Type::Planar => {
+                let mut rgb24 = Vec::new();
+
+                if let Err(error) = self.bitmap_stream_decoder.decode_bitmap_stream_to_rgb24(
+                    &pdu.bitmap_data,
+                    &mut rgb24,
+                    usize::from(destination.width()),
+                    usize::from(destination.height()),
+                ) {
+                    warn!(%error, "Invalid planar bitmap");
+                    return Ok(());
+                }
+
+                // Unlike bitmap updates, planar bitmaps received on this channel are top-down.
+                let bitmap = Bitmap {
+                    width: destination.width(),
+                    height: destination.height(),
+                    data: rgb24
+                        .chunks_exact(3)
+                        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 0xFF])
+                        .collect(),
+                };
+
+                surface.write_bitmap(&bitmap, destination.left, destination.top);
+            }
+            Codec1Type::RemoteFx => {
+                let mut input = ReadCursor::new(&pdu.bitmap_data);
+
+                let result = self.rfx_handler.decode_tiles(
+                    &destination,
+                    &mut input,
+                    |tile, clipping_rectangles, update_rectangle| {
+                        surface.apply_tile(tile, clipping_rectangles, update_rectangle);
+                        Ok(update_rectangle.clone())
+                    },
+                );
+
+                if let Err(error) = result {
+                    warn!(error = %error.report(), "Invalid RemoteFX data");
+                }
+            }
+            Codec1Type::ClearCodec => {
+                // ClearCodec bitmaps are drawn on top of the current content of the destination.
+                // Bitmaps which do not fit in the surface are still decoded on a blank bitmap to
+                // keep the decoder caches in sync, and clipped when written.
+                if let Some(mut output) = surface.region_mut(&destination) {
+                    match self.clear_codec_decoder.decode(&pdu.bitmap_data, &mut output) {
+                        Ok(()) => surface.add_damage(destination),
+                        Err(error) => warn!(%error, "Invalid ClearCodec data"),
+                    }
+                } else {
+                    let mut bitmap = Bitmap::new(destination.width(), destination.height())?;
+                    let Some(mut output) = bitmap.as_image_region_mut() else {
+                        warn!(?destination, "ClearCodec destination too wide");
+                        return Ok(());
+                    };
+
+                    match self.clear_codec_decoder.decode(&pdu.bitmap_data, &mut output) {
+                        Ok(()) => {
+                            surface.write_bitmap(&bitmap, destination.left, destination.top);
+                        }
+                        Err(error) => warn!(%error, "Invalid ClearCodec data"),
+                    }
+                }
+            }
+            Codec1Type::Avc420 | Codec1Type::Alpha | Codec1Type::Avc444 | Codec1Type::Avc444v2 => {
+                warn!(surface_id = pdu.surface_id, codec = ?pdu.codec_id, "Unsupported codec");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn wire_to_surface_2(&mut self, pdu: WireToSurface2Pdu) -> SessionResult<()> {
+        let Some(surface) = self.surfaces.get_mut(&pdu.surface_id) else {
+            warn!(
+                surface_id = pdu.surface_id,
+                "Received bitmap data for an unknown surface"
+            );
+            return Ok(());
+        };
+
+        match pdu.codec_id {
+            Codec2Type::RemoteFxProgressive => {
+                let Some(bounds) = surface.bounds() else {
+                    return Ok(());
+                };
+
+                // Encoding contexts are created implicitly by the first bitmap referencing them, and the server
+                // is expected to delete the ones it no longer uses.
+                let key = (pdu.surface_id, pdu.codec_context_id);
+                if !self.progressive_contexts.contains_key(&key) {
+                    let count = self
+                        .progressive_contexts
+                        .range((pdu.surface_id, 0)..=(pdu.surface_id, u32::MAX))
+                        .count();
+                    if count >= MAX_CODEC_CONTEXTS_PER_SURFACE {
+                        return Err(reason_err!(
+                            "GFX",
+                            "surface {} exceeds the maximum of {MAX_CODEC_CONTEXTS_PER_SURFACE} codec contexts",
+                            pdu.surface_id
+                        ));
+                    }
+                }
+
+                let decoder = self.progressive_contexts.entry(key).or_default();
+
+                let Some(mut output) = surface.region_mut(&bounds) else {
+                    return Ok(());
+                };
+
+                match decoder.decode(&pdu.bitmap_data, &mut output) {
+                    Ok(updated_rectangles) => {
+                        for rectangle in updated_rectangles {
+                            surface.add_damage(rectangle);
+                        }
+                    }
+                    Err(error) => warn!(%error, "Invalid RemoteFX Progressive data"),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn solid_fill(&mut self, pdu: SolidFillPdu) {
+        let Some(surface) = self.surfaces.get_mut(&pdu.surface_id) else {
+            warn!(surface_id = pdu.surface_id, "Attempted to fill an unknown surface");
+            return;
+        };
+
+        let alpha = match surface.pixel_format {
+            gfx::PixelFormat::XRgb => 0xFF,
+            gfx::PixelFormat::ARgb => pdu.fill_pixel.xa,
+        };
+        let color = [pdu.fill_pixel.r, pdu.fill_pixel.g, pdu.fill_pixel.b, alpha];
+
+        for rectangle in pdu.rectangles.iter().filter_map(rect16_to_inclusive) {
+            surface.fill(&rectangle, color);
+        }
+    }
+
+    fn surface_to_surface(&mut self, pdu: SurfaceToSurfacePdu) -> SessionResult<()> {
+        let Some(source_rectangle) = rect16_to_inclusive(&pdu.source_rectangle) else {
+            return Ok(());
+        };
+
+        let Some(source) = self.surfaces.get(&pdu.source_surface_id) else {
+            warn!(
+                surface_id = pdu.source_surface_id,
+                "Attempted to copy from an unknown surface"
+            );
+            return Ok(());
+        };
+
+        // The source and destination surfaces may be the same, so the source content is copied first.
+        let Some(bitmap) = source.read_bitmap(&source_rectangle)? else {
+            warn!(surface_id = pdu.source_surface_id, "Invalid surface to surface source");
+            return Ok(());
+        };
+
+        let Some(destination) = self.surfaces.get_mut(&pdu.destination_surface_id) else {
+            warn!(
+                surface_id = pdu.destination_surface_id,
+                "Attempted to copy to an unknown surface"
+            );
+            return Ok(());
+        };
+
+        for point in &pdu.destination_points {
+            destination.write_bitmap(&bitmap, point.x, point.y);
+        }
+
+        Ok(())
+    }
+
+    fn surface_to_cache(&mut self, pdu: SurfaceToCachePdu) -> SessionResult<()> {
+        if !self.is_valid_cache_slot(pdu.cache_slot) {
+            warn!(cache_slot = pdu.cache_slot, "Invalid cache slot");
+            return Ok(());
+        }
+
+        let Some(source_rectangle) = rect16_to_inclusive(&pdu.source_rectangle) else {
+            return Ok(());
+        };
+
+        let Some(surface) = self.surfaces.get(&pdu.surface_id) else {
+            warn!(surface_id = pdu.surface_id, "Attempted to cache an unknown surface");
+            return Ok(());
+        };
+
+        let Some(bitmap) = surface.read_bitmap(&source_rectangle)? else {
+            warn!(surface_id = pdu.surface_id, "Invalid surface to cache source");
+            return Ok(());
+        };
+
+        if let Some(replaced) = self.cache_slots.remove(&pdu.cache_slot) {
+            self.cache_size -= replaced.data.len();
+        }
+
+        // The server is expected to evict cache entries before exceeding the cache size.
+        let cache_size = self.cache_size + bitmap.data.len();
+        let max_cache_size = if self.is_small_cache() {
+            SMALL_CACHE_SIZE
+        } else {
+            DEFAULT_CACHE_SIZE
+        };
+        if cache_size > max_cache_size {
+            return Err(reason_err!(
+                "GFX",
+                "cache size of {cache_size} bytes exceeds the maximum of {max_cache_size} bytes"
+            ));
+        }
+
+        self.cache_size = cache_size;
+        self.cache_slots.insert(pdu.cache_slot, bitmap);
+
+        Ok(())
+    }
+
+    fn cache_to_surface(&mut self, pdu: CacheToSurfacePdu) {
+        let Some(bitmap) = self.cache_slots.get(&pdu.cache_slot) else {
+            warn!(cache_slot = pdu.cache_slot, "Attempted to read an empty cache slot");
+            return;
+        };
+
+        let Some(surface) = self.surfaces.get_mut(&pdu.surface_id) else {
+            warn!(surface_id = pdu.surface_id, "Attempted to copy to an unknown surface");
+            return;
+        };
+
+        for point in &pdu.destination_points {
+            surface.write_bitmap(bitmap, point.x, point.y);
+        }
+    }
+
+    /// Cache slots are 1-based, and their number depends on the negotiated capabilities.
+    fn is_valid_cache_slot(&self, cache_slot: u16) -> bool {
+        let max_cache_slots = if self.is_small_cache() {
+            SMALL_CACHE_SLOTS
+        } else {
+            DEFAULT_CACHE_SLOTS
+        };
+
+        (1..=max_cache_slots).contains(&cache_slot)
+    }
+
+    /// Whether the `SMALL_CACHE` capability flag is negotiated.
+    fn is_small_cache(&self) -> bool {
+        match &self.confirmed_capabilities {
+            Some(CapabilitySet::V8 { flags }) => flags.contains(CapabilitiesV8Flags::SMALL_CACHE),
+            Some(CapabilitySet::V8_1 { flags }) => flags.contains(CapabilitiesV81Flags::SMALL_CACHE),
+            Some(CapabilitySet::V10 { flags } | CapabilitySet::V10_2 { flags }) => {
+                flags.contains(CapabilitiesV10Flags::SMALL_CACHE)
+            }
+            Some(
+                CapabilitySet::V10_4 { flags }
+                | CapabilitySet::V10_5 { flags }
+                | CapabilitySet::V10_6 { flags }
+                | CapabilitySet::V10_6Err { flags },
+            ) => flags.contains(CapabilitiesV104Flags::SMALL_CACHE),
+            Some(CapabilitySet::V10_7 { flags }) => flags.contains(CapabilitiesV107Flags::SMALL_CACHE),
+            _ => false,
+        }
+    }
+}
+
+impl Default for GfxClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl_as_any!(GfxClient);
+
+impl DvcProcessor for GfxClient {
+    fn channel_name(&self) -> &str {
+        CHANNEL_NAME
+    }
+
+    fn start(&mut self, _channel_id: u32) -> PduResult<Vec<DvcMessage>> {
+        let pdu = ClientPdu::CapabilitiesAdvertise(CapabilitiesAdvertisePdu(self.capabilities.clone()));
+        debug!(?pdu, "Advertising capabilities");
+
+        Ok(vec![Box::new(GfxClientPdu(pdu))])
+    }
+
+    fn process(&mut self, _channel_id: u32, payload: &[u8]) -> PduResult<Vec<DvcMessage>> {
+        // The buffer is taken out of `self` so that PDUs borrowing it can be processed.
+        let mut decompressed = core::mem::take(&mut self.decompressed_buffer);
+        decompressed.clear();
+
+        self.decompressor
+            .decompress(payload, &mut decompressed)
+            .map_err(|e| pdu_other_err!("ZGFX decompression", source: e))?;
+
+        let mut output = Vec::new();
+        let mut src = ReadCursor::new(&decompressed);
+
+        let result = loop {
+            if src.is_empty() {
+                break Ok(output);
+            }
+
+            match ServerPdu::decode(&mut src) {
+                Ok(pdu) => {
+                    trace!(?pdu, "Received GFX PDU");
+                    if let Err(e) = self.process_pdu(pdu, &mut output) {
+                        break Err(pdu_other_err!("GFX", source: e));
+                    }
+                }
+                Err(e) => break Err(decode_err!(e)),
+            }
+        };
+
+        self.decompressed_buffer = decompressed;
+
+        result
+    }
+}
+
+impl DvcClientProcessor for GfxClient {}
+
+/// Wrapper allowing graphics pipeline client PDUs to be sent as [`DvcMessage`]s.
+struct GfxClientPdu(ClientPdu);
+
+impl Encode for GfxClientPdu {
+    fn encode(&self, dst: &mut WriteCursor<'_>) -> EncodeResult<()> {
+        self.0.encode(dst)
+    }
+
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    fn size(&self) -> usize {
+        self.0.size()
+    }
+}
+
+impl DvcEncode for GfxClientPdu {}
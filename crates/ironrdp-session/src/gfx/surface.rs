@@ -0,0 +1,314 @@
+use ironrdp_graphics::image_processing::{ImageRegion, ImageRegionMut, PixelFormat};
+use ironrdp_graphics::rectangle_processing::Region;
+use ironrdp_pdu::geometry::{InclusiveRectangle, Rectangle as _};
+use ironrdp_pdu::rdp::vc::dvc::gfx;
+
+use crate::SessionResult;
+
+/// Pixel format used to store the surfaces and the cache slots.
+pub(crate) const SURFACE_PIXEL_FORMAT: PixelFormat = PixelFormat::RgbA32;
+
+const BYTES_PER_PIXEL: usize = SURFACE_PIXEL_FORMAT.bytes_per_pixel() as usize;
+
+/// Maximum number of pixels of the surfaces and bitmaps, enough for a 8K UHD output.
+const MAX_PIXELS: usize = 8192 * 8192;
+
+/// Allocates the pixel data of a `width` x `height` bitmap, filled with transparent black pixels.
+///
+/// Fails when the bitmap has more than [`MAX_PIXELS`] pixels, or when the memory cannot be allocated.
+fn allocate_pixels(width: u16, height: u16) -> SessionResult<Vec<u8>> {
+    let pixels = usize::from(width) * usize::from(height);
+    if pixels > MAX_PIXELS {
+        return Err(reason_err!("GFX", "{width}x{height} bitmap exceeds the maximum size"));
+    }
+
+    let length = pixels * BYTES_PER_PIXEL;
+    let mut data = Vec::new();
+    data.try_reserve_exact(length)
+        .map_err(|e| custom_err!("GFX bitmap allocation", e))?;
+    data.resize(length, 0);
+
+    Ok(data)
+}
+
+/// Returns the step of the image regions of a `width` pixels wide bitmap.
+///
+/// Image regions store their step in a `u16`, which does not fit rows wider than 16383 pixels.
+pub(crate) fn image_step(width: u16) -> Option<u16> {
+    u16::try_from(usize::from(width) * BYTES_PER_PIXEL).ok()
+}
+
+/// Converts a RDPGFX_RECT16 structure into an [`InclusiveRectangle`].
+///
+/// Even though the graphics messages are decoding RDPGFX_RECT16 into an `InclusiveRectangle`,
+/// the `right` and `bottom` bounds are exclusive. Returns `None` for empty rectangles.
+pub(crate) fn rect16_to_inclusive(rect: &InclusiveRectangle) -> Option<InclusiveRectangle> {
+    if rect.right <= rect.left || rect.bottom <= rect.top {
+        return None;
+    }
+
+    Some(InclusiveRectangle {
+        left: rect.left,
+        top: rect.top,
+        right: rect.right - 1,
+        bottom: rect.bottom - 1,
+    })
+}
+
+/// A top-down bitmap stored in [`SURFACE_PIXEL_FORMAT`].
+#[derive(Debug, Clone)]
+pub(crate) struct Bitmap {
+    pub(crate) width: u16,
+    pub(crate) height: u16,
+    pub(crate) data: Vec<u8>,
+}
+
+impl Bitmap {
+    /// Creates a bitmap filled with transparent black pixels.
+    pub(crate) fn new(width: u16, height: u16) -> SessionResult<Self> {
+        Ok(Self {
+            width,
+            height,
+            data: allocate_pixels(width, height)?,
+        })
+    }
+
+    /// Returns the image region of the whole bitmap, unless it is empty or too wide.
+    pub(crate) fn as_image_region(&self) -> Option<ImageRegion<'_>> {
+        Some(ImageRegion {
+            region: InclusiveRectangle {
+                left: 0,
+                top: 0,
+                right: self.width.checked_sub(1)?,
+                bottom: self.height.checked_sub(1)?,
+            },
+            step: image_step(self.width)?,
+            pixel_format: SURFACE_PIXEL_FORMAT,
+            data: &self.data,
+        })
+    }
+
+    /// Returns the mutable image region of the whole bitmap, unless it is empty or too wide.
+    pub(crate) fn as_image_region_mut(&mut self) -> Option<ImageRegionMut<'_>> {
+        Some(ImageRegionMut {
+            region: InclusiveRectangle {
+                left: 0,
+                top: 0,
+                right: self.width.checked_sub(1)?,
+                bottom: self.height.checked_sub(1)?,
+            },
+            step: image_step(self.width)?,
+            pixel_format: SURFACE_PIXEL_FORMAT,
+            data: &mut self.data,
+        })
+    }
+}
+
+/// Graphics pipeline surface, as created by the RDPGFX_CREATE_SURFACE_PDU message.
+pub(crate) struct Surface {
+    pub(crate) width: u16,
+    pub(crate) height: u16,
+    pub(crate) pixel_format: gfx::PixelFormat,
+    pub(crate) data: Vec<u8>,
+    /// Origin of the surface in the graphics output buffer, if mapped.
+    pub(crate) output_origin: Option<(u32, u32)>,
+    /// Region of the surface modified since the last composition, in surface coordinates.
+    pub(crate) damage: Option<InclusiveRectangle>,
+}
+
+impl Surface {
+    pub(crate) fn new(width: u16, height: u16, pixel_format: gfx::PixelFormat) -> SessionResult<Self> {
+        Ok(Self {
+            width,
+            height,
+            pixel_format,
+            data: allocate_pixels(width, height)?,
+            output_origin: None,
+            damage: None,
+        })
+    }
+
+    pub(crate) fn bounds(&self) -> Option<InclusiveRectangle> {
+        if self.width == 0 || self.height == 0 {
+            return None;
+        }
+
+        Some(InclusiveRectangle {
+            left: 0,
+            top: 0,
+            right: self.width - 1,
+            bottom: self.height - 1,
+        })
+    }
+
+    pub(crate) fn stride(&self) -> usize {
+        usize::from(self.width) * BYTES_PER_PIXEL
+    }
+
+    /// Clips `rectangle` to the bounds of the surface.
+    pub(crate) fn clip(&self, rectangle: &InclusiveRectangle) -> Option<InclusiveRectangle> {
+        self.bounds()?.intersect(rectangle)
+    }
+
+    /// Returns the image region of `rectangle`, if it lies entirely within the surface.
+    ///
+    /// Also returns `None` for surfaces too wide to be described by an image region.
+    pub(crate) fn region_mut(&mut self, rectangle: &InclusiveRectangle) -> Option<ImageRegionMut<'_>> {
+        if self.clip(rectangle).as_ref() != Some(rectangle) {
+            return None;
+        }
+
+        Some(ImageRegionMut {
+            region: rectangle.clone(),
+            step: image_step(self.width)?,
+            pixel_format: SURFACE_PIXEL_FORMAT,
+            data: &mut self.data,
+        })
+    }
+
+    pub(crate) fn add_damage(&mut self, rectangle: InclusiveRectangle) {
+        self.damage = Some(match self.damage.take() {
+            Some(damage) => damage.union(&rectangle),
+            None => rectangle,
+        });
+    }
+
+    pub(crate) fn damage_all(&mut self) {
+        if let Some(bounds) = self.bounds() {
+            self.add_damage(bounds);
+        }
+    }
+
+    /// Fills `rectangle` with a solid color, returning the actually filled rectangle.
+    pub(crate) fn fill(&mut self, rectangle: &InclusiveRectangle, color: [u8; 4]) -> Option<InclusiveRectangle> {
+        let rectangle = self.clip(rectangle)?;
+
+        let stride = self.stride();
+        let left = usize::from(rectangle.left) * BYTES_PER_PIXEL;
+        let right = (usize::from(rectangle.right) + 1) * BYTES_PER_PIXEL;
+
+        for y in usize::from(rectangle.top)..=usize::from(rectangle.bottom) {
+            let row = &mut self.data[y * stride + left..y * stride + right];
+
+            for pixel in row.chunks_exact_mut(BYTES_PER_PIXEL) {
+                pixel.copy_from_slice(&color);
+            }
+        }
+
+        self.add_damage(rectangle.clone());
+
+        Some(rectangle)
+    }
+
+    /// Copies the content of `rectangle` into a new bitmap.
+    ///
+    /// Returns `None` when `rectangle` lies entirely outside of the surface.
+    pub(crate) fn read_bitmap(&self, rectangle: &InclusiveRectangle) -> SessionResult<Option<Bitmap>> {
+        let Some(rectangle) = self.clip(rectangle) else {
+            return Ok(None);
+        };
+
+        let stride = self.stride();
+        let left = usize::from(rectangle.left) * BYTES_PER_PIXEL;
+        let right = (usize::from(rectangle.right) + 1) * BYTES_PER_PIXEL;
+
+        let mut data = Vec::new();
+        data.try_reserve_exact(usize::from(rectangle.width()) * usize::from(rectangle.height()) * BYTES_PER_PIXEL)
+            .map_err(|e| custom_err!("GFX bitmap allocation", e))?;
+
+        for y in usize::from(rectangle.top)..=usize::from(rectangle.bottom) {
+            data.extend_from_slice(&self.data[y * stride + left..y * stride + right]);
+        }
+
+        Ok(Some(Bitmap {
+            width: rectangle.width(),
+            height: rectangle.height(),
+            data,
+        }))
+    }
+
+    /// Copies a top-down `source` image region with its top-left corner at (`left`, `top`).
+    ///
+    /// The parts falling outside of the surface are discarded. Returns the updated rectangle.
+    pub(crate) fn write_region(&mut self, source: &ImageRegion<'_>, left: u16, top: u16) -> Option<InclusiveRectangle> {
+        let destination = InclusiveRectangle {
+            left,
+            top,
+            right: u16::try_from(u32::from(left) + u32::from(source.region.width()) - 1).unwrap_or(u16::MAX),
+            bottom: u16::try_from(u32::from(top) + u32::from(source.region.height()) - 1).unwrap_or(u16::MAX),
+        };
+        let destination = self.clip(&destination)?;
+
+        let Some(step) = image_step(self.width) else {
+            warn!(width = self.width, "Surface too wide to copy an image region");
+            return None;
+        };
+
+        let source = ImageRegion {
+            region: InclusiveRectangle {
+                left: source.region.left,
+                top: source.region.top,
+                right: source.region.left + destination.width() - 1,
+                bottom: source.region.top + destination.height() - 1,
+            },
+            step: source.step,
+            pixel_format: source.pixel_format,
+            data: source.data,
+        };
+
+        let mut destination_region = ImageRegionMut {
+            region: destination.clone(),
+            step,
+            pixel_format: SURFACE_PIXEL_FORMAT,
+            data: &mut self.data,
+        };
+
+        if let Err(error) = source.copy_to(&mut destination_region) {
+            warn!(%error, "Failed to copy image region to surface");
+            return None;
+        }
+
+        self.add_damage(destination.clone());
+
+        Some(destination)
+    }
+
+    /// Copies `bitmap` with its top-left corner at (`left`, `top`), returning the updated rectangle.
+    pub(crate) fn write_bitmap(&mut self, bitmap: &Bitmap, left: u16, top: u16) -> Option<InclusiveRectangle> {
+        let Some(source) = bitmap.as_image_region() else {
+            warn!(width = bitmap.width, height = bitmap.height, "Invalid bitmap size");
+            return None;
+        };
+
+        self.write_region(&source, left, top)
+    }
+
+    /// Copies a decoded 64x64 RemoteFX tile, honoring the clipping rectangles of the message.
+    pub(crate) fn apply_tile(
+        &mut self,
+        tile_output: &[u8],
+        clipping_rectangles: &Region,
+        update_rectangle: &InclusiveRectangle,
+    ) {
+        const TILE_SIZE: u16 = 64;
+
+        for region_rectangle in &clipping_rectangles.intersect_rectangle(update_rectangle).rectangles {
+            let source_x = region_rectangle.left - update_rectangle.left;
+            let source_y = region_rectangle.top - update_rectangle.top;
+
+            let source = ImageRegion {
+                region: InclusiveRectangle {
+                    left: source_x,
+                    top: source_y,
+                    right: source_x + region_rectangle.width() - 1,
+                    bottom: source_y + region_rectangle.height() - 1,
+                },
+                data: tile_output,
+                step: TILE_SIZE * u16::from(SURFACE_PIXEL_FORMAT.bytes_per_pixel()),
+                pixel_format: SURFACE_PIXEL_FORMAT,
+            };
+
+            self.write_region(&source, region_rectangle.left, region_rectangle.top);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
 use ironrdp_graphics::color_conversion::rdp_16bit_to_rgb;
 use ironrdp_graphics::image_processing::{ImageRegion, ImageRegionMut, PixelFormat};
@@ -24,7 +24,7 @@ pub struct DecodedImage {
     pointer_x: u16,
     pointer_y: u16,
 
-    pointer: Option<Rc<DecodedPointer>>,
+    pointer: Option<Arc<DecodedPointer>>,
     /// Image data, overridden by pointer. Used to restore image after pointer was hidden or moved
     pointer_backbuffer: Vec<u8>,
     /// Whether to show pointer or not
@@ -384,7 +384,7 @@ impl DecodedImage {
         }
     }
 
-    pub(crate) fn update_pointer(&mut self, pointer: Rc<DecodedPointer>) -> SessionResult<Option<InclusiveRectangle>> {
+    pub(crate) fn update_pointer(&mut self, pointer: Arc<DecodedPointer>) -> SessionResult<Option<InclusiveRectangle>> {
         self.show_pointer = true;
 
         // Remove old pointer from frame buffer
@@ -513,6 +513,66 @@ impl DecodedImage {
         Ok(update_rectangle)
     }
 
+    /// Copies a top-down `source` region into the image at `update_rectangle`.
+    ///
+    /// `update_rectangle` must be fully contained in the image and have the same size as the source region.
+    pub(crate) fn apply_image_region(
+        &mut self,
+        source: &ImageRegion<'_>,
+        update_rectangle: &InclusiveRectangle,
+    ) -> SessionResult<InclusiveRectangle> {
+        trace!("Image region: {:?}", update_rectangle);
+
+        let pointer_rendering_state = self.pointer_rendering_begin(update_rectangle)?;
+
+        let mut destination_image_region = ImageRegionMut {
+            region: update_rectangle.clone(),
+            step: self.width() * u16::from(self.pixel_format.bytes_per_pixel()),
+            pixel_format: self.pixel_format,
+            data: &mut self.data,
+        };
+
+        source
+            .copy_to(&mut destination_image_region)
+            .map_err(|e| custom_err!("copy_to", e))?;
+
+        let update_rectangle = self.pointer_rendering_end(pointer_rendering_state)?;
+
+        Ok(update_rectangle)
+    }
+
+    /// Draws into the image with `draw`, which is given a region covering the whole image.
+    ///
+    /// `update_rectangle` must be fully contained in the image, and contain all the pixels read or
+    /// modified by `draw`.
+    pub(crate) fn draw(
+        &mut self,
+        update_rectangle: &InclusiveRectangle,
+        draw: impl FnOnce(&mut ImageRegionMut<'_>),
+    ) -> SessionResult<InclusiveRectangle> {
+        trace!("Drawing: {:?}", update_rectangle);
+
+        let pointer_rendering_state = self.pointer_rendering_begin(update_rectangle)?;
+
+        let mut image_region = ImageRegionMut {
+            region: InclusiveRectangle {
+                left: 0,
+                top: 0,
+                right: self.width.saturating_sub(1),
+                bottom: self.height.saturating_sub(1),
+            },
+            step: self.width() * u16::from(self.pixel_format.bytes_per_pixel()),
+            pixel_format: self.pixel_format,
+            data: &mut self.data,
+        };
+
+        draw(&mut image_region);
+
+        let update_rectangle = self.pointer_rendering_end(pointer_rendering_state)?;
+
+        Ok(update_rectangle)
+    }
+
     // FIXME: this assumes PixelFormat::RgbA32
     pub(crate) fn apply_rgb16_bitmap(
         &mut self,
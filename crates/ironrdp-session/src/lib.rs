@@ -8,7 +8,9 @@ extern crate tracing;
 #[macro_use]
 mod macros;
 
+pub mod bitmap_cache;
 pub mod fast_path;
+pub mod gfx;
 pub mod image;
 pub mod legacy;
 pub mod pointer;
@@ -17,6 +19,7 @@ pub mod utils;
 pub mod x224;
 
 mod active_stage;
+mod orders;
 
 use core::fmt;
 
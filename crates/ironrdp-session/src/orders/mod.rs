@@ -0,0 +1,1239 @@
+//! Rendering of the drawing orders ([MS-RDPEGDI])
+//!
+//! Colors of the orders depend on the color depth of the session, which is not known by the
+//! processor. It is inferred from the bitmaps sent by the server, and is 32 bpp by default.
+//!
+//! Offscreen bitmaps are not supported, and the orders drawn to them are ignored.
+//!
+//! [MS-RDPEGDI]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegdi/745f2eee-d110-464c-8aca-06fc1814f6ad
+
+mod rop;
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use ironrdp_core::ReadCursor;
+use ironrdp_graphics::color_conversion::rdp_16bit_to_rgb;
+use ironrdp_graphics::image_processing::{ImageRegionMut, Rgba};
+use ironrdp_graphics::rdp6::BitmapStreamDecoder;
+use ironrdp_graphics::rle;
+use ironrdp_pdu::geometry::{InclusiveRectangle, Rectangle as _};
+use ironrdp_pdu::orders::{
+    AlternateSecondaryOrder, Bounds, Brush, CacheBrush, Color, DrawingOrder, FastGlyph, FastIndex, Glyph, GlyphFlags,
+    GlyphIndex, OrderDecoder, OrdersUpdate, Point, PrimaryOrder, SecondaryOrder, SCREEN_BITMAP_SURFACE,
+};
+
+use crate::bitmap_cache::{BitmapCache, CachedBitmap};
+use crate::image::DecodedImage;
+use crate::SessionResult;
+
+/// HS_HORIZONTAL, HS_VERTICAL, HS_FDIAGONAL, HS_BDIAGONAL, HS_CROSS and HS_DIAGCROSS brushes
+const HATCH_PATTERNS: [[u8; 8]; 6] = [
+    [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0xFF],
+    [0xF7, 0xF7, 0xF7, 0xF7, 0xF7, 0xF7, 0xF7, 0xF7],
+    [0xFE, 0xFD, 0xFB, 0xF7, 0xEF, 0xDF, 0xBF, 0x7F],
+    [0x7F, 0xBF, 0xDF, 0xEF, 0xF7, 0xFB, 0xFD, 0xFE],
+    [0xF7, 0xF7, 0xF7, 0xF7, 0xF7, 0xF7, 0x00, 0xF7],
+    [0x7E, 0xBD, 0xDB, 0xE7, 0xE7, 0xDB, 0xBD, 0x7E],
+];
+
+const GLYPH_FRAGMENT_USE: u8 = 0xFE;
+const GLYPH_FRAGMENT_ADD: u8 = 0xFF;
+
+/// Coordinate of the Fast Index and Fast Glyph orders telling that another value must be used
+const FAST_GLYPH_DEFAULT_COORDINATE: i16 = -32768;
+
+/// Cache identifier of the offscreen bitmaps in the MemBlt and Mem3Blt orders
+const OFFSCREEN_BITMAP_CACHE_ID: u8 = 0xFF;
+
+/// Decodes the drawing orders, and renders them into a [`DecodedImage`].
+pub(crate) struct OrderProcessor {
+    decoder: OrderDecoder,
+    bitmap_stream_decoder: BitmapStreamDecoder,
+    color_depth: u8,
+    bitmaps: BitmapCache,
+    color_tables: BTreeMap<u8, Vec<u32>>,
+    glyphs: BTreeMap<(u8, u16), Glyph>,
+    brushes: BTreeMap<u8, CacheBrush>,
+    fragments: BTreeMap<u8, Vec<u8>>,
+    /// Whether the orders are drawn to an offscreen bitmap instead of the screen
+    offscreen: bool,
+}
+
+impl OrderProcessor {
+    pub(crate) fn new() -> Self {
+        Self {
+            decoder: OrderDecoder::new(),
+            bitmap_stream_decoder: BitmapStreamDecoder::default(),
+            color_depth: 32,
+            bitmaps: BitmapCache::default(),
+            color_tables: BTreeMap::new(),
+            glyphs: BTreeMap::new(),
+            brushes: BTreeMap::new(),
+            fragments: BTreeMap::new(),
+            offscreen: false,
+        }
+    }
+
+    pub(crate) fn set_bitmap_cache(&mut self, bitmap_cache: BitmapCache) {
+        self.bitmaps = bitmap_cache;
+    }
+
+    /// Sets the color depth of the session, as seen in the bitmap updates.
+    pub(crate) fn set_color_depth(&mut self, bpp: u16) {
+        if let Ok(bpp @ (8 | 15 | 16 | 24 | 32)) = u8::try_from(bpp) {
+            self.color_depth = bpp;
+        }
+    }
+
+    /// Processes the orders of a Fast-Path update, returning the updated part of the image.
+    pub(crate) fn process(
+        &mut self,
+        image: &mut DecodedImage,
+        update: &OrdersUpdate<'_>,
+    ) -> SessionResult<Option<InclusiveRectangle>> {
+        let mut src = ReadCursor::new(update.order_data);
+        let mut update_rectangle: Option<InclusiveRectangle> = None;
+
+        for _ in 0..update.number_orders {
+            let order = match self.decoder.decode_order(&mut src) {
+                Ok(order) => order,
+                Err(error) => {
+                    // The following orders can't be located anymore.
+                    warn!(%error, "Invalid drawing order");
+                    break;
+                }
+            };
+
+            let rectangle = match order {
+                DrawingOrder::Primary { order, bounds } => {
+                    trace!(order = order.as_short_name(), ?bounds, "Primary drawing order");
+                    self.draw(image, &order, bounds)?
+                }
+                DrawingOrder::Secondary(order) => {
+                    trace!(order = order.as_short_name(), "Secondary drawing order");
+                    self.cache(order);
+                    None
+                }
+                DrawingOrder::AlternateSecondary(order) => {
+                    trace!(order = order.as_short_name(), "Alternate secondary drawing order");
+                    self.process_alternate_secondary(order);
+                    None
+                }
+            };
+
+            if let Some(rectangle) = rectangle {
+                update_rectangle = Some(match update_rectangle {
+                    Some(current) => current.union(&rectangle),
+                    None => rectangle,
+                });
+            }
+        }
+
+        Ok(update_rectangle)
+    }
+
+    fn draw(
+        &mut self,
+        image: &mut DecodedImage,
+        order: &PrimaryOrder,
+        bounds: Option<Bounds>,
+    ) -> SessionResult<Option<InclusiveRectangle>> {
+        if self.offscreen {
+            trace!("Ignoring an order drawn to an offscreen bitmap");
+            return Ok(None);
+        }
+
+        let Some(image_rect) = Rect::image(image) else {
+            return Ok(None);
+        };
+
+        let clip = match bounds {
+            Some(bounds) => {
+                let bounds = Rect {
+                    left: i32::from(bounds.left),
+                    top: i32::from(bounds.top),
+                    right: i32::from(bounds.right),
+                    bottom: i32::from(bounds.bottom),
+                };
+
+                match image_rect.intersect(bounds) {
+                    Some(clip) => clip,
+                    None => return Ok(None),
+                }
+            }
+            None => image_rect,
+        };
+
+        match order {
+            PrimaryOrder::DstBlt(order) => {
+                let destination = Rect::from_size(order.left, order.top, order.width, order.height);
+                blt(image, clip, destination, order.rop, &Pattern::Solid(0), |_, _| 0)
+            }
+            PrimaryOrder::PatBlt(order) => {
+                let Some(pattern) = self.pattern(&order.brush, order.back_color, order.fore_color) else {
+                    return Ok(None);
+                };
+
+                let destination = Rect::from_size(order.left, order.top, order.width, order.height);
+                blt(image, clip, destination, order.rop, &pattern, |_, _| 0)
+            }
+            PrimaryOrder::ScrBlt(order) => {
+                let destination = Rect::from_size(order.left, order.top, order.width, order.height);
+                scr_blt(
+                    image,
+                    clip,
+                    destination,
+                    i32::from(order.src_x),
+                    i32::from(order.src_y),
+                    order.rop,
+                )
+            }
+            PrimaryOrder::OpaqueRect(order) => {
+                let destination = Rect::from_size(order.left, order.top, order.width, order.height);
+                let pattern = Pattern::Solid(self.color(order.color));
+                blt(image, clip, destination, rop::PATCOPY, &pattern, |_, _| 0)
+            }
+            PrimaryOrder::MultiOpaqueRect(order) => {
+                let pattern = Pattern::Solid(self.color(order.color));
+                let mut update_rectangle: Option<InclusiveRectangle> = None;
+
+                for rectangle in &order.rectangles {
+                    let destination = Rect::from_size(rectangle.left, rectangle.top, rectangle.width, rectangle.height);
+
+                    if let Some(rectangle) = blt(image, clip, destination, rop::PATCOPY, &pattern, |_, _| 0)? {
+                        update_rectangle = Some(match update_rectangle {
+                            Some(current) => current.union(&rectangle),
+                            None => rectangle,
+                        });
+                    }
+                }
+
+                Ok(update_rectangle)
+            }
+            PrimaryOrder::MemBlt(order) => {
+                let destination = Rect::from_size(order.left, order.top, order.width, order.height);
+                let source = (i32::from(order.src_x), i32::from(order.src_y));
+                self.mem_blt(
+                    image,
+                    clip,
+                    destination,
+                    source,
+                    (order.cache_id, order.cache_index),
+                    order.rop,
+                    &Pattern::Solid(0),
+                )
+            }
+            PrimaryOrder::Mem3Blt(order) => {
+                let Some(pattern) = self.pattern(&order.brush, order.back_color, order.fore_color) else {
+                    return Ok(None);
+                };
+
+                let destination = Rect::from_size(order.left, order.top, order.width, order.height);
+                let source = (i32::from(order.src_x), i32::from(order.src_y));
+                self.mem_blt(
+                    image,
+                    clip,
+                    destination,
+                    source,
+                    (order.cache_id, order.cache_index),
+                    order.rop,
+                    &pattern,
+                )
+            }
+            PrimaryOrder::LineTo(order) => {
+                let points = [
+                    Point {
+                        x: order.start_x,
+                        y: order.start_y,
+                    },
+                    Point {
+                        x: order.end_x,
+                        y: order.end_y,
+                    },
+                ];
+                draw_lines(image, clip, &points, order.rop2, self.color(order.pen_color))
+            }
+            PrimaryOrder::Polyline(order) => {
+                let start = Point {
+                    x: order.start_x,
+                    y: order.start_y,
+                };
+                let points: Vec<Point> = core::iter::once(start).chain(order.points.iter().copied()).collect();
+                draw_lines(image, clip, &points, order.rop2, self.color(order.pen_color))
+            }
+            PrimaryOrder::GlyphIndex(order) => {
+                let text = self.glyph_index_text(order);
+                self.draw_text(image, clip, &text)
+            }
+            PrimaryOrder::FastIndex(order) => {
+                let text = self.fast_index_text(order);
+                self.draw_text(image, clip, &text)
+            }
+            PrimaryOrder::FastGlyph(order) => {
+                let text = self.fast_glyph_text(order);
+                self.draw_text(image, clip, &text)
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn mem_blt(
+        &self,
+        image: &mut DecodedImage,
+        clip: Rect,
+        destination: Rect,
+        (src_x, src_y): (i32, i32),
+        (cache_id, cache_index): (u16, u16),
+        rop: u8,
+        pattern: &Pattern,
+    ) -> SessionResult<Option<InclusiveRectangle>> {
+        let [cache_id, color_table] = cache_id.to_le_bytes();
+
+        if cache_id == OFFSCREEN_BITMAP_CACHE_ID {
+            debug!("Offscreen bitmaps are not supported");
+            return Ok(None);
+        }
+
+        let Some(bitmap) = self.bitmaps.get(cache_id, cache_index) else {
+            warn!(cache_id, cache_index, "Unknown cached bitmap");
+            return Ok(None);
+        };
+
+        let color_table = if bitmap.bpp == 8 {
+            self.color_tables.get(&color_table).map(Vec::as_slice)
+        } else {
+            None
+        };
+
+        let (offset_x, offset_y) = (src_x - destination.left, src_y - destination.top);
+
+        blt(image, clip, destination, rop, pattern, |x, y| {
+            bitmap.pixel(x + offset_x, y + offset_y, color_table)
+        })
+    }
+
+    fn draw_text(
+        &mut self,
+        image: &mut DecodedImage,
+        clip: Rect,
+        text: &Text<'_>,
+    ) -> SessionResult<Option<InclusiveRectangle>> {
+        let glyphs = layout_glyphs(&self.glyphs, &mut self.fragments, text);
+
+        let mut update_area = text.opaque_rect.and_then(|rect| rect.intersect(clip));
+
+        for (x, y, glyph) in &glyphs {
+            let glyph_rect = Rect {
+                left: *x,
+                top: *y,
+                right: x + i32::from(glyph.width) - 1,
+                bottom: y + i32::from(glyph.height) - 1,
+            };
+
+            if let Some(rect) = glyph_rect.intersect(clip) {
+                update_area = Some(match update_area {
+                    Some(area) => area.union(rect),
+                    None => rect,
+                });
+            }
+        }
+
+        let Some(update_area) = update_area else {
+            return Ok(None);
+        };
+
+        let update_rectangle = image.draw(&update_area.to_inclusive_rectangle(), |region| {
+            if let Some(opaque_rect) = text.opaque_rect.and_then(|rect| rect.intersect(clip)) {
+                for y in opaque_rect.top..=opaque_rect.bottom {
+                    for x in opaque_rect.left..=opaque_rect.right {
+                        write_pixel(region, x, y, text.opaque_color);
+                    }
+                }
+            }
+
+            for (glyph_x, glyph_y, glyph) in &glyphs {
+                let row_size = usize::from(glyph.width).div_ceil(8);
+
+                for (row_index, row) in glyph
+                    .bitmap
+                    .chunks_exact(row_size)
+                    .take(usize::from(glyph.height))
+                    .enumerate()
+                {
+                    let y = glyph_y + i32::try_from(row_index).expect("glyph height is an u16");
+
+                    for column in 0..usize::from(glyph.width) {
+                        let x = glyph_x + i32::try_from(column).expect("glyph width is an u16");
+
+                        if row[column / 8] & (0x80 >> (column % 8)) != 0 && clip.contains(x, y) {
+                            write_pixel(region, x, y, text.text_color);
+                        }
+                    }
+                }
+            }
+        })?;
+
+        Ok(Some(update_rectangle))
+    }
+
+    fn glyph_index_text<'a>(&self, order: &'a GlyphIndex) -> Text<'a> {
+        let opaque_rect = if order.op_redundant {
+            None
+        } else {
+            Rect::from_exclusive(order.op_left, order.op_top, order.op_right, order.op_bottom)
+        };
+
+        Text {
+            cache_id: order.cache_id,
+            flags: order.flags,
+            char_inc: order.char_inc,
+            text_color: self.color(order.back_color),
+            opaque_color: self.color(order.fore_color),
+            opaque_rect,
+            x: i32::from(order.x),
+            y: i32::from(order.y),
+            data: order.data.as_slice().into(),
+        }
+    }
+
+    fn fast_index_text<'a>(&self, order: &'a FastIndex) -> Text<'a> {
+        let (x, y, opaque_rect) = fast_glyph_geometry(
+            [order.bk_left, order.bk_top, order.bk_right, order.bk_bottom],
+            [order.op_left, order.op_top, order.op_right, order.op_bottom],
+            (order.x, order.y),
+        );
+
+        Text {
+            cache_id: order.cache_id,
+            flags: order.flags,
+            char_inc: order.char_inc,
+            text_color: self.color(order.back_color),
+            opaque_color: self.color(order.fore_color),
+            opaque_rect,
+            x,
+            y,
+            data: order.data.as_slice().into(),
+        }
+    }
+
+    fn fast_glyph_text(&mut self, order: &FastGlyph) -> Text<'static> {
+        if let Some(glyph) = &order.glyph {
+            self.glyphs
+                .insert((order.cache_id, u16::from(order.cache_index)), glyph.clone());
+        }
+
+        let (x, y, opaque_rect) = fast_glyph_geometry(
+            [order.bk_left, order.bk_top, order.bk_right, order.bk_bottom],
+            [order.op_left, order.op_top, order.op_right, order.op_bottom],
+            (order.x, order.y),
+        );
+
+        let text = Text {
+            cache_id: order.cache_id,
+            flags: order.flags,
+            char_inc: order.char_inc,
+            text_color: self.color(order.back_color),
+            opaque_color: self.color(order.fore_color),
+            opaque_rect,
+            x,
+            y,
+            data: Vec::new().into(),
+        };
+
+        // The glyph is drawn as a single glyph index, with a null delta if needed.
+        let data = if text.has_deltas() {
+            vec![order.cache_index, 0]
+        } else {
+            vec![order.cache_index]
+        };
+
+        Text {
+            data: data.into(),
+            ..text
+        }
+    }
+
+    /// Returns the pattern of a brush, or `None` if it can't be drawn.
+    fn pattern(&self, brush: &Brush, back_color: Color, fore_color: Color) -> Option<Pattern> {
+        let origin = (i32::from(brush.x), i32::from(brush.y));
+        let back_color = self.color(back_color);
+        let fore_color = self.color(fore_color);
+
+        if brush.style & Brush::CACHED_BRUSH != 0 {
+            let Some(cached) = self.brushes.get(&brush.hatch) else {
+                warn!(cache_index = brush.hatch, "Unknown cached brush");
+                return None;
+            };
+
+            if cached.bpp == 1 {
+                let rows = cached.data.as_slice().try_into().ok()?;
+                return Some(Pattern::monochrome(rows, back_color, fore_color, origin));
+            }
+
+            let bytes_per_pixel = usize::from(cached.bpp).div_ceil(8);
+            let color_table = self.color_tables.get(&0).map(Vec::as_slice);
+
+            let mut pixels = [0; 64];
+            for (pixel, value) in pixels.iter_mut().zip(cached.data.chunks_exact(bytes_per_pixel)) {
+                *pixel = pixel_to_rgb(read_le(value), cached.bpp, color_table);
+            }
+
+            return Some(Pattern::Pixels {
+                pixels: Box::new(pixels),
+                origin,
+            });
+        }
+
+        match brush.style {
+            Brush::STYLE_SOLID => Some(Pattern::Solid(fore_color)),
+            Brush::STYLE_HATCHED => {
+                let Some(rows) = HATCH_PATTERNS.get(usize::from(brush.hatch)) else {
+                    warn!(hatch = brush.hatch, "Invalid hatch style");
+                    return None;
+                };
+
+                Some(Pattern::monochrome(*rows, back_color, fore_color, origin))
+            }
+            Brush::STYLE_PATTERN => Some(Pattern::monochrome(brush.pattern(), back_color, fore_color, origin)),
+            style => {
+                debug!(style, "Unsupported brush style");
+                None
+            }
+        }
+    }
+
+    /// Converts a TS_COLOR to an RGB value.
+    fn color(&self, color: Color) -> u32 {
+        match self.color_depth {
+            24 | 32 => (u32::from(color.red) << 16) | (u32::from(color.green) << 8) | u32::from(color.blue),
+            // Lower color depths are stored in the lowest bytes.
+            bpp => {
+                let value = u32::from(color.red) | (u32::from(color.green) << 8);
+                let color_table = self.color_tables.get(&0).map(Vec::as_slice);
+                pixel_to_rgb(value, bpp, color_table)
+            }
+        }
+    }
+
+    fn cache(&mut self, order: SecondaryOrder) {
+        match order {
+            SecondaryOrder::CacheBitmap(order) => self.cache_bitmap(
+                (order.cache_id, order.cache_index),
+                None,
+                order.width,
+                order.height,
+                order.bpp,
+                order.compressed,
+                &order.data,
+            ),
+            SecondaryOrder::CacheBitmapV2(order) => self.cache_bitmap(
+                (order.cache_id, order.cache_index),
+                order.persistent_key.filter(|_| !order.do_not_cache),
+                order.width,
+                order.height,
+                order.bpp,
+                order.compressed,
+                &order.data,
+            ),
+            SecondaryOrder::CacheBitmapV3(order) => {
+                if order.codec_id != 0 {
+                    warn!(codec_id = order.codec_id, "Unsupported cached bitmap codec");
+                    return;
+                }
+
+                self.cache_bitmap(
+                    (order.cache_id, order.cache_index),
+                    Some(order.persistent_key),
+                    order.width,
+                    order.height,
+                    order.bpp,
+                    false,
+                    &order.data,
+                );
+            }
+            SecondaryOrder::CacheColorTable(order) => {
+                let colors = order
+                    .colors
+                    .iter()
+                    .map(|color| (u32::from(color.red) << 16) | (u32::from(color.green) << 8) | u32::from(color.blue))
+                    .collect();
+
+                self.color_tables.insert(order.cache_index, colors);
+            }
+            SecondaryOrder::CacheGlyph(order) => {
+                for glyph in order.glyphs {
+                    self.glyphs.insert((order.cache_id, glyph.cache_index), glyph.glyph);
+                }
+            }
+            SecondaryOrder::CacheBrush(order) => {
+                self.brushes.insert(order.cache_index, order);
+            }
+            SecondaryOrder::Other { order_type, .. } => {
+                debug!(order_type, "Unsupported secondary drawing order");
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn cache_bitmap(
+        &mut self,
+        (cache_id, cache_index): (u8, u16),
+        persistent_key: Option<u64>,
+        width: u16,
+        height: u16,
+        bpp: u8,
+        compressed: bool,
+        data: &[u8],
+    ) {
+        self.set_color_depth(u16::from(bpp));
+
+        match self.decode_bitmap(width, height, bpp, compressed, data) {
+            Some(pixels) => {
+                self.bitmaps.insert(
+                    cache_id,
+                    cache_index,
+                    persistent_key,
+                    CachedBitmap {
+                        width,
+                        height,
+                        bpp,
+                        pixels,
+                    },
+                );
+            }
+            None => warn!(cache_id, cache_index, bpp, "Invalid cached bitmap"),
+        }
+    }
+
+    /// Decodes a bitmap to top-down RGB pixels, or color table indices for 8 bpp bitmaps.
+    fn decode_bitmap(&mut self, width: u16, height: u16, bpp: u8, compressed: bool, data: &[u8]) -> Option<Vec<u32>> {
+        let width = usize::from(width);
+        let height = usize::from(height);
+        let bytes_per_pixel = usize::from(bpp).div_ceil(8);
+
+        let mut buf = Vec::new();
+
+        // Bitmaps are stored from bottom to top.
+        let (rows, row_size, bytes_per_pixel) = if !compressed {
+            (data, (width * bytes_per_pixel).next_multiple_of(4), bytes_per_pixel)
+        } else if bpp == 32 {
+            self.bitmap_stream_decoder
+                .decode_bitmap_stream_to_rgb24(data, &mut buf, width, height)
+                .map_err(|error| debug!(%error, "Invalid RDP6_BITMAP_STREAM"))
+                .ok()?;
+
+            // Pixels are decoded as RGB triplets.
+            let pixels = buf
+                .chunks_exact(width * 3)
+                .rev()
+                .flat_map(|row| row.chunks_exact(3))
+                .map(|pixel| (u32::from(pixel[0]) << 16) | (u32::from(pixel[1]) << 8) | u32::from(pixel[2]))
+                .collect();
+
+            return Some(pixels);
+        } else {
+            rle::decompress(data, &mut buf, width, height, usize::from(bpp))
+                .map_err(|error| debug!(%error, "Invalid RLE-compressed bitmap"))
+                .ok()?;
+
+            (buf.as_slice(), width * bytes_per_pixel, bytes_per_pixel)
+        };
+
+        if row_size == 0 || rows.len() < row_size * height {
+            return None;
+        }
+
+        let pixels = rows
+            .chunks_exact(row_size)
+            .take(height)
+            .rev()
+            .flat_map(|row| row[..width * bytes_per_pixel].chunks_exact(bytes_per_pixel))
+            .map(|pixel| {
+                let value = read_le(pixel);
+
+                if bpp == 8 {
+                    value
+                } else {
+                    pixel_to_rgb(value, bpp, None)
+                }
+            })
+            .collect();
+
+        Some(pixels)
+    }
+
+    fn process_alternate_secondary(&mut self, order: AlternateSecondaryOrder) {
+        match order {
+            AlternateSecondaryOrder::SwitchSurface { bitmap_id } => {
+                self.offscreen = bitmap_id != SCREEN_BITMAP_SURFACE;
+            }
+            AlternateSecondaryOrder::CreateOffscreenBitmap(order) => {
+                debug!(bitmap_id = order.bitmap_id, "Offscreen bitmaps are not supported");
+            }
+            AlternateSecondaryOrder::FrameMarker(action) => {
+                trace!(?action, "Frame marker");
+            }
+        }
+    }
+}
+
+impl CachedBitmap {
+    fn pixel(&self, x: i32, y: i32, color_table: Option<&[u32]>) -> u32 {
+        let (Ok(x), Ok(y)) = (usize::try_from(x), usize::try_from(y)) else {
+            return 0;
+        };
+
+        if x >= usize::from(self.width) || y >= usize::from(self.height) {
+            return 0;
+        }
+
+        let value = self.pixels[y * usize::from(self.width) + x];
+
+        if self.bpp == 8 {
+            color_table_lookup(value, color_table)
+        } else {
+            value
+        }
+    }
+}
+
+/// Glyphs to draw, with the Glyph Index, Fast Index and Fast Glyph orders
+struct Text<'a> {
+    cache_id: u8,
+    flags: GlyphFlags,
+    char_inc: u8,
+    text_color: u32,
+    opaque_color: u32,
+    opaque_rect: Option<Rect>,
+    x: i32,
+    y: i32,
+    data: Cow<'a, [u8]>,
+}
+
+impl Text<'_> {
+    /// Returns whether each glyph index is followed by a delta to the position of the glyph.
+    fn has_deltas(&self) -> bool {
+        self.char_inc == 0 && !self.flags.contains(GlyphFlags::SO_CHAR_INC_EQUAL_BM_BASE)
+    }
+}
+
+/// Layout state of the glyphs of a text
+struct GlyphLayout<'a, 'g> {
+    text: &'a Text<'a>,
+    glyphs: &'g BTreeMap<(u8, u16), Glyph>,
+    x: i32,
+    y: i32,
+    placed: Vec<(i32, i32, &'g Glyph)>,
+}
+
+impl GlyphLayout<'_, '_> {
+    fn advance(&mut self, delta: i32) {
+        if self.text.flags.contains(GlyphFlags::SO_VERTICAL) {
+            self.y += delta;
+        } else {
+            self.x += delta;
+        }
+    }
+
+    /// Places the glyph of the index at `data[*index]`, moving the index past it.
+    fn place_glyph(&mut self, data: &[u8], index: &mut usize) {
+        let glyph_index = data[*index];
+        *index += 1;
+
+        if self.text.has_deltas() {
+            match read_glyph_delta(data, index) {
+                Some(delta) => self.advance(delta),
+                None => {
+                    *index = data.len();
+                    return;
+                }
+            }
+        }
+
+        let Some(glyph) = self.glyphs.get(&(self.text.cache_id, u16::from(glyph_index))) else {
+            debug!(cache_id = self.text.cache_id, glyph_index, "Unknown cached glyph");
+            return;
+        };
+
+        self.placed
+            .push((self.x + i32::from(glyph.x), self.y + i32::from(glyph.y), glyph));
+
+        if self.text.flags.contains(GlyphFlags::SO_CHAR_INC_EQUAL_BM_BASE) {
+            self.advance(i32::from(glyph.width));
+        } else if self.text.char_inc != 0 {
+            self.advance(i32::from(self.text.char_inc));
+        }
+    }
+}
+
+/// Returns the glyphs of a text with their position, processing its fragments.
+fn layout_glyphs<'g>(
+    glyphs: &'g BTreeMap<(u8, u16), Glyph>,
+    fragments: &mut BTreeMap<u8, Vec<u8>>,
+    text: &Text<'_>,
+) -> Vec<(i32, i32, &'g Glyph)> {
+    let mut layout = GlyphLayout {
+        text,
+        glyphs,
+        x: text.x,
+        y: text.y,
+        placed: Vec::new(),
+    };
+
+    let mut data: &[u8] = &text.data;
+    let mut index = 0;
+
+    while index < data.len() {
+        match data[index] {
+            GLYPH_FRAGMENT_USE => {
+                let Some(&fragment_index) = data.get(index + 1) else {
+                    break;
+                };
+
+                let mut next = index + 2;
+                if text.has_deltas() && next < data.len() {
+                    match read_glyph_delta(data, &mut next) {
+                        Some(delta) => layout.advance(delta),
+                        None => break,
+                    }
+                }
+
+                match fragments.get(&fragment_index) {
+                    Some(fragment) => {
+                        let mut fragment_position = 0;
+                        while fragment_position < fragment.len() {
+                            layout.place_glyph(fragment, &mut fragment_position);
+                        }
+                    }
+                    None => debug!(fragment_index, "Unknown glyph fragment"),
+                }
+
+                // The next fragment starts after this one.
+                data = &data[next..];
+                index = 0;
+            }
+            GLYPH_FRAGMENT_ADD => {
+                let (Some(&fragment_index), Some(&size)) = (data.get(index + 1), data.get(index + 2)) else {
+                    break;
+                };
+
+                // The fragment is made of the glyphs preceding this operation.
+                let size = usize::from(size).min(index);
+                fragments.insert(fragment_index, data[..size].to_vec());
+
+                data = &data[index + 3..];
+                index = 0;
+            }
+            _ => layout.place_glyph(data, &mut index),
+        }
+    }
+
+    layout.placed
+}
+
+/// Reads the delta following a glyph index or a fragment use, moving the index past it.
+fn read_glyph_delta(data: &[u8], index: &mut usize) -> Option<i32> {
+    let first = *data.get(*index)?;
+    *index += 1;
+
+    if first & 0x80 == 0 {
+        return Some(i32::from(first));
+    }
+
+    let bytes = data.get(*index..*index + 2)?;
+    *index += 2;
+
+    Some(i32::from(i16::from_le_bytes([bytes[0], bytes[1]])))
+}
+
+/// Returns the position and opaque rectangle of the Fast Index and Fast Glyph orders.
+fn fast_glyph_geometry(background: [i16; 4], opaque: [i16; 4], (x, y): (i16, i16)) -> (i32, i32, Option<Rect>) {
+    let [bk_left, bk_top, bk_right, bk_bottom] = background;
+    let [mut op_left, mut op_top, mut op_right, mut op_bottom] = opaque;
+
+    // The opaque rectangle may be taken from the background rectangle.
+    if op_bottom == FAST_GLYPH_DEFAULT_COORDINATE {
+        let flags = op_top & 0x0F;
+
+        if flags & 0x01 != 0 {
+            op_bottom = bk_bottom;
+        }
+        if flags & 0x02 != 0 {
+            op_right = bk_right;
+        }
+        if flags & 0x04 != 0 {
+            op_top = bk_top;
+        }
+        if flags & 0x08 != 0 {
+            op_left = bk_left;
+        }
+    }
+
+    if op_left == 0 {
+        op_left = bk_left;
+    }
+    if op_right == 0 {
+        op_right = bk_right;
+    }
+
+    let x = if x == FAST_GLYPH_DEFAULT_COORDINATE { bk_left } else { x };
+    let y = if y == FAST_GLYPH_DEFAULT_COORDINATE { bk_top } else { y };
+
+    (
+        i32::from(x),
+        i32::from(y),
+        Rect::from_exclusive(op_left, op_top, op_right, op_bottom),
+    )
+}
+
+/// Applies a ternary raster operation to the destination rectangle.
+///
+/// `source` returns the source pixel of the destination coordinates.
+fn blt(
+    image: &mut DecodedImage,
+    clip: Rect,
+    destination: Rect,
+    rop: u8,
+    pattern: &Pattern,
+    source: impl Fn(i32, i32) -> u32,
+) -> SessionResult<Option<InclusiveRectangle>> {
+    let Some(area) = destination.intersect(clip) else {
+        return Ok(None);
+    };
+
+    let update_rectangle = image.draw(&area.to_inclusive_rectangle(), |region| {
+        for y in area.top..=area.bottom {
+            for x in area.left..=area.right {
+                let color = rop::rop3(rop, pattern.color(x, y), source(x, y), read_pixel(region, x, y));
+                write_pixel(region, x, y, color);
+            }
+        }
+    })?;
+
+    Ok(Some(update_rectangle))
+}
+
+/// Applies a ternary raster operation with a source rectangle of the image.
+fn scr_blt(
+    image: &mut DecodedImage,
+    clip: Rect,
+    destination: Rect,
+    src_x: i32,
+    src_y: i32,
+    rop: u8,
+) -> SessionResult<Option<InclusiveRectangle>> {
+    let Some(image_rect) = Rect::image(image) else {
+        return Ok(None);
+    };
+
+    let Some(area) = destination.intersect(clip) else {
+        return Ok(None);
+    };
+
+    let (offset_x, offset_y) = (src_x - destination.left, src_y - destination.top);
+    let source_area = Rect {
+        left: area.left + offset_x,
+        top: area.top + offset_y,
+        right: area.right + offset_x,
+        bottom: area.bottom + offset_y,
+    };
+
+    // The source pixels are read before any pixel is written, as both rectangles may overlap.
+    let update_area = match source_area.intersect(image_rect) {
+        Some(source_area) => area.union(source_area),
+        None => area,
+    };
+
+    let update_rectangle = image.draw(&update_area.to_inclusive_rectangle(), |region| {
+        let mut source = Vec::with_capacity(area.pixel_count());
+
+        for y in source_area.top..=source_area.bottom {
+            for x in source_area.left..=source_area.right {
+                source.push(if image_rect.contains(x, y) {
+                    read_pixel(region, x, y)
+                } else {
+                    0
+                });
+            }
+        }
+
+        let mut source = source.into_iter();
+
+        for y in area.top..=area.bottom {
+            for x in area.left..=area.right {
+                let source = source.next().unwrap_or(0);
+                let color = rop::rop3(rop, 0, source, read_pixel(region, x, y));
+                write_pixel(region, x, y, color);
+            }
+        }
+    })?;
+
+    Ok(Some(update_rectangle))
+}
+
+/// Draws lines between the points with a binary raster operation.
+///
+/// As with GDI, the last point of the lines is not drawn.
+fn draw_lines(
+    image: &mut DecodedImage,
+    clip: Rect,
+    points: &[Point],
+    rop2: u8,
+    pen_color: u32,
+) -> SessionResult<Option<InclusiveRectangle>> {
+    let mut pixels = Vec::new();
+
+    for segment in points.windows(2) {
+        let (start, end) = (segment[0], segment[1]);
+        line_pixels(
+            (i32::from(start.x), i32::from(start.y)),
+            (i32::from(end.x), i32::from(end.y)),
+            &mut pixels,
+        );
+    }
+
+    pixels.retain(|&(x, y)| clip.contains(x, y));
+
+    let Some(update_area) = pixels
+        .iter()
+        .map(|&(x, y)| Rect {
+            left: x,
+            top: y,
+            right: x,
+            bottom: y,
+        })
+        .reduce(Rect::union)
+    else {
+        return Ok(None);
+    };
+
+    let update_rectangle = image.draw(&update_area.to_inclusive_rectangle(), |region| {
+        for (x, y) in pixels {
+            let color = rop::rop2(rop2, pen_color, read_pixel(region, x, y));
+            write_pixel(region, x, y, color);
+        }
+    })?;
+
+    Ok(Some(update_rectangle))
+}
+
+/// Pushes the pixels of a line, without its end point, using the Bresenham algorithm.
+fn line_pixels((mut x, mut y): (i32, i32), (end_x, end_y): (i32, i32), pixels: &mut Vec<(i32, i32)>) {
+    let delta_x = (end_x - x).abs();
+    let delta_y = -(end_y - y).abs();
+    let step_x = if x < end_x { 1 } else { -1 };
+    let step_y = if y < end_y { 1 } else { -1 };
+    let mut error = delta_x + delta_y;
+
+    while (x, y) != (end_x, end_y) {
+        pixels.push((x, y));
+
+        let doubled_error = 2 * error;
+
+        if doubled_error >= delta_y {
+            error += delta_y;
+            x += step_x;
+        }
+
+        if doubled_error <= delta_x {
+            error += delta_x;
+            y += step_y;
+        }
+    }
+}
+
+/// Pattern of a brush
+enum Pattern {
+    Solid(u32),
+    /// 8x8 pixels, repeated from the origin of the brush
+    Pixels {
+        pixels: Box<[u32; 64]>,
+        origin: (i32, i32),
+    },
+}
+
+impl Pattern {
+    /// Creates a pattern from the rows of a monochrome brush, the set bits having the back color.
+    fn monochrome(rows: [u8; 8], back_color: u32, fore_color: u32, origin: (i32, i32)) -> Self {
+        let mut pixels = [0; 64];
+
+        for (index, pixel) in pixels.iter_mut().enumerate() {
+            let row = rows[index / 8];
+            *pixel = if row & (0x80 >> (index % 8)) != 0 {
+                back_color
+            } else {
+                fore_color
+            };
+        }
+
+        Self::Pixels {
+            pixels: Box::new(pixels),
+            origin,
+        }
+    }
+
+    fn color(&self, x: i32, y: i32) -> u32 {
+        match self {
+            Self::Solid(color) => *color,
+            Self::Pixels {
+                pixels,
+                origin: (origin_x, origin_y),
+            } => {
+                let column = ((x - origin_x) & 7).unsigned_abs() as usize;
+                let row = ((y - origin_y) & 7).unsigned_abs() as usize;
+
+                pixels[row * 8 + column]
+            }
+        }
+    }
+}
+
+/// Inclusive rectangle, which may be out of the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rect {
+    left: i32,
+    top: i32,
+    right: i32,
+    bottom: i32,
+}
+
+impl Rect {
+    fn image(image: &DecodedImage) -> Option<Self> {
+        if image.width() == 0 || image.height() == 0 {
+            return None;
+        }
+
+        Some(Self {
+            left: 0,
+            top: 0,
+            right: i32::from(image.width()) - 1,
+            bottom: i32::from(image.height()) - 1,
+        })
+    }
+
+    fn from_size(left: i16, top: i16, width: i16, height: i16) -> Self {
+        Self {
+            left: i32::from(left),
+            top: i32::from(top),
+            right: i32::from(left) + i32::from(width) - 1,
+            bottom: i32::from(top) + i32::from(height) - 1,
+        }
+    }
+
+    /// Creates a rectangle from exclusive bounds, returning `None` if it is empty.
+    fn from_exclusive(left: i16, top: i16, right: i16, bottom: i16) -> Option<Self> {
+        (left < right && top < bottom).then(|| Self {
+            left: i32::from(left),
+            top: i32::from(top),
+            right: i32::from(right) - 1,
+            bottom: i32::from(bottom) - 1,
+        })
+    }
+
+    fn intersect(self, other: Self) -> Option<Self> {
+        let rect = Self {
+            left: self.left.max(other.left),
+            top: self.top.max(other.top),
+            right: self.right.min(other.right),
+            bottom: self.bottom.min(other.bottom),
+        };
+
+        (rect.left <= rect.right && rect.top <= rect.bottom).then_some(rect)
+    }
+
+    fn union(self, other: Self) -> Self {
+        Self {
+            left: self.left.min(other.left),
+            top: self.top.min(other.top),
+            right: self.right.max(other.right),
+            bottom: self.bottom.max(other.bottom),
+        }
+    }
+
+    fn contains(self, x: i32, y: i32) -> bool {
+        (self.left..=self.right).contains(&x) && (self.top..=self.bottom).contains(&y)
+    }
+
+    fn pixel_count(self) -> usize {
+        let width = usize::try_from(self.right - self.left + 1).unwrap_or(0);
+        let height = usize::try_from(self.bottom - self.top + 1).unwrap_or(0);
+
+        width * height
+    }
+
+    /// Converts a rectangle contained in the image.
+    fn to_inclusive_rectangle(self) -> InclusiveRectangle {
+        let coordinate = |value: i32| u16::try_from(value).expect("rectangle is contained in the image");
+
+        InclusiveRectangle {
+            left: coordinate(self.left),
+            top: coordinate(self.top),
+            right: coordinate(self.right),
+            bottom: coordinate(self.bottom),
+        }
+    }
+}
+
+fn pixel_offset(region: &ImageRegionMut<'_>, x: i32, y: i32) -> usize {
+    let x = usize::try_from(x).expect("pixel is contained in the image");
+    let y = usize::try_from(y).expect("pixel is contained in the image");
+
+    y * usize::from(region.step) + x * usize::from(region.pixel_format.bytes_per_pixel())
+}
+
+fn read_pixel(region: &ImageRegionMut<'_>, x: i32, y: i32) -> u32 {
+    let offset = pixel_offset(region, x, y);
+    let color = region
+        .pixel_format
+        .read_color(&region.data[offset..])
+        .expect("pixel is contained in the image");
+
+    (u32::from(color.r) << 16) | (u32::from(color.g) << 8) | u32::from(color.b)
+}
+
+fn write_pixel(region: &mut ImageRegionMut<'_>, x: i32, y: i32, rgb: u32) {
+    let offset = pixel_offset(region, x, y);
+    let [_, r, g, b] = rgb.to_be_bytes();
+
+    region
+        .pixel_format
+        .write_color(Rgba { r, g, b, a: 0xFF }, &mut region.data[offset..])
+        .expect("pixel is contained in the image");
+}
+
+/// Reads a little-endian pixel value of up to 4 bytes.
+fn read_le(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| (value << 8) | u32::from(byte))
+}
+
+/// Converts a pixel value of the given color depth to an RGB value.
+fn pixel_to_rgb(value: u32, bpp: u8, color_table: Option<&[u32]>) -> u32 {
+    match bpp {
+        8 => color_table_lookup(value, color_table),
+        15 => {
+            let expand = |component: u32| (component << 3) | (component >> 2);
+
+            (expand((value >> 10) & 0x1F) << 16) | (expand((value >> 5) & 0x1F) << 8) | expand(value & 0x1F)
+        }
+        16 => {
+            let [r, g, b] = rdp_16bit_to_rgb(u16::try_from(value & 0xFFFF).expect("masked to 16 bits"));
+
+            (u32::from(r) << 16) | (u32::from(g) << 8) | u32::from(b)
+        }
+        // Pixels are stored in BGR order.
+        _ => value & 0x00FF_FFFF,
+    }
+}
+
+/// Returns the color of a color table index, or a gray level if there is no color table.
+fn color_table_lookup(index: u32, color_table: Option<&[u32]>) -> u32 {
+    let index = index & 0xFF;
+
+    color_table
+        .and_then(|color_table| color_table.get(usize::try_from(index).ok()?).copied())
+        .unwrap_or(index * 0x01_01_01)
+}
@@ -0,0 +1,61 @@
+//! Raster operations ([MS-RDPEGDI] 2.2.2.2.1.1.1.7)
+//!
+//! [MS-RDPEGDI]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpegdi/745f2eee-d110-464c-8aca-06fc1814f6ad
+
+/// PATCOPY ternary raster operation
+pub(super) const PATCOPY: u8 = 0xF0;
+
+/// Applies a ternary raster operation to the pattern, source and destination pixels.
+///
+/// The index of each bit of `rop` is made of the pattern, source and destination bits, from the
+/// most significant to the least significant one, and its value is the result for these bits.
+pub(super) fn rop3(rop: u8, pattern: u32, source: u32, destination: u32) -> u32 {
+    // The most common operations are handled without going through the truth table.
+    match rop {
+        0x00 => return 0,
+        0xCC => return source,
+        0xF0 => return pattern,
+        0xAA => return destination,
+        0xFF => return 0x00FF_FFFF,
+        _ => {}
+    }
+
+    let mut result = 0;
+
+    for bit in 0..8 {
+        if rop & (1 << bit) == 0 {
+            continue;
+        }
+
+        let pattern = if bit & 0x04 != 0 { pattern } else { !pattern };
+        let source = if bit & 0x02 != 0 { source } else { !source };
+        let destination = if bit & 0x01 != 0 { destination } else { !destination };
+
+        result |= pattern & source & destination;
+    }
+
+    result & 0x00FF_FFFF
+}
+
+/// Applies a binary raster operation (R2_*) to the pen and destination pixels.
+///
+/// Binary raster operations are numbered from 1, the index of each bit of the operation minus one
+/// being made of the pen and destination bits.
+pub(super) fn rop2(rop: u8, pen: u32, destination: u32) -> u32 {
+    let rop = rop.wrapping_sub(1) & 0x0F;
+
+    let mut result = 0;
+
+    for bit in 0..4 {
+        if rop & (1 << bit) == 0 {
+            continue;
+        }
+
+        let pen = if bit & 0x02 != 0 { pen } else { !pen };
+        let destination = if bit & 0x01 != 0 { destination } else { !destination };
+
+        result |= pen & destination;
+    }
+
+    result & 0x00FF_FFFF
+}
@@ -45,6 +45,28 @@ impl DecodingContext {
         destination: &InclusiveRectangle,
         input: &mut ReadCursor<'_>,
     ) -> SessionResult<(FrameId, InclusiveRectangle)> {
+        self.decode_tiles(
+            destination,
+            input,
+            |tile_output, clipping_rectangles, update_rectangle| {
+                image.apply_tile(tile_output, PixelFormat::RgbA32, clipping_rectangles, update_rectangle)
+            },
+        )
+    }
+
+    /// Decodes a RemoteFX message, handing each decoded tile to `apply_tile`.
+    ///
+    /// The tile is a 64x64 `RgbA32` buffer. `apply_tile` receives the clipping region and the
+    /// destination rectangle of the tile, and returns the rectangle it actually updated.
+    pub(crate) fn decode_tiles<F>(
+        &mut self,
+        destination: &InclusiveRectangle,
+        input: &mut ReadCursor<'_>,
+        apply_tile: F,
+    ) -> SessionResult<(FrameId, InclusiveRectangle)>
+    where
+        F: FnMut(&[u8], &Region, &InclusiveRectangle) -> SessionResult<InclusiveRectangle>,
+    {
         loop {
             let block = rfx::Block::decode(input).map_err(|e| custom_err!("decode block", e))?;
 
@@ -53,7 +75,7 @@ impl DecodingContext {
                     self.process_sync(input)?;
                 }
                 rfx::Block::CodecChannel(rfx::CodecChannel::FrameBegin(f)) => {
-                    return self.process_frame(f, input, image, destination);
+                    return self.process_frame(f, input, destination, apply_tile);
                 }
                 _ => {
                     return Err(reason_err!(
@@ -100,13 +122,16 @@ impl DecodingContext {
     }
 
     #[instrument(skip_all)]
-    fn process_frame(
+    fn process_frame<F>(
         &mut self,
         frame_begin: rfx::FrameBeginPdu,
         input: &mut ReadCursor<'_>,
-        image: &mut DecodedImage,
         destination: &InclusiveRectangle,
-    ) -> SessionResult<(FrameId, InclusiveRectangle)> {
+        mut apply_tile: F,
+    ) -> SessionResult<(FrameId, InclusiveRectangle)>
+    where
+        F: FnMut(&[u8], &Region, &InclusiveRectangle) -> SessionResult<InclusiveRectangle>,
+    {
         let channel = self.channels.0.first().unwrap();
         let width = channel.width.try_into().map_err(|_| general_err!("invalid width"))?;
         let height = channel.height.try_into().map_err(|_| general_err!("invalid height"))?;
@@ -159,9 +184,8 @@ impl DecodingContext {
                 self.decoding_tiles.ycbcr_temp_buffer.as_mut(),
             )?;
 
-            let current_update_rectangle = image.apply_tile(
+            let current_update_rectangle = apply_tile(
                 &self.decoding_tiles.tile_output,
-                PixelFormat::RgbA32,
                 &clipping_rectangles,
                 &update_rectangle,
             )?;
@@ -235,19 +259,42 @@ fn clipping_rectangles(
 ) -> Region {
     let mut clipping_rectangles = Region::new();
 
+    // The rectangles are relative to the destination, and bounded by the channel size. Empty
+    // rectangles, and rectangles beyond the coordinate space, are skipped.
     rectangles
         .iter()
-        .map(|r| InclusiveRectangle {
-            left: min(destination.left + r.x, width - 1),
-            top: min(destination.top + r.y, height - 1),
-            right: min(destination.left + r.x + r.width - 1, width - 1),
-            bottom: min(destination.top + r.y + r.height - 1, height - 1),
+        .filter_map(|r| {
+            let (left, right) = clip_span(destination.left, r.x, r.width, width)?;
+            let (top, bottom) = clip_span(destination.top, r.y, r.height, height)?;
+
+            Some(InclusiveRectangle {
+                left,
+                top,
+                right,
+                bottom,
+            })
         })
         .for_each(|r| clipping_rectangles.union_rectangle(r));
 
     clipping_rectangles
 }
 
+/// Returns the first and last coordinates of the `length` units at `start`, bounded by `limit` and
+/// moved by `offset`, or `None` if they are empty or do not fit in `u16`
+fn clip_span(offset: u16, start: u16, length: u16, limit: u16) -> Option<(u16, u16)> {
+    if length == 0 || limit == 0 {
+        return None;
+    }
+
+    let limit = u32::from(limit) - 1;
+    let last = u32::from(start) + u32::from(length) - 1;
+
+    let first = u16::try_from(u32::from(offset) + min(u32::from(start), limit)).ok()?;
+    let last = u16::try_from(u32::from(offset) + min(last, limit)).ok()?;
+
+    Some((first, last))
+}
+
 fn tiles_to_rectangles<'a>(
     tiles: &'a [Tile<'_>],
     destination: &'a InclusiveRectangle,
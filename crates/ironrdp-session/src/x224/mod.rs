@@ -1,10 +1,18 @@
+use std::borrow::Cow;
+
 use ironrdp_connector::connection_activation::ConnectionActivationSequence;
 use ironrdp_connector::legacy::SendDataIndicationCtx;
-use ironrdp_core::WriteBuf;
+use ironrdp_connector::AutoReconnectCookie;
+use ironrdp_core::{ReadCursor, WriteBuf, WriteCursor};
 use ironrdp_dvc::{DrdynvcClient, DvcProcessor, DynamicVirtualChannel};
+use ironrdp_graphics::bulk;
 use ironrdp_pdu::mcs::{DisconnectProviderUltimatum, DisconnectReason, McsMessage};
-use ironrdp_pdu::rdp::headers::ShareDataPdu;
+use ironrdp_pdu::rdp::client_info::CompressionType;
+use ironrdp_pdu::rdp::headers::{
+    CompressionFlags, ShareControlPduType, ShareDataPdu, SHARE_DATA_HEADER_COMPRESSION_MASK,
+};
 use ironrdp_pdu::rdp::server_error_info::{ErrorInfo, ProtocolIndependentCode, ServerSetErrorInfoPdu};
+use ironrdp_pdu::rdp::session_info::{InfoData, LogonInfoExtended};
 use ironrdp_pdu::x224::X224;
 use ironrdp_svc::{client_encode_svc_messages, StaticChannelSet, SvcMessage, SvcProcessor, SvcProcessorMessages};
 
@@ -22,6 +30,8 @@ pub enum ProcessorOutput {
     ///
     /// [Deactivation-Reactivation Sequence]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/dfc234ce-481a-4674-9a5d-2a7bafb14432
     DeactivateAll(Box<ConnectionActivationSequence>),
+    /// Received an auto-reconnect cookie, which can be used to reconnect to the session.
+    AutoReconnectCookie(AutoReconnectCookie),
 }
 
 #[derive(Debug, Clone)]
@@ -88,15 +98,32 @@ impl Processor {
         self.get_svc_processor::<DrdynvcClient>()?.get_dvc_by_type_id::<T>()
     }
 
+    pub fn get_dvc_mut<T: DvcProcessor + 'static>(&mut self) -> Option<&mut DynamicVirtualChannel> {
+        self.get_svc_processor_mut::<DrdynvcClient>()?
+            .get_dvc_by_type_id_mut::<T>()
+    }
+
     /// Processes a received PDU. Returns a vector of [`ProcessorOutput`] that must be processed
     /// in the returned order.
-    pub fn process(&mut self, frame: &[u8]) -> SessionResult<Vec<ProcessorOutput>> {
+    ///
+    /// `bulk_decompressor` must be shared with the fast-path processing, as the server is using
+    /// the same compression history for all the PDUs.
+    pub fn process(
+        &mut self,
+        frame: &[u8],
+        bulk_decompressor: &mut bulk::Decompressor,
+    ) -> SessionResult<Vec<ProcessorOutput>> {
         let data_ctx: SendDataIndicationCtx<'_> =
             ironrdp_connector::legacy::decode_send_data_indication(frame).map_err(crate::legacy::map_error)?;
         let channel_id = data_ctx.channel_id;
 
         if channel_id == self.io_channel_id {
-            self.process_io_channel(data_ctx)
+            let user_data = decompress_share_data(data_ctx.user_data, bulk_decompressor)?;
+
+            self.process_io_channel(SendDataIndicationCtx {
+                user_data: &user_data,
+                ..data_ctx
+            })
         } else if let Some(svc) = self.static_channels.get_by_channel_id_mut(channel_id) {
             let response_pdus = svc.process(data_ctx.user_data).map_err(SessionError::pdu)?;
             process_svc_messages(response_pdus, channel_id, data_ctx.initiator_id)
@@ -116,7 +143,16 @@ impl Processor {
                 match ctx.pdu {
                     ShareDataPdu::SaveSessionInfo(session_info) => {
                         debug!("Got Session Save Info PDU: {session_info:?}");
-                        Ok(Vec::new())
+
+                        if let InfoData::LogonExtended(LogonInfoExtended {
+                            auto_reconnect: Some(cookie),
+                            ..
+                        }) = session_info.info_data
+                        {
+                            Ok(vec![ProcessorOutput::AutoReconnectCookie(cookie.into())])
+                        } else {
+                            Ok(Vec::new())
+                        }
                     }
                     // FIXME: workaround fix to not terminate the session on "unhandled PDU: Set Keyboard Indicators PDU"
                     ShareDataPdu::SetKeyboardIndicators(data) => {
@@ -180,6 +216,84 @@ impl Processor {
     }
 }
 
+/// Decompresses the payload of a Share Data PDU ([MS-RDPBCGR] 2.2.8.1.1.1.2).
+///
+/// Returns the user data as is if it's not a compressed Share Data PDU. Otherwise, the PDU is
+/// rebuilt with the decompressed payload and the compression fields of the header are cleared.
+///
+/// [MS-RDPBCGR]: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/
+fn decompress_share_data<'a>(
+    user_data: &'a [u8],
+    bulk_decompressor: &mut bulk::Decompressor,
+) -> SessionResult<Cow<'a, [u8]>> {
+    const HEADER_SIZE: usize = 2 /* totalLength */ + 2 /* pduType */ + 2 /* pduSource */ + 4 /* shareId */
+        + 1 /* pad1 */ + 1 /* streamId */ + 2 /* uncompressedLength */ + 1 /* pduType2 */
+        + 1 /* compressedType */ + 2 /* compressedLength */;
+
+    if user_data.len() < HEADER_SIZE {
+        return Ok(Cow::Borrowed(user_data));
+    }
+
+    let mut src = ReadCursor::new(user_data);
+    let _total_length = src.read_u16();
+    let pdu_type = src.read_u16();
+    src.advance(2 /* pduSource */ + 4 /* shareId */ + 1 /* pad1 */ + 1 /* streamId */ + 2 /* uncompressedLength */ + 1 /* pduType2 */);
+    let compressed_type = src.read_u8();
+    let compressed_length = usize::from(src.read_u16());
+
+    let compression_flags = CompressionFlags::from_bits_truncate(compressed_type & !SHARE_DATA_HEADER_COMPRESSION_MASK);
+
+    if pdu_type & 0xF != ShareControlPduType::DataPdu as u16 || compression_flags.is_empty() {
+        return Ok(Cow::Borrowed(user_data));
+    }
+
+    let compression_type = match compressed_type & SHARE_DATA_HEADER_COMPRESSION_MASK {
+        0 => CompressionType::K8,
+        1 => CompressionType::K64,
+        2 => CompressionType::Rdp6,
+        3 => CompressionType::Rdp61,
+        unknown => return Err(reason_err!("Share Data PDU", "invalid compression type: {unknown}")),
+    };
+
+    if !compression_flags.contains(CompressionFlags::COMPRESSED) {
+        // The history must still be flushed or reset as requested.
+        bulk_decompressor
+            .decompress(&user_data[HEADER_SIZE..], compression_flags, compression_type)
+            .map_err(|e| custom_err!("bulk decompression", e))?;
+
+        return Ok(Cow::Borrowed(user_data));
+    }
+
+    // The compressed length is including the header.
+    if compressed_length < HEADER_SIZE || compressed_length > user_data.len() {
+        return Err(reason_err!(
+            "Share Data PDU",
+            "invalid compressed length: {compressed_length}"
+        ));
+    }
+
+    let payload = bulk_decompressor
+        .decompress(
+            &user_data[HEADER_SIZE..compressed_length],
+            compression_flags,
+            compression_type,
+        )
+        .map_err(|e| custom_err!("bulk decompression", e))?;
+
+    let total_length = u16::try_from(HEADER_SIZE + payload.len())
+        .map_err(|_| reason_err!("Share Data PDU", "decompressed PDU is too big"))?;
+
+    let mut decompressed = vec![0; HEADER_SIZE + payload.len()];
+    let mut dst = WriteCursor::new(&mut decompressed);
+    dst.write_u16(total_length);
+    dst.write_slice(&user_data[2..HEADER_SIZE - 3]);
+    dst.write_u8(0); // compressedType
+    dst.write_u16(0); // compressedLength
+    dst.write_slice(payload);
+
+    Ok(Cow::Owned(decompressed))
+}
+
 /// Processes a vector of [`SvcMessage`] in preparation for sending them to the server on the `channel_id` channel.
 ///
 /// This includes chunkifying the messages, adding MCS, x224, and tpkt headers, and encoding them into a buffer.
@@ -0,0 +1,289 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+
+use ironrdp_cliprdr::backend::{ClipboardMessage, ClipboardMessageProxy, CliprdrBackendFactory};
+use ironrdp_cliprdr::memory::{ClipboardEvent, ClipboardFile, ClipboardItem, ClipboardItemKind, MemoryClipboard};
+use ironrdp_cliprdr::pdu::{ClipboardPdu, FileContentsFlags, FileContentsRequest, LockDataId};
+use ironrdp_cliprdr::{Client, Cliprdr, Role, Server};
+use ironrdp_svc::{StaticVirtualChannel, SvcMessage};
+
+#[derive(Debug, Clone, Default)]
+struct MessageQueue(Arc<Mutex<VecDeque<ClipboardMessage>>>);
+
+impl ClipboardMessageProxy for MessageQueue {
+    fn send_clipboard_message(&self, message: ClipboardMessage) {
+        self.0.lock().unwrap().push_back(message);
+    }
+}
+
+impl MessageQueue {
+    fn pop(&self) -> Option<ClipboardMessage> {
+        self.0.lock().unwrap().pop_front()
+    }
+}
+
+struct Endpoint {
+    clipboard: MemoryClipboard,
+    channel: StaticVirtualChannel,
+    messages: MessageQueue,
+    events: mpsc::Receiver<ClipboardEvent>,
+}
+
+impl Endpoint {
+    fn new<R: Role>(name: &str) -> Self {
+        let (sender, events) = mpsc::channel();
+        let messages = MessageQueue::default();
+
+        let clipboard = MemoryClipboard::new()
+            .with_temporary_directory(temp_dir(name))
+            .with_event_sender(sender);
+        clipboard.set_message_proxy(messages.clone());
+
+        Self {
+            channel: StaticVirtualChannel::new(Cliprdr::<R>::new(clipboard.build_cliprdr_backend())),
+            clipboard,
+            messages,
+            events,
+        }
+    }
+
+    fn next_event(&self) -> ClipboardEvent {
+        self.events.try_recv().expect("clipboard event")
+    }
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("ironrdp-cliprdr-{name}-{}", std::process::id()))
+}
+
+fn process_message<R: Role>(channel: &StaticVirtualChannel, message: ClipboardMessage) -> Vec<SvcMessage> {
+    let cliprdr = channel.channel_processor_downcast_ref::<Cliprdr<R>>().unwrap();
+
+    match message {
+        ClipboardMessage::SendInitiateCopy(formats) => cliprdr.initiate_copy(&formats),
+        ClipboardMessage::SendFormatData(response) => cliprdr.submit_format_data(response),
+        ClipboardMessage::SendInitiatePaste(format) => cliprdr.initiate_paste(format),
+        ClipboardMessage::SendFileContents(response) => cliprdr.submit_file_contents(response),
+        ClipboardMessage::SendFileContentsRequest(request) => cliprdr.request_file_contents(request),
+        ClipboardMessage::Error(error) => panic!("{error}"),
+    }
+    .unwrap()
+    .into()
+}
+
+fn deliver(messages: Vec<SvcMessage>, channel: &mut StaticVirtualChannel) -> Vec<SvcMessage> {
+    StaticVirtualChannel::chunkify(messages)
+        .unwrap()
+        .iter()
+        .flat_map(|chunk| channel.process(chunk.filled()).unwrap())
+        .collect()
+}
+
+/// Exchanges the messages of both endpoints until they are idle
+fn exchange(client: &mut Endpoint, server: &mut Endpoint) {
+    let mut to_client = Vec::new();
+    let mut to_server = Vec::new();
+
+    loop {
+        if let Some(message) = client.messages.pop() {
+            to_server.extend(process_message::<Client>(&client.channel, message));
+        } else if let Some(message) = server.messages.pop() {
+            to_client.extend(process_message::<Server>(&server.channel, message));
+        } else if !to_server.is_empty() {
+            to_client.extend(deliver(core::mem::take(&mut to_server), &mut server.channel));
+        } else if !to_client.is_empty() {
+            to_server.extend(deliver(core::mem::take(&mut to_client), &mut client.channel));
+        } else {
+            break;
+        }
+    }
+}
+
+fn connect(name: &str) -> (Endpoint, Endpoint) {
+    let mut client = Endpoint::new::<Client>(&format!("{name}-client"));
+    let mut server = Endpoint::new::<Server>(&format!("{name}-server"));
+
+    let start = server.channel.start().unwrap();
+    let response = deliver(start, &mut client.channel);
+    assert!(response.is_empty());
+    exchange(&mut client, &mut server);
+
+    // The empty clipboard of the client is advertised during the initialization.
+    assert!(matches!(server.next_event(), ClipboardEvent::RemoteCopy(kinds) if kinds.is_empty()));
+
+    (client, server)
+}
+
+#[test]
+fn copy_text_html_png() {
+    let (mut client, mut server) = connect("items");
+
+    let png = b"\x89PNG\r\n\x1a\n not really an image".to_vec();
+    client.clipboard.set_contents(vec![
+        ClipboardItem::Text("Hello, world!".to_owned()),
+        ClipboardItem::Html("<b>Hello</b>, world!".to_owned()),
+        ClipboardItem::Png(png.clone()),
+    ]);
+    exchange(&mut client, &mut server);
+
+    let ClipboardEvent::RemoteCopy(kinds) = server.next_event() else {
+        panic!("expected remote copy");
+    };
+    assert_eq!(
+        kinds,
+        [ClipboardItemKind::Text, ClipboardItemKind::Html, ClipboardItemKind::Png]
+    );
+    assert!(!server.clipboard.paste(ClipboardItemKind::Files));
+
+    for kind in [ClipboardItemKind::Text, ClipboardItemKind::Html, ClipboardItemKind::Png] {
+        assert!(server.clipboard.paste(kind));
+    }
+    exchange(&mut client, &mut server);
+
+    assert!(
+        matches!(server.next_event(), ClipboardEvent::Pasted(ClipboardItem::Text(text)) if text == "Hello, world!")
+    );
+    assert!(
+        matches!(server.next_event(), ClipboardEvent::Pasted(ClipboardItem::Html(html)) if html == "<b>Hello</b>, world!")
+    );
+    assert!(matches!(server.next_event(), ClipboardEvent::Pasted(ClipboardItem::Png(data)) if data == png));
+}
+
+#[test]
+fn copy_files() {
+    let (mut client, mut server) = connect("files");
+
+    let source = temp_dir("files-source");
+    let _ = fs::remove_dir_all(&source);
+    fs::create_dir_all(source.join("dir/empty")).unwrap();
+    let large: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(source.join("dir/large.bin"), &large).unwrap();
+    fs::write(source.join("dir/small.txt"), b"small").unwrap();
+
+    let mut files = ClipboardFile::from_paths([source.join("dir")]).unwrap();
+    let names: Vec<_> = files.iter().map(ClipboardFile::name).collect();
+    assert_eq!(names, ["dir", "dir\\empty", "dir\\large.bin", "dir\\small.txt"]);
+    files.push(ClipboardFile::from_reader(
+        "reader.txt",
+        11,
+        Cursor::new(b"from reader".to_vec()),
+    ));
+
+    server.clipboard.set_contents(vec![ClipboardItem::Files(files)]);
+    exchange(&mut client, &mut server);
+
+    assert!(matches!(client.next_event(), ClipboardEvent::RemoteCopy(kinds) if kinds == [ClipboardItemKind::Files]));
+    assert!(client.clipboard.paste(ClipboardItemKind::Files));
+    exchange(&mut client, &mut server);
+
+    let ClipboardEvent::Pasted(ClipboardItem::Files(received)) = client.next_event() else {
+        panic!("expected pasted files");
+    };
+    let names: Vec<_> = received
+        .iter()
+        .map(|file| (file.name(), file.size(), file.is_directory()))
+        .collect();
+    assert_eq!(
+        names,
+        [
+            ("dir", 0, true),
+            ("dir\\empty", 0, true),
+            ("dir\\large.bin", 200_000, false),
+            ("dir\\small.txt", 5, false),
+            ("reader.txt", 11, false),
+        ]
+    );
+
+    // The files are received into a private directory of the temporary directory.
+    let destination = received[0].path().unwrap().parent().unwrap().to_owned();
+    assert_eq!(destination.parent(), Some(temp_dir("files-client").as_path()));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        assert_eq!(fs::metadata(&destination).unwrap().permissions().mode() & 0o777, 0o700);
+    }
+    assert!(destination.join("dir/empty").is_dir());
+    assert_eq!(fs::read(destination.join("dir/large.bin")).unwrap(), large);
+    assert_eq!(fs::read(destination.join("dir/small.txt")).unwrap(), b"small");
+    assert_eq!(fs::read(destination.join("reader.txt")).unwrap(), b"from reader");
+
+    let _ = fs::remove_dir_all(source);
+    let _ = fs::remove_dir_all(temp_dir("files-client"));
+}
+
+#[test]
+fn reject_escaping_file_names() {
+    let (mut client, mut server) = connect("escape");
+
+    let file = ClipboardFile::from_reader("..\\escape.txt", 4, Cursor::new(b"evil".to_vec()));
+    server.clipboard.set_contents(vec![ClipboardItem::Files(vec![file])]);
+    exchange(&mut client, &mut server);
+    client.next_event();
+
+    assert!(client.clipboard.paste(ClipboardItemKind::Files));
+    exchange(&mut client, &mut server);
+
+    assert!(matches!(
+        client.next_event(),
+        ClipboardEvent::PasteFailed(ClipboardItemKind::Files)
+    ));
+    assert!(!temp_dir("escape").join("escape.txt").exists());
+}
+
+/// Requests the contents of the first file, from its third byte
+fn request_file(server: &mut Endpoint, data_id: Option<u32>) -> Option<Vec<u8>> {
+    let request = FileContentsRequest {
+        stream_id: 7,
+        index: 0,
+        flags: FileContentsFlags::DATA,
+        position: 2,
+        requested_size: 100,
+        data_id,
+    };
+    let request = SvcMessage::from(ClipboardPdu::FileContentsRequest(request));
+    assert!(deliver(vec![request], &mut server.channel).is_empty());
+
+    match server.messages.pop() {
+        Some(ClipboardMessage::SendFileContents(response)) => {
+            assert_eq!(response.stream_id(), 7);
+            (!response.is_error()).then(|| response.data().to_vec())
+        }
+        _ => panic!("expected file contents"),
+    }
+}
+
+#[test]
+fn locked_files() {
+    let (mut client, mut server) = connect("lock");
+
+    let file = ClipboardFile::from_reader("locked.txt", 6, Cursor::new(b"locked".to_vec()));
+    server.clipboard.set_contents(vec![ClipboardItem::Files(vec![file])]);
+    exchange(&mut client, &mut server);
+
+    let lock = SvcMessage::from(ClipboardPdu::LockData(LockDataId(1)));
+    assert!(deliver(vec![lock], &mut server.channel).is_empty());
+
+    // The locks belong to the backend of the connection.
+    let _other = server.clipboard.build_cliprdr_backend();
+
+    // The locked files stay available after the clipboard changes.
+    server
+        .clipboard
+        .set_contents(vec![ClipboardItem::Text("text".to_owned())]);
+    assert!(matches!(
+        server.messages.pop(),
+        Some(ClipboardMessage::SendInitiateCopy(_))
+    ));
+
+    assert_eq!(request_file(&mut server, Some(1)), Some(b"cked".to_vec()));
+    assert_eq!(request_file(&mut server, Some(2)), None);
+    assert_eq!(request_file(&mut server, None), None);
+
+    let unlock = SvcMessage::from(ClipboardPdu::UnlockData(LockDataId(1)));
+    assert!(deliver(vec![unlock], &mut server.channel).is_empty());
+    assert_eq!(request_file(&mut server, Some(1)), None);
+}
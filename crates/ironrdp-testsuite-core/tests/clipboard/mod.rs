@@ -1,4 +1,5 @@
 mod format;
+mod memory;
 
 use expect_test::expect;
 use ironrdp_cliprdr::pdu::{
@@ -20,6 +20,9 @@ mod input;
 mod pcb;
 mod pdu;
 mod rdcleanpath;
+mod rdpdr;
+mod rdpeai;
 mod rdpsnd;
 mod server_name;
 mod session;
+mod standard_security;
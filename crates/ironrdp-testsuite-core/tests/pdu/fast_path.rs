@@ -0,0 +1,33 @@
+use ironrdp_core::{decode, encode_vec};
+use ironrdp_pdu::fast_path::{FastPathUpdatePdu, Fragmentation, UpdateCode};
+use ironrdp_pdu::rdp::client_info::CompressionType;
+use ironrdp_pdu::rdp::headers::CompressionFlags;
+
+const COMPRESSED_UPDATE_BUFFER: [u8; 6] = [
+    0x81, // updateCode = FASTPATH_UPDATETYPE_BITMAP, compression = FASTPATH_OUTPUT_COMPRESSION_USED
+    0x62, // compressionFlags = PACKET_COMPRESSED | PACKET_AT_FRONT, type = PACKET_COMPR_TYPE_RDP6
+    0x02, 0x00, // size
+    0xAB, 0xCD, // updateData
+];
+
+const COMPRESSED_UPDATE: FastPathUpdatePdu<'static> = FastPathUpdatePdu {
+    fragmentation: Fragmentation::Single,
+    update_code: UpdateCode::Bitmap,
+    compression_flags: Some(CompressionFlags::COMPRESSED.union(CompressionFlags::AT_FRONT)),
+    compression_type: Some(CompressionType::Rdp6),
+    data: &[0xAB, 0xCD],
+};
+
+#[test]
+fn compressed_update_is_decoded() {
+    let pdu: FastPathUpdatePdu<'_> = decode(&COMPRESSED_UPDATE_BUFFER).unwrap();
+
+    assert_eq!(pdu, COMPRESSED_UPDATE);
+}
+
+#[test]
+fn compressed_update_is_encoded() {
+    let buffer = encode_vec(&COMPRESSED_UPDATE).unwrap();
+
+    assert_eq!(buffer, COMPRESSED_UPDATE_BUFFER);
+}
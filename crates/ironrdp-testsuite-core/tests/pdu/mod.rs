@@ -1,3 +1,4 @@
+mod fast_path;
 mod gcc;
 mod gfx;
 mod input;
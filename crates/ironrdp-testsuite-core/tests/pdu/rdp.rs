@@ -1,4 +1,10 @@
+use ironrdp_connector::{client_auto_reconnect_packet, AutoReconnectCookie};
 use ironrdp_core::{decode, encode_vec, Encode};
+use ironrdp_pdu::rdp::client_info::{ExtendedClientOptionalInfo, PerformanceFlags};
+use ironrdp_pdu::rdp::finalization_messages::{PersistentKeyListFlags, PersistentKeyListPdu};
+use ironrdp_pdu::rdp::headers::{BasicSecurityHeader, BasicSecurityHeaderFlags, ShareControlHeader, ShareControlPdu};
+use ironrdp_pdu::rdp::server_redirection::{ServerRedirectionFlags, ServerRedirectionPdu};
+use ironrdp_pdu::rdp::SecurityExchangePdu;
 use ironrdp_testsuite_core::capsets::*;
 use ironrdp_testsuite_core::client_info::*;
 use ironrdp_testsuite_core::rdp::*;
@@ -447,3 +453,142 @@ fn buffer_length_is_correct_for_client_demand_active() {
 
     assert_eq!(expected_buffer_len, len);
 }
+
+#[test]
+fn persistent_key_list_pdu_round_trip() {
+    #[rustfmt::skip]
+    let buffer = [
+        0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, // numEntriesCache0..4
+        0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, // totalEntriesCache0..4
+        0x03, // bBitMask
+        0x00, 0x00, 0x00, // padding
+        0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, // Key1, Key2
+        0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00,
+        0x78, 0x56, 0x34, 0x12, 0xF0, 0xDE, 0xBC, 0x9A,
+    ];
+
+    let pdu = PersistentKeyListPdu {
+        num_entries: [2, 0, 1, 0, 0],
+        total_entries: [2, 0, 1, 0, 0],
+        flags: PersistentKeyListFlags::FIRST | PersistentKeyListFlags::LAST,
+        entries: vec![0x0000_0002_0000_0001, 0x0000_0004_0000_0003, 0x9ABC_DEF0_1234_5678],
+    };
+
+    assert_eq!(pdu, decode(buffer.as_ref()).unwrap());
+    assert_eq!(buffer.len(), pdu.size());
+    assert_eq!(buffer.as_ref(), encode_vec(&pdu).unwrap());
+}
+
+#[test]
+fn persistent_key_list_is_split_into_several_pdus() {
+    let keys = [(0..200).collect::<Vec<u64>>(), Vec::new(), (1000..1010).collect()];
+
+    let pdus = PersistentKeyListPdu::from_keys(&keys).unwrap();
+
+    assert_eq!(pdus.len(), 2);
+    assert_eq!(pdus[0].flags, PersistentKeyListFlags::FIRST);
+    assert_eq!(pdus[0].num_entries, [169, 0, 0, 0, 0]);
+    assert_eq!(pdus[1].flags, PersistentKeyListFlags::LAST);
+    assert_eq!(pdus[1].num_entries, [31, 0, 10, 0, 0]);
+    assert!(pdus.iter().all(|pdu| pdu.total_entries == [200, 0, 10, 0, 0]));
+    assert_eq!(pdus[1].entries[31..], keys[2]);
+}
+
+#[test]
+fn persistent_key_list_is_limited_to_five_caches() {
+    let keys = vec![vec![1]; 6];
+
+    assert!(PersistentKeyListPdu::from_keys(&keys[..5]).is_some());
+    assert!(PersistentKeyListPdu::from_keys(&keys).is_none());
+}
+
+#[test]
+fn client_auto_reconnect_packet_round_trip() {
+    // HMAC-MD5 test case 1 of RFC 2104
+    let cookie = AutoReconnectCookie {
+        logon_id: 0x0000_0002,
+        random_bits: [0x0B; 16],
+    };
+
+    #[rustfmt::skip]
+    let buffer = [
+        0x1C, 0x00, // cbAutoReconnectCookie
+        0x1C, 0x00, 0x00, 0x00, // cbLen
+        0x01, 0x00, 0x00, 0x00, // Version
+        0x02, 0x00, 0x00, 0x00, // LogonId
+        0x92, 0x94, 0x72, 0x7A, 0x36, 0x38, 0xBB, 0x1C, // SecurityVerifier
+        0x13, 0xF4, 0x8E, 0xF8, 0x15, 0x8B, 0xFC, 0x9D,
+    ];
+
+    let optional_data = ExtendedClientOptionalInfo::builder()
+        .timezone(CLIENT_INFO_UNICODE.extra_info.optional_data.timezone().unwrap().clone())
+        .session_id(0)
+        .performance_flags(PerformanceFlags::DISABLE_WALLPAPER)
+        .reconnect_cookie(client_auto_reconnect_packet(&cookie, b"Hi There"))
+        .build();
+
+    let encoded = encode_vec(&optional_data).unwrap();
+
+    assert_eq!(encoded[encoded.len() - buffer.len()..], buffer);
+    assert_eq!(optional_data, decode(encoded.as_slice()).unwrap());
+}
+
+#[test]
+fn server_redirection_pdu_round_trip() {
+    #[rustfmt::skip]
+    let buffer = [
+        0x25, 0x00, // totalLength
+        0x1A, 0x00, // pduType
+        0xEA, 0x03, // pduSource
+        0x00, 0x00, // pad2Octets
+        0x00, 0x04, // Flags
+        0x1C, 0x00, // Length
+        0x05, 0x00, 0x00, 0x00, // SessionID
+        0x82, 0x01, 0x00, 0x00, // RedirFlags
+        0x04, 0x00, 0x00, 0x00, // LoadBalanceInfoLength
+        b'a', b'b', b'\r', b'\n', // LoadBalanceInfo
+        0x04, 0x00, 0x00, 0x00, // TargetFQDNLength
+        b'h', 0x00, 0x00, 0x00, // TargetFQDN
+        0x00, // pad1Octet
+    ];
+
+    let header = ShareControlHeader {
+        share_control_pdu: ShareControlPdu::ServerRedirect(ServerRedirectionPdu {
+            session_id: 5,
+            flags: ServerRedirectionFlags::NO_REDIRECT,
+            load_balance_info: Some(b"ab\r\n".to_vec()),
+            target_fqdn: Some("h".to_owned()),
+            ..Default::default()
+        }),
+        pdu_source: 1002,
+        share_id: 0,
+    };
+
+    assert_eq!(header, decode::<ShareControlHeader>(&buffer).unwrap());
+
+    let encoded = encode_vec(&header).unwrap();
+
+    assert_eq!(encoded[2..], buffer[2..buffer.len() - 1]);
+    assert_eq!(header, decode(encoded.as_slice()).unwrap());
+}
+
+#[test]
+fn security_exchange_pdu_round_trip() {
+    #[rustfmt::skip]
+    let buffer = [
+        0x01, 0x00, // flags
+        0x00, 0x00, // flagsHi
+        0x04, 0x00, 0x00, 0x00, // length
+        0x01, 0x02, 0x03, 0x04, // encryptedClientRandom
+    ];
+
+    let pdu = SecurityExchangePdu {
+        security_header: BasicSecurityHeader {
+            flags: BasicSecurityHeaderFlags::EXCHANGE_PKT,
+        },
+        encrypted_client_random: vec![0x01, 0x02, 0x03, 0x04],
+    };
+
+    assert_eq!(pdu, decode::<SecurityExchangePdu>(&buffer).unwrap());
+    assert_eq!(buffer.as_slice(), encode_vec(&pdu).unwrap());
+}
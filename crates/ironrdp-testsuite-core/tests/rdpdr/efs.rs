@@ -0,0 +1,170 @@
+use ironrdp_core::{encode_vec, ReadCursor};
+use ironrdp_rdpdr::pdu::efs::{
+    ClientDriveLockControlResponse, ClientDriveNotifyChangeDirectoryResponse, DeviceIoRequest, DeviceIoResponse,
+    FileAction, FileNotifyInformation, LockOperation, MajorFunction, MinorFunction, NtStatus, RdpLockInfo,
+    ServerDriveIoRequest, ServerDriveLockControlRequest,
+};
+use ironrdp_rdpdr::pdu::RdpdrPdu;
+
+#[rustfmt::skip]
+const LOCK_CONTROL_REQUEST: [u8; 84] = [
+    0x01, 0x00, 0x00, 0x00, // DeviceId
+    0x05, 0x00, 0x00, 0x00, // FileId
+    0x07, 0x00, 0x00, 0x00, // CompletionId
+    0x11, 0x00, 0x00, 0x00, // MajorFunction: IRP_MJ_LOCK_CONTROL
+    0x00, 0x00, 0x00, 0x00, // MinorFunction
+    0x03, 0x00, 0x00, 0x00, // Operation: RDP_LOWIO_OP_EXCLUSIVELOCK
+    0x01, 0x00, 0x00, 0x00, // F, Padding
+    0x02, 0x00, 0x00, 0x00, // NumLocks
+    0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, // Padding2
+    0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, 0xAA, //
+    0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Length
+    0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Offset
+    0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Length
+    0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, // Offset
+];
+
+fn decode_io_request(bytes: &[u8]) -> ironrdp_core::DecodeResult<ServerDriveIoRequest> {
+    let mut src = ReadCursor::new(bytes);
+    let device_io_request = DeviceIoRequest::decode(&mut src)?;
+    ServerDriveIoRequest::decode(device_io_request, &mut src)
+}
+
+#[test]
+fn lock_control_request_is_decoded() {
+    let expected = ServerDriveLockControlRequest {
+        device_io_request: DeviceIoRequest {
+            device_id: 1,
+            file_id: 5,
+            completion_id: 7,
+            major_function: MajorFunction::LockControl,
+            minor_function: MinorFunction::from(0),
+        },
+        operation: LockOperation::ExclusiveLock,
+        wait: true,
+        locks: vec![
+            RdpLockInfo {
+                length: 0x10,
+                offset: 0x100,
+            },
+            RdpLockInfo {
+                length: 0x20,
+                offset: 0x1_0000_0000,
+            },
+        ],
+    };
+
+    assert_eq!(
+        decode_io_request(&LOCK_CONTROL_REQUEST).unwrap(),
+        ServerDriveIoRequest::ServerDriveLockControlRequest(expected)
+    );
+}
+
+#[test]
+fn lock_control_request_without_wait() {
+    let mut bytes = LOCK_CONTROL_REQUEST;
+    bytes[20] = 0x04; // RDP_LOWIO_OP_UNLOCK
+    bytes[24] = 0x00;
+
+    let ServerDriveIoRequest::ServerDriveLockControlRequest(request) = decode_io_request(&bytes).unwrap() else {
+        panic!("expected a lock control request");
+    };
+    assert_eq!(request.operation, LockOperation::Unlock);
+    assert!(!request.wait);
+}
+
+#[test]
+fn invalid_lock_control_requests_are_rejected() {
+    // Unknown operation
+    let mut bytes = LOCK_CONTROL_REQUEST;
+    bytes[20] = 0x01;
+    assert!(decode_io_request(&bytes).is_err());
+
+    // Missing lock
+    assert!(decode_io_request(&LOCK_CONTROL_REQUEST[..LOCK_CONTROL_REQUEST.len() - 1]).is_err());
+}
+
+#[test]
+fn lock_control_response_is_encoded() {
+    let pdu = RdpdrPdu::ClientDriveLockControlResponse(ClientDriveLockControlResponse {
+        device_io_reply: DeviceIoResponse {
+            device_id: 1,
+            completion_id: 7,
+            io_status: NtStatus::LOCK_NOT_GRANTED,
+        },
+    });
+
+    #[rustfmt::skip]
+    let expected = [
+        0x72, 0x44, 0x43, 0x49, // RDPDR_CTYP_CORE, PAKID_CORE_DEVICE_IOCOMPLETION
+        0x01, 0x00, 0x00, 0x00, // DeviceId
+        0x07, 0x00, 0x00, 0x00, // CompletionId
+        0x55, 0x00, 0x00, 0xC0, // IoStatus: STATUS_LOCK_NOT_GRANTED
+        0x00, 0x00, 0x00, 0x00, 0x00, // Padding
+    ];
+    assert_eq!(encode_vec(&pdu).unwrap(), expected);
+}
+
+#[test]
+fn notify_change_directory_response_is_encoded() {
+    let pdu = RdpdrPdu::ClientDriveNotifyChangeDirectoryResponse(ClientDriveNotifyChangeDirectoryResponse {
+        device_io_reply: DeviceIoResponse {
+            device_id: 1,
+            completion_id: 7,
+            io_status: NtStatus::SUCCESS,
+        },
+        buffer: vec![
+            FileNotifyInformation {
+                action: FileAction::Added,
+                file_name: "a.txt".to_owned(),
+            },
+            FileNotifyInformation {
+                action: FileAction::RenamedNewName,
+                file_name: "dir\\b".to_owned(),
+            },
+        ],
+    });
+
+    #[rustfmt::skip]
+    let expected = [
+        0x72, 0x44, 0x43, 0x49, // RDPDR_CTYP_CORE, PAKID_CORE_DEVICE_IOCOMPLETION
+        0x01, 0x00, 0x00, 0x00, // DeviceId
+        0x07, 0x00, 0x00, 0x00, // CompletionId
+        0x00, 0x00, 0x00, 0x00, // IoStatus
+        0x2E, 0x00, 0x00, 0x00, // Length
+        // FILE_NOTIFY_INFORMATION, padded to 4 bytes
+        0x18, 0x00, 0x00, 0x00, // NextEntryOffset
+        0x01, 0x00, 0x00, 0x00, // Action: FILE_ACTION_ADDED
+        0x0A, 0x00, 0x00, 0x00, // FileNameLength
+        0x61, 0x00, 0x2E, 0x00, 0x74, 0x00, 0x78, 0x00, 0x74, 0x00, // a.txt
+        0x00, 0x00, // Padding
+        // Last FILE_NOTIFY_INFORMATION
+        0x00, 0x00, 0x00, 0x00, // NextEntryOffset
+        0x05, 0x00, 0x00, 0x00, // Action: FILE_ACTION_RENAMED_NEW_NAME
+        0x0A, 0x00, 0x00, 0x00, // FileNameLength
+        0x64, 0x00, 0x69, 0x00, 0x72, 0x00, 0x5C, 0x00, 0x62, 0x00, // dir\b
+    ];
+    assert_eq!(encode_vec(&pdu).unwrap(), expected);
+}
+
+#[test]
+fn empty_notify_change_directory_response_is_encoded() {
+    let pdu = RdpdrPdu::ClientDriveNotifyChangeDirectoryResponse(ClientDriveNotifyChangeDirectoryResponse {
+        device_io_reply: DeviceIoResponse {
+            device_id: 1,
+            completion_id: 7,
+            io_status: NtStatus::NOTIFY_ENUM_DIR,
+        },
+        buffer: Vec::new(),
+    });
+
+    #[rustfmt::skip]
+    let expected = [
+        0x72, 0x44, 0x43, 0x49, // RDPDR_CTYP_CORE, PAKID_CORE_DEVICE_IOCOMPLETION
+        0x01, 0x00, 0x00, 0x00, // DeviceId
+        0x07, 0x00, 0x00, 0x00, // CompletionId
+        0x0C, 0x01, 0x00, 0x00, // IoStatus: STATUS_NOTIFY_ENUM_DIR
+        0x00, 0x00, 0x00, 0x00, // Length
+    ];
+    assert_eq!(encode_vec(&pdu).unwrap(), expected);
+}
@@ -0,0 +1,74 @@
+use ironrdp_rdpeai::pdu;
+use ironrdp_testsuite_core::encode_decode_test;
+
+fn pcm_stereo() -> pdu::AudioFormat {
+    pdu::AudioFormat {
+        format: pdu::WaveFormat::PCM,
+        n_channels: 2,
+        n_samples_per_sec: 44100,
+        n_avg_bytes_per_sec: 176400,
+        n_block_align: 4,
+        bits_per_sample: 16,
+        data: None,
+    }
+}
+
+encode_decode_test! {
+    version: pdu::AudioInputPdu::Version(pdu::VersionPdu { version: pdu::Version::V2 }),
+    [
+        0x01, // MessageId
+        0x02, 0x00, 0x00, 0x00,
+    ];
+
+    formats: pdu::AudioInputPdu::Formats(pdu::FormatsPdu { formats: vec![pcm_stereo()] }),
+    [
+        0x02, // MessageId
+        0x01, 0x00, 0x00, 0x00, // NumFormats
+        0x1b, 0x00, 0x00, 0x00, // cbSizeFormatsPacket
+        0x01, 0x00, 0x02, 0x00, 0x44, 0xac, 0x00, 0x00, 0x10, 0xb1, 0x02, 0x00, 0x04, 0x00, 0x10, 0x00, 0x00, 0x00,
+    ];
+
+    open: pdu::AudioInputPdu::Open(pdu::OpenPdu {
+        frames_per_packet: 882,
+        initial_format: 0,
+        capture_format: pcm_stereo(),
+    }),
+    [
+        0x03, // MessageId
+        0x72, 0x03, 0x00, 0x00, // FramesPerPacket
+        0x00, 0x00, 0x00, 0x00, // initialFormat
+        0x01, 0x00, 0x02, 0x00, 0x44, 0xac, 0x00, 0x00, 0x10, 0xb1, 0x02, 0x00, 0x04, 0x00, 0x10, 0x00, 0x00, 0x00,
+    ];
+
+    open_reply: pdu::AudioInputPdu::OpenReply(pdu::OpenReplyPdu { result: pdu::OpenReplyPdu::E_FAIL }),
+    [
+        0x04, // MessageId
+        0x05, 0x40, 0x00, 0x80,
+    ];
+
+    data_incoming: pdu::AudioInputPdu::DataIncoming,
+    [
+        0x05, // MessageId
+    ];
+
+    data: pdu::AudioInputPdu::Data(pdu::DataPdu { data: vec![0x01, 0x02, 0x03, 0x04] }),
+    [
+        0x06, // MessageId
+        0x01, 0x02, 0x03, 0x04,
+    ];
+
+    format_change: pdu::AudioInputPdu::FormatChange(pdu::FormatChangePdu { new_format: 1 }),
+    [
+        0x07, // MessageId
+        0x01, 0x00, 0x00, 0x00,
+    ];
+}
+
+#[test]
+fn open_reply_status() {
+    assert!(pdu::OpenReplyPdu { result: 0 }.is_success());
+    assert!(!pdu::OpenReplyPdu {
+        result: pdu::OpenReplyPdu::E_FAIL
+    }
+    .is_success());
+}
@@ -0,0 +1,231 @@
+use ironrdp_rdpsnd::codec::{self, AudioDecoder, AudioEncoder, CodecError};
+use ironrdp_rdpsnd::pdu::{AudioFormat, WaveFormat};
+
+/// A 440 Hz sine wave on the first channel, 660 Hz on the second one
+fn sine_wave(n_channels: usize, n_samples_per_sec: u32, frames: usize) -> Vec<i16> {
+    (0..frames)
+        .flat_map(|i| {
+            (0..n_channels).map(move |channel| {
+                let frequency = 440.0 * (1.0 + 0.5 * channel as f64);
+                let t = i as f64 / f64::from(n_samples_per_sec);
+                #[allow(clippy::cast_possible_truncation)] // the amplitude fits in i16
+                let sample = (f64::sin(2.0 * core::f64::consts::PI * frequency * t) * 16000.0) as i16;
+                sample
+            })
+        })
+        .collect()
+}
+
+fn round_trip(format: &AudioFormat, samples: &[i16]) -> (Vec<u8>, Vec<i16>) {
+    let mut enc = AudioEncoder::new(format).unwrap();
+    let mut encoded = Vec::new();
+    enc.encode(samples, &mut encoded);
+    enc.flush(&mut encoded);
+
+    let mut decoded = Vec::new();
+    AudioDecoder::new(format)
+        .unwrap()
+        .decode(&encoded, &mut decoded)
+        .unwrap();
+
+    (encoded, decoded)
+}
+
+/// Signal-to-noise ratio of the decoded samples, in dB
+fn snr(samples: &[i16], decoded: &[i16]) -> f64 {
+    let (signal, noise) = samples
+        .iter()
+        .zip(decoded)
+        .fold((0.0, 0.0), |(signal, noise), (sample, decoded)| {
+            let error = f64::from(*sample) - f64::from(*decoded);
+            (signal + f64::from(*sample).powi(2), noise + error * error)
+        });
+
+    10.0 * (signal / noise.max(1.0)).log10()
+}
+
+#[test]
+fn supported_formats() {
+    for format in codec::supported_formats(2, 44100) {
+        assert!(codec::is_supported(&format), "{format:?}");
+    }
+
+    let adpcm = codec::ms_adpcm_format(2, 44100);
+    assert_eq!(adpcm.n_block_align, 2048);
+    assert_eq!(adpcm.data.as_ref().unwrap().len(), 32);
+    assert_eq!(adpcm.data.as_ref().unwrap()[..2], 2036u16.to_le_bytes());
+
+    let adpcm = codec::ima_adpcm_format(1, 22050);
+    assert_eq!(adpcm.n_block_align, 512);
+    assert_eq!(adpcm.data, Some(1017u16.to_le_bytes().to_vec()));
+}
+
+#[test]
+fn unsupported_formats() {
+    let mut format = codec::pcm_format(2, 44100);
+    format.format = WaveFormat::GSM610;
+    assert_eq!(
+        AudioDecoder::new(&format).unwrap_err(),
+        CodecError::UnsupportedFormat(WaveFormat::GSM610)
+    );
+
+    let mut format = codec::ima_adpcm_format(2, 44100);
+    format.n_block_align = 1002;
+    assert!(matches!(
+        AudioEncoder::new(&format).unwrap_err(),
+        CodecError::InvalidFormat(_)
+    ));
+
+    let mut format = codec::alaw_format(1, 8000);
+    format.bits_per_sample = 16;
+    assert!(!codec::is_supported(&format));
+}
+
+#[test]
+fn pcm_round_trip() {
+    let samples = sine_wave(2, 44100, 441);
+
+    let (encoded, decoded) = round_trip(&codec::pcm_format(2, 44100), &samples);
+    assert_eq!(encoded.len(), samples.len() * 2);
+    assert_eq!(decoded, samples);
+
+    let mut pcm8 = codec::pcm_format(1, 8000);
+    pcm8.bits_per_sample = 8;
+    pcm8.n_block_align = 1;
+    let (encoded, decoded) = round_trip(&pcm8, &[0, 0x7F00, -0x8000, 0x0123]);
+    assert_eq!(encoded, [0x80, 0xFF, 0x00, 0x81]);
+    assert_eq!(decoded, [0, 0x7F00, -0x8000, 0x0100]);
+}
+
+#[test]
+fn g711_known_values() {
+    let mut encoded = Vec::new();
+    AudioEncoder::new(&codec::alaw_format(1, 8000))
+        .unwrap()
+        .encode(&[0, -1, 32767, -32768], &mut encoded);
+    assert_eq!(encoded, [0xD5, 0x55, 0xAA, 0x2A]);
+
+    let mut encoded = Vec::new();
+    AudioEncoder::new(&codec::mulaw_format(1, 8000))
+        .unwrap()
+        .encode(&[0, -1, 32767, -32768], &mut encoded);
+    assert_eq!(encoded, [0xFF, 0x7E, 0x80, 0x00]);
+
+    let mut decoded = Vec::new();
+    AudioDecoder::new(&codec::alaw_format(1, 8000))
+        .unwrap()
+        .decode(&[0xD5, 0x55, 0xAA, 0x2A], &mut decoded)
+        .unwrap();
+    assert_eq!(decoded, [8, -8, 32256, -32256]);
+
+    let mut decoded = Vec::new();
+    AudioDecoder::new(&codec::mulaw_format(1, 8000))
+        .unwrap()
+        .decode(&[0xFF, 0x7F, 0x80, 0x00], &mut decoded)
+        .unwrap();
+    assert_eq!(decoded, [0, 0, 32124, -32124]);
+}
+
+#[test]
+fn g711_round_trip() {
+    let samples = sine_wave(2, 8000, 800);
+
+    for format in [codec::alaw_format(2, 8000), codec::mulaw_format(2, 8000)] {
+        let (encoded, decoded) = round_trip(&format, &samples);
+        assert_eq!(encoded.len(), samples.len());
+        assert_eq!(decoded.len(), samples.len());
+        assert!(snr(&samples, &decoded) > 30.0, "{format:?}");
+    }
+}
+
+#[test]
+fn ima_adpcm_decode() {
+    let mut format = codec::ima_adpcm_format(1, 8000);
+    format.n_block_align = 8;
+    let block = [0x00, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00];
+
+    let mut decoded = Vec::new();
+    AudioDecoder::new(&format)
+        .unwrap()
+        .decode(&block, &mut decoded)
+        .unwrap();
+    assert_eq!(decoded, [0, 11, 13, 14, 15, 16, 17, 18, 19]);
+
+    let mut decoded = Vec::new();
+    let error = AudioDecoder::new(&format)
+        .unwrap()
+        .decode(&[0x00, 0x00, 89, 0x00], &mut decoded)
+        .unwrap_err();
+    assert!(matches!(error, CodecError::InvalidData(_)));
+}
+
+#[test]
+fn ms_adpcm_decode() {
+    // A partial block: predictor 0, delta 16, second sample 100, first sample 50.
+    let block = [0x00, 0x10, 0x00, 0x64, 0x00, 0x32, 0x00, 0x12];
+
+    let mut decoded = Vec::new();
+    AudioDecoder::new(&codec::ms_adpcm_format(1, 8000))
+        .unwrap()
+        .decode(&block, &mut decoded)
+        .unwrap();
+    assert_eq!(decoded, [50, 100, 116, 148]);
+
+    let mut decoded = Vec::new();
+    let error = AudioDecoder::new(&codec::ms_adpcm_format(1, 8000))
+        .unwrap()
+        .decode(&[0x07, 0x10, 0x00, 0x64, 0x00, 0x32, 0x00], &mut decoded)
+        .unwrap_err();
+    assert!(matches!(error, CodecError::InvalidData(_)));
+}
+
+#[test]
+fn adpcm_round_trip() {
+    for n_channels in [1, 2] {
+        let samples = sine_wave(usize::from(n_channels), 22050, 22050);
+
+        for format in [
+            codec::ima_adpcm_format(n_channels, 22050),
+            codec::ms_adpcm_format(n_channels, 22050),
+        ] {
+            let mut enc = AudioEncoder::new(&format).unwrap();
+            let block_samples = enc.block_samples();
+
+            let mut encoded = Vec::new();
+            for chunk in samples.chunks(441 * usize::from(n_channels)) {
+                enc.encode(chunk, &mut encoded);
+                assert_eq!(encoded.len() % usize::from(format.n_block_align), 0);
+            }
+            enc.flush(&mut encoded);
+
+            let blocks = samples.len().div_ceil(block_samples);
+            assert_eq!(encoded.len(), blocks * usize::from(format.n_block_align));
+
+            let mut decoded = Vec::new();
+            AudioDecoder::new(&format)
+                .unwrap()
+                .decode(&encoded, &mut decoded)
+                .unwrap();
+            assert_eq!(decoded.len(), blocks * block_samples);
+
+            for channel in 0..usize::from(n_channels) {
+                let channel_samples: Vec<_> = samples
+                    .iter()
+                    .skip(channel)
+                    .step_by(usize::from(n_channels))
+                    .copied()
+                    .collect();
+                let channel_decoded: Vec<_> = decoded
+                    .iter()
+                    .skip(channel)
+                    .step_by(usize::from(n_channels))
+                    .copied()
+                    .collect();
+                assert!(
+                    snr(&channel_samples, &channel_decoded) > 30.0,
+                    "{format:?} channel {channel}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+
+use ironrdp_core::{decode, encode_vec};
+use ironrdp_rdpsnd::client::{Rdpsnd, RdpsndClientHandler};
+use ironrdp_rdpsnd::pdu::{self, AudioFormat, ClientAudioFormatPdu, PitchPdu, VolumePdu};
+use ironrdp_rdpsnd::server::{RdpsndServer, RdpsndServerHandler};
+use ironrdp_svc::{StaticVirtualChannel, SvcMessage, SvcProcessor};
+
+type Waves = Arc<Mutex<Vec<(u32, Vec<u8>)>>>;
+type SentWaves = Arc<Mutex<Vec<(u8, u32)>>>;
+
+#[derive(Debug)]
+struct TestClientHandler {
+    formats: Vec<AudioFormat>,
+    waves: Waves,
+}
+
+impl RdpsndClientHandler for TestClientHandler {
+    fn get_formats(&self) -> &[AudioFormat] {
+        &self.formats
+    }
+
+    fn wave(&mut self, _format: &AudioFormat, ts: u32, data: Cow<'_, [u8]>) {
+        self.waves.lock().unwrap().push((ts, data.into_owned()));
+    }
+
+    fn set_volume(&mut self, _volume: VolumePdu) {}
+
+    fn set_pitch(&mut self, _pitch: PitchPdu) {}
+
+    fn close(&mut self) {}
+}
+
+#[derive(Debug)]
+struct TestServerHandler {
+    formats: Vec<AudioFormat>,
+    sent_waves: SentWaves,
+}
+
+impl RdpsndServerHandler for TestServerHandler {
+    fn get_formats(&self) -> &[AudioFormat] {
+        &self.formats
+    }
+
+    fn start(&mut self, _client_format: &ClientAudioFormatPdu) -> Option<u16> {
+        Some(0)
+    }
+
+    fn wave_sent(&mut self, block_no: u8, ts: u32) {
+        self.sent_waves.lock().unwrap().push((block_no, ts));
+    }
+
+    fn stop(&mut self) {}
+}
+
+fn pcm_format() -> AudioFormat {
+    AudioFormat {
+        format: pdu::WaveFormat::PCM,
+        n_channels: 2,
+        n_samples_per_sec: 22050,
+        n_avg_bytes_per_sec: 88200,
+        n_block_align: 4,
+        bits_per_sample: 16,
+        data: None,
+    }
+}
+
+fn client() -> (Rdpsnd, Waves) {
+    let waves = Waves::default();
+    let handler = TestClientHandler {
+        formats: vec![pcm_format()],
+        waves: Arc::clone(&waves),
+    };
+
+    (Rdpsnd::new(Box::new(handler)), waves)
+}
+
+/// Payloads of the messages, without the channel PDU header
+fn encode(messages: Vec<SvcMessage>) -> Vec<Vec<u8>> {
+    StaticVirtualChannel::chunkify(messages)
+        .unwrap()
+        .iter()
+        .map(|chunk| chunk.filled()[8..].to_vec())
+        .collect()
+}
+
+fn decode_client_pdus(messages: Vec<SvcMessage>) -> Vec<pdu::ClientAudioOutputPdu> {
+    encode(messages)
+        .iter()
+        .map(|payload| decode::<pdu::ClientAudioOutputPdu>(payload).unwrap())
+        .collect()
+}
+
+fn server_formats(version: pdu::Version) -> Vec<u8> {
+    encode_vec(&pdu::ServerAudioOutputPdu::AudioFormat(pdu::ServerAudioFormatPdu {
+        version,
+        formats: vec![pcm_format()],
+    }))
+    .unwrap()
+}
+
+const TRAINING: [u8; 8] = [0x06, 0x00, 0x04, 0x00, 0xda, 0x89, 0x00, 0x00];
+
+const TRAINING_CONFIRM: pdu::ClientAudioOutputPdu =
+    pdu::ClientAudioOutputPdu::TrainingConfirm(pdu::TrainingConfirmPdu {
+        timestamp: 0x89da,
+        pack_size: 0,
+    });
+
+/// Moves the client to the Ready state, and returns the PDUs sent in response to the formats
+fn negotiate(client: &mut Rdpsnd, version: pdu::Version) -> Vec<pdu::ClientAudioOutputPdu> {
+    let response = decode_client_pdus(client.process(&server_formats(version)).unwrap());
+    assert_eq!(
+        decode_client_pdus(client.process(&TRAINING).unwrap()),
+        [TRAINING_CONFIRM]
+    );
+
+    response
+}
+
+#[test]
+fn client_v5_negotiation() {
+    let (mut client, _) = client();
+
+    let response = negotiate(&mut client, pdu::Version::V5);
+
+    // No Quality Mode PDU before version 6.
+    let [pdu::ClientAudioOutputPdu::AudioFormat(formats)] = response.as_slice() else {
+        panic!("unexpected response: {response:?}");
+    };
+    assert_eq!(formats.version, pdu::Version::V5);
+    assert_eq!(formats.formats, [pcm_format()]);
+    assert_eq!(formats.dgram_port, 0);
+}
+
+#[test]
+fn client_v6_negotiation() {
+    let (mut client, _) = client();
+
+    let response = negotiate(&mut client, pdu::Version::V6);
+
+    assert!(matches!(
+        response.as_slice(),
+        [
+            pdu::ClientAudioOutputPdu::AudioFormat(_),
+            pdu::ClientAudioOutputPdu::QualityMode(_)
+        ]
+    ));
+}
+
+#[test]
+fn client_split_wave() {
+    let (mut client, waves) = client();
+    negotiate(&mut client, pdu::Version::V5);
+
+    // Wave Info PDU, with the first 4 bytes of the data
+    let wave_info = [
+        0x02, 0x00, 0x10, 0x00, 0xd7, 0xad, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04,
+    ];
+    // Wave PDU, without header
+    let wave = [0x00, 0x00, 0x00, 0x00, 0x05, 0x06, 0x07, 0x08];
+
+    assert!(client.process(&wave_info).unwrap().is_empty());
+    assert!(waves.lock().unwrap().is_empty());
+
+    let confirm = decode_client_pdus(client.process(&wave).unwrap());
+    assert_eq!(
+        confirm,
+        [pdu::ClientAudioOutputPdu::WaveConfirm(pdu::WaveConfirmPdu {
+            timestamp: 0xadd7,
+            block_no: 8,
+        })]
+    );
+    assert_eq!(
+        *waves.lock().unwrap(),
+        [(0xadd7, vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08])]
+    );
+
+    // Both PDUs in the same message
+    let combined = [wave_info.as_slice(), wave.as_slice()].concat();
+    assert_eq!(client.process(&combined).unwrap().len(), 1);
+    assert_eq!(waves.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn client_renegotiation() {
+    let (mut client, waves) = client();
+    negotiate(&mut client, pdu::Version::V8);
+    negotiate(&mut client, pdu::Version::V5);
+
+    let wave = encode_vec(&pdu::ServerAudioOutputPdu::Wave(pdu::WavePdu {
+        timestamp: 1,
+        format_no: 0,
+        block_no: 0,
+        data: Cow::Borrowed(&[0; 8]),
+    }))
+    .unwrap();
+    assert_eq!(client.process(&wave).unwrap().len(), 1);
+    assert_eq!(waves.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn client_udp_wave() {
+    let (client, waves) = client();
+    let mut client = client.with_dgram_port(5000);
+
+    let response = negotiate(&mut client, pdu::Version::V5);
+    let [pdu::ClientAudioOutputPdu::AudioFormat(formats)] = response.as_slice() else {
+        panic!("unexpected response: {response:?}");
+    };
+    assert_eq!(formats.dgram_port, 5000);
+
+    // The fragments may arrive out of order.
+    assert!(client
+        .process_datagram(&[0x0E, 0x07, 0x01, 0x04, 0x05, 0x06])
+        .unwrap()
+        .is_empty());
+    assert!(client
+        .process_datagram(&[0x0E, 0x07, 0x00, 0x01, 0x02, 0x03])
+        .unwrap()
+        .is_empty());
+    let confirm = client
+        .process_datagram(&[
+            0x0F, 0x00, 0x0C, 0x00, 0x08, 0x00, 0x34, 0x12, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x07, 0x08,
+        ])
+        .unwrap();
+
+    assert_eq!(
+        decode_client_pdus(confirm),
+        [pdu::ClientAudioOutputPdu::WaveConfirm(pdu::WaveConfirmPdu {
+            timestamp: 0x1234,
+            block_no: 7,
+        })]
+    );
+    assert_eq!(
+        *waves.lock().unwrap(),
+        [(0x1234, vec![0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08])]
+    );
+
+    // A lost fragment drops the wave, without confirmation.
+    assert!(client
+        .process_datagram(&[0x0E, 0x08, 0x01, 0x04, 0x05, 0x06])
+        .unwrap()
+        .is_empty());
+    assert!(client
+        .process_datagram(&[
+            0x0F, 0x00, 0x0C, 0x00, 0x08, 0x00, 0x34, 0x12, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x07, 0x08,
+        ])
+        .unwrap()
+        .is_empty());
+    assert_eq!(waves.lock().unwrap().len(), 1);
+}
+
+fn server(version: pdu::Version) -> (RdpsndServer, SentWaves) {
+    let sent_waves = SentWaves::default();
+    let mut server = RdpsndServer::new(Box::new(TestServerHandler {
+        formats: vec![pcm_format()],
+        sent_waves: Arc::clone(&sent_waves),
+    }));
+    server.start().unwrap();
+
+    let formats = encode_vec(&pdu::ClientAudioOutputPdu::AudioFormat(ClientAudioFormatPdu {
+        version,
+        flags: pdu::AudioFormatFlags::ALIVE,
+        formats: vec![pcm_format()],
+        volume_left: 0xFFFF,
+        volume_right: 0xFFFF,
+        pitch: 0x00010000,
+        dgram_port: 5000,
+    }))
+    .unwrap();
+    assert_eq!(server.process(&formats).unwrap().len(), 1);
+    assert!(server.process(&TRAINING_CONFIRM_BYTES).unwrap().is_empty());
+
+    (server, sent_waves)
+}
+
+/// Timestamp of the channel, as set by the server in a wave PDU
+fn wave_timestamp(payload: &[u8]) -> u16 {
+    match decode(payload).unwrap() {
+        pdu::ServerAudioOutputPdu::WaveInfo(pdu) => pdu.timestamp,
+        pdu::ServerAudioOutputPdu::UdpWaveLast(pdu) => pdu.timestamp,
+        pdu => panic!("unexpected PDU: {pdu:?}"),
+    }
+}
+
+const TRAINING_CONFIRM_BYTES: [u8; 8] = [0x06, 0x00, 0x04, 0x00, 0x87, 0x10, 0x00, 0x00];
+
+#[test]
+fn server_split_wave() {
+    let (mut server, sent_waves) = server(pdu::Version::V5);
+
+    let messages = encode(server.wave(vec![1, 2, 3, 4, 5, 6, 7, 8], 0x1234).unwrap().into());
+    let [timestamp_lo, timestamp_hi] = wave_timestamp(&messages[0]).to_le_bytes();
+    assert_eq!(
+        messages,
+        [
+            vec![
+                0x02,
+                0x00,
+                0x10,
+                0x00,
+                timestamp_lo,
+                timestamp_hi,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x00,
+                0x01,
+                0x02,
+                0x03,
+                0x04,
+            ],
+            vec![0x00, 0x00, 0x00, 0x00, 0x05, 0x06, 0x07, 0x08],
+        ]
+    );
+    assert_eq!(*sent_waves.lock().unwrap(), [(0, 0x1234)]);
+
+    assert!(server.wave(vec![1, 2, 3], 0).is_err());
+}
+
+#[test]
+fn server_udp_wave() {
+    let (mut server, sent_waves) = server(pdu::Version::V5);
+    assert_eq!(server.dgram_port(), Some(5000));
+
+    let data: Vec<u8> = (0..100).collect();
+    let datagrams = server.udp_wave(&data, 0x1234, 20).unwrap();
+    assert!(datagrams.iter().all(|datagram| datagram.len() <= 20));
+    assert_eq!(*sent_waves.lock().unwrap(), [(0, 0x1234)]);
+
+    let (mut client, waves) = client();
+    negotiate(&mut client, pdu::Version::V5);
+
+    let (last, fragments) = datagrams.split_last().unwrap();
+    for datagram in fragments.iter().rev() {
+        assert!(client.process_datagram(datagram).unwrap().is_empty());
+    }
+    assert_eq!(client.process_datagram(last).unwrap().len(), 1);
+    assert_eq!(*waves.lock().unwrap(), [(u32::from(wave_timestamp(last)), data)]);
+}
+
+#[test]
+fn server_udp_wave_long_fragment_numbers() {
+    let (mut server, _) = server(pdu::Version::V5);
+
+    let data: Vec<u8> = (0..3000u16).map(|i| i.to_le_bytes()[0]).collect();
+    let datagrams = server.udp_wave(&data, 0, 20).unwrap();
+    assert!(datagrams.len() > 129);
+    assert!(datagrams.iter().all(|datagram| datagram.len() <= 20));
+
+    let (mut client, waves) = client();
+    negotiate(&mut client, pdu::Version::V5);
+
+    let (last, fragments) = datagrams.split_last().unwrap();
+    for datagram in fragments {
+        assert!(client.process_datagram(datagram).unwrap().is_empty());
+    }
+    assert_eq!(client.process_datagram(last).unwrap().len(), 1);
+    assert_eq!(*waves.lock().unwrap(), [(u32::from(wave_timestamp(last)), data)]);
+}
@@ -3,6 +3,9 @@ use std::borrow::Cow;
 use ironrdp_rdpsnd::pdu;
 use ironrdp_testsuite_core::encode_decode_test;
 
+mod codec;
+mod legacy;
+
 encode_decode_test! {
     server_format: pdu::ServerAudioOutputPdu::AudioFormat(pdu::ServerAudioFormatPdu {
         version: pdu::Version::V5,
@@ -169,6 +172,16 @@ encode_decode_test! {
         // Wave
         0x0, 0x0, 0x0, 0x0, 0x5, 0x6, 0x7, 0x8,
     ];
+    wave_info: pdu::ServerAudioOutputPdu::WaveInfo(pdu::WaveInfoPdu {
+        timestamp: 0xadd7,
+        format_no: 0xf,
+        block_no: 8,
+        data: [0x1, 0x2, 0x3, 0x4],
+        data_len: 8,
+    }),
+    [
+        0x02, 0x00, 0x10, 0x00, 0xd7, 0xad, 0x0f, 0x00, 0x08, 0x00, 0x00, 0x00, 0x1, 0x2, 0x3, 0x4,
+    ];
     wave_confirm: pdu::ClientAudioOutputPdu::WaveConfirm(pdu::WaveConfirmPdu {
         timestamp: 0x5ab7,
         block_no: 8
@@ -186,4 +199,30 @@ encode_decode_test! {
     [
         0x0D, 0x00, 0x14, 0x00, 0x16, 0xA1, 0x03, 0x00, 0x02, 0x00, 0x00, 0x00, 0xC2, 0xB8, 0xAC, 0x0D, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08,
     ];
+    udp_wave: pdu::ServerAudioOutputPdu::UdpWave(pdu::UdpWavePdu {
+        block_no: 3,
+        frag_no: 0x12,
+        data: Cow::Borrowed(&[0x1, 0x2, 0x3]),
+    }),
+    [
+        0x0E, 0x03, 0x12, 0x01, 0x02, 0x03,
+    ];
+    udp_wave_long_frag_no: pdu::ServerAudioOutputPdu::UdpWave(pdu::UdpWavePdu {
+        block_no: 3,
+        frag_no: 0x123,
+        data: Cow::Borrowed(&[0x1, 0x2, 0x3]),
+    }),
+    [
+        0x0E, 0x03, 0x81, 0x23, 0x01, 0x02, 0x03,
+    ];
+    udp_wave_last: pdu::ServerAudioOutputPdu::UdpWaveLast(pdu::UdpWaveLastPdu {
+        total_size: 0x100,
+        timestamp: 0xa116,
+        format_no: 0x3,
+        block_no: 3,
+        data: Cow::Borrowed(&[0x1, 0x2]),
+    }),
+    [
+        0x0F, 0x00, 0x0C, 0x00, 0x00, 0x01, 0x16, 0xA1, 0x03, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x02,
+    ];
 }
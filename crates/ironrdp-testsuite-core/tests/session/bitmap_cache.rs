@@ -0,0 +1,132 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use ironrdp_connector::{BitmapCacheConfig, CellCacheConfig};
+use ironrdp_session::bitmap_cache::{BitmapCache, CachedBitmap, FileBitmapStore, PersistentBitmapStore};
+
+/// Store shared with the test, keeping the bitmaps in the order they were stored
+#[derive(Clone, Default)]
+pub(crate) struct MemoryBitmapStore(pub(crate) Arc<Mutex<Vec<(u8, u64, CachedBitmap)>>>);
+
+impl PersistentBitmapStore for MemoryBitmapStore {
+    fn keys(&self, cache_id: u8) -> io::Result<Vec<u64>> {
+        let bitmaps = self.0.lock().unwrap();
+
+        Ok(bitmaps
+            .iter()
+            .rev()
+            .filter(|(id, _, _)| *id == cache_id)
+            .map(|(_, key, _)| *key)
+            .collect())
+    }
+
+    fn load(&self, cache_id: u8, key: u64) -> io::Result<CachedBitmap> {
+        let bitmaps = self.0.lock().unwrap();
+
+        bitmaps
+            .iter()
+            .find(|(id, k, _)| *id == cache_id && *k == key)
+            .map(|(_, _, bitmap)| bitmap.clone())
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    fn store(&mut self, cache_id: u8, key: u64, bitmap: &CachedBitmap) -> io::Result<()> {
+        self.0.lock().unwrap().push((cache_id, key, bitmap.clone()));
+
+        Ok(())
+    }
+
+    fn remove(&mut self, cache_id: u8, key: u64) -> io::Result<()> {
+        self.0.lock().unwrap().retain(|(id, k, _)| (*id, *k) != (cache_id, key));
+
+        Ok(())
+    }
+}
+
+pub(crate) fn cell_caches(caches: &[(u16, bool)]) -> BitmapCacheConfig {
+    BitmapCacheConfig {
+        cell_caches: caches
+            .iter()
+            .map(|&(entries, persistent)| CellCacheConfig {
+                entries,
+                persistent,
+                persistent_keys: Vec::new(),
+            })
+            .collect(),
+    }
+}
+
+fn bitmap(value: u32) -> CachedBitmap {
+    CachedBitmap {
+        width: 2,
+        height: 1,
+        bpp: 32,
+        pixels: vec![value, value + 1],
+    }
+}
+
+#[test]
+fn persistent_bitmaps_are_loaded_in_the_first_cells() {
+    let mut store = MemoryBitmapStore::default();
+
+    for key in 1..=4 {
+        store.store(0, key, &bitmap(0)).unwrap();
+    }
+    store.store(1, 10, &bitmap(0)).unwrap();
+    store.store(2, 20, &bitmap(0)).unwrap();
+
+    let mut bitmap_cache = BitmapCache::new(&cell_caches(&[(3, true), (10, false), (10, true)]));
+    bitmap_cache.load_persistent(Box::new(store));
+
+    let keys = bitmap_cache
+        .config()
+        .cell_caches
+        .into_iter()
+        .map(|cell_cache| cell_cache.persistent_keys)
+        .collect::<Vec<_>>();
+
+    // The most recent bitmaps are kept when the cache is too small, and the non-persistent caches are
+    // not loaded.
+    assert_eq!(keys, [vec![4, 3, 2], vec![], vec![20]]);
+}
+
+#[test]
+fn persistent_bitmaps_not_fitting_in_the_cache_are_removed() {
+    let mut store = MemoryBitmapStore::default();
+
+    for key in 1..=4 {
+        store.store(0, key, &bitmap(0)).unwrap();
+    }
+
+    let mut bitmap_cache = BitmapCache::new(&cell_caches(&[(3, true)]));
+    bitmap_cache.load_persistent(Box::new(store.clone()));
+
+    assert_eq!(store.keys(0).unwrap(), [4, 3, 2]);
+}
+
+#[test]
+fn file_bitmap_store_round_trip() {
+    let directory = std::env::temp_dir().join(format!("ironrdp-bitmap-cache-{}", std::process::id()));
+
+    let mut store = FileBitmapStore::new(&directory);
+
+    assert!(store.keys(0).unwrap().is_empty());
+
+    store.store(0, 0x0123_4567_89AB_CDEF, &bitmap(0x00FF_0000)).unwrap();
+    store.store(1, 42, &bitmap(7)).unwrap();
+
+    let keys = (0..3).map(|cache_id| store.keys(cache_id).unwrap()).collect::<Vec<_>>();
+    let loaded = store.load(0, 0x0123_4567_89AB_CDEF).unwrap();
+    let missing = store.load(1, 43);
+
+    store.remove(1, 42).unwrap();
+    store.remove(1, 43).unwrap();
+    let removed = store.keys(1).unwrap();
+
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(keys, [vec![0x0123_4567_89AB_CDEF], vec![42], vec![]]);
+    assert_eq!(loaded, bitmap(0x00FF_0000));
+    assert_eq!(missing.unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert!(removed.is_empty());
+}
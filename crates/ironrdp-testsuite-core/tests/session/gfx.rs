@@ -0,0 +1,499 @@
+use ironrdp_core::{decode, encode_vec};
+use ironrdp_dvc::{DvcMessage, DvcProcessor as _};
+use ironrdp_graphics::image_processing::PixelFormat;
+use ironrdp_pdu::geometry::InclusiveRectangle;
+use ironrdp_pdu::rdp::vc::dvc::gfx::{
+    self, CapabilitiesConfirmPdu, CapabilitiesV107Flags, CapabilitySet, ClientPdu, Codec1Type, Codec2Type, Color,
+    CreateSurfacePdu, DeleteEncodingContextPdu, EndFramePdu, EvictCacheEntryPdu, FrameAcknowledgePdu,
+    MapSurfaceToOutputPdu, Point, QueueDepth, ResetGraphicsPdu, ServerPdu, SolidFillPdu, StartFramePdu,
+    SurfaceToCachePdu, SurfaceToSurfacePdu, Timestamp, WireToSurface1Pdu, WireToSurface2Pdu,
+};
+use ironrdp_session::gfx::GfxClient;
+use ironrdp_session::image::DecodedImage;
+
+const CHANNEL_ID: u32 = 3;
+const IMAGE_SIZE: u16 = 64;
+const SURFACE_ID: u16 = 1;
+const SURFACE_SIZE: u16 = 32;
+const SURFACE_ORIGIN: u16 = 8;
+
+/// Wraps the PDUs into a single uncompressed RDP8 segment.
+fn encode_segment(pdus: &[ServerPdu]) -> Vec<u8> {
+    let mut segment = vec![
+        0xE0, // SEGMENTED_SINGLE
+        0x04, // PACKET_COMPR_TYPE_RDP8
+    ];
+
+    for pdu in pdus {
+        segment.extend_from_slice(&encode_vec(pdu).unwrap());
+    }
+
+    segment
+}
+
+fn decode_messages(messages: Vec<DvcMessage>) -> Vec<ClientPdu> {
+    messages
+        .iter()
+        .map(|message| decode(&encode_vec(message.as_ref()).unwrap()).unwrap())
+        .collect()
+}
+
+fn frame(frame_id: u32, pdus: Vec<ServerPdu>) -> Vec<ServerPdu> {
+    let mut frame = vec![ServerPdu::StartFrame(StartFramePdu {
+        timestamp: Timestamp {
+            milliseconds: 0,
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+        },
+        frame_id,
+    })];
+    frame.extend(pdus);
+    frame.push(ServerPdu::EndFrame(EndFramePdu { frame_id }));
+    frame
+}
+
+fn solid_fill(color: Color, right: u16, bottom: u16) -> ServerPdu {
+    ServerPdu::SolidFill(SolidFillPdu {
+        surface_id: SURFACE_ID,
+        fill_pixel: color,
+        // RDPGFX_RECT16 bounds are exclusive.
+        rectangles: vec![InclusiveRectangle {
+            left: 0,
+            top: 0,
+            right,
+            bottom,
+        }],
+    })
+}
+
+/// Confirms the capabilities, and creates a surface mapped to the output.
+fn setup_segment() -> Vec<u8> {
+    encode_segment(&[
+        ServerPdu::CapabilitiesConfirm(CapabilitiesConfirmPdu(CapabilitySet::V10_7 {
+            flags: CapabilitiesV107Flags::AVC_DISABLED,
+        })),
+        ServerPdu::ResetGraphics(ResetGraphicsPdu {
+            width: u32::from(IMAGE_SIZE),
+            height: u32::from(IMAGE_SIZE),
+            monitors: Vec::new(),
+        }),
+        ServerPdu::CreateSurface(CreateSurfacePdu {
+            surface_id: SURFACE_ID,
+            width: SURFACE_SIZE,
+            height: SURFACE_SIZE,
+            pixel_format: gfx::PixelFormat::XRgb,
+        }),
+        ServerPdu::MapSurfaceToOutput(MapSurfaceToOutputPdu {
+            surface_id: SURFACE_ID,
+            output_origin_x: u32::from(SURFACE_ORIGIN),
+            output_origin_y: u32::from(SURFACE_ORIGIN),
+        }),
+    ])
+}
+
+fn pixel(image: &DecodedImage, x: u16, y: u16) -> &[u8] {
+    let offset = (usize::from(y) * usize::from(image.width()) + usize::from(x)) * 4;
+    &image.data()[offset..offset + 4]
+}
+
+#[test]
+fn start_advertises_capabilities() {
+    let mut client = GfxClient::with_capabilities(vec![CapabilitySet::V10_7 {
+        flags: CapabilitiesV107Flags::AVC_DISABLED,
+    }]);
+
+    let messages = decode_messages(client.start(CHANNEL_ID).unwrap());
+
+    assert_eq!(
+        messages,
+        [ClientPdu::CapabilitiesAdvertise(gfx::CapabilitiesAdvertisePdu(vec![
+            CapabilitySet::V10_7 {
+                flags: CapabilitiesV107Flags::AVC_DISABLED,
+            }
+        ]))]
+    );
+}
+
+#[test]
+fn frames_are_acknowledged_and_composited() {
+    let mut client = GfxClient::new();
+    let mut image = DecodedImage::new(PixelFormat::RgbA32, IMAGE_SIZE, IMAGE_SIZE);
+
+    let setup = setup_segment();
+    assert!(client.process(CHANNEL_ID, &setup).unwrap().is_empty());
+    assert_eq!(
+        client.output_size(),
+        Some((u32::from(IMAGE_SIZE), u32::from(IMAGE_SIZE)))
+    );
+
+    let red = Color {
+        b: 0x00,
+        g: 0x00,
+        r: 0xFF,
+        xa: 0x00,
+    };
+    let first_frame = encode_segment(&frame(1, vec![solid_fill(red, SURFACE_SIZE, SURFACE_SIZE)]));
+
+    let messages = decode_messages(client.process(CHANNEL_ID, &first_frame).unwrap());
+    assert_eq!(
+        messages,
+        [ClientPdu::FrameAcknowledge(FrameAcknowledgePdu {
+            queue_depth: QueueDepth::Unavailable,
+            frame_id: 1,
+            total_frames_decoded: 1,
+        })]
+    );
+
+    // The whole surface was damaged when it was mapped to the output.
+    let updated = client.update_image(&mut image).unwrap();
+    assert_eq!(
+        updated,
+        Some(InclusiveRectangle {
+            left: SURFACE_ORIGIN,
+            top: SURFACE_ORIGIN,
+            right: SURFACE_ORIGIN + SURFACE_SIZE - 1,
+            bottom: SURFACE_ORIGIN + SURFACE_SIZE - 1,
+        })
+    );
+    assert_eq!(pixel(&image, SURFACE_ORIGIN - 1, SURFACE_ORIGIN), [0, 0, 0, 0]);
+    assert_eq!(pixel(&image, SURFACE_ORIGIN, SURFACE_ORIGIN), [0xFF, 0x00, 0x00, 0xFF]);
+    assert_eq!(
+        pixel(
+            &image,
+            SURFACE_ORIGIN + SURFACE_SIZE - 1,
+            SURFACE_ORIGIN + SURFACE_SIZE - 1
+        ),
+        [0xFF, 0x00, 0x00, 0xFF]
+    );
+    assert_eq!(
+        pixel(&image, SURFACE_ORIGIN + SURFACE_SIZE, SURFACE_ORIGIN + SURFACE_SIZE),
+        [0, 0, 0, 0]
+    );
+
+    // Nothing changed since the last composition.
+    assert_eq!(client.update_image(&mut image).unwrap(), None);
+
+    let blue = Color {
+        b: 0xFF,
+        g: 0x00,
+        r: 0x00,
+        xa: 0x00,
+    };
+    let second_frame = encode_segment(&frame(2, vec![solid_fill(blue, 4, 4)]));
+
+    let messages = decode_messages(client.process(CHANNEL_ID, &second_frame).unwrap());
+    assert_eq!(
+        messages,
+        [ClientPdu::FrameAcknowledge(FrameAcknowledgePdu {
+            queue_depth: QueueDepth::Unavailable,
+            frame_id: 2,
+            total_frames_decoded: 2,
+        })]
+    );
+
+    let updated = client.update_image(&mut image).unwrap();
+    assert_eq!(
+        updated,
+        Some(InclusiveRectangle {
+            left: SURFACE_ORIGIN,
+            top: SURFACE_ORIGIN,
+            right: SURFACE_ORIGIN + 3,
+            bottom: SURFACE_ORIGIN + 3,
+        })
+    );
+    assert_eq!(
+        pixel(&image, SURFACE_ORIGIN + 3, SURFACE_ORIGIN + 3),
+        [0x00, 0x00, 0xFF, 0xFF]
+    );
+    assert_eq!(
+        pixel(&image, SURFACE_ORIGIN + 4, SURFACE_ORIGIN + 4),
+        [0xFF, 0x00, 0x00, 0xFF]
+    );
+}
+
+#[test]
+fn clear_codec_bitmaps_are_drawn() {
+    let mut client = GfxClient::new();
+    let mut image = DecodedImage::new(PixelFormat::RgbA32, IMAGE_SIZE, IMAGE_SIZE);
+
+    assert!(client.process(CHANNEL_ID, &setup_segment()).unwrap().is_empty());
+    client.update_image(&mut image).unwrap();
+
+    #[rustfmt::skip]
+    let bitmap_data = vec![
+        // No glyph, sequence number 0
+        0x00, 0x00,
+        // Residual, bands and subcodecs byte counts
+        0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // Two green pixels
+        0x00, 0xFF, 0x00, 0x02,
+    ];
+
+    let wire_to_surface = ServerPdu::WireToSurface1(WireToSurface1Pdu {
+        surface_id: SURFACE_ID,
+        codec_id: Codec1Type::ClearCodec,
+        pixel_format: gfx::PixelFormat::XRgb,
+        // RDPGFX_RECT16 bounds are exclusive.
+        destination_rectangle: InclusiveRectangle {
+            left: 2,
+            top: 2,
+            right: 4,
+            bottom: 3,
+        },
+        bitmap_data,
+    });
+    client
+        .process(CHANNEL_ID, &encode_segment(&frame(1, vec![wire_to_surface])))
+        .unwrap();
+
+    let updated = client.update_image(&mut image).unwrap();
+    assert_eq!(
+        updated,
+        Some(InclusiveRectangle {
+            left: SURFACE_ORIGIN + 2,
+            top: SURFACE_ORIGIN + 2,
+            right: SURFACE_ORIGIN + 3,
+            bottom: SURFACE_ORIGIN + 2,
+        })
+    );
+    assert_eq!(
+        pixel(&image, SURFACE_ORIGIN + 3, SURFACE_ORIGIN + 2),
+        [0x00, 0xFF, 0x00, 0xFF]
+    );
+}
+
+#[test]
+fn progressive_bitmaps_are_drawn() {
+    let mut client = GfxClient::new();
+    let mut image = DecodedImage::new(PixelFormat::RgbA32, IMAGE_SIZE, IMAGE_SIZE);
+
+    assert!(client.process(CHANNEL_ID, &setup_segment()).unwrap().is_empty());
+    client.update_image(&mut image).unwrap();
+
+    #[rustfmt::skip]
+    let bitmap_data = vec![
+        // WBT_REGION, 53 bytes
+        0xC4, 0xCC, 0x35, 0x00, 0x00, 0x00,
+        // Tile size, 1 rectangle, 1 quant, no progressive quant, no flags
+        0x40, 0x01, 0x00, 0x01, 0x00, 0x00,
+        // 1 tile, 22 bytes of tile data
+        0x01, 0x00, 0x16, 0x00, 0x00, 0x00,
+        // Rectangle (0, 0), 4x2
+        0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x02, 0x00,
+        // Quant
+        0x66, 0x66, 0x66, 0x66, 0x66,
+        // WBT_TILE_SIMPLE, 22 bytes
+        0xC5, 0xCC, 0x16, 0x00, 0x00, 0x00,
+        // Quant indices, tile (0, 0), no flags
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        // No component data
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    let wire_to_surface = ServerPdu::WireToSurface2(WireToSurface2Pdu {
+        surface_id: SURFACE_ID,
+        codec_id: Codec2Type::RemoteFxProgressive,
+        codec_context_id: 1,
+        pixel_format: gfx::PixelFormat::XRgb,
+        bitmap_data,
+    });
+    let delete_encoding_context = ServerPdu::DeleteEncodingContext(DeleteEncodingContextPdu {
+        surface_id: SURFACE_ID,
+        codec_context_id: 1,
+    });
+    client
+        .process(
+            CHANNEL_ID,
+            &encode_segment(&frame(1, vec![wire_to_surface, delete_encoding_context])),
+        )
+        .unwrap();
+
+    let updated = client.update_image(&mut image).unwrap();
+    assert_eq!(
+        updated,
+        Some(InclusiveRectangle {
+            left: SURFACE_ORIGIN,
+            top: SURFACE_ORIGIN,
+            right: SURFACE_ORIGIN + 3,
+            bottom: SURFACE_ORIGIN + 1,
+        })
+    );
+    // Zero coefficients are mid-gray.
+    assert_eq!(
+        pixel(&image, SURFACE_ORIGIN + 3, SURFACE_ORIGIN + 1),
+        [0x80, 0x80, 0x80, 0xFF]
+    );
+    assert_eq!(pixel(&image, SURFACE_ORIGIN + 4, SURFACE_ORIGIN + 1), [0, 0, 0, 0]);
+}
+
+#[test]
+fn wide_surfaces_are_copied() {
+    const WIDE_SURFACE_ID: u16 = 2;
+    const WIDE_SURFACE_WIDTH: u16 = 20_000;
+
+    let mut client = GfxClient::new();
+    let mut image = DecodedImage::new(PixelFormat::RgbA32, IMAGE_SIZE, IMAGE_SIZE);
+
+    assert!(client.process(CHANNEL_ID, &setup_segment()).unwrap().is_empty());
+    client.update_image(&mut image).unwrap();
+
+    let green = Color {
+        b: 0x00,
+        g: 0xFF,
+        r: 0x00,
+        xa: 0x00,
+    };
+    // The rows of this surface are too long for their stride to fit in a `u16`.
+    let pdus = vec![
+        ServerPdu::CreateSurface(CreateSurfacePdu {
+            surface_id: WIDE_SURFACE_ID,
+            width: WIDE_SURFACE_WIDTH,
+            height: 2,
+            pixel_format: gfx::PixelFormat::XRgb,
+        }),
+        ServerPdu::SolidFill(SolidFillPdu {
+            surface_id: WIDE_SURFACE_ID,
+            fill_pixel: green,
+            rectangles: vec![InclusiveRectangle {
+                left: WIDE_SURFACE_WIDTH - 2,
+                top: 1,
+                right: WIDE_SURFACE_WIDTH,
+                bottom: 2,
+            }],
+        }),
+        ServerPdu::SurfaceToSurface(SurfaceToSurfacePdu {
+            source_surface_id: WIDE_SURFACE_ID,
+            destination_surface_id: SURFACE_ID,
+            source_rectangle: InclusiveRectangle {
+                left: WIDE_SURFACE_WIDTH - 4,
+                top: 0,
+                right: WIDE_SURFACE_WIDTH,
+                bottom: 2,
+            },
+            destination_points: vec![Point { x: 0, y: 0 }],
+        }),
+    ];
+    client.process(CHANNEL_ID, &encode_segment(&frame(1, pdus))).unwrap();
+
+    let updated = client.update_image(&mut image).unwrap();
+    assert_eq!(
+        updated,
+        Some(InclusiveRectangle {
+            left: SURFACE_ORIGIN,
+            top: SURFACE_ORIGIN,
+            right: SURFACE_ORIGIN + 3,
+            bottom: SURFACE_ORIGIN + 1,
+        })
+    );
+    assert_eq!(pixel(&image, SURFACE_ORIGIN + 1, SURFACE_ORIGIN + 1), [0, 0, 0, 0]);
+    assert_eq!(
+        pixel(&image, SURFACE_ORIGIN + 3, SURFACE_ORIGIN + 1),
+        [0x00, 0xFF, 0x00, 0xFF]
+    );
+}
+
+#[test]
+fn oversized_surfaces_are_rejected() {
+    let mut client = GfxClient::new();
+
+    assert!(client.process(CHANNEL_ID, &setup_segment()).unwrap().is_empty());
+
+    let pdu = ServerPdu::CreateSurface(CreateSurfacePdu {
+        surface_id: 2,
+        width: u16::MAX,
+        height: u16::MAX,
+        pixel_format: gfx::PixelFormat::XRgb,
+    });
+    assert!(client.process(CHANNEL_ID, &encode_segment(&[pdu])).is_err());
+}
+
+#[test]
+fn cache_size_is_bounded() {
+    const LARGE_SURFACE_ID: u16 = 2;
+    // The whole surface fills the 16 MiB of the small cache.
+    const LARGE_SURFACE_SIZE: u16 = 2048;
+
+    let surface_to_cache = |cache_slot, size| {
+        encode_segment(&[ServerPdu::SurfaceToCache(SurfaceToCachePdu {
+            surface_id: LARGE_SURFACE_ID,
+            cache_key: u64::from(cache_slot),
+            cache_slot,
+            source_rectangle: InclusiveRectangle {
+                left: 0,
+                top: 0,
+                right: size,
+                bottom: size,
+            },
+        })])
+    };
+
+    let mut client = GfxClient::new();
+    let pdus = [
+        ServerPdu::CapabilitiesConfirm(CapabilitiesConfirmPdu(CapabilitySet::V10_7 {
+            flags: CapabilitiesV107Flags::AVC_DISABLED | CapabilitiesV107Flags::SMALL_CACHE,
+        })),
+        ServerPdu::CreateSurface(CreateSurfacePdu {
+            surface_id: LARGE_SURFACE_ID,
+            width: LARGE_SURFACE_SIZE,
+            height: LARGE_SURFACE_SIZE,
+            pixel_format: gfx::PixelFormat::XRgb,
+        }),
+    ];
+    client.process(CHANNEL_ID, &encode_segment(&pdus)).unwrap();
+
+    client
+        .process(CHANNEL_ID, &surface_to_cache(1, LARGE_SURFACE_SIZE))
+        .unwrap();
+    // Replacing a cache slot releases its previous bitmap.
+    client
+        .process(CHANNEL_ID, &surface_to_cache(1, LARGE_SURFACE_SIZE))
+        .unwrap();
+    assert!(client.process(CHANNEL_ID, &surface_to_cache(2, 1)).is_err());
+
+    let evict = ServerPdu::EvictCacheEntry(EvictCacheEntryPdu { cache_slot: 1 });
+    client.process(CHANNEL_ID, &encode_segment(&[evict])).unwrap();
+    client.process(CHANNEL_ID, &surface_to_cache(2, 1)).unwrap();
+}
+
+#[test]
+fn codec_contexts_are_bounded() {
+    let wire_to_surface = |codec_context_id| {
+        encode_segment(&[ServerPdu::WireToSurface2(WireToSurface2Pdu {
+            surface_id: SURFACE_ID,
+            codec_id: Codec2Type::RemoteFxProgressive,
+            codec_context_id,
+            pixel_format: gfx::PixelFormat::XRgb,
+            bitmap_data: Vec::new(),
+        })])
+    };
+
+    let mut client = GfxClient::new();
+    client.process(CHANNEL_ID, &setup_segment()).unwrap();
+
+    for codec_context_id in 0..16 {
+        client.process(CHANNEL_ID, &wire_to_surface(codec_context_id)).unwrap();
+    }
+    // Existing contexts are still usable.
+    client.process(CHANNEL_ID, &wire_to_surface(0)).unwrap();
+    assert!(client.process(CHANNEL_ID, &wire_to_surface(16)).is_err());
+
+    let delete_encoding_context = ServerPdu::DeleteEncodingContext(DeleteEncodingContextPdu {
+        surface_id: SURFACE_ID,
+        codec_context_id: 0,
+    });
+    client
+        .process(CHANNEL_ID, &encode_segment(&[delete_encoding_context]))
+        .unwrap();
+    client.process(CHANNEL_ID, &wire_to_surface(16)).unwrap();
+
+    // The contexts of a surface are dropped along with it.
+    let create_surface = ServerPdu::CreateSurface(CreateSurfacePdu {
+        surface_id: SURFACE_ID,
+        width: SURFACE_SIZE,
+        height: SURFACE_SIZE,
+        pixel_format: gfx::PixelFormat::XRgb,
+    });
+    client.process(CHANNEL_ID, &encode_segment(&[create_surface])).unwrap();
+    client.process(CHANNEL_ID, &wire_to_surface(17)).unwrap();
+}
@@ -1 +1,4 @@
+mod bitmap_cache;
+mod gfx;
+mod orders;
 mod rfx;
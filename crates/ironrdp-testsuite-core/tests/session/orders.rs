@@ -0,0 +1,233 @@
+use ironrdp_core::{encode_vec, WriteBuf};
+use ironrdp_graphics::bulk;
+use ironrdp_graphics::image_processing::PixelFormat;
+use ironrdp_pdu::fast_path::{EncryptionFlags, FastPathHeader, FastPathUpdatePdu, Fragmentation, UpdateCode};
+use ironrdp_pdu::orders::OrdersUpdate;
+use ironrdp_session::bitmap_cache::{BitmapCache, PersistentBitmapStore as _};
+use ironrdp_session::fast_path::{Processor, ProcessorBuilder, UpdateKind};
+use ironrdp_session::image::DecodedImage;
+
+use super::bitmap_cache::{cell_caches, MemoryBitmapStore};
+
+const IMAGE_SIZE: u16 = 32;
+
+const CYAN: [u8; 4] = [0x00, 0xFF, 0xFF, 0xFF];
+const RED: [u8; 4] = [0xFF, 0x00, 0x00, 0xFF];
+
+#[rustfmt::skip]
+const ORDERS: [u8; 90] = [
+    // OpaqueRect at (2, 2), 4x4, red
+    0x09, 0x0A, 0x7F,
+    0x02, 0x00, 0x02, 0x00, 0x04, 0x00, 0x04, 0x00,
+    0xFF, 0x00, 0x00,
+    // Cache Bitmap - Revision 2, 2x2 at 24 bpp
+    0x03, 0x0D, 0x00, 0x28, 0x00, 0x04,
+    0x02, 0x02, 0x10, 0x00,
+    // bottom row: blue and green
+    0xFF, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00,
+    // top row: white and black
+    0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // MemBlt of the bitmap at (10, 10), with SRCCOPY
+    0x09, 0x0D, 0xFF, 0x01,
+    0x00, 0x00, 0x0A, 0x00, 0x0A, 0x00, 0x02, 0x00, 0x02, 0x00, 0xCC,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // PatBlt at (2, 2), 1x1, with DSTINVERT
+    0x09, 0x01, 0x1F, 0x00,
+    0x02, 0x00, 0x02, 0x00, 0x01, 0x00, 0x01, 0x00, 0x55,
+    // ScrBlt of (2, 2), 2x2, at (20, 20), with SRCCOPY
+    0x09, 0x02, 0x7F,
+    0x14, 0x00, 0x14, 0x00, 0x02, 0x00, 0x02, 0x00, 0xCC,
+    0x02, 0x00, 0x02, 0x00,
+];
+
+fn processor() -> Processor {
+    ProcessorBuilder {
+        io_channel_id: 1003,
+        user_channel_id: 1007,
+        no_server_pointer: true,
+        pointer_software_rendering: false,
+    }
+    .build()
+}
+
+fn fast_path_orders(number_orders: u16, order_data: &[u8]) -> Vec<u8> {
+    let update = encode_vec(&OrdersUpdate {
+        number_orders,
+        order_data,
+    })
+    .unwrap();
+
+    let update_pdu = encode_vec(&FastPathUpdatePdu {
+        fragmentation: Fragmentation::Single,
+        update_code: UpdateCode::Orders,
+        compression_flags: None,
+        compression_type: None,
+        data: &update,
+    })
+    .unwrap();
+
+    let mut frame = encode_vec(&FastPathHeader::new(EncryptionFlags::empty(), update_pdu.len())).unwrap();
+    frame.extend_from_slice(&update_pdu);
+    frame
+}
+
+fn pixel(image: &DecodedImage, x: u16, y: u16) -> &[u8] {
+    let offset = (usize::from(y) * usize::from(image.width()) + usize::from(x)) * 4;
+    &image.data()[offset..offset + 4]
+}
+
+#[test]
+fn drawing_orders_are_rendered() {
+    let mut image = DecodedImage::new(PixelFormat::RgbA32, IMAGE_SIZE, IMAGE_SIZE);
+
+    let updates = processor()
+        .process(
+            &mut image,
+            &fast_path_orders(5, &ORDERS),
+            &mut WriteBuf::new(),
+            &mut bulk::Decompressor::new(),
+        )
+        .unwrap();
+
+    assert!(matches!(
+        updates.as_slice(),
+        [UpdateKind::Region(region)] if (region.left, region.top, region.right, region.bottom) == (2, 2, 21, 21)
+    ));
+
+    // The OpaqueRect, inverted at its origin by the PatBlt
+    assert_eq!(pixel(&image, 2, 2), CYAN);
+    assert_eq!(pixel(&image, 5, 5), RED);
+    assert_eq!(pixel(&image, 6, 6), [0x00; 4]);
+
+    // The cached bitmap, stored from bottom to top
+    assert_eq!(pixel(&image, 10, 10), [0xFF, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(pixel(&image, 11, 10), [0x00, 0x00, 0x00, 0xFF]);
+    assert_eq!(pixel(&image, 10, 11), [0x00, 0x00, 0xFF, 0xFF]);
+    assert_eq!(pixel(&image, 11, 11), [0x00, 0xFF, 0x00, 0xFF]);
+
+    // The copy of the inverted corner
+    assert_eq!(pixel(&image, 20, 20), CYAN);
+    assert_eq!(pixel(&image, 21, 21), RED);
+}
+
+#[test]
+fn primary_order_fields_are_kept_between_updates() {
+    let mut image = DecodedImage::new(PixelFormat::RgbA32, IMAGE_SIZE, IMAGE_SIZE);
+    let mut processor = processor();
+    let mut decompressor = bulk::Decompressor::new();
+
+    processor
+        .process(
+            &mut image,
+            &fast_path_orders(1, &ORDERS[..14]),
+            &mut WriteBuf::new(),
+            &mut decompressor,
+        )
+        .unwrap();
+
+    // OpaqueRect moved by delta coordinates, with the same size and color
+    let updates = processor
+        .process(
+            &mut image,
+            &fast_path_orders(1, &[0x11, 0x03, 0x10, 0x10]),
+            &mut WriteBuf::new(),
+            &mut decompressor,
+        )
+        .unwrap();
+
+    assert!(matches!(
+        updates.as_slice(),
+        [UpdateKind::Region(region)] if (region.left, region.top, region.right, region.bottom) == (18, 18, 21, 21)
+    ));
+    assert_eq!(pixel(&image, 18, 18), RED);
+    assert_eq!(pixel(&image, 21, 21), RED);
+    assert_eq!(pixel(&image, 22, 22), [0x00; 4]);
+}
+
+#[test]
+fn persistent_bitmaps_are_drawn_in_the_next_session() {
+    #[rustfmt::skip]
+    let cache_bitmap = [
+        // Cache Bitmap - Revision 2, 2x2 at 24 bpp, with a persistent key
+        0x03, 0x15, 0x00, 0x28, 0x01, 0x04,
+        0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+        0x02, 0x02, 0x10, 0x00,
+        0xFF, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00,
+        0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    let store = MemoryBitmapStore::default();
+    let config = cell_caches(&[(16, true)]);
+
+    let mut bitmap_cache = BitmapCache::new(&config);
+    bitmap_cache.load_persistent(Box::new(store.clone()));
+
+    let mut first_session = processor();
+    first_session.set_bitmap_cache(bitmap_cache);
+    first_session
+        .process(
+            &mut DecodedImage::new(PixelFormat::RgbA32, IMAGE_SIZE, IMAGE_SIZE),
+            &fast_path_orders(1, &cache_bitmap),
+            &mut WriteBuf::new(),
+            &mut bulk::Decompressor::new(),
+        )
+        .unwrap();
+
+    // The next session only receives the MemBlt order.
+    let mut bitmap_cache = BitmapCache::new(&config);
+    bitmap_cache.load_persistent(Box::new(store));
+
+    assert_eq!(
+        bitmap_cache.config().cell_caches[0].persistent_keys,
+        [0x0000_0002_0000_0001]
+    );
+
+    let mut image = DecodedImage::new(PixelFormat::RgbA32, IMAGE_SIZE, IMAGE_SIZE);
+    let mut next_session = processor();
+    next_session.set_bitmap_cache(bitmap_cache);
+    next_session
+        .process(
+            &mut image,
+            &fast_path_orders(1, &ORDERS[40..61]),
+            &mut WriteBuf::new(),
+            &mut bulk::Decompressor::new(),
+        )
+        .unwrap();
+
+    assert_eq!(pixel(&image, 10, 10), [0xFF, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(pixel(&image, 11, 11), [0x00, 0xFF, 0x00, 0xFF]);
+}
+
+#[test]
+fn overwritten_persistent_bitmaps_are_removed_from_the_store() {
+    #[rustfmt::skip]
+    let cache_bitmap = |key1: u8| [
+        // Cache Bitmap - Revision 2, 2x2 at 24 bpp, with a persistent key
+        0x03, 0x15, 0x00, 0x28, 0x01, 0x04,
+        key1, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+        0x02, 0x02, 0x10, 0x00,
+        0xFF, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00,
+        0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    let store = MemoryBitmapStore::default();
+
+    let mut bitmap_cache = BitmapCache::new(&cell_caches(&[(16, true)]));
+    bitmap_cache.load_persistent(Box::new(store.clone()));
+
+    let mut session = processor();
+    session.set_bitmap_cache(bitmap_cache);
+
+    for key1 in [1, 3] {
+        session
+            .process(
+                &mut DecodedImage::new(PixelFormat::RgbA32, IMAGE_SIZE, IMAGE_SIZE),
+                &fast_path_orders(1, &cache_bitmap(key1)),
+                &mut WriteBuf::new(),
+                &mut bulk::Decompressor::new(),
+            )
+            .unwrap();
+    }
+
+    assert_eq!(store.keys(0).unwrap(), [0x0000_0002_0000_0003]);
+}
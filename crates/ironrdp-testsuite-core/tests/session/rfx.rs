@@ -32,6 +32,70 @@ fn decode_decodes_valid_sequence_of_messages() {
     assert_eq!(expected, image.data());
 }
 
+#[test]
+fn region_is_relative_to_the_destination() {
+    let width = u16::try_from(IMAGE_WIDTH).unwrap();
+    let height = u16::try_from(IMAGE_HEIGHT).unwrap();
+    let destination = InclusiveRectangle {
+        left: width,
+        top: height,
+        right: 2 * width - 1,
+        bottom: 2 * height - 1,
+    };
+    let data = &mut ReadCursor::new(ENCODED_MESSAGES.as_ref());
+
+    let mut image = DecodedImage::new(PixelFormat::BgrX32, 2 * width, 2 * height);
+
+    let mut handler = DecodingContext::default();
+
+    let (_, updated) = handler.decode(&mut image, &destination, data).unwrap();
+    assert_eq!(updated, destination);
+
+    // The tile is entirely drawn in the bottom right quarter of the image.
+    let stride = 2 * IMAGE_WIDTH * FORMAT_SIZE;
+    for (y, row) in image.data().chunks_exact(stride).enumerate() {
+        let (left, right) = row.split_at(stride / 2);
+        assert!(left.iter().all(|&byte| byte == 0));
+        if y < IMAGE_HEIGHT {
+            assert!(right.iter().all(|&byte| byte == 0));
+        } else {
+            let start = (y - IMAGE_HEIGHT) * IMAGE_WIDTH * FORMAT_SIZE;
+            assert_eq!(right, &DECODED_IMAGE[start..][..IMAGE_WIDTH * FORMAT_SIZE]);
+        }
+    }
+}
+
+#[test]
+fn empty_region_rectangles_are_skipped() {
+    let destination = InclusiveRectangle {
+        left: 0,
+        top: 0,
+        right: u16::try_from(IMAGE_WIDTH).unwrap() - 1,
+        bottom: u16::try_from(IMAGE_HEIGHT).unwrap() - 1,
+    };
+
+    // The rectangle of the region (4.2.3) is 0x0 instead of 64x64.
+    let mut messages = ENCODED_MESSAGES;
+    let region = messages
+        .windows(4)
+        .position(|block| block == [0xc6, 0xcc, 0x17, 0x00])
+        .unwrap();
+    messages[region + 15..region + 19].fill(0);
+    let data = &mut ReadCursor::new(messages.as_ref());
+
+    let mut image = DecodedImage::new(
+        PixelFormat::BgrX32,
+        IMAGE_WIDTH.try_into().unwrap(),
+        IMAGE_HEIGHT.try_into().unwrap(),
+    );
+
+    let mut handler = DecodingContext::default();
+
+    handler.decode(&mut image, &destination, data).unwrap();
+
+    assert!(image.data().iter().all(|&byte| byte == 0));
+}
+
 const ENCODED_MESSAGES: [u8; 2970] = [
     /* HEADERS as in 4.2.2 */
     0xc0, 0xcc, 0x0c, 0x00, 0x00, 0x00, 0xca, 0xac, 0xcc, 0xca, 0x00, 0x01, 0xc3, 0xcc, 0x0d, 0x00, 0x00, 0x00, 0x01,
@@ -0,0 +1,225 @@
+use std::borrow::Cow;
+
+use ironrdp_connector::StandardSecurity;
+use ironrdp_core::{decode, encode_vec};
+use ironrdp_pdu::gcc::EncryptionMethod;
+use ironrdp_pdu::input::fast_path::{FastPathInput, FastPathInputEvent, SynchronizeFlags};
+use ironrdp_pdu::mcs::{SendDataIndication, SendDataRequest};
+use ironrdp_pdu::x224::X224;
+use rstest::rstest;
+
+const CLIENT_RANDOM: [u8; 32] = [0x11; 32];
+const SERVER_RANDOM: [u8; 32] = [0x22; 32];
+
+fn security(method: EncryptionMethod) -> StandardSecurity {
+    StandardSecurity::new(method, CLIENT_RANDOM, &SERVER_RANDOM).unwrap()
+}
+
+fn send_data_indication(user_data: Vec<u8>) -> Vec<u8> {
+    encode_vec(&X224(SendDataIndication {
+        initiator_id: 1002,
+        channel_id: 1003,
+        user_data: Cow::Owned(user_data),
+    }))
+    .unwrap()
+}
+
+fn send_data_request(user_data: &[u8]) -> Vec<u8> {
+    encode_vec(&X224(SendDataRequest {
+        initiator_id: 1007,
+        channel_id: 1003,
+        user_data: Cow::Borrowed(user_data),
+    }))
+    .unwrap()
+}
+
+fn user_data_of(frame: &[u8]) -> Vec<u8> {
+    decode::<X224<SendDataRequest<'_>>>(frame)
+        .unwrap()
+        .0
+        .user_data
+        .into_owned()
+}
+
+#[test]
+fn security_header_of_unencrypted_data_pdu_is_removed() {
+    let mut security = security(EncryptionMethod::BIT_128);
+
+    let frame = send_data_indication([&[0x00, 0x00, 0x00, 0x00][..], b"data"].concat());
+
+    assert_eq!(
+        security.decrypt_frame(&frame).unwrap(),
+        send_data_indication(b"data".to_vec())
+    );
+}
+
+#[test]
+fn security_header_of_license_pdu_is_kept() {
+    let mut security = security(EncryptionMethod::BIT_128);
+
+    let frame = send_data_indication([&[0x80, 0x00, 0x00, 0x00][..], b"data"].concat());
+
+    assert_eq!(security.decrypt_frame(&frame).unwrap(), frame);
+}
+
+#[rstest]
+#[case(EncryptionMethod::BIT_40)]
+#[case(EncryptionMethod::BIT_56)]
+#[case(EncryptionMethod::BIT_128)]
+fn slow_path_pdus_are_signed_and_encrypted(#[case] method: EncryptionMethod) {
+    let mut security = security(method);
+
+    let request = send_data_request(b"some data");
+    let frames = security
+        .encrypt_frames(&[request.as_slice(), &request].concat())
+        .unwrap();
+
+    let (first, second) = frames.split_at(frames.len() / 2);
+    let first = user_data_of(first);
+    let second = user_data_of(second);
+
+    // ENCRYPT, followed by the 8-byte signature and the data
+    assert_eq!(first[..4], [0x08, 0x00, 0x00, 0x00]);
+    assert_eq!(first.len(), 4 + 8 + b"some data".len());
+    assert_ne!(first[12..], b"some data"[..]);
+
+    // The RC4 key stream goes on from one PDU to the next
+    assert_ne!(first, second);
+}
+
+#[test]
+fn fips_pdus_are_padded() {
+    let mut security = security(EncryptionMethod::FIPS);
+
+    let frame = security.encrypt_frames(&send_data_request(b"some data")).unwrap();
+    let user_data = user_data_of(&frame);
+
+    assert_eq!(user_data[..4], [0x08, 0x00, 0x00, 0x00]);
+    // length, version and padding length of the FIPS information
+    assert_eq!(user_data[4..8], [0x10, 0x00, 0x01, 0x07]);
+    assert_eq!(user_data.len(), 8 + 8 + 16);
+}
+
+#[test]
+fn fast_path_input_is_encrypted() {
+    let mut security = security(EncryptionMethod::BIT_128);
+
+    let input = encode_vec(&FastPathInput(vec![FastPathInputEvent::SyncEvent(
+        SynchronizeFlags::NUM_LOCK,
+    )]))
+    .unwrap();
+    let frame = security.encrypt_frames(&input).unwrap();
+
+    // FASTPATH_INPUT_ENCRYPTED
+    assert_eq!(frame[0], input[0] | 0x80);
+    assert_eq!(usize::from(frame[1]), frame.len());
+    assert_eq!(frame.len(), input.len() + 8);
+}
+
+#[test]
+fn forged_pdu_is_rejected() {
+    let mut security = security(EncryptionMethod::BIT_128);
+
+    let frame = send_data_indication([&[0x08, 0x00, 0x00, 0x00][..], &[0x00; 8], b"data"].concat());
+
+    assert!(security.decrypt_frame(&frame).is_err());
+}
+
+// The expected bytes below were computed with an independent implementation of the non-FIPS key generation
+// (5.3.5.1), MAC generation (5.3.6.1) and key updates (5.3.7) of MS-RDPBCGR, for the client and server
+// randoms above.
+
+#[rstest]
+#[case(EncryptionMethod::BIT_40, [0x5D, 0xFB, 0x4F, 0x21, 0x9A, 0x21, 0xB7, 0x70, 0xD4, 0xE4, 0x4C, 0xA2, 0xF9, 0x82, 0xF1, 0x17, 0xE4])]
+#[case(EncryptionMethod::BIT_56, [0x16, 0xF5, 0x1D, 0x6D, 0x2C, 0xAE, 0x77, 0xA3, 0x74, 0x3C, 0x97, 0xDE, 0x0F, 0xF7, 0xB9, 0x0A, 0x1C])]
+#[case(EncryptionMethod::BIT_128, [0x17, 0x72, 0x65, 0x0C, 0xC9, 0x6B, 0x51, 0x65, 0xCD, 0xF3, 0x30, 0x81, 0x4A, 0x73, 0xEF, 0xFC, 0x76])]
+fn rc4_keys_are_derived_from_the_randoms(#[case] method: EncryptionMethod, #[case] expected: [u8; 17]) {
+    let mut security = security(method);
+
+    let frame = security.encrypt_frames(&send_data_request(b"some data")).unwrap();
+
+    assert_eq!(
+        user_data_of(&frame),
+        [&[0x08, 0x00, 0x00, 0x00][..], &expected].concat()
+    );
+}
+
+#[test]
+fn salted_mac_is_used_once_the_server_uses_it() {
+    let mut security = security(EncryptionMethod::BIT_128);
+    assert!(!security.salted_mac());
+
+    // ENCRYPT | SECURE_CHECKSUM, signed with the salted MAC of the first packet
+    let frame = send_data_indication(vec![
+        0x08, 0x08, 0x00, 0x00, 0x04, 0xC9, 0xEA, 0xB7, 0x9B, 0xF0, 0xD5, 0xA8, 0x72, 0xB4, 0xEB, 0x2A, 0xC9, 0x91,
+        0xFB, 0x6F, 0x06, 0xED, 0x8E,
+    ]);
+    assert_eq!(
+        security.decrypt_frame(&frame).unwrap(),
+        send_data_indication(b"from server".to_vec())
+    );
+    assert!(security.salted_mac());
+
+    let frame = security.encrypt_frames(&send_data_request(b"some data")).unwrap();
+    assert_eq!(
+        user_data_of(&frame),
+        [
+            0x08, 0x08, 0x00, 0x00, 0x14, 0xD9, 0x9A, 0x36, 0xD4, 0xA0, 0x87, 0x0B, 0xCD, 0xF3, 0x30, 0x81, 0x4A, 0x73,
+            0xEF, 0xFC, 0x76,
+        ]
+    );
+}
+
+#[test]
+fn server_pdus_are_decrypted_in_sequence() {
+    let mut security = security(EncryptionMethod::BIT_128);
+
+    let packets: [(&[u8], &[u8]); 3] = [
+        (
+            b"first",
+            &[
+                0x23, 0x84, 0xDC, 0x07, 0x70, 0x5E, 0x48, 0xC2, 0x72, 0xAF, 0xF6, 0x34, 0x9D,
+            ],
+        ),
+        (
+            b"second",
+            &[
+                0xC6, 0xD5, 0x47, 0x25, 0x2A, 0x7F, 0xCB, 0x69, 0x91, 0xFB, 0x7E, 0x1F, 0xE6, 0x98,
+            ],
+        ),
+        (
+            b"third",
+            &[
+                0x5A, 0x4F, 0x5F, 0xC0, 0xF3, 0x51, 0xDD, 0xE7, 0xB3, 0xEB, 0xCA, 0xB3, 0xE0,
+            ],
+        ),
+    ];
+
+    for (data, encrypted) in packets {
+        let frame = send_data_indication([&[0x08, 0x00, 0x00, 0x00][..], encrypted].concat());
+
+        assert_eq!(
+            security.decrypt_frame(&frame).unwrap(),
+            send_data_indication(data.to_vec())
+        );
+    }
+    assert!(!security.salted_mac());
+}
+
+#[rstest]
+#[case(EncryptionMethod::BIT_40, [0x5D, 0xFB, 0x4F, 0x21, 0x9A, 0x21, 0xB7, 0x70, 0xFA, 0xFA, 0x50, 0xE1, 0x23, 0x59, 0x53, 0x89, 0x71])]
+#[case(EncryptionMethod::BIT_128, [0x17, 0x72, 0x65, 0x0C, 0xC9, 0x6B, 0x51, 0x65, 0xD1, 0x77, 0xA8, 0x91, 0xC6, 0xC4, 0xA5, 0xC8, 0x9A])]
+fn rc4_keys_are_updated_after_4096_packets(#[case] method: EncryptionMethod, #[case] expected: [u8; 17]) {
+    let mut security = security(method);
+    let request = send_data_request(b"some data");
+
+    for _ in 0..4096 {
+        security.encrypt_frames(&request).unwrap();
+    }
+    let frame = security.encrypt_frames(&request).unwrap();
+
+    assert_eq!(
+        user_data_of(&frame),
+        [&[0x08, 0x00, 0x00, 0x00][..], &expected].concat()
+    );
+}
@@ -0,0 +1,252 @@
+use ironrdp::rdpsnd::client::{Rdpsnd, RdpsndClientHandler};
+use ironrdp::rdpsnd::codec::{self, AudioDecoder};
+use ironrdp::rdpsnd::pdu::{AudioFormatFlags, ClientAudioFormatPdu, PitchPdu, Version, VolumePdu};
+use ironrdp::rdpsnd::server::RdpsndServerMessage;
+use ironrdp::server::{
+    AudioFormat, AudioOutput, PixelFormat, RdpServer, ServerEvent, ServerEventSender, SoundServerFactory,
+};
+use ironrdp::session::image::DecodedImage;
+use ironrdp::session::ActiveStageOutput;
+use ironrdp_async::FramedWrite;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::debug;
+
+use crate::common::{
+    client_server_with, default_client_config, tls_acceptor, TestInputHandler, Transport, DESKTOP_HEIGHT, DESKTOP_WIDTH,
+};
+
+#[test]
+fn test_audio_output_pipeline() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut output = AudioOutput::new();
+    output.set_sender(tx);
+
+    let mut handler = output.build_backend();
+    let client_format = ClientAudioFormatPdu {
+        version: Version::V8,
+        flags: AudioFormatFlags::ALIVE,
+        formats: vec![
+            codec::pcm_format(2, 22050),
+            codec::ima_adpcm_format(2, 44100),
+            codec::alaw_format(2, 8000),
+        ],
+        volume_left: 0xFFFF,
+        volume_right: 0xFFFF,
+        pitch: 0x00010000,
+        dgram_port: 0,
+    };
+
+    // Nobody is listening yet.
+    output.write_f32(&[0.0; 4800], 1, 48000);
+    assert!(rx.try_recv().is_err());
+
+    // ADPCM is preferred to PCM.
+    assert_eq!(handler.start(&client_format), Some(1));
+    let format = output.format().expect("negotiated format");
+    assert_eq!(format, codec::ima_adpcm_format(2, 44100));
+
+    // One second of a mono 440 Hz sine wave, resampled from 48 kHz to 44.1 kHz.
+    let samples: Vec<f32> = (0..48000)
+        .map(|i| {
+            #[allow(clippy::cast_possible_truncation)] // small amplitude
+            let sample = (f64::sin(2.0 * core::f64::consts::PI * 440.0 * f64::from(i) / 48000.0) * 0.5) as f32;
+            sample
+        })
+        .collect();
+    for chunk in samples.chunks(480) {
+        output.write_f32(chunk, 1, 48000);
+    }
+
+    let mut audio_decoder = AudioDecoder::new(&format).unwrap();
+    let mut decoded = Vec::new();
+    let mut timestamps = Vec::new();
+    while let Ok(event) = rx.try_recv() {
+        let ServerEvent::Rdpsnd(RdpsndServerMessage::Wave(data, ts)) = event else {
+            panic!("unexpected event");
+        };
+        assert_eq!(data.len(), usize::from(format.n_block_align));
+        audio_decoder.decode(&data, &mut decoded).unwrap();
+        timestamps.push(ts);
+    }
+    assert!(timestamps.windows(2).all(|ts| ts[0] < ts[1]));
+    // The last block is incomplete.
+    assert_eq!(decoded.len(), 2 * 2041 * 21);
+
+    let expected: Vec<f64> = (0..decoded.len() / 2)
+        .map(|i| {
+            #[allow(clippy::cast_precision_loss)] // small index
+            let t = i as f64 / 44100.0;
+            f64::sin(2.0 * core::f64::consts::PI * 440.0 * t) * 0.5 * 32768.0
+        })
+        .collect();
+    let (signal, noise) =
+        decoded
+            .chunks_exact(2)
+            .zip(&expected)
+            .fold((0.0, 0.0), |(signal, noise), (frame, expected)| {
+                assert_eq!(frame[0], frame[1]);
+                let error = f64::from(frame[0]) - expected;
+                (signal + expected * expected, noise + error * error)
+            });
+    assert!(10.0 * (signal / noise).log10() > 20.0);
+
+    // The waves are confirmed by block number, the ones queued before a sent wave were dropped.
+    handler.wave_sent(0, timestamps[1]);
+    handler.wave_confirm(1, core::time::Duration::from_millis(10));
+    assert_eq!(output.latency(), None);
+
+    // The audio is dropped while the client is too far behind.
+    handler.wave_confirm(0, core::time::Duration::from_secs(1));
+    assert_eq!(output.latency(), Some(core::time::Duration::from_secs(1)));
+    output.write_f32(&samples, 1, 48000);
+    assert!(rx.try_recv().is_err());
+
+    handler.stop();
+    assert!(output.format().is_none());
+}
+
+#[test]
+fn test_audio_output_connections() {
+    let output = AudioOutput::new();
+    let client_format = ClientAudioFormatPdu {
+        version: Version::V8,
+        flags: AudioFormatFlags::ALIVE,
+        formats: vec![codec::pcm_format(2, 44100)],
+        volume_left: 0xFFFF,
+        volume_right: 0xFFFF,
+        pitch: 0x00010000,
+        dgram_port: 0,
+    };
+
+    // Each connection has its own factory, cloned from the audio output.
+    let connection = || {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let mut factory = output.clone();
+        factory.set_sender(tx);
+        (factory.build_backend(), rx)
+    };
+    let (mut first, mut first_rx) = connection();
+    let (mut second, mut second_rx) = connection();
+
+    assert_eq!(first.start(&client_format), Some(0));
+    output.write_i16(&[0; 2 * 882], 2, 44100);
+    assert!(first_rx.try_recv().is_ok());
+    assert!(second_rx.try_recv().is_err());
+
+    // A late client doesn't hold back the others.
+    assert_eq!(second.start(&client_format), Some(0));
+    output.write_i16(&[0; 2 * 2 * 882], 2, 44100);
+    second.wave_sent(0, 0);
+    second.wave_sent(1, 20);
+    second.wave_confirm(0, core::time::Duration::from_secs(1));
+    output.write_i16(&[0; 2 * 882], 2, 44100);
+    let count = |rx: &mut UnboundedReceiver<ServerEvent>| core::iter::from_fn(|| rx.try_recv().ok()).count();
+    assert_eq!((count(&mut first_rx), count(&mut second_rx)), (3, 2));
+
+    drop(first);
+    assert_eq!(output.format(), Some(codec::pcm_format(2, 44100)));
+    second.stop();
+    assert!(output.format().is_none());
+}
+
+#[tokio::test]
+async fn test_audio_output() {
+    let output = AudioOutput::new();
+    let writer_output = output.clone();
+
+    // 60 ms of stereo audio, in the format of the client: sent without conversion.
+    let samples: Vec<i16> = (0..2880)
+        .flat_map(|i| {
+            #[allow(clippy::cast_possible_truncation)] // the amplitude fits in i16
+            let sample = (f64::sin(2.0 * core::f64::consts::PI * 440.0 * f64::from(i) / 48000.0) * 10000.0) as i16;
+            [sample, -sample]
+        })
+        .collect();
+    let expected: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+    let (wave_tx, mut wave_rx) = mpsc::unbounded_channel();
+
+    client_server_with(
+        Transport::InMemory,
+        default_client_config(),
+        |display| {
+            RdpServer::builder()
+                .with_addr(([127, 0, 0, 1], 0))
+                .with_tls(tls_acceptor())
+                .with_input_handler(TestInputHandler)
+                .with_display_handler(display)
+                .with_sound_factory(Some(Box::new(output)))
+                .build()
+        },
+        |connector| {
+            connector.attach_static_channel(Rdpsnd::new(Box::new(TestRdpsndHandler {
+                formats: vec![codec::pcm_format(2, 48000)],
+                wave_tx,
+            })));
+        },
+        |mut stage, mut framed, _server| async move {
+            let writer = async move {
+                while writer_output.format().is_none() {
+                    tokio::time::sleep(core::time::Duration::from_millis(10)).await;
+                }
+                assert_eq!(writer_output.format(), Some(codec::pcm_format(2, 48000)));
+
+                for chunk in samples.chunks(960) {
+                    writer_output.write_i16(chunk, 2, 48000);
+                }
+            };
+
+            let reader = async {
+                let mut image = DecodedImage::new(PixelFormat::RgbA32, DESKTOP_WIDTH, DESKTOP_HEIGHT);
+
+                // Each wave is 20 ms of audio.
+                let mut received = Vec::new();
+                while received.len() < expected.len() {
+                    let (action, payload) = framed.read_pdu().await.expect("valid PDU");
+                    for out in stage.process(&mut image, action, &payload).expect("stage process") {
+                        match out {
+                            ActiveStageOutput::ResponseFrame(frame) => {
+                                framed.write_all(&frame).await.expect("write frame")
+                            }
+                            out => debug!(?out),
+                        }
+                    }
+
+                    while let Ok(data) = wave_rx.try_recv() {
+                        assert_eq!(data.len(), 3840);
+                        received.extend(data);
+                    }
+                }
+                assert_eq!(received, expected);
+            };
+
+            tokio::join!(writer, reader);
+
+            (stage, framed)
+        },
+    )
+    .await
+    .expect("connect");
+}
+
+/// Forwards the waves received by the client
+#[derive(Debug)]
+struct TestRdpsndHandler {
+    formats: Vec<AudioFormat>,
+    wave_tx: UnboundedSender<Vec<u8>>,
+}
+
+impl RdpsndClientHandler for TestRdpsndHandler {
+    fn get_formats(&self) -> &[AudioFormat] {
+        &self.formats
+    }
+
+    fn wave(&mut self, _format: &AudioFormat, _ts: u32, data: std::borrow::Cow<'_, [u8]>) {
+        let _ = self.wave_tx.send(data.into_owned());
+    }
+
+    fn set_volume(&mut self, _volume: VolumePdu) {}
+
+    fn set_pitch(&mut self, _pitch: PitchPdu) {}
+
+    fn close(&mut self) {}
+}
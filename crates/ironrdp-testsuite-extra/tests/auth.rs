@@ -0,0 +1,206 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ironrdp::connector;
+use ironrdp::server::{
+    AuthBackend, AuthDecision, AuthRequest, AuthSecret, ClientLogon, DelegatedCredentials, DesktopSize, RdpServer,
+    RdpServerDisplay, RdpServerDisplayUpdates,
+};
+
+use crate::common::{
+    client_server_with, default_client_config, tls_identity, TestDisplay, TestInputHandler, Transport,
+};
+
+#[tokio::test]
+async fn test_auth_backend() {
+    for (hybrid, username, password, allowed) in [
+        (true, "alice", "Password", true),
+        (true, "alice", "Passw0rd", false),
+        (true, "bob", "Password", false),
+        (true, "carol", "Password", true),
+        (true, "carol", "Passw0rd", false),
+        (false, "alice", "Password", true),
+        (false, "alice", "Passw0rd", false),
+    ] {
+        let mut client_config = default_client_config();
+        client_config.credentials = connector::Credentials::UsernamePassword {
+            username: username.into(),
+            password: password.into(),
+        };
+
+        let result = client_server_with(
+            Transport::InMemory,
+            client_config,
+            |display| auth_server(hybrid, display),
+            |_| {},
+            |stage, framed, _server| async { (stage, framed) },
+        )
+        .await;
+        assert_eq!(
+            result.is_ok(),
+            allowed,
+            "hybrid: {hybrid}, username: {username}, password: {password}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_client_logon() {
+    for hybrid in [true, false] {
+        let logon = Arc::new(std::sync::Mutex::new(None));
+
+        let mut client_config = default_client_config();
+        client_config.credentials = connector::Credentials::UsernamePassword {
+            username: "alice".into(),
+            password: "Password".into(),
+        };
+        client_config.domain = Some("EXAMPLE".into());
+
+        let display_logon = Arc::clone(&logon);
+        client_server_with(
+            Transport::InMemory,
+            client_config,
+            move |inner| {
+                auth_server(
+                    hybrid,
+                    LogonDisplay {
+                        inner,
+                        logon: display_logon,
+                    },
+                )
+            },
+            |_| {},
+            |stage, framed, _server| async { (stage, framed) },
+        )
+        .await
+        .expect("connect");
+
+        let logon = logon.lock().unwrap().take().expect("logon");
+        let client_info = &logon.client_info;
+        assert_eq!(client_info.credentials.username, "alice");
+        assert_eq!(client_info.credentials.domain.as_deref(), Some("EXAMPLE"));
+        assert_eq!(client_info.extra_info.dir, "C:\\Windows\\System32\\mstscax.dll");
+
+        if hybrid {
+            let Some(DelegatedCredentials::Password {
+                username,
+                domain,
+                password,
+            }) = logon.delegated_credentials
+            else {
+                panic!("expected delegated password credentials");
+            };
+            assert_eq!(username, "alice");
+            assert_eq!(domain.as_deref(), Some("EXAMPLE"));
+            assert_eq!(password, "Password");
+        } else {
+            assert!(logon.delegated_credentials.is_none());
+        }
+    }
+}
+
+#[test]
+fn test_delegated_credentials() {
+    // TSCredentials with TSPasswordCreds
+    let password = [
+        0x30, 0x4D, 0xA0, 0x03, 0x02, 0x01, 0x01, 0xA1, 0x46, 0x04, 0x44, 0x30, 0x42, 0xA0, 0x18, 0x04, 0x16, 0x65,
+        0x00, 0x78, 0x00, 0x61, 0x00, 0x6D, 0x00, 0x70, 0x00, 0x6C, 0x00, 0x65, 0x00, 0x2E, 0x00, 0x63, 0x00, 0x6F,
+        0x00, 0x6D, 0x00, 0xA1, 0x0A, 0x04, 0x08, 0x70, 0x00, 0x77, 0x00, 0x31, 0x00, 0x33, 0x00, 0xA2, 0x1A, 0x04,
+        0x18, 0x71, 0x00, 0x71, 0x00, 0x71, 0x00, 0x51, 0x00, 0x51, 0x00, 0x51, 0x00, 0x31, 0x00, 0x31, 0x00, 0x31,
+        0x00, 0x21, 0x00, 0x21, 0x00, 0x21, 0x00,
+    ];
+    let DelegatedCredentials::Password {
+        username,
+        domain,
+        password,
+    } = DelegatedCredentials::decode(&password).expect("password credentials")
+    else {
+        panic!("expected password credentials");
+    };
+    assert_eq!(username, "pw13");
+    assert_eq!(domain.as_deref(), Some("example.com"));
+    assert_eq!(password, "qqqQQQ111!!!");
+
+    // TSCredentials with TSSmartCardCreds
+    let smart_card = [
+        0x30, 0x82, 0x01, 0x33, 0xA0, 0x03, 0x02, 0x01, 0x02, 0xA1, 0x82, 0x01, 0x2A, 0x04, 0x82, 0x01, 0x26, 0x30,
+        0x82, 0x01, 0x22, 0xA0, 0x1A, 0x04, 0x18, 0x32, 0x00, 0x31, 0x00, 0x34, 0x00, 0x36, 0x00, 0x35, 0x00, 0x33,
+        0x00, 0x32, 0x00, 0x31, 0x00, 0x34, 0x00, 0x36, 0x00, 0x35, 0x00, 0x33, 0x00, 0xA1, 0x82, 0x01, 0x02, 0x30,
+        0x81, 0xFF, 0xA0, 0x03, 0x02, 0x01, 0x01, 0xA1, 0x10, 0x04, 0x0E, 0x56, 0x00, 0x53, 0x00, 0x43, 0x00, 0x74,
+        0x00, 0x65, 0x00, 0x73, 0x00, 0x74, 0x00, 0xA2, 0x3E, 0x04, 0x3C, 0x4D, 0x00, 0x69, 0x00, 0x63, 0x00, 0x72,
+        0x00, 0x6F, 0x00, 0x73, 0x00, 0x6F, 0x00, 0x66, 0x00, 0x74, 0x00, 0x20, 0x00, 0x56, 0x00, 0x69, 0x00, 0x72,
+        0x00, 0x74, 0x00, 0x75, 0x00, 0x61, 0x00, 0x6C, 0x00, 0x20, 0x00, 0x53, 0x00, 0x6D, 0x00, 0x61, 0x00, 0x72,
+        0x00, 0x74, 0x00, 0x20, 0x00, 0x43, 0x00, 0x61, 0x00, 0x72, 0x00, 0x64, 0x00, 0x20, 0x00, 0x30, 0x00, 0xA3,
+        0x50, 0x04, 0x4E, 0x74, 0x00, 0x65, 0x00, 0x2D, 0x00, 0x52, 0x00, 0x44, 0x00, 0x50, 0x00, 0x73, 0x00, 0x6D,
+        0x00, 0x61, 0x00, 0x72, 0x00, 0x74, 0x00, 0x63, 0x00, 0x61, 0x00, 0x72, 0x00, 0x64, 0x00, 0x6C, 0x00, 0x6F,
+        0x00, 0x67, 0x00, 0x6F, 0x00, 0x6E, 0x00, 0x35, 0x00, 0x2D, 0x00, 0x38, 0x00, 0x66, 0x00, 0x66, 0x00, 0x33,
+        0x00, 0x61, 0x00, 0x33, 0x00, 0x38, 0x00, 0x65, 0x00, 0x2D, 0x00, 0x63, 0x00, 0x36, 0x00, 0x2D, 0x00, 0x35,
+        0x00, 0x30, 0x00, 0x39, 0x00, 0x38, 0x00, 0x37, 0x00, 0xA4, 0x54, 0x04, 0x52, 0x4D, 0x00, 0x69, 0x00, 0x63,
+        0x00, 0x72, 0x00, 0x6F, 0x00, 0x73, 0x00, 0x6F, 0x00, 0x66, 0x00, 0x74, 0x00, 0x20, 0x00, 0x42, 0x00, 0x61,
+        0x00, 0x73, 0x00, 0x65, 0x00, 0x20, 0x00, 0x53, 0x00, 0x6D, 0x00, 0x61, 0x00, 0x72, 0x00, 0x74, 0x00, 0x20,
+        0x00, 0x43, 0x00, 0x61, 0x00, 0x72, 0x00, 0x64, 0x00, 0x20, 0x00, 0x43, 0x00, 0x72, 0x00, 0x79, 0x00, 0x70,
+        0x00, 0x74, 0x00, 0x6F, 0x00, 0x20, 0x00, 0x50, 0x00, 0x72, 0x00, 0x6F, 0x00, 0x76, 0x00, 0x69, 0x00, 0x64,
+        0x00, 0x65, 0x00, 0x72, 0x00,
+    ];
+    // Smart card credentials are not supported.
+    assert!(DelegatedCredentials::decode(&smart_card).is_err());
+}
+
+/// Records the logon of the client
+struct LogonDisplay {
+    inner: TestDisplay,
+    logon: Arc<std::sync::Mutex<Option<ClientLogon>>>,
+}
+
+#[async_trait::async_trait]
+impl RdpServerDisplay for LogonDisplay {
+    async fn size(&mut self) -> DesktopSize {
+        self.inner.size().await
+    }
+
+    async fn updates(&mut self) -> Result<Box<dyn RdpServerDisplayUpdates>> {
+        self.inner.updates().await
+    }
+
+    async fn logon(&mut self, logon: &ClientLogon) {
+        *self.logon.lock().unwrap() = Some(logon.clone());
+    }
+}
+
+/// Allows alice and carol, whose password is "Password"
+///
+/// With CredSSP, the backend knows the password of alice and only the NT hash of the password of carol.
+struct TestAuthBackend;
+
+#[async_trait::async_trait]
+impl AuthBackend for TestAuthBackend {
+    async fn authenticate(&self, request: &AuthRequest) -> AuthDecision {
+        match (request.username.as_str(), request.password.as_deref()) {
+            // CredSSP, the client proves that it knows the password.
+            ("alice", None) => AuthDecision::Allow(Some(AuthSecret::Password("Password".to_owned()))),
+            ("carol", None) => AuthDecision::Allow(Some(AuthSecret::NtHash([
+                0xA4, 0xF4, 0x9C, 0x40, 0x65, 0x10, 0xBD, 0xCA, 0xB6, 0x82, 0x4E, 0xE7, 0xC3, 0x0F, 0xD8, 0x52,
+            ]))),
+            ("alice", Some("Password")) => AuthDecision::Allow(None),
+            _ => AuthDecision::Deny,
+        }
+    }
+}
+
+/// Builds a server authenticating the users with [`TestAuthBackend`], over TLS or CredSSP
+fn auth_server(hybrid: bool, display: impl RdpServerDisplay + 'static) -> RdpServer {
+    let identity = tls_identity();
+    let tls_acceptor = identity.make_acceptor().expect("failed to build TLS acceptor");
+    let builder = RdpServer::builder().with_addr(([127, 0, 0, 1], 0));
+    let builder = if hybrid {
+        builder.with_hybrid(tls_acceptor, identity.pub_key)
+    } else {
+        builder.with_tls(tls_acceptor)
+    };
+
+    builder
+        .with_input_handler(TestInputHandler)
+        .with_display_handler(display)
+        .with_auth_backend(TestAuthBackend)
+        .build()
+}
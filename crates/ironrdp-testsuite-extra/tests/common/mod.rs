@@ -0,0 +1,467 @@
+use core::future::Future;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use ironrdp::connector;
+use ironrdp::dvc::DrdynvcClient;
+use ironrdp::pdu::gcc;
+use ironrdp::pdu::rdp::capability_sets::MajorPlatformType;
+use ironrdp::rdpdr::pdu::efs::DeviceAnnounceHeader;
+use ironrdp::rdpeai::client::AudioCaptureSource;
+use ironrdp::rdpeai::pdu::WaveFormat;
+use ironrdp::server::tokio_rustls::TlsAcceptor;
+use ironrdp::server::{
+    self, AudioFormat, AudioInputServerFactory, AudioInputServerHandler, DesktopSize, DisplayUpdate, KeyboardEvent,
+    MouseEvent, RdpServer, RdpServerDisplay, RdpServerDisplayUpdates, RdpServerHandlerFactory, RdpServerInputHandler,
+    RdpdrServerFactory, RdpdrServerHandler, ServerEvent, ServerEventSender, SessionInfo, TlsIdentityCtx,
+};
+use ironrdp::session::gfx::GfxClient;
+use ironrdp::session::{ActiveStage, ActiveStageOutput};
+use ironrdp_async::{Framed, FramedWrite};
+use ironrdp_tls::TlsStream;
+use ironrdp_tokio::TokioStream;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{oneshot, Mutex};
+use tracing::debug;
+
+pub(crate) const DESKTOP_WIDTH: u16 = 1024;
+pub(crate) const DESKTOP_HEIGHT: u16 = 768;
+pub(crate) const USERNAME: &str = "";
+pub(crate) const PASSWORD: &str = "";
+
+type DisplayUpdatesRx = Arc<Mutex<UnboundedReceiver<DisplayUpdate>>>;
+
+struct TestDisplayUpdates {
+    rx: DisplayUpdatesRx,
+}
+
+#[async_trait::async_trait]
+impl RdpServerDisplayUpdates for TestDisplayUpdates {
+    async fn next_update(&mut self) -> Option<DisplayUpdate> {
+        let mut rx = self.rx.lock().await;
+
+        rx.recv().await
+    }
+}
+
+pub(crate) struct TestDisplay {
+    rx: DisplayUpdatesRx,
+}
+
+#[async_trait::async_trait]
+impl RdpServerDisplay for TestDisplay {
+    async fn size(&mut self) -> DesktopSize {
+        DesktopSize {
+            width: DESKTOP_WIDTH,
+            height: DESKTOP_HEIGHT,
+        }
+    }
+
+    async fn updates(&mut self) -> Result<Box<dyn RdpServerDisplayUpdates>> {
+        Ok(Box::new(TestDisplayUpdates {
+            rx: Arc::clone(&self.rx),
+        }))
+    }
+}
+
+pub(crate) struct TestInputHandler;
+impl RdpServerInputHandler for TestInputHandler {
+    fn keyboard(&mut self, _: KeyboardEvent) {}
+    fn mouse(&mut self, _: MouseEvent) {}
+}
+
+#[derive(Default)]
+pub(crate) struct TestHandlerFactory {
+    // Keeps the display of each session open.
+    display_senders: std::sync::Mutex<Vec<UnboundedSender<DisplayUpdate>>>,
+}
+
+impl RdpServerHandlerFactory for TestHandlerFactory {
+    fn build_input_handler(&self, _: &SessionInfo) -> Box<dyn RdpServerInputHandler> {
+        Box::new(TestInputHandler)
+    }
+
+    fn build_display(&self, _: &SessionInfo) -> Box<dyn RdpServerDisplay> {
+        let (display_tx, display_rx) = mpsc::unbounded_channel();
+        self.display_senders.lock().unwrap().push(display_tx);
+
+        Box::new(TestDisplay {
+            rx: Arc::new(Mutex::new(display_rx)),
+        })
+    }
+}
+
+/// Forwards the audio captured by the client
+#[derive(Debug)]
+struct TestAudioInputHandler {
+    data_tx: UnboundedSender<(AudioFormat, Vec<u8>)>,
+}
+
+impl AudioInputServerHandler for TestAudioInputHandler {
+    fn get_formats(&self) -> &[AudioFormat] {
+        core::slice::from_ref(&SineSource::FORMAT)
+    }
+
+    fn data(&mut self, format: &AudioFormat, data: Vec<u8>) {
+        let _ = self.data_tx.send((format.clone(), data));
+    }
+
+    fn stop(&mut self) {}
+}
+
+pub(crate) struct TestAudioInputFactory {
+    pub(crate) data_tx: UnboundedSender<(AudioFormat, Vec<u8>)>,
+}
+
+impl AudioInputServerFactory for TestAudioInputFactory {
+    fn build_backend(&self) -> Box<dyn AudioInputServerHandler> {
+        Box::new(TestAudioInputHandler {
+            data_tx: self.data_tx.clone(),
+        })
+    }
+}
+
+/// Captures a 440 Hz sine wave, 20 ms at a time
+#[derive(Debug, Default)]
+pub(crate) struct SineSource {
+    /// Number of samples captured
+    position: usize,
+}
+
+impl SineSource {
+    pub(crate) const FORMAT: AudioFormat = AudioFormat {
+        format: WaveFormat::PCM,
+        n_channels: 1,
+        n_samples_per_sec: 16000,
+        n_avg_bytes_per_sec: 32000,
+        n_block_align: 2,
+        bits_per_sample: 16,
+        data: None,
+    };
+}
+
+impl AudioCaptureSource for SineSource {
+    fn get_formats(&self) -> &[AudioFormat] {
+        core::slice::from_ref(&Self::FORMAT)
+    }
+
+    fn open(&mut self, format: &AudioFormat) -> bool {
+        self.position = 0;
+        *format == Self::FORMAT
+    }
+
+    fn read(&mut self, buffer: &mut Vec<u8>) {
+        buffer.extend(sine_wave(self.position, 320));
+        self.position += 320;
+    }
+
+    fn close(&mut self) {}
+}
+
+/// 16-bit samples of a 440 Hz sine wave, sampled at 16 kHz
+pub(crate) fn sine_wave(start: usize, count: usize) -> Vec<u8> {
+    (start..start + count)
+        .flat_map(|i| {
+            #[allow(clippy::cast_possible_truncation)] // the amplitude fits in i16
+            let sample = (f64::sin(2.0 * core::f64::consts::PI * 440.0 * i as f64 / 16000.0) * 10000.0) as i16;
+            sample.to_le_bytes()
+        })
+        .collect()
+}
+
+/// Notifies the devices announced by the client
+#[derive(Debug)]
+struct TestRdpdrHandler {
+    announced_tx: UnboundedSender<Vec<DeviceAnnounceHeader>>,
+}
+
+impl RdpdrServerHandler for TestRdpdrHandler {
+    fn devices_announced(&mut self, devices: &[DeviceAnnounceHeader]) {
+        let _ = self.announced_tx.send(devices.to_vec());
+    }
+
+    fn devices_removed(&mut self, _: &[u32]) {}
+}
+
+pub(crate) struct TestRdpdrFactory {
+    pub(crate) announced_tx: UnboundedSender<Vec<DeviceAnnounceHeader>>,
+}
+
+impl ServerEventSender for TestRdpdrFactory {
+    fn set_sender(&mut self, _: UnboundedSender<ServerEvent>) {}
+}
+
+impl RdpdrServerFactory for TestRdpdrFactory {
+    fn build_backend(&self) -> Box<dyn RdpdrServerHandler> {
+        Box::new(TestRdpdrHandler {
+            announced_tx: self.announced_tx.clone(),
+        })
+    }
+}
+
+pub(crate) async fn client_server<F, Fut>(client_config: connector::Config, clientfn: F)
+where
+    F: FnOnce(ActiveStage, TestFramed, TestServer) -> Fut + 'static,
+    Fut: Future<Output = (ActiveStage, TestFramed)>,
+{
+    client_server_with(Transport::Tcp, client_config, tls_server, |_| {}, clientfn)
+        .await
+        .expect("connect");
+}
+
+/// Connects a client, with the static channels attached by `attach`, to the server built by
+/// `build_server`, and runs `clientfn` before disconnecting
+///
+/// Returns the connection error, when both the client and the server failed to connect.
+pub(crate) async fn client_server_with<B, A, F, Fut>(
+    transport: Transport,
+    client_config: connector::Config,
+    build_server: B,
+    attach: A,
+    clientfn: F,
+) -> connector::ConnectorResult<()>
+where
+    B: FnOnce(TestDisplay) -> RdpServer,
+    A: FnOnce(&mut connector::ClientConnector) + 'static,
+    F: FnOnce(ActiveStage, TestFramed, TestServer) -> Fut + 'static,
+    Fut: Future<Output = (ActiveStage, TestFramed)>,
+{
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .try_init();
+
+    let (display_tx, display_rx) = mpsc::unbounded_channel();
+    let mut server = build_server(TestDisplay {
+        rx: Arc::new(Mutex::new(display_rx)),
+    });
+    server.set_credentials(Some(server::Credentials {
+        username: USERNAME.into(),
+        password: PASSWORD.into(),
+        domain: None,
+    }));
+    let ev = server.event_sender().clone();
+    // The display updates of the session end with the last sender.
+    let test_server = TestServer {
+        display_tx: display_tx.clone(),
+        ev: ev.clone(),
+    };
+
+    let client = |stream: Box<dyn TestStream>, addr: SocketAddr| async move {
+        let (active_stage, upgraded_framed) = connect_over_with(client_config, stream, addr, attach).await?;
+        let (mut active_stage, mut upgraded_framed) = clientfn(active_stage, upgraded_framed, test_server).await;
+        let outputs = active_stage.graceful_shutdown().expect("shutdown");
+        for out in outputs {
+            match out {
+                ActiveStageOutput::ResponseFrame(frame) => {
+                    upgraded_framed.write_all(&frame).await.expect("write frame");
+                }
+                _ => unimplemented!(),
+            }
+        }
+
+        // server should probably send TLS close_notify
+        while let Ok(pdu) = upgraded_framed.read_pdu().await {
+            debug!(?pdu);
+        }
+        drop(display_tx);
+
+        connector::ConnectorResult::Ok(())
+    };
+
+    let local = tokio::task::LocalSet::new();
+    local
+        .run_until(async move {
+            match transport {
+                Transport::Tcp => {
+                    let server = tokio::task::spawn_local(async move {
+                        server.run().await.unwrap();
+                    });
+
+                    let client = tokio::task::spawn_local(async move {
+                        let (tx, rx) = oneshot::channel();
+                        ev.send(ServerEvent::GetLocalAddr(tx)).unwrap();
+                        let addr = rx.await.unwrap().unwrap();
+                        let tcp_stream = TcpStream::connect(addr).await.expect("TCP connect");
+                        let result = client(Box::new(tcp_stream), addr).await;
+                        ev.send(ServerEvent::Quit("bye".into())).unwrap();
+                        result
+                    });
+
+                    let ((), result) = tokio::try_join!(server, client).expect("join");
+                    result
+                }
+                Transport::InMemory => {
+                    let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+                    let addr = SocketAddr::from(([127, 0, 0, 1], 3389));
+
+                    // The server returns once the client disconnected.
+                    match tokio::join!(
+                        server.run_connection(server_stream, None),
+                        client(Box::new(client_stream), addr)
+                    ) {
+                        (Ok(()), Ok(())) => Ok(()),
+                        (Err(_), Err(error)) => Err(error),
+                        (server_result, client_result) => {
+                            panic!("server: {server_result:?}, client: {client_result:?}")
+                        }
+                    }
+                }
+            }
+        })
+        .await
+}
+
+/// Transport between the client and the server of a test
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Transport {
+    /// TCP connection to the server, listening on a local port
+    Tcp,
+    /// In-memory stream, served with [`RdpServer::run_connection`]
+    InMemory,
+}
+
+pub(crate) trait TestStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin> TestStream for S {}
+
+pub(crate) type TestFramed = Framed<TokioStream<TlsStream<Box<dyn TestStream>>>>;
+
+/// Server of a test, as seen from the client
+pub(crate) struct TestServer {
+    pub(crate) display_tx: UnboundedSender<DisplayUpdate>,
+    pub(crate) ev: UnboundedSender<ServerEvent>,
+}
+
+/// Builds a server accepting TLS connections, with the test handlers
+pub(crate) fn tls_server(display: TestDisplay) -> RdpServer {
+    RdpServer::builder()
+        .with_addr(([127, 0, 0, 1], 0))
+        .with_tls(tls_acceptor())
+        .with_input_handler(TestInputHandler)
+        .with_display_handler(display)
+        .build()
+}
+
+pub(crate) fn tls_identity() -> TlsIdentityCtx {
+    let cert_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/certs/server-cert.pem");
+    let key_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/certs/server-key.pem");
+    TlsIdentityCtx::init_from_paths(&cert_path, &key_path).expect("failed to init TLS identity")
+}
+
+pub(crate) fn tls_acceptor() -> TlsAcceptor {
+    tls_identity().make_acceptor().expect("failed to build TLS acceptor")
+}
+
+pub(crate) async fn connect(
+    client_config: connector::Config,
+    addr: SocketAddr,
+) -> connector::ConnectorResult<(ActiveStage, Framed<TokioStream<TlsStream<TcpStream>>>)> {
+    let tcp_stream = TcpStream::connect(addr).await.expect("TCP connect");
+    connect_over_with(client_config, tcp_stream, addr, |_| {}).await
+}
+
+/// Connects with the static channels attached by `attach`
+async fn connect_over_with<S>(
+    client_config: connector::Config,
+    stream: S,
+    addr: SocketAddr,
+    attach: impl FnOnce(&mut connector::ClientConnector),
+) -> connector::ConnectorResult<(ActiveStage, Framed<TokioStream<TlsStream<S>>>)>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin,
+{
+    let mut framed = ironrdp_tokio::TokioFramed::new(stream);
+    let mut connector = connector::ClientConnector::new(client_config).with_server_addr(addr);
+    if connector.config.enable_gfx {
+        connector.attach_static_channel(DrdynvcClient::new().with_dynamic_channel(GfxClient::new()));
+    }
+    attach(&mut connector);
+    let should_upgrade = ironrdp_async::connect_begin(&mut framed, &mut connector).await?;
+    let initial_stream = framed.into_inner_no_leftover();
+    let (upgraded_stream, server_public_key) = ironrdp_tls::upgrade(initial_stream, "localhost")
+        .await
+        .expect("TLS upgrade");
+    let upgraded = ironrdp_tokio::mark_as_upgraded(should_upgrade, &mut connector);
+    let mut upgraded_framed = ironrdp_tokio::TokioFramed::new(upgraded_stream);
+    let connection_result = ironrdp_async::connect_finalize(
+        upgraded,
+        &mut upgraded_framed,
+        connector,
+        "localhost".into(),
+        server_public_key,
+        None,
+        None,
+    )
+    .await?;
+
+    Ok((ActiveStage::new(connection_result), upgraded_framed))
+}
+
+// Maybe implement Default for Config
+pub(crate) fn default_client_config() -> connector::Config {
+    connector::Config {
+        desktop_size: DesktopSize {
+            width: DESKTOP_WIDTH,
+            height: DESKTOP_HEIGHT,
+        },
+        desktop_scale_factor: 0, // Default to 0 per FreeRDP
+        enable_tls: true,
+        enable_credssp: true,
+        enable_standard_rdp_security: false,
+        credentials: connector::Credentials::UsernamePassword {
+            username: USERNAME.into(),
+            password: PASSWORD.into(),
+        },
+        domain: None,
+        client_build: semver::Version::parse(env!("CARGO_PKG_VERSION"))
+            .map(|version| version.major * 100 + version.minor * 10 + version.patch)
+            .unwrap_or(0)
+            .try_into()
+            .unwrap(),
+        client_name: "ironrdp".into(),
+        keyboard_type: gcc::KeyboardType::IbmEnhanced,
+        keyboard_subtype: 0,
+        keyboard_layout: 0,
+        keyboard_functional_keys_count: 12,
+        ime_file_name: "".into(),
+        bitmap: None,
+        bitmap_cache: None,
+        dig_product_id: "".into(),
+        // NOTE: hardcode this value like in freerdp
+        // https://github.com/FreeRDP/FreeRDP/blob/4e24b966c86fdf494a782f0dfcfc43a057a2ea60/libfreerdp/core/settings.c#LL49C34-L49C70
+        client_dir: "C:\\Windows\\System32\\mstscax.dll".into(),
+        #[cfg(windows)]
+        platform: MajorPlatformType::WINDOWS,
+        #[cfg(target_os = "macos")]
+        platform: MajorPlatformType::MACINTOSH,
+        #[cfg(target_os = "ios")]
+        platform: MajorPlatformType::IOS,
+        #[cfg(target_os = "linux")]
+        platform: MajorPlatformType::UNIX,
+        #[cfg(target_os = "android")]
+        platform: MajorPlatformType::ANDROID,
+        #[cfg(target_os = "freebsd")]
+        platform: MajorPlatformType::UNIX,
+        #[cfg(target_os = "dragonfly")]
+        platform: MajorPlatformType::UNIX,
+        #[cfg(target_os = "openbsd")]
+        platform: MajorPlatformType::UNIX,
+        #[cfg(target_os = "netbsd")]
+        platform: MajorPlatformType::UNIX,
+        hardware_id: None,
+        request_data: None,
+        autologon: false,
+        enable_gfx: false,
+        compression_type: None,
+        auto_reconnect: None,
+        redirected_session_id: None,
+        license_cache: None,
+        no_server_pointer: true,
+        pointer_software_rendering: true,
+        performance_flags: Default::default(),
+    }
+}
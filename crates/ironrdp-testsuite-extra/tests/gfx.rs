@@ -0,0 +1,152 @@
+use core::num::NonZeroU16;
+
+use ironrdp::connector;
+use ironrdp::pdu::geometry::InclusiveRectangle;
+use ironrdp::pdu::rdp::vc::dvc::gfx::QuantQuality;
+use ironrdp::server::{Avc420Update, AvcRegion, AvcStream, BitmapUpdate, DisplayUpdate, PixelFormat, PixelOrder};
+use ironrdp::session::gfx::GfxClient;
+use ironrdp::session::image::DecodedImage;
+use ironrdp::session::{ActiveStage, ActiveStageOutput};
+use ironrdp_async::FramedWrite;
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::debug;
+
+use crate::common::{client_server, default_client_config, TestFramed};
+
+#[tokio::test]
+async fn test_gfx() {
+    let client_config = connector::Config {
+        enable_gfx: true,
+        ..default_client_config()
+    };
+    let mut image = DecodedImage::new(
+        PixelFormat::RgbA32,
+        client_config.desktop_size.width,
+        client_config.desktop_size.height,
+    );
+    client_server(client_config, |mut stage, mut framed, server| async move {
+        let updates = vec![DisplayUpdate::Bitmap(blue_bitmap())];
+        let region = first_gfx_frame(&mut stage, &mut framed, &mut image, &server.display_tx, updates).await;
+
+        // The whole surface is drawn with the first frame.
+        assert!(region.right >= 79 && region.bottom >= 39);
+        assert_blue_bitmap(&image);
+
+        (stage, framed)
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_gfx_avc_unsupported() {
+    let client_config = connector::Config {
+        enable_gfx: true,
+        ..default_client_config()
+    };
+    let mut image = DecodedImage::new(
+        PixelFormat::RgbA32,
+        client_config.desktop_size.width,
+        client_config.desktop_size.height,
+    );
+    client_server(client_config, |mut stage, mut framed, server| async move {
+        // The client does not advertise AVC, the H.264 frame is dropped.
+        let avc = DisplayUpdate::Avc420(Avc420Update {
+            top: 0,
+            left: 0,
+            width: NonZeroU16::new(64).unwrap(),
+            height: NonZeroU16::new(64).unwrap(),
+            stream: AvcStream {
+                data: vec![0x00, 0x00, 0x00, 0x01, 0x09, 0xF0],
+                regions: vec![AvcRegion {
+                    rectangle: InclusiveRectangle {
+                        left: 0,
+                        top: 0,
+                        right: 63,
+                        bottom: 63,
+                    },
+                    quant_quality: QuantQuality {
+                        quantization_parameter: 22,
+                        progressive: false,
+                        quality: 100,
+                    },
+                }],
+            },
+        });
+        let updates = vec![avc, DisplayUpdate::Bitmap(blue_bitmap())];
+        first_gfx_frame(&mut stage, &mut framed, &mut image, &server.display_tx, updates).await;
+
+        assert_blue_bitmap(&image);
+
+        (stage, framed)
+    })
+    .await
+}
+
+/// Sends the updates once the graphics pipeline is active, and returns the region updated by the
+/// first frame
+async fn first_gfx_frame(
+    stage: &mut ActiveStage,
+    framed: &mut TestFramed,
+    image: &mut DecodedImage,
+    display_tx: &UnboundedSender<DisplayUpdate>,
+    mut updates: Vec<DisplayUpdate>,
+) -> InclusiveRectangle {
+    loop {
+        let (action, payload) = framed.read_pdu().await.expect("valid PDU");
+        let outputs = stage.process(image, action, &payload).expect("stage process");
+
+        let mut updated_region = None;
+        for out in outputs {
+            match out {
+                ActiveStageOutput::ResponseFrame(frame) => framed.write_all(&frame).await.expect("write frame"),
+                ActiveStageOutput::GraphicsUpdate(region) => updated_region = Some(region),
+                out => debug!(?out),
+            }
+        }
+
+        let gfx = stage
+            .get_dvc_mut::<GfxClient>()
+            .and_then(|dvc| dvc.channel_processor_downcast_mut::<GfxClient>())
+            .expect("GFX client");
+
+        if gfx.total_frames_decoded() > 0 {
+            assert_eq!(gfx.total_frames_decoded(), 1);
+            return updated_region.expect("updated region");
+        }
+
+        if gfx.confirmed_capabilities().is_some() {
+            for update in updates.drain(..) {
+                display_tx.send(update).unwrap();
+            }
+        }
+    }
+}
+
+/// Blue rectangle at (16, 8), 64 pixels wide and 32 pixels high
+fn blue_bitmap() -> BitmapUpdate {
+    BitmapUpdate {
+        top: 8,
+        left: 16,
+        width: NonZeroU16::new(64).unwrap(),
+        height: NonZeroU16::new(32).unwrap(),
+        format: PixelFormat::BgrA32,
+        order: PixelOrder::TopToBottom,
+        data: [0xFF, 0x00, 0x00, 0xFF].repeat(64 * 32),
+        stride: 64 * 4,
+    }
+}
+
+fn assert_blue_bitmap(image: &DecodedImage) {
+    // RemoteFX is lossy, especially at the edges of the tiles.
+    let stride = usize::from(image.width()) * 4;
+    let assert_pixel = |x: usize, y: usize, expected: [u8; 3]| {
+        let pixel = &image.data()[y * stride + x * 4..][..3];
+        assert!(
+            pixel.iter().zip(expected).all(|(a, b)| a.abs_diff(b) <= 0x20),
+            "pixel ({x}, {y}): {pixel:?} != {expected:?}"
+        );
+    };
+    assert_pixel(16, 8, [0x00, 0x00, 0xFF]);
+    assert_pixel(79, 39, [0x00, 0x00, 0xFF]);
+    assert_pixel(80, 40, [0x00, 0x00, 0x00]);
+}
@@ -0,0 +1,268 @@
+use ironrdp::pdu::{encode_err, PduResult};
+use ironrdp::rdpdr::pdu::efs::{
+    ClientDriveLockControlResponse, ClientDriveNotifyChangeDirectoryResponse, ClientDriveQueryDirectoryResponse,
+    ClientDriveQueryInformationResponse, ClientDriveQueryVolumeInformationResponse, ClientDriveSetInformationResponse,
+    CreateDisposition, CreateOptions, DesiredAccess, DeviceCloseResponse, DeviceControlRequest, DeviceControlResponse,
+    DeviceCreateResponse, DeviceIoResponse, DeviceReadResponse, DeviceType, DeviceWriteResponse, FileAttributes,
+    FileBothDirectoryInformation, FileInformationClass, Information, MajorFunction, NtStatus,
+    ServerDeviceAnnounceResponse, ServerDriveIoRequest,
+};
+use ironrdp::rdpdr::pdu::esc::{ScardCall, ScardIoCtlCode};
+use ironrdp::rdpdr::pdu::RdpdrPdu;
+use ironrdp::rdpdr::{backend::RdpdrBackend, Rdpdr};
+use ironrdp::server::{ClientDevices, DeviceIoError, PixelFormat, RdpServer};
+use ironrdp::session::image::DecodedImage;
+use ironrdp::session::ActiveStageOutput;
+use ironrdp::svc::SvcMessage;
+use ironrdp_async::FramedWrite;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tracing::debug;
+
+use crate::common::{
+    client_server_with, default_client_config, tls_acceptor, TestInputHandler, TestRdpdrFactory, Transport,
+    DESKTOP_HEIGHT, DESKTOP_WIDTH,
+};
+
+#[cfg(target_os = "linux")]
+mod native;
+
+#[tokio::test]
+async fn test_drive_redirection() {
+    let (announced_tx, mut announced_rx) = mpsc::unbounded_channel();
+    let (requests_tx, mut requests_rx) = mpsc::unbounded_channel();
+
+    client_server_with(
+        Transport::InMemory,
+        default_client_config(),
+        |display| {
+            RdpServer::builder()
+                .with_addr(([127, 0, 0, 1], 0))
+                .with_tls(tls_acceptor())
+                .with_input_handler(TestInputHandler)
+                .with_display_handler(display)
+                .with_rdpdr_factory(Some(Box::new(TestRdpdrFactory { announced_tx })))
+                .build()
+        },
+        |connector| {
+            connector.attach_static_channel(
+                Rdpdr::new(Box::new(TestRdpdrBackend { requests_tx }), "ironrdp".into())
+                    .with_drives(Some(vec![(1, "drive".into())])),
+            );
+        },
+        |mut stage, mut framed, server| async move {
+            let devices = ClientDevices::new(server.ev.clone());
+            let check = async move {
+                let announced = announced_rx.recv().await.expect("announced devices");
+                assert_eq!(announced.len(), 1);
+                assert_eq!(announced[0].device_id(), 1);
+                assert_eq!(announced[0].device_type(), DeviceType::Filesystem);
+                assert_eq!(announced[0].name(), "drive");
+                assert_eq!(devices.devices().await.unwrap(), announced);
+
+                let file_id = devices
+                    .create(
+                        1,
+                        "\\hello.txt",
+                        DesiredAccess::FILE_READ_DATA_OR_FILE_LIST_DIRECTORY,
+                        CreateDisposition::FILE_OPEN,
+                        CreateOptions::FILE_NON_DIRECTORY_FILE,
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(devices.read(1, file_id, 7, 64).await.unwrap(), b"world!");
+                devices.close(1, file_id).await.unwrap();
+
+                let error = devices
+                    .create(
+                        1,
+                        "\\missing.txt",
+                        DesiredAccess::FILE_READ_DATA_OR_FILE_LIST_DIRECTORY,
+                        CreateDisposition::FILE_OPEN,
+                        CreateOptions::FILE_NON_DIRECTORY_FILE,
+                    )
+                    .await
+                    .unwrap_err();
+                assert_eq!(
+                    error.downcast_ref::<DeviceIoError>(),
+                    Some(&DeviceIoError(NtStatus::NO_SUCH_FILE))
+                );
+
+                let dir_id = devices
+                    .create(
+                        1,
+                        "\\",
+                        DesiredAccess::FILE_READ_DATA_OR_FILE_LIST_DIRECTORY,
+                        CreateDisposition::FILE_OPEN,
+                        CreateOptions::FILE_DIRECTORY_FILE,
+                    )
+                    .await
+                    .unwrap();
+                let entry = devices.query_directory(1, dir_id, true, "\\*").await.unwrap();
+                assert_eq!(entry.expect("directory entry").file_name, "hello.txt");
+                assert!(devices.query_directory(1, dir_id, false, "").await.unwrap().is_none());
+                devices.close(1, dir_id).await.unwrap();
+            };
+            tokio::pin!(check);
+
+            // The client answers the device I/O requests of the server until the checks are done.
+            let mut image = DecodedImage::new(PixelFormat::RgbA32, DESKTOP_WIDTH, DESKTOP_HEIGHT);
+            loop {
+                tokio::select! {
+                    () = &mut check => break,
+                    pdu = framed.read_pdu() => {
+                        let (action, payload) = pdu.expect("valid PDU");
+                        for out in stage.process(&mut image, action, &payload).expect("stage process") {
+                            match out {
+                                ActiveStageOutput::ResponseFrame(frame) => {
+                                    framed.write_all(&frame).await.expect("write frame");
+                                }
+                                out => debug!(?out),
+                            }
+                        }
+                    }
+                }
+            }
+
+            let requests: Vec<_> = core::iter::from_fn(|| requests_rx.try_recv().ok()).collect();
+            assert_eq!(
+                requests,
+                [
+                    MajorFunction::Create,
+                    MajorFunction::Read,
+                    MajorFunction::Close,
+                    MajorFunction::Create,
+                    MajorFunction::Create,
+                    MajorFunction::DirectoryControl,
+                    MajorFunction::DirectoryControl,
+                    MajorFunction::Close,
+                ]
+            );
+
+            (stage, framed)
+        },
+    )
+    .await
+    .expect("connect");
+}
+
+/// Client drive with a root directory (file ID 1) containing "hello.txt" (file ID 2)
+///
+/// The other requests are not supported.
+#[derive(Debug)]
+struct TestRdpdrBackend {
+    /// Records the major function of each device I/O request
+    requests_tx: UnboundedSender<MajorFunction>,
+}
+
+ironrdp::core::impl_as_any!(TestRdpdrBackend);
+
+impl RdpdrBackend for TestRdpdrBackend {
+    fn handle_server_device_announce_response(&mut self, _: ServerDeviceAnnounceResponse) -> PduResult<()> {
+        Ok(())
+    }
+
+    fn handle_scard_call(&mut self, _: DeviceControlRequest<ScardIoCtlCode>, _: ScardCall) -> PduResult<()> {
+        Ok(())
+    }
+
+    fn handle_drive_io_request(&mut self, req: ServerDriveIoRequest) -> PduResult<Vec<SvcMessage>> {
+        const CONTENT: &[u8] = b"Hello, world!";
+
+        let device_io_request = match &req {
+            ServerDriveIoRequest::ServerCreateDriveRequest(req) => &req.device_io_request,
+            ServerDriveIoRequest::ServerDriveQueryInformationRequest(req) => &req.device_io_request,
+            ServerDriveIoRequest::DeviceCloseRequest(req) => &req.device_io_request,
+            ServerDriveIoRequest::ServerDriveQueryDirectoryRequest(req) => &req.device_io_request,
+            ServerDriveIoRequest::ServerDriveNotifyChangeDirectoryRequest(req) => &req.device_io_request,
+            ServerDriveIoRequest::ServerDriveQueryVolumeInformationRequest(req) => &req.device_io_request,
+            ServerDriveIoRequest::DeviceReadRequest(req) => &req.device_io_request,
+            ServerDriveIoRequest::DeviceWriteRequest(req) => &req.device_io_request,
+            ServerDriveIoRequest::ServerDriveSetInformationRequest(req) => &req.device_io_request,
+            ServerDriveIoRequest::ServerDriveLockControlRequest(req) => &req.device_io_request,
+            ServerDriveIoRequest::DeviceControlRequest(req) => &req.header,
+        };
+        let _ = self.requests_tx.send(device_io_request.major_function);
+
+        let pdu = match req {
+            ServerDriveIoRequest::ServerCreateDriveRequest(req) => {
+                let (status, file_id) = match req.path.as_str() {
+                    "\\" => (NtStatus::SUCCESS, 1),
+                    "\\hello.txt" => (NtStatus::SUCCESS, 2),
+                    _ => (NtStatus::NO_SUCH_FILE, 0),
+                };
+                RdpdrPdu::DeviceCreateResponse(DeviceCreateResponse {
+                    device_io_reply: DeviceIoResponse::new(req.device_io_request, status),
+                    file_id,
+                    information: Information::FILE_OPENED,
+                })
+            }
+            ServerDriveIoRequest::DeviceReadRequest(req) => {
+                let offset = usize::try_from(req.offset).unwrap().min(CONTENT.len());
+                let end = (offset + usize::try_from(req.length).unwrap()).min(CONTENT.len());
+                RdpdrPdu::DeviceReadResponse(DeviceReadResponse {
+                    device_io_reply: DeviceIoResponse::new(req.device_io_request, NtStatus::SUCCESS),
+                    read_data: CONTENT[offset..end].to_vec(),
+                })
+            }
+            ServerDriveIoRequest::ServerDriveQueryDirectoryRequest(req) if req.initial_query != 0 => {
+                RdpdrPdu::ClientDriveQueryDirectoryResponse(ClientDriveQueryDirectoryResponse {
+                    device_io_reply: DeviceIoResponse::new(req.device_io_request, NtStatus::SUCCESS),
+                    buffer: Some(FileInformationClass::BothDirectory(FileBothDirectoryInformation::new(
+                        0,
+                        0,
+                        0,
+                        0,
+                        i64::try_from(CONTENT.len()).unwrap(),
+                        FileAttributes::FILE_ATTRIBUTE_NORMAL,
+                        "hello.txt".into(),
+                    ))),
+                })
+            }
+            ServerDriveIoRequest::ServerDriveQueryDirectoryRequest(req) => {
+                RdpdrPdu::ClientDriveQueryDirectoryResponse(ClientDriveQueryDirectoryResponse {
+                    device_io_reply: DeviceIoResponse::new(req.device_io_request, NtStatus::NO_MORE_FILES),
+                    buffer: None,
+                })
+            }
+            ServerDriveIoRequest::DeviceCloseRequest(req) => RdpdrPdu::DeviceCloseResponse(DeviceCloseResponse {
+                device_io_response: DeviceIoResponse::new(req.device_io_request, NtStatus::SUCCESS),
+            }),
+            ServerDriveIoRequest::ServerDriveQueryInformationRequest(req) => {
+                RdpdrPdu::ClientDriveQueryInformationResponse(ClientDriveQueryInformationResponse {
+                    device_io_response: DeviceIoResponse::new(req.device_io_request, NtStatus::NOT_SUPPORTED),
+                    buffer: None,
+                })
+            }
+            ServerDriveIoRequest::ServerDriveNotifyChangeDirectoryRequest(req) => {
+                RdpdrPdu::ClientDriveNotifyChangeDirectoryResponse(ClientDriveNotifyChangeDirectoryResponse {
+                    device_io_reply: DeviceIoResponse::new(req.device_io_request, NtStatus::NOT_SUPPORTED),
+                    buffer: Vec::new(),
+                })
+            }
+            ServerDriveIoRequest::ServerDriveQueryVolumeInformationRequest(req) => {
+                RdpdrPdu::ClientDriveQueryVolumeInformationResponse(ClientDriveQueryVolumeInformationResponse {
+                    device_io_reply: DeviceIoResponse::new(req.device_io_request, NtStatus::NOT_SUPPORTED),
+                    buffer: None,
+                })
+            }
+            ServerDriveIoRequest::DeviceControlRequest(req) => RdpdrPdu::DeviceControlResponse(DeviceControlResponse {
+                device_io_reply: DeviceIoResponse::new(req.header, NtStatus::NOT_SUPPORTED),
+                output_buffer: None,
+            }),
+            ServerDriveIoRequest::DeviceWriteRequest(req) => RdpdrPdu::DeviceWriteResponse(DeviceWriteResponse {
+                device_io_reply: DeviceIoResponse::new(req.device_io_request, NtStatus::NOT_SUPPORTED),
+                length: 0,
+            }),
+            ServerDriveIoRequest::ServerDriveSetInformationRequest(req) => RdpdrPdu::ClientDriveSetInformationResponse(
+                ClientDriveSetInformationResponse::new(&req, NtStatus::NOT_SUPPORTED).map_err(|e| encode_err!(e))?,
+            ),
+            ServerDriveIoRequest::ServerDriveLockControlRequest(req) => {
+                RdpdrPdu::ClientDriveLockControlResponse(ClientDriveLockControlResponse {
+                    device_io_reply: DeviceIoResponse::new(req.device_io_request, NtStatus::NOT_SUPPORTED),
+                })
+            }
+        };
+
+        Ok(vec![SvcMessage::from(pdu)])
+    }
+}
@@ -0,0 +1,379 @@
+use std::fs;
+use std::path::PathBuf;
+
+use ironrdp::core::ReadCursor;
+use ironrdp::rdpdr::pdu::efs::{
+    CompletionFilter, CreateDisposition, CreateOptions, DesiredAccess, DeviceCloseRequest, DeviceCreateRequest,
+    DeviceIoRequest, DeviceIoResponse, FileAttributes, LockOperation, MajorFunction, MinorFunction, NtStatus,
+    RdpLockInfo, ServerDriveIoRequest, ServerDriveLockControlRequest, ServerDriveNotifyChangeDirectoryRequest,
+    SharedAccess,
+};
+use ironrdp::rdpdr::RdpdrBackend as _;
+use ironrdp::svc::{StaticVirtualChannel, SvcMessage};
+use ironrdp_rdpdr_native::backend::NixRdpdrBackend;
+
+/// Directory shared as a drive, removed when dropped
+struct Drive {
+    path: PathBuf,
+    backend: NixRdpdrBackend,
+    completion_id: u32,
+}
+
+impl Drive {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("ironrdp-rdpdr-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+
+        Self {
+            backend: NixRdpdrBackend::new(path.to_str().unwrap().to_owned()),
+            path,
+            completion_id: 0,
+        }
+    }
+
+    fn io_request(&mut self, file_id: u32, major_function: MajorFunction, minor_function: u32) -> DeviceIoRequest {
+        self.completion_id += 1;
+        DeviceIoRequest {
+            device_id: 1,
+            file_id,
+            completion_id: self.completion_id,
+            major_function,
+            minor_function: MinorFunction::from(minor_function),
+        }
+    }
+
+    fn handle(&mut self, request: ServerDriveIoRequest) -> Vec<Response> {
+        responses(self.backend.handle_drive_io_request(request).unwrap())
+    }
+
+    /// Opens `path`, creating it if it is a file, and returns its file ID
+    fn open(&mut self, path: &str, create_options: CreateOptions) -> u32 {
+        let create_disposition = if create_options.contains(CreateOptions::FILE_DIRECTORY_FILE) {
+            CreateDisposition::FILE_OPEN
+        } else {
+            CreateDisposition::FILE_OPEN_IF
+        };
+        let request = DeviceCreateRequest {
+            device_io_request: self.io_request(0, MajorFunction::Create, 0),
+            desired_access: DesiredAccess::FILE_READ_DATA_OR_FILE_LIST_DIRECTORY
+                | DesiredAccess::FILE_WRITE_DATA_OR_FILE_ADD_FILE,
+            allocation_size: 0,
+            file_attributes: FileAttributes::empty(),
+            shared_access: SharedAccess::all(),
+            create_disposition,
+            create_options,
+            path: path.to_owned(),
+        };
+
+        let [response] = self.handle(request.into()).try_into().unwrap();
+        assert_eq!(response.status, NtStatus::SUCCESS);
+        u32::from_le_bytes(response.data[..4].try_into().unwrap())
+    }
+
+    /// Closes `file_id`, and returns the responses of its pending requests
+    fn close(&mut self, file_id: u32) -> Vec<Response> {
+        let request = DeviceCloseRequest {
+            device_io_request: self.io_request(file_id, MajorFunction::Close, 0),
+        };
+
+        let mut responses = self.handle(request.into());
+        let close = responses.pop().unwrap();
+        assert_eq!(close.status, NtStatus::SUCCESS);
+        responses
+    }
+
+    fn lock(&mut self, file_id: u32, operation: LockOperation, wait: bool, locks: &[(u64, u64)]) -> Option<Response> {
+        let request = ServerDriveLockControlRequest {
+            device_io_request: self.io_request(file_id, MajorFunction::LockControl, 0),
+            operation,
+            wait,
+            locks: locks
+                .iter()
+                .map(|&(offset, length)| RdpLockInfo { length, offset })
+                .collect(),
+        };
+
+        let mut responses = self.handle(request.into());
+        assert!(responses.len() <= 1);
+        responses.pop()
+    }
+
+    fn notify(&mut self, file_id: u32, watch_tree: bool) -> Option<Response> {
+        let request = ServerDriveNotifyChangeDirectoryRequest {
+            device_io_request: self.io_request(file_id, MajorFunction::DirectoryControl, 2),
+            watch_tree: u8::from(watch_tree),
+            completion_filter: (CompletionFilter::FILE_NOTIFY_CHANGE_FILE_NAME
+                | CompletionFilter::FILE_NOTIFY_CHANGE_DIR_NAME)
+                .bits(),
+        };
+
+        let mut responses = self.handle(request.into());
+        assert!(responses.len() <= 1);
+        responses.pop()
+    }
+
+    fn process_pending_requests(&mut self) -> Vec<Response> {
+        responses(self.backend.process_pending_requests().unwrap())
+    }
+}
+
+impl Drop for Drive {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// I/O completion sent to the server
+#[derive(Debug)]
+struct Response {
+    completion_id: u32,
+    status: NtStatus,
+    /// The fields following DR_DEVICE_IOCOMPLETION
+    data: Vec<u8>,
+}
+
+impl Response {
+    /// The FILE_NOTIFY_INFORMATION of a notify change directory response, as actions and file names
+    fn changes(&self) -> Vec<(u32, String)> {
+        let mut changes = Vec::new();
+        let buffer = &self.data[4..];
+        let mut offset = 0;
+
+        while offset < buffer.len() {
+            let entry = &buffer[offset..];
+            let next_entry_offset = u32::from_le_bytes(entry[..4].try_into().unwrap());
+            let action = u32::from_le_bytes(entry[4..8].try_into().unwrap());
+            let length = usize::try_from(u32::from_le_bytes(entry[8..12].try_into().unwrap())).unwrap();
+            let name: Vec<u16> = entry[12..12 + length]
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect();
+            changes.push((action, String::from_utf16(&name).unwrap()));
+
+            if next_entry_offset == 0 {
+                break;
+            }
+            offset += usize::try_from(next_entry_offset).unwrap();
+        }
+
+        changes
+    }
+}
+
+fn responses(messages: Vec<SvcMessage>) -> Vec<Response> {
+    messages
+        .into_iter()
+        .map(|message| {
+            let chunk = StaticVirtualChannel::chunkify(vec![message]).unwrap().pop().unwrap();
+            // CHANNEL_PDU_HEADER, then RDPDR_HEADER
+            let mut src = ReadCursor::new(&chunk.filled()[8 + 4..]);
+            let reply = DeviceIoResponse::decode(&mut src).unwrap();
+
+            Response {
+                completion_id: reply.completion_id,
+                status: reply.io_status,
+                data: src.remaining().to_vec(),
+            }
+        })
+        .collect()
+}
+
+const FILE_ACTION_ADDED: u32 = 1;
+const FILE_ACTION_REMOVED: u32 = 2;
+const FILE_ACTION_RENAMED_OLD_NAME: u32 = 4;
+const FILE_ACTION_RENAMED_NEW_NAME: u32 = 5;
+
+#[test]
+fn conflicting_locks_are_not_granted() {
+    let mut drive = Drive::new("locks");
+    let first = drive.open("\\locked.bin", CreateOptions::FILE_NON_DIRECTORY_FILE);
+    let second = drive.open("\\locked.bin", CreateOptions::FILE_NON_DIRECTORY_FILE);
+
+    let response = drive
+        .lock(first, LockOperation::ExclusiveLock, false, &[(0, 10)])
+        .unwrap();
+    assert_eq!(response.status, NtStatus::SUCCESS);
+
+    let response = drive
+        .lock(second, LockOperation::SharedLock, false, &[(5, 10)])
+        .unwrap();
+    assert_eq!(response.status, NtStatus::LOCK_NOT_GRANTED);
+
+    // Outside of the locked range
+    let response = drive
+        .lock(second, LockOperation::SharedLock, false, &[(10, 10)])
+        .unwrap();
+    assert_eq!(response.status, NtStatus::SUCCESS);
+
+    // The locks of a request are granted all at once.
+    let response = drive
+        .lock(second, LockOperation::ExclusiveLock, false, &[(20, 10), (0, 1)])
+        .unwrap();
+    assert_eq!(response.status, NtStatus::LOCK_NOT_GRANTED);
+    let response = drive
+        .lock(first, LockOperation::ExclusiveLock, false, &[(20, 10)])
+        .unwrap();
+    assert_eq!(response.status, NtStatus::SUCCESS);
+
+    // Only the locked ranges can be unlocked.
+    let response = drive.lock(first, LockOperation::Unlock, false, &[(0, 5)]).unwrap();
+    assert_eq!(response.status, NtStatus::RANGE_NOT_LOCKED);
+    let response = drive.lock(first, LockOperation::Unlock, false, &[(0, 10)]).unwrap();
+    assert_eq!(response.status, NtStatus::SUCCESS);
+
+    let response = drive
+        .lock(second, LockOperation::SharedLock, false, &[(5, 10)])
+        .unwrap();
+    assert_eq!(response.status, NtStatus::SUCCESS);
+}
+
+#[test]
+fn waiting_lock_is_granted_once_released() {
+    let mut drive = Drive::new("wait");
+    let first = drive.open("\\locked.bin", CreateOptions::FILE_NON_DIRECTORY_FILE);
+    let second = drive.open("\\locked.bin", CreateOptions::FILE_NON_DIRECTORY_FILE);
+
+    drive
+        .lock(first, LockOperation::ExclusiveLock, false, &[(0, 10)])
+        .unwrap();
+
+    assert!(drive
+        .lock(second, LockOperation::ExclusiveLock, true, &[(0, 10)])
+        .is_none());
+    let waiting = drive.completion_id;
+    assert!(drive.backend.is_waiting_for_locks());
+    assert!(drive.process_pending_requests().is_empty());
+
+    // Closing a file releases its locks.
+    assert!(drive.close(first).is_empty());
+
+    let [response] = drive.process_pending_requests().try_into().unwrap();
+    assert_eq!((response.completion_id, response.status), (waiting, NtStatus::SUCCESS));
+    assert!(!drive.backend.is_waiting_for_locks());
+}
+
+#[test]
+fn waiting_lock_is_cancelled_on_close() {
+    let mut drive = Drive::new("cancel");
+    let first = drive.open("\\locked.bin", CreateOptions::FILE_NON_DIRECTORY_FILE);
+    let second = drive.open("\\locked.bin", CreateOptions::FILE_NON_DIRECTORY_FILE);
+
+    drive.lock(first, LockOperation::SharedLock, false, &[(0, 10)]).unwrap();
+    assert!(drive
+        .lock(second, LockOperation::ExclusiveLock, true, &[(0, 1)])
+        .is_none());
+    let waiting = drive.completion_id;
+
+    let [response] = drive.close(second).try_into().unwrap();
+    assert_eq!(
+        (response.completion_id, response.status),
+        (waiting, NtStatus::CANCELLED)
+    );
+    assert!(!drive.backend.is_waiting_for_locks());
+}
+
+#[test]
+fn directory_changes_are_notified() {
+    let mut drive = Drive::new("notify");
+    let dir = drive.open("\\", CreateOptions::FILE_DIRECTORY_FILE);
+
+    assert!(drive.notify(dir, false).is_none());
+    let pending = drive.completion_id;
+    assert!(drive.backend.change_notification_fd().is_some());
+    assert!(drive.process_pending_requests().is_empty());
+
+    fs::write(drive.path.join("new.txt"), b"new").unwrap();
+
+    let [response] = drive.process_pending_requests().try_into().unwrap();
+    assert_eq!((response.completion_id, response.status), (pending, NtStatus::SUCCESS));
+    assert_eq!(response.changes(), [(FILE_ACTION_ADDED, "new.txt".to_owned())]);
+
+    // The changes are kept until the next request.
+    fs::rename(drive.path.join("new.txt"), drive.path.join("renamed.txt")).unwrap();
+    fs::remove_file(drive.path.join("renamed.txt")).unwrap();
+    assert!(drive.process_pending_requests().is_empty());
+
+    let response = drive.notify(dir, false).unwrap();
+    assert_eq!(response.status, NtStatus::SUCCESS);
+    assert_eq!(
+        response.changes(),
+        [
+            (FILE_ACTION_RENAMED_OLD_NAME, "new.txt".to_owned()),
+            (FILE_ACTION_RENAMED_NEW_NAME, "renamed.txt".to_owned()),
+            (FILE_ACTION_REMOVED, "renamed.txt".to_owned()),
+        ]
+    );
+
+    // The pending request is completed when the directory is closed.
+    assert!(drive.notify(dir, false).is_none());
+    let [response] = drive.close(dir).try_into().unwrap();
+    assert_eq!(
+        (response.completion_id, response.status),
+        (drive.completion_id - 1, NtStatus::NOTIFY_CLEANUP)
+    );
+}
+
+#[test]
+fn replaced_notification_is_cancelled() {
+    let mut drive = Drive::new("replaced");
+    let dir = drive.open("\\", CreateOptions::FILE_DIRECTORY_FILE);
+
+    assert!(drive.notify(dir, false).is_none());
+    let replaced = drive.completion_id;
+
+    let response = drive.notify(dir, false).unwrap();
+    assert_eq!(
+        (response.completion_id, response.status),
+        (replaced, NtStatus::CANCELLED)
+    );
+
+    // The new request is the pending one.
+    fs::write(drive.path.join("new.txt"), b"new").unwrap();
+    let [response] = drive.process_pending_requests().try_into().unwrap();
+    assert_eq!(
+        (response.completion_id, response.status),
+        (replaced + 1, NtStatus::SUCCESS)
+    );
+}
+
+#[test]
+fn directory_tree_changes_are_notified() {
+    let mut drive = Drive::new("tree");
+    fs::create_dir(drive.path.join("sub")).unwrap();
+    let dir = drive.open("\\", CreateOptions::FILE_DIRECTORY_FILE);
+
+    assert!(drive.notify(dir, true).is_none());
+    fs::create_dir(drive.path.join("sub/new")).unwrap();
+    let [response] = drive.process_pending_requests().try_into().unwrap();
+    assert_eq!(response.changes(), [(FILE_ACTION_ADDED, "sub\\new".to_owned())]);
+
+    // The directories created in the tree are watched too.
+    assert!(drive.notify(dir, true).is_none());
+    fs::write(drive.path.join("sub/new/file.txt"), b"file").unwrap();
+    let [response] = drive.process_pending_requests().try_into().unwrap();
+    assert_eq!(
+        response.changes(),
+        [(FILE_ACTION_ADDED, "sub\\new\\file.txt".to_owned())]
+    );
+}
+
+#[test]
+fn too_many_changes_require_enumeration() {
+    let mut drive = Drive::new("overflow");
+    let dir = drive.open("\\", CreateOptions::FILE_DIRECTORY_FILE);
+
+    assert!(drive.notify(dir, false).is_none());
+    fs::write(drive.path.join("first.txt"), b"").unwrap();
+    assert_eq!(drive.process_pending_requests().len(), 1);
+
+    // Without a pending request, the changes are kept until there are too many.
+    for i in 0..1100 {
+        fs::write(drive.path.join(format!("{i}.txt")), b"").unwrap();
+    }
+    assert!(drive.process_pending_requests().is_empty());
+
+    let response = drive.notify(dir, false).unwrap();
+    assert_eq!(response.status, NtStatus::NOTIFY_ENUM_DIR);
+    assert!(response.changes().is_empty());
+}
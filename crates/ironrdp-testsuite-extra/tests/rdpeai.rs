@@ -0,0 +1,90 @@
+use ironrdp::dvc::DrdynvcClient;
+use ironrdp::rdpeai::client::AudioInputClient;
+use ironrdp::server::{PixelFormat, RdpServer};
+use ironrdp::session::image::DecodedImage;
+use ironrdp::session::{ActiveStage, ActiveStageOutput};
+use ironrdp::svc::SvcProcessorMessages;
+use ironrdp_async::FramedWrite;
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use crate::common::{
+    client_server_with, default_client_config, sine_wave, tls_acceptor, SineSource, TestAudioInputFactory,
+    TestInputHandler, Transport, DESKTOP_HEIGHT, DESKTOP_WIDTH,
+};
+
+#[tokio::test]
+async fn test_audio_input() {
+    let (data_tx, mut data_rx) = mpsc::unbounded_channel();
+
+    client_server_with(
+        Transport::InMemory,
+        default_client_config(),
+        |display| {
+            RdpServer::builder()
+                .with_addr(([127, 0, 0, 1], 0))
+                .with_tls(tls_acceptor())
+                .with_input_handler(TestInputHandler)
+                .with_display_handler(display)
+                .with_audio_input_factory(Some(Box::new(TestAudioInputFactory { data_tx })))
+                .build()
+        },
+        |connector| {
+            connector.attach_static_channel(
+                DrdynvcClient::new().with_dynamic_channel(AudioInputClient::new(Box::new(SineSource::default()))),
+            );
+        },
+        |mut stage, mut framed, _server| async move {
+            let mut image = DecodedImage::new(PixelFormat::RgbA32, DESKTOP_WIDTH, DESKTOP_HEIGHT);
+
+            let audio_input = |stage: &mut ActiveStage| {
+                let dvc = stage.get_dvc_mut::<AudioInputClient>().expect("audio input channel");
+                let channel_id = dvc.channel_id();
+                let client = dvc.channel_processor_downcast_mut::<AudioInputClient>().unwrap();
+                client.format().is_some().then_some(channel_id).flatten()
+            };
+
+            // Negotiates the format, until the server opens the capture.
+            let channel_id = loop {
+                let (action, payload) = framed.read_pdu().await.expect("valid PDU");
+                for out in stage.process(&mut image, action, &payload).expect("stage process") {
+                    match out {
+                        ActiveStageOutput::ResponseFrame(frame) => framed.write_all(&frame).await.expect("write frame"),
+                        out => debug!(?out),
+                    }
+                }
+
+                if let Some(channel_id) = audio_input(&mut stage) {
+                    break channel_id;
+                }
+            };
+
+            for _ in 0..3 {
+                let messages = stage
+                    .get_dvc_mut::<AudioInputClient>()
+                    .and_then(|dvc| dvc.channel_processor_downcast_mut::<AudioInputClient>())
+                    .unwrap()
+                    .capture(channel_id)
+                    .expect("capture");
+                let frame = stage
+                    .process_svc_processor_messages(SvcProcessorMessages::<DrdynvcClient>::new(messages))
+                    .expect("encode audio input");
+                framed.write_all(&frame).await.expect("write frame");
+            }
+
+            // Each capture is a packet of 20 ms.
+            let mut received = Vec::new();
+            for _ in 0..3 {
+                let (format, data) = data_rx.recv().await.expect("audio input data");
+                assert_eq!(format, SineSource::FORMAT);
+                assert_eq!(data.len(), 640);
+                received.extend(data);
+            }
+            assert_eq!(received, sine_wave(0, 960));
+
+            (stage, framed)
+        },
+    )
+    .await
+    .expect("connect");
+}
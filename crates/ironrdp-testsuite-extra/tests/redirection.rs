@@ -0,0 +1,73 @@
+use ironrdp::connector;
+use ironrdp::pdu;
+use ironrdp::pdu::x224::X224;
+
+use crate::common::{default_client_config, PASSWORD};
+
+#[test]
+fn test_redirection_load_balance_info() {
+    use ironrdp::pdu::nego::{ConnectionRequest, RequestFlags, SecurityProtocol};
+    use ironrdp::pdu::rdp::server_redirection::ServerRedirectionPdu;
+
+    // The load balancing info is opaque, and not necessarily valid UTF-8.
+    let load_balance_info = b"Cookie: msts=\xFF\x00\xC3\r\n".to_vec();
+
+    let mut config = default_client_config();
+    config.apply_redirection(&ServerRedirectionPdu {
+        session_id: 7,
+        load_balance_info: Some(load_balance_info.clone()),
+        ..Default::default()
+    });
+    assert_eq!(config.redirected_session_id, Some(7));
+
+    let request = pdu::encode_vec(&X224(ConnectionRequest {
+        nego_data: config.request_data,
+        flags: RequestFlags::empty(),
+        protocol: SecurityProtocol::SSL,
+    }))
+    .unwrap();
+
+    // TPKT header (4 bytes), TPDU header (7 bytes), routing token, RDP_NEG_REQ (8 bytes)
+    assert_eq!(request[11..request.len() - 8], load_balance_info);
+}
+
+#[test]
+fn test_redirection_password() {
+    use ironrdp::pdu::rdp::server_redirection::{ServerRedirectionFlags, ServerRedirectionPdu};
+
+    // Null-terminated UTF-16 password cookie.
+    let cookie: Vec<u8> = "cookie-\u{e9}\0".encode_utf16().flat_map(u16::to_le_bytes).collect();
+
+    let encoded = pdu::encode_vec(&ServerRedirectionPdu {
+        session_id: 7,
+        username: Some("redirected".to_owned()),
+        password: Some(cookie),
+        ..Default::default()
+    })
+    .unwrap();
+    let redirection = pdu::decode::<ServerRedirectionPdu>(&encoded).unwrap();
+
+    // flags (2 bytes), length (2 bytes), sessionId (4 bytes), redirFlags (4 bytes)
+    let redirection_flags =
+        ServerRedirectionFlags::from_bits_retain(u32::from_le_bytes(encoded[8..12].try_into().unwrap()));
+    assert!(redirection_flags.contains(ServerRedirectionFlags::PASSWORD));
+
+    let mut config = default_client_config();
+    config.apply_redirection(&redirection);
+    assert!(matches!(
+        &config.credentials,
+        connector::Credentials::UsernamePassword { username, password }
+            if username == "redirected" && password == "cookie-\u{e9}"
+    ));
+
+    // A password encrypted for the target server cannot be sent as is.
+    let mut config = default_client_config();
+    config.apply_redirection(&ServerRedirectionPdu {
+        flags: ServerRedirectionFlags::PASSWORD_IS_PK_ENCRYPTED,
+        ..redirection
+    });
+    assert!(matches!(
+        &config.credentials,
+        connector::Credentials::UsernamePassword { password, .. } if password == PASSWORD
+    ));
+}
@@ -0,0 +1,315 @@
+use core::num::NonZeroU16;
+
+use ironrdp::connector;
+use ironrdp::pdu;
+use ironrdp::pdu::mcs::McsMessage;
+use ironrdp::pdu::rdp::server_error_info::{ErrorInfo, ProtocolIndependentCode};
+use ironrdp::pdu::x224::X224;
+use ironrdp::server::{
+    self, BitmapUpdate, DesktopSize, DisplayUpdate, PixelFormat, PixelOrder, RdpServer, ServerEvent, SessionInfo,
+};
+use ironrdp::session::image::DecodedImage;
+use ironrdp::session::{self, ActiveStageOutput, GracefulDisconnectReason};
+use ironrdp_async::FramedWrite;
+use ironrdp_tokio::{ActiveSession, SessionEvent};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::oneshot;
+use tracing::debug;
+
+use crate::common::{
+    client_server, client_server_with, connect, default_client_config, tls_acceptor, tls_server, TestHandlerFactory,
+    Transport, DESKTOP_HEIGHT, DESKTOP_WIDTH, PASSWORD, USERNAME,
+};
+
+#[tokio::test]
+async fn test_client_server() {
+    client_server(default_client_config(), |stage, framed, _server| async {
+        (stage, framed)
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_deactivation_reactivation() {
+    let client_config = default_client_config();
+    let mut image = DecodedImage::new(
+        PixelFormat::RgbA32,
+        client_config.desktop_size.width,
+        client_config.desktop_size.height,
+    );
+    client_server(client_config, |mut stage, mut framed, server| async move {
+        server
+            .display_tx
+            .send(DisplayUpdate::Resize(DesktopSize {
+                width: 2048,
+                height: 2048,
+            }))
+            .unwrap();
+        {
+            let (action, payload) = framed.read_pdu().await.expect("valid PDU");
+            let outputs = stage.process(&mut image, action, &payload).expect("stage process");
+            let out = outputs.into_iter().next().unwrap();
+            match out {
+                ActiveStageOutput::DeactivateAll(mut connection_activation) => {
+                    // TODO: factor this out in common client code
+                    // Execute the Deactivation-Reactivation Sequence:
+                    // https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-rdpbcgr/dfc234ce-481a-4674-9a5d-2a7bafb14432
+                    debug!("Received Server Deactivate All PDU, executing Deactivation-Reactivation Sequence");
+                    let mut buf = pdu::WriteBuf::new();
+                    'activation_seq: loop {
+                        let written = ironrdp_async::single_sequence_step_read(
+                            &mut framed,
+                            &mut *connection_activation,
+                            &mut buf,
+                        )
+                        .await
+                        .map_err(|e| session::custom_err!("read deactivation-reactivation sequence step", e))
+                        .unwrap();
+
+                        if written.size().is_some() {
+                            framed
+                                .write_all(buf.filled())
+                                .await
+                                .map_err(|e| session::custom_err!("write deactivation-reactivation sequence step", e))
+                                .unwrap();
+                        }
+
+                        if let connector::connection_activation::ConnectionActivationState::Finalized {
+                            io_channel_id,
+                            user_channel_id,
+                            desktop_size,
+                            no_server_pointer,
+                            pointer_software_rendering,
+                        } = connection_activation.state
+                        {
+                            debug!(?desktop_size, "Deactivation-Reactivation Sequence completed");
+                            // Update image size with the new desktop size.
+                            // image = DecodedImage::new(PixelFormat::RgbA32, desktop_size.width, desktop_size.height);
+                            // Update the active stage with the new channel IDs and pointer settings.
+                            stage.set_fastpath_processor(
+                                session::fast_path::ProcessorBuilder {
+                                    io_channel_id,
+                                    user_channel_id,
+                                    no_server_pointer,
+                                    pointer_software_rendering,
+                                }
+                                .build(),
+                            );
+                            stage.set_no_server_pointer(no_server_pointer);
+                            break 'activation_seq;
+                        }
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+        (stage, framed)
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_active_session() {
+    let client_config = default_client_config();
+    let image = DecodedImage::new(
+        PixelFormat::RgbA32,
+        client_config.desktop_size.width,
+        client_config.desktop_size.height,
+    );
+    client_server(client_config, |stage, framed, server| async move {
+        let mut session = ActiveSession::new(framed, stage, image);
+
+        server
+            .display_tx
+            .send(DisplayUpdate::Resize(DesktopSize {
+                width: 2048,
+                height: 2048,
+            }))
+            .unwrap();
+
+        loop {
+            match session.next_event().await.expect("session event").expect("no error") {
+                SessionEvent::DeactivateAll(desktop_size) => {
+                    assert_eq!((desktop_size.width, desktop_size.height), (2048, 2048));
+                    break;
+                }
+                event => debug!(?event),
+            }
+        }
+
+        assert_eq!(session.image().expect("no step in progress").width(), 2048);
+
+        let (framed, stage, _) = session.into_inner().expect("no step in progress");
+        (stage, framed)
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_in_memory_transport() {
+    client_server_with(
+        Transport::InMemory,
+        default_client_config(),
+        tls_server,
+        |_| {},
+        |stage, framed, _server| async { (stage, framed) },
+    )
+    .await
+    .expect("connect");
+}
+
+#[tokio::test]
+async fn test_damage_tracking() {
+    client_server_with(
+        Transport::InMemory,
+        default_client_config(),
+        tls_server,
+        |_| {},
+        |mut stage, mut framed, server| async move {
+            let mut image = DecodedImage::new(PixelFormat::RgbA32, DESKTOP_WIDTH, DESKTOP_HEIGHT);
+
+            // 2x2 tiles, sent twice, then with a single tile changed.
+            let mut data = [0x00, 0x00, 0xFF, 0xFF].repeat(128 * 128);
+            let bitmap = |data: Vec<u8>| {
+                DisplayUpdate::Bitmap(BitmapUpdate {
+                    top: 0,
+                    left: 0,
+                    width: NonZeroU16::new(128).unwrap(),
+                    height: NonZeroU16::new(128).unwrap(),
+                    format: PixelFormat::BgrA32,
+                    order: PixelOrder::TopToBottom,
+                    data,
+                    stride: 128 * 4,
+                })
+            };
+            server.display_tx.send(bitmap(data.clone())).unwrap();
+            server.display_tx.send(bitmap(data.clone())).unwrap();
+            for row in data.chunks_mut(128 * 4).skip(64) {
+                row[64 * 4..].copy_from_slice(&[0xFF, 0x00, 0x00, 0xFF].repeat(64));
+            }
+            server.display_tx.send(bitmap(data)).unwrap();
+
+            // The second bitmap is skipped, so the second frame is the changed tile.
+            let mut updates = 0;
+            'updates: loop {
+                let (action, payload) = framed.read_pdu().await.expect("valid PDU");
+                for out in stage.process(&mut image, action, &payload).expect("stage process") {
+                    match out {
+                        ActiveStageOutput::ResponseFrame(frame) => framed.write_all(&frame).await.expect("write frame"),
+                        ActiveStageOutput::GraphicsUpdate(_) => {
+                            updates += 1;
+                            if updates == 2 {
+                                break 'updates;
+                            }
+                        }
+                        out => debug!(?out),
+                    }
+                }
+            }
+
+            let (tx, rx) = oneshot::channel();
+            server.ev.send(ServerEvent::GetDamageStats(tx)).unwrap();
+            let stats = rx.await.unwrap();
+            assert_eq!((stats.encoded_tiles, stats.skipped_tiles), (5, 7));
+
+            // RemoteFX is lossy.
+            let assert_pixel = |x: usize, y: usize, expected: [u8; 3]| {
+                let pixel = &image.data()[(y * usize::from(image.width()) + x) * 4..][..3];
+                assert!(
+                    pixel.iter().zip(expected).all(|(a, b)| a.abs_diff(b) < 0x20),
+                    "pixel ({x}, {y}) is {pixel:?}, expected {expected:?}"
+                );
+            };
+            assert_pixel(100, 100, [0x00, 0x00, 0xFF]);
+            assert_pixel(10, 10, [0xFF, 0x00, 0x00]);
+
+            (stage, framed)
+        },
+    )
+    .await
+    .expect("connect");
+}
+
+#[tokio::test]
+async fn test_concurrent_sessions() {
+    let mut server = RdpServer::builder()
+        .with_addr(([127, 0, 0, 1], 0))
+        .with_tls(tls_acceptor())
+        .with_handler_factory(TestHandlerFactory::default())
+        .with_max_sessions(2)
+        .build();
+    server.set_credentials(Some(server::Credentials {
+        username: USERNAME.into(),
+        password: PASSWORD.into(),
+        domain: None,
+    }));
+    let ev = server.event_sender().clone();
+
+    let server = tokio::spawn(async move {
+        server.run().await.unwrap();
+    });
+
+    let (tx, rx) = oneshot::channel();
+    ev.send(ServerEvent::GetLocalAddr(tx)).unwrap();
+    let addr = rx.await.unwrap().unwrap();
+
+    // The second client connects while the first one is still connected.
+    let (_first_stage, mut first_framed) = connect(default_client_config(), addr).await.expect("connect");
+    let (_second_stage, mut second_framed) = connect(default_client_config(), addr).await.expect("connect");
+
+    let mut sessions = get_sessions(&ev).await;
+    sessions.sort_by_key(|session| session.id);
+    assert_eq!(sessions.len(), 2);
+
+    // Over the limit, the client is told the server denied the connection.
+    let (mut denied_stage, mut denied_framed) = connect(default_client_config(), addr).await.expect("connect");
+    let mut image = DecodedImage::new(PixelFormat::RgbA32, 0, 0);
+    let reason = loop {
+        let (action, payload) = denied_framed.read_pdu().await.expect("valid PDU");
+        let outputs = denied_stage
+            .process(&mut image, action, &payload)
+            .expect("stage process");
+        if let Some(reason) = outputs.into_iter().find_map(|out| match out {
+            ActiveStageOutput::Terminate(reason) => Some(reason),
+            _ => None,
+        }) {
+            break reason;
+        }
+    };
+    let denied = ErrorInfo::ProtocolIndependentCode(ProtocolIndependentCode::ServerDeniedConnection);
+    assert!(matches!(reason, GracefulDisconnectReason::Other(desc) if desc == denied.description()));
+    assert_eq!(get_sessions(&ev).await.len(), 2);
+
+    let (tx, rx) = oneshot::channel();
+    ev.send(ServerEvent::GetSessionDamageStats(sessions[0].id, tx)).unwrap();
+    assert_eq!(rx.await.unwrap(), Some(server::DamageStats::default()));
+
+    // The client is told it is disconnected.
+    ev.send(ServerEvent::DisconnectSession(sessions[1].id)).unwrap();
+    let mut ultimatum = false;
+    while let Ok((_, pdu)) = second_framed.read_pdu().await {
+        ultimatum |= matches!(
+            pdu::decode::<X224<McsMessage<'_>>>(&pdu),
+            Ok(X224(McsMessage::DisconnectProviderUltimatum(_)))
+        );
+    }
+    assert!(ultimatum);
+
+    while get_sessions(&ev).await.len() != 1 {
+        tokio::time::sleep(core::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(get_sessions(&ev).await[0].id, sessions[0].id);
+
+    ev.send(ServerEvent::Quit("bye".into())).unwrap();
+    while let Ok(pdu) = first_framed.read_pdu().await {
+        debug!(?pdu);
+    }
+
+    server.await.expect("join");
+}
+
+async fn get_sessions(ev: &UnboundedSender<ServerEvent>) -> Vec<SessionInfo> {
+    let (tx, rx) = oneshot::channel();
+    ev.send(ServerEvent::GetSessions(tx)).unwrap();
+    rx.await.unwrap()
+}
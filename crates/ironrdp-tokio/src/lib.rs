@@ -1,20 +1,30 @@
 #![doc = include_str!("../README.md")]
 #![doc(html_logo_url = "https://cdnweb.devolutions.net/images/projects/devolutions/logos/devolutions-icon-shadow.svg")]
 
+#[cfg(feature = "session")]
+#[macro_use]
+extern crate tracing;
+
 #[rustfmt::skip] // do not re-order this pub use
 pub use ironrdp_async::*;
 
+#[cfg(feature = "session")]
+mod session;
+
 use core::pin::Pin;
 use std::io;
 
 use bytes::BytesMut;
 use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
 
+#[cfg(feature = "session")]
+pub use self::session::{ActiveSession, SessionHandle};
+
 pub type TokioFramed<S> = Framed<TokioStream<S>>;
 
 pub fn split_tokio_framed<S>(framed: TokioFramed<S>) -> (TokioFramed<ReadHalf<S>>, TokioFramed<WriteHalf<S>>)
 where
-    S: Sync + Unpin + AsyncRead + AsyncWrite,
+    S: Unpin + AsyncRead + AsyncWrite,
 {
     let (stream, leftover) = framed.into_inner();
     let (read_half, write_half) = tokio::io::split(stream);
@@ -25,7 +35,7 @@ where
 
 pub fn unsplit_tokio_framed<S>(reader: TokioFramed<ReadHalf<S>>, writer: TokioFramed<WriteHalf<S>>) -> TokioFramed<S>
 where
-    S: Sync + Unpin + AsyncRead + AsyncWrite,
+    S: Unpin + AsyncRead + AsyncWrite,
 {
     let (reader, leftover) = reader.into_inner();
     let writer = writer.into_inner_no_leftover();
@@ -58,10 +68,10 @@ impl<S> StreamWrapper for TokioStream<S> {
 
 impl<S> FramedRead for TokioStream<S>
 where
-    S: Send + Sync + Unpin + AsyncRead,
+    S: Send + Unpin + AsyncRead,
 {
     type ReadFut<'read>
-        = Pin<Box<dyn core::future::Future<Output = io::Result<usize>> + Send + Sync + 'read>>
+        = Pin<Box<dyn core::future::Future<Output = io::Result<usize>> + Send + 'read>>
     where
         Self: 'read;
 
@@ -74,10 +84,10 @@ where
 
 impl<S> FramedWrite for TokioStream<S>
 where
-    S: Send + Sync + Unpin + AsyncWrite,
+    S: Send + Unpin + AsyncWrite,
 {
     type WriteAllFut<'write>
-        = Pin<Box<dyn core::future::Future<Output = io::Result<()>> + Send + Sync + 'write>>
+        = Pin<Box<dyn core::future::Future<Output = io::Result<()>> + Send + 'write>>
     where
         Self: 'write;
 
@@ -0,0 +1,276 @@
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{ready, Context, Poll};
+use std::collections::VecDeque;
+
+use futures_core::Stream;
+use ironrdp_async::{
+    process_session_command, run_deactivation_reactivation, FramedWrite as _, SessionCommand, SessionEvent, SvcMessages,
+};
+use ironrdp_cliprdr::backend::{ClipboardMessage, ClipboardMessageProxy};
+use ironrdp_pdu::input::fast_path::FastPathInputEvent;
+use ironrdp_session::image::DecodedImage;
+use ironrdp_session::{custom_err, general_err, ActiveStage, ActiveStageOutput, SessionResult};
+use ironrdp_svc::{SvcProcessor, SvcProcessorMessages};
+use tokio::io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf};
+use tokio::sync::mpsc;
+
+use crate::{split_tokio_framed, unsplit_tokio_framed, TokioFramed};
+
+type StepFuture<S> = Pin<Box<dyn Future<Output = (Box<Driver<S>>, SessionResult<Vec<SessionEvent>>)> + Send>>;
+
+/// Drives an active session, after the connection sequence
+///
+/// The session is a [`Stream`] of [`SessionEvent`]s, and must be polled for anything to happen,
+/// including the processing of the commands sent with its [`SessionHandle`]s. The
+/// Deactivation-Reactivation Sequence is executed internally. The stream ends after
+/// [`SessionEvent::Terminated`], or after the first error.
+pub struct ActiveSession<S> {
+    /// `None` while a step is in progress
+    driver: Option<Box<Driver<S>>>,
+    step: Option<StepFuture<S>>,
+    events: VecDeque<SessionEvent>,
+    handle: SessionHandle,
+    terminated: bool,
+}
+
+// The session is meant to be spawned on a multi-threaded runtime.
+const _: () = {
+    fn assert_send<T: Send>() {}
+
+    fn assert_active_session_is_send() {
+        assert_send::<ActiveSession<tokio::io::DuplexStream>>();
+    }
+};
+
+impl<S> ActiveSession<S>
+where
+    S: Send + Unpin + AsyncRead + AsyncWrite + 'static,
+{
+    pub fn new(framed: TokioFramed<S>, active_stage: ActiveStage, image: DecodedImage) -> Self {
+        let (reader, writer) = split_tokio_framed(framed);
+        let (command_tx, command_rx) = mpsc::unbounded_channel();
+
+        Self {
+            driver: Some(Box::new(Driver {
+                reader,
+                writer,
+                active_stage,
+                image,
+                commands: command_rx,
+            })),
+            step: None,
+            events: VecDeque::new(),
+            handle: SessionHandle { commands: command_tx },
+            terminated: false,
+        }
+    }
+
+    /// Returns a handle to send commands to the session
+    pub fn handle(&self) -> SessionHandle {
+        self.handle.clone()
+    }
+
+    /// The image updated by the session
+    ///
+    /// Returns `None` if the session was polled, but could not complete its current step yet.
+    pub fn image(&self) -> Option<&DecodedImage> {
+        self.driver.as_ref().map(|driver| &driver.image)
+    }
+
+    /// Returns `None` if the session was polled, but could not complete its current step yet.
+    pub fn active_stage_mut(&mut self) -> Option<&mut ActiveStage> {
+        self.driver.as_mut().map(|driver| &mut driver.active_stage)
+    }
+
+    /// Waits for the next event, see [`Stream::poll_next`]
+    pub async fn next_event(&mut self) -> Option<SessionResult<SessionEvent>> {
+        core::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// Returns the underlying stream, the active stage and the image
+    ///
+    /// Returns `None` if the session was polled, but could not complete its current step yet.
+    pub fn into_inner(self) -> Option<(TokioFramed<S>, ActiveStage, DecodedImage)> {
+        let driver = self.driver?;
+
+        Some((
+            unsplit_tokio_framed(driver.reader, driver.writer),
+            driver.active_stage,
+            driver.image,
+        ))
+    }
+}
+
+impl<S> Stream for ActiveSession<S>
+where
+    S: Send + Unpin + AsyncRead + AsyncWrite + 'static,
+{
+    type Item = SessionResult<SessionEvent>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        loop {
+            if let Some(event) = this.events.pop_front() {
+                if matches!(event, SessionEvent::Terminated(_)) {
+                    this.terminated = true;
+                }
+
+                return Poll::Ready(Some(Ok(event)));
+            }
+
+            if this.terminated {
+                return Poll::Ready(None);
+            }
+
+            let step = match &mut this.step {
+                Some(step) => step,
+                None => {
+                    let Some(mut driver) = this.driver.take() else {
+                        return Poll::Ready(None);
+                    };
+
+                    this.step.insert(Box::pin(async move {
+                        let result = driver.step().await;
+                        (driver, result)
+                    }))
+                }
+            };
+
+            let (driver, result) = ready!(step.as_mut().poll(cx));
+            this.step = None;
+            this.driver = Some(driver);
+
+            match result {
+                Ok(events) => this.events.extend(events),
+                Err(e) => {
+                    this.terminated = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+        }
+    }
+}
+
+struct Driver<S> {
+    reader: TokioFramed<ReadHalf<S>>,
+    writer: TokioFramed<WriteHalf<S>>,
+    active_stage: ActiveStage,
+    image: DecodedImage,
+    commands: mpsc::UnboundedReceiver<SessionCommand>,
+}
+
+impl<S> Driver<S>
+where
+    S: Send + Unpin + AsyncRead + AsyncWrite,
+{
+    /// Processes the next frame or command, and returns the resulting events
+    async fn step(&mut self) -> SessionResult<Vec<SessionEvent>> {
+        let (outputs, event) = tokio::select! {
+            frame = self.reader.read_pdu() => {
+                let (action, payload) = frame.map_err(|e| custom_err!("read frame", e))?;
+                trace!(?action, frame_length = payload.len(), "Frame received");
+
+                (self.active_stage.process(&mut self.image, action, &payload)?, None)
+            }
+            command = self.commands.recv() => {
+                // The session itself holds a sender, so the channel is never closed.
+                let command = command.expect("command channel is open");
+
+                process_session_command(&mut self.active_stage, &mut self.image, command)?
+            }
+        };
+
+        let mut events: Vec<SessionEvent> = event.into_iter().collect();
+
+        for output in outputs {
+            let event = match output {
+                ActiveStageOutput::ResponseFrame(frame) => {
+                    self.writer
+                        .write_all(&frame)
+                        .await
+                        .map_err(|e| custom_err!("write response", e))?;
+                    continue;
+                }
+                ActiveStageOutput::GraphicsUpdate(region) => SessionEvent::GraphicsUpdate(region),
+                ActiveStageOutput::PointerDefault => SessionEvent::PointerDefault,
+                ActiveStageOutput::PointerHidden => SessionEvent::PointerHidden,
+                ActiveStageOutput::PointerPosition { x, y } => SessionEvent::PointerPosition { x, y },
+                ActiveStageOutput::PointerBitmap(pointer) => SessionEvent::PointerBitmap(pointer),
+                ActiveStageOutput::DeactivateAll(connection_activation) => {
+                    let desktop_size = run_deactivation_reactivation(
+                        &mut self.reader,
+                        &mut self.writer,
+                        &mut self.active_stage,
+                        &mut self.image,
+                        connection_activation,
+                    )
+                    .await?;
+
+                    SessionEvent::DeactivateAll(desktop_size)
+                }
+                ActiveStageOutput::AutoReconnectCookie(cookie) => SessionEvent::AutoReconnectCookie(cookie),
+                ActiveStageOutput::Terminate(reason) => SessionEvent::Terminated(reason),
+            };
+
+            events.push(event);
+        }
+
+        Ok(events)
+    }
+}
+
+/// Cloneable handle to send commands to an [`ActiveSession`]
+///
+/// The commands are processed in order, when the session is polled. Sending fails once the
+/// session is dropped.
+#[derive(Debug, Clone)]
+pub struct SessionHandle {
+    commands: mpsc::UnboundedSender<SessionCommand>,
+}
+
+impl SessionHandle {
+    pub fn send(&self, command: SessionCommand) -> SessionResult<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| general_err!("session is closed"))
+    }
+
+    pub fn send_fastpath_input(&self, events: Vec<FastPathInputEvent>) -> SessionResult<()> {
+        self.send(SessionCommand::FastPathInput(events))
+    }
+
+    /// See [`SessionCommand::Resize`]
+    pub fn resize(
+        &self,
+        width: u16,
+        height: u16,
+        scale_factor: Option<u32>,
+        physical_size: Option<(u32, u32)>,
+    ) -> SessionResult<()> {
+        self.send(SessionCommand::Resize {
+            width,
+            height,
+            scale_factor,
+            physical_size,
+        })
+    }
+
+    pub fn send_svc_messages<C: SvcProcessor + 'static>(&self, messages: SvcProcessorMessages<C>) -> SessionResult<()> {
+        self.send(SessionCommand::SvcMessages(SvcMessages::new(messages)))
+    }
+
+    /// Requests a graceful shutdown, the session terminates when the server disconnects
+    pub fn shutdown(&self) -> SessionResult<()> {
+        self.send(SessionCommand::Shutdown)
+    }
+}
+
+impl ClipboardMessageProxy for SessionHandle {
+    fn send_clipboard_message(&self, message: ClipboardMessage) {
+        if self.send(SessionCommand::Clipboard(message)).is_err() {
+            warn!("Clipboard message dropped, the session is closed");
+        }
+    }
+}
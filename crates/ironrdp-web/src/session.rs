@@ -483,6 +483,14 @@ impl Session {
                                         cliprdr.initiate_paste(format)
                                             .context("CLIPRDR initiate paste")?
                                     ),
+                                    ClipboardMessage::SendFileContents(response) => Some(
+                                        cliprdr.submit_file_contents(response)
+                                            .context("CLIPRDR submit file contents")?
+                                    ),
+                                    ClipboardMessage::SendFileContentsRequest(request) => Some(
+                                        cliprdr.request_file_contents(request)
+                                            .context("CLIPRDR request file contents")?
+                                    ),
                                     ClipboardMessage::Error(e) => {
                                         error!("Clipboard backend error: {}", e);
                                         None
@@ -689,6 +697,7 @@ impl Session {
                         }
                     }
                     ActiveStageOutput::Terminate(reason) => break 'outer reason,
+                    ActiveStageOutput::AutoReconnectCookie(_) => {}
                 }
             }
         };
@@ -833,6 +842,7 @@ fn build_config(
         // TODO(#327): expose these options from the WASM module.
         enable_tls: true,
         enable_credssp: true,
+        enable_standard_rdp_security: false,
         keyboard_type: ironrdp::pdu::gcc::KeyboardType::IbmEnhanced,
         keyboard_subtype: 0,
         keyboard_layout: 0, // the server SHOULD use the default active input locale identifier
@@ -847,6 +857,7 @@ fn build_config(
             color_depth: 16,
             lossy_compression: true,
         }),
+        bitmap_cache: None,
         #[allow(clippy::arithmetic_side_effects)] // fine unless we end up with an insanely big version
         client_build: semver::Version::parse(env!("CARGO_PKG_VERSION"))
             .map(|version| version.major * 100 + version.minor * 10 + version.patch)
@@ -860,6 +871,10 @@ fn build_config(
         platform: ironrdp::pdu::rdp::capability_sets::MajorPlatformType::UNSPECIFIED,
         no_server_pointer: false,
         autologon: false,
+        enable_gfx: false,
+        compression_type: None,
+        auto_reconnect: None,
+        redirected_session_id: None,
         request_data: None,
         pointer_software_rendering: false,
         performance_flags: PerformanceFlags::default(),
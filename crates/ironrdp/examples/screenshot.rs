@@ -171,6 +171,7 @@ fn build_config(username: String, password: String, domain: Option<String>) -> c
         domain,
         enable_tls: false, // This example does not expose any frontend.
         enable_credssp: true,
+        enable_standard_rdp_security: false,
         keyboard_type: KeyboardType::IbmEnhanced,
         keyboard_subtype: 0,
         keyboard_layout: 0,
@@ -182,6 +183,7 @@ fn build_config(username: String, password: String, domain: Option<String>) -> c
             height: 1024,
         },
         bitmap: None,
+        bitmap_cache: None,
         client_build: 0,
         client_name: "ironrdp-screenshot-example".to_owned(),
         client_dir: "C:\\Windows\\System32\\mstscax.dll".to_owned(),
@@ -209,6 +211,10 @@ fn build_config(username: String, password: String, domain: Option<String>) -> c
         no_server_pointer: true,
         request_data: None,
         autologon: false,
+        enable_gfx: false,
+        compression_type: None,
+        auto_reconnect: None,
+        redirected_session_id: None,
         pointer_software_rendering: true,
         performance_flags: PerformanceFlags::default(),
         desktop_scale_factor: 0,
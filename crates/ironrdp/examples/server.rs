@@ -14,6 +14,7 @@ use std::sync::{Arc, Mutex};
 use anyhow::Context as _;
 use ironrdp::cliprdr::backend::{CliprdrBackend, CliprdrBackendFactory};
 use ironrdp::connector::DesktopSize;
+use ironrdp::rdpsnd::codec::{self, AudioEncoder};
 use ironrdp::rdpsnd::pdu::{AudioFormat, ClientAudioFormatPdu, WaveFormat};
 use ironrdp::rdpsnd::server::{RdpsndServerHandler, RdpsndServerMessage};
 use ironrdp::server::tokio::sync::mpsc::UnboundedSender;
@@ -238,8 +239,20 @@ impl ServerEventSender for StubSoundServerFactory {
 
 impl SoundServerFactory for StubSoundServerFactory {
     fn build_backend(&self) -> Box<dyn RdpsndServerHandler> {
+        let mut formats = vec![AudioFormat {
+            format: WaveFormat::OPUS,
+            n_channels: 2,
+            n_samples_per_sec: 48000,
+            n_avg_bytes_per_sec: 192000,
+            n_block_align: 4,
+            bits_per_sample: 16,
+            data: None,
+        }];
+        formats.extend(codec::supported_formats(2, 44100));
+
         Box::new(SndHandler {
             inner: Arc::clone(&self.inner),
+            formats,
             task: None,
         })
     }
@@ -248,6 +261,7 @@ impl SoundServerFactory for StubSoundServerFactory {
 #[derive(Debug)]
 struct SndHandler {
     inner: Arc<Mutex<Inner>>,
+    formats: Vec<AudioFormat>,
     task: Option<tokio::task::JoinHandle<()>>,
 }
 
@@ -264,26 +278,7 @@ impl SndHandler {
 
 impl RdpsndServerHandler for SndHandler {
     fn get_formats(&self) -> &[AudioFormat] {
-        &[
-            AudioFormat {
-                format: WaveFormat::OPUS,
-                n_channels: 2,
-                n_samples_per_sec: 48000,
-                n_avg_bytes_per_sec: 192000,
-                n_block_align: 4,
-                bits_per_sample: 16,
-                data: None,
-            },
-            AudioFormat {
-                format: WaveFormat::PCM,
-                n_channels: 2,
-                n_samples_per_sec: 44100,
-                n_avg_bytes_per_sec: 176400,
-                n_block_align: 4,
-                bits_per_sample: 16,
-                data: None,
-            },
-        ]
+        &self.formats
     }
 
     fn start(&mut self, client_format: &ClientAudioFormatPdu) -> Option<u16> {
@@ -295,7 +290,7 @@ impl RdpsndServerHandler for SndHandler {
 
         let fmt = client_format.formats[usize::from(nfmt)].clone();
 
-        let mut opus_enc = if fmt.format == WaveFormat::OPUS {
+        let mut encoder = if fmt.format == WaveFormat::OPUS {
             let n_channels: opus::Channels = match fmt.n_channels {
                 1 => opus::Channels::Mono,
                 2 => opus::Channels::Stereo,
@@ -306,14 +301,20 @@ impl RdpsndServerHandler for SndHandler {
             };
 
             match opus::Encoder::new(fmt.n_samples_per_sec, n_channels, opus::Application::Audio) {
-                Ok(enc) => Some(enc),
+                Ok(enc) => Encoder::Opus(enc),
                 Err(err) => {
                     warn!("Failed to create OPUS encoder: {}", err);
                     return Some(0);
                 }
             }
         } else {
-            None
+            match AudioEncoder::new(&fmt) {
+                Ok(enc) => Encoder::Codec(enc),
+                Err(err) => {
+                    warn!("Failed to create audio encoder: {}", err);
+                    return Some(0);
+                }
+            }
         };
 
         let inner = Arc::clone(&self.inner);
@@ -325,16 +326,23 @@ impl RdpsndServerHandler for SndHandler {
                 interval.tick().await;
                 let wave = generate_sine_wave(fmt.n_samples_per_sec, 440.0, 20, &mut phase);
 
-                let data = if let Some(ref mut enc) = opus_enc {
-                    match enc.encode_vec(&wave, wave.len()) {
+                let data = match encoder {
+                    Encoder::Opus(ref mut enc) => match enc.encode_vec(&wave, wave.len()) {
                         Ok(data) => data,
                         Err(err) => {
                             warn!("Failed to encode with OPUS: {}", err);
                             return;
                         }
+                    },
+                    Encoder::Codec(ref mut enc) => {
+                        // ADPCM is encoded by blocks, which may be longer than 20 ms.
+                        let mut data = Vec::new();
+                        enc.encode(&wave, &mut data);
+                        if data.is_empty() {
+                            continue;
+                        }
+                        data
                     }
-                } else {
-                    wave.into_iter().flat_map(|value| value.to_le_bytes()).collect()
                 };
 
                 let inner = inner.lock().unwrap();
@@ -356,6 +364,11 @@ impl RdpsndServerHandler for SndHandler {
     }
 }
 
+enum Encoder {
+    Opus(opus::Encoder),
+    Codec(AudioEncoder),
+}
+
 fn generate_sine_wave(sample_rate: u32, frequency: f32, duration_ms: u64, phase: &mut f32) -> Vec<i16> {
     use core::f32::consts::PI;
 
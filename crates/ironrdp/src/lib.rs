@@ -48,6 +48,10 @@ pub use ironrdp_pdu as pdu;
 #[doc(inline)]
 pub use ironrdp_rdpdr as rdpdr;
 
+#[cfg(feature = "rdpeai")]
+#[doc(inline)]
+pub use ironrdp_rdpeai as rdpeai;
+
 #[cfg(feature = "rdpsnd")]
 #[doc(inline)]
 pub use ironrdp_rdpsnd as rdpsnd;
@@ -15,6 +15,12 @@ pub mod ffi {
                     ClipboardMessageType::SendInitiatePaste
                 }
                 ironrdp::cliprdr::backend::ClipboardMessage::Error(_) => ClipboardMessageType::Error,
+                ironrdp::cliprdr::backend::ClipboardMessage::SendFileContents(_) => {
+                    ClipboardMessageType::SendFileContents
+                }
+                ironrdp::cliprdr::backend::ClipboardMessage::SendFileContentsRequest(_) => {
+                    ClipboardMessageType::SendFileContentsRequest
+                }
             }
         }
 
@@ -52,6 +58,8 @@ pub mod ffi {
         SendFormatData,
         SendInitiatePaste,
         Error,
+        SendFileContents,
+        SendFileContentsRequest,
     }
 
     #[diplomat::opaque]
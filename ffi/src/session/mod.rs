@@ -177,6 +177,7 @@ pub mod ffi {
         PointerBitmap,
         Terminate,
         DeactivateAll,
+        AutoReconnectCookie,
     }
 
     impl ActiveStageOutput {
@@ -190,6 +191,9 @@ pub mod ffi {
                 ironrdp::session::ActiveStageOutput::PointerBitmap { .. } => ActiveStageOutputType::PointerBitmap,
                 ironrdp::session::ActiveStageOutput::Terminate { .. } => ActiveStageOutputType::Terminate,
                 ironrdp::session::ActiveStageOutput::DeactivateAll { .. } => ActiveStageOutputType::DeactivateAll,
+                ironrdp::session::ActiveStageOutput::AutoReconnectCookie { .. } => {
+                    ActiveStageOutputType::AutoReconnectCookie
+                }
             }
         }
 
@@ -225,7 +229,7 @@ pub mod ffi {
         pub fn get_pointer_bitmap(&self) -> Result<Box<DecodedPointer>, Box<IronRdpError>> {
             match &self.0 {
                 ironrdp::session::ActiveStageOutput::PointerBitmap(decoded_pointer) => {
-                    Ok(DecodedPointer(std::rc::Rc::clone(decoded_pointer)))
+                    Ok(DecodedPointer(std::sync::Arc::clone(decoded_pointer)))
                 }
                 _ => Err(IncorrectEnumTypeError::on_variant("PointerBitmap")
                     .of_enum("ActiveStageOutput")